@@ -28,14 +28,25 @@ in
       }.${level};
     };
 
+    listenStreams = lib.mkOption {
+      type = with lib.types; listOf str;
+      default = [ ];
+      example = [ "%t/greg-ng/http.sock" "8008" ];
+      description = ''
+        If not empty, a systemd socket unit listening on these addresses is created,
+        and greg-ng is started through socket activation. This takes precedence over
+        the host, port and listen settings. See systemd.socket(5) for the format.
+      '';
+    };
+
     # TODO: create some better descriptions
     settings = {
       host = lib.mkOption {
-        type = lib.types.str;
+        type = with lib.types; either str (listOf str);
         default = "localhost";
-        example = "0.0.0.0";
+        example = [ "::" "0.0.0.0" ];
         description = ''
-          Which host to bind to.
+          Which host(s) to bind to. Every address a host resolves to is bound.
         '';
       };
 
@@ -48,6 +59,705 @@ in
         '';
       };
 
+      listen = lib.mkOption {
+        type = with lib.types; either str (listOf str);
+        default = [ ];
+        example = "unix:/run/user/2000/greg-ng/http.sock";
+        description = ''
+          Where to serve the APIs, either `<host>:<port>` or `unix:<path>`.
+          Overrides host and port.
+        '';
+      };
+
+      backend = lib.mkOption {
+        type = lib.types.enum [ "mpv" "dlna" ];
+        default = "mpv";
+        description = ''
+          Which player backend to use. With dlna, media is cast to the renderer
+          given in dlna-renderer, and the websocket API is not available.
+        '';
+      };
+
+      dlna-renderer = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "http://192.168.1.20:49152/description.xml";
+        description = ''
+          URL of the device description of the DLNA renderer to cast to.
+        '';
+      };
+
+      sync-leader = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "ws://livingroom:8008/sync";
+        description = ''
+          Follow the playlist and playback position of another greg-ng instance,
+          to play the same thing in several rooms at once.
+        '';
+      };
+
+      remote = lib.mkOption {
+        type = with lib.types; listOf str;
+        default = [ ];
+        example = [ "terminalen=http://terminalen:8008" ];
+        description = ''
+          Other greg-ng instances to forward queue operations to, as `<name>=<url>`.
+          Items are added to them with `POST /api/remote/<name>/load`.
+        '';
+      };
+
+      soundboard-clip = lib.mkOption {
+        type = with lib.types; listOf str;
+        default = [ ];
+        example = [ "airhorn=/srv/clips/airhorn.mp3" ];
+        description = ''
+          Soundboard clips, as `<name>=<path>`. They are listed at `GET /api/soundboard`,
+          and played over whatever is on with `POST /api/soundboard/<name>`.
+        '';
+      };
+
+      radio-station = lib.mkOption {
+        type = with lib.types; listOf str;
+        default = [ ];
+        example = [ "p3=https://lyd.nrk.no/nrk_radio_p3_mp3_h" ];
+        description = ''
+          Web radio stations, as `<name>=<url>`. They are listed at `GET /api/radio`,
+          and tuned into with `POST /api/radio/<name>/play`.
+        '';
+      };
+
+      input = lib.mkOption {
+        type = with lib.types; listOf str;
+        default = [ ];
+        example = [ "console=av://v4l2:/dev/video0" ];
+        description = ''
+          External inputs mpv can be switched over to, as `<name>=<url>`. They are activated
+          with `POST /api/inputs/<name>/activate`, which suspends the playlist until
+          `POST /api/inputs/deactivate`.
+        '';
+      };
+
+      prefetch = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = ''
+          Whether to resolve the next few playlist items with yt-dlp ahead of time,
+          to reduce the gap between tracks.
+        '';
+      };
+
+      replay-gain = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = ''
+          Whether to measure the loudness of upcoming items with ffmpeg, and adjust
+          their volume so that consecutive items play equally loud. Requires `prefetch`.
+        '';
+      };
+
+      prefetch-playlist = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = ''
+          Whether to let mpv open the next playlist item while the current one is
+          playing. Can also be toggled at runtime through the API.
+        '';
+      };
+
+      hwdec = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "vaapi";
+        description = ''
+          The hardware decoding mode mpv starts with, like `auto`, `vaapi` or `none`.
+          Can be switched at runtime through the API.
+        '';
+      };
+
+      screen = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "HDMI-A-1";
+        description = ''
+          The display output mpv is shown and goes fullscreen on, by number or by name.
+          Can be switched at runtime through the API.
+        '';
+      };
+
+      party-source = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "playlist:friday";
+        description = ''
+          Where party mode picks items from when the playlist runs out: `playlist:<name>`
+          for a saved playlist, `library` or `library:<dir>` for the media library, or
+          `related` for the YouTube mix of the last played video.
+        '';
+      };
+
+      party-mode = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = ''
+          Whether to start with party mode on. Requires `party-source`.
+        '';
+      };
+
+      announcement = lib.mkOption {
+        type = with lib.types; listOf str;
+        default = [ ];
+        example = [ "/srv/jingles/sponsor.ogg" ];
+        description = ''
+          Clips to slip in after the item playing now every `announcement-interval`
+          minutes, like a sponsor jingle. Several clips take turns.
+        '';
+      };
+
+      announcement-interval = lib.mkOption {
+        type = lib.types.ints.positive;
+        default = 30;
+        description = ''
+          How many minutes apart announcements are made.
+        '';
+      };
+
+      announce = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = ''
+          Whether to start with announcements on. Requires `announcement`.
+        '';
+      };
+
+      sponsorblock = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = ''
+          Whether to skip sponsored segments and the like in YouTube videos, using SponsorBlock.
+        '';
+      };
+
+      sponsorblock-categories = lib.mkOption {
+        type = with lib.types; nullOr (listOf str);
+        default = null;
+        example = [ "sponsor" "intro" "outro" ];
+        description = ''
+          The SponsorBlock categories to skip. If not set, sponsors and self promotion
+          are skipped.
+        '';
+      };
+
+      lyrics = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = ''
+          Whether to fetch synced lyrics for music, which are served at `/api/lyrics`
+          and sent to clients line by line as they are sung.
+        '';
+      };
+
+      lyrics-provider = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "https://lrclib.example.org";
+        description = ''
+          The LRCLIB compatible server to fetch lyrics from. If not set, the public
+          LRCLIB server is used.
+        '';
+      };
+
+      history-retention-days = lib.mkOption {
+        type = with lib.types; nullOr ints.positive;
+        default = null;
+        example = 180;
+        description = ''
+          Forget what was played more than this many days ago. The history is served at
+          `/api/history`, and can be exported from `/api/history/export`.
+        '';
+      };
+
+      autoplay = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = ''
+          Whether to start with autoplay on, which plays a video related to the last
+          one when the playlist runs out.
+        '';
+      };
+
+      autoplay-min-duration = lib.mkOption {
+        type = with lib.types; nullOr ints.positive;
+        default = null;
+        example = 60;
+        description = ''
+          Don't autoplay videos shorter than this many seconds.
+        '';
+      };
+
+      autoplay-max-duration = lib.mkOption {
+        type = with lib.types; nullOr ints.positive;
+        default = null;
+        example = 900;
+        description = ''
+          Don't autoplay videos longer than this many seconds. Defaults to 15 minutes.
+        '';
+      };
+
+      autoplay-deny = lib.mkOption {
+        type = with lib.types; listOf str;
+        default = [ ];
+        example = [ "10 hours" "compilation" ];
+        description = ''
+          Don't autoplay videos with any of these in their title, channel or url,
+          ignoring case.
+        '';
+      };
+
+      resume-min-duration = lib.mkOption {
+        type = lib.types.ints.positive;
+        default = 1200;
+        description = ''
+          Remember how far into items at least this many seconds long playback got,
+          so they can be queued with resume to continue from there.
+        '';
+      };
+
+      resume-days = lib.mkOption {
+        type = lib.types.ints.positive;
+        default = 30;
+        description = ''
+          Forget how far into an item playback got after this many days.
+        '';
+      };
+
+      item-ttl = lib.mkOption {
+        type = with lib.types; nullOr ints.positive;
+        default = null;
+        example = 12;
+        description = ''
+          Remove items that have waited in the playlist for this many hours without
+          being played. Items can be given their own TTL when they are added.
+        '';
+      };
+
+      idle-pause-after = lib.mkOption {
+        type = with lib.types; nullOr ints.positive;
+        default = null;
+        example = 30;
+        description = ''
+          Quiet background music when no clients have been connected for this many
+          minutes, and bring it back when someone connects. Background music is
+          whatever autoplay picked, and items flagged as such by an admin.
+        '';
+      };
+
+      idle-action = lib.mkOption {
+        type = lib.types.enum [ "pause" "lower-volume" ];
+        default = "pause";
+        description = ''
+          What to do with background music when nobody is around.
+        '';
+      };
+
+      idle-volume = lib.mkOption {
+        type = lib.types.ints.between 0 100;
+        default = 20;
+        description = ''
+          The volume to turn background music down to, when idle-action is
+          lower-volume.
+        '';
+      };
+
+      playback-timeout = lib.mkOption {
+        type = with lib.types; nullOr ints.positive;
+        default = null;
+        example = 30;
+        description = ''
+          Skip items that make no progress for this many seconds while not paused,
+          like dead streams.
+        '';
+      };
+
+      retry-attempts = lib.mkOption {
+        type = with lib.types; nullOr ints.unsigned;
+        default = null;
+        example = 2;
+        description = ''
+          Retry items that fail to play this many times before giving up on them.
+        '';
+      };
+
+      retry-backoff = lib.mkOption {
+        type = with lib.types; nullOr ints.positive;
+        default = null;
+        example = 5;
+        description = ''
+          How many seconds to wait before retrying a failed item. Doubles with every attempt.
+        '';
+      };
+
+      retry-format = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "best[height<=720]";
+        description = ''
+          A yt-dlp format selection to retry failed items with.
+        '';
+      };
+
+      cache-size = lib.mkOption {
+        type = with lib.types; nullOr ints.positive;
+        default = null;
+        example = 150;
+        description = ''
+          How much mpv buffers ahead, in megabytes. Uses mpv's default if not set.
+        '';
+      };
+
+      library-dir = lib.mkOption {
+        type = with lib.types; listOf str;
+        default = [ ];
+        example = [ "/srv/music" ];
+        description = ''
+          Directories of local media files to index, and make searchable through
+          `/api/library/search`.
+        '';
+      };
+
+      library-index = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "/var/lib/greg-ng/library.sqlite";
+        description = ''
+          Where to store the library index. If null, it is kept in memory.
+        '';
+      };
+
+      upload-dir = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "/var/cache/greg-ng/uploads";
+        description = ''
+          If set, media files can be uploaded through `POST /api/upload`, and are
+          stored here until they have been played.
+        '';
+      };
+
+      max-upload-size = lib.mkOption {
+        type = lib.types.ints.positive;
+        default = 200;
+        description = ''
+          The largest file that can be uploaded, in megabytes.
+        '';
+      };
+
+      webhook = lib.mkOption {
+        type = with lib.types; listOf str;
+        default = [ ];
+        example = [ "track_started,player_crashed=https://example.com/hooks/greg" ];
+        description = ''
+          URLs to POST player events to, as `[<event>,...=]<url>`.
+          The events are track_started, track_finished, playlist_emptied and player_crashed.
+        '';
+      };
+
+      webhook-secret-file = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "/run/secrets/greg-ng-webhook";
+        description = ''
+          A file containing the secret used to sign webhook requests.
+        '';
+      };
+
+      notify = lib.mkOption {
+        type = with lib.types; listOf str;
+        default = [ ];
+        example = [ "irc://irc.pvv.ntnu.no/pvv?nick=greg" ];
+        description = ''
+          Chats to post now-playing updates and queue additions to, either
+          `matrix://<homeserver>/<room id>` or `irc://<host>[:<port>]/<channel>[?nick=<nick>]`.
+        '';
+      };
+
+      matrix-token-file = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "/run/secrets/greg-ng-matrix-token";
+        description = ''
+          A file containing the Matrix access token used to post notifications.
+        '';
+      };
+
+      mpv-property = lib.mkOption {
+        type = with lib.types; nullOr (listOf str);
+        default = null;
+        example = [ "speed" "sub-delay" "audio-delay" ];
+        description = ''
+          mpv properties that can be read and written through `/api/property/<name>`.
+          If not set, a few playback and picture adjustments are exposed.
+        '';
+      };
+
+      mpv-connect-attempts = lib.mkOption {
+        type = lib.types.ints.positive;
+        default = 20;
+        description = ''
+          How many times to try connecting to mpv before giving up.
+        '';
+      };
+
+      mpv-connect-timeout = lib.mkOption {
+        type = lib.types.ints.positive;
+        default = 10;
+        description = ''
+          How long to wait for mpv to accept a connection, in seconds, before giving up.
+        '';
+      };
+
+      mpv-log-file = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "/var/log/greg-ng/mpv.log";
+        description = ''
+          Have mpv write a detailed log to this file.
+        '';
+      };
+
+      takeover = lib.mkOption {
+        type = lib.types.enum [ "refuse" "steal" "read-only" ];
+        default = "refuse";
+        description = ''
+          What to do when another greg-ng instance is already in charge of mpv:
+          exit, ask it to shut down and take over its mpv, or share mpv without
+          changing anything.
+        '';
+      };
+
+      no-splash = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = ''
+          Don't show the Grzegorz image when mpv is idle on startup.
+        '';
+      };
+
+      no-wake-on-queue = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = ''
+          Don't start playing when something is queued while the idle screen is
+          showing, and wait for someone to press play instead.
+        '';
+      };
+
+      player-timeout = lib.mkOption {
+        type = lib.types.ints.positive;
+        default = 5;
+        description = ''
+          Give up on calls to the player that take longer than this many seconds.
+          After a few in a row, calls fail right away for a while, so that requests
+          don't pile up.
+        '';
+      };
+
+      exit-when-unresponsive = lib.mkOption {
+        type = lib.types.ints.unsigned;
+        default = 60;
+        description = ''
+          Exit when mpv has not responded for this many seconds, so that systemd
+          starts it over. 0 never exits.
+        '';
+      };
+
+      policy = lib.mkOption {
+        type = with lib.types; nonEmptyListOf (enum [ "read-only" "control-lock" "scopes" "quota" ]);
+        default = [ "read-only" "control-lock" "scopes" "quota" ];
+        example = [ "read-only" ];
+        description = ''
+          The checks every change goes through, in order. Leave out the ones this
+          deployment does not want.
+        '';
+      };
+
+      quota-limit = lib.mkOption {
+        type = with lib.types; nullOr ints.positive;
+        default = null;
+        example = 10;
+        description = ''
+          How many items each client can add within about a half-life. Clients that
+          have not logged in are told apart by their address and user agent. Without
+          it, how much each client adds is only tracked.
+        '';
+      };
+
+      quota-half-life = lib.mkOption {
+        type = lib.types.ints.positive;
+        default = 30;
+        example = 60;
+        description = ''
+          How many minutes it takes for half of what a client has added to stop counting.
+        '';
+      };
+
+      fair-queue = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = ''
+          Let clients take turns in the playlist, so that items go ahead of the items
+          of clients that have had more turns. Clients are told apart like for
+          quota-limit, so this needs the quota policy.
+        '';
+      };
+
+      queue-window = lib.mkOption {
+        type = with lib.types; nullOr ints.positive;
+        default = null;
+        example = 3;
+        description = ''
+          Only keep the current item and this many after it in mpv, and hold the
+          rest of the queue in greg-ng until mpv gets to them. Held items survive
+          mpv restarting, but not greg-ng.
+        '';
+      };
+
+      headless = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = ''
+          Start mpv without a window, for hosts without a graphical session.
+          Audio is still played.
+        '';
+      };
+
+      admin-token-file = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "/run/secrets/greg-ng-admin-token";
+        description = ''
+          A file containing the token required by the admin API. If this is not
+          set, and admins can not log in through oidc-admin-group, the admin
+          endpoints, like raw mpv commands, bans, and pinning and locking playlist
+          items, are disabled.
+        '';
+      };
+
+      oidc-issuer = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "https://auth.pvv.ntnu.no/oauth2/openid/greg-ng";
+        description = ''
+          Let people log in with this OpenID Connect provider. The items they
+          queue are credited to them. Requires oidc-client-id and oidc-redirect-url.
+        '';
+      };
+
+      oidc-client-id = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "greg-ng";
+        description = ''
+          The client id greg-ng is registered with at the provider.
+        '';
+      };
+
+      oidc-client-secret-file = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "/run/secrets/greg-ng-oidc-secret";
+        description = ''
+          A file containing the client secret greg-ng is registered with at the
+          provider, for confidential clients.
+        '';
+      };
+
+      oidc-redirect-url = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "https://greg.pvv.ntnu.no/api/auth/callback";
+        description = ''
+          Where the provider sends people back to after logging in, which is
+          `/api/auth/callback` on the address greg-ng is reached at.
+        '';
+      };
+
+      oidc-admin-group = lib.mkOption {
+        type = with lib.types; listOf str;
+        default = [ ];
+        example = [ "drift" ];
+        description = ''
+          Members of these groups count as admins, as if they gave the admin token.
+        '';
+      };
+
+      oidc-groups-claim = lib.mkOption {
+        type = lib.types.str;
+        default = "groups";
+        description = ''
+          The claim of the ID token that lists the groups of the user.
+        '';
+      };
+
+      mpv-command-allow = lib.mkOption {
+        type = with lib.types; listOf str;
+        default = [ ];
+        example = [ "get_property" "set_property" ];
+        description = ''
+          mpv commands to allow through the admin API. If empty, every command
+          that is not denied is allowed.
+        '';
+      };
+
+      mpv-command-deny = lib.mkOption {
+        type = with lib.types; nullOr (listOf str);
+        default = null;
+        example = [ "run" "subprocess" "quit" ];
+        description = ''
+          mpv commands to deny through the admin API. If not set, commands that
+          run programs, load scripts, write files or quit mpv are denied.
+        '';
+      };
+
+      clear-confirm-threshold = lib.mkOption {
+        type = with lib.types; nullOr ints.unsigned;
+        default = null;
+        example = 50;
+        description = ''
+          Require confirmation before clearing a playlist with more than this many items.
+        '';
+      };
+
+      data-dir = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "/var/lib/greg-ng";
+        description = ''
+          Where to keep persistent data, like saved playlists.
+          Defaults to `$XDG_STATE_HOME/greg-ng`.
+        '';
+      };
+
+      db-path = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "/var/lib/greg-ng/greg.db";
+        description = ''
+          Where to keep the SQLite database with saved playlists and the play history.
+          Defaults to `greg.db` in the data directory.
+        '';
+      };
+
+      runtime-dir = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        example = "/run/user/1000/greg-ng";
+        description = ''
+          Where the mpv socket and other files that only live as long as greg-ng are kept.
+          Defaults to `/run/mpv`.
+        '';
+      };
+
       mpv-socket-path = lib.mkOption {
         type = lib.types.str;
         default = "%t/greg-ng-mpv.sock";
@@ -90,6 +800,32 @@ in
           Whether to force auto starting mpv.
         '';
       };
+
+      frontend-dir = lib.mkOption {
+        type = with lib.types; nullOr (either path str);
+        default = null;
+        example = "/srv/greg-ng/web";
+        description = ''
+          Directory containing a web UI to serve at `/`, alongside the APIs.
+        '';
+      };
+
+      tls-cert = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        description = ''
+          Path to a PEM encoded TLS certificate. If set together with tls-key,
+          the APIs are served over HTTPS and WSS.
+        '';
+      };
+
+      tls-key = lib.mkOption {
+        type = with lib.types; nullOr str;
+        default = null;
+        description = ''
+          Path to the PEM encoded private key for tls-cert.
+        '';
+      };
     };
   };
 
@@ -97,8 +833,8 @@ in
     (lib.mkIf cfg.enable {
       systemd.user.services.greg-ng = {
         description = "greg-ng, an mpv based media player";
-        wantedBy = [ "graphical-session.target" ];
-        partOf = [ "graphical-session.target" ];
+        wantedBy = [ (if cfg.settings.headless then "default.target" else "graphical-session.target") ];
+        partOf = lib.optional (!cfg.settings.headless) "graphical-session.target";
         serviceConfig = {
           Type = "notify";
           ExecStart = let
@@ -152,6 +888,14 @@ in
         };
       };
     })
+    (lib.mkIf (cfg.enable && cfg.listenStreams != [ ]) {
+      systemd.user.sockets.greg-ng = {
+        description = "greg-ng API sockets";
+        wantedBy = [ "sockets.target" ];
+        listenStreams = cfg.listenStreams;
+        socketConfig.SocketMode = "0660";
+      };
+    })
     (lib.mkIf (cfg.enable && cfg.enablePipewire) {
       services.pipewire = {
         enable = true;
@@ -0,0 +1,227 @@
+//! Announcements, like a sponsor jingle every 30 minutes during events. They are kept
+//! apart from the playlist, and one of them is slipped in after the item playing now
+//! whenever the interval has passed, taking turns in the order they were given.
+//!
+//! Nothing is announced while nothing is playing, and the interval starts over when
+//! announcements are turned on.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::sync::Notify;
+
+use crate::{
+    api::ApiError,
+    player::{self, ItemState, PlayerHandle, PlaylistMove},
+};
+
+/// How often it is checked whether an announcement is due.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct State {
+    enabled: bool,
+    interval: Duration,
+    /// When the last announcement was slipped in, or announcements were turned on.
+    last: Instant,
+    /// The clip that is up next.
+    next: usize,
+}
+
+/// What `/api/announcements` shows.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnnouncementStatus {
+    pub enabled: bool,
+    pub interval_minutes: u64,
+    pub clips: Vec<String>,
+    /// The clip that is slipped in next.
+    pub next_clip: Option<String>,
+    /// How many seconds are left until it is, while announcements are on.
+    pub next_in: Option<u64>,
+}
+
+/// A shared handle for turning announcements on and off, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct Announcements {
+    clips: Arc<Vec<String>>,
+    state: Arc<Mutex<State>>,
+    changed: Arc<Notify>,
+}
+
+impl Announcements {
+    pub fn new(clips: Vec<String>, interval: Duration, enabled: bool) -> Self {
+        Self {
+            clips: Arc::new(clips),
+            state: Arc::new(Mutex::new(State {
+                enabled,
+                interval,
+                last: Instant::now(),
+                next: 0,
+            })),
+            changed: Arc::default(),
+        }
+    }
+
+    pub fn status(&self) -> AnnouncementStatus {
+        let state = self.state.lock().unwrap();
+        AnnouncementStatus {
+            enabled: state.enabled,
+            interval_minutes: state.interval.as_secs() / 60,
+            clips: self.clips.to_vec(),
+            next_clip: self.clips.get(state.next).cloned(),
+            next_in: state.enabled.then(|| {
+                state
+                    .interval
+                    .saturating_sub(state.last.elapsed())
+                    .as_secs()
+            }),
+        }
+    }
+
+    /// Turn announcements on or off, and change how often they are made.
+    pub fn set(&self, enabled: bool, interval: Option<Duration>) -> anyhow::Result<()> {
+        if enabled && self.clips.is_empty() {
+            return Err(ApiError::Conflict("No announcements are configured".to_string()).into());
+        }
+        if interval.is_some_and(|interval| interval.is_zero()) {
+            return Err(ApiError::InvalidArgument(
+                "The interval must be at least a minute".to_string(),
+            )
+            .into());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if enabled && !state.enabled {
+            state.last = Instant::now();
+        }
+        state.enabled = enabled;
+        if let Some(interval) = interval {
+            state.interval = interval;
+        }
+        log::info!(
+            "Announcements {}, every {} minutes",
+            if enabled { "enabled" } else { "disabled" },
+            state.interval.as_secs() / 60
+        );
+        drop(state);
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    /// The clip to slip in at `now`, if one is due.
+    fn due(&self, now: Instant) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        if !state.enabled || now.saturating_duration_since(state.last) < state.interval {
+            return None;
+        }
+        self.clips.get(state.next).cloned()
+    }
+
+    /// Start the interval over at `now`, with the next clip up.
+    fn announced(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        state.last = now;
+        state.next = (state.next + 1) % self.clips.len().max(1);
+    }
+}
+
+/// Add `clip` right after the item playing now. Returns whether there was one.
+async fn announce(player: &PlayerHandle, clip: &str) -> anyhow::Result<bool> {
+    let _lock = player::lock_playlist(player.queue_state()).await;
+    let playlist = player.playlist().await?;
+    let Some(current) = playlist.iter().position(|entry| entry.current) else {
+        return Ok(false);
+    };
+    if !player.is_playing().await? {
+        return Ok(false);
+    }
+
+    player.load(clip, ItemState::default()).await?;
+    let playlist = player.playlist().await?;
+    let added = playlist.len() - 1;
+    let moves = [PlaylistMove {
+        from: added,
+        to: current + 1,
+    }];
+    if added > current + 1 {
+        match player::check_moves(player.queue_state(), &playlist, &moves) {
+            Ok(()) => player.playlist_move(added, current + 1).await?,
+            Err(e) => log::debug!("Leaving the announcement at the end: {}", e),
+        }
+    }
+    log::info!("Announcing '{}' after the current item", clip);
+    Ok(true)
+}
+
+/// Keep slipping announcements in after the item playing now, while they are on.
+pub async fn run_announcements(player: PlayerHandle, announcements: Announcements) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = announcements.changed.notified() => {}
+        }
+
+        // A due announcement waits for something to play, instead of being skipped.
+        let now = Instant::now();
+        let Some(clip) = announcements.due(now) else {
+            continue;
+        };
+        match announce(&player, &clip).await {
+            Ok(true) => announcements.announced(now),
+            Ok(false) => {}
+            Err(e) => log::warn!("Failed to make an announcement: {:#}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_announcements() {
+        let minute = Duration::from_secs(60);
+        let announcements = Announcements::new(
+            vec!["sponsor.ogg".to_string(), "jingle.ogg".to_string()],
+            30 * minute,
+            true,
+        );
+        let start = announcements.state.lock().unwrap().last;
+
+        let take_due = |now: Instant| {
+            let clip = announcements.due(now);
+            if clip.is_some() {
+                announcements.announced(now);
+            }
+            clip
+        };
+        assert_eq!(take_due(start + 29 * minute), None);
+        assert_eq!(
+            take_due(start + 30 * minute).as_deref(),
+            Some("sponsor.ogg")
+        );
+        assert_eq!(take_due(start + 31 * minute), None);
+        // It stays due until it is announced.
+        assert!(announcements.due(start + 60 * minute).is_some());
+        assert_eq!(take_due(start + 61 * minute).as_deref(), Some("jingle.ogg"));
+        assert_eq!(
+            take_due(start + 91 * minute).as_deref(),
+            Some("sponsor.ogg")
+        );
+
+        announcements.set(false, Some(minute)).unwrap();
+        assert_eq!(announcements.due(start + 200 * minute), None);
+        assert_eq!(announcements.status().interval_minutes, 1);
+        assert!(announcements.set(true, Some(Duration::ZERO)).is_err());
+
+        let none = Announcements::new(vec![], minute, false);
+        assert!(none.set(true, None).is_err());
+        assert!(none.set(false, None).is_ok());
+    }
+}
@@ -1,6 +1,180 @@
+use axum::Router;
+use utoipa::openapi::{OpenApi, server::Server};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{
+    autoplay::Autoplay,
+    player::{PlayerHandle, PlaylistClearGuard, RetryQueue},
+    resolver::ResolverChain,
+    volume_transition::VolumeTransitionEngine,
+};
+
+mod admin;
+mod announcements;
+mod asyncapi;
+mod autoplay;
 mod base;
+mod blocks;
+mod bookmarks;
+mod control;
+mod control_page;
+mod deferred;
+mod display;
+mod error;
+mod event_broadcast;
+mod events;
+mod health;
+mod history;
+mod inputs;
+mod library;
+mod load_check;
+mod lyrics;
+mod me;
+mod party;
+mod playlist_ops;
+mod playlists;
+mod policy;
+mod property;
+mod queue_ahead;
+mod radio;
+mod remote;
+mod rest_endpoints;
 mod rest_wrapper_v1;
+mod rest_wrapper_v2;
+mod resume;
+mod soundboard;
+mod sse;
+mod stats;
+mod status;
+mod upload;
 mod websocket_v1;
+mod ws_encoding;
+mod ws_outbox;
 
-pub use rest_wrapper_v1::{rest_api_docs, rest_api_routes};
+pub use admin::{AdminAuth, DEFAULT_DENIED_COMMANDS, MpvCommandPolicy, admin_routes};
+pub use announcements::announcement_routes;
+pub use autoplay::autoplay_routes;
+pub use blocks::block_routes;
+pub use bookmarks::bookmark_routes;
+pub use control::control_routes;
+pub use control_page::control_page_routes;
+pub use deferred::release_deferred_loads;
+pub use display::display_routes;
+pub use error::ApiError;
+pub use event_broadcast::EventBroadcast;
+pub use health::health_routes;
+pub use history::history_routes;
+pub use inputs::inputs_routes;
+pub use library::library_routes;
+pub use load_check::load_check_routes;
+pub use lyrics::lyrics_routes;
+pub use me::me_routes;
+pub use party::party_routes;
+pub use playlist_ops::playlist_ops_routes;
+pub use playlists::playlist_routes;
+pub use policy::{FromAdmin, reject_by_policy};
+pub use property::{DEFAULT_PROPERTIES, property_routes};
+pub use queue_ahead::{PendingLoads, flush_queued_loads};
+pub use radio::radio_routes;
+pub use remote::{Remote, remote_routes};
+pub use rest_wrapper_v1::rest_api_routes;
+pub use rest_wrapper_v2::rest_api_v2_routes;
+pub use resume::resume_routes;
+pub use soundboard::soundboard_routes;
+pub use sse::event_stream_routes;
+pub use stats::stats_routes;
+pub use status::{StatusTracker, revision_etag, status_routes};
+pub use upload::upload_routes;
 pub use websocket_v1::websocket_api;
+
+pub fn rest_api_docs(
+    player: PlayerHandle,
+    volume_engine: VolumeTransitionEngine,
+    clear_guard: PlaylistClearGuard,
+    retries: RetryQueue,
+    autoplay: Autoplay,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+) -> Router {
+    let (router, api) = rest_wrapper_v1::rest_api_docs_parts(
+        player,
+        volume_engine,
+        clear_guard,
+        retries,
+        autoplay,
+        resolvers,
+        pending,
+    );
+
+    router.merge(
+        SwaggerUi::new("/docs")
+            .url("/docs/openapi.json", api)
+            .url("/docs/v2/openapi.json", rest_api_v2_openapi()),
+    )
+}
+
+/// The v2 API, along with the routes of the other features, which are served under `/api`
+/// itself rather than under `/api/v2`.
+fn rest_api_v2_openapi() -> OpenApi {
+    let mut api = rest_wrapper_v2::rest_api_v2_openapi();
+    for mut feature in [
+        bookmarks::bookmark_openapi(),
+        playlists::playlist_openapi(),
+        blocks::block_openapi(),
+        stats::stats_openapi(),
+        history::history_openapi(),
+        radio::radio_openapi(),
+        soundboard::soundboard_openapi(),
+        inputs::inputs_openapi(),
+        property::property_openapi(),
+        control::control_openapi(),
+    ] {
+        for item in feature.paths.paths.values_mut() {
+            item.servers = Some(vec![Server::new("/")]);
+        }
+        api.merge(feature);
+    }
+    api
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_routes_are_documented() {
+        let api = rest_api_v2_openapi();
+
+        let bookmark = &api.paths.paths["/api/bookmarks/{id}"];
+        assert!(bookmark.servers == Some(vec![Server::new("/")]));
+        let parameters = bookmark
+            .delete
+            .as_ref()
+            .unwrap()
+            .parameters
+            .as_ref()
+            .unwrap();
+        assert!(parameters.iter().any(|parameter| parameter.name == "id"));
+        let property = &api.paths.paths["/api/property/{name}"];
+        assert!(property.post.as_ref().unwrap().request_body.is_some());
+
+        // The v2 API itself is still served under its own prefix.
+        assert!(api.paths.paths["/load"].servers.is_none());
+
+        let mut operation_ids: Vec<_> = api
+            .paths
+            .paths
+            .values()
+            .flat_map(|item| {
+                [&item.get, &item.post, &item.delete]
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|operation| operation.operation_id.clone())
+            })
+            .collect();
+        let count = operation_ids.len();
+        operation_ids.sort();
+        operation_ids.dedup();
+        assert_eq!(operation_ids.len(), count, "operation ids should be unique");
+    }
+}
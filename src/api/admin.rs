@@ -0,0 +1,873 @@
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, Request, State, rejection::JsonRejection, rejection::QueryRejection},
+    http::{HeaderMap, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use mpvipc_async::Mpv;
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::{
+    api_keys::ApiKeys,
+    bans::BanList,
+    idle_policy::{IdleAction, IdlePolicy, IdlePolicyHandle},
+    mpv_log::{self, MpvLog},
+    oidc::AuthenticatedUser,
+    player::{self, ItemFlags},
+    quotas::Quotas,
+    read_only::ReadOnlyMode,
+    scopes::Scope,
+    util::ClientRegistry,
+    volume_transition::VolumeTransitionEngine,
+};
+
+use super::error::ApiError;
+
+/// How many lines of the mpv log are returned by default.
+const DEFAULT_LOG_LINES: usize = 200;
+
+/// mpv commands that are denied unless `--mpv-command-deny` is given, since they run
+/// programs, load code, write files or stop the player.
+pub const DEFAULT_DENIED_COMMANDS: [&str; 7] = [
+    "run",
+    "subprocess",
+    "load-script",
+    "quit",
+    "quit-watch-later",
+    "screenshot-to-file",
+    "dump-cache",
+];
+
+/// Which mpv commands can be sent through `/api/admin/mpv-command`.
+#[derive(Debug, Clone, Default)]
+pub struct MpvCommandPolicy {
+    /// If not empty, only these commands are allowed.
+    pub allow: Vec<String>,
+    /// These commands are never allowed.
+    pub deny: Vec<String>,
+}
+
+impl MpvCommandPolicy {
+    fn permits(&self, command: &str) -> bool {
+        let command = normalize_command(command);
+        let listed = |list: &[String]| list.iter().any(|c| normalize_command(c) == command);
+
+        (self.allow.is_empty() || listed(&self.allow)) && !listed(&self.deny)
+    }
+}
+
+/// mpv accepts both `set_property` and `set-property` style names for some commands.
+fn normalize_command(command: &str) -> String {
+    command.trim().to_ascii_lowercase().replace('_', "-")
+}
+
+/// What makes a request come from an admin: the admin token, or having logged in as a member
+/// of one of the admin groups.
+#[derive(Debug, Clone, Default)]
+pub struct AdminAuth {
+    token: Option<Arc<str>>,
+    /// Whether anyone can log in as an admin.
+    oidc_admins: bool,
+}
+
+impl AdminAuth {
+    pub fn new(token: Option<String>, oidc_admins: bool) -> Self {
+        Self {
+            token: token.map(Into::into),
+            oidc_admins,
+        }
+    }
+
+    /// Whether anyone can be an admin at all.
+    pub fn exists(&self) -> bool {
+        self.token.is_some() || self.oidc_admins
+    }
+}
+
+#[derive(Debug, Clone)]
+struct AdminState {
+    mpv: Mpv,
+    policy: Arc<MpvCommandPolicy>,
+    log: MpvLog,
+    clients: ClientRegistry,
+    bans: BanList,
+    keys: ApiKeys,
+    quotas: Quotas,
+    read_only: ReadOnlyMode,
+    idle_policy: IdlePolicyHandle,
+    volume_engine: VolumeTransitionEngine,
+}
+
+/// Routes for administrating the player, under `/api/admin`: kicking and banning clients,
+/// seeing how much each client has added lately, turning read-only mode on and off, capping
+/// the volume for a while, pinning and locking playlist items, sending raw mpv commands,
+/// handing out API keys, reading the mpv log and changing what happens to background music
+/// when nobody is connected.
+///
+/// They are all only for admins, as checked by [`check_token`]: every request needs the
+/// admin token as `Authorization: Bearer <token>`, or has to be from an admin who logged
+/// in. None of them are served when nobody can be an admin.
+#[allow(clippy::too_many_arguments)]
+pub fn admin_routes(
+    mpv: Mpv,
+    auth: AdminAuth,
+    policy: MpvCommandPolicy,
+    log: MpvLog,
+    clients: ClientRegistry,
+    bans: BanList,
+    keys: ApiKeys,
+    quotas: Quotas,
+    read_only: ReadOnlyMode,
+    idle_policy: IdlePolicyHandle,
+    volume_engine: VolumeTransitionEngine,
+) -> Router {
+    // Nobody can be let in, so there is nothing to serve.
+    if !auth.exists() {
+        return Router::new();
+    }
+    let router = Router::new()
+        .route("/api/admin/connections", get(connections))
+        .route("/api/admin/connections/delivery", get(delivery_stats))
+        .route("/api/admin/connections/{id}/kick", post(kick))
+        .route("/api/admin/bans", get(list_bans))
+        .route("/api/admin/bans/{address}", post(ban).delete(unban))
+        .route("/api/admin/quotas", get(list_quotas))
+        .route(
+            "/api/admin/readonly",
+            get(get_read_only).post(set_read_only),
+        )
+        .route(
+            "/api/admin/volume-cap",
+            get(get_volume_cap)
+                .post(set_volume_cap)
+                .delete(clear_volume_cap),
+        )
+        .route("/api/admin/playlist/flags", post(set_playlist_flags))
+        .route("/api/admin/mpv-command", post(mpv_command))
+        .route("/api/admin/keys", get(list_keys).post(create_key))
+        .route("/api/admin/keys/{id}", post(relabel_key).delete(revoke_key))
+        .route(
+            "/api/admin/idle-policy",
+            get(get_idle_policy).post(set_idle_policy),
+        )
+        .route("/api/admin/mpv-log", get(mpv_log))
+        .route_layer(middleware::from_fn_with_state(auth, require_admin));
+
+    router.with_state(AdminState {
+        mpv,
+        policy: Arc::new(policy),
+        log,
+        clients,
+        bans,
+        keys,
+        quotas,
+        read_only,
+        idle_policy,
+        volume_engine,
+    })
+}
+
+/// Refuse the requests that are not from an admin, see [`is_admin`].
+async fn require_admin(State(auth): State<AdminAuth>, request: Request, next: Next) -> Response {
+    let user = request.extensions().get::<AuthenticatedUser>();
+    if let Err(e) = check_token(request.headers(), user, &auth) {
+        return e.into_response();
+    }
+    next.run(request).await
+}
+
+/// Whether the request has the admin token, or is from someone who logged in with the
+/// admin role. Unlike [`check_token`], nobody is an admin if nobody can be.
+pub(super) fn is_admin(
+    headers: &HeaderMap,
+    user: Option<&AuthenticatedUser>,
+    auth: &AdminAuth,
+) -> bool {
+    auth.exists() && check_token(headers, user, auth).is_ok()
+}
+
+/// Fail unless the request has the admin token, or is from someone who logged in with the
+/// admin role. Anyone passes if nobody can be an admin. Those who logged in without the
+/// admin role are [forbidden](ApiError::Forbidden), everyone else is
+/// [unauthorized](ApiError::Unauthorized).
+pub(super) fn check_token(
+    headers: &HeaderMap,
+    user: Option<&AuthenticatedUser>,
+    auth: &AdminAuth,
+) -> Result<(), ApiError> {
+    if user.is_some_and(AuthenticatedUser::is_admin) || !auth.exists() {
+        return Ok(());
+    }
+    let refused = |message: &str| match user {
+        Some(_) => ApiError::Forbidden("Only admins can do this".to_string()),
+        None => ApiError::Unauthorized(message.to_string()),
+    };
+    let Some(token) = auth.token.as_deref() else {
+        return Err(refused("Logging in as an admin is required"));
+    };
+
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| refused("An admin token is required"))?;
+
+    // Compare every byte, so the time taken does not reveal how much of the token was right.
+    let matches = given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0;
+
+    if matches {
+        Ok(())
+    } else {
+        Err(refused("Invalid admin token"))
+    }
+}
+
+#[derive(Deserialize)]
+struct MpvCommandBody {
+    command: Vec<Value>,
+}
+
+/// Run an mpv JSON IPC command, like `{ "command": ["get_property", "volume"] }`,
+/// and return its raw result.
+async fn mpv_command(
+    State(state): State<AdminState>,
+    body: Result<Json<MpvCommandBody>, JsonRejection>,
+) -> Response {
+    let MpvCommandBody { command } = match body {
+        Ok(Json(body)) => body,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+
+    let Some((name, args)) = command.split_first() else {
+        return ApiError::InvalidArgument("The command can not be empty".to_string())
+            .into_response();
+    };
+    let Some(name) = name.as_str() else {
+        return ApiError::InvalidArgument("The command name must be a string".to_string())
+            .into_response();
+    };
+
+    if !state.policy.permits(name) {
+        return ApiError::PolicyViolation(format!("The mpv command '{}' is not allowed", name))
+            .into_response();
+    }
+
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| match arg {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })
+        .collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    log::info!(
+        "Running mpv command from the admin API: {} {:?}",
+        name,
+        args
+    );
+
+    match state.mpv.run_command_raw(name, &args).await {
+        Ok(value) => Json(json!({ "success": true, "value": value })).into_response(),
+        Err(e) => ApiError::from(anyhow::Error::from(e)).into_response(),
+    }
+}
+
+/// List the connected websocket clients, with their name and version if they gave one,
+/// their address, when they connected, when they last sent something and how many
+/// messages to them were dropped for being too slow
+async fn connections(State(state): State<AdminState>) -> Response {
+    Json(json!({ "success": true, "value": state.clients.list() })).into_response()
+}
+
+/// How many messages were dropped before reaching websocket clients that were too slow to
+/// take them, and how many clients were disconnected for not keeping up at all
+async fn delivery_stats(State(state): State<AdminState>) -> Response {
+    Json(json!({ "success": true, "value": state.clients.delivery_stats() })).into_response()
+}
+
+/// Close the websocket connection of a client, by the id listed in `/api/admin/connections`
+async fn kick(State(state): State<AdminState>, Path(id): Path<u64>) -> Response {
+    if !state.clients.kick(id) {
+        return ApiError::NotFound(format!("No client is connected with id {}", id))
+            .into_response();
+    }
+    Json(json!({ "success": true, "value": null })).into_response()
+}
+
+/// List the banned addresses, with when their bans expire
+async fn list_bans(State(state): State<AdminState>) -> Response {
+    Json(json!({ "success": true, "value": state.bans.list() })).into_response()
+}
+
+fn parse_address(address: &str) -> Result<IpAddr, ApiError> {
+    address
+        .parse::<IpAddr>()
+        .map(|address| address.to_canonical())
+        .map_err(|_| ApiError::InvalidArgument(format!("Invalid IP address '{}'", address)))
+}
+
+#[derive(Deserialize)]
+struct BanArgs {
+    /// How many seconds the ban lasts. Without it, the ban lasts until it is lifted.
+    ttl: Option<u64>,
+    reason: Option<String>,
+}
+
+/// Ban an IP address from the whole API, closing its websocket connections
+async fn ban(
+    State(state): State<AdminState>,
+    Path(address): Path<String>,
+    query: Result<Query<BanArgs>, QueryRejection>,
+) -> Response {
+    let BanArgs { ttl, reason } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+    let address = match parse_address(&address) {
+        Ok(address) => address,
+        Err(e) => return e.into_response(),
+    };
+
+    match state
+        .bans
+        .ban(address, ttl.map(Duration::from_secs), reason)
+    {
+        Ok(ban) => {
+            let kicked = state.clients.kick_address(&address);
+            if kicked > 0 {
+                log::info!("Kicking {} clients connected from {}", kicked, address);
+            }
+            Json(json!({ "success": true, "value": ban })).into_response()
+        }
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+/// Lift the ban of an IP address
+async fn unban(State(state): State<AdminState>, Path(address): Path<String>) -> Response {
+    let address = match parse_address(&address) {
+        Ok(address) => address,
+        Err(e) => return e.into_response(),
+    };
+    match state.bans.unban(&address) {
+        Ok(true) => Json(json!({ "success": true, "value": null })).into_response(),
+        Ok(false) => ApiError::NotFound(format!("{} is not banned", address)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+/// List how much each client has added lately, most first, with the limit if there is one.
+/// Clients who have not logged in are listed by a hash of their address and user agent.
+async fn list_quotas(State(state): State<AdminState>) -> Response {
+    Json(json!({
+        "success": true,
+        "value": { "limit": state.quotas.limit(), "entries": state.quotas.list() },
+    }))
+    .into_response()
+}
+
+/// List the API keys, without the keys themselves
+async fn list_keys(State(state): State<AdminState>) -> Response {
+    Json(json!({ "success": true, "value": state.keys.list() })).into_response()
+}
+
+#[derive(Deserialize)]
+struct CreateKeyArgs {
+    label: String,
+    /// Like `queue:add,playback:control`. Without it, the key can do what anyone can.
+    scope: Option<String>,
+}
+
+/// Make an API key for a bot. The key is only shown in the response, and is given as
+/// `Authorization: Bearer <key>`.
+async fn create_key(
+    State(state): State<AdminState>,
+    query: Result<Query<CreateKeyArgs>, QueryRejection>,
+) -> Response {
+    let CreateKeyArgs { label, scope } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+    let scopes = match scope {
+        Some(scope) => match Scope::parse_list(&scope) {
+            Ok(scopes) => scopes,
+            Err(e) => return ApiError::InvalidArgument(e).into_response(),
+        },
+        None => Scope::EVERYONE.to_vec(),
+    };
+    match state.keys.create(&label, scopes) {
+        Ok(key) => Json(json!({ "success": true, "value": key })).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RelabelKeyArgs {
+    label: String,
+}
+
+/// Change the label of an API key, which is what the items it adds are credited to
+async fn relabel_key(
+    State(state): State<AdminState>,
+    Path(id): Path<u64>,
+    query: Result<Query<RelabelKeyArgs>, QueryRejection>,
+) -> Response {
+    let RelabelKeyArgs { label } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+    match state.keys.relabel(id, &label) {
+        Ok(key) => Json(json!({ "success": true, "value": key })).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+/// Revoke an API key. Requests with it are treated like requests without one from then on.
+async fn revoke_key(State(state): State<AdminState>, Path(id): Path<u64>) -> Response {
+    match state.keys.revoke(id) {
+        Ok(true) => Json(json!({ "success": true, "value": null })).into_response(),
+        Ok(false) => ApiError::NotFound(format!("No API key with id {}", id)).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+/// Get whether read-only mode is on
+async fn get_read_only(State(state): State<AdminState>) -> Response {
+    Json(json!({ "success": true, "value": state.read_only.is_enabled() })).into_response()
+}
+
+#[derive(Deserialize)]
+struct ReadOnlyArgs {
+    enabled: bool,
+}
+
+/// Turn read-only mode on or off. While it is on, only admins can change anything, and
+/// websocket clients can only watch.
+///
+/// This fails with 409 if read-only mode is left out of `--policy`.
+async fn set_read_only(
+    State(state): State<AdminState>,
+    query: Result<Query<ReadOnlyArgs>, QueryRejection>,
+) -> Response {
+    let ReadOnlyArgs { enabled } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+    if !state.read_only.is_applied() {
+        return ApiError::Conflict(
+            "Read-only mode is not one of the --policy policies, so it would change nothing"
+                .to_string(),
+        )
+        .into_response();
+    }
+    state.read_only.set_enabled(enabled);
+    Json(json!({ "success": true, "value": null })).into_response()
+}
+
+/// Get the volume cap, if there is one
+async fn get_volume_cap(State(state): State<AdminState>) -> Response {
+    Json(json!({ "success": true, "value": state.volume_engine.volume_cap() })).into_response()
+}
+
+/// The longest a volume cap can last, so that a forgotten one does not last forever.
+const MAX_VOLUME_CAP_DURATION: Duration = Duration::from_secs(4 * 60 * 60);
+
+#[derive(Deserialize)]
+struct VolumeCapArgs {
+    max_volume: f64,
+    duration_secs: u64,
+}
+
+/// Cap the volume of the player for a while, overriding every volume command until it
+/// expires. Clients are told about the cap, so their sliders can show it.
+async fn set_volume_cap(
+    State(state): State<AdminState>,
+    query: Result<Query<VolumeCapArgs>, QueryRejection>,
+) -> Response {
+    let VolumeCapArgs {
+        max_volume,
+        duration_secs,
+    } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+    if !(0.0..=100.0).contains(&max_volume) {
+        return ApiError::InvalidArgument("max_volume must be between 0 and 100".to_string())
+            .into_response();
+    }
+    let duration = Duration::from_secs(duration_secs);
+    if duration > MAX_VOLUME_CAP_DURATION {
+        return ApiError::InvalidArgument(format!(
+            "duration_secs can be at most {}",
+            MAX_VOLUME_CAP_DURATION.as_secs()
+        ))
+        .into_response();
+    }
+    match state
+        .volume_engine
+        .set_volume_cap(max_volume, duration)
+        .await
+    {
+        Ok(()) => Json(json!({ "success": true, "value": null })).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+/// Lift the volume cap before it expires
+async fn clear_volume_cap(State(state): State<AdminState>) -> Response {
+    state.volume_engine.clear_volume_cap().await;
+    Json(json!({ "success": true, "value": null })).into_response()
+}
+
+/// Get what happens to background music when no clients have been connected for a while
+async fn get_idle_policy(State(state): State<AdminState>) -> Response {
+    Json(json!({ "success": true, "value": state.idle_policy.get() })).into_response()
+}
+
+#[derive(Deserialize)]
+struct IdlePolicyArgs {
+    /// 0 turns the idle policy off.
+    after_minutes: Option<u64>,
+    action: Option<IdleAction>,
+    volume: Option<f64>,
+}
+
+/// Change what happens to background music when no clients have been connected for some
+/// minutes. Settings that are left out are kept as they are, and 0 minutes turns it off.
+async fn set_idle_policy(
+    State(state): State<AdminState>,
+    query: Result<Query<IdlePolicyArgs>, QueryRejection>,
+) -> Response {
+    let IdlePolicyArgs {
+        after_minutes,
+        action,
+        volume,
+    } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+    if let Some(volume) = volume
+        && !(0.0..=100.0).contains(&volume)
+    {
+        return ApiError::InvalidArgument("The volume must be between 0 and 100".to_string())
+            .into_response();
+    }
+
+    let current = state.idle_policy.get();
+    let policy = IdlePolicy {
+        after_minutes: match after_minutes {
+            Some(0) => None,
+            Some(minutes) => Some(minutes),
+            None => current.after_minutes,
+        },
+        action: action.unwrap_or(current.action),
+        volume: volume.unwrap_or(current.volume),
+    };
+    state.idle_policy.set(policy);
+    Json(json!({ "success": true, "value": policy })).into_response()
+}
+
+#[derive(Deserialize)]
+struct MpvLogArgs {
+    lines: Option<usize>,
+}
+
+/// Get the most recent output of the mpv process started by greg-ng
+async fn mpv_log(
+    State(state): State<AdminState>,
+    query: Result<Query<MpvLogArgs>, QueryRejection>,
+) -> Response {
+    let MpvLogArgs { lines } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+    let lines = lines.unwrap_or(DEFAULT_LOG_LINES).min(mpv_log::MAX_LINES);
+
+    Json(json!({ "success": true, "value": state.log.recent(lines) })).into_response()
+}
+
+#[derive(Deserialize)]
+struct PlaylistFlagsArgs {
+    index: usize,
+    pinned: Option<bool>,
+    locked: Option<bool>,
+    background: Option<bool>,
+}
+
+/// Pin or lock a playlist item, so that other clients can not move or remove it, or mark
+/// it as background music. Flags that are left out are kept as they are.
+async fn set_playlist_flags(
+    State(state): State<AdminState>,
+    query: Result<Query<PlaylistFlagsArgs>, QueryRejection>,
+) -> Response {
+    let PlaylistFlagsArgs {
+        index,
+        pinned,
+        locked,
+        background,
+    } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+
+    let player = state.volume_engine.player();
+    let _lock = player::lock_playlist(player.queue_state()).await;
+    let playlist = match player.playlist().await {
+        Ok(playlist) => playlist,
+        Err(e) => return ApiError::from(e).into_response(),
+    };
+    let Some(entry) = playlist.get(index) else {
+        return ApiError::InvalidIndex(format!(
+            "No playlist item at index {} (playlist has {} items)",
+            index,
+            playlist.len()
+        ))
+        .into_response();
+    };
+
+    let queue = player.queue_state();
+    let current = queue.item(entry).flags;
+    let flags = ItemFlags {
+        pinned: pinned.unwrap_or(current.pinned),
+        locked: locked.unwrap_or(current.locked),
+        background: background.unwrap_or(current.background),
+    };
+    log::info!("Setting the flags of '{}' to {:?}", entry.filename, flags);
+    queue.update(entry.id, |item| item.flags = flags);
+
+    Json(json!({ "success": true, "value": flags })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        api::reject_by_policy,
+        player::MpvPlayer,
+        policy::Policies,
+        storage::{MemoryStorage, StorageHandle},
+        test_support::FakeMpv,
+    };
+
+    #[test]
+    fn test_mpv_command_policy() {
+        let policy = MpvCommandPolicy {
+            allow: vec![],
+            deny: DEFAULT_DENIED_COMMANDS.map(String::from).to_vec(),
+        };
+        assert!(policy.permits("get_property"));
+        assert!(!policy.permits("run"));
+        assert!(!policy.permits("load_script"));
+        assert!(!policy.permits(" Quit"));
+
+        let policy = MpvCommandPolicy {
+            allow: vec!["get_property".to_string(), "set-property".to_string()],
+            deny: vec!["set_property".to_string()],
+        };
+        assert!(policy.permits("get-property"));
+        assert!(!policy.permits("set_property"));
+        assert!(!policy.permits("loadfile"));
+    }
+
+    #[test]
+    fn test_check_token() {
+        let mut headers = HeaderMap::new();
+        let admin = AuthenticatedUser {
+            subject: "alice".to_string(),
+            name: "alice".to_string(),
+            scopes: Scope::ALL.to_vec(),
+        };
+
+        let nobody = AdminAuth::default();
+        assert!(check_token(&headers, None, &nobody).is_ok());
+        assert!(!is_admin(&headers, None, &nobody));
+
+        // Without a token, logging in as an admin is the only way in.
+        let oidc = AdminAuth::new(None, true);
+        assert!(check_token(&headers, None, &oidc).is_err());
+        assert!(is_admin(&headers, Some(&admin), &oidc));
+
+        let token = AdminAuth::new(Some("secret".to_string()), false);
+        assert!(!is_admin(&headers, None, &token));
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        assert!(is_admin(&headers, None, &token));
+    }
+
+    #[tokio::test]
+    async fn test_read_only_mode() {
+        let read_only = ReadOnlyMode::default();
+        let auth = AdminAuth::new(Some("secret".to_string()), false);
+        let router = Router::new()
+            .route(
+                "/api/volume",
+                get(|| async { "50" }).post(|| async { "ok" }),
+            )
+            .route("/api/admin/readonly", post(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(
+                (Policies::new(vec![Arc::new(read_only.clone())]), auth),
+                reject_by_policy,
+            ));
+        let status = async |method: Method, path: &str, token: Option<&str>| {
+            let mut request = Request::builder().method(method).uri(path);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let request = request.body(Body::empty()).unwrap();
+            router.clone().oneshot(request).await.unwrap().status()
+        };
+
+        assert_eq!(
+            status(Method::POST, "/api/volume", None).await,
+            StatusCode::OK
+        );
+        read_only.set_enabled(true);
+        assert_eq!(
+            status(Method::POST, "/api/volume", None).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(Method::POST, "/api/volume", Some("wrong")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(Method::POST, "/api/volume", Some("secret")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(Method::GET, "/api/volume", None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(Method::POST, "/api/admin/readonly", None).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_only_routes() {
+        let mpv = FakeMpv::start();
+        let storage: StorageHandle = Arc::new(MemoryStorage::new());
+        let routes = async |auth: AdminAuth| {
+            admin_routes(
+                mpv.connect().await,
+                auth,
+                MpvCommandPolicy::default(),
+                MpvLog::new(),
+                ClientRegistry::default(),
+                BanList::load(storage.clone()).unwrap(),
+                ApiKeys::load(storage.clone()).unwrap(),
+                Quotas::new(None, Duration::from_secs(60)),
+                ReadOnlyMode::default(),
+                IdlePolicyHandle::new(IdlePolicy {
+                    after_minutes: None,
+                    action: IdleAction::Pause,
+                    volume: 0.0,
+                }),
+                VolumeTransitionEngine::new(Arc::new(MpvPlayer::new(mpv.connect().await)), false),
+            )
+        };
+        let status = async |router: &Router, path: &str, token: Option<&str>| {
+            let mut request = Request::builder().uri(path);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let request = request.body(Body::empty()).unwrap();
+            router.clone().oneshot(request).await.unwrap().status()
+        };
+
+        // Nobody can be an admin, so there is nobody to let in.
+        let open = routes(AdminAuth::default()).await;
+        assert_eq!(
+            status(&open, "/api/admin/bans", None).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(&open, "/api/admin/readonly", None).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(&open, "/api/admin/playlist/flags", None).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(&open, "/api/admin/volume-cap", None).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(&open, "/api/admin/mpv-log", None).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(&open, "/api/admin/idle-policy", None).await,
+            StatusCode::NOT_FOUND
+        );
+
+        let admin = routes(AdminAuth::new(Some("secret".to_string()), false)).await;
+        assert_eq!(
+            status(&admin, "/api/admin/bans", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&admin, "/api/admin/bans", Some("secret")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&admin, "/api/admin/volume-cap", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&admin, "/api/admin/mpv-log", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Someone who logged in, but is not an admin.
+        let mut request = Request::get("/api/admin/mpv-log")
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(AuthenticatedUser {
+            subject: "bob".to_string(),
+            name: "bob".to_string(),
+            scopes: Scope::EVERYONE.to_vec(),
+        });
+        let response = admin.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Read-only mode is not among the policies here.
+        let request = Request::post("/api/admin/readonly?enabled=true")
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = admin.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        for (duration_secs, expected) in [(60, StatusCode::OK), (u64::MAX, StatusCode::BAD_REQUEST)]
+        {
+            let request = Request::post(format!(
+                "/api/admin/volume-cap?max_volume=50&duration_secs={}",
+                duration_secs
+            ))
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+            let response = admin.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), expected);
+        }
+    }
+}
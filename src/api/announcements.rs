@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use axum::{
+    Json, Router,
+    extract::{Query, State, rejection::QueryRejection},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{announcements::Announcements, oidc::LoggedIn};
+
+use super::{
+    admin::{AdminAuth, check_token},
+    error::ApiError,
+};
+
+#[derive(Debug, Clone)]
+struct AnnouncementsState {
+    announcements: Announcements,
+    admin: AdminAuth,
+}
+
+/// The `/api/announcements` endpoint. Anyone can see what is announced and when, but only
+/// admins can turn announcements on and off.
+pub fn announcement_routes(announcements: Announcements, admin: AdminAuth) -> Router {
+    Router::new()
+        .route(
+            "/api/announcements",
+            get(get_announcements).post(set_announcements),
+        )
+        .with_state(AnnouncementsState {
+            announcements,
+            admin,
+        })
+}
+
+/// Get whether announcements are on, how often they are made, and which one is next
+async fn get_announcements(State(state): State<AnnouncementsState>) -> Response {
+    Json(json!({
+        "success": true,
+        "value": state.announcements.status(),
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct AnnouncementArgs {
+    enabled: bool,
+    interval_minutes: Option<u64>,
+}
+
+/// Turn announcements on or off, optionally changing how many minutes apart they are
+async fn set_announcements(
+    State(state): State<AnnouncementsState>,
+    headers: HeaderMap,
+    user: LoggedIn,
+    query: Result<Query<AnnouncementArgs>, QueryRejection>,
+) -> Response {
+    if let Err(e) = check_token(&headers, user.as_deref(), &state.admin) {
+        return e.into_response();
+    }
+    let AnnouncementArgs {
+        enabled,
+        interval_minutes,
+    } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+
+    let interval = interval_minutes.map(|minutes| Duration::from_secs(minutes * 60));
+    match state.announcements.set(enabled, interval) {
+        Ok(()) => Json(json!({ "success": true, "value": null })).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
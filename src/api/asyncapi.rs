@@ -0,0 +1,74 @@
+use axum::Json;
+use serde_json::{Value, json};
+use utoipa::openapi::ComponentsBuilder;
+
+use crate::{control_lock::ControlHolder, playback_clock::Heartbeat, volume_transition::VolumeCap};
+
+use super::{
+    events::{OutgoingEvent, PlaylistItem},
+    websocket_v1::{InitialState, OutgoingMessage, WSCommand},
+};
+
+/// Build an AsyncAPI document describing the websocket protocol.
+///
+/// The message schemas are generated from the same types that are used
+/// for (de)serialization, so they should never go out of sync.
+pub fn websocket_asyncapi_document() -> Value {
+    let components = ComponentsBuilder::new()
+        .schema_from::<WSCommand>()
+        .schema_from::<OutgoingMessage>()
+        .schema_from::<OutgoingEvent>()
+        .schema_from::<InitialState>()
+        .schema_from::<PlaylistItem>()
+        .schema_from::<VolumeCap>()
+        .schema_from::<ControlHolder>()
+        .schema_from::<Heartbeat>()
+        .build();
+
+    json!({
+        "asyncapi": "3.0.0",
+        "info": {
+            "title": "Grzegorz Brzeczyszczykiewicz websocket API",
+            "version": "1.0.0",
+            "description": "Used to control and receive live updates from a running mpv instance",
+        },
+        "channels": {
+            "websocket": {
+                "address": "/ws",
+                "messages": {
+                    "command": { "$ref": "#/components/messages/command" },
+                    "outgoing": { "$ref": "#/components/messages/outgoing" },
+                },
+            },
+        },
+        "operations": {
+            "sendCommand": {
+                "action": "receive",
+                "channel": { "$ref": "#/channels/websocket" },
+                "messages": [{ "$ref": "#/channels/websocket/messages/command" }],
+            },
+            "receiveUpdates": {
+                "action": "send",
+                "channel": { "$ref": "#/channels/websocket" },
+                "messages": [{ "$ref": "#/channels/websocket/messages/outgoing" }],
+            },
+        },
+        "components": {
+            "messages": {
+                "command": {
+                    "contentType": "application/json",
+                    "payload": { "$ref": "#/components/schemas/WSCommand" },
+                },
+                "outgoing": {
+                    "contentType": "application/json",
+                    "payload": { "$ref": "#/components/schemas/OutgoingMessage" },
+                },
+            },
+            "schemas": components.schemas,
+        },
+    })
+}
+
+pub async fn websocket_schema() -> Json<Value> {
+    Json(websocket_asyncapi_document())
+}
@@ -0,0 +1,43 @@
+use axum::{
+    Json, Router,
+    extract::{Query, State, rejection::QueryRejection},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::autoplay::Autoplay;
+
+use super::error::ApiError;
+
+/// The `/api/autoplay` endpoint, for turning autoplay on and off.
+pub fn autoplay_routes(autoplay: Autoplay) -> Router {
+    Router::new()
+        .route("/api/autoplay", get(get_autoplay).post(set_autoplay))
+        .with_state(autoplay)
+}
+
+/// Get whether autoplay is on
+async fn get_autoplay(State(autoplay): State<Autoplay>) -> Response {
+    Json(json!({ "success": true, "value": autoplay.is_enabled() })).into_response()
+}
+
+#[derive(Deserialize)]
+struct AutoplayArgs {
+    enabled: bool,
+}
+
+/// Turn autoplay on or off
+async fn set_autoplay(
+    State(autoplay): State<Autoplay>,
+    query: Result<Query<AutoplayArgs>, QueryRejection>,
+) -> Response {
+    let AutoplayArgs { enabled } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+
+    autoplay.set_enabled(enabled);
+    Json(json!({ "success": true, "value": null })).into_response()
+}
@@ -1,57 +1,532 @@
-use mpvipc_async::{
-    LoopProperty, Mpv, MpvExt, NumberChangeOptions, PlaylistAddOptions, PlaylistAddTypeOptions,
-    SeekOptions, Switch,
-};
+use mpvipc_async::Switch;
 use serde_json::{Value, json};
 
+use crate::{
+    autoplay::Autoplay,
+    history, mpv_setup,
+    oidc::AuthenticatedUser,
+    player::{self, PlayerHandle, PlaylistClearGuard, PlaylistLock, PlaylistMove, RetryQueue},
+    quotas::QuotaCharge,
+    resolver::ResolverChain,
+    scopes::Scope,
+    util::{Page, canonicalize_url, parse_timestamp},
+    volume_transition::VolumeTransitionEngine,
+};
+
+use super::error::ApiError;
+use super::queue_ahead::{PendingLoads, QueuedLoad};
+
+/// Parse a `start` or `end` offset for [`loadfile`], like `72.5` or `1:12`
+fn parse_offset(name: &str, value: Option<&str>) -> anyhow::Result<Option<f64>> {
+    value
+        .map(|value| {
+            parse_timestamp(value).ok_or_else(|| {
+                ApiError::InvalidArgument(format!("Invalid {} timestamp '{}'", name, value)).into()
+            })
+        })
+        .transpose()
+}
+
 /// Add item to playlist
-pub async fn loadfile(mpv: Mpv, path: &str) -> anyhow::Result<()> {
-    log::trace!("api::loadfile({:?})", path);
-    mpv.playlist_add(
+///
+/// With a `quality`, yt-dlp picks the streams for this item accordingly, instead of
+/// going by the quality set with [`quality_set`]. `start` and `end` are timestamps
+/// like `1:12`, limiting playback to that part of the item.
+///
+/// The path goes through `resolvers` first, and may be added as several items.
+/// Every one of them gets the note, lane and TTL in `item`.
+///
+/// With a `not_before` in the future, in seconds since the unix epoch, the item is kept out
+/// of the playlist until then.
+///
+/// A `priority` of `high` needs the `queue:priority` scope and `admin` needs an admin,
+/// whether or not the scopes policy is on.
+///
+/// If someone logged in, the item is credited to them instead of whoever the note says.
+/// Every item that is added is counted by `charge`, if there is one, and takes turns with
+/// the items of other clients if fair scheduling is on.
+///
+/// If the player can not be reached, the item is kept and added once it is back.
+#[allow(clippy::too_many_arguments)]
+pub async fn loadfile(
+    player: PlayerHandle,
+    resolvers: &ResolverChain,
+    pending: &PendingLoads,
+    path: &str,
+    quality: mpv_setup::Quality,
+    start: Option<&str>,
+    end: Option<&str>,
+    item: player::ItemState,
+    resume: bool,
+    not_before: Option<u64>,
+    user: Option<&AuthenticatedUser>,
+    is_admin: bool,
+    charge: Option<&QuotaCharge>,
+) -> anyhow::Result<()> {
+    log::trace!(
+        "api::loadfile({:?}, {:?}, {:?}, {:?}, {:?}, {:?}, {:?}, {:?})",
         path,
-        PlaylistAddTypeOptions::File,
-        PlaylistAddOptions::Append,
-    )
-    .await?;
+        quality,
+        start,
+        end,
+        item,
+        resume,
+        not_before,
+        user.map(|user| &user.subject)
+    );
+    let scopes = user.map_or(&Scope::EVERYONE[..], |user| &user.scopes);
+    if let Some(needs) = item.lane.priority.needs()
+        && !is_admin
+        && !needs.granted_by(scopes)
+    {
+        return Err(ApiError::Unauthorized(format!("This needs the {} scope", needs)).into());
+    }
+
+    let load = QueuedLoad {
+        path: path.to_string(),
+        quality,
+        start: start.map(str::to_string),
+        end: end.map(str::to_string),
+        item: player::ItemState {
+            note: item.note.credited_to(user),
+            lane: player::Lane {
+                fair_identity: charge
+                    .and_then(QuotaCharge::fair_identity)
+                    .map(str::to_string),
+                ..item.lane
+            },
+            ..item
+        },
+        resume,
+        charge: charge.cloned(),
+        urls: None,
+    };
+    check_load(&load)?;
+
+    if let Some(not_before) = not_before.filter(|&not_before| not_before > history::now()) {
+        return pending.defer(load, not_before).map(|_| ());
+    }
+    add_load(&player, resolvers, pending, load).await
+}
+
+/// Add an item that has passed [`check_load`] to the playlist, or keep it until the player
+/// is back if it can not be reached.
+pub(super) async fn add_load(
+    player: &PlayerHandle,
+    resolvers: &ResolverChain,
+    pending: &PendingLoads,
+    mut load: QueuedLoad,
+) -> anyhow::Result<()> {
+    // Items queued earlier are still waiting for the player, and go first.
+    if pending.queued_count() > 0 {
+        return pending.queue(load);
+    }
+    match load_now(player, resolvers, &mut load).await {
+        Err(e) if player::is_unreachable(&e) => pending.queue(load),
+        result => result,
+    }
+}
+
+/// List the items kept out of the playlist until their time
+pub async fn deferred_list(pending: PendingLoads) -> anyhow::Result<Value> {
+    log::trace!("api::deferred_list()");
+    Ok(json!(pending.deferred_items()))
+}
+
+/// Forget an item kept out of the playlist, before its time
+pub async fn deferred_cancel(pending: PendingLoads, id: u64) -> anyhow::Result<()> {
+    log::trace!("api::deferred_cancel({:?})", id);
+    if !pending.cancel_deferred(id) {
+        return Err(ApiError::NotFound(format!("No deferred item with id {}", id)).into());
+    }
+    Ok(())
+}
 
+/// Check the arguments of a load, before trying to reach the player.
+pub(super) fn check_load(load: &QueuedLoad) -> anyhow::Result<()> {
+    if load.resume && load.start.is_some() {
+        return Err(ApiError::InvalidArgument(
+            "start and resume can not both be given".to_string(),
+        )
+        .into());
+    }
+    if load.item.ttl_hours == Some(0) {
+        return Err(ApiError::InvalidArgument("ttl_hours must be at least 1".to_string()).into());
+    }
+    let start = parse_offset("start", load.start.as_deref())?;
+    let end = parse_offset("end", load.end.as_deref())?;
+    if let (Some(start), Some(end)) = (start, end)
+        && end <= start
+    {
+        return Err(ApiError::InvalidArgument("end must be after start".to_string()).into());
+    }
     Ok(())
 }
 
+/// Add an item that has passed [`check_load`] to the playlist.
+///
+/// The items it resolves to are kept in `load`, and taken out of it as they are added, so
+/// that nothing is added twice if it is tried again after failing partway.
+pub(super) async fn load_now(
+    player: &PlayerHandle,
+    resolvers: &ResolverChain,
+    load: &mut QueuedLoad,
+) -> anyhow::Result<()> {
+    let item = player::ItemState {
+        options: player::LoadOptions {
+            ytdl_format: load.quality.ytdl_format(),
+            start: parse_offset("start", load.start.as_deref())?,
+            end: parse_offset("end", load.end.as_deref())?,
+        },
+        resume: load.resume,
+        ..load.item.clone()
+    };
+
+    if load.urls.is_none() {
+        load.urls = Some(resolvers.resolve(&load.path).await?);
+    }
+    let urls = load.urls.clone().unwrap_or_default();
+    let playlist = player.playlist().await?;
+    let queue = player.queue_state();
+    queue.forget_gone(&playlist);
+    for url in urls {
+        let id = player.load(&url, item.clone()).await?;
+        if let Some(urls) = &mut load.urls {
+            urls.remove(0);
+        }
+        if let Some(charge) = &load.charge {
+            charge.charge(1);
+        }
+        if item.lane.is_ordered() {
+            move_to_lane(player, id, &item.lane).await?;
+        }
+    }
+    player::wake_on_queue(player, &playlist, playlist.len()).await
+}
+
+/// Move the item `id`, just added to the end of the playlist, ahead of the items with a lower
+/// priority, and with fair scheduling, ahead of the items of clients that have had more
+/// turns in its lane. It is left at the end if that would move a pinned item.
+///
+/// The playlist lock may already be held by whoever is adding the item, so it is not taken
+/// here. If something else was added in the meantime, the item is left where it is.
+async fn move_to_lane(
+    player: &PlayerHandle,
+    id: player::EntryId,
+    lane: &player::Lane,
+) -> anyhow::Result<()> {
+    let playlist = player.playlist().await?;
+    let Some(added) = playlist.len().checked_sub(1) else {
+        return Ok(());
+    };
+    if playlist[added].id != id {
+        return Ok(());
+    }
+    let queue = player.queue_state();
+    let to = match lane.fair_identity.as_deref() {
+        Some(identity) => player::fair_index(queue, &playlist[..added], lane.priority, identity),
+        None => player::priority_index(queue, &playlist[..added], lane.priority),
+    };
+    if to == added {
+        return Ok(());
+    }
+    let moves = [PlaylistMove { from: added, to }];
+    if let Err(e) = player::check_moves(queue, &playlist, &moves) {
+        log::debug!(
+            "Leaving the {:?} priority item at the end: {}",
+            lane.priority,
+            e
+        );
+        return Ok(());
+    }
+    player.playlist_move(added, to).await
+}
+
 /// Check whether the player is paused or playing
-pub async fn play_get(mpv: Mpv) -> anyhow::Result<Value> {
+pub async fn play_get(player: PlayerHandle) -> anyhow::Result<Value> {
     log::trace!("api::play_get()");
-    let paused: bool = !mpv.is_playing().await?;
+    let paused: bool = !player.is_playing().await?;
     Ok(json!(!paused))
 }
 
 /// Set whether the player is paused or playing
-pub async fn play_set(mpv: Mpv, should_play: bool) -> anyhow::Result<()> {
+pub async fn play_set(
+    volume_engine: VolumeTransitionEngine,
+    should_play: bool,
+) -> anyhow::Result<()> {
     log::trace!("api::play_set({:?})", should_play);
-    mpv.set_playback(if should_play { Switch::On } else { Switch::Off })
+    volume_engine
+        .set_playback(if should_play { Switch::On } else { Switch::Off })
         .await
-        .map_err(|e| e.into())
+}
+
+/// Pause, and resume at `unix_ms` milliseconds since the unix epoch
+pub async fn play_at(volume_engine: VolumeTransitionEngine, unix_ms: u64) -> anyhow::Result<()> {
+    log::trace!("api::play_at({:?})", unix_ms);
+    player::play_at(volume_engine, unix_ms).await
+}
+
+/// Cancel the scheduled start of playback
+pub async fn play_at_cancel(player: PlayerHandle) -> anyhow::Result<()> {
+    log::trace!("api::play_at_cancel()");
+    if !player::cancel_play_at(&player) {
+        return Err(ApiError::Conflict("No start of playback is scheduled".to_string()).into());
+    }
+    Ok(())
 }
 
 /// Get the current player volume
-pub async fn volume_get(mpv: Mpv) -> anyhow::Result<Value> {
+pub async fn volume_get(player: PlayerHandle) -> anyhow::Result<Value> {
     log::trace!("api::volume_get()");
-    let volume: f64 = mpv.get_volume().await?;
+    let volume: f64 = player.get_volume().await?;
     Ok(json!(volume))
 }
 
 /// Set the player volume
-pub async fn volume_set(mpv: Mpv, value: f64) -> anyhow::Result<()> {
+pub async fn volume_set(volume_engine: VolumeTransitionEngine, value: f64) -> anyhow::Result<()> {
     log::trace!("api::volume_set({:?})", value);
-    mpv.set_volume(value, NumberChangeOptions::Absolute)
-        .await
-        .map_err(|e| e.into())
+    if value < 0.0 {
+        return Err(ApiError::InvalidArgument("volume can not be negative".to_string()).into());
+    }
+    volume_engine.set_volume(value).await
+}
+
+/// Play a short clip right away, and resume the current item afterwards
+pub async fn interject(
+    volume_engine: VolumeTransitionEngine,
+    path: &str,
+    user: Option<&AuthenticatedUser>,
+    charge: Option<&QuotaCharge>,
+) -> anyhow::Result<()> {
+    log::trace!("api::interject({:?})", path);
+    let url = canonicalize_url(path);
+    player::interject(volume_engine, &url, player::ItemState::credited_to(user)).await?;
+    if let Some(charge) = charge {
+        charge.charge(1);
+    }
+    Ok(())
+}
+
+/// The audio devices mpv can output to, as `{ name, description }` objects
+async fn audio_devices(player: &PlayerHandle) -> anyhow::Result<Vec<Value>> {
+    let devices = match player.get_property("audio-device-list").await? {
+        Some(Value::Array(devices)) => devices,
+        _ => vec![],
+    };
+
+    Ok(devices
+        .into_iter()
+        .map(|device| {
+            json!({
+                "name": device.get("name"),
+                "description": device.get("description"),
+            })
+        })
+        .collect())
+}
+
+/// Get the current audio output device, and all available devices
+pub async fn audio_device_get(player: PlayerHandle) -> anyhow::Result<Value> {
+    log::trace!("api::audio_device_get()");
+    let current = player.get_property("audio-device").await?;
+    let devices = audio_devices(&player).await?;
+
+    Ok(json!({
+        "current": current,
+        "devices": devices,
+    }))
+}
+
+/// Switch the audio output device
+///
+/// Only devices listed by mpv can be chosen. Sinks like a Snapcast FIFO have to be
+/// exposed as a device by the audio server (e.g. a pipewire pipe sink) to show up here.
+pub async fn audio_device_set(player: PlayerHandle, name: String) -> anyhow::Result<()> {
+    log::trace!("api::audio_device_set({:?})", name);
+    let devices = audio_devices(&player).await?;
+    if !devices.iter().any(|device| device["name"] == name.as_str()) {
+        return Err(ApiError::InvalidArgument(format!("Unknown audio device '{}'", name)).into());
+    }
+
+    player.set_property("audio-device", json!(name)).await
+}
+
+/// Check whether mpv opens the next playlist item ahead of time
+pub async fn prefetch_get(player: PlayerHandle) -> anyhow::Result<Value> {
+    log::trace!("api::prefetch_get()");
+    let enabled = player.get_property("prefetch-playlist").await?;
+    Ok(json!(
+        enabled
+            .and_then(|enabled| enabled.as_bool())
+            .unwrap_or(false)
+    ))
+}
+
+/// Set whether mpv opens the next playlist item ahead of time
+pub async fn prefetch_set(player: PlayerHandle, enabled: bool) -> anyhow::Result<()> {
+    log::trace!("api::prefetch_set({:?})", enabled);
+    mpv_setup::set_playlist_prefetch(player.as_ref(), enabled).await
+}
+
+/// Get the state of the demuxer cache
+///
+/// `duration` is how many seconds are buffered ahead, and `bytes` how much data that is.
+/// `buffering` is how much of the cache has been filled before playback resumes, in
+/// percent, while `paused_for_cache` is true.
+pub async fn cache_get(player: PlayerHandle) -> anyhow::Result<Value> {
+    log::trace!("api::cache_get()");
+    let state = player
+        .get_property("demuxer-cache-state")
+        .await?
+        .unwrap_or(Value::Null);
+    let paused_for_cache = player.get_property("paused-for-cache").await?;
+    let buffering = player.get_property("cache-buffering-state").await?;
+
+    Ok(json!({
+        "duration": state.get("cache-duration"),
+        "end": state.get("cache-end"),
+        "bytes": state.get("fw-bytes"),
+        "total_bytes": state.get("total-bytes"),
+        "paused_for_cache": paused_for_cache
+            .and_then(|paused| paused.as_bool())
+            .unwrap_or(false),
+        "buffering": buffering,
+    }))
+}
+
+/// Get the requested hardware decoding mode, and the decoder actually in use
+pub async fn hwdec_get(player: PlayerHandle) -> anyhow::Result<Value> {
+    log::trace!("api::hwdec_get()");
+    let requested = player.get_property("hwdec").await?;
+    let current = player.get_property("hwdec-current").await.unwrap_or(None);
+
+    Ok(json!({
+        "requested": requested,
+        "current": current,
+    }))
+}
+
+/// Switch the hardware decoding mode, like `auto`, `vaapi` or `none`
+pub async fn hwdec_set(player: PlayerHandle, mode: String) -> anyhow::Result<()> {
+    log::trace!("api::hwdec_set({:?})", mode);
+    if mpv_setup::hwdec_mode(&mode).is_none() {
+        return Err(ApiError::InvalidArgument(format!("Invalid hwdec mode '{}'", mode)).into());
+    }
+    mpv_setup::set_hwdec(player.as_ref(), &mode).await
+}
+
+/// Get the `ytdl-format` items are loaded with, or `null` for yt-dlp's default
+pub async fn quality_get(player: PlayerHandle) -> anyhow::Result<Value> {
+    log::trace!("api::quality_get()");
+    let format = player.get_property("ytdl-format").await?;
+    Ok(json!(
+        format
+            .as_ref()
+            .and_then(Value::as_str)
+            .filter(|format| !format.is_empty())
+    ))
+}
+
+/// Set the quality yt-dlp picks for items loaded from now on
+///
+/// Items that are already in the playlist keep the quality they were loaded with.
+pub async fn quality_set(player: PlayerHandle, quality: mpv_setup::Quality) -> anyhow::Result<()> {
+    log::trace!("api::quality_set({:?})", quality);
+    mpv_setup::set_quality(player.as_ref(), quality).await
+}
+
+/// The mpv properties reported by [`stats_get`], by the name they are reported as
+const STATS_PROPERTIES: [(&str, &str); 10] = [
+    ("video_codec", "video-codec"),
+    ("audio_codec", "audio-codec"),
+    ("hwdec", "hwdec-current"),
+    ("video_bitrate", "video-bitrate"),
+    ("audio_bitrate", "audio-bitrate"),
+    ("fps", "container-fps"),
+    ("estimated_fps", "estimated-vf-fps"),
+    ("frame_drop_count", "frame-drop-count"),
+    ("decoder_frame_drop_count", "decoder-frame-drop-count"),
+    ("avsync", "avsync"),
+];
+
+/// Get playback statistics, for figuring out why a stream stutters
+///
+/// Properties that are not available for the current item are `null`.
+pub async fn stats_get(player: PlayerHandle) -> anyhow::Result<Value> {
+    log::trace!("api::stats_get()");
+    let values = futures::future::join_all(
+        STATS_PROPERTIES
+            .iter()
+            .map(|(_, property)| player.get_property(property)),
+    )
+    .await;
+
+    let mut stats = serde_json::Map::new();
+    for ((name, _), value) in STATS_PROPERTIES.iter().zip(values) {
+        // Properties without a value make mpv respond with an error, but errors from
+        // greg-ng itself, like the player not responding, are passed on.
+        let value = match value {
+            Ok(value) => value.unwrap_or(Value::Null),
+            Err(e) if e.downcast_ref::<ApiError>().is_some() => return Err(e),
+            Err(_) => Value::Null,
+        };
+        stats.insert(name.to_string(), value);
+    }
+    Ok(Value::Object(stats))
+}
+
+/// A delay that can be adjusted to bring a stream back in sync
+#[derive(Debug, Clone, Copy)]
+pub enum Delay {
+    Subtitles,
+    Audio,
+}
+
+impl Delay {
+    /// The mpv property holding the delay, in seconds
+    fn property(self) -> &'static str {
+        match self {
+            Delay::Subtitles => "sub-delay",
+            Delay::Audio => "audio-delay",
+        }
+    }
+}
+
+/// Get the subtitle or audio delay, in milliseconds
+pub async fn delay_get(player: &PlayerHandle, delay: Delay) -> anyhow::Result<Value> {
+    log::trace!("api::delay_get({:?})", delay);
+    let seconds = player.get_property(delay.property()).await?;
+    Ok(json!(
+        seconds.and_then(|seconds| seconds.as_f64()).unwrap_or(0.0) * 1000.0
+    ))
+}
+
+/// Set the subtitle or audio delay to `ms` milliseconds, or adjust it by `ms` if `relative`
+pub async fn delay_set(
+    player: &PlayerHandle,
+    delay: Delay,
+    ms: f64,
+    relative: bool,
+) -> anyhow::Result<()> {
+    log::trace!("api::delay_set({:?}, {}, {})", delay, ms, relative);
+    if !ms.is_finite() {
+        return Err(ApiError::InvalidArgument(format!("Invalid delay {}", ms)).into());
+    }
+
+    let mut seconds = ms / 1000.0;
+    if relative {
+        let current = player.get_property(delay.property()).await?;
+        seconds += current.and_then(|current| current.as_f64()).unwrap_or(0.0);
+    }
+
+    player.set_property(delay.property(), json!(seconds)).await
 }
 
 /// Get current playback position
-pub async fn time_get(mpv: Mpv) -> anyhow::Result<Value> {
+pub async fn time_get(player: PlayerHandle) -> anyhow::Result<Value> {
     log::trace!("api::time_get()");
-    let current: Option<f64> = mpv.get_time_pos().await?;
-    let remaining: Option<f64> = mpv.get_time_remaining().await?;
+    let current: Option<f64> = player.get_time_pos().await?;
+    let remaining: Option<f64> = player.get_time_remaining().await?;
     let total = match (current, remaining) {
         (Some(c), Some(r)) => Some(c + r),
         (_, _) => None,
@@ -65,108 +540,307 @@ pub async fn time_get(mpv: Mpv) -> anyhow::Result<Value> {
 }
 
 /// Set playback position
-pub async fn time_set(mpv: Mpv, pos: Option<f64>, percent: Option<f64>) -> anyhow::Result<()> {
+pub async fn time_set(
+    player: PlayerHandle,
+    pos: Option<f64>,
+    percent: Option<f64>,
+) -> anyhow::Result<()> {
     log::trace!("api::time_set({:?}, {:?})", pos, percent);
-    if pos.is_some() && percent.is_some() {
-        anyhow::bail!("pos and percent cannot be provided at the same time");
-    }
-
-    if let Some(pos) = pos {
-        mpv.seek(pos, SeekOptions::Absolute).await?;
-    } else if let Some(percent) = percent {
-        mpv.seek(percent, SeekOptions::AbsolutePercent).await?;
-    } else {
-        anyhow::bail!("Either pos or percent must be provided");
+    match (pos, percent) {
+        (Some(_), Some(_)) => {
+            return Err(ApiError::InvalidArgument(
+                "pos and percent cannot be provided at the same time".to_string(),
+            )
+            .into());
+        }
+        (None, None) => {
+            return Err(ApiError::InvalidArgument(
+                "Either pos or percent must be provided".to_string(),
+            )
+            .into());
+        }
+        _ => {}
     };
 
-    Ok(())
+    if player.get_time_pos().await?.is_none() {
+        return Err(ApiError::Conflict("Nothing is playing".to_string()).into());
+    }
+
+    match (pos, percent) {
+        (Some(pos), _) => player.seek(pos).await,
+        (_, Some(percent)) => player.seek_percent(percent).await,
+        (None, None) => unreachable!(),
+    }
 }
 
-/// Get the current playlist
-pub async fn playlist_get(mpv: Mpv) -> anyhow::Result<Value> {
-    log::trace!("api::playlist_get()");
-    let playlist: mpvipc_async::Playlist = mpv.get_playlist().await?;
-    let is_playing: bool = mpv.is_playing().await?;
+/// Get the current playlist, or the part of it on `page`, optionally only the items
+/// added by `queued_by`
+pub async fn playlist_get(
+    player: PlayerHandle,
+    retries: &RetryQueue,
+    autoplay: &Autoplay,
+    queued_by: Option<&str>,
+    page: &Page,
+) -> anyhow::Result<Value> {
+    log::trace!("api::playlist_get({:?}, {:?})", queued_by, page);
+    let playlist = player.playlist().await?;
+    let is_playing: bool = player.is_playing().await?;
 
-    let items: Vec<Value> = playlist
-        .0
+    let items = playlist
         .iter()
         .enumerate()
-        .map(|(i, item)| {
+        .map(|(i, item)| (i, item, player.queue_state().note(item)))
+        .filter(|(_, _, note)| {
+            queued_by.is_none_or(|queued_by| {
+                note.queued_by
+                    .as_deref()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(queued_by.trim()))
+            })
+        })
+        .map(|(i, item, note)| {
+            let state = player.queue_state().item(item);
             json!({
               "index": i,
               "current": item.current,
               "playing": is_playing,
-              "filename": item.title.as_ref().unwrap_or(&item.filename),
+              "filename": player.queue_state().display_title(item),
               "data": {
                 "fetching": true,
+                "note": note.note,
+                "queued_by": note.queued_by,
+                "pinned": state.flags.pinned,
+                "locked": state.flags.locked,
+                "block": state.block,
+                "retry": retries.status(&item.filename),
+                "autoplay": autoplay.is_autoplayed(&item.filename),
               }
             })
-        })
-        .collect();
+        });
 
-    Ok(json!(items))
+    Ok(json!(page.apply(items)))
 }
 
 /// Skip to the next item in the playlist
-pub async fn playlist_next(mpv: Mpv) -> anyhow::Result<()> {
+pub async fn playlist_next(player: PlayerHandle) -> anyhow::Result<()> {
     log::trace!("api::playlist_next()");
-    mpv.next().await.map_err(|e| e.into())
+    player.playlist_next().await
 }
 
 /// Go back to the previous item in the playlist
-pub async fn playlist_previous(mpv: Mpv) -> anyhow::Result<()> {
+pub async fn playlist_previous(player: PlayerHandle) -> anyhow::Result<()> {
     log::trace!("api::playlist_previous()");
-    mpv.prev().await.map_err(|e| e.into())
+    player.playlist_previous().await
 }
 
 /// Go chosen item in the playlist
-pub async fn playlist_goto(mpv: Mpv, index: usize) -> anyhow::Result<()> {
+pub async fn playlist_goto(player: PlayerHandle, index: usize) -> anyhow::Result<()> {
     log::trace!("api::playlist_goto({:?})", index);
-    mpv.playlist_play_id(index).await.map_err(|e| e.into())
+    ensure_playlist_index(&player, index).await?;
+    player.playlist_goto(index).await
+}
+
+/// Fail with [`ApiError::InvalidIndex`] if there is no playlist item at `index`
+async fn ensure_playlist_index(player: &PlayerHandle, index: usize) -> anyhow::Result<()> {
+    let length = player.playlist().await?.len();
+    if index >= length {
+        return Err(ApiError::InvalidIndex(format!(
+            "No playlist item at index {} (playlist has {} items)",
+            index, length
+        ))
+        .into());
+    }
+    Ok(())
 }
 
 /// Clears the playlist
-pub async fn playlist_clear(mpv: Mpv) -> anyhow::Result<()> {
-    log::trace!("api::playlist_clear()");
-    mpv.playlist_clear().await.map_err(|e| e.into())
+///
+/// Large playlists are only cleared if `force` is set, or with a token from an earlier attempt.
+pub async fn playlist_clear(
+    player: PlayerHandle,
+    guard: &PlaylistClearGuard,
+    force: bool,
+    token: Option<&str>,
+) -> anyhow::Result<()> {
+    log::trace!("api::playlist_clear({:?}, {:?})", force, token);
+    let lock = player::lock_playlist(player.queue_state()).await;
+    playlist_clear_locked(&player, guard, force, token, &lock).await
+}
+
+/// Like [`playlist_clear`], while already holding the playlist lock
+pub async fn playlist_clear_locked(
+    player: &PlayerHandle,
+    guard: &PlaylistClearGuard,
+    force: bool,
+    token: Option<&str>,
+    _lock: &PlaylistLock,
+) -> anyhow::Result<()> {
+    let playlist = player.playlist().await?;
+    player::check_clear(player.queue_state(), &playlist)?;
+    guard.check(playlist.len(), force, token)?;
+    player.playlist_clear().await
 }
 
 /// Remove an item from the playlist by index
-pub async fn playlist_remove(mpv: Mpv, index: usize) -> anyhow::Result<()> {
+pub async fn playlist_remove(player: PlayerHandle, index: usize) -> anyhow::Result<()> {
     log::trace!("api::playlist_remove({:?})", index);
-    mpv.playlist_remove_id(index).await.map_err(|e| e.into())
+    let lock = player::lock_playlist(player.queue_state()).await;
+    playlist_remove_locked(&player, &[index], &lock).await
+}
+
+/// Remove several items from the playlist by index, while already holding the playlist lock
+pub async fn playlist_remove_locked(
+    player: &PlayerHandle,
+    indices: &[usize],
+    _lock: &PlaylistLock,
+) -> anyhow::Result<()> {
+    let playlist = player.playlist().await?;
+    let length = playlist.len();
+    if let Some(index) = indices.iter().find(|index| **index >= length) {
+        return Err(ApiError::InvalidIndex(format!(
+            "No playlist item at index {} (playlist has {} items)",
+            index, length
+        ))
+        .into());
+    }
+    player::check_removal(player.queue_state(), &playlist, indices)?;
+
+    let mut indices = indices.to_vec();
+    indices.sort_unstable();
+    indices.dedup();
+
+    // Back to front, so that the indices stay valid.
+    for index in indices.into_iter().rev() {
+        player.playlist_remove(index).await?;
+    }
+    Ok(())
 }
 
 /// Move an item in the playlist from one index to another
-pub async fn playlist_move(mpv: Mpv, from: usize, to: usize) -> anyhow::Result<()> {
+pub async fn playlist_move(player: PlayerHandle, from: usize, to: usize) -> anyhow::Result<()> {
     log::trace!("api::playlist_move({:?}, {:?})", from, to);
-    mpv.playlist_move_id(from, to).await.map_err(|e| e.into())
+    playlist_move_many(player, &[PlaylistMove { from, to }]).await
+}
+
+/// Apply several moves in order, without any other playlist changes in between
+pub async fn playlist_move_many(
+    player: PlayerHandle,
+    moves: &[PlaylistMove],
+) -> anyhow::Result<()> {
+    log::trace!("api::playlist_move_many({:?})", moves);
+    let lock = player::lock_playlist(player.queue_state()).await;
+    playlist_move_many_locked(&player, moves, &lock).await
+}
+
+/// Like [`playlist_move_many`], while already holding the playlist lock
+pub async fn playlist_move_many_locked(
+    player: &PlayerHandle,
+    moves: &[PlaylistMove],
+    _lock: &PlaylistLock,
+) -> anyhow::Result<()> {
+    // Moves never change the length of the playlist, so everything can be checked up front.
+    let playlist = player.playlist().await?;
+    let length = playlist.len();
+    for PlaylistMove { from, to } in moves {
+        if *from >= length {
+            return Err(ApiError::InvalidIndex(format!(
+                "No playlist item at index {} (playlist has {} items)",
+                from, length
+            ))
+            .into());
+        }
+        // Moving an item to one past the end of the playlist is allowed
+        if *to > length {
+            return Err(ApiError::InvalidIndex(format!(
+                "Can not move item to index {} (playlist has {} items)",
+                to, length
+            ))
+            .into());
+        }
+    }
+    player::check_moves(player.queue_state(), &playlist, moves)?;
+
+    for PlaylistMove { from, to } in moves {
+        player.playlist_move(*from, *to).await?;
+    }
+    Ok(())
+}
+
+/// Where to move a playlist item, relative to the playlist itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveTarget {
+    Top,
+    Bottom,
+    AfterCurrent,
+}
+
+/// Move an item to the top, to the bottom, or to right after the current item
+pub async fn playlist_move_to(
+    player: PlayerHandle,
+    index: usize,
+    target: MoveTarget,
+) -> anyhow::Result<()> {
+    log::trace!("api::playlist_move_to({:?}, {:?})", index, target);
+    let lock = player::lock_playlist(player.queue_state()).await;
+    playlist_move_to_locked(&player, index, target, &lock).await
+}
+
+/// Like [`playlist_move_to`], while already holding the playlist lock
+pub async fn playlist_move_to_locked(
+    player: &PlayerHandle,
+    index: usize,
+    target: MoveTarget,
+    _lock: &PlaylistLock,
+) -> anyhow::Result<()> {
+    let playlist = player.playlist().await?;
+    if index >= playlist.len() {
+        return Err(ApiError::InvalidIndex(format!(
+            "No playlist item at index {} (playlist has {} items)",
+            index,
+            playlist.len()
+        ))
+        .into());
+    }
+
+    let to = match target {
+        MoveTarget::Top => 0,
+        MoveTarget::Bottom => playlist.len(),
+        MoveTarget::AfterCurrent => {
+            let current = playlist
+                .iter()
+                .position(|entry| entry.current)
+                .ok_or_else(|| ApiError::Conflict("Nothing is playing".to_string()))?;
+            current + 1
+        }
+    };
+
+    player::check_moves(
+        player.queue_state(),
+        &playlist,
+        &[PlaylistMove { from: index, to }],
+    )?;
+    player.playlist_move(index, to).await
 }
 
 /// Shuffle the playlist
-pub async fn shuffle(mpv: Mpv) -> anyhow::Result<()> {
+pub async fn shuffle(player: PlayerHandle) -> anyhow::Result<()> {
     log::trace!("api::shuffle()");
-    mpv.playlist_shuffle().await.map_err(|e| e.into())
+    let lock = player::lock_playlist(player.queue_state()).await;
+    shuffle_locked(&player, &lock).await
+}
+
+/// Like [`shuffle`], while already holding the playlist lock
+pub async fn shuffle_locked(player: &PlayerHandle, _lock: &PlaylistLock) -> anyhow::Result<()> {
+    player::check_shuffle(player.queue_state(), &player.playlist().await?)?;
+    player.playlist_shuffle().await
 }
 
 /// See whether it loops the playlist or not
-pub async fn playlist_get_looping(mpv: Mpv) -> anyhow::Result<Value> {
+pub async fn playlist_get_looping(player: PlayerHandle) -> anyhow::Result<Value> {
     log::trace!("api::playlist_get_looping()");
-
-    let loop_status = match mpv.playlist_is_looping().await? {
-        LoopProperty::No => false,
-        LoopProperty::Inf => true,
-        LoopProperty::N(_) => true,
-    };
-
-    Ok(json!(loop_status))
+    Ok(json!(player.is_looping().await?))
 }
 
-pub async fn playlist_set_looping(mpv: Mpv, r#loop: bool) -> anyhow::Result<()> {
+pub async fn playlist_set_looping(player: PlayerHandle, r#loop: bool) -> anyhow::Result<()> {
     log::trace!("api::playlist_set_looping({:?})", r#loop);
-
-    mpv.set_loop_playlist(if r#loop { Switch::On } else { Switch::Off })
-        .await
-        .map_err(|e| e.into())
+    player.set_looping(r#loop).await
 }
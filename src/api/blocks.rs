@@ -0,0 +1,124 @@
+use axum::Router;
+use serde_json::{Value, json};
+
+use crate::player::{self, PlayerHandle};
+
+use super::{
+    base,
+    error::ApiError,
+    rest_endpoints::rest_endpoints,
+    rest_wrapper_v2::{
+        EmptySuccessResponse, ErrorResponses, RestResponse, SuccessResponse, query_rejection,
+    },
+};
+
+/// The `/api/playlist/blocks` endpoints, for grouping playlist items into blocks that are
+/// moved, removed and skipped as one.
+pub fn block_routes(player: PlayerHandle) -> Router {
+    let (router, _) = api_router().with_state(player).split_for_parts();
+
+    router
+}
+
+pub(super) fn block_openapi() -> utoipa::openapi::OpenApi {
+    let (_, api): (Router<PlayerHandle>, _) = api_router().split_for_parts();
+    api
+}
+
+rest_endpoints! {
+    router = api_router, state = PlayerHandle;
+
+    /// List the blocks in the playlist
+    get "/api/playlist/blocks" -> SuccessResponse;
+    async fn get_blocks(player: PlayerHandle) {
+        player
+            .playlist()
+            .await
+            .map(|playlist| json!(player::blocks(player.queue_state(), &playlist)))
+    }
+
+    /// Group `count` items from `start` into a block
+    post "/api/playlist/blocks" -> SuccessResponse;
+    async fn group_block(player: PlayerHandle, name: String, start: usize, count: usize) {
+        group(&player, &name, start, count).await
+    }
+
+    /// Remove every item in the block from the playlist
+    delete "/api/playlist/blocks/{name}" -> EmptySuccessResponse, path = (name: String);
+    async fn remove_block(player: PlayerHandle) {
+        remove(&player, &name).await
+    }
+
+    /// Move the block to before the item at `to`, bringing its items together
+    post "/api/playlist/blocks/{name}/move" -> EmptySuccessResponse, path = (name: String);
+    async fn move_block(player: PlayerHandle, to: usize) {
+        move_to(&player, &name, to).await
+    }
+
+    /// Skip past the rest of the block that is playing
+    post "/api/playlist/blocks/{name}/skip" -> EmptySuccessResponse, path = (name: String);
+    async fn skip_block(player: PlayerHandle) {
+        skip(&player, &name).await
+    }
+
+    /// Split up the block, leaving its items where they are
+    post "/api/playlist/blocks/{name}/ungroup" -> EmptySuccessResponse, path = (name: String);
+    async fn ungroup_block(player: PlayerHandle) {
+        ungroup(&player, &name).await
+    }
+}
+
+async fn group(
+    player: &PlayerHandle,
+    name: &str,
+    start: usize,
+    count: usize,
+) -> anyhow::Result<Value> {
+    let _lock = player::lock_playlist(player.queue_state()).await;
+    let playlist = player.playlist().await?;
+    player::group_items(
+        player.queue_state(),
+        &playlist,
+        name,
+        start..start.saturating_add(count),
+    )?;
+    log::info!("Grouped {} items from {} into '{}'", count, start, name);
+    Ok(json!(player::block_indices(
+        player.queue_state(),
+        &playlist,
+        name.trim()
+    )?))
+}
+
+async fn remove(player: &PlayerHandle, name: &str) -> anyhow::Result<()> {
+    let lock = player::lock_playlist(player.queue_state()).await;
+    let indices = player::block_indices(player.queue_state(), &player.playlist().await?, name)?;
+    base::playlist_remove_locked(player, &indices, &lock).await
+}
+
+async fn move_to(player: &PlayerHandle, name: &str, to: usize) -> anyhow::Result<()> {
+    let lock = player::lock_playlist(player.queue_state()).await;
+    let indices = player::block_indices(player.queue_state(), &player.playlist().await?, name)?;
+    let moves = player::block_moves(&indices, to)?;
+    base::playlist_move_many_locked(player, &moves, &lock).await
+}
+
+async fn skip(player: &PlayerHandle, name: &str) -> anyhow::Result<()> {
+    let _lock = player::lock_playlist(player.queue_state()).await;
+    let playlist = player.playlist().await?;
+    let indices = player::block_indices(player.queue_state(), &playlist, name)?;
+    let current = playlist.iter().position(|entry| entry.current);
+    let Some(current) = current.filter(|current| indices.contains(current)) else {
+        return Err(ApiError::Conflict(format!("The block '{}' is not playing", name)).into());
+    };
+    let next = (current + 1..playlist.len())
+        .find(|index| !indices.contains(index))
+        .ok_or_else(|| ApiError::Conflict(format!("Nothing comes after the block '{}'", name)))?;
+    player.playlist_goto(next).await
+}
+
+async fn ungroup(player: &PlayerHandle, name: &str) -> anyhow::Result<()> {
+    let _lock = player::lock_playlist(player.queue_state()).await;
+    player::ungroup_items(player.queue_state(), &player.playlist().await?, name)?;
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use axum::{Router, extract::FromRef};
+use serde_json::json;
+
+use crate::{
+    bookmarks::Bookmarks,
+    player::{ItemNote, ItemState, PlayerHandle},
+    resolver::ResolverChain,
+};
+
+use super::{
+    base,
+    queue_ahead::PendingLoads,
+    rest_endpoints::rest_endpoints,
+    rest_wrapper_v2::{
+        EmptySuccessResponse, ErrorResponses, RestResponse, SuccessResponse, query_rejection,
+    },
+};
+
+#[derive(Debug, Clone, FromRef)]
+struct BookmarksState {
+    player: PlayerHandle,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+    bookmarks: Bookmarks,
+}
+
+/// Routes for bookmarking positions within media, under `/api/bookmarks`.
+pub fn bookmark_routes(
+    player: PlayerHandle,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+    bookmarks: Bookmarks,
+) -> Router {
+    let state = BookmarksState {
+        player,
+        resolvers,
+        pending,
+        bookmarks,
+    };
+    let (router, _) = api_router().with_state(state).split_for_parts();
+
+    router
+}
+
+pub(super) fn bookmark_openapi() -> utoipa::openapi::OpenApi {
+    let (_, api): (Router<BookmarksState>, _) = api_router().split_for_parts();
+    api
+}
+
+rest_endpoints! {
+    router = api_router, state = BookmarksState;
+
+    /// List the bookmarks in the item at `url`, or every bookmark, sorted by position
+    get "/api/bookmarks" -> SuccessResponse;
+    async fn list_bookmarks(bookmarks: Bookmarks, url: Option<String>) {
+        bookmarks.list(url.as_deref()).map(|list| json!(list))
+    }
+
+    /// Bookmark a position in the item at `url`
+    ///
+    /// `position` is a timestamp like `1:12` or `72.5`.
+    post "/api/bookmarks" -> SuccessResponse;
+    async fn add_bookmark(bookmarks: Bookmarks, url: String, position: String, label: String) {
+        bookmarks
+            .add(&url, &position, &label)
+            .map(|bookmark| json!(bookmark))
+    }
+
+    /// Delete a bookmark
+    delete "/api/bookmarks/{id}" -> EmptySuccessResponse, path = (id: u64);
+    async fn delete_bookmark(bookmarks: Bookmarks) {
+        bookmarks.delete(id)
+    }
+
+    /// Add the bookmarked item to the playlist, starting at the bookmarked position, with the
+    /// label of the bookmark as its note
+    post "/api/bookmarks/{id}/play" -> EmptySuccessResponse,
+        user = user, charge = charge, path = (id: u64);
+    async fn play_bookmark(state: BookmarksState) {
+        match state.bookmarks.get(id) {
+            Ok(bookmark) => {
+                base::loadfile(
+                    state.player,
+                    &state.resolvers,
+                    &state.pending,
+                    &bookmark.url,
+                    Default::default(),
+                    Some(&bookmark.position.to_string()),
+                    None,
+                    ItemState {
+                        note: ItemNote::new(None, Some(&bookmark.label), None),
+                        ..ItemState::default()
+                    },
+                    false,
+                    None,
+                    user.as_deref(),
+                    false,
+                    charge.as_deref(),
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
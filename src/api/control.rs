@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use axum::{Router, http::HeaderMap};
+use serde_json::{Value, json};
+
+use crate::{control_lock::ControlLock, oidc::AuthenticatedUser};
+
+use super::{
+    error::ApiError,
+    rest_endpoints::rest_endpoints,
+    rest_wrapper_v2::{
+        EmptySuccessResponse, ErrorResponses, RestResponse, SuccessResponse, query_rejection,
+    },
+};
+
+/// The header the holder gives its token in, with every request that changes something.
+pub const CONTROL_TOKEN_HEADER: &str = "x-control-token";
+
+pub(super) fn control_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(CONTROL_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// The `/api/control` endpoints, for claiming exclusive control of the player.
+pub fn control_routes(lock: ControlLock) -> Router {
+    let (router, _) = api_router().with_state(lock).split_for_parts();
+
+    router
+}
+
+pub(super) fn control_openapi() -> utoipa::openapi::OpenApi {
+    let (_, api): (Router<ControlLock>, _) = api_router().split_for_parts();
+    api
+}
+
+rest_endpoints! {
+    router = api_router, state = ControlLock;
+
+    /// Get who has claimed control and until when, or null if nobody has
+    get "/api/control" -> SuccessResponse;
+    async fn get_control(lock: ControlLock) {
+        Ok(json!(lock.holder()))
+    }
+
+    /// Claim control for some minutes, so that nobody else can change anything. Returns the
+    /// token to give in the `X-Control-Token` header, or with `hello` over the websocket.
+    /// Claiming again with the token extends the claim.
+    ///
+    /// `name` is shown to everyone else, like "Locked by alice until 21:30 UTC". Only those
+    /// who logged in or have an API key can claim control, so that anyone who locks
+    /// everybody else out can be told apart.
+    post "/api/control/claim" -> SuccessResponse, user = user, headers = headers;
+    async fn claim(lock: ControlLock, name: String, minutes: u64) {
+        claim_control(&lock, user.as_deref(), &headers, name, minutes)
+    }
+
+    /// Give up control before the claim runs out. Needs the token of the claim.
+    post "/api/control/release" -> EmptySuccessResponse, headers = headers;
+    async fn release(lock: ControlLock) {
+        lock.release(control_token(&headers)).map_err(anyhow::Error::from)
+    }
+}
+
+fn claim_control(
+    lock: &ControlLock,
+    user: Option<&AuthenticatedUser>,
+    headers: &HeaderMap,
+    name: String,
+    minutes: u64,
+) -> anyhow::Result<Value> {
+    let Some(user) = user else {
+        return Err(ApiError::Unauthorized(
+            "Logging in or an API key is required to claim control".to_string(),
+        )
+        .into());
+    };
+    log::info!("{} is claiming control as {}", user.name, name);
+    if name.trim().is_empty() {
+        return Err(ApiError::InvalidArgument("The name can not be empty".to_string()).into());
+    }
+
+    let duration = Duration::from_secs(minutes.saturating_mul(60));
+    let (holder, token) = lock.claim(name, duration, control_token(headers))?;
+    Ok(json!({
+        "name": holder.name,
+        "expires_at": holder.expires_at,
+        "token": token,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{Body, to_bytes},
+        http::{Request, StatusCode},
+        routing::post,
+    };
+    use std::sync::Arc;
+
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        api::{AdminAuth, reject_by_policy},
+        policy::Policies,
+    };
+
+    #[tokio::test]
+    async fn test_claim_control() {
+        let lock = ControlLock::default();
+        let router = control_routes(lock.clone())
+            .route("/api/volume", post(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(
+                (Policies::new(vec![Arc::new(lock)]), AdminAuth::default()),
+                reject_by_policy,
+            ));
+        let post = |path: &str, token: Option<&str>| {
+            let mut request = Request::post(path);
+            if let Some(token) = token {
+                request = request.header(CONTROL_TOKEN_HEADER, token);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let response = router
+            .clone()
+            .oneshot(post("/api/control/claim?name=alice&minutes=30", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let mut request = post("/api/control/claim?name=alice&minutes=30", None);
+        request.extensions_mut().insert(AuthenticatedUser {
+            subject: "alice".to_string(),
+            name: "alice".to_string(),
+            scopes: vec![],
+        });
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), 1024).await.unwrap()).unwrap();
+        let token = body["value"]["token"].as_str().unwrap();
+
+        let response = router
+            .clone()
+            .oneshot(post("/api/volume", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = router
+            .clone()
+            .oneshot(post("/api/volume", Some(token)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router
+            .clone()
+            .oneshot(post("/api/control/release", Some(token)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = router.oneshot(post("/api/volume", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,148 @@
+use axum::{Router, extract::State, response::Html, routing::get};
+
+use crate::player::PlayerHandle;
+
+const CONTROL_PAGE_TEMPLATE: &str = include_str!("../../assets/control.html");
+
+/// The page talks to the REST API, relative to where greg-ng is served.
+const API_BASE: &str = "/api/v2";
+
+/// A tiny, dependency free control page for debugging on machines where
+/// the real frontend is not deployed.
+pub fn control_page_routes(player: PlayerHandle) -> Router {
+    Router::new()
+        .route("/control", get(control_page))
+        .with_state(player)
+}
+
+/// The player state the page is initially rendered with.
+/// After loading, the page keeps itself up to date by polling the API.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ControlPageState {
+    now_playing: Option<String>,
+    playing: bool,
+    volume: f64,
+    playlist: Vec<(String, bool)>,
+}
+
+async fn control_page(State(player): State<PlayerHandle>) -> Html<String> {
+    let playlist: Vec<(String, bool)> = player
+        .playlist()
+        .await
+        .map(|playlist| {
+            playlist
+                .into_iter()
+                .map(|item| (player.queue_state().display_title(&item), item.current))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let state = ControlPageState {
+        now_playing: playlist
+            .iter()
+            .find(|(_, current)| *current)
+            .map(|(title, _)| title.clone()),
+        playing: player.is_playing().await.unwrap_or(false),
+        volume: player.get_volume().await.unwrap_or(0.0),
+        playlist,
+    };
+
+    Html(render_control_page(&state))
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn render_control_page(state: &ControlPageState) -> String {
+    let playlist: String = state
+        .playlist
+        .iter()
+        .enumerate()
+        .map(|(index, (title, current))| {
+            format!(
+                r#"<li data-index="{}"{}>{}</li>"#,
+                index,
+                if *current { r#" class="current""# } else { "" },
+                escape_html(title)
+            )
+        })
+        .collect();
+
+    let now_playing = escape_html(state.now_playing.as_deref().unwrap_or("Nothing is playing"));
+    let volume = format!("{:.0}", state.volume);
+    fill_template(CONTROL_PAGE_TEMPLATE, |name| match name {
+        "now_playing" => Some(&now_playing),
+        "play_pause" if state.playing => Some("&#x23F8;"),
+        "play_pause" => Some("&#x25B6;"),
+        "volume" => Some(&volume),
+        "playing" => Some(if state.playing { "true" } else { "false" }),
+        "playlist" => Some(&playlist),
+        "api_base" => Some(API_BASE),
+        _ => None,
+    })
+}
+
+/// Replace every `{{name}}` in `template` with its value, in one pass, so that values
+/// that look like placeholders themselves, like a title of `{{playlist}}`, are left as
+/// they are. Unknown placeholders are kept.
+fn fill_template<'a>(template: &str, value: impl Fn(&str) -> Option<&'a str>) -> String {
+    let mut page = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}").map(|end| start + end) else {
+            break;
+        };
+        page.push_str(&rest[..start]);
+        match value(&rest[start + 2..end]) {
+            Some(value) => page.push_str(value),
+            None => page.push_str(&rest[start..end + 2]),
+        }
+        rest = &rest[end + 2..];
+    }
+    page.push_str(rest);
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_control_page() {
+        let page = render_control_page(&ControlPageState {
+            now_playing: Some("<script>alert(1)</script>".to_string()),
+            playing: true,
+            volume: 42.4,
+            playlist: vec![
+                ("first & best".to_string(), false),
+                ("second".to_string(), true),
+            ],
+        });
+
+        assert!(!page.contains("{{"));
+        assert!(page.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(page.contains(r#"value="42""#));
+        assert!(page.contains("let playing = true;"));
+        assert!(page.contains(r#"<li data-index="0">first &amp; best</li>"#));
+        assert!(page.contains(r#"<li data-index="1" class="current">second</li>"#));
+    }
+
+    #[test]
+    fn test_placeholders_in_titles() {
+        let page = render_control_page(&ControlPageState {
+            now_playing: Some("{{playlist}}".to_string()),
+            playing: false,
+            volume: 0.0,
+            playlist: vec![("{{api_base}}".to_string(), true)],
+        });
+
+        assert!(page.contains(r#"<li data-index="0" class="current">{{api_base}}</li>"#));
+        assert_eq!(page.matches("{{playlist}}").count(), 1);
+        assert_eq!(page.matches(r#"<li data-index="0""#).count(), 1);
+    }
+}
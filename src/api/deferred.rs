@@ -0,0 +1,191 @@
+//! Items added with a `not_before` time, like the birthday song queued to play at midnight.
+//! They are kept out of the playlist until then, and added to the end of it once the time
+//! has come. Like the items waiting in [`queue_ahead`](super::queue_ahead), they are only
+//! kept in memory, and are lost when greg restarts.
+
+use std::{sync::Mutex, time::Duration};
+
+use serde::Serialize;
+
+use crate::{history::now, player::PlayerHandle, resolver::ResolverChain};
+
+use super::{
+    ApiError, base,
+    queue_ahead::{PendingLoads, QueuedLoad},
+};
+
+/// How often the deferred items are checked for being due.
+const RELEASE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The most items that can be deferred at once.
+const MAX_DEFERRED: usize = 500;
+
+/// An item waiting for its time, as listed by `/playlist/deferred`.
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct DeferredItem {
+    /// For cancelling the item before its time.
+    pub id: u64,
+    pub path: String,
+    pub title: Option<String>,
+    pub queued_by: Option<String>,
+    /// When the item is added to the playlist, in seconds since the unix epoch.
+    pub not_before: u64,
+}
+
+#[derive(Debug)]
+struct DeferredLoad {
+    id: u64,
+    not_before: u64,
+    load: QueuedLoad,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct DeferredLoads {
+    loads: Mutex<(u64, Vec<DeferredLoad>)>,
+}
+
+impl DeferredLoads {
+    fn push(&self, load: QueuedLoad, not_before: u64) -> anyhow::Result<u64> {
+        let mut guard = self.loads.lock().unwrap();
+        let (next_id, loads) = &mut *guard;
+        if loads.len() >= MAX_DEFERRED {
+            return Err(ApiError::Conflict(format!(
+                "{} items are already waiting for their time",
+                MAX_DEFERRED
+            ))
+            .into());
+        }
+        let id = *next_id;
+        *next_id += 1;
+        log::info!(
+            "Keeping {} out of the playlist until {}",
+            load.path,
+            not_before
+        );
+        loads.push(DeferredLoad {
+            id,
+            not_before,
+            load,
+        });
+        Ok(id)
+    }
+
+    /// Every item waiting for its time, the first to be added first.
+    fn list(&self) -> Vec<DeferredItem> {
+        let (_, loads) = &*self.loads.lock().unwrap();
+        let mut items: Vec<_> = loads
+            .iter()
+            .map(|deferred| DeferredItem {
+                id: deferred.id,
+                path: deferred.load.path.clone(),
+                title: deferred.load.item.note.title.clone(),
+                queued_by: deferred.load.item.note.queued_by.clone(),
+                not_before: deferred.not_before,
+            })
+            .collect();
+        items.sort_by_key(|item| (item.not_before, item.id));
+        items
+    }
+
+    /// Forget the item with `id`. Returns whether there was such an item.
+    fn cancel(&self, id: u64) -> bool {
+        let (_, loads) = &mut *self.loads.lock().unwrap();
+        let before = loads.len();
+        loads.retain(|deferred| deferred.id != id);
+        loads.len() < before
+    }
+
+    /// Take out the items whose time has come at `now`, in the order they are due.
+    fn take_due(&self, now: u64) -> Vec<QueuedLoad> {
+        let (_, loads) = &mut *self.loads.lock().unwrap();
+        let (mut due, waiting): (Vec<_>, Vec<_>) = std::mem::take(loads)
+            .into_iter()
+            .partition(|deferred| deferred.not_before <= now);
+        *loads = waiting;
+        due.sort_by_key(|deferred| (deferred.not_before, deferred.id));
+        due.into_iter().map(|deferred| deferred.load).collect()
+    }
+}
+
+impl PendingLoads {
+    /// Keep `load` out of the playlist until `not_before`, in seconds since the unix epoch.
+    /// Returns the id it can be cancelled by.
+    pub fn defer(&self, load: QueuedLoad, not_before: u64) -> anyhow::Result<u64> {
+        self.deferred.push(load, not_before)
+    }
+
+    pub fn deferred_items(&self) -> Vec<DeferredItem> {
+        self.deferred.list()
+    }
+
+    /// Forget a deferred item before its time. Returns whether there was such an item.
+    pub fn cancel_deferred(&self, id: u64) -> bool {
+        self.deferred.cancel(id)
+    }
+}
+
+/// Keep adding deferred items to the playlist as their time comes.
+pub async fn release_deferred_loads(
+    player: PlayerHandle,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+) {
+    let mut interval = tokio::time::interval(RELEASE_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        for load in pending.deferred.take_due(now()) {
+            log::info!("The time has come for {}, adding it", load.path);
+            if let Err(e) = base::add_load(&player, &resolvers, &pending, load).await {
+                log::warn!("Failed to add a deferred item: {:#}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mpv_setup::Quality,
+        player::{ItemNote, ItemState},
+    };
+
+    #[test]
+    fn test_deferred_loads() {
+        let load = |path: &str| QueuedLoad {
+            path: path.to_string(),
+            quality: Quality::default(),
+            start: None,
+            end: None,
+            item: ItemState {
+                note: ItemNote::new(None, None, Some("someone")),
+                ..ItemState::default()
+            },
+            resume: false,
+            charge: None,
+            urls: None,
+        };
+        let deferred = DeferredLoads::default();
+        let midnight = deferred.push(load("birthday.mp3"), 200).unwrap();
+        let cancelled = deferred.push(load("cancelled.mp3"), 100).unwrap();
+        deferred.push(load("early.mp3"), 100).unwrap();
+
+        assert!(deferred.cancel(cancelled));
+        assert!(!deferred.cancel(cancelled));
+        let items = deferred.list();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].path, "early.mp3");
+        assert_eq!(items[1].id, midnight);
+        assert_eq!(items[1].queued_by.as_deref(), Some("someone"));
+
+        assert!(deferred.take_due(99).is_empty());
+        let due: Vec<_> = deferred
+            .take_due(300)
+            .into_iter()
+            .map(|load| load.path)
+            .collect();
+        assert_eq!(due, ["early.mp3", "birthday.mp3"]);
+        assert!(deferred.list().is_empty());
+    }
+}
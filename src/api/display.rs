@@ -0,0 +1,108 @@
+use axum::{
+    Json, Router,
+    extract::{Query, State, rejection::QueryRejection},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use mpvipc_async::Mpv;
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::mpv_setup::{self, Screen};
+
+use super::error::ApiError;
+
+/// The `/api/display` endpoints, for choosing which display output mpv is shown on.
+pub fn display_routes(mpv: Mpv) -> Router {
+    Router::new()
+        .route("/api/display/screen", get(get_screen).post(set_screen))
+        .with_state(mpv)
+}
+
+fn respond(result: anyhow::Result<Value>) -> Response {
+    match result {
+        Ok(value) => Json(json!({ "success": true, "value": value })).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+/// Get the screen mpv goes fullscreen on, by number or name, or null if mpv picks it
+async fn get_screen(State(mpv): State<Mpv>) -> Response {
+    respond(
+        mpv_setup::get_screen(&mpv)
+            .await
+            .map(|screen| json!(screen.map(|screen| screen.to_string()))),
+    )
+}
+
+#[derive(Deserialize)]
+struct SetScreenArgs {
+    screen: String,
+}
+
+/// Move mpv to another screen, given by number like `1` or by name like `HDMI-A-1`
+async fn set_screen(
+    State(mpv): State<Mpv>,
+    query: Result<Query<SetScreenArgs>, QueryRejection>,
+) -> Response {
+    let args = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+    let screen: Screen = match args.screen.parse() {
+        Ok(screen) => screen,
+        Err(e) => return ApiError::InvalidArgument(e).into_response(),
+    };
+
+    respond(
+        mpv_setup::set_screen(&mpv, &screen)
+            .await
+            .map(|()| Value::Null),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::FakeMpv;
+
+    #[tokio::test]
+    async fn test_set_screen() {
+        let mpv = FakeMpv::start();
+        mpv.set_property("fullscreen", json!(true));
+        let router = display_routes(mpv.connect().await);
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::post("/api/display/screen?screen=HDMI-A-1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(mpv.property("fs-screen-name"), Some(json!("HDMI-A-1")));
+        // Fullscreen was left and entered again, to move the window.
+        assert_eq!(mpv.property("fullscreen"), Some(json!(true)));
+        assert!(mpv.commands().iter().any(|command| {
+            command[0] == "set_property" && command[1] == "fullscreen" && command[2] == false
+        }));
+
+        let response = router
+            .oneshot(
+                Request::post("/api/display/screen?screen=HDMI%20A")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,259 @@
+use std::fmt;
+
+use axum::{
+    Json,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use mpvipc_async::MpvError;
+use serde::{Deserialize, Serialize};
+
+/// Errors that can be reported to API clients.
+///
+/// The base API functions return these wrapped in an [`anyhow::Error`], so that
+/// the API wrappers can pick a fitting status code by downcasting.
+///
+/// When used as a response, these are rendered as RFC 7807 problem details.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiError {
+    /// The request was malformed or contained invalid values.
+    InvalidArgument(String),
+
+    /// The request referred to a playlist index that does not exist.
+    InvalidIndex(String),
+
+    /// The request can not be fulfilled in the current state of the player.
+    Conflict(String),
+
+    /// The request has to be repeated with the token to confirm it, like clearing a large playlist.
+    ConfirmationRequired { message: String, token: String },
+
+    /// The request was understood, but is not allowed by the configured policies.
+    PolicyViolation(String),
+
+    /// The request body was larger than allowed.
+    PayloadTooLarge(String),
+
+    /// The request referred to something else that does not exist.
+    NotFound(String),
+
+    /// The client is not authenticated, or not allowed to do this.
+    Unauthorized(String),
+
+    /// The client is authenticated, but not allowed to do this, like someone who logged in
+    /// without the admin role trying to do what only admins can.
+    Forbidden(String),
+
+    /// mpv could not be reached.
+    MpvUnavailable(String),
+
+    /// A remote greg-ng instance could not be reached.
+    RemoteUnavailable(String),
+
+    /// Anything else.
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidArgument(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidIndex(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::ConfirmationRequired { .. } => StatusCode::CONFLICT,
+            ApiError::PolicyViolation(_) => StatusCode::FORBIDDEN,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::MpvUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::RemoteUnavailable(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// A short, stable identifier for the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidArgument(_) => "invalid-argument",
+            ApiError::InvalidIndex(_) => "invalid-index",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::ConfirmationRequired { .. } => "confirmation-required",
+            ApiError::PolicyViolation(_) => "policy-violation",
+            ApiError::PayloadTooLarge(_) => "payload-too-large",
+            ApiError::NotFound(_) => "not-found",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::MpvUnavailable(_) => "mpv-unreachable",
+            ApiError::RemoteUnavailable(_) => "remote-unreachable",
+            ApiError::Internal(_) => "internal-error",
+        }
+    }
+
+    /// A short, human readable summary of the kind of error.
+    pub fn title(&self) -> &'static str {
+        match self {
+            ApiError::InvalidArgument(_) => "Invalid argument",
+            ApiError::InvalidIndex(_) => "Invalid playlist index",
+            ApiError::Conflict(_) => "Conflicting player state",
+            ApiError::ConfirmationRequired { .. } => "Confirmation required",
+            ApiError::PolicyViolation(_) => "Not allowed by policy",
+            ApiError::PayloadTooLarge(_) => "Payload too large",
+            ApiError::NotFound(_) => "Not found",
+            ApiError::Unauthorized(_) => "Unauthorized",
+            ApiError::Forbidden(_) => "Forbidden",
+            ApiError::MpvUnavailable(_) => "mpv is unreachable",
+            ApiError::RemoteUnavailable(_) => "Remote instance is unreachable",
+            ApiError::Internal(_) => "Internal server error",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::InvalidArgument(message)
+            | ApiError::InvalidIndex(message)
+            | ApiError::Conflict(message)
+            | ApiError::ConfirmationRequired { message, .. }
+            | ApiError::PolicyViolation(message)
+            | ApiError::PayloadTooLarge(message)
+            | ApiError::NotFound(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::MpvUnavailable(message)
+            | ApiError::RemoteUnavailable(message)
+            | ApiError::Internal(message) => message,
+        }
+    }
+
+    pub fn to_problem_details(&self) -> ProblemDetails {
+        ProblemDetails {
+            r#type: format!("urn:greg-ng:problem:{}", self.code()),
+            title: self.title().to_string(),
+            status: self.status_code().as_u16(),
+            detail: self.message().to_string(),
+            code: self.code().to_string(),
+            confirmation_token: match self {
+                ApiError::ConfirmationRequired { token, .. } => Some(token.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// An error response body, as described in RFC 7807.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ProblemDetails {
+    /// A URI identifying the kind of problem.
+    #[schema(example = "urn:greg-ng:problem:invalid-index")]
+    pub r#type: String,
+
+    /// A short summary of the kind of problem.
+    #[schema(example = "Invalid playlist index")]
+    pub title: String,
+
+    /// The HTTP status code of the response.
+    #[schema(example = 404)]
+    pub status: u16,
+
+    /// An explanation specific to this occurrence of the problem.
+    #[schema(example = "No playlist item at index 7 (playlist has 3 items)")]
+    pub detail: String,
+
+    /// The same identifier as in `type`, without the URI prefix.
+    #[schema(example = "invalid-index")]
+    pub code: String,
+
+    /// The token to repeat the request with, for `confirmation-required` problems.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation_token: Option<String>,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (
+            self.status_code(),
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(self.to_problem_details()),
+        )
+            .into_response()
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if let Some(api_error) = cause.downcast_ref::<ApiError>() {
+                return api_error.clone();
+            }
+
+            if let Some(mpv_error) = cause.downcast_ref::<MpvError>() {
+                return match mpv_error {
+                    MpvError::MpvSocketConnectionError(_)
+                    | MpvError::InternalConnectionError(_) => {
+                        ApiError::MpvUnavailable(format!("{:#}", err))
+                    }
+                    _ => ApiError::Internal(format!("{:#}", err)),
+                };
+            }
+        }
+
+        ApiError::Internal(format!("{:#}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_api_error_from_anyhow() {
+        let err: anyhow::Error = ApiError::InvalidIndex("no such item".to_string()).into();
+        assert_eq!(
+            ApiError::from(err),
+            ApiError::InvalidIndex("no such item".to_string())
+        );
+
+        let err = Err::<(), _>(ApiError::Conflict("busy".to_string()))
+            .context("Failed to seek")
+            .unwrap_err();
+        assert_eq!(ApiError::from(err), ApiError::Conflict("busy".to_string()));
+
+        let err = anyhow::anyhow!("something broke");
+        assert_eq!(
+            ApiError::from(err),
+            ApiError::Internal("something broke".to_string())
+        );
+    }
+
+    #[test]
+    fn test_api_error_problem_details() {
+        let err = ApiError::InvalidArgument("volume can not be negative".to_string());
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            serde_json::to_value(err.to_problem_details()).unwrap(),
+            serde_json::json!({
+                "type": "urn:greg-ng:problem:invalid-argument",
+                "title": "Invalid argument",
+                "status": 400,
+                "detail": "volume can not be negative",
+                "code": "invalid-argument",
+            }),
+        );
+
+        let response = ApiError::MpvUnavailable("connection refused".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/problem+json"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Reads the mpv events once for every client, instead of once per connection.
+
+use std::collections::HashMap;
+
+use futures::StreamExt;
+use mpvipc_async::{Event, Mpv, MpvDataType, MpvExt, Playlist};
+use tokio::sync::broadcast;
+
+use crate::player::QueueState;
+
+use super::events::{OBSERVED_PROPERTIES, OutgoingEvent, playlist_items, with_held_items};
+
+/// How many events can wait for the slowest subscriber before it starts missing some.
+const BROADCAST_CAPACITY: usize = 1024;
+
+/// Translates the mpv events into [`OutgoingEvent`]s in one place, and sends them to the
+/// websocket and event stream clients that subscribe.
+#[derive(Debug, Clone)]
+pub struct EventBroadcast {
+    sender: broadcast::Sender<OutgoingEvent>,
+}
+
+impl EventBroadcast {
+    /// Start observing the properties of `mpv` in the background, describing the items in
+    /// its playlist with what `queue` knows about them.
+    pub fn spawn(mpv: Mpv, queue: QueueState) -> Self {
+        let (sender, _) = broadcast::channel(BROADCAST_CAPACITY);
+        tokio::spawn(run(mpv, queue, sender.clone()));
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<OutgoingEvent> {
+        self.sender.subscribe()
+    }
+}
+
+async fn run(mpv: Mpv, queue: QueueState, sender: broadcast::Sender<OutgoingEvent>) {
+    let mut event_stream = mpv.get_event_stream().await;
+    for property in OBSERVED_PROPERTIES {
+        if let Err(e) = mpv.observe_property(0, property).await {
+            log::warn!("Failed to observe '{}': {}", property, e);
+        }
+    }
+
+    // mpv sends some properties again without them changing, like when another observer
+    // is added, which is not worth passing on for the frequent ones.
+    let mut latest: HashMap<&'static str, OutgoingEvent> = HashMap::new();
+
+    // The playlist sent to clients also has the items held back from mpv, and the notes of
+    // the items, so it is sent again when those change.
+    let mut mpv_playlist = Playlist(Vec::new());
+    let mut held_items = queue.subscribe_held_items();
+    let mut item_states = queue.subscribe_items();
+
+    loop {
+        let event = tokio::select! {
+            event = event_stream.next() => event,
+            Ok(()) = held_items.changed() => {
+                let _ = sender.send(playlist_changed(&queue, &mpv_playlist));
+                continue;
+            }
+            Ok(()) = item_states.changed() => {
+                let _ = sender.send(playlist_changed(&queue, &mpv_playlist));
+                continue;
+            }
+        };
+        let event = match event {
+            Some(Ok(event)) => event,
+            Some(Err(e)) => {
+                log::error!("Error reading mpv events: {:?}", e);
+                return;
+            }
+            None => return,
+        };
+        if let Event::PropertyChange { name, data, .. } = &event
+            && name == "playlist"
+        {
+            mpv_playlist = match data {
+                Some(MpvDataType::Playlist(playlist)) => playlist.clone(),
+                _ => Playlist(Vec::new()),
+            };
+        }
+        let event = match OutgoingEvent::from_mpv_event(event, &queue) {
+            Some(OutgoingEvent::PlaylistChanged(playlist)) => {
+                OutgoingEvent::PlaylistChanged(with_held_items(&queue, playlist))
+            }
+            Some(event) => event,
+            None => continue,
+        };
+        if let Some(key) = event.coalesce_key() {
+            if latest.get(key) == Some(&event) {
+                continue;
+            }
+            latest.insert(key, event.clone());
+        }
+
+        // Nobody listening is fine.
+        let _ = sender.send(event);
+    }
+}
+
+/// The playlist sent to clients, with the items held back from `mpv_playlist`.
+fn playlist_changed(queue: &QueueState, mpv_playlist: &Playlist) -> OutgoingEvent {
+    let playlist = playlist_items(queue, mpv_playlist.clone());
+    OutgoingEvent::PlaylistChanged(with_held_items(queue, playlist))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::FakeMpv;
+
+    #[tokio::test]
+    async fn test_event_broadcast() {
+        let mpv = FakeMpv::start();
+        let broadcast = EventBroadcast::spawn(mpv.connect().await, QueueState::default());
+        let mut first = broadcast.subscribe();
+        let mut second = broadcast.subscribe();
+        let observing = json!(["observe_property", 0, "volume"]);
+        mpv.wait_for(|mpv| {
+            let commands = mpv.commands();
+            commands.iter().any(|command| json!(command) == observing)
+        })
+        .await;
+
+        mpv.set_property("volume", json!(30.0));
+        mpv.set_property("volume", json!(30.0));
+        mpv.set_property("mute", json!(true));
+        for receiver in [&mut first, &mut second] {
+            let mut events = Vec::new();
+            while events.last() != Some(&OutgoingEvent::Muted(true)) {
+                let event = tokio::time::timeout(Duration::from_secs(2), receiver.recv());
+                events.push(event.await.unwrap().unwrap());
+            }
+            let volumes = events
+                .iter()
+                .filter(|event| **event == OutgoingEvent::Volume(30.0))
+                .count();
+            assert_eq!(volumes, 1);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+
+use mpvipc_async::{Event, MpvDataType, Playlist};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+
+use crate::{
+    control_lock::ControlHolder,
+    expiry, lyrics,
+    playback_clock::Heartbeat,
+    player::{EntryId, QueueState},
+    radio::{self, NowPlaying},
+    sponsorblock,
+    volume_transition::VolumeCap,
+    watchdog,
+};
+
+/// A single item in the playlist, as presented to clients.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct PlaylistItem {
+    pub id: usize,
+    pub filename: String,
+    /// The title the item was added with, if any, or the one mpv found.
+    pub title: Option<String>,
+    pub note: Option<String>,
+    /// The block the item is grouped into, if any.
+    pub block: Option<String>,
+    pub current: bool,
+}
+
+pub fn playlist_items(queue: &QueueState, playlist: Playlist) -> Vec<PlaylistItem> {
+    playlist
+        .0
+        .into_iter()
+        .map(|entry| {
+            let item = queue.get(EntryId::Player(entry.id));
+            PlaylistItem {
+                id: entry.id,
+                filename: entry.filename,
+                title: item.note.title.or(entry.title),
+                note: item.note.note,
+                block: item.block,
+                current: entry.current,
+            }
+        })
+        .collect()
+}
+
+/// `items` from mpv, followed by the items held back from it, see
+/// [`QueueState::held_items`].
+pub fn with_held_items(queue: &QueueState, mut items: Vec<PlaylistItem>) -> Vec<PlaylistItem> {
+    let start = items.len();
+    items.extend(queue.held_items().into_iter().enumerate().map(|(i, held)| {
+        let item = queue.get(EntryId::Held(held.id));
+        PlaylistItem {
+            id: start + i,
+            title: item.note.title,
+            note: item.note.note,
+            block: item.block,
+            filename: held.url,
+            current: false,
+        }
+    }));
+    items
+}
+
+/// The set of events that are sent to clients whenever the player state changes.
+///
+/// These are translated from the raw mpv events, so that the format stays stable
+/// regardless of changes in mpv or the mpv ipc library.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum OutgoingEvent {
+    /// The playlist was modified, contains the entire new playlist.
+    PlaylistChanged(Vec<PlaylistItem>),
+
+    /// The path of the currently playing item changed.
+    TrackChanged(Option<String>),
+
+    /// Playback position, in percent of the duration of the current item.
+    Position(Option<f64>),
+
+    /// Sent every second with the playback position, duration and speed, and when the
+    /// position was read, for moving a progress bar along smoothly between them.
+    Heartbeat(Heartbeat),
+
+    /// Duration of the current item, in seconds.
+    Duration(Option<f64>),
+
+    /// The player volume changed.
+    Volume(f64),
+
+    /// A volume cap was set, replaced or lifted.
+    VolumeCap(Option<VolumeCap>),
+
+    /// Someone claimed exclusive control, or the claim was released or ran out.
+    ControlLock(Option<ControlHolder>),
+
+    /// The player was muted or unmuted.
+    Muted(bool),
+
+    /// The player was paused or resumed.
+    Paused(bool),
+
+    /// The player was paused or resumed while waiting for the cache to fill up.
+    PausedForCache(bool),
+
+    /// Looping of the playlist was turned on or off.
+    Looping(bool),
+
+    /// The chapters of the current item changed.
+    Chapters(Vec<Value>),
+
+    /// The subtitle tracks of the current item changed.
+    SubtitleTracks(Vec<Value>),
+
+    /// How far into the current item the demuxer cache reaches, in seconds.
+    CachedTimestamp(Option<f64>),
+
+    /// Playback stalled while waiting for the cache. Contains how much of the cache has
+    /// been filled before playback resumes, in percent. When it does, `paused_for_cache`
+    /// is sent with `false`.
+    Buffering(f64),
+
+    /// An item could not be played, and was skipped.
+    PlaybackFailed { path: String, error: String },
+
+    /// An item waited in the playlist for too long, and was removed without playing.
+    ItemExpired { path: String, title: String },
+
+    /// A SponsorBlock segment of the current item was skipped. `start` and `end` are in seconds.
+    SegmentSkipped {
+        category: String,
+        start: f64,
+        end: f64,
+    },
+
+    /// A new line of the lyrics of the current item was reached. `index` is the position
+    /// of the line in the lyrics from `/api/lyrics`, and `time` is when it starts, in seconds.
+    LyricsLine {
+        index: usize,
+        time: f64,
+        text: String,
+    },
+
+    /// A live stream, like a radio station, started playing, or its metadata changed.
+    StreamMetadata(NowPlaying),
+
+    /// The mpv instance is shutting down.
+    Shutdown,
+}
+
+/// The mpv properties that need to be observed in order to produce [`OutgoingEvent`]s.
+pub const OBSERVED_PROPERTIES: [&str; 13] = [
+    "cache-buffering-state",
+    "chapter-list",
+    "demuxer-cache-state",
+    "duration",
+    "loop-playlist",
+    "mute",
+    "path",
+    "pause",
+    "paused-for-cache",
+    "percent-pos",
+    "playlist",
+    "track-list",
+    "volume",
+];
+
+impl OutgoingEvent {
+    /// Events that are sent often and only matter until the next one of the same kind, so
+    /// that older ones can be skipped.
+    pub fn coalesce_key(&self) -> Option<&'static str> {
+        match self {
+            OutgoingEvent::Heartbeat(_) => Some("heartbeat"),
+            OutgoingEvent::Position(_) => Some("position"),
+            OutgoingEvent::Volume(_) => Some("volume"),
+            OutgoingEvent::CachedTimestamp(_) => Some("cached_timestamp"),
+            OutgoingEvent::Buffering(_) => Some("buffering"),
+            _ => None,
+        }
+    }
+
+    /// Translate a raw mpv event into an outgoing event.
+    ///
+    /// Returns `None` for events that are not relevant to clients.
+    pub fn from_mpv_event(event: Event, queue: &QueueState) -> Option<Self> {
+        match event {
+            Event::PropertyChange { name, data, .. } => {
+                Self::from_property_change(&name, data, queue)
+            }
+            Event::Shutdown => Some(OutgoingEvent::Shutdown),
+            Event::ClientMessage { args } => {
+                if let Some((category, start, end)) = sponsorblock::parse_skip_message(&args) {
+                    return Some(OutgoingEvent::SegmentSkipped {
+                        category: category.to_string(),
+                        start,
+                        end,
+                    });
+                }
+                if let Some(now_playing) = radio::parse_metadata_message(&args) {
+                    return Some(OutgoingEvent::StreamMetadata(now_playing));
+                }
+                if let Some((index, time, text)) = lyrics::parse_line_message(&args) {
+                    return Some(OutgoingEvent::LyricsLine {
+                        index,
+                        time,
+                        text: text.to_string(),
+                    });
+                }
+                if let Some((_, path, title)) = expiry::parse_expired_message(&args) {
+                    return Some(OutgoingEvent::ItemExpired {
+                        path: path.to_string(),
+                        title: title.to_string(),
+                    });
+                }
+                watchdog::parse_failure_message(&args).map(|(_, path, error)| {
+                    OutgoingEvent::PlaybackFailed {
+                        path: path.to_string(),
+                        error: error.to_string(),
+                    }
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn from_property_change(
+        name: &str,
+        data: Option<MpvDataType>,
+        queue: &QueueState,
+    ) -> Option<Self> {
+        match (name, data) {
+            ("playlist", Some(MpvDataType::Playlist(playlist))) => Some(
+                OutgoingEvent::PlaylistChanged(playlist_items(queue, playlist)),
+            ),
+            ("playlist", None) => Some(OutgoingEvent::PlaylistChanged(vec![])),
+            ("path", Some(MpvDataType::String(path))) => {
+                Some(OutgoingEvent::TrackChanged(Some(path)))
+            }
+            ("path", None) => Some(OutgoingEvent::TrackChanged(None)),
+            ("percent-pos", data) => Some(OutgoingEvent::Position(data.and_then(as_f64))),
+            ("duration", data) => Some(OutgoingEvent::Duration(data.and_then(as_f64))),
+            ("volume", Some(data)) => as_f64(data).map(OutgoingEvent::Volume),
+            ("mute", Some(MpvDataType::Bool(muted))) => Some(OutgoingEvent::Muted(muted)),
+            ("pause", Some(MpvDataType::Bool(paused))) => Some(OutgoingEvent::Paused(paused)),
+            ("paused-for-cache", Some(MpvDataType::Bool(paused))) => {
+                Some(OutgoingEvent::PausedForCache(paused))
+            }
+            ("loop-playlist", Some(MpvDataType::Bool(looping))) => {
+                Some(OutgoingEvent::Looping(looping))
+            }
+            ("loop-playlist", Some(MpvDataType::String(s))) => {
+                Some(OutgoingEvent::Looping(s != "no"))
+            }
+            ("loop-playlist", Some(MpvDataType::Usize(n))) => Some(OutgoingEvent::Looping(n > 0)),
+            ("chapter-list", data) => {
+                Some(OutgoingEvent::Chapters(match data.map(mpv_data_to_json) {
+                    Some(Value::Array(chapters)) => chapters,
+                    _ => vec![],
+                }))
+            }
+            ("track-list", data) => Some(OutgoingEvent::SubtitleTracks(
+                match data.map(mpv_data_to_json) {
+                    Some(Value::Array(tracks)) => subtitle_tracks(tracks),
+                    _ => vec![],
+                },
+            )),
+            ("demuxer-cache-state", data) => Some(OutgoingEvent::CachedTimestamp(
+                data.map(mpv_data_to_json)
+                    .as_ref()
+                    .and_then(|v| v.get("cache-end"))
+                    .and_then(|v| v.as_f64()),
+            )),
+            // mpv keeps this at 100 unless playback is stalled.
+            ("cache-buffering-state", Some(data)) => as_f64(data)
+                .filter(|percent| *percent < 100.0)
+                .map(OutgoingEvent::Buffering),
+            ("cache-buffering-state", None) => None,
+            (name, _) => {
+                log::trace!("Ignoring unexpected property change: {}", name);
+                None
+            }
+        }
+    }
+}
+
+/// Filter a list of mpv tracks down to the subtitle tracks.
+pub fn subtitle_tracks(tracks: Vec<Value>) -> Vec<Value> {
+    tracks
+        .into_iter()
+        .filter(|t| {
+            t.as_object()
+                .and_then(|o| o.get("type"))
+                .and_then(|t| t.as_str())
+                .unwrap_or("")
+                == "sub"
+        })
+        .collect()
+}
+
+fn as_f64(data: MpvDataType) -> Option<f64> {
+    match data {
+        MpvDataType::Double(d) => Some(d),
+        MpvDataType::Usize(u) => Some(u as f64),
+        _ => None,
+    }
+}
+
+fn mpv_data_to_json(data: MpvDataType) -> Value {
+    match data {
+        MpvDataType::Array(items) => {
+            Value::Array(items.into_iter().map(mpv_data_to_json).collect())
+        }
+        MpvDataType::Bool(b) => Value::Bool(b),
+        MpvDataType::Double(d) => serde_json::Number::from_f64(d)
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        MpvDataType::HashMap(map) => Value::Object(hashmap_to_json(map)),
+        MpvDataType::Null => Value::Null,
+        MpvDataType::MinusOne => Value::from(-1),
+        MpvDataType::Playlist(playlist) => Value::Array(
+            playlist
+                .0
+                .into_iter()
+                .map(|entry| {
+                    json!({
+                        "id": entry.id,
+                        "filename": entry.filename,
+                        "title": entry.title,
+                        "current": entry.current,
+                    })
+                })
+                .collect(),
+        ),
+        MpvDataType::String(s) => Value::String(s),
+        MpvDataType::Usize(u) => Value::from(u),
+    }
+}
+
+fn hashmap_to_json(map: HashMap<String, MpvDataType>) -> Map<String, Value> {
+    map.into_iter()
+        .map(|(k, v)| (k, mpv_data_to_json(v)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffering_event() {
+        assert_eq!(
+            OutgoingEvent::from_property_change(
+                "cache-buffering-state",
+                Some(MpvDataType::Usize(42)),
+                &QueueState::default()
+            ),
+            Some(OutgoingEvent::Buffering(42.0))
+        );
+        assert_eq!(
+            OutgoingEvent::from_property_change(
+                "cache-buffering-state",
+                Some(MpvDataType::Usize(100)),
+                &QueueState::default()
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use axum::{
+    Json, Router,
+    extract::{FromRef, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::get,
+};
+use serde_json::json;
+
+use crate::player::{BreakerState, CircuitBreaker};
+
+use super::queue_ahead::PendingLoads;
+
+#[derive(Debug, Clone, FromRef)]
+struct HealthState {
+    breaker: CircuitBreaker,
+    pending: PendingLoads,
+}
+
+/// The `/health` endpoint, for monitoring whether the player is responding.
+pub fn health_routes(breaker: CircuitBreaker, pending: PendingLoads) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .with_state(HealthState { breaker, pending })
+}
+
+/// Whether the player is responding, with the state of the circuit breaker in front of it,
+/// and how many items are waiting for it to come back. Answers with 503 while calls to the
+/// player are failing.
+async fn health(State(HealthState { breaker, pending }): State<HealthState>) -> impl IntoResponse {
+    let player = breaker.status();
+    let (status, code) = match player.state {
+        BreakerState::Closed => (StatusCode::OK, "ok"),
+        BreakerState::Open | BreakerState::HalfOpen => {
+            (StatusCode::SERVICE_UNAVAILABLE, "player-unavailable")
+        }
+    };
+    (
+        status,
+        Json(json!({
+            "status": code,
+            "player": player,
+            "queued_loads": pending.queued_count(),
+        })),
+    )
+}
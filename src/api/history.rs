@@ -0,0 +1,182 @@
+use std::convert::Infallible;
+
+use axum::{
+    Router,
+    body::Body,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::{
+    history::{self, PlayHistory},
+    stats::TimeRange,
+    util::Page,
+};
+
+use super::{
+    error::ApiError,
+    rest_endpoints::rest_endpoints,
+    rest_wrapper_v2::{self, ErrorResponses, SuccessResponse, query_rejection},
+};
+
+/// How many entries are returned by default.
+const DEFAULT_LIMIT: usize = 50;
+
+/// The `/api/history` endpoints, listing what has been played.
+pub fn history_routes(history: PlayHistory) -> Router {
+    let (router, _) = api_router().with_state(history).split_for_parts();
+
+    router
+}
+
+pub(super) fn history_openapi() -> utoipa::openapi::OpenApi {
+    let (_, api): (Router<PlayHistory>, _) = api_router().split_for_parts();
+    api
+}
+
+rest_endpoints! {
+    router = api_router, state = PlayHistory;
+
+    /// Get the most recently played items, newest first
+    ///
+    /// `offset` and `limit` page through the history, and `queued_by` only returns the items
+    /// added by someone. `fields` is a comma separated list of the fields to return for each
+    /// entry, like `path,title,started_at`.
+    get "/api/history" -> SuccessResponse;
+    async fn get_history(
+        history: PlayHistory,
+        offset: Option<usize>,
+        limit: Option<usize>,
+        queued_by: Option<String>,
+        fields: Option<String>,
+    ) {
+        recent(&history, offset, limit, queued_by, fields)
+    }
+
+    /// Download everything that has been played, oldest first, as CSV or JSON lines
+    ///
+    /// `from` and `to` are either seconds since the unix epoch, or `YYYY-MM-DD` dates.
+    get "/api/history/export" -> String;
+    async fn export_history(
+        history: PlayHistory,
+        format: Option<ExportFormat>,
+        from: Option<String>,
+        to: Option<String>,
+    ) {
+        export(&history, format.unwrap_or_default(), from, to)
+    }
+}
+
+/// Like the [`RestResponse`](rest_wrapper_v2::RestResponse) of the other endpoints, except
+/// that exports are sent as a download rather than in the `{ success, value }` envelope.
+enum RestResponse {
+    Value(rest_wrapper_v2::RestResponse),
+    Download(Result<Response, ApiError>),
+}
+
+impl From<anyhow::Result<Value>> for RestResponse {
+    fn from(result: anyhow::Result<Value>) -> Self {
+        Self::Value(result.into())
+    }
+}
+
+impl From<anyhow::Result<Response>> for RestResponse {
+    fn from(result: anyhow::Result<Response>) -> Self {
+        Self::Download(result.map_err(ApiError::from))
+    }
+}
+
+impl IntoResponse for RestResponse {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Value(response) => response.into_response(),
+            Self::Download(Ok(response)) => response,
+            Self::Download(Err(err)) => err.into_response(),
+        }
+    }
+}
+
+fn recent(
+    history: &PlayHistory,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    queued_by: Option<String>,
+    fields: Option<String>,
+) -> anyhow::Result<Value> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(history::MAX_ENTRIES);
+    let page = Page::new(offset, Some(limit), fields.as_deref());
+
+    // Entries added by someone can be anywhere, so all of them are looked through.
+    let count = match queued_by {
+        Some(_) => history::MAX_ENTRIES,
+        None => page.end().unwrap_or(limit).min(history::MAX_ENTRIES),
+    };
+    let entries = history
+        .recent(count)?
+        .into_iter()
+        .filter(|entry| {
+            queued_by.as_deref().is_none_or(|queued_by| {
+                entry
+                    .queued_by
+                    .as_deref()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(queued_by.trim()))
+            })
+        })
+        .map(|entry| json!(entry));
+
+    Ok(json!(page.apply(entries)))
+}
+
+#[derive(Deserialize, Clone, Copy, Default, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    Csv,
+    #[default]
+    Jsonl,
+}
+
+fn export(
+    history: &PlayHistory,
+    format: ExportFormat,
+    from: Option<String>,
+    to: Option<String>,
+) -> anyhow::Result<Response> {
+    let range = TimeRange::parse(from.as_deref(), to.as_deref())?;
+    let entries = history.entries(&range)?.into_iter();
+    let (content_type, extension, lines): (_, _, Box<dyn Iterator<Item = String> + Send>) =
+        match format {
+            ExportFormat::Csv => (
+                "text/csv; charset=utf-8",
+                "csv",
+                Box::new(
+                    std::iter::once(history::CSV_HEADER.to_string())
+                        .chain(entries.map(|entry| entry.to_csv_record()))
+                        .map(|line| line + "\r\n"),
+                ),
+            ),
+            ExportFormat::Jsonl => (
+                "application/jsonl",
+                "jsonl",
+                Box::new(
+                    entries.map(|entry| serde_json::to_string(&entry).unwrap_or_default() + "\n"),
+                ),
+            ),
+        };
+
+    let disposition = format!(
+        "attachment; filename=\"greg-history-{}.{}\"",
+        history::now(),
+        extension
+    );
+    let body = Body::from_stream(futures::stream::iter(lines.map(Ok::<_, Infallible>)));
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
@@ -0,0 +1,61 @@
+use axum::Router;
+use serde_json::json;
+
+use crate::{inputs::Inputs, player::PlayerHandle};
+
+use super::{
+    rest_endpoints::rest_endpoints,
+    rest_wrapper_v2::{
+        EmptySuccessResponse, ErrorResponses, RestResponse, SuccessResponse, query_rejection,
+    },
+};
+
+#[derive(Debug, Clone)]
+struct InputsState {
+    player: PlayerHandle,
+    inputs: Inputs,
+}
+
+/// The `/api/inputs` endpoints, for switching between the playlist and external inputs.
+pub fn inputs_routes(player: PlayerHandle, inputs: Inputs) -> Router {
+    let (router, _) = api_router()
+        .with_state(InputsState { player, inputs })
+        .split_for_parts();
+
+    router
+}
+
+pub(super) fn inputs_openapi() -> utoipa::openapi::OpenApi {
+    let (_, api): (Router<InputsState>, _) = api_router().split_for_parts();
+    api
+}
+
+rest_endpoints! {
+    router = api_router, state = InputsState;
+
+    /// List the inputs, and which of them is active
+    get "/api/inputs" -> SuccessResponse;
+    async fn get_inputs(state: InputsState) {
+        Ok(json!({
+            "inputs": state
+                .inputs
+                .inputs()
+                .into_iter()
+                .map(|input| json!({ "name": input.name, "url": input.url }))
+                .collect::<Vec<_>>(),
+            "active": state.inputs.active().await,
+        }))
+    }
+
+    /// Switch back from the active input to the playlist
+    post "/api/inputs/deactivate" -> EmptySuccessResponse;
+    async fn deactivate_input(state: InputsState) {
+        state.inputs.deactivate(&state.player).await
+    }
+
+    /// Suspend the playlist, and play from an input instead
+    post "/api/inputs/{name}/activate" -> EmptySuccessResponse, path = (name: String);
+    async fn activate_input(state: InputsState) {
+        state.inputs.activate(&state.player, &name).await
+    }
+}
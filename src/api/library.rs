@@ -0,0 +1,63 @@
+use axum::{
+    Json, Router,
+    extract::{Query, State, rejection::QueryRejection},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{library::LibraryIndex, util::Page};
+
+use super::error::ApiError;
+
+/// The most results a single search can return.
+const MAX_SEARCH_RESULTS: usize = 500;
+
+/// Routes for the local media library, under `/api/library`.
+pub fn library_routes(index: LibraryIndex) -> Router {
+    Router::new()
+        .route("/api/library/search", get(library_search))
+        .with_state(index)
+}
+
+#[derive(Deserialize)]
+struct SearchArgs {
+    q: String,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    fields: Option<String>,
+}
+
+/// Search the local media library by title, artist, album and filename.
+///
+/// The returned paths can be passed directly to the load endpoint. `offset` and `limit`
+/// page through the results, and `fields` is a comma separated list of the fields to
+/// return for each track, like `path,title`.
+async fn library_search(
+    State(index): State<LibraryIndex>,
+    query: Result<Query<SearchArgs>, QueryRejection>,
+) -> Response {
+    let SearchArgs {
+        q,
+        offset,
+        limit,
+        fields,
+    } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+
+    let limit = limit.unwrap_or(50).min(MAX_SEARCH_RESULTS);
+    let page = Page::new(offset, Some(limit), fields.as_deref());
+    let count = page.end().unwrap_or(limit).min(MAX_SEARCH_RESULTS);
+
+    match tokio::task::spawn_blocking(move || index.search(&q, count)).await {
+        Ok(Ok(tracks)) => {
+            let tracks = page.apply(tracks.into_iter().map(|track| json!(track)));
+            Json(json!({ "success": true, "value": tracks })).into_response()
+        }
+        Ok(Err(e)) => ApiError::from(e).into_response(),
+        Err(e) => ApiError::Internal(e.to_string()).into_response(),
+    }
+}
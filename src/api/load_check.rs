@@ -0,0 +1,222 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{
+    Extension, Json, Router,
+    extract::{ConnectInfo, Query, State, rejection::QueryRejection},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::process::Command as Process;
+
+use crate::{
+    mpv_setup::Quality,
+    oidc::LoggedIn,
+    player::{ItemState, Priority},
+    policy::{Command, Policies, PolicyEngine},
+    quotas::coarse_identity,
+    resolver::ResolverChain,
+    scopes::Scope,
+    server::ClientAddr,
+};
+
+use super::{
+    admin::{AdminAuth, is_admin},
+    base,
+    control::control_token,
+    error::ApiError,
+    queue_ahead::QueuedLoad,
+};
+
+/// Where the load check is served. It changes nothing, so the policies let it through.
+pub const LOAD_CHECK_PATH: &str = "/api/load/check";
+
+/// How long yt-dlp gets to look up a single item.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// At most this many of the items a path resolves to are looked up, since a playlist or
+/// an album can be long.
+const MAX_PROBED: usize = 5;
+
+#[derive(Debug, Clone)]
+struct LoadCheckState {
+    policies: Policies,
+    admin: AdminAuth,
+    resolvers: ResolverChain,
+    yt_dlp_path: String,
+}
+
+/// The `/api/load/check` endpoint, for finding out what `/api/load` would do.
+pub fn load_check_routes(
+    policies: Policies,
+    admin: AdminAuth,
+    resolvers: ResolverChain,
+    yt_dlp_path: String,
+) -> Router {
+    Router::new()
+        .route(LOAD_CHECK_PATH, post(load_check))
+        .with_state(LoadCheckState {
+            policies,
+            admin,
+            resolvers,
+            yt_dlp_path,
+        })
+}
+
+#[derive(Deserialize)]
+struct LoadCheckArgs {
+    path: String,
+    start: Option<String>,
+    end: Option<String>,
+    #[serde(default)]
+    resume: bool,
+    #[serde(default)]
+    priority: Priority,
+}
+
+/// One of the items a path would be added as.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct CheckedItem {
+    url: String,
+    title: Option<String>,
+    /// In seconds.
+    duration: Option<f64>,
+}
+
+/// Parse the output of yt-dlp with the template used in [`probe`].
+fn parse_probe_output(output: &str) -> (Option<String>, Option<f64>) {
+    let line = output.lines().next().unwrap_or_default().trim();
+    let mut fields = line
+        .split('\t')
+        .map(|field| Some(field).filter(|field| !field.is_empty() && *field != "NA"));
+    let title = fields.next().flatten().map(str::to_string);
+    let duration = fields.next().flatten().and_then(|d| d.parse().ok());
+    (title, duration)
+}
+
+/// Look up the title and duration of `url` with yt-dlp.
+async fn probe(yt_dlp_path: &str, url: &str) -> anyhow::Result<(Option<String>, Option<f64>)> {
+    let output = tokio::time::timeout(
+        PROBE_TIMEOUT,
+        Process::new(yt_dlp_path)
+            .args(["--no-playlist", "--no-warnings", "--skip-download"])
+            .args(["--print", "%(title)s\t%(duration)s"])
+            .arg("--")
+            .arg(url.strip_prefix("ytdl://").unwrap_or(url))
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .context("yt-dlp timed out")?
+    .context("Failed to run yt-dlp")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "yt-dlp exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(parse_probe_output(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Check an item without adding it: whether the policies and arguments allow it, and what
+/// it would be added as, with the title and duration of the first few items. Takes the same
+/// `path`, `start`, `end`, `resume` and `priority` as `/api/load`.
+///
+/// Refusals are listed in `violations` instead of failing the request, so that a frontend
+/// can show them before submitting.
+async fn load_check(
+    State(state): State<LoadCheckState>,
+    headers: HeaderMap,
+    user: LoggedIn,
+    addr: Option<Extension<ConnectInfo<ClientAddr>>>,
+    query: Result<Query<LoadCheckArgs>, QueryRejection>,
+) -> Response {
+    let args = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+
+    let mut violations = Vec::new();
+    let identity =
+        addr.map(|Extension(ConnectInfo(addr))| coarse_identity(&addr, &headers, user.as_deref()));
+    let command = Command {
+        path: Some("/api/load"),
+        is_admin: is_admin(&headers, user.as_deref(), &state.admin),
+        control_token: control_token(&headers),
+        scopes: user.as_ref().map(|user| user.scopes.as_slice()),
+        needs: Some(Scope::QueueAdd),
+        identity: identity.as_deref(),
+        priority: args.priority,
+    };
+    if let Err(e) = state.policies.check_command(&command) {
+        violations.push(e.to_problem_details());
+    }
+    let load = QueuedLoad {
+        path: args.path.clone(),
+        quality: Quality::default(),
+        start: args.start,
+        end: args.end,
+        item: ItemState::default(),
+        resume: args.resume,
+        charge: None,
+        urls: None,
+    };
+    if let Err(e) = base::check_load(&load) {
+        violations.push(ApiError::from(e).to_problem_details());
+    }
+
+    let urls = match state.resolvers.resolve(&args.path).await {
+        Ok(urls) => urls,
+        Err(e) => {
+            violations.push(ApiError::from(e).to_problem_details());
+            vec![]
+        }
+    };
+    let mut items = Vec::with_capacity(urls.len());
+    for (i, url) in urls.into_iter().enumerate() {
+        // Local files are left to mpv, yt-dlp only knows about URLs.
+        let (title, duration) = if i < MAX_PROBED && url.contains("://") {
+            probe(&state.yt_dlp_path, &url).await.unwrap_or_else(|e| {
+                log::debug!("Failed to look up {}: {:#}", url, e);
+                (None, None)
+            })
+        } else {
+            (None, None)
+        };
+        items.push(CheckedItem {
+            url,
+            title,
+            duration,
+        });
+    }
+
+    Json(json!({
+        "success": true,
+        "value": {
+            "allowed": violations.is_empty(),
+            "violations": violations,
+            "items": items,
+        },
+    }))
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_probe_output() {
+        assert_eq!(
+            parse_probe_output("Never Gonna Give You Up\t212\n"),
+            (Some("Never Gonna Give You Up".to_string()), Some(212.0))
+        );
+        assert_eq!(parse_probe_output("NA\tNA\n"), (None, None));
+        assert_eq!(parse_probe_output(""), (None, None));
+    }
+}
@@ -0,0 +1,21 @@
+use axum::{
+    Json, Router,
+    extract::State,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde_json::json;
+
+use crate::lyrics::Lyrics;
+
+/// The `/api/lyrics` endpoint, serving the lyrics of the current item.
+pub fn lyrics_routes(lyrics: Lyrics) -> Router {
+    Router::new()
+        .route("/api/lyrics", get(get_lyrics))
+        .with_state(lyrics)
+}
+
+/// Get the lyrics of the current item, or null if none were found
+async fn get_lyrics(State(lyrics): State<Lyrics>) -> Response {
+    Json(json!({ "success": true, "value": lyrics.current() })).into_response()
+}
@@ -0,0 +1,33 @@
+use axum::{
+    Json, Router,
+    extract::State,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde_json::json;
+
+use crate::{oidc::LoggedIn, scopes::Scope};
+
+use super::admin::{AdminAuth, is_admin};
+
+/// The `/api/me` endpoint, for clients to find out what they can do.
+pub fn me_routes(admin: AdminAuth) -> Router {
+    Router::new().route("/api/me", get(me)).with_state(admin)
+}
+
+/// Who the request is from, if they logged in or gave an API key, and the scopes they have,
+/// so that clients can hide what they can not do. `admin:*` is listed for the admin token.
+async fn me(State(admin): State<AdminAuth>, headers: HeaderMap, user: LoggedIn) -> Response {
+    let scopes = match &user {
+        _ if is_admin(&headers, user.as_deref(), &admin) => Scope::ALL.to_vec(),
+        Some(user) => Scope::effective(&user.scopes),
+        None => Scope::EVERYONE.to_vec(),
+    };
+    let user = user.map(|user| user.0);
+    Json(json!({
+        "success": true,
+        "value": { "user": user, "scopes": scopes },
+    }))
+    .into_response()
+}
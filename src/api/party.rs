@@ -0,0 +1,52 @@
+use axum::{
+    Json, Router,
+    extract::{Query, State, rejection::QueryRejection},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::party::PartyMode;
+
+use super::error::ApiError;
+
+/// The `/api/party` endpoint, for turning party mode on and off.
+pub fn party_routes(party: PartyMode) -> Router {
+    Router::new()
+        .route("/api/party", get(get_party).post(set_party))
+        .with_state(party)
+}
+
+/// Get whether party mode is on, and where it picks items from
+async fn get_party(State(party): State<PartyMode>) -> Response {
+    Json(json!({
+        "success": true,
+        "value": {
+            "enabled": party.is_enabled(),
+            "source": party.source().map(|source| source.to_string()),
+        },
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct PartyArgs {
+    enabled: bool,
+}
+
+/// Turn party mode on or off
+async fn set_party(
+    State(party): State<PartyMode>,
+    query: Result<Query<PartyArgs>, QueryRejection>,
+) -> Response {
+    let PartyArgs { enabled } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+
+    match party.set_enabled(enabled) {
+        Ok(()) => Json(json!({ "success": true, "value": null })).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
@@ -0,0 +1,413 @@
+use axum::{
+    Json, Router,
+    extract::{State, rejection::JsonRejection},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    autoplay::Autoplay,
+    oidc::LoggedIn,
+    player::{
+        self, EntryId, ItemFlags, PlayerHandle, PlaylistEntry, PlaylistMove, QueueState, RetryQueue,
+    },
+    policy::{Command, Policies, PolicyEngine},
+    scopes::Scope,
+    util::Page,
+};
+
+use super::{
+    admin::{AdminAuth, is_admin},
+    base,
+    control::control_token,
+    error::ApiError,
+};
+
+/// Where the bulk operations are served. The policies are asked about each operation by
+/// the endpoint itself, since they need different scopes.
+pub const PLAYLIST_OPS_PATH: &str = "/api/playlist/ops";
+
+/// The most operations that can be sent at once.
+const MAX_OPS: usize = 100;
+
+#[derive(Debug, Clone)]
+struct PlaylistOpsState {
+    player: PlayerHandle,
+    retries: RetryQueue,
+    autoplay: Autoplay,
+    policies: Policies,
+    admin: AdminAuth,
+}
+
+/// The `/api/playlist/ops` endpoint, for changing several things in the playlist at once.
+pub fn playlist_ops_routes(
+    player: PlayerHandle,
+    retries: RetryQueue,
+    autoplay: Autoplay,
+    policies: Policies,
+    admin: AdminAuth,
+) -> Router {
+    Router::new()
+        .route(PLAYLIST_OPS_PATH, post(playlist_ops))
+        .with_state(PlaylistOpsState {
+            player,
+            retries,
+            autoplay,
+            policies,
+            admin,
+        })
+}
+
+/// One change to the playlist. Indices are positions in the playlist as it is after the
+/// operations before it.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum PlaylistOp {
+    /// Remove the items at `indices`.
+    Remove { indices: Vec<usize> },
+    /// Move `count` items starting at `from` to before the item at `to`, keeping their order.
+    Move {
+        from: usize,
+        #[serde(default = "one")]
+        count: usize,
+        to: usize,
+    },
+    /// Set the flags of the item at `index`, leaving out the ones not given. Only admins
+    /// can do this.
+    SetFlags {
+        index: usize,
+        pinned: Option<bool>,
+        locked: Option<bool>,
+        background: Option<bool>,
+    },
+}
+
+fn one() -> usize {
+    1
+}
+
+impl PlaylistOp {
+    fn needs(&self) -> Scope {
+        match self {
+            PlaylistOp::Remove { .. } => Scope::QueueRemove,
+            PlaylistOp::Move { .. } => Scope::PlaybackControl,
+            PlaylistOp::SetFlags { .. } => Scope::Admin,
+        }
+    }
+}
+
+/// An operation checked against the playlist, ready to be applied.
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Remove(usize),
+    Move(PlaylistMove),
+    SetFlags { id: EntryId, flags: ItemFlags },
+}
+
+fn no_item(index: usize, length: usize) -> ApiError {
+    ApiError::InvalidIndex(format!(
+        "No playlist item at index {} (playlist has {} items)",
+        index, length
+    ))
+}
+
+/// The single moves that move `count` items at `from` to before the item at `to`.
+fn range_moves(from: usize, count: usize, to: usize) -> Vec<PlaylistMove> {
+    if to > from + count {
+        (0..count).map(|_| PlaylistMove { from, to }).collect()
+    } else if to < from {
+        (0..count)
+            .map(|i| PlaylistMove {
+                from: from + i,
+                to: to + i,
+            })
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Check `ops` against `playlist`, playing them out on a copy of it, so that an operation
+/// that does not fit is refused before any of them are applied. Locked items can not be
+/// removed, and pinned items can not be moved, the same as one at a time.
+fn plan(
+    queue: &QueueState,
+    playlist: &[PlaylistEntry],
+    ops: &[PlaylistOp],
+) -> Result<Vec<Step>, ApiError> {
+    let mut playlist = playlist.to_vec();
+    let mut steps = Vec::new();
+    for op in ops {
+        let length = playlist.len();
+        match op {
+            PlaylistOp::Remove { indices } => {
+                if let Some(index) = indices.iter().find(|index| **index >= length) {
+                    return Err(no_item(*index, length));
+                }
+                player::check_removal(queue, &playlist, indices)?;
+                let mut indices = indices.clone();
+                indices.sort_unstable();
+                indices.dedup();
+                // Back to front, so that the indices stay valid.
+                for index in indices.into_iter().rev() {
+                    playlist.remove(index);
+                    steps.push(Step::Remove(index));
+                }
+            }
+            PlaylistOp::Move { from, count, to } => {
+                if *count == 0 {
+                    return Err(ApiError::InvalidArgument(
+                        "count must be at least 1".to_string(),
+                    ));
+                }
+                if from + count > length {
+                    return Err(no_item(from + count - 1, length));
+                }
+                if *to > length {
+                    return Err(ApiError::InvalidIndex(format!(
+                        "Can not move items to index {} (playlist has {} items)",
+                        to, length
+                    )));
+                }
+                let moves = range_moves(*from, *count, *to);
+                player::check_moves(queue, &playlist, &moves)?;
+                for PlaylistMove { from, to } in moves {
+                    let entry = playlist.remove(from);
+                    playlist.insert(if to > from { to - 1 } else { to }, entry);
+                    steps.push(Step::Move(PlaylistMove { from, to }));
+                }
+            }
+            PlaylistOp::SetFlags {
+                index,
+                pinned,
+                locked,
+                background,
+            } => {
+                let entry = playlist
+                    .get(*index)
+                    .ok_or_else(|| no_item(*index, length))?;
+                let current = queue.item(entry).flags;
+                steps.push(Step::SetFlags {
+                    id: entry.id,
+                    flags: ItemFlags {
+                        pinned: pinned.unwrap_or(current.pinned),
+                        locked: locked.unwrap_or(current.locked),
+                        background: background.unwrap_or(current.background),
+                    },
+                });
+            }
+        }
+    }
+    Ok(steps)
+}
+
+/// Change several things in the playlist at once, like removing or moving the items
+/// picked in a frontend, and get the playlist back as it is afterwards.
+///
+/// The body is a JSON array of operations: `{"op": "remove", "indices": [..]}`,
+/// `{"op": "move", "from": .., "count": .., "to": ..}` and
+/// `{"op": "set_flags", "index": .., "pinned": .., "locked": .., "background": ..}`. They are
+/// applied in order, with the indices of each one going by the playlist as the ones before
+/// left it. Every operation is checked before any of them is applied, and nobody else can
+/// change the playlist in between. If the player fails partway through, the changes made
+/// before that are kept, so the playlist should be fetched again after an error.
+async fn playlist_ops(
+    State(state): State<PlaylistOpsState>,
+    headers: HeaderMap,
+    user: LoggedIn,
+    body: Result<Json<Vec<PlaylistOp>>, JsonRejection>,
+) -> Response {
+    let ops = match body {
+        Ok(Json(ops)) => ops,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+    if ops.len() > MAX_OPS {
+        return ApiError::InvalidArgument(format!("At most {} operations can be sent", MAX_OPS))
+            .into_response();
+    }
+
+    let is_admin = is_admin(&headers, user.as_deref(), &state.admin);
+    for op in &ops {
+        let command = Command {
+            path: Some(PLAYLIST_OPS_PATH),
+            is_admin,
+            control_token: control_token(&headers),
+            scopes: user.as_ref().map(|user| user.scopes.as_slice()),
+            needs: Some(op.needs()),
+            identity: None,
+            priority: player::Priority::Normal,
+        };
+        if let Err(e) = state.policies.check_command(&command) {
+            return e.into_response();
+        }
+        if matches!(op, PlaylistOp::SetFlags { .. }) && !is_admin {
+            return ApiError::Unauthorized("Only admins can set the flags of items".to_string())
+                .into_response();
+        }
+    }
+
+    let result = async {
+        let _lock = player::lock_playlist(state.player.queue_state()).await;
+        let playlist = state.player.playlist().await?;
+        let queue = state.player.queue_state();
+        for step in plan(queue, &playlist, &ops)? {
+            match step {
+                Step::Remove(index) => state.player.playlist_remove(index).await?,
+                Step::Move(PlaylistMove { from, to }) => {
+                    state.player.playlist_move(from, to).await?
+                }
+                Step::SetFlags { id, flags } => {
+                    log::info!("Setting the flags of item {} to {:?}", id, flags);
+                    queue.update(id, |item| item.flags = flags);
+                }
+            }
+        }
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        return ApiError::from(e).into_response();
+    }
+
+    match base::playlist_get(
+        state.player,
+        &state.retries,
+        &state.autoplay,
+        None,
+        &Page::default(),
+    )
+    .await
+    {
+        Ok(playlist) => Json(json!({ "success": true, "value": playlist })).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    fn entries(filenames: &[&str]) -> Vec<PlaylistEntry> {
+        filenames
+            .iter()
+            .enumerate()
+            .map(|(id, filename)| PlaylistEntry {
+                id: EntryId::Player(id),
+                filename: filename.to_string(),
+                title: None,
+                current: false,
+            })
+            .collect()
+    }
+
+    /// Plan `ops` on `playlist`, and apply the steps the way mpv would.
+    fn planned(playlist: &[&str], ops: Value) -> (Vec<Step>, Vec<String>) {
+        let ops: Vec<PlaylistOp> = serde_json::from_value(ops).unwrap();
+        let steps = plan(&QueueState::default(), &entries(playlist), &ops).unwrap();
+        let mut order: Vec<_> = playlist.iter().map(|s| s.to_string()).collect();
+        for step in &steps {
+            match step {
+                Step::Remove(index) => {
+                    order.remove(*index);
+                }
+                Step::Move(PlaylistMove { from, to }) => {
+                    let item = order.remove(*from);
+                    order.insert(if to > from { to - 1 } else { *to }, item);
+                }
+                Step::SetFlags { .. } => {}
+            }
+        }
+        (steps, order)
+    }
+
+    fn moved(from: usize, to: usize) -> Step {
+        Step::Move(PlaylistMove { from, to })
+    }
+
+    #[test]
+    fn test_plan() {
+        let playlist = ["ops-a", "ops-b", "ops-c", "ops-d", "ops-e"];
+
+        // Removed back to front, with repeated indices only removed once.
+        let (steps, order) = planned(&playlist, json!([{ "op": "remove", "indices": [3, 0, 3] }]));
+        assert_eq!(steps, [Step::Remove(3), Step::Remove(0)]);
+        assert_eq!(order, ["ops-b", "ops-c", "ops-e"]);
+
+        // Moved forwards, past the items after them.
+        let (steps, order) = planned(
+            &playlist,
+            json!([{ "op": "move", "from": 0, "count": 2, "to": 4 }]),
+        );
+        assert_eq!(steps, [moved(0, 4), moved(0, 4)]);
+        assert_eq!(order, ["ops-c", "ops-d", "ops-a", "ops-b", "ops-e"]);
+
+        // Moved backwards, before the items in front of them.
+        let (steps, order) = planned(
+            &playlist,
+            json!([{ "op": "move", "from": 3, "count": 2, "to": 1 }]),
+        );
+        assert_eq!(steps, [moved(3, 1), moved(4, 2)]);
+        assert_eq!(order, ["ops-a", "ops-d", "ops-e", "ops-b", "ops-c"]);
+
+        // Moved to where they already are.
+        let (steps, order) = planned(
+            &playlist,
+            json!([{ "op": "move", "from": 1, "count": 2, "to": 2 }]),
+        );
+        assert_eq!(steps, []);
+        assert_eq!(order, playlist);
+
+        // Flags left out keep what they were.
+        let (steps, order) = planned(
+            &playlist,
+            json!([{ "op": "set_flags", "index": 1, "pinned": true }]),
+        );
+        assert_eq!(
+            steps,
+            [Step::SetFlags {
+                id: EntryId::Player(1),
+                flags: ItemFlags {
+                    pinned: true,
+                    locked: false,
+                    background: false,
+                },
+            }]
+        );
+        assert_eq!(order, playlist);
+
+        // Each operation goes by the playlist as the ones before left it.
+        let (steps, order) = planned(
+            &playlist,
+            json!([
+                { "op": "remove", "indices": [3, 0] },
+                { "op": "move", "from": 0, "count": 2, "to": 3 },
+                { "op": "set_flags", "index": 0, "pinned": true },
+            ]),
+        );
+        assert!(matches!(&steps[4], Step::SetFlags { id, .. } if *id == EntryId::Player(4)));
+        assert_eq!(order, ["ops-e", "ops-b", "ops-c"]);
+
+        // Nothing is planned if any operation does not fit.
+        let out_of_range: Vec<PlaylistOp> = serde_json::from_value(json!([
+            { "op": "remove", "indices": [0] },
+            { "op": "move", "from": 3, "count": 2, "to": 0 },
+        ]))
+        .unwrap();
+        assert!(matches!(
+            plan(&QueueState::default(), &entries(&playlist), &out_of_range),
+            Err(ApiError::InvalidIndex(_))
+        ));
+        let no_count: Vec<PlaylistOp> =
+            serde_json::from_value(json!([{ "op": "move", "from": 0, "count": 0, "to": 2 }]))
+                .unwrap();
+        assert!(matches!(
+            plan(&QueueState::default(), &entries(&playlist), &no_count),
+            Err(ApiError::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,82 @@
+use axum::{Router, extract::FromRef};
+use serde_json::json;
+
+use crate::{
+    player::PlayerHandle,
+    playlists::{LoadMode, PlaylistStore},
+};
+
+use super::{
+    rest_endpoints::rest_endpoints,
+    rest_wrapper_v2::{
+        EmptySuccessResponse, ErrorResponses, RestResponse, SuccessResponse, query_rejection,
+    },
+};
+
+#[derive(Debug, Clone, FromRef)]
+struct PlaylistsState {
+    player: PlayerHandle,
+    store: PlaylistStore,
+}
+
+/// Routes for saving and restoring named playlists, under `/api/playlists`.
+pub fn playlist_routes(player: PlayerHandle, store: PlaylistStore) -> Router {
+    let (router, _) = api_router()
+        .with_state(PlaylistsState { player, store })
+        .split_for_parts();
+
+    router
+}
+
+pub(super) fn playlist_openapi() -> utoipa::openapi::OpenApi {
+    let (_, api): (Router<PlaylistsState>, _) = api_router().split_for_parts();
+    api
+}
+
+rest_endpoints! {
+    router = api_router, state = PlaylistsState;
+
+    /// List all saved playlists
+    get "/api/playlists" -> SuccessResponse;
+    async fn list_playlists(store: PlaylistStore) {
+        store.list().await.map(|playlists| json!(playlists))
+    }
+
+    /// Get a single saved playlist
+    get "/api/playlists/{name}" -> SuccessResponse, path = (name: String);
+    async fn get_playlist(store: PlaylistStore) {
+        store.get(&name).await.map(|playlist| json!(playlist))
+    }
+
+    /// Save the current playlist under a name, replacing any saved playlist with the same name
+    post "/api/playlists/{name}" -> SuccessResponse, path = (name: String);
+    async fn save_playlist(state: PlaylistsState) {
+        state
+            .store
+            .save(&name, &state.player)
+            .await
+            .map(|playlist| json!(playlist))
+    }
+
+    /// Delete a saved playlist
+    delete "/api/playlists/{name}" -> EmptySuccessResponse, path = (name: String);
+    async fn delete_playlist(store: PlaylistStore) {
+        store.delete(&name).await
+    }
+
+    /// Add a saved playlist to the player, either after the current playlist or replacing it
+    post "/api/playlists/{name}/load" -> EmptySuccessResponse,
+        user = user, charge = charge, path = (name: String);
+    async fn load_playlist(state: PlaylistsState, mode: Option<LoadMode>) {
+        state
+            .store
+            .load(
+                &name,
+                mode.unwrap_or_default(),
+                &state.player,
+                user.as_deref(),
+                charge.as_deref(),
+            )
+            .await
+    }
+}
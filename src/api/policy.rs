@@ -0,0 +1,73 @@
+use axum::{
+    Extension,
+    extract::{ConnectInfo, Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    oidc::AuthenticatedUser,
+    player::Priority,
+    policy::{Command, Policies, PolicyEngine},
+    quotas::coarse_identity,
+    scopes::Scope,
+    server::ClientAddr,
+};
+
+use super::{
+    admin::{AdminAuth, is_admin},
+    control::control_token,
+    load_check::LOAD_CHECK_PATH,
+};
+
+/// Marks the requests [`reject_by_policy`] found to be from an admin.
+#[derive(Debug, Clone, Copy)]
+pub struct AdminRequest;
+
+/// Whether [`reject_by_policy`] found the request to be from an admin, for handlers that
+/// take it as an extractor.
+pub type FromAdmin = Option<Extension<AdminRequest>>;
+
+/// Ask the policies about every request that could change something, and reject the ones
+/// they refuse. Requests that add items get a
+/// [`QuotaCharge`](crate::quotas::QuotaCharge) for counting them, and the ones from admins
+/// are marked with [`AdminRequest`].
+pub async fn reject_by_policy(
+    State((policies, admin)): State<(Policies, AdminAuth)>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    // The load check asks the policies itself, to list what they refuse.
+    let changes_nothing = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) || request.uri().path() == LOAD_CHECK_PATH;
+    if !changes_nothing {
+        let user = request.extensions().get::<AuthenticatedUser>();
+        let identity = request
+            .extensions()
+            .get::<ConnectInfo<ClientAddr>>()
+            .map(|ConnectInfo(addr)| coarse_identity(addr, request.headers(), user));
+        let command = Command {
+            path: Some(request.uri().path()),
+            is_admin: is_admin(request.headers(), user, &admin),
+            control_token: control_token(request.headers()),
+            scopes: user.map(|user| user.scopes.as_slice()),
+            needs: Scope::needed_for(request.method(), request.uri().path()),
+            identity: identity.as_deref(),
+            priority: Priority::from_query(request.uri().query()),
+        };
+        if let Err(e) = policies.check_command(&command) {
+            return e.into_response();
+        }
+        let is_admin = command.is_admin;
+        if let Some(charge) = policies.quota_charge(&command) {
+            request.extensions_mut().insert(charge);
+        }
+        if is_admin {
+            request.extensions_mut().insert(AdminRequest);
+        }
+    }
+    next.run(request).await
+}
@@ -0,0 +1,123 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use anyhow::Context;
+use axum::Router;
+use mpvipc_async::Mpv;
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use super::{
+    error::ApiError,
+    rest_endpoints::rest_endpoints,
+    rest_wrapper_v2::{
+        EmptySuccessResponse, ErrorResponses, RestResponse, SuccessResponse, json_rejection,
+        query_rejection,
+    },
+};
+
+/// The mpv properties exposed through `/api/property` unless `--mpv-property` is given.
+pub const DEFAULT_PROPERTIES: [&str; 8] = [
+    "speed",
+    "sub-delay",
+    "audio-delay",
+    "brightness",
+    "contrast",
+    "saturation",
+    "gamma",
+    "sub-visibility",
+];
+
+#[derive(Debug, Clone)]
+struct PropertyState {
+    mpv: Mpv,
+    allowed: Arc<BTreeSet<String>>,
+}
+
+impl PropertyState {
+    fn check_allowed(&self, name: &str) -> Result<(), ApiError> {
+        if self.allowed.contains(name) {
+            Ok(())
+        } else {
+            Err(ApiError::PolicyViolation(format!(
+                "The property '{}' is not available through the API",
+                name
+            )))
+        }
+    }
+}
+
+/// Routes for reading and writing the allowed mpv properties, under `/api/property`.
+pub fn property_routes(mpv: Mpv, allowed: Vec<String>) -> Router {
+    let state = PropertyState {
+        mpv,
+        allowed: Arc::new(allowed.into_iter().collect()),
+    };
+    let (router, _) = api_router().with_state(state).split_for_parts();
+
+    router
+}
+
+pub(super) fn property_openapi() -> utoipa::openapi::OpenApi {
+    let (_, api): (Router<PropertyState>, _) = api_router().split_for_parts();
+    api
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct SetPropertyBody {
+    value: Value,
+}
+
+rest_endpoints! {
+    router = api_router, state = PropertyState;
+
+    /// List the properties that can be read and written
+    get "/api/property" -> SuccessResponse;
+    async fn list_properties(state: PropertyState) {
+        Ok(json!(*state.allowed))
+    }
+
+    /// Get the value of an mpv property
+    get "/api/property/{name}" -> SuccessResponse, path = (name: String);
+    async fn get_property(state: PropertyState) {
+        get(&state, &name).await
+    }
+
+    /// Set an mpv property, given as `{ "value": ... }`
+    post "/api/property/{name}" -> EmptySuccessResponse,
+        path = (name: String), body = body: SetPropertyBody;
+    async fn set_property(state: PropertyState) {
+        set(&state, &name, body.value).await
+    }
+}
+
+async fn get(state: &PropertyState, name: &str) -> anyhow::Result<Value> {
+    state.check_allowed(name)?;
+    let value = state
+        .mpv
+        .get_property_value(name)
+        .await
+        .with_context(|| format!("Failed to get the property '{}'", name))?;
+    Ok(value.unwrap_or(Value::Null))
+}
+
+async fn set(state: &PropertyState, name: &str, value: Value) -> anyhow::Result<()> {
+    state.check_allowed(name)?;
+    let result = match value {
+        Value::Bool(value) => state.mpv.set_property(name, value).await,
+        Value::Number(ref number) => match number.as_f64() {
+            Some(value) => state.mpv.set_property(name, value).await,
+            None => {
+                return Err(ApiError::InvalidArgument(format!("Invalid number {}", number)).into());
+            }
+        },
+        Value::String(value) => state.mpv.set_property(name, value).await,
+        _ => {
+            return Err(ApiError::InvalidArgument(
+                "The value must be a boolean, a number or a string".to_string(),
+            )
+            .into());
+        }
+    };
+
+    result.with_context(|| format!("Failed to set the property '{}'", name))
+}
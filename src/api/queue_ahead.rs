@@ -0,0 +1,201 @@
+//! Items queued while the player is unreachable, like when mpv is restarting. They are
+//! kept here and added to the playlist once it is back, so that nothing queued in the
+//! meantime is lost.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    mpv_setup::Quality,
+    player::{self, ItemState, PlayerHandle},
+    quotas::QuotaCharge,
+    resolver::ResolverChain,
+};
+
+use super::{ApiError, base, deferred::DeferredLoads};
+
+/// How often the player is checked for being back while items are waiting.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The most items that can wait for the player. More are refused, as if nothing waited.
+const MAX_QUEUED: usize = 500;
+
+/// The arguments of a [`base::loadfile`] that could not reach the player.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueuedLoad {
+    pub path: String,
+    pub quality: Quality,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub item: ItemState,
+    pub resume: bool,
+    /// Counts the items towards the quota of whoever added them, as they are added.
+    pub charge: Option<QuotaCharge>,
+    /// The items the path resolved to that are not in the playlist yet, once it has been
+    /// resolved. If the player goes away partway, only these are added once it is back.
+    pub urls: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default)]
+struct LoadQueue {
+    loads: Mutex<VecDeque<QueuedLoad>>,
+}
+
+impl LoadQueue {
+    fn len(&self) -> usize {
+        self.loads.lock().unwrap().len()
+    }
+
+    fn push(&self, load: QueuedLoad) -> anyhow::Result<()> {
+        let mut loads = self.loads.lock().unwrap();
+        if loads.len() >= MAX_QUEUED {
+            return Err(ApiError::MpvUnavailable(format!(
+                "The player is unavailable, and {} items are already waiting for it",
+                MAX_QUEUED
+            ))
+            .into());
+        }
+        log::warn!(
+            "The player is unavailable, keeping {} until it is back",
+            load.path
+        );
+        loads.push_back(load);
+        Ok(())
+    }
+
+    /// Add the waiting items to the playlist, in the order they were queued. Returns
+    /// whether every one of them was added, or given up on for some other reason than the
+    /// player being unreachable.
+    async fn flush(&self, player: &PlayerHandle, resolvers: &ResolverChain) -> bool {
+        loop {
+            let Some(mut load) = self.loads.lock().unwrap().pop_front() else {
+                return true;
+            };
+            match base::load_now(player, resolvers, &mut load).await {
+                Ok(()) => log::info!("The player is back, added {}", load.path),
+                Err(e) if player::is_unreachable(&e) => {
+                    self.loads.lock().unwrap().push_front(load);
+                    return false;
+                }
+                Err(e) => log::warn!("Failed to add {} after waiting: {:#}", load.path, e),
+            }
+        }
+    }
+}
+
+/// The items that are not in the playlist yet, shared by everything that adds items to it.
+///
+/// These are the items waiting for the player, and the ones waiting for their time, see
+/// [`deferred`](super::deferred).
+#[derive(Debug, Clone, Default)]
+pub struct PendingLoads {
+    queued: Arc<LoadQueue>,
+    pub(super) deferred: Arc<DeferredLoads>,
+}
+
+impl PendingLoads {
+    /// How many items are waiting for the player.
+    pub fn queued_count(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Keep `load` until the player is back.
+    pub fn queue(&self, load: QueuedLoad) -> anyhow::Result<()> {
+        self.queued.push(load)
+    }
+}
+
+/// Keep trying to add the items that are waiting for the player.
+pub async fn flush_queued_loads(
+    player: PlayerHandle,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+) {
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if pending.queued_count() > 0 && !pending.queued.flush(&player, &resolvers).await {
+            log::debug!(
+                "The player is still unavailable, {} items waiting",
+                pending.queued_count()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        player::{CircuitBreaker, GuardedPlayer, MpvPlayer},
+        quotas::Quotas,
+        test_support::FakeMpv,
+    };
+
+    #[tokio::test]
+    async fn test_flush() {
+        let mpv = FakeMpv::start();
+        let player: PlayerHandle = Arc::new(MpvPlayer::new(mpv.connect().await));
+        let resolvers = ResolverChain::new(vec![]);
+
+        // A breaker that has opened stands in for a player that can not be reached.
+        let breaker = CircuitBreaker::new(Duration::from_millis(1));
+        for _ in 0..3 {
+            let hang = std::future::pending::<anyhow::Result<()>>();
+            assert!(breaker.call(hang).await.is_err());
+        }
+        let unreachable: PlayerHandle = Arc::new(GuardedPlayer::new(player.clone(), breaker));
+
+        let quotas = Quotas::new(None, Duration::from_secs(600));
+        let queue = LoadQueue::default();
+        for (path, urls) in [
+            ("queued-a.mp3", None),
+            ("queued-b.mp3", None),
+            // Half of an album was added before the player went away.
+            ("album", Some(vec!["album-2.mp3".to_string()])),
+        ] {
+            queue
+                .push(QueuedLoad {
+                    path: path.to_string(),
+                    quality: Quality::default(),
+                    start: None,
+                    end: None,
+                    item: ItemState::default(),
+                    resume: false,
+                    charge: Some(quotas.charge_to("anon:a")),
+                    urls,
+                })
+                .unwrap();
+        }
+
+        // Items only count towards the quota once they are added.
+        assert!(!queue.flush(&unreachable, &resolvers).await);
+        assert_eq!(queue.len(), 3);
+        assert!(quotas.list().is_empty());
+        assert!(queue.flush(&player, &resolvers).await);
+        assert_eq!(queue.len(), 0);
+        assert_eq!(quotas.list()[0].added, 3);
+        let loaded: Vec<_> = mpv
+            .commands()
+            .into_iter()
+            .filter(|command| command[0] == json!("loadfile"))
+            .map(|command| command[1].clone())
+            .collect();
+        assert_eq!(
+            loaded,
+            vec![
+                json!("queued-a.mp3"),
+                json!("queued-b.mp3"),
+                json!("album-2.mp3")
+            ]
+        );
+    }
+}
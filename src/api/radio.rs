@@ -0,0 +1,72 @@
+use axum::Router;
+use serde_json::json;
+
+use crate::{player::PlayerHandle, radio::Radio};
+
+use super::{
+    rest_endpoints::rest_endpoints,
+    rest_wrapper_v2::{
+        EmptySuccessResponse, ErrorResponses, RestResponse, SuccessResponse, query_rejection,
+    },
+};
+
+#[derive(Debug, Clone)]
+struct RadioState {
+    player: PlayerHandle,
+    radio: Radio,
+}
+
+/// The `/api/radio` endpoints, for listing and tuning into the configured stations, and
+/// for what the live stream that is playing says it is playing.
+pub fn radio_routes(player: PlayerHandle, radio: Radio) -> Router {
+    let (router, _) = api_router()
+        .with_state(RadioState { player, radio })
+        .split_for_parts();
+
+    router
+}
+
+pub(super) fn radio_openapi() -> utoipa::openapi::OpenApi {
+    let (_, api): (Router<RadioState>, _) = api_router().split_for_parts();
+    api
+}
+
+rest_endpoints! {
+    router = api_router, state = RadioState;
+
+    /// List the stations, and the stream that is playing with its current title
+    get "/api/radio" -> SuccessResponse;
+    async fn get_radio(state: RadioState) {
+        Ok(json!({
+            "stations": state
+                .radio
+                .stations()
+                .into_iter()
+                .map(|station| json!({ "name": station.name, "url": station.url.as_str() }))
+                .collect::<Vec<_>>(),
+            "now_playing": state.radio.now_playing(),
+        }))
+    }
+
+    /// Get the live stream that is playing and its metadata, or null if none is
+    get "/api/radio/now-playing" -> SuccessResponse;
+    async fn get_now_playing(state: RadioState) {
+        Ok(json!(state.radio.now_playing()))
+    }
+
+    /// Switch to a station right away
+    post "/api/radio/{name}/play" -> EmptySuccessResponse,
+        user = user, charge = charge, path = (name: String);
+    async fn play_station(state: RadioState) {
+        let result = state
+            .radio
+            .play(&state.player, &name, user.as_deref())
+            .await;
+        if result.is_ok()
+            && let Some(charge) = charge
+        {
+            charge.charge(1);
+        }
+        result
+    }
+}
@@ -0,0 +1,196 @@
+use std::{collections::BTreeMap, str::FromStr, sync::Arc, time::Duration};
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::Deserialize;
+use serde_json::json;
+use url::Url;
+
+use crate::oidc::LoggedIn;
+
+use super::error::ApiError;
+
+/// How long to wait for a remote instance to respond.
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Another greg-ng instance that queue operations can be forwarded to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Remote {
+    pub name: String,
+    /// The base URL of the instance, e.g. `http://terminalen:8008`.
+    pub url: Url,
+}
+
+impl FromStr for Remote {
+    type Err = String;
+
+    /// Parses `<name>=<url>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, url) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <name>=<url>, got '{}'", s))?;
+
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "invalid remote name '{}', only letters, digits, '-' and '_' are allowed",
+                name
+            ));
+        }
+
+        let url = Url::parse(url).map_err(|e| format!("invalid URL '{}': {}", url, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("remote URL '{}' must be http or https", url));
+        }
+
+        Ok(Remote {
+            name: name.to_string(),
+            url,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RemoteState {
+    client: reqwest::Client,
+    remotes: Arc<BTreeMap<String, Url>>,
+}
+
+/// Routes for forwarding queue operations to other instances, under `/api/remote`.
+///
+/// These let a web UI served by one instance control the others without
+/// having to deal with CORS.
+pub fn remote_routes(remotes: Vec<Remote>) -> Router {
+    let state = RemoteState {
+        client: reqwest::Client::builder()
+            .timeout(REMOTE_TIMEOUT)
+            .build()
+            .expect("Failed to build HTTP client"),
+        remotes: Arc::new(
+            remotes
+                .into_iter()
+                .map(|remote| (remote.name, remote.url))
+                .collect(),
+        ),
+    };
+
+    Router::new()
+        .route("/api/remote", get(remote_list))
+        .route("/api/remote/{name}/load", post(remote_load))
+        .with_state(state)
+}
+
+async fn remote_list(State(state): State<RemoteState>) -> Response {
+    let remotes: Vec<_> = state
+        .remotes
+        .iter()
+        .map(|(name, url)| json!({ "name": name, "url": url.as_str() }))
+        .collect();
+
+    Json(json!({ "success": true, "value": remotes })).into_response()
+}
+
+#[derive(Deserialize)]
+struct LoadArgs {
+    path: String,
+}
+
+/// Add an item to the playlist of a remote instance, relaying its response.
+async fn remote_load(
+    State(state): State<RemoteState>,
+    user: LoggedIn,
+    Path(name): Path<String>,
+    query: Result<Query<LoadArgs>, axum::extract::rejection::QueryRejection>,
+) -> Response {
+    let LoadArgs { path } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+
+    let Some(base_url) = state.remotes.get(&name) else {
+        return ApiError::NotFound(format!("No remote named '{}'", name)).into_response();
+    };
+
+    let mut url = match base_url.join("api/v2/load") {
+        Ok(url) => url,
+        Err(e) => return ApiError::Internal(e.to_string()).into_response(),
+    };
+    url.query_pairs_mut().append_pair("path", &path);
+    // The remote can not know who logged in here, so it is only told their name.
+    if let Some(user) = &user {
+        url.query_pairs_mut().append_pair("queued_by", &user.name);
+    }
+
+    log::info!("Forwarding '{}' to remote '{}'", path, name);
+
+    match forward(&state.client, url).await {
+        Ok(response) => response,
+        Err(e) => {
+            log::warn!("Failed to reach remote '{}': {}", name, e);
+            ApiError::RemoteUnavailable(format!("Failed to reach remote '{}': {}", name, e))
+                .into_response()
+        }
+    }
+}
+
+async fn forward(client: &reqwest::Client, url: Url) -> reqwest::Result<Response> {
+    let response = client.post(url).send().await?;
+
+    let status =
+        StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/json")
+        .to_string();
+    let body = response.bytes().await?;
+
+    Ok((status, [(header::CONTENT_TYPE, content_type)], body).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_parse_remote() {
+        assert_eq!(
+            "terminalen=http://terminalen:8008".parse(),
+            Ok(Remote {
+                name: "terminalen".to_string(),
+                url: Url::parse("http://terminalen:8008").unwrap(),
+            })
+        );
+
+        assert!("terminalen".parse::<Remote>().is_err());
+        assert!("=http://terminalen:8008".parse::<Remote>().is_err());
+        assert!("a/b=http://terminalen:8008".parse::<Remote>().is_err());
+        assert!("terminalen=ws://terminalen:8008".parse::<Remote>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_remote() {
+        let router = remote_routes(vec!["terminalen=http://terminalen:8008".parse().unwrap()]);
+
+        let response = router
+            .oneshot(
+                Request::post("/api/remote/kontoret/load?path=foo")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
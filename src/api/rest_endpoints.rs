@@ -0,0 +1,130 @@
+/// Define a set of REST endpoints, generating the axum handler, the query parameter
+/// struct and the utoipa path item for each of them from a single definition.
+///
+/// Every endpoint takes one piece of state as its first argument, and any number of
+/// query parameters after that. The body should evaluate to something that can be
+/// converted into a `RestResponse`. Endpoints that end their first line with
+/// `, user = <name>` also get whoever is logged in, as a [`crate::oidc::LoggedIn`]. The ones
+/// that go on with `, charge = <name>` get what counts the items they add towards a quota,
+/// as a [`crate::quotas::Charged`], and `, admin = <name>` whether the request is from an
+/// admin, as a [`crate::api::FromAdmin`]. After those, `, path = (<name>: <type>, ...)` gives
+/// the parameters in the path, `, headers = <name>` the headers of the request, and
+/// `, body = <name>: <type>` its JSON body.
+///
+/// ```ignore
+/// rest_endpoints! {
+///     router = api_router, state = RestApiState, openapi = ApiDoc;
+///
+///     /// Set the player volume
+///     post "/volume" -> EmptySuccessResponse;
+///     async fn volume_set(volume_engine: VolumeTransitionEngine, volume: f64) {
+///         base::volume_set(volume_engine, volume).await
+///     }
+/// }
+/// ```
+///
+/// Each endpoint is expanded into its own module, named after the endpoint, containing
+/// an `Args` struct, a `PathArgs` struct and a `handler` function. `RestResponse`,
+/// `ErrorResponses` (which should implement `utoipa::IntoResponses`) and `query_rejection`
+/// (which turns a `QueryRejection` into a response), along with `json_rejection` for
+/// endpoints with a body, are resolved from the invoking module. Finally, a function named
+/// after `router` is generated, which returns an `OpenApiRouter` with all the endpoints
+/// registered, starting from the document of `openapi` if one is given.
+macro_rules! rest_endpoints {
+    (
+        router = $router:ident, state = $state:ty $(, openapi = $openapi:ty)?;
+
+        $(
+            $(#[doc = $doc:expr])*
+            $method:ident $path:literal -> $response:ty
+                $(, user = $user:ident)? $(, charge = $charge:ident)? $(, admin = $admin:ident)?
+                $(, path = ($($path_arg:ident: $path_ty:ty),* $(,)?))?
+                $(, headers = $headers:ident)? $(, body = $body_arg:ident: $body_ty:ty)?;
+            async fn $name:ident($state_arg:ident: $state_ty:ty $(, $arg:ident: $arg_ty:ty)* $(,)?)
+            $body:block
+        )*
+    ) => {
+        $(
+            mod $name {
+                use super::*;
+
+                #[derive(serde::Deserialize, utoipa::IntoParams)]
+                pub struct Args {
+                    $(pub $arg: $arg_ty,)*
+                }
+
+                #[derive(serde::Deserialize, utoipa::IntoParams)]
+                #[into_params(parameter_in = Path)]
+                pub struct PathArgs {
+                    $($(pub $path_arg: $path_ty,)*)?
+                }
+
+                $(#[doc = $doc])*
+                #[utoipa::path(
+                    $method,
+                    path = $path,
+                    operation_id = stringify!($name),
+                    params(PathArgs, Args),
+                    $(request_body = $body_ty,)?
+                    responses(
+                        (status = 200, description = "Success", body = $response),
+                        ErrorResponses,
+                    )
+                )]
+                pub async fn handler(
+                    axum::extract::State($state_arg): axum::extract::State<$state_ty>,
+                    $($user: crate::oidc::LoggedIn,)?
+                    $($charge: crate::quotas::Charged,)?
+                    $($admin: crate::api::FromAdmin,)?
+                    $(
+                        axum::extract::Path(PathArgs { $($path_arg),* }):
+                            axum::extract::Path<PathArgs>,
+                    )?
+                    $($headers: axum::http::HeaderMap,)?
+                    query: Result<
+                        axum::extract::Query<Args>,
+                        axum::extract::rejection::QueryRejection,
+                    >,
+                    $(
+                        $body_arg: Result<
+                            axum::Json<$body_ty>,
+                            axum::extract::rejection::JsonRejection,
+                        >,
+                    )?
+                ) -> axum::response::Response {
+                    use axum::response::IntoResponse;
+
+                    let Args { $($arg),* } = match query {
+                        Ok(axum::extract::Query(args)) => args,
+                        Err(rejection) => return query_rejection(rejection),
+                    };
+                    $(
+                        let $body_arg = match $body_arg {
+                            Ok(axum::Json(body)) => body,
+                            Err(rejection) => return json_rejection(rejection),
+                        };
+                    )?
+
+                    RestResponse::from($body).into_response()
+                }
+            }
+        )*
+
+        fn $router() -> utoipa_axum::router::OpenApiRouter<$state> {
+            utoipa_axum::router::OpenApiRouter::with_openapi(
+                rest_endpoints!(@openapi $($openapi)?),
+            )
+            $(.routes(utoipa_axum::routes!($name::handler)))*
+        }
+    };
+
+    (@openapi $openapi:ty) => {
+        <$openapi as utoipa::OpenApi>::openapi()
+    };
+
+    (@openapi) => {
+        utoipa::openapi::OpenApi::default()
+    };
+}
+
+pub(super) use rest_endpoints;
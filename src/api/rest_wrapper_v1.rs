@@ -1,68 +1,87 @@
 use axum::{
     Json, Router,
-    extract::{Query, State},
+    extract::{FromRef, rejection::QueryRejection},
     http::StatusCode,
     response::{IntoResponse, Response},
-    routing::{delete, get, post},
 };
-use mpvipc_async::Mpv;
 use serde_json::{Value, json};
 
 use utoipa::OpenApi;
-use utoipa_axum::{router::OpenApiRouter, routes};
-use utoipa_swagger_ui::SwaggerUi;
-
-use super::base;
-
-pub fn rest_api_routes(mpv: Mpv) -> Router {
-    Router::new()
-        .route("/load", post(loadfile))
-        .route("/play", get(play_get))
-        .route("/play", post(play_set))
-        .route("/volume", get(volume_get))
-        .route("/volume", post(volume_set))
-        .route("/time", get(time_get))
-        .route("/time", post(time_set))
-        .route("/playlist", get(playlist_get))
-        .route("/playlist/next", post(playlist_next))
-        .route("/playlist/previous", post(playlist_previous))
-        .route("/playlist/goto", post(playlist_goto))
-        .route("/playlist", delete(playlist_remove_or_clear))
-        .route("/playlist/move", post(playlist_move))
-        .route("/playlist/shuffle", post(shuffle))
-        .route("/playlist/loop", get(playlist_get_looping))
-        .route("/playlist/loop", post(playlist_set_looping))
-        .with_state(mpv)
-}
 
-pub fn rest_api_docs(mpv: Mpv) -> Router {
-    let (router, api) = OpenApiRouter::with_openapi(ApiDoc::openapi())
-        .routes(routes!(loadfile))
-        .routes(routes!(play_get, play_set))
-        .routes(routes!(volume_get, volume_set))
-        .routes(routes!(time_get, time_set))
-        .routes(routes!(playlist_get, playlist_remove_or_clear))
-        .routes(routes!(playlist_next))
-        .routes(routes!(playlist_previous))
-        .routes(routes!(playlist_goto))
-        .routes(routes!(playlist_move))
-        .routes(routes!(playlist_get_looping, playlist_set_looping))
-        .routes(routes!(shuffle))
-        .with_state(mpv)
-        .split_for_parts();
-
-    router.merge(SwaggerUi::new("/docs").url("/docs/openapi.json", api))
-}
+use crate::{
+    autoplay::Autoplay,
+    mpv_setup::Quality,
+    player::{ItemNote, ItemState, Lane, PlayerHandle, PlaylistClearGuard, Priority, RetryQueue},
+    resolver::ResolverChain,
+    util::Page,
+    volume_transition::VolumeTransitionEngine,
+};
 
-// NOTE: the openapi stuff is very heavily duplicated and introduces
-//       a lot of maintenance overhead and boilerplate. It should theoretically
-//       be possible to infer a lot of this from axum, but I haven't found a
-//       good library that does this and works properly yet (I have tried some
-//       but they all had issues). Feel free to replace this with a better solution.
+use super::{base, error::ApiError, queue_ahead::PendingLoads, rest_endpoints::rest_endpoints};
+
+#[derive(Debug, Clone, FromRef)]
+struct RestApiState {
+    player: PlayerHandle,
+    volume_engine: VolumeTransitionEngine,
+    clear_guard: PlaylistClearGuard,
+    retries: RetryQueue,
+    autoplay: Autoplay,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+}
+
+pub fn rest_api_routes(
+    player: PlayerHandle,
+    volume_engine: VolumeTransitionEngine,
+    clear_guard: PlaylistClearGuard,
+    retries: RetryQueue,
+    autoplay: Autoplay,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+) -> Router {
+    let state = RestApiState {
+        player,
+        volume_engine,
+        clear_guard,
+        retries,
+        autoplay,
+        resolvers,
+        pending,
+    };
+
+    let (router, _) = api_router().with_state(state).split_for_parts();
+
+    router
+}
+
+/// The v1 routes, together with their OpenAPI document.
+///
+/// The routes are mounted at the root next to the docs, so that "try it out" works.
+pub fn rest_api_docs_parts(
+    player: PlayerHandle,
+    volume_engine: VolumeTransitionEngine,
+    clear_guard: PlaylistClearGuard,
+    retries: RetryQueue,
+    autoplay: Autoplay,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+) -> (Router, utoipa::openapi::OpenApi) {
+    let state = RestApiState {
+        player,
+        volume_engine,
+        clear_guard,
+        retries,
+        autoplay,
+        resolvers,
+        pending,
+    };
+
+    api_router().with_state(state).split_for_parts()
+}
 
 #[derive(OpenApi)]
 #[openapi(info(
-    description = "The legacy Grzegorz Brzeczyszczykiewicz API, used to control a running mpv instance",
+    description = "The legacy Grzegorz Brzeczyszczykiewicz API, used to control a running media player",
     version = "1.0.0",
 ))]
 struct ApiDoc;
@@ -93,6 +112,14 @@ struct ErrorResponse {
     success: bool,
 }
 
+/// The error responses of every endpoint, only used for documentation.
+#[derive(utoipa::IntoResponses)]
+#[allow(dead_code)]
+enum ErrorResponses {
+    #[response(status = 500, description = "Internal server error")]
+    InternalServerError(ErrorResponse),
+}
+
 pub struct RestResponse(anyhow::Result<Value>);
 
 impl From<anyhow::Result<Value>> for RestResponse {
@@ -111,293 +138,341 @@ impl IntoResponse for RestResponse {
     fn into_response(self) -> Response {
         match self.0 {
             Ok(value) => (StatusCode::OK, Json(value)).into_response(),
-            Err(err) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({ "error": err.to_string(), "errortext": err.to_string(), "success": false })),
-            )
-                .into_response(),
+            Err(err) => match err.downcast_ref::<ApiError>() {
+                // The token has to reach the client, so this one is not a plain 500.
+                Some(ApiError::ConfirmationRequired { message, token }) => (
+                    StatusCode::CONFLICT,
+                    Json(json!({ "error": message, "errortext": message, "success": false, "confirmation_token": token })),
+                )
+                    .into_response(),
+                _ => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({ "error": err.to_string(), "errortext": err.to_string(), "success": false })),
+                )
+                    .into_response(),
+            },
         }
     }
 }
 
-// -------------------//
-// Boilerplate galore //
-// -------------------//
+fn query_rejection(rejection: QueryRejection) -> Response {
+    rejection.into_response()
+}
+
+// --------- //
+// Endpoints //
+// --------- //
+
+rest_endpoints! {
+    router = api_router, state = RestApiState, openapi = ApiDoc;
+
+    /// Add item to playlist
+    ///
+    /// `maxheight` and `audio_only` choose the streams yt-dlp picks for this item,
+    /// overriding the quality set through `/playback/quality`. `start` and `end` are
+    /// timestamps like `1:12` or `72.5`, to only play part of the item.
+    ///
+    /// Spotify tracks, albums and playlists are added as YouTube searches for their tracks.
+    ///
+    /// `title` is shown instead of the title of the item, and `note` is shown along with it,
+    /// in the playlist and wherever the item is shown as playing. `queued_by` is the name of
+    /// whoever added the item, which it is credited to in the stats. Items added by someone
+    /// who logged in or gave an API key are credited to them instead.
+    ///
+    /// With `resume`, a long item that was stopped partway through in the last few days
+    /// continues from where it was stopped.
+    ///
+    /// With `ttl_hours`, the item is removed if it is still waiting in the playlist after that
+    /// many hours, instead of after the TTL set for everything.
+    ///
+    /// With `not_before`, in seconds since the unix epoch, the item is kept out of the
+    /// playlist until then, and added to the end of it once the time has come.
+    ///
+    /// With a `priority` of `high` or `admin`, the item goes ahead of the items of a lower
+    /// priority that have not started playing. `high` needs the `queue:priority` scope, and
+    /// `admin` needs an admin.
+    post "/load" -> EmptySuccessResponse, user = user, charge = charge, admin = admin;
+    async fn loadfile(
+        state: RestApiState,
+        path: String,
+        maxheight: Option<u32>,
+        audio_only: Option<bool>,
+        start: Option<String>,
+        end: Option<String>,
+        title: Option<String>,
+        note: Option<String>,
+        queued_by: Option<String>,
+        resume: Option<bool>,
+        ttl_hours: Option<u32>,
+        not_before: Option<u64>,
+        priority: Option<Priority>,
+    ) {
+        let quality = Quality {
+            max_height: maxheight,
+            audio_only: audio_only.unwrap_or(false),
+        };
+        base::loadfile(
+            state.player,
+            &state.resolvers,
+            &state.pending,
+            &path,
+            quality,
+            start.as_deref(),
+            end.as_deref(),
+            ItemState {
+                note: ItemNote::new(title.as_deref(), note.as_deref(), queued_by.as_deref()),
+                lane: Lane {
+                    priority: priority.unwrap_or_default(),
+                    ..Lane::default()
+                },
+                ttl_hours,
+                ..ItemState::default()
+            },
+            resume.unwrap_or(false),
+            not_before,
+            user.as_deref(),
+            admin.is_some(),
+            charge.as_deref(),
+        )
+        .await
+    }
 
-// TODO: These could possibly be generated with a proc macro
+    /// Check whether the player is paused or playing
+    get "/play" -> SuccessResponse;
+    async fn play_get(player: PlayerHandle) {
+        base::play_get(player).await
+    }
 
-#[derive(serde::Deserialize, utoipa::IntoParams)]
-struct LoadFileArgs {
-    path: String,
-}
+    /// Set whether the player is paused or playing
+    post "/play" -> EmptySuccessResponse;
+    async fn play_set(volume_engine: VolumeTransitionEngine, play: String) {
+        base::play_set(volume_engine, play.to_lowercase() == "true").await
+    }
 
-/// Add item to playlist
-#[utoipa::path(
-    post,
-    path = "/load",
-    params(LoadFileArgs),
-    responses(
-        (status = 200, description = "Success", body = EmptySuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn loadfile(State(mpv): State<Mpv>, Query(query): Query<LoadFileArgs>) -> RestResponse {
-    base::loadfile(mpv, &query.path).await.into()
-}
+    /// Pause, and resume at a precise time, given in milliseconds since the unix epoch
+    ///
+    /// Used to start playback at the same time on several hosts, which should have their
+    /// clocks synced, e.g. with NTP. Scheduling again replaces the earlier time.
+    post "/playback/play-at" -> EmptySuccessResponse;
+    async fn play_at(volume_engine: VolumeTransitionEngine, unix_ms: u64) {
+        base::play_at(volume_engine, unix_ms).await
+    }
 
-/// Check whether the player is paused or playing
-#[utoipa::path(
-    get,
-    path = "/play",
-    responses(
-        (status = 200, description = "Success", body = SuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn play_get(State(mpv): State<Mpv>) -> RestResponse {
-    base::play_get(mpv).await.into()
-}
+    /// Cancel the scheduled start of playback, leaving the player paused
+    delete "/playback/play-at" -> EmptySuccessResponse;
+    async fn play_at_cancel(player: PlayerHandle) {
+        base::play_at_cancel(player).await
+    }
 
-#[derive(serde::Deserialize, utoipa::IntoParams)]
-struct PlaySetArgs {
-    play: String,
-}
+    /// Get the current player volume
+    get "/volume" -> SuccessResponse;
+    async fn volume_get(player: PlayerHandle) {
+        base::volume_get(player).await
+    }
 
-/// Set whether the player is paused or playing
-#[utoipa::path(
-    post,
-    path = "/play",
-    params(PlaySetArgs),
-    responses(
-        (status = 200, description = "Success", body = EmptySuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn play_set(State(mpv): State<Mpv>, Query(query): Query<PlaySetArgs>) -> RestResponse {
-    let play = query.play.to_lowercase() == "true";
-    base::play_set(mpv, play).await.into()
-}
+    /// Set the player volume
+    post "/volume" -> EmptySuccessResponse;
+    async fn volume_set(volume_engine: VolumeTransitionEngine, volume: f64) {
+        base::volume_set(volume_engine, volume).await
+    }
 
-/// Get the current player volume
-#[utoipa::path(
-    get,
-    path = "/volume",
-    responses(
-        (status = 200, description = "Success", body = SuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn volume_get(State(mpv): State<Mpv>) -> RestResponse {
-    base::volume_get(mpv).await.into()
-}
+    /// Play a short clip right away, pausing the current item and resuming it afterwards
+    post "/interject" -> EmptySuccessResponse, user = user, charge = charge;
+    async fn interject(volume_engine: VolumeTransitionEngine, path: String) {
+        base::interject(volume_engine, &path, user.as_deref(), charge.as_deref()).await
+    }
 
-#[derive(serde::Deserialize, utoipa::IntoParams)]
-struct VolumeSetArgs {
-    volume: f64,
-}
+    /// Get the current audio output device, and all available devices
+    get "/audio/device" -> SuccessResponse;
+    async fn audio_device_get(player: PlayerHandle) {
+        base::audio_device_get(player).await
+    }
 
-/// Set the player volume
-#[utoipa::path(
-    post,
-    path = "/volume",
-    params(VolumeSetArgs),
-    responses(
-        (status = 200, description = "Success", body = EmptySuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn volume_set(State(mpv): State<Mpv>, Query(query): Query<VolumeSetArgs>) -> RestResponse {
-    base::volume_set(mpv, query.volume).await.into()
-}
+    /// Switch the audio output device, to one of the devices listed by the GET endpoint
+    post "/audio/device" -> EmptySuccessResponse;
+    async fn audio_device_set(player: PlayerHandle, name: String) {
+        base::audio_device_set(player, name).await
+    }
 
-/// Get current playback position
-#[utoipa::path(
-    get,
-    path = "/time",
-    responses(
-        (status = 200, description = "Success", body = SuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn time_get(State(mpv): State<Mpv>) -> RestResponse {
-    base::time_get(mpv).await.into()
-}
+    /// Check whether the next playlist item is opened ahead of time
+    get "/playback/prefetch" -> SuccessResponse;
+    async fn prefetch_get(player: PlayerHandle) {
+        base::prefetch_get(player).await
+    }
 
-#[derive(serde::Deserialize, utoipa::IntoParams)]
-struct TimeSetArgs {
-    pos: Option<f64>,
-    percent: Option<f64>,
-}
+    /// Set whether the next playlist item is opened ahead of time, to avoid a gap between items
+    post "/playback/prefetch" -> EmptySuccessResponse;
+    async fn prefetch_set(player: PlayerHandle, enabled: bool) {
+        base::prefetch_set(player, enabled).await
+    }
 
-/// Set playback position
-#[utoipa::path(
-    post,
-    path = "/time",
-    params(TimeSetArgs),
-    responses(
-        (status = 200, description = "Success", body = EmptySuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn time_set(State(mpv): State<Mpv>, Query(query): Query<TimeSetArgs>) -> RestResponse {
-    base::time_set(mpv, query.pos, query.percent).await.into()
-}
+    /// Get how much is buffered, and whether playback is stalled waiting for the cache
+    get "/playback/cache" -> SuccessResponse;
+    async fn cache_get(player: PlayerHandle) {
+        base::cache_get(player).await
+    }
 
-/// Get the current playlist
-#[utoipa::path(
-    get,
-    path = "/playlist",
-    responses(
-        (status = 200, description = "Success", body = SuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn playlist_get(State(mpv): State<Mpv>) -> RestResponse {
-    base::playlist_get(mpv).await.into()
-}
+    /// Get codecs, bitrates and dropped frames of the current item
+    get "/playback/stats" -> SuccessResponse;
+    async fn stats_get(player: PlayerHandle) {
+        base::stats_get(player).await
+    }
 
-/// Go to the next item in the playlist
-#[utoipa::path(
-    post,
-    path = "/playlist/next",
-    responses(
-        (status = 200, description = "Success", body = EmptySuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn playlist_next(State(mpv): State<Mpv>) -> RestResponse {
-    base::playlist_next(mpv).await.into()
-}
+    /// Get the requested hardware decoding mode, and the decoder actually in use
+    get "/playback/hwdec" -> SuccessResponse;
+    async fn hwdec_get(player: PlayerHandle) {
+        base::hwdec_get(player).await
+    }
 
-/// Go back to the previous item in the playlist
-#[utoipa::path(
-    post,
-    path = "/playlist/previous",
-    responses(
-        (status = 200, description = "Success", body = EmptySuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn playlist_previous(State(mpv): State<Mpv>) -> RestResponse {
-    base::playlist_previous(mpv).await.into()
-}
+    /// Switch the hardware decoding mode, like `auto`, `vaapi` or `none`
+    post "/playback/hwdec" -> EmptySuccessResponse;
+    async fn hwdec_set(player: PlayerHandle, mode: String) {
+        base::hwdec_set(player, mode).await
+    }
 
-#[derive(serde::Deserialize, utoipa::IntoParams)]
-struct PlaylistGotoArgs {
-    index: usize,
-}
+    /// Get the `ytdl-format` items are loaded with, or `null` for yt-dlp's default
+    get "/playback/quality" -> SuccessResponse;
+    async fn quality_get(player: PlayerHandle) {
+        base::quality_get(player).await
+    }
 
-/// Go to a specific item in the playlist
-#[utoipa::path(
-    post,
-    path = "/playlist/goto",
-    params(PlaylistGotoArgs),
-    responses(
-        (status = 200, description = "Success", body = EmptySuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn playlist_goto(
-    State(mpv): State<Mpv>,
-    Query(query): Query<PlaylistGotoArgs>,
-) -> RestResponse {
-    base::playlist_goto(mpv, query.index).await.into()
-}
+    /// Set the quality yt-dlp picks for items loaded from now on
+    ///
+    /// `maxheight` skips taller video streams, and `audio_only` skips video entirely.
+    /// Without either, yt-dlp picks the best streams.
+    post "/playback/quality" -> EmptySuccessResponse;
+    async fn quality_set(player: PlayerHandle, maxheight: Option<u32>, audio_only: Option<bool>) {
+        let quality = Quality {
+            max_height: maxheight,
+            audio_only: audio_only.unwrap_or(false),
+        };
+        base::quality_set(player, quality).await
+    }
 
-#[derive(serde::Deserialize, utoipa::IntoParams)]
-struct PlaylistRemoveOrClearArgs {
-    index: Option<usize>,
-}
+    /// Get the audio delay, in milliseconds
+    get "/audio/delay" -> SuccessResponse;
+    async fn audio_delay_get(player: PlayerHandle) {
+        base::delay_get(&player, base::Delay::Audio).await
+    }
 
-/// Clears a single item or the entire playlist
-#[utoipa::path(
-    delete,
-    path = "/playlist",
-    params(PlaylistRemoveOrClearArgs),
-    responses(
-        (status = 200, description = "Success", body = EmptySuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn playlist_remove_or_clear(
-    State(mpv): State<Mpv>,
-    Query(query): Query<PlaylistRemoveOrClearArgs>,
-) -> RestResponse {
-    match query.index {
-        Some(index) => base::playlist_remove(mpv, index).await.into(),
-        None => base::playlist_clear(mpv).await.into(),
+    /// Set the audio delay in milliseconds, or adjust it with `relative=true`
+    post "/audio/delay" -> EmptySuccessResponse;
+    async fn audio_delay_set(player: PlayerHandle, ms: f64, relative: Option<bool>) {
+        base::delay_set(&player, base::Delay::Audio, ms, relative.unwrap_or(false)).await
     }
-}
 
-#[derive(serde::Deserialize, utoipa::IntoParams)]
-struct PlaylistMoveArgs {
-    index1: usize,
-    index2: usize,
-}
+    /// Get the subtitle delay, in milliseconds
+    get "/subtitles/delay" -> SuccessResponse;
+    async fn subtitle_delay_get(player: PlayerHandle) {
+        base::delay_get(&player, base::Delay::Subtitles).await
+    }
 
-/// Move a playlist item to a different position
-#[utoipa::path(
-    post,
-    path = "/playlist/move",
-    params(PlaylistMoveArgs),
-    responses(
-        (status = 200, description = "Success", body = EmptySuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn playlist_move(
-    State(mpv): State<Mpv>,
-    Query(query): Query<PlaylistMoveArgs>,
-) -> RestResponse {
-    base::playlist_move(mpv, query.index1, query.index2)
+    /// Set the subtitle delay in milliseconds, or adjust it with `relative=true`
+    post "/subtitles/delay" -> EmptySuccessResponse;
+    async fn subtitle_delay_set(player: PlayerHandle, ms: f64, relative: Option<bool>) {
+        base::delay_set(&player, base::Delay::Subtitles, ms, relative.unwrap_or(false)).await
+    }
+
+    /// Get current playback position
+    get "/time" -> SuccessResponse;
+    async fn time_get(player: PlayerHandle) {
+        base::time_get(player).await
+    }
+
+    /// Set playback position
+    post "/time" -> EmptySuccessResponse;
+    async fn time_set(player: PlayerHandle, pos: Option<f64>, percent: Option<f64>) {
+        base::time_set(player, pos, percent).await
+    }
+
+    /// Get the current playlist
+    ///
+    /// For long playlists, `offset` and `limit` return only part of it, and `queued_by` only
+    /// the items added by someone. The items keep their index in the whole playlist.
+    /// `fields` is a comma separated list of the fields to return for each item, like
+    /// `index,filename,data.queued_by`.
+    get "/playlist" -> SuccessResponse;
+    async fn playlist_get(
+        state: RestApiState,
+        offset: Option<usize>,
+        limit: Option<usize>,
+        queued_by: Option<String>,
+        fields: Option<String>,
+    ) {
+        base::playlist_get(
+            state.player,
+            &state.retries,
+            &state.autoplay,
+            queued_by.as_deref(),
+            &Page::new(offset, limit, fields.as_deref()),
+        )
         .await
-        .into()
-}
+    }
 
-/// Shuffle the playlist
-#[utoipa::path(
-    post,
-    path = "/playlist/shuffle",
-    responses(
-        (status = 200, description = "Success", body = EmptySuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn shuffle(State(mpv): State<Mpv>) -> RestResponse {
-    base::shuffle(mpv).await.into()
-}
+    /// Go to the next item in the playlist
+    post "/playlist/next" -> EmptySuccessResponse;
+    async fn playlist_next(player: PlayerHandle) {
+        base::playlist_next(player).await
+    }
 
-/// Check whether the playlist is looping
-#[utoipa::path(
-    get,
-    path = "/playlist/loop",
-    responses(
-        (status = 200, description = "Success", body = SuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn playlist_get_looping(State(mpv): State<Mpv>) -> RestResponse {
-    base::playlist_get_looping(mpv).await.into()
-}
+    /// Go back to the previous item in the playlist
+    post "/playlist/previous" -> EmptySuccessResponse;
+    async fn playlist_previous(player: PlayerHandle) {
+        base::playlist_previous(player).await
+    }
 
-#[derive(serde::Deserialize, utoipa::IntoParams)]
-struct PlaylistSetLoopingArgs {
-    r#loop: bool,
-}
+    /// Go to a specific item in the playlist
+    post "/playlist/goto" -> EmptySuccessResponse;
+    async fn playlist_goto(player: PlayerHandle, index: usize) {
+        base::playlist_goto(player, index).await
+    }
 
-/// Set whether the playlist should loop
-#[utoipa::path(
-    post,
-    path = "/playlist/loop",
-    params(PlaylistSetLoopingArgs),
-    responses(
-        (status = 200, description = "Success", body = EmptySuccessResponse),
-        (status = 500, description = "Internal server error", body = ErrorResponse),
-    )
-)]
-async fn playlist_set_looping(
-    State(mpv): State<Mpv>,
-    Query(query): Query<PlaylistSetLoopingArgs>,
-) -> RestResponse {
-    base::playlist_set_looping(mpv, query.r#loop).await.into()
+    /// Clears a single item or the entire playlist
+    ///
+    /// Clearing a large playlist fails with a confirmation token, unless `force` is set.
+    /// Repeat the request with the token to clear it anyway.
+    delete "/playlist" -> EmptySuccessResponse;
+    async fn playlist_remove_or_clear(
+        state: RestApiState,
+        index: Option<usize>,
+        force: Option<bool>,
+        token: Option<String>,
+    ) {
+        match index {
+            Some(index) => base::playlist_remove(state.player, index).await,
+            None => {
+                base::playlist_clear(
+                    state.player,
+                    &state.clear_guard,
+                    force.unwrap_or(false),
+                    token.as_deref(),
+                )
+                .await
+            }
+        }
+    }
+
+    /// Move a playlist item to a different position
+    post "/playlist/move" -> EmptySuccessResponse;
+    async fn playlist_move(player: PlayerHandle, index1: usize, index2: usize) {
+        base::playlist_move(player, index1, index2).await
+    }
+
+    /// Shuffle the playlist
+    post "/playlist/shuffle" -> EmptySuccessResponse;
+    async fn shuffle(player: PlayerHandle) {
+        base::shuffle(player).await
+    }
+
+    /// Check whether the playlist is looping
+    get "/playlist/loop" -> SuccessResponse;
+    async fn playlist_get_looping(player: PlayerHandle) {
+        base::playlist_get_looping(player).await
+    }
+
+    /// Set whether the playlist should loop
+    post "/playlist/loop" -> EmptySuccessResponse;
+    async fn playlist_set_looping(player: PlayerHandle, r#loop: bool) {
+        base::playlist_set_looping(player, r#loop).await
+    }
 }
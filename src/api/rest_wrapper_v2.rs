@@ -0,0 +1,610 @@
+use axum::{
+    Json, Router,
+    extract::{
+        FromRef,
+        rejection::{JsonRejection, QueryRejection},
+    },
+    response::{IntoResponse, Response},
+};
+use serde_json::{Value, json};
+
+use utoipa::OpenApi;
+
+use crate::{
+    autoplay::Autoplay,
+    mpv_setup::Quality,
+    player::{ItemNote, ItemState, Lane, PlayerHandle, PlaylistClearGuard, Priority, RetryQueue},
+    resolver::ResolverChain,
+    util::Page,
+    volume_transition::VolumeTransitionEngine,
+};
+
+use super::{
+    base,
+    error::{ApiError, ProblemDetails},
+    queue_ahead::PendingLoads,
+    rest_endpoints::rest_endpoints,
+};
+
+#[derive(Debug, Clone, FromRef)]
+struct RestApiState {
+    player: PlayerHandle,
+    volume_engine: VolumeTransitionEngine,
+    clear_guard: PlaylistClearGuard,
+    retries: RetryQueue,
+    autoplay: Autoplay,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+}
+
+pub fn rest_api_v2_routes(
+    player: PlayerHandle,
+    volume_engine: VolumeTransitionEngine,
+    clear_guard: PlaylistClearGuard,
+    retries: RetryQueue,
+    autoplay: Autoplay,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+) -> Router {
+    let state = RestApiState {
+        player,
+        volume_engine,
+        clear_guard,
+        retries,
+        autoplay,
+        resolvers,
+        pending,
+    };
+
+    let (router, _) = api_router().with_state(state).split_for_parts();
+
+    router
+}
+
+pub fn rest_api_v2_openapi() -> utoipa::openapi::OpenApi {
+    let (_, api): (Router<RestApiState>, _) = api_router().split_for_parts();
+    api
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        description = "The Grzegorz Brzeczyszczykiewicz API, used to control a running media player",
+        version = "2.0.0",
+    ),
+    servers((url = "/api/v2")),
+)]
+struct ApiDoc;
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub(super) struct EmptySuccessResponse {
+    #[schema(example = true)]
+    success: bool,
+    #[schema(example = json!(null))]
+    value: Option<Value>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub(super) struct SuccessResponse {
+    #[schema(example = true)]
+    success: bool,
+    #[schema(example = json!({ some: "arbitrary json value" }))]
+    value: Value,
+}
+
+/// The error responses of every endpoint, only used for documentation.
+#[derive(utoipa::IntoResponses)]
+#[allow(dead_code)]
+pub(super) enum ErrorResponses {
+    #[response(
+        status = 400,
+        description = "Invalid arguments",
+        content_type = "application/problem+json"
+    )]
+    BadRequest(ProblemDetails),
+
+    #[response(
+        status = 404,
+        description = "Referenced playlist item not found",
+        content_type = "application/problem+json"
+    )]
+    InvalidIndex(ProblemDetails),
+
+    #[response(
+        status = 409,
+        description = "Not possible in the current player state, or needs confirmation",
+        content_type = "application/problem+json"
+    )]
+    Conflict(ProblemDetails),
+
+    #[response(
+        status = 500,
+        description = "Internal server error",
+        content_type = "application/problem+json"
+    )]
+    InternalServerError(ProblemDetails),
+
+    #[response(
+        status = 503,
+        description = "mpv is unavailable",
+        content_type = "application/problem+json"
+    )]
+    ServiceUnavailable(ProblemDetails),
+}
+
+/// A successful response wrapped in the `{ success, value }` envelope,
+/// or an error rendered as RFC 7807 problem details.
+pub struct RestResponse(Result<Value, ApiError>);
+
+impl From<anyhow::Result<Value>> for RestResponse {
+    fn from(result: anyhow::Result<Value>) -> Self {
+        Self(result.map_err(ApiError::from))
+    }
+}
+
+impl From<anyhow::Result<()>> for RestResponse {
+    fn from(result: anyhow::Result<()>) -> Self {
+        Self(result.map(|_| Value::Null).map_err(ApiError::from))
+    }
+}
+
+impl IntoResponse for RestResponse {
+    fn into_response(self) -> Response {
+        match self.0 {
+            Ok(value) => Json(json!({ "success": true, "value": value })).into_response(),
+            Err(err) => {
+                log::debug!("Responding with error: {:?}", err);
+                err.into_response()
+            }
+        }
+    }
+}
+
+pub(super) fn query_rejection(rejection: QueryRejection) -> Response {
+    ApiError::InvalidArgument(rejection.body_text()).into_response()
+}
+
+pub(super) fn json_rejection(rejection: JsonRejection) -> Response {
+    ApiError::InvalidArgument(rejection.body_text()).into_response()
+}
+
+// --------- //
+// Endpoints //
+// --------- //
+
+rest_endpoints! {
+    router = api_router, state = RestApiState, openapi = ApiDoc;
+
+    /// Add item to playlist
+    ///
+    /// `maxheight` and `audio_only` choose the streams yt-dlp picks for this item,
+    /// overriding the quality set through `/playback/quality`. `start` and `end` are
+    /// timestamps like `1:12` or `72.5`, to only play part of the item.
+    ///
+    /// Spotify tracks, albums and playlists are added as YouTube searches for their tracks.
+    ///
+    /// `title` is shown instead of the title of the item, and `note` is shown along with it,
+    /// in the playlist and wherever the item is shown as playing. `queued_by` is the name of
+    /// whoever added the item, which it is credited to in the stats. Items added by someone
+    /// who logged in or gave an API key are credited to them instead.
+    ///
+    /// With `resume`, a long item that was stopped partway through in the last few days
+    /// continues from where it was stopped.
+    ///
+    /// With `ttl_hours`, the item is removed if it is still waiting in the playlist after that
+    /// many hours, instead of after the TTL set for everything.
+    ///
+    /// With `not_before`, in seconds since the unix epoch, the item is kept out of the
+    /// playlist until then, and added to the end of it once the time has come.
+    ///
+    /// With a `priority` of `high` or `admin`, the item goes ahead of the items of a lower
+    /// priority that have not started playing. `high` needs the `queue:priority` scope, and
+    /// `admin` needs an admin.
+    post "/load" -> EmptySuccessResponse, user = user, charge = charge, admin = admin;
+    async fn loadfile(
+        state: RestApiState,
+        path: String,
+        maxheight: Option<u32>,
+        audio_only: Option<bool>,
+        start: Option<String>,
+        end: Option<String>,
+        title: Option<String>,
+        note: Option<String>,
+        queued_by: Option<String>,
+        resume: Option<bool>,
+        ttl_hours: Option<u32>,
+        not_before: Option<u64>,
+        priority: Option<Priority>,
+    ) {
+        let quality = Quality {
+            max_height: maxheight,
+            audio_only: audio_only.unwrap_or(false),
+        };
+        base::loadfile(
+            state.player,
+            &state.resolvers,
+            &state.pending,
+            &path,
+            quality,
+            start.as_deref(),
+            end.as_deref(),
+            ItemState {
+                note: ItemNote::new(title.as_deref(), note.as_deref(), queued_by.as_deref()),
+                lane: Lane {
+                    priority: priority.unwrap_or_default(),
+                    ..Lane::default()
+                },
+                ttl_hours,
+                ..ItemState::default()
+            },
+            resume.unwrap_or(false),
+            not_before,
+            user.as_deref(),
+            admin.is_some(),
+            charge.as_deref(),
+        )
+        .await
+    }
+
+    /// Check whether the player is paused or playing
+    get "/play" -> SuccessResponse;
+    async fn play_get(player: PlayerHandle) {
+        base::play_get(player).await
+    }
+
+    /// Set whether the player is paused or playing
+    post "/play" -> EmptySuccessResponse;
+    async fn play_set(volume_engine: VolumeTransitionEngine, play: bool) {
+        base::play_set(volume_engine, play).await
+    }
+
+    /// Pause, and resume at a precise time, given in milliseconds since the unix epoch
+    ///
+    /// Used to start playback at the same time on several hosts, which should have their
+    /// clocks synced, e.g. with NTP. Scheduling again replaces the earlier time.
+    post "/playback/play-at" -> EmptySuccessResponse;
+    async fn play_at(volume_engine: VolumeTransitionEngine, unix_ms: u64) {
+        base::play_at(volume_engine, unix_ms).await
+    }
+
+    /// Cancel the scheduled start of playback, leaving the player paused
+    delete "/playback/play-at" -> EmptySuccessResponse;
+    async fn play_at_cancel(player: PlayerHandle) {
+        base::play_at_cancel(player).await
+    }
+
+    /// Get the current player volume
+    get "/volume" -> SuccessResponse;
+    async fn volume_get(player: PlayerHandle) {
+        base::volume_get(player).await
+    }
+
+    /// Set the player volume
+    post "/volume" -> EmptySuccessResponse;
+    async fn volume_set(volume_engine: VolumeTransitionEngine, volume: f64) {
+        base::volume_set(volume_engine, volume).await
+    }
+
+    /// Play a short clip right away, pausing the current item and resuming it afterwards
+    post "/interject" -> EmptySuccessResponse, user = user, charge = charge;
+    async fn interject(volume_engine: VolumeTransitionEngine, path: String) {
+        base::interject(volume_engine, &path, user.as_deref(), charge.as_deref()).await
+    }
+
+    /// Get the current audio output device, and all available devices
+    get "/audio/device" -> SuccessResponse;
+    async fn audio_device_get(player: PlayerHandle) {
+        base::audio_device_get(player).await
+    }
+
+    /// Switch the audio output device, to one of the devices listed by the GET endpoint
+    post "/audio/device" -> EmptySuccessResponse;
+    async fn audio_device_set(player: PlayerHandle, name: String) {
+        base::audio_device_set(player, name).await
+    }
+
+    /// Check whether the next playlist item is opened ahead of time
+    get "/playback/prefetch" -> SuccessResponse;
+    async fn prefetch_get(player: PlayerHandle) {
+        base::prefetch_get(player).await
+    }
+
+    /// Set whether the next playlist item is opened ahead of time, to avoid a gap between items
+    post "/playback/prefetch" -> EmptySuccessResponse;
+    async fn prefetch_set(player: PlayerHandle, enabled: bool) {
+        base::prefetch_set(player, enabled).await
+    }
+
+    /// Get how much is buffered, and whether playback is stalled waiting for the cache
+    get "/playback/cache" -> SuccessResponse;
+    async fn cache_get(player: PlayerHandle) {
+        base::cache_get(player).await
+    }
+
+    /// Get codecs, bitrates and dropped frames of the current item
+    get "/playback/stats" -> SuccessResponse;
+    async fn stats_get(player: PlayerHandle) {
+        base::stats_get(player).await
+    }
+
+    /// Get the requested hardware decoding mode, and the decoder actually in use
+    get "/playback/hwdec" -> SuccessResponse;
+    async fn hwdec_get(player: PlayerHandle) {
+        base::hwdec_get(player).await
+    }
+
+    /// Switch the hardware decoding mode, like `auto`, `vaapi` or `none`
+    post "/playback/hwdec" -> EmptySuccessResponse;
+    async fn hwdec_set(player: PlayerHandle, mode: String) {
+        base::hwdec_set(player, mode).await
+    }
+
+    /// Get the `ytdl-format` items are loaded with, or `null` for yt-dlp's default
+    get "/playback/quality" -> SuccessResponse;
+    async fn quality_get(player: PlayerHandle) {
+        base::quality_get(player).await
+    }
+
+    /// Set the quality yt-dlp picks for items loaded from now on
+    ///
+    /// `maxheight` skips taller video streams, and `audio_only` skips video entirely.
+    /// Without either, yt-dlp picks the best streams.
+    post "/playback/quality" -> EmptySuccessResponse;
+    async fn quality_set(player: PlayerHandle, maxheight: Option<u32>, audio_only: Option<bool>) {
+        let quality = Quality {
+            max_height: maxheight,
+            audio_only: audio_only.unwrap_or(false),
+        };
+        base::quality_set(player, quality).await
+    }
+
+    /// Get the audio delay, in milliseconds
+    get "/audio/delay" -> SuccessResponse;
+    async fn audio_delay_get(player: PlayerHandle) {
+        base::delay_get(&player, base::Delay::Audio).await
+    }
+
+    /// Set the audio delay in milliseconds, or adjust it with `relative=true`
+    post "/audio/delay" -> EmptySuccessResponse;
+    async fn audio_delay_set(player: PlayerHandle, ms: f64, relative: Option<bool>) {
+        base::delay_set(&player, base::Delay::Audio, ms, relative.unwrap_or(false)).await
+    }
+
+    /// Get the subtitle delay, in milliseconds
+    get "/subtitles/delay" -> SuccessResponse;
+    async fn subtitle_delay_get(player: PlayerHandle) {
+        base::delay_get(&player, base::Delay::Subtitles).await
+    }
+
+    /// Set the subtitle delay in milliseconds, or adjust it with `relative=true`
+    post "/subtitles/delay" -> EmptySuccessResponse;
+    async fn subtitle_delay_set(player: PlayerHandle, ms: f64, relative: Option<bool>) {
+        base::delay_set(&player, base::Delay::Subtitles, ms, relative.unwrap_or(false)).await
+    }
+
+    /// Get current playback position
+    get "/time" -> SuccessResponse;
+    async fn time_get(player: PlayerHandle) {
+        base::time_get(player).await
+    }
+
+    /// Set playback position, either in seconds or in percent
+    post "/time" -> EmptySuccessResponse;
+    async fn time_set(player: PlayerHandle, pos: Option<f64>, percent: Option<f64>) {
+        base::time_set(player, pos, percent).await
+    }
+
+    /// Get the current playlist
+    ///
+    /// For long playlists, `offset` and `limit` return only part of it, and `queued_by` only
+    /// the items added by someone. The items keep their index in the whole playlist.
+    /// `fields` is a comma separated list of the fields to return for each item, like
+    /// `index,filename,data.queued_by`.
+    get "/playlist" -> SuccessResponse;
+    async fn playlist_get(
+        state: RestApiState,
+        offset: Option<usize>,
+        limit: Option<usize>,
+        queued_by: Option<String>,
+        fields: Option<String>,
+    ) {
+        base::playlist_get(
+            state.player,
+            &state.retries,
+            &state.autoplay,
+            queued_by.as_deref(),
+            &Page::new(offset, limit, fields.as_deref()),
+        )
+        .await
+    }
+
+    /// Clear the entire playlist
+    ///
+    /// Clearing a large playlist fails with a `confirmation-required` problem, unless `force`
+    /// is set. Repeat the request with its `confirmation_token` as `token` to clear it anyway.
+    delete "/playlist" -> EmptySuccessResponse;
+    async fn playlist_clear(state: RestApiState, force: Option<bool>, token: Option<String>) {
+        base::playlist_clear(
+            state.player,
+            &state.clear_guard,
+            force.unwrap_or(false),
+            token.as_deref(),
+        )
+        .await
+    }
+
+    /// Remove a single item from the playlist
+    delete "/playlist/item" -> EmptySuccessResponse;
+    async fn playlist_remove(player: PlayerHandle, index: usize) {
+        base::playlist_remove(player, index).await
+    }
+
+    /// List the items kept out of the playlist until their time, the first to be added first
+    get "/playlist/deferred" -> SuccessResponse;
+    async fn deferred_list(pending: PendingLoads) {
+        base::deferred_list(pending).await
+    }
+
+    /// Forget an item kept out of the playlist, before its time
+    delete "/playlist/deferred" -> EmptySuccessResponse;
+    async fn deferred_cancel(pending: PendingLoads, id: u64) {
+        base::deferred_cancel(pending, id).await
+    }
+
+    /// Go to the next item in the playlist
+    post "/playlist/next" -> EmptySuccessResponse;
+    async fn playlist_next(player: PlayerHandle) {
+        base::playlist_next(player).await
+    }
+
+    /// Go back to the previous item in the playlist
+    post "/playlist/previous" -> EmptySuccessResponse;
+    async fn playlist_previous(player: PlayerHandle) {
+        base::playlist_previous(player).await
+    }
+
+    /// Go to a specific item in the playlist
+    post "/playlist/goto" -> EmptySuccessResponse;
+    async fn playlist_goto(player: PlayerHandle, index: usize) {
+        base::playlist_goto(player, index).await
+    }
+
+    /// Move playlist items to different positions
+    ///
+    /// Either move a single item with `from` and `to`, or several with a comma separated
+    /// list of `from:to` pairs in `moves`, like `3:0,7:1`. The moves are applied in order,
+    /// without any other playlist changes in between.
+    post "/playlist/move" -> EmptySuccessResponse;
+    async fn playlist_move(
+        player: PlayerHandle,
+        from: Option<usize>,
+        to: Option<usize>,
+        moves: Option<String>,
+    ) {
+        match (from, to, moves) {
+            (Some(from), Some(to), None) => base::playlist_move(player, from, to).await,
+            (None, None, Some(moves)) => match moves.split(',').map(str::parse).collect::<Result<Vec<_>, _>>() {
+                Ok(moves) => base::playlist_move_many(player, &moves).await,
+                Err(e) => Err(ApiError::InvalidArgument(e).into()),
+            },
+            _ => Err(ApiError::InvalidArgument(
+                "Either both 'from' and 'to', or only 'moves' must be given".to_string(),
+            )
+            .into()),
+        }
+    }
+
+    /// Move a playlist item to the top of the playlist
+    post "/playlist/move-to-top" -> EmptySuccessResponse;
+    async fn playlist_move_to_top(player: PlayerHandle, index: usize) {
+        base::playlist_move_to(player, index, base::MoveTarget::Top).await
+    }
+
+    /// Move a playlist item to the bottom of the playlist
+    post "/playlist/move-to-bottom" -> EmptySuccessResponse;
+    async fn playlist_move_to_bottom(player: PlayerHandle, index: usize) {
+        base::playlist_move_to(player, index, base::MoveTarget::Bottom).await
+    }
+
+    /// Move a playlist item to right after the item that is currently playing, so it plays next
+    post "/playlist/move-after-current" -> EmptySuccessResponse;
+    async fn playlist_move_after_current(player: PlayerHandle, index: usize) {
+        base::playlist_move_to(player, index, base::MoveTarget::AfterCurrent).await
+    }
+
+    /// Shuffle the playlist
+    post "/playlist/shuffle" -> EmptySuccessResponse;
+    async fn shuffle(player: PlayerHandle) {
+        base::shuffle(player).await
+    }
+
+    /// Check whether the playlist is looping
+    get "/playlist/loop" -> SuccessResponse;
+    async fn playlist_get_looping(player: PlayerHandle) {
+        base::playlist_get_looping(player).await
+    }
+
+    /// Set whether the playlist should loop
+    post "/playlist/loop" -> EmptySuccessResponse;
+    async fn playlist_set_looping(player: PlayerHandle, r#loop: bool) {
+        base::playlist_set_looping(player, r#loop).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        autoplay::AutoplayFilter,
+        player::{MpvPlayer, PlaylistClearGuard, RetryPolicy},
+        test_support::FakeMpv,
+    };
+
+    #[tokio::test]
+    async fn test_against_fake_mpv() {
+        let mpv = FakeMpv::start();
+        let player: PlayerHandle = Arc::new(MpvPlayer::new(mpv.connect().await));
+        let router = rest_api_v2_routes(
+            player.clone(),
+            VolumeTransitionEngine::new(player, false),
+            PlaylistClearGuard::new(None),
+            RetryQueue::new(RetryPolicy {
+                attempts: 0,
+                backoff: Duration::from_secs(1),
+                fallback_format: None,
+            }),
+            Autoplay::new(AutoplayFilter::default(), false),
+            ResolverChain::new(vec![]),
+            PendingLoads::default(),
+        );
+        let request = async |method: &str, uri: &str| -> (StatusCode, Value) {
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, serde_json::from_slice(&body).unwrap_or_default())
+        };
+
+        let (status, body) = request("GET", "/volume").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["value"], 50.0);
+
+        request("POST", "/volume?volume=30").await;
+        assert_eq!(mpv.property("volume"), Some(json!(30.0)));
+
+        let (status, _) = request("POST", "/load?path=/music/song.mp3&title=Song").await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = request("GET", "/playlist").await;
+        assert_eq!(body["value"][0]["filename"], "Song");
+        assert!(
+            mpv.commands()
+                .iter()
+                .any(|command| command[0] == "loadfile" && command[1] == "/music/song.mp3")
+        );
+
+        // Without the scopes policy, the priority is still only for those allowed it.
+        let (status, _) = request("POST", "/load?path=/music/vip.mp3&priority=high").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = request("POST", "/load?path=/music/vip.mp3&priority=admin").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
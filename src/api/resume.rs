@@ -0,0 +1,61 @@
+use axum::{
+    Json, Router,
+    extract::{Query, State, rejection::QueryRejection},
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::resume::ResumePositions;
+
+use super::error::ApiError;
+
+/// The `/api/resume` endpoints, for offering to resume long items that were stopped partway.
+pub fn resume_routes(positions: ResumePositions) -> Router {
+    Router::new()
+        .route("/api/resume", get(get_position))
+        .route("/api/resume", delete(forget_position))
+        .with_state(positions)
+}
+
+#[derive(Deserialize)]
+struct ResumeArgs {
+    path: String,
+}
+
+/// Get where the item at `path` would be resumed from if queued with `resume`, or null if
+/// it would start from the beginning
+async fn get_position(
+    State(positions): State<ResumePositions>,
+    query: Result<Query<ResumeArgs>, QueryRejection>,
+) -> Response {
+    let ResumeArgs { path } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+
+    match positions.position(&path) {
+        Ok(position) => Json(json!({ "success": true, "value": position })).into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+/// Forget where the item at `path` was stopped, so that it starts from the beginning
+async fn forget_position(
+    State(positions): State<ResumePositions>,
+    query: Result<Query<ResumeArgs>, QueryRejection>,
+) -> Response {
+    let ResumeArgs { path } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+
+    match positions.forget(&path) {
+        Ok(true) => Json(json!({ "success": true, "value": null })).into_response(),
+        Ok(false) => {
+            ApiError::NotFound(format!("No position is saved for {}", path)).into_response()
+        }
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
@@ -0,0 +1,56 @@
+use axum::Router;
+use serde_json::json;
+
+use crate::{player, soundboard::Soundboard, volume_transition::VolumeTransitionEngine};
+
+use super::{
+    error::ApiError,
+    rest_endpoints::rest_endpoints,
+    rest_wrapper_v2::{
+        EmptySuccessResponse, ErrorResponses, RestResponse, SuccessResponse, query_rejection,
+    },
+};
+
+#[derive(Debug, Clone)]
+struct SoundboardState {
+    volume_engine: VolumeTransitionEngine,
+    soundboard: Soundboard,
+}
+
+/// The `/api/soundboard` endpoints, for listing and playing the configured clips.
+pub fn soundboard_routes(volume_engine: VolumeTransitionEngine, soundboard: Soundboard) -> Router {
+    let state = SoundboardState {
+        volume_engine,
+        soundboard,
+    };
+    let (router, _) = api_router().with_state(state).split_for_parts();
+
+    router
+}
+
+pub(super) fn soundboard_openapi() -> utoipa::openapi::OpenApi {
+    let (_, api): (Router<SoundboardState>, _) = api_router().split_for_parts();
+    api
+}
+
+rest_endpoints! {
+    router = api_router, state = SoundboardState;
+
+    /// List the clips, with their durations in seconds
+    get "/api/soundboard" -> SuccessResponse;
+    async fn list_clips(state: SoundboardState) {
+        Ok(json!(state.soundboard.clips()))
+    }
+
+    /// Play a clip right away, resuming what was playing afterwards
+    post "/api/soundboard/{name}" -> EmptySuccessResponse, path = (name: String);
+    async fn play_clip(state: SoundboardState) {
+        match state.soundboard.get(&name) {
+            Some(clip) => {
+                let path = clip.path.to_string_lossy();
+                player::interject(state.volume_engine, &path, player::ItemState::default()).await
+            }
+            None => Err(ApiError::NotFound(format!("No clip named '{}'", name)).into()),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    Router,
+    extract::State,
+    http::HeaderMap,
+    response::{
+        IntoResponse,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::get,
+};
+use futures::{Stream, StreamExt, stream};
+use serde_json::json;
+use tokio::sync::broadcast;
+
+use super::event_broadcast::EventBroadcast;
+use super::events::OutgoingEvent;
+use crate::volume_transition::VolumeTransitionEngine;
+
+/// How many past events are kept around for clients resuming with `Last-Event-ID`.
+const BUFFER_SIZE: usize = 512;
+
+/// How often a comment is sent to keep idle connections from being closed by proxies.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Default)]
+struct EventBuffer {
+    next_id: u64,
+    events: VecDeque<(u64, OutgoingEvent)>,
+}
+
+#[derive(Debug, Clone)]
+struct EventStreamState {
+    buffer: Arc<Mutex<EventBuffer>>,
+    sender: broadcast::Sender<(u64, OutgoingEvent)>,
+}
+
+impl EventStreamState {
+    fn push(&self, event: OutgoingEvent) {
+        let mut buffer = self.buffer.lock().unwrap();
+        let id = buffer.next_id;
+        buffer.next_id += 1;
+
+        if buffer.events.len() == BUFFER_SIZE {
+            buffer.events.pop_front();
+        }
+        buffer.events.push_back((id, event.clone()));
+
+        // Nobody listening is fine, the event is still buffered.
+        let _ = self.sender.send((id, event));
+    }
+}
+
+/// The `/api/events` endpoint, streaming the websocket events as server-sent events.
+pub fn event_stream_routes(
+    events: EventBroadcast,
+    volume_engine: VolumeTransitionEngine,
+) -> Router {
+    let (sender, _) = broadcast::channel(BUFFER_SIZE);
+    let state = EventStreamState {
+        buffer: Arc::new(Mutex::new(EventBuffer::default())),
+        sender,
+    };
+
+    tokio::spawn(collect_events(events, volume_engine, state.clone()));
+
+    Router::new()
+        .route("/api/events", get(event_stream))
+        .with_state(state)
+}
+
+async fn collect_events(
+    events: EventBroadcast,
+    volume_engine: VolumeTransitionEngine,
+    state: EventStreamState,
+) {
+    let mut events = events.subscribe();
+    let mut volume_cap_receiver = volume_engine.get_volume_cap_watch_receiver();
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => state.push(event),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    log::warn!("The event stream missed {} events", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+
+            changed = volume_cap_receiver.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                let volume_cap = *volume_cap_receiver.borrow_and_update();
+                state.push(OutgoingEvent::VolumeCap(volume_cap));
+            }
+        }
+    }
+}
+
+fn to_sse_event(id: u64, event: &OutgoingEvent) -> Result<Event, Infallible> {
+    let data = json!(event);
+    let name = data["type"].as_str().unwrap_or("event").to_string();
+    Ok(Event::default()
+        .id(id.to_string())
+        .event(name)
+        .data(data.to_string()))
+}
+
+/// Stream player events as server-sent events
+///
+/// Each event has the same JSON format as the websocket events. Reconnecting clients
+/// get the events they missed, as long as they are still buffered.
+async fn event_stream(
+    State(state): State<EventStreamState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let last_event_id = headers
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    // Subscribe while holding the lock, so that no event falls between the replay and the live events.
+    let (missed, receiver) = {
+        let buffer = state.buffer.lock().unwrap();
+        let missed: Vec<_> = match last_event_id {
+            Some(last_id) => buffer
+                .events
+                .iter()
+                .filter(|(id, _)| *id > last_id)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        (missed, state.sender.subscribe())
+    };
+
+    let replay = stream::iter(
+        missed
+            .into_iter()
+            .map(|(id, event)| to_sse_event(id, &event)),
+    );
+
+    Sse::new(replay.chain(live_events(receiver)))
+        .keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL))
+}
+
+/// The events as they happen. Ends if the client falls too far behind, so that it
+/// reconnects and catches up from the buffer.
+fn live_events(
+    receiver: broadcast::Receiver<(u64, OutgoingEvent)>,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok((id, event)) => Some((to_sse_event(id, &event), receiver)),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                log::debug!("Event stream client fell {} events behind", skipped);
+                None
+            }
+            Err(broadcast::error::RecvError::Closed) => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_buffer() {
+        let (sender, mut receiver) = broadcast::channel(BUFFER_SIZE);
+        let state = EventStreamState {
+            buffer: Arc::new(Mutex::new(EventBuffer::default())),
+            sender,
+        };
+
+        for i in 0..BUFFER_SIZE + 2 {
+            state.push(OutgoingEvent::Volume(i as f64));
+        }
+
+        let buffer = state.buffer.lock().unwrap();
+        assert_eq!(buffer.events.len(), BUFFER_SIZE);
+        assert_eq!(buffer.events.front().unwrap().0, 2);
+        assert_eq!(
+            buffer.events.back().unwrap(),
+            &(
+                BUFFER_SIZE as u64 + 1,
+                OutgoingEvent::Volume((BUFFER_SIZE + 1) as f64)
+            )
+        );
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(2))
+        ));
+    }
+}
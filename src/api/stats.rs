@@ -0,0 +1,83 @@
+use axum::Router;
+use serde_json::{Value, json};
+
+use crate::{
+    history::{self, PlayHistory},
+    stats::{self, TimeRange},
+};
+
+use super::{
+    rest_endpoints::rest_endpoints,
+    rest_wrapper_v2::{ErrorResponses, RestResponse, SuccessResponse, query_rejection},
+};
+
+/// How many tracks or people are listed by default.
+const DEFAULT_LIMIT: usize = 10;
+
+/// The `/api/stats` endpoints, with statistics over what has been played.
+pub fn stats_routes(history: PlayHistory) -> Router {
+    let (router, _) = api_router().with_state(history).split_for_parts();
+
+    router
+}
+
+pub(super) fn stats_openapi() -> utoipa::openapi::OpenApi {
+    let (_, api): (Router<PlayHistory>, _) = api_router().split_for_parts();
+    api
+}
+
+rest_endpoints! {
+    router = api_router, state = PlayHistory;
+
+    /// Get the most played tracks
+    ///
+    /// `from` and `to` are either seconds since the unix epoch, or `YYYY-MM-DD` dates.
+    get "/api/stats/top-tracks" -> SuccessResponse;
+    async fn get_top_tracks(
+        history: PlayHistory,
+        from: Option<String>,
+        to: Option<String>,
+        limit: Option<usize>,
+    ) {
+        stat(&history, from, to, |entries, range, now| {
+            json!(stats::top_tracks(entries, range, now, limit.unwrap_or(DEFAULT_LIMIT)))
+        })
+    }
+
+    /// Get the people whose items were played the most
+    ///
+    /// `from` and `to` are either seconds since the unix epoch, or `YYYY-MM-DD` dates.
+    get "/api/stats/top-queuers" -> SuccessResponse;
+    async fn get_top_queuers(
+        history: PlayHistory,
+        from: Option<String>,
+        to: Option<String>,
+        limit: Option<usize>,
+    ) {
+        stat(&history, from, to, |entries, range, now| {
+            json!(stats::top_queuers(entries, range, now, limit.unwrap_or(DEFAULT_LIMIT)))
+        })
+    }
+
+    /// Get how many minutes were played each day
+    ///
+    /// `from` and `to` are either seconds since the unix epoch, or `YYYY-MM-DD` dates.
+    get "/api/stats/daily-minutes" -> SuccessResponse;
+    async fn get_daily_minutes(history: PlayHistory, from: Option<String>, to: Option<String>) {
+        stat(&history, from, to, |entries, range, now| {
+            json!(stats::daily_minutes(entries, range, now))
+        })
+    }
+}
+
+/// Work out a statistic over what was played between `from` and `to`.
+fn stat(
+    history: &PlayHistory,
+    from: Option<String>,
+    to: Option<String>,
+    stat: impl FnOnce(&[history::HistoryEntry], &TimeRange, u64) -> Value,
+) -> anyhow::Result<Value> {
+    let range = TimeRange::parse(from.as_deref(), to.as_deref())?;
+    let entries = history.entries(&range)?;
+    Ok(stat(&entries, &range, history::now()))
+}
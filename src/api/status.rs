@@ -0,0 +1,283 @@
+use std::time::Duration;
+
+use axum::{
+    Json, Router,
+    extract::{Query, Request, State, rejection::QueryRejection},
+    http::{HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use futures::StreamExt;
+use mpvipc_async::{Event, MpvExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::watch;
+
+use crate::player::PlayerHandle;
+
+use super::error::ApiError;
+
+/// How often the player is checked for changes. With mpv, changes are also picked up
+/// right away from its events.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The mpv properties that are part of [`PlayerStatus`].
+const STATUS_PROPERTIES: [&str; 4] = ["playlist", "pause", "volume", "loop-playlist"];
+
+/// The endpoints of the REST APIs that only depend on [`PlayerStatus`], and can be
+/// cached by revision. These are relative to where the APIs are mounted.
+const CACHEABLE_PATHS: [&str; 4] = ["/playlist", "/play", "/volume", "/playlist/loop"];
+
+/// How long a request waits for a change by default, and at most.
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_WAIT_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct StatusItem {
+    filename: String,
+    title: Option<String>,
+    note: Option<String>,
+}
+
+/// Everything a change is reported for. The playback position is left out,
+/// since it changes all the time.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct PlayerStatus {
+    playing: bool,
+    volume: f64,
+    looping: bool,
+    current: Option<usize>,
+    playlist: Vec<StatusItem>,
+}
+
+/// The latest status, and how many times it has changed.
+type Revisioned = (u64, Option<PlayerStatus>);
+
+/// Keeps track of the player status, and counts how many times it has changed.
+#[derive(Debug, Clone)]
+pub struct StatusTracker {
+    player: PlayerHandle,
+    status: watch::Receiver<Revisioned>,
+    /// Revisions start over when greg-ng restarts, so they are only compared within one run.
+    instance: u64,
+}
+
+impl StatusTracker {
+    /// Start following the status of `player` in the background.
+    pub fn spawn(player: PlayerHandle) -> Self {
+        let (sender, receiver) = watch::channel((0, None));
+        tokio::spawn(track_status(player.clone(), sender));
+
+        Self {
+            player,
+            status: receiver,
+            instance: rand::random(),
+        }
+    }
+
+    /// An ETag for the current revision, unless the status is not known yet.
+    fn etag(&self) -> Option<String> {
+        match &*self.status.borrow() {
+            (revision, Some(_)) => Some(format!("\"{:x}-{}\"", self.instance, revision)),
+            (_, None) => None,
+        }
+    }
+}
+
+/// The `/api/status` endpoint, for following the player state with long polling.
+pub fn status_routes(tracker: StatusTracker) -> Router {
+    Router::new()
+        .route("/api/status", get(status))
+        .with_state(tracker)
+}
+
+async fn player_status(player: &PlayerHandle) -> anyhow::Result<PlayerStatus> {
+    let playlist = player.playlist().await?;
+    Ok(PlayerStatus {
+        playing: player.is_playing().await?,
+        volume: player.get_volume().await?,
+        looping: player.is_looping().await?,
+        current: playlist.iter().position(|entry| entry.current),
+        playlist: playlist
+            .into_iter()
+            .map(|entry| {
+                let note = player.queue_state().note(&entry);
+                StatusItem {
+                    filename: entry.filename,
+                    title: note.title.or(entry.title),
+                    note: note.note,
+                }
+            })
+            .collect(),
+    })
+}
+
+async fn track_status(player: PlayerHandle, sender: watch::Sender<Revisioned>) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let mut events = match player.mpv() {
+        Some(mpv) => {
+            for property in STATUS_PROPERTIES {
+                if let Err(e) = mpv.observe_property(0, property).await {
+                    log::warn!("Failed to observe '{}' for the status: {}", property, e);
+                }
+            }
+            Some(mpv.get_event_stream().await)
+        }
+        None => None,
+    };
+
+    loop {
+        let poll = match events.as_mut() {
+            Some(stream) => tokio::select! {
+                _ = interval.tick() => Some(true),
+                event = stream.next() => match event {
+                    Some(Ok(Event::PropertyChange { name, .. })) => {
+                        Some(STATUS_PROPERTIES.contains(&name.as_str()))
+                    }
+                    Some(Ok(Event::FileLoaded | Event::EndFile { .. })) => Some(true),
+                    Some(Ok(_)) => Some(false),
+                    Some(Err(_)) | None => None,
+                },
+            },
+            None => {
+                interval.tick().await;
+                Some(true)
+            }
+        };
+
+        match poll {
+            Some(true) => {}
+            Some(false) => continue,
+            None => {
+                log::debug!("Lost the mpv events, only polling the status from now on");
+                events = None;
+                continue;
+            }
+        }
+
+        let status = match player_status(&player).await {
+            Ok(status) => status,
+            Err(e) => {
+                log::trace!("Failed to get the player status: {:#}", e);
+                continue;
+            }
+        };
+
+        sender.send_if_modified(|(revision, current)| {
+            if current.as_ref() == Some(&status) {
+                return false;
+            }
+            *revision += 1;
+            *current = Some(status);
+            true
+        });
+    }
+}
+
+#[derive(Deserialize)]
+struct StatusArgs {
+    #[serde(default)]
+    wait: bool,
+    since: Option<u64>,
+    /// In seconds.
+    timeout: Option<u64>,
+}
+
+/// Get the player status, with a revision that increases on every change.
+///
+/// With `wait=true`, the request blocks until the revision is no longer `since`,
+/// or until the timeout runs out, in which case the unchanged status is returned.
+async fn status(
+    State(mut state): State<StatusTracker>,
+    query: Result<Query<StatusArgs>, QueryRejection>,
+) -> Response {
+    let StatusArgs {
+        wait,
+        since,
+        timeout,
+    } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+
+    if wait {
+        let timeout = timeout
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_WAIT_TIMEOUT)
+            .min(MAX_WAIT_TIMEOUT);
+
+        // Without `since`, wait for the next change after the current revision.
+        let since = since.unwrap_or(state.status.borrow().0);
+        let changed = state
+            .status
+            .wait_for(|(revision, status)| *revision != since && status.is_some());
+        // Running out of time is not an error, the client just asks again.
+        let _ = tokio::time::timeout(timeout, changed).await;
+    }
+
+    let (revision, status) = state.status.borrow().clone();
+    let status = match status {
+        Some(status) => status,
+        // Nothing has been seen yet, so ask the player directly.
+        None => match player_status(&state.player).await {
+            Ok(status) => status,
+            Err(e) => return ApiError::from(e).into_response(),
+        },
+    };
+
+    let position = state.player.get_time_pos().await.ok().flatten();
+
+    Json(json!({
+        "success": true,
+        "value": {
+            "revision": revision,
+            "position": position,
+            "status": status,
+        },
+    }))
+    .into_response()
+}
+
+/// Add an ETag with the status revision to the responses of the cacheable endpoints,
+/// and answer `If-None-Match` with 304 Not Modified while the revision stays the same.
+pub async fn revision_etag(
+    State(tracker): State<StatusTracker>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let cacheable =
+        request.method() == Method::GET && CACHEABLE_PATHS.contains(&request.uri().path());
+    let Some(etag) = tracker.etag().filter(|_| cacheable) else {
+        return next.run(request).await;
+    };
+
+    let not_modified = request
+        .headers()
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        });
+
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
+    let mut response = next.run(request).await;
+
+    // Only tag the response if nothing changed while it was being made.
+    if response.status().is_success()
+        && tracker.etag().as_ref() == Some(&etag)
+        && let Ok(etag) = HeaderValue::from_str(&etag)
+    {
+        response.headers_mut().insert(header::ETAG, etag);
+    }
+
+    response
+}
@@ -0,0 +1,124 @@
+use axum::{
+    Json, Router,
+    extract::{DefaultBodyLimit, FromRef, Multipart, State, multipart::MultipartError},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    oidc::LoggedIn,
+    player::{ItemState, PlayerHandle},
+    quotas::Charged,
+    upload::UploadSpool,
+};
+
+use super::error::ApiError;
+
+/// Extra room for the multipart boundaries and headers around the file itself.
+const MULTIPART_OVERHEAD: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, FromRef)]
+struct UploadState {
+    player: PlayerHandle,
+    spool: UploadSpool,
+}
+
+/// The `/api/upload` endpoint, for playing local files without a URL.
+pub fn upload_routes(player: PlayerHandle, spool: UploadSpool) -> Router {
+    let body_limit = (spool.max_size() + MULTIPART_OVERHEAD) as usize;
+
+    Router::new()
+        .route("/api/upload", post(upload))
+        .layer(DefaultBodyLimit::max(body_limit))
+        .with_state(UploadState { player, spool })
+}
+
+fn multipart_error(err: MultipartError) -> ApiError {
+    if err.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        ApiError::PayloadTooLarge(err.body_text())
+    } else {
+        ApiError::InvalidArgument(err.body_text())
+    }
+}
+
+/// Upload a media file in the `file` field of a multipart form, and add it to the playlist.
+///
+/// The file is deleted again once it has been played.
+async fn upload(
+    State(player): State<PlayerHandle>,
+    State(spool): State<UploadSpool>,
+    user: LoggedIn,
+    charge: Charged,
+    mut multipart: Multipart,
+) -> Response {
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) if field.name() == Some("file") => field,
+            Ok(Some(_)) => continue,
+            Ok(None) => {
+                return ApiError::InvalidArgument("Missing the 'file' field".to_string())
+                    .into_response();
+            }
+            Err(e) => return multipart_error(e).into_response(),
+        };
+
+        let path = spool.new_path(field.file_name());
+        let result: Result<(), ApiError> = async {
+            let mut file = tokio::fs::File::create(&path)
+                .await
+                .map_err(|e| ApiError::Internal(format!("Failed to store upload: {}", e)))?;
+
+            let mut size = 0;
+            while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+                size += chunk.len() as u64;
+                if size > spool.max_size() {
+                    return Err(ApiError::PayloadTooLarge(format!(
+                        "Uploads can be at most {} bytes",
+                        spool.max_size()
+                    )));
+                }
+                file.write_all(&chunk)
+                    .await
+                    .map_err(|e| ApiError::Internal(format!("Failed to store upload: {}", e)))?;
+            }
+
+            file.flush()
+                .await
+                .map_err(|e| ApiError::Internal(format!("Failed to store upload: {}", e)))?;
+
+            log::info!(
+                "Received upload {:?} ({} bytes) as {}",
+                field.file_name().unwrap_or_default(),
+                size,
+                path.display()
+            );
+
+            let filename = path.to_string_lossy().to_string();
+            player
+                .load(&filename, ItemState::credited_to(user.as_deref()))
+                .await?;
+            if let Some(charge) = &charge {
+                charge.charge(1);
+            }
+            Ok(())
+        }
+        .await;
+
+        return match result {
+            Ok(()) => Json(json!({
+                "success": true,
+                "value": { "path": path.to_string_lossy() },
+            }))
+            .into_response(),
+            Err(err) => {
+                if let Err(e) = tokio::fs::remove_file(&path).await {
+                    log::warn!("Failed to remove failed upload {}: {}", path.display(), e);
+                }
+                err.into_response()
+            }
+        };
+    }
+}
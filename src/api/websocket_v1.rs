@@ -1,79 +1,228 @@
-use std::{
-    net::SocketAddr,
-    sync::{Arc, Mutex},
-};
+use std::{sync::Arc, time::Duration};
 
 use anyhow::Context;
-use futures::{StreamExt, stream::FuturesUnordered};
+use futures::{SinkExt, StreamExt, stream::SplitSink};
 use serde::{Deserialize, Serialize};
 
 use axum::{
-    Router,
+    Extension, Router,
     extract::{
-        ConnectInfo, State, WebSocketUpgrade,
-        ws::{Message, WebSocket},
+        ConnectInfo, Query, State, WebSocketUpgrade,
+        ws::{CloseFrame, Message, WebSocket, close_code},
     },
+    http::HeaderMap,
     response::IntoResponse,
-    routing::any,
-};
-use mpvipc_async::{
-    LoopProperty, Mpv, MpvExt, NumberChangeOptions, Playlist, PlaylistAddTypeOptions, SeekOptions,
-    Switch,
+    routing::{any, get},
 };
+use mpvipc_async::{LoopProperty, Mpv, MpvExt, SeekOptions, Switch};
 use serde_json::{Value, json};
 use tokio::{
     select,
-    sync::{mpsc, watch},
+    sync::{Notify, broadcast, mpsc, watch},
+};
+
+use super::asyncapi::websocket_schema;
+use super::base::{self, MoveTarget};
+use super::error::ApiError;
+use super::event_broadcast::EventBroadcast;
+use super::events::{
+    OutgoingEvent, PlaylistItem, playlist_items, subtitle_tracks, with_held_items,
 };
+use super::queue_ahead::PendingLoads;
+use super::ws_encoding::{WsEncoding, message_size};
+use super::ws_outbox::{OUTBOX_CAPACITY, Outbox, Overflow};
+use crate::{
+    autoplay::Autoplay,
+    control_lock::{ControlHolder, ControlLock},
+    oidc::{AuthenticatedUser, LoggedIn},
+    party::PartyMode,
+    playback_clock::{Heartbeat, PlaybackClock},
+    player::{
+        self, ItemNote, ItemState, Lane, PlaylistClearGuard, PlaylistLock, PlaylistMove, Priority,
+    },
+    policy::{Command, Policies, PolicyEngine},
+    quotas::{QuotaCharge, coarse_identity},
+    resolver::ResolverChain,
+    scopes::Scope,
+    server::ClientAddr,
+    util::{ClientRegistry, ConnectionEvent, IdPoolHandle, PooledId},
+    volume_transition::{VolumeCap, VolumeTransitionEngine},
+};
+
+/// The largest command a client can send, in bytes. Larger commands are answered with a
+/// `payload-too-large` problem, and otherwise ignored.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
 
-use crate::util::{ConnectionEvent, IdPool};
+/// Messages larger than this are not even read, and close the connection.
+const MAX_RECEIVED_SIZE: usize = 1024 * 1024;
+
+/// How long a client can take to receive a message before the connection is closed.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Debug, Clone)]
 struct WebsocketState {
     mpv: Mpv,
-    id_pool: Arc<Mutex<IdPool>>,
+    volume_engine: VolumeTransitionEngine,
+    clear_guard: PlaylistClearGuard,
+    party: PartyMode,
+    autoplay: Autoplay,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+    id_pool: IdPoolHandle,
     connection_counter_tx: mpsc::Sender<ConnectionEvent>,
+    clients: ClientRegistry,
+    policies: Policies,
+    control: ControlLock,
+    clock: PlaybackClock,
+    events: EventBroadcast,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn websocket_api(
     mpv: Mpv,
-    id_pool: Arc<Mutex<IdPool>>,
+    volume_engine: VolumeTransitionEngine,
+    clear_guard: PlaylistClearGuard,
+    party: PartyMode,
+    autoplay: Autoplay,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+    id_pool: IdPoolHandle,
     connection_counter_tx: mpsc::Sender<ConnectionEvent>,
+    clients: ClientRegistry,
+    policies: Policies,
+    control: ControlLock,
+    clock: PlaybackClock,
+    events: EventBroadcast,
 ) -> Router {
     let state = WebsocketState {
         mpv,
+        volume_engine,
+        clear_guard,
+        party,
+        autoplay,
+        resolvers,
+        pending,
         id_pool,
         connection_counter_tx,
+        clients,
+        policies,
+        control,
+        clock,
+        events,
     };
     Router::new()
         .route("/", any(websocket_handler))
+        .route("/schema.json", get(websocket_schema))
         .with_state(state)
 }
 
+/// Clients can say who they are when connecting, like `/ws?name=grzegorz-clients&version=1.2`,
+/// or later with a `hello` message.
+#[derive(Debug, Deserialize)]
+struct ClientIdentity {
+    name: Option<String>,
+    version: Option<String>,
+    /// The token from `/api/control/claim`, for the client that has claimed control.
+    control_token: Option<String>,
+    /// How messages are encoded, both ways.
+    #[serde(default)]
+    encoding: WsEncoding,
+}
+
 async fn websocket_handler(
     ws: WebSocketUpgrade,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    State(WebsocketState {
-        mpv,
-        id_pool,
-        connection_counter_tx,
-    }): State<WebsocketState>,
+    ConnectInfo(addr): ConnectInfo<ClientAddr>,
+    Query(client): Query<ClientIdentity>,
+    State(state): State<WebsocketState>,
+    headers: HeaderMap,
+    user: LoggedIn,
 ) -> impl IntoResponse {
-    let mpv = mpv.clone();
-    let id = match id_pool.lock().unwrap().request_id() {
+    let pooled_id = match state.id_pool.request_id().await {
         Ok(id) => id,
         Err(e) => {
             log::error!("Failed to get id from id pool: {:?}", e);
             return axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
     };
+    // Moved into the upgrade, so that the id is given back even if it never completes.
+    let registration = Registration {
+        pooled_id,
+        id_pool: state.id_pool.clone(),
+        clients: state.clients.clone(),
+        connection_counter_tx: state.connection_counter_tx.clone(),
+        addr,
+        connected: false,
+    };
+
+    let user = user.map(|Extension(user)| user);
+    let identity = coarse_identity(&addr, &headers, user.as_ref());
+    ws.max_message_size(MAX_RECEIVED_SIZE)
+        .on_upgrade(move |socket| {
+            handle_connection(socket, state, registration, client, user, identity)
+        })
+}
+
+/// A websocket client's id, and its place in the client registry and the connection count
+/// once it has connected. They are all given back when this is dropped, however the
+/// connection ends.
+struct Registration {
+    pooled_id: PooledId,
+    id_pool: IdPoolHandle,
+    clients: ClientRegistry,
+    connection_counter_tx: mpsc::Sender<ConnectionEvent>,
+    addr: ClientAddr,
+    /// Whether the connection has been counted, and so has to be uncounted.
+    connected: bool,
+}
 
-    ws.on_upgrade(move |socket| {
-        handle_connection(socket, addr, mpv, id, id_pool, connection_counter_tx)
-    })
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let Self {
+            pooled_id,
+            ref id_pool,
+            ref clients,
+            ref connection_counter_tx,
+            addr,
+            connected,
+        } = *self;
+        clients.disconnect(pooled_id.id);
+
+        // Both of these wait, which dropping can not.
+        let id_pool = id_pool.clone();
+        let connection_counter_tx = connection_counter_tx.clone();
+        tokio::spawn(async move {
+            match id_pool.release_id(pooled_id).await {
+                Ok(()) => {
+                    log::trace!("Released id {} for {:?}", pooled_id.id, addr);
+                }
+                Err(e) => {
+                    log::error!(
+                        "Error releasing id {} for {:?}: {:?}",
+                        pooled_id.id,
+                        addr,
+                        e
+                    );
+                }
+            }
+            if !connected {
+                return;
+            }
+            match connection_counter_tx
+                .send(ConnectionEvent::Disconnected)
+                .await
+            {
+                Ok(()) => {
+                    log::trace!("Connection count updated for {:?}", addr);
+                }
+                Err(e) => {
+                    log::error!("Error updating connection count for {:?}: {:?}", addr, e);
+                }
+            }
+        });
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct InitialState {
     pub cached_timestamp: Option<f64>,
     pub chapters: Vec<Value>,
@@ -85,12 +234,33 @@ pub struct InitialState {
     pub is_muted: bool,
     pub is_playing: bool,
     pub is_paused_for_cache: bool,
-    pub playlist: Playlist,
+    pub playlist: Vec<PlaylistItem>,
     pub tracks: Vec<Value>,
     pub volume: f64,
+    pub volume_cap: Option<VolumeCap>,
+    pub control_lock: Option<ControlHolder>,
 }
 
-async fn get_initial_state(mpv: &Mpv, id_pool: Arc<Mutex<IdPool>>) -> InitialState {
+/// All messages sent from the server to the websocket clients.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum OutgoingMessage {
+    InitialState(Box<InitialState>),
+    ConnectionCount(u64),
+    /// The result of a command. Commands that succeed are answered with
+    /// `{"success": true, "value": ..., "delta": [...]}`, where `delta` holds the events for
+    /// the state the command changed, read back right after it. A batch is answered with
+    /// one of those per command.
+    Response(Value),
+    Event(OutgoingEvent),
+}
+
+async fn get_initial_state(
+    mpv: &Mpv,
+    volume_engine: &VolumeTransitionEngine,
+    control: &ControlLock,
+    id_pool: &IdPoolHandle,
+) -> InitialState {
     let cached_timestamp = mpv
         .get_property_value("demuxer-cache-state")
         .await
@@ -106,7 +276,7 @@ async fn get_initial_state(mpv: &Mpv, id_pool: Arc<Mutex<IdPool>>) -> InitialSta
         Ok(Some(Value::Array(chapters))) => chapters,
         _ => vec![],
     };
-    let connections = id_pool.lock().unwrap().id_count();
+    let connections = id_pool.id_count();
     let current_percent_pos = mpv.get_property("percent-pos").await.unwrap_or(None);
     let current_track = mpv.get_file_path().await.unwrap_or("".to_string());
     let duration = mpv.get_duration().await.unwrap_or(0.0);
@@ -123,21 +293,20 @@ async fn get_initial_state(mpv: &Mpv, id_pool: Arc<Mutex<IdPool>>) -> InitialSta
         .await
         .unwrap_or(Some(false))
         .unwrap_or(false);
-    let playlist = mpv.get_playlist().await.unwrap_or(Playlist(vec![]));
+    let queue = volume_engine.player().queue_state();
+    let playlist = mpv
+        .get_playlist()
+        .await
+        .map(|playlist| playlist_items(queue, playlist))
+        .unwrap_or_default();
+    let playlist = with_held_items(queue, playlist);
     let tracks = match mpv.get_property_value("track-list").await {
-        Ok(Some(Value::Array(tracks))) => tracks
-            .into_iter()
-            .filter(|t| {
-                t.as_object()
-                    .and_then(|o| o.get("type"))
-                    .and_then(|t| t.as_str())
-                    .unwrap_or("")
-                    == "sub"
-            })
-            .collect(),
+        Ok(Some(Value::Array(tracks))) => subtitle_tracks(tracks),
         _ => vec![],
     };
     let volume = mpv.get_volume().await.unwrap_or(0.0);
+    let volume_cap = volume_engine.volume_cap();
+    let control_lock = control.holder();
     // TODO: use default when new version is released
     InitialState {
         cached_timestamp,
@@ -153,83 +322,97 @@ async fn get_initial_state(mpv: &Mpv, id_pool: Arc<Mutex<IdPool>>) -> InitialSta
         playlist,
         tracks,
         volume,
+        volume_cap,
+        control_lock,
     }
 }
 
-const DEFAULT_PROPERTY_SUBSCRIPTIONS: [&str; 11] = [
-    "chapter-list",
-    "demuxer-cache-state",
-    "duration",
-    "loop-playlist",
-    "mute",
-    "pause",
-    "paused-for-cache",
-    "percent-pos",
-    "playlist",
-    "track-list",
-    "volume",
-];
-
-async fn setup_default_subscribes(mpv: &Mpv) -> anyhow::Result<()> {
-    let mut futures = FuturesUnordered::new();
-
-    futures.extend(
-        DEFAULT_PROPERTY_SUBSCRIPTIONS
-            .iter()
-            .map(|property| mpv.observe_property(0, property)),
-    );
-
-    while let Some(result) = futures.next().await {
-        result?;
-    }
-
-    Ok(())
-}
-
 async fn handle_connection(
     mut socket: WebSocket,
-    addr: SocketAddr,
-    mpv: Mpv,
-    channel_id: u64,
-    id_pool: Arc<Mutex<IdPool>>,
-    connection_counter_tx: mpsc::Sender<ConnectionEvent>,
+    WebsocketState {
+        mpv,
+        volume_engine,
+        clear_guard,
+        party,
+        autoplay,
+        resolvers,
+        pending,
+        id_pool,
+        connection_counter_tx,
+        clients,
+        policies,
+        control,
+        clock,
+        events,
+    }: WebsocketState,
+    mut registration: Registration,
+    ClientIdentity {
+        name,
+        version,
+        control_token,
+        encoding,
+    }: ClientIdentity,
+    user: Option<AuthenticatedUser>,
+    identity: String,
 ) {
+    let addr = registration.addr;
+    let channel_id = registration.pooled_id.id;
+    let kicked = clients.connect(channel_id, &addr, name, version);
+    if control_token.is_some() {
+        clients.set_control_token(channel_id, control_token);
+    }
+    clients.set_identity(channel_id, identity);
+    clients.set_user(channel_id, user);
+
     match connection_counter_tx.send(ConnectionEvent::Connected).await {
         Ok(()) => {
             log::trace!("Connection count updated for {:?}", addr);
+            registration.connected = true;
         }
         Err(e) => {
             log::error!("Error updating connection count for {:?}: {:?}", addr, e);
         }
     }
 
-    // TODO: There is an asynchronous gap between gathering the initial state and subscribing to the properties
-    //       This could lead to missing events if they happen in that gap. Send initial state, but also ensure
-    //       that there is an additional "initial state" sent upon subscription to all properties to ensure that
-    //       the state is correct.
-    let initial_state = get_initial_state(&mpv, id_pool.clone()).await;
-
-    let message = Message::Text(
-        json!({
-            "type": "initial_state",
-            "value": initial_state,
-        })
-        .to_string()
-        .into(),
-    );
-
-    socket.send(message).await.unwrap();
+    // Subscribe before gathering the initial state, so that nothing that changes in between
+    // is missed. The events from then are sent after the initial state, and are just as new.
+    let events = events.subscribe();
+    let initial_state = get_initial_state(&mpv, &volume_engine, &control, &id_pool).await;
 
-    setup_default_subscribes(&mpv).await.unwrap();
+    let initial_state =
+        match encoding.encode(&OutgoingMessage::InitialState(Box::new(initial_state))) {
+            Ok(message) => message,
+            Err(e) => {
+                log::error!("Failed to encode the initial state for {:?}: {:?}", addr, e);
+                return;
+            }
+        };
+    if let Err(e) = socket.send(initial_state).await {
+        log::debug!("Failed to send the initial state to {:?}: {:?}", addr, e);
+        return;
+    }
 
-    let id_count_watch_receiver = id_pool.lock().unwrap().get_id_count_watch_receiver();
+    let id_count_watch_receiver = id_pool.get_id_count_watch_receiver();
 
     let connection_loop_result = tokio::spawn(connection_loop(
         socket,
         addr,
         mpv.clone(),
+        volume_engine,
+        clear_guard,
+        party,
+        autoplay,
+        resolvers,
+        pending,
+        clients.clone(),
+        policies,
+        control,
+        clock.subscribe(),
+        events,
         channel_id,
         id_count_watch_receiver,
+        kicked,
+        encoding,
     ));
 
     match connection_loop_result.await {
@@ -243,67 +426,175 @@ async fn handle_connection(
             log::error!("Error in connection loop for {:?}: {:?}", addr, e);
         }
     }
+}
 
-    match mpv.unobserve_property(channel_id).await {
-        Ok(()) => {
-            log::trace!("Unsubscribed from properties for {:?}", addr);
-        }
-        Err(e) => {
-            log::error!(
-                "Error unsubscribing from properties for {:?}: {:?}",
-                addr,
-                e
-            );
-        }
+/// Queues messages for one client in its encoding, to be sent by [`send_queued`]. When it
+/// is dropped, the messages still waiting are sent and then the connection ends.
+struct ClientSender {
+    outbox: Arc<Outbox>,
+    encoding: WsEncoding,
+    clients: ClientRegistry,
+    channel_id: u64,
+}
+
+impl ClientSender {
+    fn send(&self, message: &OutgoingMessage) -> anyhow::Result<()> {
+        self.push(coalesce_key(message), self.encoding.encode(message)?)
     }
 
-    match id_pool.lock().unwrap().release_id(channel_id) {
-        Ok(()) => {
-            log::trace!("Released id {} for {:?}", channel_id, addr);
-        }
-        Err(e) => {
-            log::error!("Error releasing id {} for {:?}: {:?}", channel_id, addr, e);
+    /// Queue a message, closing the connection if the client has too much waiting already.
+    fn push(&self, key: Option<&'static str>, message: Message) -> anyhow::Result<()> {
+        match self.outbox.push(key, message) {
+            Ok(0) => Ok(()),
+            Ok(dropped) => {
+                self.clients.count_dropped(self.channel_id, dropped as u64);
+                Ok(())
+            }
+            Err(Overflow) => {
+                self.clients.count_slow_disconnect();
+                self.close(CloseFrame {
+                    code: close_code::AGAIN,
+                    reason: "Too slow to keep up with events".into(),
+                });
+                anyhow::bail!("The client is too slow to keep up with events")
+            }
         }
     }
 
-    match connection_counter_tx
-        .send(ConnectionEvent::Disconnected)
-        .await
-    {
-        Ok(()) => {
-            log::trace!("Connection count updated for {:?}", addr);
-        }
-        Err(e) => {
-            log::error!("Error updating connection count for {:?}: {:?}", addr, e);
+    fn close(&self, frame: CloseFrame) {
+        self.outbox.close(frame);
+    }
+}
+
+impl Drop for ClientSender {
+    fn drop(&mut self) {
+        self.outbox.finish();
+    }
+}
+
+/// Messages that are sent often and only matter until the next one of the same kind, so
+/// that older ones can be dropped for clients that fall behind.
+fn coalesce_key(message: &OutgoingMessage) -> Option<&'static str> {
+    match message {
+        OutgoingMessage::ConnectionCount(_) => Some("connection_count"),
+        OutgoingMessage::Event(event) => event.coalesce_key(),
+        _ => None,
+    }
+}
+
+/// Send the messages queued for a client, until its outbox is closed. A client that takes
+/// longer than [`SEND_TIMEOUT`] to take a message is given up on.
+async fn send_queued(
+    mut sink: SplitSink<WebSocket, Message>,
+    outbox: Arc<Outbox>,
+    clients: ClientRegistry,
+) -> anyhow::Result<()> {
+    while let Some(message) = outbox.next().await {
+        match tokio::time::timeout(SEND_TIMEOUT, sink.send(message)).await {
+            Ok(result) => result?,
+            Err(_) => {
+                clients.count_slow_disconnect();
+                anyhow::bail!("Timed out sending a message, the client is too slow");
+            }
         }
     }
+    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn connection_loop(
-    mut socket: WebSocket,
-    addr: SocketAddr,
+    socket: WebSocket,
+    addr: ClientAddr,
     mpv: Mpv,
+    volume_engine: VolumeTransitionEngine,
+    clear_guard: PlaylistClearGuard,
+    party: PartyMode,
+    autoplay: Autoplay,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+    clients: ClientRegistry,
+    policies: Policies,
+    control: ControlLock,
+    mut heartbeat_receiver: watch::Receiver<Option<Heartbeat>>,
+    mut events: broadcast::Receiver<OutgoingEvent>,
     channel_id: u64,
     mut id_count_watch_receiver: watch::Receiver<u64>,
+    kicked: Arc<Notify>,
+    encoding: WsEncoding,
 ) -> Result<(), anyhow::Error> {
-    let mut event_stream = mpv.get_event_stream().await;
+    let mut volume_cap_watch_receiver = volume_engine.get_volume_cap_watch_receiver();
+    let mut control_watch_receiver = control.get_holder_watch_receiver();
+    let (sink, mut stream) = socket.split();
+    let outbox = Arc::new(Outbox::new(OUTBOX_CAPACITY));
+    let mut writer = tokio::spawn(send_queued(sink, outbox.clone(), clients.clone()));
+    let sender = ClientSender {
+        outbox,
+        encoding,
+        clients: clients.clone(),
+        channel_id,
+    };
+
     loop {
         select! {
+            _ = kicked.notified() => {
+                log::info!("Kicking {:?}", addr);
+                let close = CloseFrame {
+                    code: close_code::POLICY,
+                    reason: "Kicked by an admin".into(),
+                };
+                sender.close(close);
+                return Ok(());
+            }
+
             id_count = id_count_watch_receiver.changed() => {
                 if let Err(e) = id_count {
                     anyhow::bail!("Error reading id count watch receiver for {:?}: {:?}", addr, e);
                 }
 
-                let message = Message::Text(json!({
-                    "type": "connection_count",
-                    "value": id_count_watch_receiver.borrow().clone(),
-                }).to_string().into(),);
+                let id_count = *id_count_watch_receiver.borrow();
+                sender.send(&OutgoingMessage::ConnectionCount(id_count))?;
+            }
+
+            volume_cap = volume_cap_watch_receiver.changed() => {
+                if let Err(e) = volume_cap {
+                    anyhow::bail!("Error reading volume cap watch receiver for {:?}: {:?}", addr, e);
+                }
+
+                let volume_cap = *volume_cap_watch_receiver.borrow_and_update();
+                sender.send(&OutgoingMessage::Event(OutgoingEvent::VolumeCap(volume_cap)))?;
+            }
+
+            holder = control_watch_receiver.changed() => {
+                if let Err(e) = holder {
+                    anyhow::bail!("Error reading control lock watch receiver for {:?}: {:?}", addr, e);
+                }
+
+                let holder = control_watch_receiver.borrow_and_update().clone();
+                sender.send(&OutgoingMessage::Event(OutgoingEvent::ControlLock(holder)))?;
+            }
+
+            heartbeat = heartbeat_receiver.changed() => {
+                if let Err(e) = heartbeat {
+                    anyhow::bail!("Error reading heartbeat receiver for {:?}: {:?}", addr, e);
+                }
 
-                socket.send(message).await?;
+                let heartbeat = heartbeat_receiver.borrow_and_update().clone();
+                if let Some(heartbeat) = heartbeat {
+                    sender.send(&OutgoingMessage::Event(OutgoingEvent::Heartbeat(heartbeat)))?;
+                }
+            }
+
+            result = &mut writer => {
+                match result {
+                    Ok(Ok(())) => return Ok(()),
+                    Ok(Err(e)) => anyhow::bail!("Error sending to {:?}: {:?}", addr, e),
+                    Err(e) => anyhow::bail!("Error in the sender task for {:?}: {:?}", addr, e),
+                }
             }
 
-            message = socket.recv() => {
+            message = stream.next() => {
                 log::trace!("Received command from {:?}: {:?}", addr, message);
+                clients.touch(channel_id);
 
                 let ws_message_content = match message {
                     Some(Ok(message)) => message,
@@ -335,16 +626,22 @@ async fn connection_loop(
 
                 if let Message::Ping(xs) = ws_message_content {
                     log::trace!("Ponging {:?} with {:?}", addr, xs);
-                    socket.send(Message::Pong(xs)).await?;
+                    sender.push(None, Message::Pong(xs))?;
                     continue;
                 }
 
-                let message_content = match ws_message_content {
-                    Message::Text(text) => text,
-                    m => anyhow::bail!("Unexpected message type: {:?}", m),
-                };
+                let size = message_size(&ws_message_content);
+                if size > MAX_MESSAGE_SIZE {
+                    log::warn!("Ignoring a message of {} bytes from {:?}", size, addr);
+                    let error = ApiError::PayloadTooLarge(format!(
+                        "Messages can be at most {} bytes",
+                        MAX_MESSAGE_SIZE
+                    ));
+                    sender.send(&OutgoingMessage::Response(json!(error.to_problem_details())))?;
+                    continue;
+                }
 
-                let message_json = match serde_json::from_str::<Value>(&message_content) {
+                let message_json = match encoding.decode(&ws_message_content) {
                     Ok(json) => json,
                     Err(e) => anyhow::bail!("Error parsing message from {:?}: {:?}", addr, e),
                 };
@@ -352,38 +649,36 @@ async fn connection_loop(
                 log::trace!("Handling command from {:?}: {:?}", addr, message_json);
 
                 // TODO: handle errors
-                match handle_message(message_json, mpv.clone(), channel_id).await {
+                match handle_message(message_json, mpv.clone(), volume_engine.clone(), clear_guard.clone(), party.clone(), autoplay.clone(), resolvers.clone(), pending.clone(), &clients, &policies, channel_id).await {
                     Ok(Some(response)) => {
                         log::trace!("Handled command from {:?} successfully, sending response", addr);
-                        let message = Message::Text(json!({
-                            "type": "response",
-                            "value": response,
-                        }).to_string().into(),);
-                        socket.send(message).await?;
+                        sender.send(&OutgoingMessage::Response(response))?;
                     }
                     Ok(None) => {
                         log::trace!("Handled command from {:?} successfully", addr);
                     }
                     Err(e) => {
                         log::error!("Error handling message from {:?}: {:?}", addr, e);
+
+                        // The client can not confirm without the token, or know why nothing
+                        // happened, so these errors are sent back.
+                        if let Some(error @ (ApiError::ConfirmationRequired { .. } | ApiError::PolicyViolation(_))) = e.downcast_ref::<ApiError>() {
+                            sender.send(&OutgoingMessage::Response(json!(error.to_problem_details())))?;
+                        }
                     }
                 }
             }
-            event = event_stream.next() => {
+            event = events.recv() => {
                 match event {
-                    Some(Ok(event)) => {
+                    Ok(event) => {
                         log::trace!("Sending event to {:?}: {:?}", addr, event);
-                        let message = Message::Text(json!({
-                            "type": "event",
-                            "value": event,
-                        }).to_string().into(),);
-                        socket.send(message).await?;
+                        sender.send(&OutgoingMessage::Event(event))?;
                     }
-                    Some(Err(e)) => {
-                        log::error!("Error reading event stream for {:?}: {:?}", addr, e);
-                        anyhow::bail!("Error reading event stream for {:?}: {:?}", addr, e);
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        log::warn!("{:?} missed {} events", addr, missed);
+                        clients.count_dropped(channel_id, missed);
                     }
-                    None => {
+                    Err(broadcast::error::RecvError::Closed) => {
                         log::trace!("Event stream ended for {:?}", addr);
                         return Ok(());
                     }
@@ -393,36 +688,409 @@ async fn connection_loop(
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// All commands that can be sent from the websocket clients to the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum WSCommand {
+    /// Say which client this is, for `/api/admin/connections`.
+    Hello {
+        name: String,
+        #[serde(default)]
+        version: Option<String>,
+        /// The token from `/api/control/claim`, if this client has claimed control.
+        #[serde(default)]
+        control_token: Option<String>,
+    },
     // Subscribe { property: String },
     // UnsubscribeAll,
-    Load { urls: Vec<String> },
+    Load {
+        urls: Vec<String>,
+        /// Only play the items from this timestamp, like `1:12`.
+        #[serde(default)]
+        start: Option<String>,
+        /// Stop playing the items at this timestamp.
+        #[serde(default)]
+        end: Option<String>,
+        /// Shown instead of the titles of the items.
+        #[serde(default)]
+        title: Option<String>,
+        /// Shown along with the items, like "play this at midnight".
+        #[serde(default)]
+        note: Option<String>,
+        /// The name of whoever added the items, unless the client logged in.
+        #[serde(default)]
+        queued_by: Option<String>,
+        /// Continue long items from where they were stopped, if that was recently.
+        #[serde(default)]
+        resume: bool,
+        /// Remove the items if they are still waiting in the playlist after this many hours.
+        #[serde(default)]
+        ttl_hours: Option<u32>,
+        /// Keep the items out of the playlist until this time, in seconds since the unix
+        /// epoch.
+        #[serde(default)]
+        not_before: Option<u64>,
+        /// Add the items ahead of the ones with a lower priority.
+        #[serde(default)]
+        priority: Priority,
+    },
+    Interject {
+        url: String,
+    },
     TogglePlayback,
-    Volume { volume: f64 },
-    Time { time: f64 },
+    Volume {
+        volume: f64,
+    },
+    Time {
+        time: f64,
+    },
     PlaylistNext,
     PlaylistPrevious,
-    PlaylistGoto { position: usize },
-    PlaylistClear,
-    PlaylistRemove { positions: Vec<usize> },
-    PlaylistMove { from: usize, to: usize },
+    PlaylistGoto {
+        position: usize,
+    },
+    /// Large playlists are only cleared with `force`, or with the `confirmation_token`
+    /// from the response to an earlier attempt.
+    PlaylistClear {
+        #[serde(default)]
+        force: bool,
+        #[serde(default)]
+        token: Option<String>,
+    },
+    PlaylistRemove {
+        positions: Vec<usize>,
+    },
+    PlaylistMove {
+        from: usize,
+        to: usize,
+    },
+    /// Several moves, applied in order without any other playlist changes in between.
+    PlaylistMoveMany {
+        moves: Vec<PlaylistMove>,
+    },
+    PlaylistMoveToTop {
+        position: usize,
+    },
+    PlaylistMoveToBottom {
+        position: usize,
+    },
+    PlaylistMoveAfterCurrent {
+        position: usize,
+    },
     Shuffle,
-    SetSubtitleTrack { track: Option<usize> },
-    SetLooping { value: bool },
+    SetSubtitleTrack {
+        track: Option<usize>,
+    },
+    SetPartyMode {
+        value: bool,
+    },
+    SetAutoplay {
+        value: bool,
+    },
+    SetLooping {
+        value: bool,
+    },
+    /// Set the subtitle delay in milliseconds, or adjust it by `ms` if `relative`.
+    SubtitleDelay {
+        ms: f64,
+        #[serde(default)]
+        relative: bool,
+    },
+    /// Set the audio delay in milliseconds, or adjust it by `ms` if `relative`.
+    AudioDelay {
+        ms: f64,
+        #[serde(default)]
+        relative: bool,
+    },
+    /// Several commands, executed in order without any other playlist changes in between.
+    ///
+    /// The response holds one result per command. If a command fails, the rest are skipped.
+    /// Batches can not be nested.
+    Batch {
+        #[schema(no_recursion)]
+        commands: Vec<WSCommand>,
+    },
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_message(
     message: Value,
     mpv: Mpv,
-    _channel_id: u64,
+    volume_engine: VolumeTransitionEngine,
+    clear_guard: PlaylistClearGuard,
+    party: PartyMode,
+    autoplay: Autoplay,
+    resolvers: ResolverChain,
+    pending: PendingLoads,
+    clients: &ClientRegistry,
+    policies: &Policies,
+    channel_id: u64,
 ) -> anyhow::Result<Option<Value>> {
     let command =
         serde_json::from_value::<WSCommand>(message).context("Failed to parse message")?;
 
     log::trace!("Successfully parsed message: {:?}", command);
 
+    if let WSCommand::Hello {
+        name,
+        version,
+        control_token,
+    } = command
+    {
+        clients.identify(channel_id, name, version);
+        if control_token.is_some() {
+            clients.set_control_token(channel_id, control_token);
+        }
+        return Ok(None);
+    }
+    // Every other command changes something.
+    let control_token = clients.control_token(channel_id);
+    let user = clients.user(channel_id);
+    let identity = clients.identity(channel_id);
+    let commands = match &command {
+        WSCommand::Batch { commands } => commands.iter().collect(),
+        command => vec![command],
+    };
+    // What counts the items added towards the quota of the client, if any are.
+    let mut charge = None;
+    for command in commands {
+        let command = Command {
+            path: None,
+            is_admin: user.as_ref().is_some_and(AuthenticatedUser::is_admin),
+            control_token: control_token.as_deref(),
+            scopes: user.as_ref().map(|user| user.scopes.as_slice()),
+            needs: scope_needed(command),
+            identity: identity.as_deref(),
+            priority: match command {
+                WSCommand::Load { priority, .. } => *priority,
+                _ => Priority::Normal,
+            },
+        };
+        policies.check_command(&command)?;
+        charge = charge.or_else(|| policies.quota_charge(&command));
+    }
+
+    // Commands are often made up of several mpv commands. Holding the playlist lock keeps
+    // other clients and the REST API from changing the playlist in between them.
+    let lock = player::lock_playlist(volume_engine.player().queue_state()).await;
+
+    match command {
+        WSCommand::Batch { commands } => Ok(Some(
+            execute_batch(
+                commands,
+                &mpv,
+                &volume_engine,
+                &clear_guard,
+                &party,
+                &autoplay,
+                &resolvers,
+                &pending,
+                &lock,
+                user.as_ref(),
+                charge.as_ref(),
+            )
+            .await,
+        )),
+        command => {
+            let delta = StateDelta::of(&command);
+            let value = execute_command(
+                command,
+                &mpv,
+                &volume_engine,
+                &clear_guard,
+                &party,
+                &autoplay,
+                &resolvers,
+                &pending,
+                &lock,
+                user.as_ref(),
+                charge.as_ref(),
+            )
+            .await?;
+            Ok(Some(json!({
+                "success": true,
+                "value": value,
+                "delta": delta.read(&mpv, &volume_engine).await,
+            })))
+        }
+    }
+}
+
+/// The scope needed for what `command` does.
+fn scope_needed(command: &WSCommand) -> Option<Scope> {
+    match command {
+        WSCommand::Hello { .. } | WSCommand::Batch { .. } => None,
+        WSCommand::Load { .. } | WSCommand::Interject { .. } => Some(Scope::QueueAdd),
+        WSCommand::PlaylistClear { .. } | WSCommand::PlaylistRemove { .. } => {
+            Some(Scope::QueueRemove)
+        }
+        _ => Some(Scope::PlaybackControl),
+    }
+}
+
+/// The part of the state a command changes, which is read back and sent in the response
+/// to it, so that the client can update right away instead of waiting for the events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StateDelta {
+    Playlist,
+    Volume,
+    Paused,
+    Position,
+    SubtitleTracks,
+    Looping,
+    Nothing,
+}
+
+impl StateDelta {
+    fn of(command: &WSCommand) -> Self {
+        match command {
+            WSCommand::Load { .. }
+            | WSCommand::Interject { .. }
+            | WSCommand::PlaylistNext
+            | WSCommand::PlaylistPrevious
+            | WSCommand::PlaylistGoto { .. }
+            | WSCommand::PlaylistClear { .. }
+            | WSCommand::PlaylistRemove { .. }
+            | WSCommand::PlaylistMove { .. }
+            | WSCommand::PlaylistMoveMany { .. }
+            | WSCommand::PlaylistMoveToTop { .. }
+            | WSCommand::PlaylistMoveToBottom { .. }
+            | WSCommand::PlaylistMoveAfterCurrent { .. }
+            | WSCommand::Shuffle => StateDelta::Playlist,
+            WSCommand::Volume { .. } => StateDelta::Volume,
+            WSCommand::TogglePlayback => StateDelta::Paused,
+            WSCommand::Time { .. } => StateDelta::Position,
+            WSCommand::SetSubtitleTrack { .. } => StateDelta::SubtitleTracks,
+            WSCommand::SetLooping { .. } => StateDelta::Looping,
+            WSCommand::SetPartyMode { .. }
+            | WSCommand::SetAutoplay { .. }
+            | WSCommand::SubtitleDelay { .. }
+            | WSCommand::AudioDelay { .. }
+            | WSCommand::Batch { .. }
+            | WSCommand::Hello { .. } => StateDelta::Nothing,
+        }
+    }
+
+    /// The events a client would get for the change, or none if the state can not be read.
+    async fn read(self, mpv: &Mpv, volume_engine: &VolumeTransitionEngine) -> Vec<OutgoingEvent> {
+        let player = volume_engine.player();
+        let event = match self {
+            StateDelta::Playlist => mpv
+                .get_playlist()
+                .await
+                .map(|playlist| {
+                    let queue = player.queue_state();
+                    OutgoingEvent::PlaylistChanged(with_held_items(
+                        queue,
+                        playlist_items(queue, playlist),
+                    ))
+                })
+                .map_err(anyhow::Error::from),
+            StateDelta::Volume => player.get_volume().await.map(OutgoingEvent::Volume),
+            StateDelta::Paused => player
+                .is_playing()
+                .await
+                .map(|playing| OutgoingEvent::Paused(!playing)),
+            StateDelta::Position => mpv
+                .get_property("percent-pos")
+                .await
+                .map(OutgoingEvent::Position)
+                .map_err(anyhow::Error::from),
+            StateDelta::SubtitleTracks => match mpv.get_property_value("track-list").await {
+                Ok(Some(Value::Array(tracks))) => {
+                    Ok(OutgoingEvent::SubtitleTracks(subtitle_tracks(tracks)))
+                }
+                Ok(_) => Ok(OutgoingEvent::SubtitleTracks(vec![])),
+                Err(e) => Err(e.into()),
+            },
+            StateDelta::Looping => player.is_looping().await.map(OutgoingEvent::Looping),
+            StateDelta::Nothing => return vec![],
+        };
+        match event {
+            Ok(event) => vec![event],
+            Err(e) => {
+                log::debug!("Failed to read back the state after a command: {:#}", e);
+                vec![]
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn execute_batch(
+    commands: Vec<WSCommand>,
+    mpv: &Mpv,
+    volume_engine: &VolumeTransitionEngine,
+    clear_guard: &PlaylistClearGuard,
+    party: &PartyMode,
+    autoplay: &Autoplay,
+    resolvers: &ResolverChain,
+    pending: &PendingLoads,
+    lock: &PlaylistLock,
+    user: Option<&AuthenticatedUser>,
+    charge: Option<&QuotaCharge>,
+) -> Value {
+    let mut results = Vec::with_capacity(commands.len());
+    let mut failed = false;
+
+    for command in commands {
+        let delta = StateDelta::of(&command);
+        if failed {
+            results.push(json!({
+                "success": false,
+                "error": "Skipped because an earlier command in the batch failed",
+            }));
+            continue;
+        }
+
+        match execute_command(
+            command,
+            mpv,
+            volume_engine,
+            clear_guard,
+            party,
+            autoplay,
+            resolvers,
+            pending,
+            lock,
+            user,
+            charge,
+        )
+        .await
+        {
+            Ok(value) => results.push(json!({
+                "success": true,
+                "value": value,
+                "delta": delta.read(mpv, volume_engine).await,
+            })),
+            Err(e) => {
+                log::debug!("Command in batch failed: {:#}", e);
+                failed = true;
+                results.push(json!({
+                    "success": false,
+                    "error": format!("{:#}", e),
+                }));
+            }
+        }
+    }
+
+    Value::Array(results)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn execute_command(
+    command: WSCommand,
+    mpv: &Mpv,
+    volume_engine: &VolumeTransitionEngine,
+    clear_guard: &PlaylistClearGuard,
+    party: &PartyMode,
+    autoplay: &Autoplay,
+    resolvers: &ResolverChain,
+    pending: &PendingLoads,
+    lock: &PlaylistLock,
+    user: Option<&AuthenticatedUser>,
+    charge: Option<&QuotaCharge>,
+) -> anyhow::Result<Option<Value>> {
     match command {
         // WSCommand::Subscribe { property } => {
         //     mpv.observe_property(channel_id, &property).await?;
@@ -432,24 +1100,60 @@ async fn handle_message(
         //     mpv.unobserve_property(channel_id).await?;
         //     Ok(None)
         // }
-        WSCommand::Load { urls } => {
+        WSCommand::Load {
+            urls,
+            start,
+            end,
+            title,
+            note,
+            queued_by,
+            resume,
+            ttl_hours,
+            not_before,
+            priority,
+        } => {
             for url in urls {
-                mpv.playlist_add(
+                base::loadfile(
+                    volume_engine.player().clone(),
+                    resolvers,
+                    pending,
                     &url,
-                    PlaylistAddTypeOptions::File,
-                    mpvipc_async::PlaylistAddOptions::Append,
+                    Default::default(),
+                    start.as_deref(),
+                    end.as_deref(),
+                    ItemState {
+                        note: ItemNote::new(
+                            title.as_deref(),
+                            note.as_deref(),
+                            queued_by.as_deref(),
+                        ),
+                        lane: Lane {
+                            priority,
+                            ..Lane::default()
+                        },
+                        ttl_hours,
+                        ..ItemState::default()
+                    },
+                    resume,
+                    not_before,
+                    user,
+                    user.is_some_and(AuthenticatedUser::is_admin),
+                    charge,
                 )
                 .await?;
             }
             Ok(None)
         }
+        WSCommand::Interject { url } => {
+            base::interject(volume_engine.clone(), &url, user, charge).await?;
+            Ok(None)
+        }
         WSCommand::TogglePlayback => {
-            mpv.set_playback(mpvipc_async::Switch::Toggle).await?;
+            volume_engine.set_playback(Switch::Toggle).await?;
             Ok(None)
         }
         WSCommand::Volume { volume } => {
-            mpv.set_volume(volume, NumberChangeOptions::Absolute)
-                .await?;
+            volume_engine.set_volume(volume).await?;
             Ok(None)
         }
         WSCommand::Time { time } => {
@@ -465,41 +1169,261 @@ async fn handle_message(
             Ok(None)
         }
         WSCommand::PlaylistGoto { position } => {
-            mpv.playlist_play_id(position).await?;
+            base::playlist_goto(volume_engine.player().clone(), position).await?;
             Ok(None)
         }
-        WSCommand::PlaylistClear => {
-            mpv.playlist_clear().await?;
+        WSCommand::PlaylistClear { force, token } => {
+            base::playlist_clear_locked(
+                volume_engine.player(),
+                clear_guard,
+                force,
+                token.as_deref(),
+                lock,
+            )
+            .await?;
             Ok(None)
         }
-
-        // FIXME: this could lead to a race condition between `playlist_remove_id` commands
-        WSCommand::PlaylistRemove { mut positions } => {
-            positions.sort();
-
-            for position in positions.iter().rev() {
-                mpv.playlist_remove_id(*position).await?;
-            }
-
+        WSCommand::PlaylistRemove { positions } => {
+            base::playlist_remove_locked(volume_engine.player(), &positions, lock).await?;
             Ok(None)
         }
-
         WSCommand::PlaylistMove { from, to } => {
-            mpv.playlist_move_id(from, to).await?;
+            base::playlist_move_many_locked(
+                volume_engine.player(),
+                &[PlaylistMove { from, to }],
+                lock,
+            )
+            .await?;
+            Ok(None)
+        }
+        WSCommand::PlaylistMoveMany { moves } => {
+            base::playlist_move_many_locked(volume_engine.player(), &moves, lock).await?;
+            Ok(None)
+        }
+        WSCommand::PlaylistMoveToTop { position } => {
+            base::playlist_move_to_locked(volume_engine.player(), position, MoveTarget::Top, lock)
+                .await?;
+            Ok(None)
+        }
+        WSCommand::PlaylistMoveToBottom { position } => {
+            base::playlist_move_to_locked(
+                volume_engine.player(),
+                position,
+                MoveTarget::Bottom,
+                lock,
+            )
+            .await?;
+            Ok(None)
+        }
+        WSCommand::PlaylistMoveAfterCurrent { position } => {
+            base::playlist_move_to_locked(
+                volume_engine.player(),
+                position,
+                MoveTarget::AfterCurrent,
+                lock,
+            )
+            .await?;
             Ok(None)
         }
         WSCommand::Shuffle => {
-            mpv.playlist_shuffle().await?;
+            base::shuffle_locked(volume_engine.player(), lock).await?;
             Ok(None)
         }
         WSCommand::SetSubtitleTrack { track } => {
             mpv.set_property("sid", track).await?;
             Ok(None)
         }
+        WSCommand::SetPartyMode { value } => {
+            party.set_enabled(value)?;
+            Ok(None)
+        }
+        WSCommand::SetAutoplay { value } => {
+            autoplay.set_enabled(value);
+            Ok(None)
+        }
         WSCommand::SetLooping { value } => {
             mpv.set_loop_playlist(if value { Switch::On } else { Switch::Off })
                 .await?;
             Ok(None)
         }
+        WSCommand::SubtitleDelay { ms, relative } => {
+            base::delay_set(volume_engine.player(), base::Delay::Subtitles, ms, relative).await?;
+            Ok(None)
+        }
+        WSCommand::AudioDelay { ms, relative } => {
+            base::delay_set(volume_engine.player(), base::Delay::Audio, ms, relative).await?;
+            Ok(None)
+        }
+        WSCommand::Batch { .. } => anyhow::bail!("Batches can not be nested"),
+        WSCommand::Hello { .. } => anyhow::bail!("Clients can not say hello in a batch"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::SinkExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite};
+
+    use super::*;
+    use crate::{
+        autoplay::AutoplayFilter,
+        player::{MpvPlayer, QueueState},
+        test_support::FakeMpv,
+        util::IdPool,
+    };
+
+    async fn receive(socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>) -> OutgoingMessage {
+        let message = tokio::time::timeout(Duration::from_secs(2), socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_against_fake_mpv() {
+        let mpv = FakeMpv::start();
+        let client = mpv.connect().await;
+        let (connection_counter_tx, _connection_counter_rx) = mpsc::channel(10);
+        let clients = ClientRegistry::default();
+        let router = websocket_api(
+            client.clone(),
+            VolumeTransitionEngine::new(Arc::new(MpvPlayer::new(client.clone())), false),
+            PlaylistClearGuard::new(None),
+            PartyMode::new(None, false),
+            Autoplay::new(AutoplayFilter::default(), false),
+            ResolverChain::new(vec![]),
+            PendingLoads::default(),
+            IdPoolHandle::spawn(IdPool::new_with_max_limit(10)),
+            connection_counter_tx,
+            clients.clone(),
+            Policies::default(),
+            ControlLock::default(),
+            PlaybackClock::spawn(client.clone()),
+            EventBroadcast::spawn(client.clone(), QueueState::default()),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<ClientAddr>(),
+            )
+            .await
+        });
+
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}/?name=test&version=1.0", addr))
+                .await
+                .unwrap();
+        let OutgoingMessage::InitialState(initial_state) = receive(&mut socket).await else {
+            panic!("Expected the initial state first");
+        };
+        assert_eq!(initial_state.volume, 50.0);
+        assert!(!initial_state.is_playing);
+        let connected = clients.list();
+        assert_eq!(connected.len(), 1);
+        assert_eq!(connected[0].name.as_deref(), Some("test"));
+        assert_eq!(connected[0].version.as_deref(), Some("1.0"));
+
+        let command = json!({"type": "volume", "volume": 30.0});
+        socket
+            .send(tungstenite::Message::text(command.to_string()))
+            .await
+            .unwrap();
+        mpv.wait_for(|mpv| mpv.property("volume") == Some(json!(30.0)))
+            .await;
+
+        // The response has the new volume, without waiting for the event.
+        let response = loop {
+            if let OutgoingMessage::Response(response) = receive(&mut socket).await {
+                break response;
+            }
+        };
+        assert_eq!(response["success"], true);
+        assert_eq!(
+            response["delta"],
+            json!([{"type": "volume", "value": 30.0}])
+        );
+
+        // Changes made by mpv itself reach the client as events.
+        mpv.set_property("mute", json!(true));
+        while receive(&mut socket).await != OutgoingMessage::Event(OutgoingEvent::Muted(true)) {}
+
+        let hello = json!({"type": "hello", "name": "renamed"});
+        socket
+            .send(tungstenite::Message::text(hello.to_string()))
+            .await
+            .unwrap();
+        mpv.wait_for(|_| clients.list()[0].name.as_deref() == Some("renamed"))
+            .await;
+        assert_eq!(clients.list()[0].version.as_deref(), Some("1.0"));
+
+        // Oversized messages are refused, without closing the connection.
+        let huge = json!({"type": "load", "urls": ["x".repeat(MAX_MESSAGE_SIZE)]});
+        socket
+            .send(tungstenite::Message::text(huge.to_string()))
+            .await
+            .unwrap();
+        let problem = loop {
+            if let OutgoingMessage::Response(response) = receive(&mut socket).await {
+                break response;
+            }
+        };
+        assert_eq!(problem["code"], "payload-too-large");
+
+        // Kicked clients are told why before the connection is closed.
+        assert!(clients.kick(clients.list()[0].id));
+        let message = loop {
+            match socket.next().await.unwrap().unwrap() {
+                tungstenite::Message::Close(frame) => break frame.unwrap(),
+                _ => continue,
+            }
+        };
+        assert_eq!(message.reason, "Kicked by an admin");
+        mpv.wait_for(|_| clients.list().is_empty()).await;
+    }
+
+    #[tokio::test]
+    async fn test_registration_dropped() {
+        let id_pool = IdPoolHandle::spawn(IdPool::default());
+        let clients = ClientRegistry::default();
+        let (connection_counter_tx, mut connection_counter_rx) = mpsc::channel(10);
+        let registration = |pooled_id, connected| Registration {
+            pooled_id,
+            id_pool: id_pool.clone(),
+            clients: clients.clone(),
+            connection_counter_tx: connection_counter_tx.clone(),
+            addr: ClientAddr::Unix,
+            connected,
+        };
+
+        // An upgrade that never completed only holds an id.
+        drop(registration(id_pool.request_id().await.unwrap(), false));
+
+        // A connection that ended partway, like when sending to it failed.
+        let pooled_id = id_pool.request_id().await.unwrap();
+        clients.connect(pooled_id.id, &ClientAddr::Unix, None, None);
+        drop(registration(pooled_id, true));
+        assert!(clients.list().is_empty());
+
+        let disconnected =
+            tokio::time::timeout(Duration::from_secs(2), connection_counter_rx.recv())
+                .await
+                .unwrap();
+        assert!(matches!(disconnected, Some(ConnectionEvent::Disconnected)));
+        let mut id_count = id_pool.get_id_count_watch_receiver();
+        tokio::time::timeout(
+            Duration::from_secs(2),
+            id_count.wait_for(|count| *count == 0),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(connection_counter_rx.try_recv().is_err());
     }
 }
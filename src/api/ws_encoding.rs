@@ -0,0 +1,81 @@
+use axum::extract::ws::Message;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// How websocket messages are encoded, chosen by the client when connecting, like
+/// `/ws?encoding=msgpack`. The binary encodings are smaller and quicker to parse for
+/// constrained clients, and carry the same messages as JSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WsEncoding {
+    /// Text messages with JSON.
+    #[default]
+    Json,
+    /// Binary messages with MessagePack.
+    Msgpack,
+    /// Binary messages with CBOR.
+    Cbor,
+}
+
+impl WsEncoding {
+    pub fn encode(self, message: &impl Serialize) -> anyhow::Result<Message> {
+        Ok(match self {
+            WsEncoding::Json => Message::Text(serde_json::to_string(message)?.into()),
+            // Structs are encoded as maps, so the messages look the same as in JSON.
+            WsEncoding::Msgpack => Message::Binary(rmp_serde::to_vec_named(message)?.into()),
+            WsEncoding::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(message, &mut bytes)?;
+                Message::Binary(bytes.into())
+            }
+        })
+    }
+
+    /// Decode a message from the client, which is text with JSON and binary otherwise.
+    pub fn decode(self, message: &Message) -> anyhow::Result<Value> {
+        match (self, message) {
+            (WsEncoding::Json, Message::Text(text)) => Ok(serde_json::from_str(text)?),
+            (WsEncoding::Msgpack, Message::Binary(bytes)) => Ok(rmp_serde::from_slice(bytes)?),
+            (WsEncoding::Cbor, Message::Binary(bytes)) => Ok(ciborium::from_reader(&bytes[..])?),
+            (encoding, message) => anyhow::bail!(
+                "Unexpected message type for the {:?} encoding: {:?}",
+                encoding,
+                message
+            ),
+        }
+    }
+}
+
+/// The size of a message with data in it, in bytes.
+pub fn message_size(message: &Message) -> usize {
+    match message {
+        Message::Text(text) => text.len(),
+        Message::Binary(bytes) => bytes.len(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let command = json!({"type": "volume", "volume": 30.0, "urls": ["a", "b"]});
+        for encoding in [WsEncoding::Json, WsEncoding::Msgpack, WsEncoding::Cbor] {
+            let message = encoding.encode(&command).unwrap();
+            assert_eq!(
+                matches!(message, Message::Binary(_)),
+                encoding != WsEncoding::Json
+            );
+            assert_eq!(encoding.decode(&message).unwrap(), command);
+        }
+        assert!(
+            WsEncoding::Msgpack
+                .decode(&Message::Text("{}".into()))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use axum::extract::ws::{CloseFrame, Message};
+use tokio::sync::Notify;
+
+/// How many messages can wait to be sent to one client before some are dropped.
+pub const OUTBOX_CAPACITY: usize = 256;
+
+/// The outbox is full of messages that can not be dropped, so the client can not keep up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overflow;
+
+#[derive(Debug)]
+struct Queued {
+    key: Option<&'static str>,
+    message: Message,
+}
+
+#[derive(Debug, Default)]
+struct OutboxState {
+    queue: VecDeque<Queued>,
+    closed: bool,
+}
+
+/// The messages waiting to be sent to one websocket client, so that a slow client only
+/// holds up itself and not the events meant for it.
+///
+/// Messages with a key, like the playback position, only matter until the next one with the
+/// same key. An older one still waiting is dropped when a new one is queued, and they are
+/// dropped first when the outbox is full.
+#[derive(Debug)]
+pub struct Outbox {
+    capacity: usize,
+    state: Mutex<OutboxState>,
+    notify: Notify,
+}
+
+impl Outbox {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(OutboxState::default()),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Queue a message, returning how many messages were dropped to make room for it.
+    pub fn push(&self, key: Option<&'static str>, message: Message) -> Result<usize, Overflow> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Ok(0);
+        }
+
+        let mut dropped = 0;
+        if let Some(key) = key
+            && let Some(older) = state
+                .queue
+                .iter()
+                .position(|queued| queued.key == Some(key))
+        {
+            state.queue.remove(older);
+            dropped += 1;
+        }
+        if state.queue.len() >= self.capacity {
+            match state.queue.iter().position(|queued| queued.key.is_some()) {
+                Some(oldest) => {
+                    state.queue.remove(oldest);
+                    dropped += 1;
+                }
+                None if key.is_some() => return Ok(dropped + 1),
+                None => return Err(Overflow),
+            }
+        }
+        state.queue.push_back(Queued { key, message });
+        drop(state);
+
+        self.notify.notify_one();
+        Ok(dropped)
+    }
+
+    /// Drop everything still waiting, and send `frame` as the last message.
+    pub fn close(&self, frame: CloseFrame) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.clear();
+        state.queue.push_back(Queued {
+            key: None,
+            message: Message::Close(Some(frame)),
+        });
+        state.closed = true;
+        drop(state);
+
+        self.notify.notify_one();
+    }
+
+    /// Stop taking messages, sending the ones still waiting.
+    pub fn finish(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_one();
+    }
+
+    /// Wait for the next message to send, or `None` once the outbox is closed and empty.
+    pub async fn next(&self) -> Option<Message> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(queued) = state.queue.pop_front() {
+                    return Some(queued.message);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::ws::close_code;
+
+    use super::*;
+
+    async fn drain(outbox: &Outbox) -> Vec<Message> {
+        outbox.finish();
+        let mut messages = Vec::new();
+        while let Some(message) = outbox.next().await {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_outbox() {
+        let outbox = Outbox::new(3);
+        assert_eq!(outbox.push(Some("position"), Message::text("p1")), Ok(0));
+        assert_eq!(outbox.push(None, Message::text("a")), Ok(0));
+        // The older position is replaced, and the newest one is sent after the rest.
+        assert_eq!(outbox.push(Some("position"), Message::text("p2")), Ok(1));
+        assert_eq!(outbox.push(Some("volume"), Message::text("v1")), Ok(0));
+        // When full, the oldest message that can be dropped makes room.
+        assert_eq!(outbox.push(None, Message::text("b")), Ok(1));
+        assert_eq!(
+            drain(&outbox).await,
+            vec![Message::text("a"), Message::text("v1"), Message::text("b")]
+        );
+
+        let outbox = Outbox::new(2);
+        outbox.push(None, Message::text("a")).unwrap();
+        outbox.push(None, Message::text("b")).unwrap();
+        assert_eq!(outbox.push(Some("position"), Message::text("p")), Ok(1));
+        assert_eq!(outbox.push(None, Message::text("c")), Err(Overflow));
+
+        let frame = CloseFrame {
+            code: close_code::AGAIN,
+            reason: "Too slow".into(),
+        };
+        outbox.close(frame.clone());
+        assert_eq!(outbox.push(None, Message::text("d")), Ok(0));
+        assert_eq!(drain(&outbox).await, vec![Message::Close(Some(frame))]);
+    }
+}
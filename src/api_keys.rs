@@ -0,0 +1,255 @@
+//! Keys for bots, like the IRC bridge or the infoscreen, made and revoked at runtime through
+//! `/api/admin/keys` instead of being configured.
+//!
+//! A request with a key as `Authorization: Bearer <key>` is from an [`AuthenticatedUser`]
+//! named after the label of the key, with the [scopes](Scope) of the key. Only a hash of each
+//! key is kept, in the storage so they survive restarts, and in memory since requests are
+//! checked against them.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::http::{HeaderMap, header};
+use ring::digest;
+use serde::Serialize;
+
+use crate::{
+    api::ApiError, history::now, oidc::AuthenticatedUser, scopes::Scope, storage::StorageHandle,
+};
+
+/// The longest label a key can have, in characters.
+const MAX_LABEL_LENGTH: usize = 100;
+
+/// Keys start with this, so that they are easy to recognize when they leak.
+const KEY_PREFIX: &str = "greg_";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ApiKey {
+    pub id: u64,
+    /// Who the key is for, like `irc-bridge`.
+    pub label: String,
+    pub scopes: Vec<Scope>,
+    /// Seconds since the unix epoch.
+    pub created_at: u64,
+    /// The SHA-256 of the key, in hex. The key itself is only shown when it is made.
+    #[serde(skip)]
+    pub hash: String,
+}
+
+/// A key that has not been stored yet, and so has no id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewApiKey {
+    pub label: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: u64,
+    pub hash: String,
+}
+
+impl NewApiKey {
+    pub fn with_id(self, id: u64) -> ApiKey {
+        ApiKey {
+            id,
+            label: self.label,
+            scopes: self.scopes,
+            created_at: self.created_at,
+            hash: self.hash,
+        }
+    }
+}
+
+/// A key that was just made, with the key itself.
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+/// The scopes of a key as they are stored, like `queue:add,playback:control`.
+pub fn scopes_column(scopes: &[Scope]) -> String {
+    let scopes: Vec<_> = scopes.iter().map(|scope| scope.as_str()).collect();
+    scopes.join(",")
+}
+
+/// Scopes that are not known are left out, so that a key never gets more than it had.
+pub fn scopes_from_column(scopes: &str) -> Vec<Scope> {
+    scopes
+        .split(',')
+        .filter_map(|scope| scope.parse().ok())
+        .collect()
+}
+
+fn hash_key(key: &str) -> String {
+    digest::digest(&digest::SHA256, key.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn check_label(label: &str) -> Result<String, ApiError> {
+    let label = label.trim();
+    if label.is_empty() {
+        return Err(ApiError::InvalidArgument(
+            "The label can not be empty".to_string(),
+        ));
+    }
+    if label.chars().count() > MAX_LABEL_LENGTH {
+        return Err(ApiError::InvalidArgument(format!(
+            "The label can be at most {} characters",
+            MAX_LABEL_LENGTH
+        )));
+    }
+    Ok(label.to_string())
+}
+
+fn not_found(id: u64) -> anyhow::Error {
+    ApiError::NotFound(format!("No API key with id {}", id)).into()
+}
+
+/// The API keys that can be used, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct ApiKeys {
+    storage: StorageHandle,
+    keys: Arc<Mutex<BTreeMap<u64, ApiKey>>>,
+}
+
+impl ApiKeys {
+    pub fn load(storage: StorageHandle) -> anyhow::Result<Self> {
+        let keys = storage
+            .api_keys()?
+            .into_iter()
+            .map(|key| (key.id, key))
+            .collect();
+        Ok(Self {
+            storage,
+            keys: Arc::new(Mutex::new(keys)),
+        })
+    }
+
+    /// Make a key for `label` with `scopes`. This is the only time the key itself is known.
+    pub fn create(&self, label: &str, scopes: Vec<Scope>) -> anyhow::Result<CreatedApiKey> {
+        let label = check_label(label)?;
+        if scopes.is_empty() {
+            return Err(
+                ApiError::InvalidArgument("A key needs at least one scope".to_string()).into(),
+            );
+        }
+        let key = format!("{}{:032x}", KEY_PREFIX, rand::random::<u128>());
+        let api_key = self.storage.add_api_key(&NewApiKey {
+            label,
+            scopes,
+            created_at: now(),
+            hash: hash_key(&key),
+        })?;
+        log::info!(
+            "Made API key {} for {} ({})",
+            api_key.id,
+            api_key.label,
+            scopes_column(&api_key.scopes)
+        );
+        self.keys
+            .lock()
+            .unwrap()
+            .insert(api_key.id, api_key.clone());
+        Ok(CreatedApiKey { api_key, key })
+    }
+
+    pub fn relabel(&self, id: u64, label: &str) -> anyhow::Result<ApiKey> {
+        let label = check_label(label)?;
+        if !self.storage.set_api_key_label(id, &label)? {
+            return Err(not_found(id));
+        }
+        let mut keys = self.keys.lock().unwrap();
+        let api_key = keys.get_mut(&id).ok_or_else(|| not_found(id))?;
+        api_key.label = label;
+        Ok(api_key.clone())
+    }
+
+    /// Stop accepting a key. Returns whether there was such a key.
+    pub fn revoke(&self, id: u64) -> anyhow::Result<bool> {
+        self.storage.delete_api_key(id)?;
+        let revoked = self.keys.lock().unwrap().remove(&id);
+        if let Some(api_key) = &revoked {
+            log::info!("Revoked API key {} for {}", id, api_key.label);
+        }
+        Ok(revoked.is_some())
+    }
+
+    /// Every key, in the order they were made.
+    pub fn list(&self) -> Vec<ApiKey> {
+        self.keys.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Who the key given as `Authorization: Bearer <key>` in `headers` is for, if any.
+    pub fn user(&self, headers: &HeaderMap) -> Option<AuthenticatedUser> {
+        let key = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .filter(|key| key.starts_with(KEY_PREFIX))?;
+        let hash = hash_key(key);
+        let keys = self.keys.lock().unwrap();
+        let api_key = keys.values().find(|api_key| api_key.hash == hash)?;
+        Some(AuthenticatedUser {
+            subject: format!("api-key:{}", api_key.id),
+            name: api_key.label.clone(),
+            scopes: api_key.scopes.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_api_keys() {
+        let storage: StorageHandle = Arc::new(MemoryStorage::new());
+        let keys = ApiKeys::load(storage.clone()).unwrap();
+        let with_key = |key: &str| {
+            let mut headers = HeaderMap::new();
+            let value = HeaderValue::from_str(&format!("Bearer {}", key)).unwrap();
+            headers.insert(header::AUTHORIZATION, value);
+            headers
+        };
+
+        let bridge = keys.create("irc-bridge", vec![Scope::QueueAdd]).unwrap();
+        let admin = keys.create("infoscreen", vec![Scope::Admin]).unwrap();
+        assert!(keys.create("  ", vec![Scope::QueueAdd]).is_err());
+        assert!(keys.create("nothing", vec![]).is_err());
+        let user = keys.user(&with_key(&bridge.key)).unwrap();
+        assert_eq!(user.name, "irc-bridge");
+        assert_eq!(user.scopes, [Scope::QueueAdd]);
+        assert!(!user.is_admin());
+        assert!(keys.user(&with_key(&admin.key)).unwrap().is_admin());
+        assert!(keys.user(&with_key("greg_wrong")).is_none());
+        assert!(keys.user(&HeaderMap::new()).is_none());
+
+        // Keys are kept in the storage, without the keys themselves.
+        keys.relabel(bridge.api_key.id, "irc").unwrap();
+        assert!(keys.relabel(100, "irc").is_err());
+        let loaded = ApiKeys::load(storage.clone()).unwrap();
+        assert_eq!(loaded.user(&with_key(&bridge.key)).unwrap().name, "irc");
+        assert!(
+            !serde_json::to_string(&loaded.list())
+                .unwrap()
+                .contains(&bridge.api_key.hash)
+        );
+        assert_eq!(
+            scopes_from_column(&scopes_column(&Scope::EVERYONE)),
+            Scope::EVERYONE
+        );
+        assert_eq!(scopes_from_column("admin:*,root"), [Scope::Admin]);
+
+        assert!(loaded.revoke(bridge.api_key.id).unwrap());
+        assert!(!loaded.revoke(bridge.api_key.id).unwrap());
+        assert!(loaded.user(&with_key(&bridge.key)).is_none());
+        assert_eq!(storage.api_keys().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,299 @@
+//! Autoplay: when the playlist runs out, keep playing videos related to the last one.
+//!
+//! Related videos come from the YouTube mix of the last played video, as listed by yt-dlp.
+//! Items added by autoplay are remembered, so they can be flagged in the playlist and history.
+
+use std::{
+    collections::HashSet,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use futures::StreamExt;
+use mpvipc_async::{Event, Mpv};
+use tokio::{process::Command, sync::Notify};
+use url::Url;
+
+use crate::{history::PlayHistory, party, player::QueueState};
+
+/// How many related videos are fetched from a YouTube mix.
+const RELATED_COUNT: usize = 25;
+
+/// How long yt-dlp gets to list the related videos.
+const RELATED_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many of the items added by autoplay are remembered.
+const MAX_PICKED: usize = 1000;
+
+/// A video related to another one, as listed by yt-dlp.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelatedVideo {
+    pub url: String,
+    /// In seconds, if known.
+    pub duration: Option<f64>,
+    pub title: Option<String>,
+    pub channel: Option<String>,
+}
+
+/// The video id of a canonical YouTube watch url.
+pub fn youtube_video_id(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    if url.host_str() != Some("www.youtube.com") {
+        return None;
+    }
+    url.query_pairs()
+        .find(|(key, _)| key == "v")
+        .map(|(_, id)| id.into_owned())
+}
+
+/// Parse a line printed by yt-dlp with the template used in [`related_videos`].
+fn parse_related_video(line: &str) -> Option<RelatedVideo> {
+    let mut fields = line
+        .split('\t')
+        .map(|field| Some(field.trim()).filter(|field| !field.is_empty() && *field != "NA"));
+    let url = fields.next()??.to_string();
+    if !url.starts_with("http") {
+        return None;
+    }
+
+    Some(RelatedVideo {
+        url,
+        duration: fields.next().flatten().and_then(|d| d.parse().ok()),
+        title: fields.next().flatten().map(str::to_string),
+        channel: fields.next().flatten().map(str::to_string),
+    })
+}
+
+/// The videos in the YouTube mix of `video_id`, most related first.
+pub async fn related_videos(
+    yt_dlp_path: &str,
+    video_id: &str,
+) -> anyhow::Result<Vec<RelatedVideo>> {
+    let mix = format!(
+        "https://www.youtube.com/watch?v={}&list=RD{}",
+        video_id, video_id
+    );
+    let output = tokio::time::timeout(
+        RELATED_TIMEOUT,
+        Command::new(yt_dlp_path)
+            .args(["--flat-playlist", "--no-warnings"])
+            .args(["--print", "%(url)s\t%(duration)s\t%(title)s\t%(channel)s"])
+            .args(["--playlist-end", &RELATED_COUNT.to_string()])
+            .arg("--")
+            .arg(&mix)
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .context("yt-dlp timed out")?
+    .context("Failed to run yt-dlp")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "yt-dlp exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_related_video)
+        .collect())
+}
+
+/// Which related videos autoplay may pick.
+#[derive(Debug, Clone, Default)]
+pub struct AutoplayFilter {
+    /// In seconds.
+    pub min_duration: Option<f64>,
+    /// In seconds.
+    pub max_duration: Option<f64>,
+    /// Videos whose title, channel or url contains any of these, ignoring case, are skipped.
+    pub denylist: Vec<String>,
+}
+
+impl AutoplayFilter {
+    fn allows(&self, video: &RelatedVideo) -> bool {
+        if let Some(duration) = video.duration
+            && (self.min_duration.is_some_and(|min| duration < min)
+                || self.max_duration.is_some_and(|max| duration > max))
+        {
+            return false;
+        }
+
+        let haystacks = [
+            Some(&video.url),
+            video.title.as_ref(),
+            video.channel.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|text| text.to_lowercase())
+        .collect::<Vec<_>>();
+        !self.denylist.iter().any(|word| {
+            let word = word.to_lowercase();
+            haystacks.iter().any(|text| text.contains(&word))
+        })
+    }
+}
+
+/// A shared handle for turning autoplay on and off, and for checking what it has added.
+#[derive(Debug, Clone, Default)]
+pub struct Autoplay {
+    filter: Arc<AutoplayFilter>,
+    enabled: Arc<AtomicBool>,
+    changed: Arc<Notify>,
+    /// The urls added by autoplay, oldest first.
+    picked: Arc<Mutex<Vec<String>>>,
+}
+
+impl Autoplay {
+    pub fn new(filter: AutoplayFilter, enabled: bool) -> Self {
+        Self {
+            filter: Arc::new(filter),
+            enabled: Arc::new(AtomicBool::new(enabled)),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        log::info!("Autoplay {}", if enabled { "enabled" } else { "disabled" });
+        self.enabled.store(enabled, Ordering::Relaxed);
+        self.changed.notify_one();
+    }
+
+    /// Whether the item at `url` was added by autoplay.
+    pub fn is_autoplayed(&self, url: &str) -> bool {
+        self.picked
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|picked| picked == url)
+    }
+
+    fn remember(&self, url: &str) {
+        let mut picked = self.picked.lock().unwrap();
+        if picked.len() == MAX_PICKED {
+            picked.remove(0);
+        }
+        picked.push(url.to_string());
+    }
+
+    /// The most related video that passes the filter, and hasn't been played recently.
+    fn choose<'a>(
+        &self,
+        related: &'a [RelatedVideo],
+        recent: &[String],
+    ) -> Option<&'a RelatedVideo> {
+        let recent: HashSet<&str> = recent.iter().map(String::as_str).collect();
+        related
+            .iter()
+            .find(|video| !recent.contains(video.url.as_str()) && self.filter.allows(video))
+    }
+}
+
+/// Add a related video to the playlist, if autoplay is on and nothing is left after the
+/// current item.
+async fn top_up(
+    mpv: &Mpv,
+    queue: &QueueState,
+    autoplay: &Autoplay,
+    yt_dlp_path: &str,
+    history: &PlayHistory,
+) -> anyhow::Result<()> {
+    if !autoplay.is_enabled() {
+        return Ok(());
+    }
+    let Some((_lock, recent)) = party::lock_empty_queue(mpv, queue, history).await? else {
+        return Ok(());
+    };
+    let Some(video_id) = recent.iter().find_map(|path| youtube_video_id(path)) else {
+        return Ok(());
+    };
+
+    let related = related_videos(yt_dlp_path, &video_id).await?;
+    let Some(video) = autoplay.choose(&related, &recent) else {
+        log::info!("Autoplay found nothing related to '{}' to play", video_id);
+        return Ok(());
+    };
+
+    log::info!(
+        "Autoplay picked '{}'",
+        video.title.as_deref().unwrap_or(&video.url)
+    );
+    autoplay.remember(&video.url);
+    mpv.run_command_raw("loadfile", &[&video.url, "append-play"])
+        .await?;
+    Ok(())
+}
+
+/// Keep the playlist going with related videos while autoplay is on, until mpv goes away.
+pub async fn run_autoplay(
+    mpv: Mpv,
+    queue: QueueState,
+    autoplay: Autoplay,
+    yt_dlp_path: String,
+    history: PlayHistory,
+) -> anyhow::Result<()> {
+    let mut event_stream = mpv.get_event_stream().await;
+
+    loop {
+        tokio::select! {
+            event = event_stream.next() => match event.context("Lost the mpv events")?? {
+                Event::StartFile { .. } | Event::Idle => {}
+                Event::Shutdown => return Ok(()),
+                _ => continue,
+            },
+            _ = autoplay.changed.notified() => {}
+        }
+
+        if let Err(e) = top_up(&mpv, &queue, &autoplay, &yt_dlp_path, &history).await {
+            log::warn!("Autoplay could not pick the next item: {:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose() {
+        let related: Vec<RelatedVideo> = [
+            "https://www.youtube.com/watch?v=aaaaaaaaaaa\t212\tSong A\tBand",
+            "https://www.youtube.com/watch?v=bbbbbbbbbbb\t7200\tSong B (10 hour version)\tBand",
+            "https://www.youtube.com/watch?v=ccccccccccc\t180\tSong C [Sponsored]\tBand",
+            "https://www.youtube.com/watch?v=ddddddddddd\tNA\tSong D\tNA",
+        ]
+        .into_iter()
+        .filter_map(parse_related_video)
+        .collect();
+        assert_eq!(related[3].duration, None);
+        assert_eq!(related[3].channel, None);
+
+        let autoplay = Autoplay::new(
+            AutoplayFilter {
+                min_duration: None,
+                max_duration: Some(900.0),
+                denylist: vec!["sponsored".to_string()],
+            },
+            true,
+        );
+        let recent = vec![related[0].url.clone()];
+        assert_eq!(autoplay.choose(&related, &recent), Some(&related[3]));
+
+        assert_eq!(
+            youtube_video_id("https://www.youtube.com/watch?v=dQw4w9WgXcQ").as_deref(),
+            Some("dQw4w9WgXcQ")
+        );
+    }
+}
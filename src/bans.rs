@@ -0,0 +1,148 @@
+//! Addresses that are not let in, for when someone's script goes haywire.
+//!
+//! Bans are kept in the storage, so they survive restarts, and in memory, since every
+//! request is checked against them.
+
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::{api::ApiError, history::now, server::ClientAddr, storage::StorageHandle};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ban {
+    pub address: IpAddr,
+    /// Seconds since the unix epoch.
+    pub banned_at: u64,
+    /// When the ban is lifted, or never if not given.
+    pub expires_at: Option<u64>,
+    pub reason: Option<String>,
+}
+
+impl Ban {
+    fn is_active(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+}
+
+/// The banned addresses.
+#[derive(Debug, Clone)]
+pub struct BanList {
+    storage: StorageHandle,
+    bans: Arc<Mutex<BTreeMap<IpAddr, Ban>>>,
+}
+
+impl BanList {
+    /// Load the bans from `storage`, forgetting those that have expired.
+    pub fn load(storage: StorageHandle) -> anyhow::Result<Self> {
+        let now = now();
+        let mut bans = BTreeMap::new();
+        for ban in storage.bans()? {
+            if ban.is_active(now) {
+                bans.insert(ban.address, ban);
+            } else {
+                storage.unban(&ban.address)?;
+            }
+        }
+        Ok(Self {
+            storage,
+            bans: Arc::new(Mutex::new(bans)),
+        })
+    }
+
+    pub fn is_banned(&self, address: &IpAddr) -> bool {
+        let now = now();
+        self.bans
+            .lock()
+            .unwrap()
+            .get(address)
+            .is_some_and(|ban| ban.is_active(now))
+    }
+
+    /// Ban `address` for `ttl`, or until it is unbanned if not given. Banning an address
+    /// again replaces its ban.
+    pub fn ban(
+        &self,
+        address: IpAddr,
+        ttl: Option<Duration>,
+        reason: Option<String>,
+    ) -> anyhow::Result<Ban> {
+        let now = now();
+        let ban = Ban {
+            address,
+            banned_at: now,
+            expires_at: ttl.map(|ttl| now + ttl.as_secs()),
+            reason,
+        };
+        self.storage.ban(&ban)?;
+        log::info!("Banned {} until {:?}", address, ban.expires_at);
+        self.bans.lock().unwrap().insert(address, ban.clone());
+        Ok(ban)
+    }
+
+    /// Returns whether the address was banned.
+    pub fn unban(&self, address: &IpAddr) -> anyhow::Result<bool> {
+        self.storage.unban(address)?;
+        let ban = self.bans.lock().unwrap().remove(address);
+        Ok(ban.is_some_and(|ban| ban.is_active(now())))
+    }
+
+    /// The bans that have not expired, by address.
+    pub fn list(&self) -> Vec<Ban> {
+        let now = now();
+        let mut bans = self.bans.lock().unwrap();
+        bans.retain(|_, ban| ban.is_active(now));
+        bans.values().cloned().collect()
+    }
+}
+
+/// Reject every request from a banned address, including websocket upgrades.
+pub async fn reject_banned(State(bans): State<BanList>, request: Request, next: Next) -> Response {
+    let banned = match request.extensions().get::<ConnectInfo<ClientAddr>>() {
+        Some(ConnectInfo(ClientAddr::Tcp(addr))) => bans.is_banned(&addr.ip().to_canonical()),
+        _ => false,
+    };
+    if banned {
+        return ApiError::PolicyViolation("This address is banned".to_string()).into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_ban_list() {
+        let storage: StorageHandle = Arc::new(MemoryStorage::new());
+        let bans = BanList::load(storage.clone()).unwrap();
+        let (a, b): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "::1".parse().unwrap());
+
+        bans.ban(a, None, Some("spam".to_string())).unwrap();
+        bans.ban(b, Some(Duration::ZERO), None).unwrap();
+        assert!(bans.is_banned(&a));
+        assert!(!bans.is_banned(&b));
+        assert_eq!(bans.list().len(), 1);
+
+        // Bans are kept in the storage, and expired ones are forgotten on loading.
+        let loaded = BanList::load(storage.clone()).unwrap();
+        assert!(loaded.is_banned(&a));
+        assert_eq!(storage.bans().unwrap().len(), 1);
+
+        assert!(loaded.unban(&a).unwrap());
+        assert!(!loaded.unban(&a).unwrap());
+        assert!(!loaded.is_banned(&a));
+        assert!(storage.bans().unwrap().is_empty());
+    }
+}
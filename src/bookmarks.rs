@@ -0,0 +1,146 @@
+//! Named positions within media, like where each topic starts in a lecture recording.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{api::ApiError, history::now, storage::StorageHandle, util::parse_timestamp};
+
+/// The longest label a bookmark can have, in characters.
+const MAX_LABEL_LENGTH: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub id: u64,
+    pub url: String,
+    /// Seconds into the item.
+    pub position: f64,
+    pub label: String,
+    /// Seconds since the unix epoch.
+    pub created_at: u64,
+}
+
+/// A bookmark that has not been stored yet, and so has no id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewBookmark {
+    pub url: String,
+    pub position: f64,
+    pub label: String,
+    pub created_at: u64,
+}
+
+impl NewBookmark {
+    pub fn with_id(self, id: u64) -> Bookmark {
+        Bookmark {
+            id,
+            url: self.url,
+            position: self.position,
+            label: self.label,
+            created_at: self.created_at,
+        }
+    }
+}
+
+/// Bookmarks, kept in the storage.
+#[derive(Debug, Clone)]
+pub struct Bookmarks {
+    storage: StorageHandle,
+}
+
+fn not_found(id: u64) -> anyhow::Error {
+    ApiError::NotFound(format!("No bookmark with id {}", id)).into()
+}
+
+impl Bookmarks {
+    pub fn new(storage: StorageHandle) -> Self {
+        Self { storage }
+    }
+
+    /// Bookmark `position`, a timestamp like `1:12` or `72.5`, in the item at `url`.
+    pub fn add(&self, url: &str, position: &str, label: &str) -> anyhow::Result<Bookmark> {
+        let url = url.trim();
+        if url.is_empty() {
+            return Err(ApiError::InvalidArgument("The url can not be empty".to_string()).into());
+        }
+        let label = label.trim();
+        if label.is_empty() || label.chars().count() > MAX_LABEL_LENGTH {
+            return Err(ApiError::InvalidArgument(format!(
+                "The label must be between 1 and {} characters long",
+                MAX_LABEL_LENGTH
+            ))
+            .into());
+        }
+        let Some(position) = parse_timestamp(position) else {
+            return Err(ApiError::InvalidArgument(format!(
+                "Invalid position timestamp '{}'",
+                position
+            ))
+            .into());
+        };
+
+        let bookmark = self.storage.add_bookmark(&NewBookmark {
+            url: url.to_string(),
+            position,
+            label: label.to_string(),
+            created_at: now(),
+        })?;
+        log::info!(
+            "Bookmarked '{}' at {} seconds into {}",
+            bookmark.label,
+            bookmark.position,
+            bookmark.url
+        );
+        Ok(bookmark)
+    }
+
+    pub fn get(&self, id: u64) -> anyhow::Result<Bookmark> {
+        self.storage.bookmark(id)?.ok_or_else(|| not_found(id))
+    }
+
+    /// The bookmarks in the item at `url`, or every bookmark if no url is given.
+    pub fn list(&self, url: Option<&str>) -> anyhow::Result<Vec<Bookmark>> {
+        let mut bookmarks = self.storage.bookmarks(url.map(str::trim))?;
+        bookmarks.sort_by(|a, b| a.url.cmp(&b.url).then(a.position.total_cmp(&b.position)));
+        Ok(bookmarks)
+    }
+
+    pub fn delete(&self, id: u64) -> anyhow::Result<()> {
+        if !self.storage.delete_bookmark(id)? {
+            return Err(not_found(id));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::storage::MemoryStorage;
+
+    use super::*;
+
+    #[test]
+    fn test_bookmarks() {
+        let bookmarks = Bookmarks::new(Arc::new(MemoryStorage::new()));
+        assert!(bookmarks.add("lecture.mp4", "1:00", " ").is_err());
+        assert!(bookmarks.add("lecture.mp4", "soon", "Intro").is_err());
+
+        let proofs = bookmarks.add("lecture.mp4", "1:02:03", "Proofs").unwrap();
+        assert_eq!(proofs.position, 3723.0);
+        bookmarks.add("lecture.mp4", "90", "Definitions").unwrap();
+        bookmarks.add("other.mp4", "10", "Intro").unwrap();
+
+        let labels: Vec<String> = bookmarks
+            .list(Some("lecture.mp4"))
+            .unwrap()
+            .into_iter()
+            .map(|bookmark| bookmark.label)
+            .collect();
+        assert_eq!(labels, vec!["Definitions", "Proofs"]);
+        assert_eq!(bookmarks.list(None).unwrap().len(), 3);
+
+        assert_eq!(bookmarks.get(proofs.id).unwrap(), proofs);
+        bookmarks.delete(proofs.id).unwrap();
+        assert!(bookmarks.get(proofs.id).is_err());
+        assert!(bookmarks.delete(proofs.id).is_err());
+    }
+}
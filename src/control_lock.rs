@@ -0,0 +1,200 @@
+//! Exclusive control of the player, for a DJ who does not want the queue changed under them.
+//!
+//! Whoever claims control, having logged in or with an API key, gets a token, and while the
+//! claim lasts only requests with that token can change anything.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::{api::ApiError, history::now};
+
+/// The longest anyone can claim control for at once.
+pub const MAX_CLAIM_DURATION: Duration = Duration::from_secs(4 * 60 * 60);
+
+/// Who has claimed control, and until when.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ControlHolder {
+    pub name: String,
+    /// When the claim runs out, in seconds since the unix epoch.
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct LockState {
+    token: Option<String>,
+    /// Incremented every time control is claimed or released, so that an expiry timer
+    /// can notice that the claim it was started for has been replaced.
+    generation: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ControlLock {
+    state: Arc<Mutex<LockState>>,
+    holder_watch_sender: Arc<watch::Sender<Option<ControlHolder>>>,
+}
+
+impl Default for ControlLock {
+    fn default() -> Self {
+        Self {
+            state: Arc::default(),
+            holder_watch_sender: Arc::new(watch::channel(None).0),
+        }
+    }
+}
+
+/// A time of day like `21:30 UTC`.
+fn format_time(seconds: u64) -> String {
+    let seconds = seconds % (24 * 60 * 60);
+    format!("{:02}:{:02} UTC", seconds / 3600, seconds / 60 % 60)
+}
+
+impl ControlLock {
+    /// Who has control right now, if anyone.
+    pub fn holder(&self) -> Option<ControlHolder> {
+        self.holder_watch_sender.borrow().clone()
+    }
+
+    pub fn get_holder_watch_receiver(&self) -> watch::Receiver<Option<ControlHolder>> {
+        self.holder_watch_sender.subscribe()
+    }
+
+    fn locked_error(holder: &ControlHolder) -> ApiError {
+        ApiError::PolicyViolation(format!(
+            "Locked by {} until {}",
+            holder.name,
+            format_time(holder.expires_at)
+        ))
+    }
+
+    /// Claim control as `name` for `duration`, returning the token that has to be given with
+    /// every change. The holder can claim again with its token to extend or rename the claim.
+    pub fn claim(
+        &self,
+        name: String,
+        duration: Duration,
+        token: Option<&str>,
+    ) -> Result<(ControlHolder, String), ApiError> {
+        if duration.is_zero() || duration > MAX_CLAIM_DURATION {
+            return Err(ApiError::InvalidArgument(format!(
+                "Control can be claimed for at most {} minutes",
+                MAX_CLAIM_DURATION.as_secs() / 60
+            )));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(holder) = self.holder()
+            && state.token.as_deref() != token
+        {
+            return Err(Self::locked_error(&holder));
+        }
+
+        let token = match token.filter(|_| state.token.is_some()) {
+            Some(token) => token.to_string(),
+            None => format!("{:032x}", rand::random::<u128>()),
+        };
+        let holder = ControlHolder {
+            name,
+            expires_at: now() + duration.as_secs(),
+        };
+        state.token = Some(token.clone());
+        state.generation += 1;
+        let generation = state.generation;
+        log::info!("{} claimed control for {:?}", holder.name, duration);
+        self.holder_watch_sender.send_replace(Some(holder.clone()));
+        drop(state);
+
+        let lock = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            let mut state = lock.state.lock().unwrap();
+            if state.generation == generation {
+                log::info!("The claim on control ran out");
+                state.token = None;
+                lock.holder_watch_sender.send_replace(None);
+            }
+        });
+
+        Ok((holder, token))
+    }
+
+    /// Give up control before the claim runs out.
+    pub fn release(&self, token: Option<&str>) -> Result<(), ApiError> {
+        let mut state = self.state.lock().unwrap();
+        if state.token.is_none() {
+            return Err(ApiError::Conflict("Nobody has claimed control".to_string()));
+        }
+        if state.token.as_deref() != token {
+            return Err(ApiError::Unauthorized(
+                "Only the holder can release control".to_string(),
+            ));
+        }
+
+        state.token = None;
+        state.generation += 1;
+        log::info!("Control was released");
+        self.holder_watch_sender.send_replace(None);
+        Ok(())
+    }
+
+    /// Fails unless nobody has claimed control, or `token` is the token of the claim.
+    pub fn check(&self, token: Option<&str>) -> Result<(), ApiError> {
+        let state = self.state.lock().unwrap();
+        match self.holder() {
+            Some(holder) if state.token.as_deref() != token => Err(Self::locked_error(&holder)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_control_lock() {
+        let lock = ControlLock::default();
+        let mut changes = lock.get_holder_watch_receiver();
+        assert!(lock.check(None).is_ok());
+
+        let (holder, token) = lock
+            .claim("alice".to_string(), Duration::from_secs(60), None)
+            .unwrap();
+        assert_eq!(holder.name, "alice");
+        assert!(changes.has_changed().unwrap());
+        assert!(lock.check(Some(&token)).is_ok());
+        let Err(ApiError::PolicyViolation(message)) = lock.check(None) else {
+            panic!("Expected the lock to be held");
+        };
+        assert!(message.starts_with("Locked by alice until "));
+
+        assert!(
+            lock.claim("bob".to_string(), Duration::from_secs(60), None)
+                .is_err()
+        );
+        assert!(lock.release(Some("wrong")).is_err());
+        // The holder can extend the claim, keeping the token.
+        let (_, renewed) = lock
+            .claim("alice".to_string(), Duration::from_secs(120), Some(&token))
+            .unwrap();
+        assert_eq!(renewed, token);
+        lock.release(Some(&token)).unwrap();
+        assert_eq!(lock.holder(), None);
+
+        // Claims run out on their own.
+        lock.claim("bob".to_string(), Duration::from_secs(1), None)
+            .unwrap();
+        changes.borrow_and_update();
+        changes.changed().await.unwrap();
+        assert_eq!(*changes.borrow(), None);
+        assert!(lock.check(None).is_ok());
+        assert_eq!(
+            format_time(1779235200 + 21 * 3600 + 30 * 60 + 5),
+            "21:30 UTC"
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! `greg-ng ctl`, a small client for controlling a running instance over its REST API.
+
+use anyhow::Context;
+use clap::Subcommand;
+use serde_json::Value;
+use url::Url;
+
+#[derive(Debug, clap::Args)]
+pub struct CtlArgs {
+    /// The greg-ng instance to control.
+    #[clap(long, value_name = "URL", default_value = "http://localhost:8008")]
+    url: Url,
+
+    #[command(subcommand)]
+    command: CtlCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum CtlCommand {
+    /// Add an item to the end of the playlist.
+    Queue { url: String },
+    /// Show what is playing.
+    Status,
+    /// Skip to the next item in the playlist.
+    Skip,
+    /// Show the volume, or set it to a percentage.
+    Volume { volume: Option<f64> },
+    /// Show the playlist.
+    Playlist,
+}
+
+pub async fn run(args: CtlArgs) -> anyhow::Result<()> {
+    let client = Client {
+        http: reqwest::Client::new(),
+        base_url: args.url,
+    };
+
+    match args.command {
+        CtlCommand::Queue { url } => {
+            client.post("api/v2/load", &[("path", &url)]).await?;
+            println!("Queued {}", url);
+        }
+        CtlCommand::Status => {
+            let status = client.get("api/status").await?;
+            print_status(&status);
+        }
+        CtlCommand::Skip => {
+            client.post("api/v2/playlist/next", &[]).await?;
+        }
+        CtlCommand::Volume { volume: None } => {
+            let volume = client.get("api/v2/volume").await?;
+            println!("{}%", volume);
+        }
+        CtlCommand::Volume {
+            volume: Some(volume),
+        } => {
+            client
+                .post("api/v2/volume", &[("volume", &volume.to_string())])
+                .await?;
+        }
+        CtlCommand::Playlist => {
+            let status = client.get("api/status").await?;
+            print_playlist(&status["status"]);
+        }
+    }
+
+    Ok(())
+}
+
+struct Client {
+    http: reqwest::Client,
+    base_url: Url,
+}
+
+impl Client {
+    fn url(&self, path: &str, query: &[(&str, &str)]) -> anyhow::Result<Url> {
+        let mut url = self.base_url.join(path)?;
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        Ok(url)
+    }
+
+    async fn get(&self, path: &str) -> anyhow::Result<Value> {
+        let request = self.http.get(self.url(path, &[])?);
+        self.send(request).await
+    }
+
+    async fn post(&self, path: &str, query: &[(&str, &str)]) -> anyhow::Result<Value> {
+        let request = self.http.post(self.url(path, query)?);
+        self.send(request).await
+    }
+
+    /// Send `request`, and unwrap the value from the `{ success, value }` envelope.
+    async fn send(&self, request: reqwest::RequestBuilder) -> anyhow::Result<Value> {
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach greg-ng at {}", self.base_url))?;
+
+        let status = response.status();
+        let body: Value = serde_json::from_slice(&response.bytes().await?)
+            .with_context(|| format!("Unexpected response from greg-ng ({})", status))?;
+
+        if !status.is_success() {
+            // Errors are RFC 7807 problem details.
+            let detail = body["detail"].as_str().unwrap_or("unknown error");
+            anyhow::bail!("{} ({})", detail, status);
+        }
+
+        Ok(body["value"].clone())
+    }
+}
+
+fn item_name(item: &Value) -> &str {
+    item["title"]
+        .as_str()
+        .or_else(|| item["filename"].as_str())
+        .unwrap_or("?")
+}
+
+fn print_status(value: &Value) {
+    let status = &value["status"];
+    let current = status["current"]
+        .as_u64()
+        .and_then(|index| status["playlist"].get(index as usize));
+
+    match current {
+        Some(item) => {
+            let state = if status["playing"].as_bool() == Some(true) {
+                "Playing"
+            } else {
+                "Paused"
+            };
+            match value["position"].as_f64() {
+                Some(position) => {
+                    println!("{}: {} [{}]", state, item_name(item), format_time(position))
+                }
+                None => println!("{}: {}", state, item_name(item)),
+            }
+        }
+        None => println!("Nothing is playing"),
+    }
+
+    println!("Volume: {}%", status["volume"]);
+    if status["looping"].as_bool() == Some(true) {
+        println!("Looping the playlist");
+    }
+}
+
+fn print_playlist(status: &Value) {
+    let current = status["current"].as_u64();
+    let items = status["playlist"].as_array().cloned().unwrap_or_default();
+    if items.is_empty() {
+        println!("The playlist is empty");
+    }
+
+    for (index, item) in items.iter().enumerate() {
+        let marker = if current == Some(index as u64) {
+            '>'
+        } else {
+            ' '
+        };
+        println!("{} {:>3}  {}", marker, index, item_name(item));
+    }
+}
+
+fn format_time(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0.0), "0:00");
+        assert_eq!(format_time(61.7), "1:01");
+        assert_eq!(format_time(3725.0), "62:05");
+    }
+}
@@ -0,0 +1,184 @@
+//! Removing items that have waited in the playlist for too long, so that links from last
+//! week's session do not suddenly start playing.
+//!
+//! Items expire after their own TTL, given when they were added, or else after the one
+//! everything gets with `--item-ttl`. The item playing now and locked items never expire.
+//! How long an item has waited is counted from when it was first seen in the playlist,
+//! which is kept in its [`ItemState`] and starts over when greg restarts.
+//!
+//! Expired items are announced to every mpv client as a `script-message`, which is how
+//! they reach the websocket and event stream clients, and the play history.
+
+use std::{collections::HashMap, time::Duration};
+
+use mpvipc_async::Mpv;
+
+use crate::{
+    history::now,
+    player::{self, EntryId, ItemState, PlayerHandle, PlaylistEntry},
+};
+
+/// The `script-message` sent when an item expires, followed by its entry id, its path and
+/// its title.
+pub const ITEM_EXPIRED_MESSAGE: &str = "greg-item-expired";
+
+/// How often the playlist is checked for items that have waited too long.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The entry id, path and title of an item announced with [`ITEM_EXPIRED_MESSAGE`].
+pub fn parse_expired_message(args: &[String]) -> Option<(Option<EntryId>, &str, &str)> {
+    match args {
+        [message, id, path, title] if message == ITEM_EXPIRED_MESSAGE => {
+            Some((id.parse().ok(), path, title))
+        }
+        _ => None,
+    }
+}
+
+/// How long an item with the state `item` can wait in the playlist, or `None` if it never
+/// expires.
+fn ttl(item: &ItemState, default_ttl: Option<Duration>) -> Option<Duration> {
+    if item.flags.locked {
+        return None;
+    }
+    item.ttl_hours
+        .map(|hours| Duration::from_secs(u64::from(hours) * 60 * 60))
+        .or(default_ttl)
+}
+
+/// The indices of the items in `playlist` that have waited longer than `ttl` lets them,
+/// given when each was first seen. The item playing now is never expired.
+fn expired(
+    playlist: &[PlaylistEntry],
+    first_seen: &HashMap<EntryId, u64>,
+    ttl: impl Fn(&PlaylistEntry) -> Option<Duration>,
+    now: u64,
+) -> Vec<usize> {
+    playlist
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| !entry.current)
+        .filter(|(_, entry)| {
+            let seen = first_seen.get(&entry.id).copied().unwrap_or(now);
+            ttl(entry).is_some_and(|ttl| now.saturating_sub(seen) > ttl.as_secs())
+        })
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// Remove the items that have waited too long, and announce them.
+async fn remove_expired(
+    mpv: &Mpv,
+    player: &PlayerHandle,
+    default_ttl: Option<Duration>,
+) -> anyhow::Result<()> {
+    let _lock = player::lock_playlist(player.queue_state()).await;
+    let playlist = player.playlist().await?;
+    let queue = player.queue_state();
+    let now = now();
+    let mut first_seen = HashMap::new();
+    for entry in &playlist {
+        let seen = match queue.item(entry).first_seen {
+            Some(seen) => seen,
+            None => {
+                queue.update(entry.id, |item| item.first_seen = Some(now));
+                now
+            }
+        };
+        first_seen.insert(entry.id, seen);
+    }
+
+    // Back to front, so that the indices stay valid.
+    let ttl_of = |entry: &PlaylistEntry| ttl(&queue.item(entry), default_ttl);
+    for index in expired(&playlist, &first_seen, ttl_of, now)
+        .into_iter()
+        .rev()
+    {
+        let entry = &playlist[index];
+        let title = queue.display_title(entry);
+        log::info!("'{}' waited too long in the playlist, removing it", title);
+        player.playlist_remove(index).await?;
+
+        let result = mpv
+            .run_command_raw(
+                "script-message",
+                &[
+                    ITEM_EXPIRED_MESSAGE,
+                    &entry.id.to_string(),
+                    &entry.filename,
+                    &title,
+                ],
+            )
+            .await;
+        if let Err(e) = result {
+            log::warn!("Failed to announce the expired item: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Keep removing items that have waited in the playlist for longer than their TTL, or
+/// `default_ttl`.
+pub async fn expire_items(mpv: Mpv, player: PlayerHandle, default_ttl: Option<Duration>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = remove_expired(&mpv, &player, default_ttl).await {
+            log::warn!("Failed to remove expired items: {:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expired() {
+        let entry = |id: usize, filename: &str, current: bool| PlaylistEntry {
+            id: EntryId::Player(id),
+            filename: filename.to_string(),
+            title: None,
+            current,
+        };
+        let playlist = [
+            entry(0, "playing", true),
+            entry(1, "old", false),
+            entry(2, "locked", false),
+            entry(3, "own-ttl", false),
+            entry(4, "new", false),
+        ];
+        let hour = 60 * 60;
+        let first_seen: HashMap<_, _> = [0, 0, 0, 3 * hour, 4 * hour]
+            .into_iter()
+            .enumerate()
+            .map(|(id, seen)| (EntryId::Player(id), seen))
+            .collect();
+        let ttl = |default_ttl: Option<u64>| {
+            move |entry: &PlaylistEntry| match entry.filename.as_str() {
+                "locked" => None,
+                "own-ttl" => Some(Duration::from_secs(hour)),
+                _ => default_ttl.map(|hours| Duration::from_secs(hours * hour)),
+            }
+        };
+
+        assert_eq!(
+            expired(&playlist, &first_seen, ttl(Some(3)), 5 * hour),
+            [1, 3]
+        );
+        assert_eq!(expired(&playlist, &first_seen, ttl(None), 5 * hour), [3]);
+        assert!(expired(&playlist, &first_seen, ttl(Some(3)), 3 * hour).is_empty());
+
+        assert_eq!(
+            parse_expired_message(&[
+                ITEM_EXPIRED_MESSAGE.to_string(),
+                "held-2".to_string(),
+                "a.mp3".to_string(),
+                "A".to_string()
+            ]),
+            Some((Some(EntryId::Held(2)), "a.mp3", "A"))
+        );
+    }
+}
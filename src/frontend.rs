@@ -0,0 +1,118 @@
+use std::path::Path;
+
+use axum::{
+    Router,
+    extract::Request,
+    http::{HeaderValue, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+};
+use tower_http::services::{ServeDir, ServeFile};
+
+/// Paths that belong to the APIs, and should never fall back to the web UI.
+const API_PREFIXES: &[&str] = &["/api", "/ws", "/docs"];
+
+/// Serve a web UI from the given directory.
+///
+/// Paths that don't match a file are answered with `index.html`, so that a single page
+/// application can handle its own routing.
+pub fn frontend_routes(dir: &Path) -> Router {
+    let serve_dir = ServeDir::new(dir)
+        .append_index_html_on_directories(true)
+        .fallback(ServeFile::new(dir.join("index.html")));
+
+    Router::new()
+        .fallback_service(serve_dir)
+        .layer(middleware::from_fn(frontend_middleware))
+}
+
+fn is_api_path(path: &str) -> bool {
+    API_PREFIXES.iter().any(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Pick a cache policy based on what is being served.
+///
+/// The html entrypoint has to be revalidated every time, so that new builds are picked up.
+/// Files under `/assets` are expected to have a content hash in their name (as produced by
+/// most bundlers), and can be cached forever.
+fn cache_control(path: &str, response: &Response) -> &'static str {
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/html"));
+
+    if is_html {
+        "no-cache"
+    } else if path.starts_with("/assets/") {
+        "public, max-age=31536000, immutable"
+    } else {
+        "public, max-age=3600"
+    }
+}
+
+async fn frontend_middleware(request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+
+    // NOTE: the API routers inherit this as their fallback, so unknown API paths end up here.
+    if is_api_path(&path) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let mut response = next.run(request).await;
+    if response.status().is_success() {
+        let cache_control = cache_control(&path, &response);
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(cache_control),
+        );
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    async fn get(router: &Router, path: &str) -> Response {
+        router
+            .clone()
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_frontend_routes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "<html></html>").unwrap();
+        std::fs::create_dir(dir.path().join("assets")).unwrap();
+        std::fs::write(dir.path().join("assets/app-1234.js"), "").unwrap();
+
+        let router = frontend_routes(dir.path());
+
+        let response = get(&router, "/").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+
+        let response = get(&router, "/assets/app-1234.js").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "public, max-age=31536000, immutable"
+        );
+
+        // Client side routes fall back to the entrypoint
+        let response = get(&router, "/playlist/3").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+
+        let response = get(&router, "/api/does-not-exist").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,358 @@
+//! Remembers what has been played, and how it went.
+
+use std::time::{Duration, SystemTime};
+
+use futures::StreamExt;
+use mpvipc_async::{EndFileReason, Event, Mpv};
+use serde::Serialize;
+
+use crate::{
+    autoplay::Autoplay,
+    expiry,
+    player::{self, EntryId, QueueState},
+    radio,
+    stats::TimeRange,
+    storage::StorageHandle,
+    watchdog,
+};
+
+/// How many entries are listed at once, at most.
+pub const MAX_ENTRIES: usize = 1000;
+
+/// How often entries past their retention are pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    Playing,
+    Finished,
+    Skipped,
+    Failed {
+        error: String,
+    },
+    /// The item waited in the playlist for too long, and was removed without playing.
+    Expired,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    pub path: String,
+    pub title: Option<String>,
+    /// Seconds since the unix epoch.
+    pub started_at: u64,
+    pub ended_at: Option<u64>,
+    /// Whether the item was picked by autoplay.
+    pub autoplay: bool,
+    /// Who added the item, if they gave their name.
+    pub queued_by: Option<String>,
+    /// The subject of whoever added the item, if they logged in.
+    pub queued_by_subject: Option<String>,
+    /// Whether the item is an endless stream, like a radio station. How long it was
+    /// played for says nothing about its length, so it should be left out of duration stats.
+    pub live: bool,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+/// Seconds since the unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// The columns of [`HistoryEntry::to_csv_record`].
+pub const CSV_HEADER: &str = "path,title,started_at,ended_at,autoplay,queued_by,live,outcome,error";
+
+/// Quote a CSV field if it needs it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl HistoryEntry {
+    /// The entry as a line of CSV, without the line ending.
+    pub fn to_csv_record(&self) -> String {
+        let (outcome, error) = self.outcome.to_columns();
+        [
+            csv_field(&self.path),
+            csv_field(self.title.as_deref().unwrap_or_default()),
+            self.started_at.to_string(),
+            self.ended_at.map(|t| t.to_string()).unwrap_or_default(),
+            self.autoplay.to_string(),
+            csv_field(self.queued_by.as_deref().unwrap_or_default()),
+            self.live.to_string(),
+            outcome.to_string(),
+            csv_field(error.unwrap_or_default()),
+        ]
+        .join(",")
+    }
+}
+
+impl Outcome {
+    /// The name of the outcome and its error, which is how it is stored and exported.
+    pub fn to_columns(&self) -> (&'static str, Option<&str>) {
+        match self {
+            Outcome::Playing => ("playing", None),
+            Outcome::Finished => ("finished", None),
+            Outcome::Skipped => ("skipped", None),
+            Outcome::Failed { error } => ("failed", Some(error)),
+            Outcome::Expired => ("expired", None),
+        }
+    }
+
+    pub fn from_columns(outcome: &str, error: Option<String>) -> Self {
+        match outcome {
+            "playing" => Outcome::Playing,
+            "finished" => Outcome::Finished,
+            "failed" => Outcome::Failed {
+                error: error.unwrap_or_default(),
+            },
+            "expired" => Outcome::Expired,
+            _ => Outcome::Skipped,
+        }
+    }
+}
+
+/// Everything that has been played, kept in the storage.
+#[derive(Debug, Clone)]
+pub struct PlayHistory {
+    storage: StorageHandle,
+}
+
+impl PlayHistory {
+    pub fn new(storage: StorageHandle) -> Self {
+        Self { storage }
+    }
+
+    /// Recording the history is best effort, and never gets in the way of playing.
+    fn record<T>(&self, what: &str, result: anyhow::Result<T>) {
+        if let Err(e) = result {
+            log::warn!("Failed to record {} in the history: {:#}", what, e);
+        }
+    }
+
+    fn started(
+        &self,
+        path: String,
+        title: Option<String>,
+        autoplay: bool,
+        note: player::ItemNote,
+        live: bool,
+    ) {
+        let entry = HistoryEntry {
+            path,
+            title,
+            started_at: now(),
+            ended_at: None,
+            autoplay,
+            queued_by: note.queued_by,
+            queued_by_subject: note.queued_by_subject,
+            live,
+            outcome: Outcome::Playing,
+        };
+        self.record("a new item", self.storage.push_history(&entry));
+    }
+
+    /// End the item that is playing, unless it has already ended.
+    fn ended(&self, outcome: Outcome) {
+        self.record(
+            "the end of an item",
+            self.storage.end_history(None, &outcome, now()),
+        );
+    }
+
+    /// Record that `path` failed to play, either while playing or before it even started.
+    fn failed(&self, path: &str, error: &str, note: player::ItemNote) {
+        let outcome = Outcome::Failed {
+            error: error.to_string(),
+        };
+        let now = now();
+
+        let result = self
+            .storage
+            .end_history(Some(path), &outcome, now)
+            .and_then(|ended| {
+                if ended {
+                    return Ok(());
+                }
+                self.storage.push_history(&HistoryEntry {
+                    path: path.to_string(),
+                    title: None,
+                    started_at: now,
+                    ended_at: Some(now),
+                    autoplay: false,
+                    queued_by: note.queued_by,
+                    queued_by_subject: note.queued_by_subject,
+                    live: false,
+                    outcome,
+                })
+            });
+        self.record("a failure", result);
+    }
+
+    /// Record that `path` waited in the playlist for too long, and was removed.
+    fn expired(&self, path: &str, title: &str, note: player::ItemNote) {
+        let now = now();
+        let entry = HistoryEntry {
+            path: path.to_string(),
+            title: Some(title.to_string()),
+            started_at: now,
+            ended_at: Some(now),
+            autoplay: false,
+            queued_by: note.queued_by,
+            queued_by_subject: note.queued_by_subject,
+            live: false,
+            outcome: Outcome::Expired,
+        };
+        self.record("an expired item", self.storage.push_history(&entry));
+    }
+
+    /// Every entry that started within `range`, oldest first.
+    pub fn entries(&self, range: &TimeRange) -> anyhow::Result<Vec<HistoryEntry>> {
+        self.storage.history(range)
+    }
+
+    /// Forget the entries that started before `cutoff`, returning how many there were.
+    fn prune_before(&self, cutoff: u64) -> anyhow::Result<usize> {
+        self.storage.prune_history(cutoff)
+    }
+
+    /// The last `count` entries, newest first.
+    pub fn recent(&self, count: usize) -> anyhow::Result<Vec<HistoryEntry>> {
+        self.storage.recent_history(count)
+    }
+}
+
+/// Keep forgetting the entries in `history` that are older than `retention`.
+pub async fn prune_history(history: PlayHistory, retention: Duration) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match history.prune_before(now().saturating_sub(retention.as_secs())) {
+            Ok(0) => {}
+            Ok(pruned) => log::debug!("Pruned {} history entries", pruned),
+            Err(e) => log::warn!("Failed to prune the history: {:#}", e),
+        }
+    }
+}
+
+/// Keep recording what mpv plays into `history`, until mpv goes away. Who queued each item
+/// is looked up in `queue`.
+pub async fn record_history(mpv: Mpv, queue: QueueState, history: PlayHistory, autoplay: Autoplay) {
+    let mut event_stream = mpv.get_event_stream().await;
+    let mut started: Option<EntryId> = None;
+    let note = |id: Option<EntryId>| id.map(|id| queue.get(id).note).unwrap_or_default();
+
+    while let Some(Ok(event)) = event_stream.next().await {
+        match event {
+            Event::StartFile { playlist_entry_id } => {
+                started = Some(EntryId::Player(playlist_entry_id));
+            }
+            Event::FileLoaded => {
+                let path: Option<String> = mpv.get_property("path").await.unwrap_or(None);
+                let title: Option<String> = mpv.get_property("media-title").await.unwrap_or(None);
+                if let Some(path) = path {
+                    history.ended(Outcome::Skipped);
+                    let autoplayed = autoplay.is_autoplayed(&path);
+                    let live = radio::is_live(&mpv).await;
+                    history.started(path, title, autoplayed, note(started), live);
+                }
+            }
+            Event::EndFile { reason, .. } => match reason {
+                EndFileReason::Eof => history.ended(Outcome::Finished),
+                EndFileReason::Stop | EndFileReason::Quit => history.ended(Outcome::Skipped),
+                // Failures are announced by the watchdog, together with the path.
+                _ => {}
+            },
+            Event::ClientMessage { args } => {
+                if let Some((id, path, error)) = watchdog::parse_failure_message(&args) {
+                    history.failed(path, error, note(id));
+                }
+                if let Some((id, path, title)) = expiry::parse_expired_message(&args) {
+                    history.expired(path, title, note(id));
+                }
+            }
+            Event::Shutdown => break,
+            _ => {}
+        }
+    }
+
+    history.ended(Outcome::Skipped);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::storage::MemoryStorage;
+
+    use super::*;
+
+    #[test]
+    fn test_history_failures() {
+        let history = PlayHistory::new(Arc::new(MemoryStorage::new()));
+
+        history.started(
+            "a".to_string(),
+            Some("A".to_string()),
+            false,
+            Default::default(),
+            false,
+        );
+        history.ended(Outcome::Finished);
+        history.started("b".to_string(), None, true, Default::default(), false);
+        history.failed("b", "No progress for 30 seconds", Default::default());
+        // The skip that follows the failure does not overwrite it.
+        history.ended(Outcome::Skipped);
+        history.failed("c", "Loading failed", Default::default());
+
+        let outcomes: Vec<_> = history
+            .recent(10)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.path, entry.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (
+                    "c".to_string(),
+                    Outcome::Failed {
+                        error: "Loading failed".to_string()
+                    }
+                ),
+                (
+                    "b".to_string(),
+                    Outcome::Failed {
+                        error: "No progress for 30 seconds".to_string()
+                    }
+                ),
+                ("a".to_string(), Outcome::Finished),
+            ]
+        );
+
+        let failed = &history.recent(1).unwrap()[0];
+        assert_eq!(
+            failed.to_csv_record(),
+            format!(
+                "c,,{0},{0},false,,false,failed,Loading failed",
+                failed.started_at
+            )
+        );
+        assert_eq!(csv_field("Band, \"The\""), "\"Band, \"\"The\"\"\"");
+
+        let range = TimeRange {
+            from: Some(failed.started_at),
+            to: None,
+        };
+        assert_eq!(history.entries(&range).unwrap().len(), 3);
+        assert_eq!(history.prune_before(0).unwrap(), 0);
+        assert_eq!(history.prune_before(now() + 1).unwrap(), 3);
+    }
+}
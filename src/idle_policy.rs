@@ -0,0 +1,223 @@
+//! Quieting background music when nobody is around, to save the neighbours at night.
+//!
+//! When no websocket client has been connected for a while, background music is paused
+//! or turned down, and it is brought back as soon as someone connects again. Background
+//! music is whatever autoplay picked, and items an admin flagged as such.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use mpvipc_async::Switch;
+use serde::{Deserialize, Serialize};
+
+use crate::{autoplay::Autoplay, util::ClientRegistry, volume_transition::VolumeTransitionEngine};
+
+/// How often the clients and the current item are checked.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long the volume takes to go down, and back up.
+const FADE_DURATION: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdleAction {
+    #[default]
+    Pause,
+    LowerVolume,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct IdlePolicy {
+    /// How many minutes without clients before background music is quieted, or never if
+    /// not given.
+    pub after_minutes: Option<u64>,
+    pub action: IdleAction,
+    /// The volume background music is lowered to, with [`IdleAction::LowerVolume`].
+    pub volume: f64,
+}
+
+/// The idle policy in effect, which admins can change at runtime.
+#[derive(Debug, Clone)]
+pub struct IdlePolicyHandle {
+    policy: Arc<Mutex<IdlePolicy>>,
+}
+
+impl IdlePolicyHandle {
+    pub fn new(policy: IdlePolicy) -> Self {
+        Self {
+            policy: Arc::new(Mutex::new(policy)),
+        }
+    }
+
+    pub fn get(&self) -> IdlePolicy {
+        *self.policy.lock().unwrap()
+    }
+
+    pub fn set(&self, policy: IdlePolicy) {
+        log::info!("Setting the idle policy to {:?}", policy);
+        *self.policy.lock().unwrap() = policy;
+    }
+}
+
+/// What was done to the background music, so it can be undone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quieted {
+    Paused,
+    Lowered { volume: f64 },
+}
+
+/// Whether the item playing now is background music.
+async fn playing_background_music(
+    volume_engine: &VolumeTransitionEngine,
+    autoplay: &Autoplay,
+) -> anyhow::Result<bool> {
+    let player = volume_engine.player();
+    if !player.is_playing().await? {
+        return Ok(false);
+    }
+    let playlist = player.playlist().await?;
+    Ok(playlist
+        .iter()
+        .find(|entry| entry.current)
+        .is_some_and(|entry| {
+            player.queue_state().item(entry).flags.background
+                || autoplay.is_autoplayed(&entry.filename)
+        }))
+}
+
+async fn quiet(
+    volume_engine: &VolumeTransitionEngine,
+    policy: &IdlePolicy,
+) -> anyhow::Result<Option<Quieted>> {
+    match policy.action {
+        IdleAction::Pause => {
+            log::info!("Nobody is around, pausing the background music");
+            volume_engine.set_playback(Switch::Off).await?;
+            Ok(Some(Quieted::Paused))
+        }
+        IdleAction::LowerVolume => {
+            let volume = volume_engine.player().get_volume().await?;
+            if volume <= policy.volume {
+                return Ok(None);
+            }
+            log::info!("Nobody is around, turning the background music down");
+            volume_engine.fade_to(policy.volume, FADE_DURATION).await?;
+            Ok(Some(Quieted::Lowered { volume }))
+        }
+    }
+}
+
+async fn restore(volume_engine: &VolumeTransitionEngine, quieted: Quieted) -> anyhow::Result<()> {
+    log::info!("Someone connected, bringing the background music back");
+    match quieted {
+        Quieted::Paused => volume_engine.set_playback(Switch::On).await,
+        Quieted::Lowered { volume } => volume_engine
+            .fade_to(volume, FADE_DURATION)
+            .await
+            .map(|_| ()),
+    }
+}
+
+/// Keep quieting background music while nobody is connected, following `policy`.
+pub async fn enforce_idle_policy(
+    policy: IdlePolicyHandle,
+    volume_engine: VolumeTransitionEngine,
+    autoplay: Autoplay,
+    clients: ClientRegistry,
+) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut last_seen = Instant::now();
+    let mut quieted = None;
+
+    loop {
+        interval.tick().await;
+        let policy = policy.get();
+
+        if !clients.list().is_empty() {
+            last_seen = Instant::now();
+            if let Some(q) = quieted.take()
+                && let Err(e) = restore(&volume_engine, q).await
+            {
+                log::warn!("Failed to bring the background music back: {:#}", e);
+            }
+            continue;
+        }
+
+        let Some(after_minutes) = policy.after_minutes else {
+            continue;
+        };
+        if quieted.is_some() || last_seen.elapsed() < Duration::from_secs(after_minutes * 60) {
+            continue;
+        }
+
+        let result = match playing_background_music(&volume_engine, &autoplay).await {
+            Ok(true) => quiet(&volume_engine, &policy).await,
+            Ok(false) => Ok(None),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(q) => quieted = q,
+            Err(e) => log::warn!("Failed to quiet the background music: {:#}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        player::{ItemFlags, MpvPlayer},
+        test_support::FakeMpv,
+    };
+
+    #[tokio::test]
+    async fn test_quiet_and_restore() {
+        let mpv = FakeMpv::start();
+        let file = "idle-policy-background.mp3";
+        mpv.set_property(
+            "playlist",
+            json!([{"filename": file, "id": 1, "current": true}]),
+        );
+        mpv.set_property("pause", json!(false));
+        let engine =
+            VolumeTransitionEngine::new(Arc::new(MpvPlayer::new(mpv.connect().await)), false);
+        let autoplay = Autoplay::default();
+
+        assert!(!playing_background_music(&engine, &autoplay).await.unwrap());
+        let playlist = engine.player().playlist().await.unwrap();
+        let flags = ItemFlags {
+            background: true,
+            ..Default::default()
+        };
+        engine
+            .player()
+            .queue_state()
+            .update(playlist[0].id, |item| item.flags = flags);
+        assert!(playing_background_music(&engine, &autoplay).await.unwrap());
+
+        let policy = IdlePolicy {
+            after_minutes: Some(1),
+            action: IdleAction::Pause,
+            volume: 20.0,
+        };
+        let quieted = quiet(&engine, &policy).await.unwrap().unwrap();
+        assert_eq!(mpv.property("pause"), Some(json!(true)));
+        restore(&engine, quieted).await.unwrap();
+        assert_eq!(mpv.property("pause"), Some(json!(false)));
+
+        // The volume is only lowered if it is louder.
+        let policy = IdlePolicy {
+            action: IdleAction::LowerVolume,
+            volume: 60.0,
+            ..policy
+        };
+        assert_eq!(quiet(&engine, &policy).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,216 @@
+//! External inputs, like a capture card with a game console plugged in, that mpv can be
+//! switched over to.
+//!
+//! Activating an input suspends the playlist, and deactivating it puts the playlist back
+//! the way it was, at the same position.
+
+use std::{collections::BTreeMap, str::FromStr, sync::Arc};
+
+use anyhow::Context;
+use tokio::sync::Mutex;
+
+use crate::{
+    api::ApiError,
+    player::{self, ItemState, PlayerHandle},
+};
+
+/// An input as given on the command line, as `<name>=<url>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputSpec {
+    pub name: String,
+    /// What mpv plays for this input, like `av://v4l2:/dev/video0`.
+    pub url: String,
+}
+
+impl FromStr for InputSpec {
+    type Err = String;
+
+    /// Parses `<name>=<url>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, url) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <name>=<url>, got '{}'", s))?;
+
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "invalid input name '{}', only letters, digits, '-' and '_' are allowed",
+                name
+            ));
+        }
+        if url.is_empty() {
+            return Err(format!("missing url for input '{}'", name));
+        }
+
+        Ok(InputSpec {
+            name: name.to_string(),
+            url: url.to_string(),
+        })
+    }
+}
+
+/// The playlist as it was before an input was activated.
+#[derive(Debug)]
+struct Suspended {
+    items: Vec<(String, ItemState)>,
+    current: Option<usize>,
+    position: Option<f64>,
+    was_playing: bool,
+}
+
+#[derive(Debug)]
+struct ActiveInput {
+    name: String,
+    suspended: Suspended,
+}
+
+/// The configured inputs, and which of them is active.
+#[derive(Debug, Clone, Default)]
+pub struct Inputs {
+    inputs: Arc<BTreeMap<String, InputSpec>>,
+    active: Arc<Mutex<Option<ActiveInput>>>,
+}
+
+impl Inputs {
+    pub fn new(specs: Vec<InputSpec>) -> anyhow::Result<Self> {
+        let mut inputs = BTreeMap::new();
+        for spec in specs {
+            let name = spec.name.clone();
+            if inputs.insert(name.clone(), spec).is_some() {
+                anyhow::bail!("The input '{}' is configured more than once", name);
+            }
+        }
+
+        Ok(Self {
+            inputs: Arc::new(inputs),
+            ..Default::default()
+        })
+    }
+
+    /// Every input, ordered by name.
+    pub fn inputs(&self) -> Vec<InputSpec> {
+        self.inputs.values().cloned().collect()
+    }
+
+    /// The name of the active input, if any.
+    pub async fn active(&self) -> Option<String> {
+        self.active
+            .lock()
+            .await
+            .as_ref()
+            .map(|active| active.name.clone())
+    }
+
+    /// Switch to the input called `name`, suspending the playlist unless another input
+    /// is already active.
+    pub async fn activate(&self, player: &PlayerHandle, name: &str) -> anyhow::Result<()> {
+        let input = self
+            .inputs
+            .get(name)
+            .ok_or_else(|| ApiError::NotFound(format!("No input named '{}'", name)))?;
+        let mpv = player.mpv().context("External inputs need mpv")?;
+
+        let mut active = self.active.lock().await;
+        let _lock = player::lock_playlist(player.queue_state()).await;
+
+        let suspended = match active.take() {
+            Some(previous) => previous.suspended,
+            None => {
+                let playlist = player.playlist().await?;
+                let queue = player.queue_state();
+                Suspended {
+                    current: playlist.iter().position(|entry| entry.current),
+                    items: playlist
+                        .into_iter()
+                        .map(|entry| (entry.filename.clone(), queue.item(&entry)))
+                        .collect(),
+                    position: player.get_time_pos().await?,
+                    was_playing: player.is_playing().await?,
+                }
+            }
+        };
+
+        log::info!("Switching to the input '{}'", name);
+        let result = async {
+            mpv.run_command_raw("loadfile", &[&input.url, "replace"])
+                .await?;
+            player.set_playing(true).await
+        }
+        .await;
+
+        // Even if switching failed, the playlist may already be gone, so it is kept for
+        // when the input is deactivated.
+        *active = Some(ActiveInput {
+            name: name.to_string(),
+            suspended,
+        });
+        player.queue_state().set_suspended(true);
+        result
+    }
+
+    /// Switch back from the active input to the playlist, where it was left off.
+    pub async fn deactivate(&self, player: &PlayerHandle) -> anyhow::Result<()> {
+        let mpv = player.mpv().context("External inputs need mpv")?;
+
+        let mut active = self.active.lock().await;
+        let Some(ActiveInput { name, suspended }) = active.take() else {
+            return Err(ApiError::Conflict("No input is active".to_string()).into());
+        };
+        player.queue_state().set_suspended(false);
+        let _lock = player::lock_playlist(player.queue_state()).await;
+
+        log::info!(
+            "Switching back from the input '{}' to {} suspended items",
+            name,
+            suspended.items.len()
+        );
+        let Some((first, rest)) = suspended.items.split_first() else {
+            mpv.run_command_raw("stop", &[]).await?;
+            return Ok(());
+        };
+
+        let (url, item) = first;
+        mpv.run_command_raw("loadfile", &[url, "replace"]).await?;
+        if let Some(entry) = player.playlist().await?.first() {
+            player.queue_state().set(entry.id, item.clone());
+        }
+        for (url, item) in rest {
+            player.load(url, item.clone()).await?;
+        }
+
+        let Some(current) = suspended.current else {
+            player.set_playing(suspended.was_playing).await?;
+            return Ok(());
+        };
+        player.playlist_goto(current).await?;
+        if let Some(position) = suspended.position {
+            player::seek_when_loaded(player, position).await?;
+        }
+        player.set_playing(suspended.was_playing).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_specs() {
+        let spec: InputSpec = "console=av://v4l2:/dev/video0".parse().unwrap();
+        assert_eq!(spec.name, "console");
+        assert_eq!(spec.url, "av://v4l2:/dev/video0");
+
+        assert!("console".parse::<InputSpec>().is_err());
+        assert!(
+            "game console=av://v4l2:/dev/video0"
+                .parse::<InputSpec>()
+                .is_err()
+        );
+        assert!("console=".parse::<InputSpec>().is_err());
+
+        assert!(Inputs::new(vec![spec.clone(), spec]).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+//! An index of local media files, searchable by tags and filename.
+//!
+//! The configured directories are scanned in the background, and the tags of
+//! every media file are stored in an SQLite full-text index. Files are only
+//! re-read when their modification time changes.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey},
+    probe::Hint,
+};
+use walkdir::WalkDir;
+
+/// How often the library directories are rescanned.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// File extensions that are considered media files.
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "aac", "flac", "m4a", "mkv", "mp3", "mp4", "oga", "ogg", "opus", "wav", "webm",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct Track {
+    pub path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+/// A shared handle to the library index.
+#[derive(Debug, Clone)]
+pub struct LibraryIndex {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl LibraryIndex {
+    /// Open the index stored at `path`, or an in-memory index if no path is given.
+    pub fn open(path: Option<&Path>) -> anyhow::Result<Self> {
+        let conn = match path {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent).with_context(|| {
+                        format!("Failed to create directory {}", parent.display())
+                    })?;
+                }
+                Connection::open(path).with_context(|| {
+                    format!("Failed to open library index at {}", path.display())
+                })?
+            }
+            None => Connection::open_in_memory()?,
+        };
+
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS files (
+                path TEXT PRIMARY KEY,
+                mtime INTEGER NOT NULL
+            );
+            CREATE VIRTUAL TABLE IF NOT EXISTS tracks USING fts5(
+                path UNINDEXED, title, artist, album, filename
+            );",
+        )
+        .context("Failed to create library index tables")?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn insert(&self, track: &Track, mtime: i64) -> anyhow::Result<()> {
+        let filename = Path::new(&track.path)
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned());
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM tracks WHERE path = ?1", params![track.path])?;
+        tx.execute(
+            "INSERT INTO tracks (path, title, artist, album, filename) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![track.path, track.title, track.artist, track.album, filename],
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO files (path, mtime) VALUES (?1, ?2)",
+            params![track.path, mtime],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn remove(&self, path: &str) -> anyhow::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM tracks WHERE path = ?1", params![path])?;
+        conn.execute("DELETE FROM files WHERE path = ?1", params![path])?;
+        Ok(())
+    }
+
+    fn indexed_mtime(&self, path: &str) -> anyhow::Result<Option<i64>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row(
+                "SELECT mtime FROM files WHERE path = ?1",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// The paths of every indexed file.
+    pub fn indexed_paths(&self) -> anyhow::Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare("SELECT path FROM files")?;
+        let paths = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(paths)
+    }
+
+    /// Find tracks where every word in `query` prefixes a word in the title, artist,
+    /// album or filename, best matches first.
+    pub fn search(&self, query: &str, limit: usize) -> anyhow::Result<Vec<Track>> {
+        let Some(fts_query) = fts_query(query) else {
+            return Ok(Vec::new());
+        };
+
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT path, title, artist, album FROM tracks
+             WHERE tracks MATCH ?1 ORDER BY rank LIMIT ?2",
+        )?;
+        let tracks = statement
+            .query_map(params![fts_query, limit as i64], |row| {
+                Ok(Track {
+                    path: row.get(0)?,
+                    title: row.get(1)?,
+                    artist: row.get(2)?,
+                    album: row.get(3)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(tracks)
+    }
+
+    /// Bring the index up to date with the contents of `dirs`.
+    fn scan(&self, dirs: &[PathBuf]) -> anyhow::Result<()> {
+        let mut seen = Vec::new();
+        let mut updated = 0;
+
+        for dir in dirs {
+            for entry in WalkDir::new(dir).follow_links(true) {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        log::debug!("Skipping unreadable library entry: {}", e);
+                        continue;
+                    }
+                };
+
+                if !entry.file_type().is_file() || !is_media_file(entry.path()) {
+                    continue;
+                }
+
+                let path = entry.path().to_string_lossy().into_owned();
+                let mtime = entry
+                    .metadata()
+                    .ok()
+                    .and_then(|metadata| metadata.modified().ok())
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |duration| duration.as_secs() as i64);
+
+                if self.indexed_mtime(&path)? != Some(mtime) {
+                    let mut track = read_tags(entry.path()).unwrap_or_else(|e| {
+                        log::debug!("Could not read tags of {}: {:#}", path, e);
+                        Track::default()
+                    });
+                    track.path = path.clone();
+                    self.insert(&track, mtime)?;
+                    updated += 1;
+                }
+
+                seen.push(path);
+            }
+        }
+
+        seen.sort();
+        let mut removed = 0;
+        for path in self.indexed_paths()? {
+            if seen.binary_search(&path).is_err() {
+                self.remove(&path)?;
+                removed += 1;
+            }
+        }
+
+        log::info!(
+            "Library scan done: {} files, {} updated, {} removed",
+            seen.len(),
+            updated,
+            removed
+        );
+
+        Ok(())
+    }
+}
+
+fn is_media_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| MEDIA_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Turn free text into an FTS5 query, where every word is matched as a prefix.
+fn fts_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|word| word.replace('"', ""))
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{}\"*", word))
+        .collect();
+
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+fn read_tags(path: &Path) -> anyhow::Result<Track> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    let mut track = Track::default();
+    // Tags can be found both before the container (e.g. ID3), and inside it.
+    if let Some(metadata) = probed.metadata.get()
+        && let Some(revision) = metadata.current()
+    {
+        apply_tags(&mut track, revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        apply_tags(&mut track, revision);
+    }
+
+    Ok(track)
+}
+
+fn apply_tags(track: &mut Track, revision: &MetadataRevision) {
+    for tag in revision.tags() {
+        let field = match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => &mut track.title,
+            Some(StandardTagKey::Artist) => &mut track.artist,
+            Some(StandardTagKey::Album) => &mut track.album,
+            _ => continue,
+        };
+        field.get_or_insert_with(|| tag.value.to_string());
+    }
+}
+
+/// Scan `dirs` into `index` now, and then periodically.
+pub fn spawn_indexer(index: LibraryIndex, dirs: Vec<PathBuf>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RESCAN_INTERVAL);
+        loop {
+            interval.tick().await;
+            log::debug!("Scanning library directories {:?}", dirs);
+
+            let index = index.clone();
+            let dirs = dirs.clone();
+            match tokio::task::spawn_blocking(move || index.scan(&dirs)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => log::warn!("Library scan failed: {:#}", e),
+                Err(e) => log::warn!("Library scan panicked: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fts_query() {
+        assert_eq!(fts_query("  "), None);
+        assert_eq!(
+            fts_query("rick \"astley"),
+            Some("\"rick\"* \"astley\"*".to_string())
+        );
+    }
+
+    #[test]
+    fn test_scan_and_search() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("Never Gonna Give You Up.mp3"), "").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+        let index = LibraryIndex::open(None).unwrap();
+        index.scan(&[dir.path().to_path_buf()]).unwrap();
+
+        // Untagged files are found by filename
+        let results = index.search("gonna giv", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].path.ends_with("Never Gonna Give You Up.mp3"));
+        assert!(index.search("notes", 10).unwrap().is_empty());
+
+        index
+            .insert(
+                &Track {
+                    path: "/music/a.flac".to_string(),
+                    title: Some("Together Forever".to_string()),
+                    artist: Some("Rick Astley".to_string()),
+                    album: None,
+                },
+                0,
+            )
+            .unwrap();
+        assert_eq!(index.search("astley", 10).unwrap().len(), 1);
+
+        // Files that disappear are removed on the next scan
+        index.scan(&[dir.path().to_path_buf()]).unwrap();
+        assert!(index.search("astley", 10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,106 @@
+//! Measures how loud items are with ffmpeg, so that they can be played at a
+//! consistent loudness through mpv's replay gain options.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::process::Command;
+
+/// The loudness items are adjusted to, in LUFS. This is the ReplayGain 2.0 reference level.
+const REFERENCE_LOUDNESS: f64 = -18.0;
+
+/// Items are never adjusted by more than this many dB either way, so that quiet intros
+/// and silence don't end up deafening.
+const MAX_GAIN: f64 = 12.0;
+
+/// Only this much of every item is scanned, in seconds, to keep long mixes from
+/// taking forever.
+const SCAN_LENGTH: &str = "600";
+
+/// How long ffmpeg gets to scan a single item.
+const SCAN_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Find the integrated loudness in the summary printed by ffmpeg's `ebur128` filter.
+fn parse_ebur128_summary(output: &str) -> Option<f64> {
+    let (_, summary) = output.rsplit_once("Summary:")?;
+    let (_, integrated) = summary.split_once("Integrated loudness:")?;
+    let line = integrated
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("I:"))?;
+    line.trim_start_matches("I:")
+        .trim()
+        .trim_end_matches("LUFS")
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// The gain in dB that brings something with the given loudness to the reference level.
+fn gain_for(loudness: f64) -> f64 {
+    (REFERENCE_LOUDNESS - loudness).clamp(-MAX_GAIN, MAX_GAIN)
+}
+
+/// Per-file mpv options that play an item with the given gain, unless it has replay
+/// gain tags of its own.
+pub fn mpv_options(gain: f64) -> String {
+    format!("replaygain=track,replaygain-fallback={:.2}", gain)
+}
+
+/// Scan `input`, a local file or a direct media URL, and return the gain in dB it
+/// should be played with.
+pub async fn measure_gain(ffmpeg_path: &str, input: &str) -> anyhow::Result<f64> {
+    let output = tokio::time::timeout(
+        SCAN_TIMEOUT,
+        Command::new(ffmpeg_path)
+            .args([
+                "-hide_banner",
+                "-nostats",
+                "-nostdin",
+                "-t",
+                SCAN_LENGTH,
+                "-i",
+            ])
+            .arg(input)
+            .args(["-vn", "-af", "ebur128=framelog=quiet", "-f", "null", "-"])
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .context("ffmpeg timed out")?
+    .context("Failed to run ffmpeg")?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        anyhow::bail!(
+            "ffmpeg exited with {}: {}",
+            output.status,
+            stderr.lines().last().unwrap_or_default().trim()
+        );
+    }
+
+    let loudness = parse_ebur128_summary(&stderr).context("ffmpeg did not report a loudness")?;
+    Ok(gain_for(loudness))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ebur128_summary() {
+        let output = "[Parsed_ebur128_0 @ 0x55d5c8e0b400] Summary:\n\
+            \n  Integrated loudness:\n    I:         -11.4 LUFS\n    Threshold: -21.6 LUFS\n\
+            \n  Loudness range:\n    LRA:         5.2 LU\n";
+
+        let loudness = parse_ebur128_summary(output).unwrap();
+        assert_eq!(loudness, -11.4);
+        assert!((gain_for(loudness) - -6.6).abs() < 1e-9);
+        assert_eq!(gain_for(-70.0), MAX_GAIN);
+        assert_eq!(
+            mpv_options(-6.6),
+            "replaygain=track,replaygain-fallback=-6.60"
+        );
+        assert_eq!(parse_ebur128_summary("No summary here"), None);
+    }
+}
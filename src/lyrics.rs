@@ -0,0 +1,316 @@
+//! Fetches synced lyrics for music, so clients can show them karaoke style.
+//!
+//! Lyrics are looked up by artist and title when an item starts, from a provider with
+//! the same API as [LRCLIB](https://lrclib.net). While the item plays, every new line is
+//! announced to every mpv client as a `script-message`, which is how they reach the
+//! websocket and event stream clients.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use futures::StreamExt;
+use mpvipc_async::{Event, Mpv};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// The `script-message` sent when a new line of lyrics is reached, followed by its index,
+/// time and text.
+pub const LYRICS_LINE_MESSAGE: &str = "greg-lyrics-line";
+
+/// The public LRCLIB server.
+pub const DEFAULT_PROVIDER: &str = "https://lrclib.net";
+
+/// How often the playback position is checked while the current item has lyrics.
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long the provider gets to answer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LyricsLine {
+    /// When the line starts, in seconds.
+    pub time: f64,
+    pub text: String,
+}
+
+/// The lyrics of the current item.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrackLyrics {
+    pub artist: String,
+    pub title: String,
+    /// Empty if the provider only has unsynced lyrics.
+    pub lines: Vec<LyricsLine>,
+    pub plain: Option<String>,
+}
+
+/// Parse synced lyrics in the LRC format, where every line is prefixed with one or more
+/// `[mm:ss.xx]` timestamps. Lines without timestamps, like `[ar:Artist]` tags, are skipped.
+fn parse_lrc(lrc: &str) -> Vec<LyricsLine> {
+    let mut lines = Vec::new();
+
+    for line in lrc.lines() {
+        let mut rest = line.trim();
+        let mut times = Vec::new();
+        while let Some((tag, after)) = rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+            let Some(time) = tag
+                .split_once(':')
+                .and_then(|(m, s)| Some(m.parse::<f64>().ok()? * 60.0 + s.parse::<f64>().ok()?))
+            else {
+                break;
+            };
+            times.push(time);
+            rest = after;
+        }
+
+        for time in times {
+            lines.push(LyricsLine {
+                time,
+                text: rest.trim().to_string(),
+            });
+        }
+    }
+
+    lines.sort_by(|a, b| a.time.total_cmp(&b.time));
+    lines
+}
+
+/// The index of the line being sung at `time_pos`.
+fn line_at(lines: &[LyricsLine], time_pos: f64) -> Option<usize> {
+    lines
+        .partition_point(|line| line.time <= time_pos)
+        .checked_sub(1)
+}
+
+/// The index, time and text of a line announced with [`LYRICS_LINE_MESSAGE`].
+pub fn parse_line_message(args: &[String]) -> Option<(usize, f64, &str)> {
+    match args {
+        [message, index, time, text] if message == LYRICS_LINE_MESSAGE => {
+            Some((index.parse().ok()?, time.parse().ok()?, text))
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LrclibTrack {
+    duration: Option<f64>,
+    plain_lyrics: Option<String>,
+    synced_lyrics: Option<String>,
+}
+
+/// A client for an LRCLIB compatible lyrics provider.
+#[derive(Debug, Clone)]
+pub struct LyricsProvider {
+    client: reqwest::Client,
+    url: Url,
+}
+
+impl LyricsProvider {
+    pub fn new(url: Url) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("Failed to build HTTP client"),
+            url,
+        }
+    }
+
+    /// Look up the lyrics of a track, preferring the match closest to `duration`.
+    async fn fetch(
+        &self,
+        artist: &str,
+        title: &str,
+        duration: Option<f64>,
+    ) -> anyhow::Result<Option<TrackLyrics>> {
+        let mut url = self.url.join("/api/search")?;
+        url.query_pairs_mut()
+            .append_pair("artist_name", artist)
+            .append_pair("track_name", title);
+
+        let body = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let mut tracks: Vec<LrclibTrack> =
+            serde_json::from_slice(&body).context("Failed to parse the lyrics search results")?;
+
+        if let Some(duration) = duration {
+            tracks.sort_by(|a, b| {
+                let distance =
+                    |track: &LrclibTrack| (track.duration.unwrap_or(0.0) - duration).abs();
+                distance(a).total_cmp(&distance(b))
+            });
+        }
+        let Some(track) = tracks
+            .iter()
+            .find(|track| track.synced_lyrics.is_some())
+            .or(tracks.first())
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(TrackLyrics {
+            artist: artist.to_string(),
+            title: title.to_string(),
+            lines: track
+                .synced_lyrics
+                .as_deref()
+                .map(parse_lrc)
+                .unwrap_or_default(),
+            plain: track.plain_lyrics.clone(),
+        }))
+    }
+}
+
+/// The lyrics of whatever is playing, shared with the API.
+#[derive(Debug, Clone, Default)]
+pub struct Lyrics {
+    current: Arc<Mutex<Option<TrackLyrics>>>,
+}
+
+impl Lyrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn current(&self) -> Option<TrackLyrics> {
+        self.current.lock().unwrap().clone()
+    }
+
+    fn set(&self, lyrics: Option<TrackLyrics>) {
+        *self.current.lock().unwrap() = lyrics;
+    }
+}
+
+/// The artist and title of the current item, from its tags, or from a media title like
+/// `Artist - Title`, which is how most music videos are named.
+async fn artist_and_title(mpv: &Mpv) -> Option<(String, String)> {
+    let artist: Option<String> = mpv
+        .get_property("metadata/by-key/artist")
+        .await
+        .unwrap_or(None);
+    let title: Option<String> = mpv
+        .get_property("metadata/by-key/title")
+        .await
+        .unwrap_or(None);
+    if let (Some(artist), Some(title)) = (artist, title) {
+        return Some((artist, title));
+    }
+
+    let media_title: String = mpv.get_property("media-title").await.ok()??;
+    let (artist, title) = media_title.split_once(" - ")?;
+    // Drop suffixes like "(Official Video)".
+    let title = title.split(['(', '[']).next().unwrap_or(title);
+    Some((artist.trim().to_string(), title.trim().to_string()))
+}
+
+async fn fetch_current(mpv: &Mpv, provider: &LyricsProvider) -> Option<TrackLyrics> {
+    let (artist, title) = artist_and_title(mpv).await?;
+    let duration: Option<f64> = mpv.get_property("duration").await.unwrap_or(None);
+
+    match provider.fetch(&artist, &title, duration).await {
+        Ok(lyrics) => {
+            log::debug!(
+                "Found {} lyrics for '{} - {}'",
+                if lyrics.is_some() { "the" } else { "no" },
+                artist,
+                title
+            );
+            lyrics
+        }
+        Err(e) => {
+            log::debug!(
+                "Could not fetch lyrics for '{} - {}': {:#}",
+                artist,
+                title,
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Keep fetching the lyrics of whatever is playing into `lyrics`, and announce every line
+/// as it is reached, until mpv goes away.
+pub async fn run_lyrics(mpv: Mpv, provider: LyricsProvider, lyrics: Lyrics) -> anyhow::Result<()> {
+    let mut event_stream = mpv.get_event_stream().await;
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let mut lines: Vec<LyricsLine> = Vec::new();
+    let mut last_line: Option<usize> = None;
+
+    loop {
+        tokio::select! {
+            event = event_stream.next() => match event.context("Lost the mpv events")?? {
+                Event::FileLoaded => {
+                    let current = fetch_current(&mpv, &provider).await;
+                    lines = current.as_ref().map(|lyrics| lyrics.lines.clone()).unwrap_or_default();
+                    last_line = None;
+                    lyrics.set(current);
+                }
+                Event::EndFile { .. } => {
+                    lines.clear();
+                    lyrics.set(None);
+                }
+                Event::Shutdown => return Ok(()),
+                _ => {}
+            },
+
+            _ = interval.tick(), if !lines.is_empty() => {
+                let time_pos: Option<f64> = mpv.get_property("time-pos").await.unwrap_or(None);
+                let line = time_pos.and_then(|time_pos| line_at(&lines, time_pos));
+                if line == last_line {
+                    continue;
+                }
+                last_line = line;
+
+                if let Some(index) = line {
+                    let LyricsLine { time, text } = &lines[index];
+                    let result = mpv
+                        .run_command_raw(
+                            "script-message",
+                            &[LYRICS_LINE_MESSAGE, &index.to_string(), &time.to_string(), text],
+                        )
+                        .await;
+                    if let Err(e) = result {
+                        log::warn!("Failed to announce a line of lyrics: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lrc() {
+        let lrc = "[ar:Someone]\n\
+            [00:12.50]First line\n\
+            [00:20.00][01:20.00]Chorus\n\
+            [00:30.25]\n\
+            Not timed\n";
+
+        let lines = parse_lrc(lrc);
+        let times: Vec<f64> = lines.iter().map(|line| line.time).collect();
+        assert_eq!(times, vec![12.5, 20.0, 30.25, 80.0]);
+        assert_eq!(lines[3].text, "Chorus");
+
+        assert_eq!(line_at(&lines, 5.0), None);
+        assert_eq!(line_at(&lines, 12.5), Some(0));
+        assert_eq!(line_at(&lines, 25.0), Some(1));
+        assert_eq!(line_at(&lines, 200.0), Some(3));
+    }
+}
@@ -1,33 +1,137 @@
+use announcements::Announcements;
 use anyhow::Context;
+use api_keys::ApiKeys;
+use autoplay::{Autoplay, AutoplayFilter};
 use axum::Router;
-use clap::Parser;
+use axum_server::tls_rustls::RustlsConfig;
+use bans::BanList;
+use bookmarks::Bookmarks;
+use clap::{Parser, Subcommand};
 use clap_verbosity_flag::Verbosity;
+use control_lock::ControlLock;
 use futures::StreamExt;
+use history::PlayHistory;
+use idle_policy::{IdleAction, IdlePolicy, IdlePolicyHandle};
+use inputs::{InputSpec, Inputs};
+use library::LibraryIndex;
+use lyrics::{Lyrics, LyricsProvider};
+use mpv_log::MpvLog;
 use mpv_setup::{connect_to_mpv, create_mpv_config_file, show_grzegorz_image};
 use mpvipc_async::{Event, Mpv, MpvDataType, MpvExt};
+use oidc::Oidc;
+use party::{PartyMode, PartyPool, PartySource};
+use playback_clock::PlaybackClock;
+use player::{
+    Backend, CircuitBreaker, DlnaPlayer, GuardedPlayer, MpvPlayer, Player, PlayerHandle,
+    PlaylistClearGuard, RetryPolicy, RetryQueue, WindowedPlayer,
+};
+use playlists::PlaylistStore;
+use policy::{Policies, PolicyKind};
+use quotas::Quotas;
+use radio::{Radio, Station};
+use read_only::ReadOnlyMode;
+use resolver::{ResolverChain, SpotifyResolver};
+use resume::ResumePositions;
+use server::{ApiListener, ListenAddr};
+use session::{Session, Takeover};
+use soundboard::{ClipSpec, Soundboard};
+use sponsorblock::SponsorBlock;
+use startup_checks::StartupCheckArgs;
 use std::{
-    net::{IpAddr, SocketAddr},
-    sync::{Arc, Mutex},
+    fs::DirBuilder,
+    net::IpAddr,
+    os::unix::fs::{DirBuilderExt, MetadataExt},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
 };
+use storage::{SqliteStorage, StorageHandle};
 use systemd_journal_logger::JournalLog;
 use tempfile::NamedTempFile;
 use tokio::{sync::mpsc, task::JoinHandle};
-use util::{ConnectionEvent, IdPool};
+use upload::UploadSpool;
+use util::{ClientRegistry, ConnectionEvent, IdPool, IdPoolHandle};
+use volume_transition::VolumeTransitionEngine;
+use webhooks::{WebhookEvent, Webhooks};
 
+mod announcements;
 mod api;
+mod api_keys;
+mod autoplay;
+mod bans;
+mod bookmarks;
+mod control_lock;
+mod ctl;
+mod expiry;
+mod frontend;
+mod history;
+mod idle_policy;
+mod inputs;
+mod library;
+mod loudness;
+mod lyrics;
+mod mpv_log;
 mod mpv_setup;
+mod notifier;
+mod oidc;
+mod party;
+mod playback_clock;
+mod player;
+mod playlists;
+mod policy;
+mod prefetch;
+mod quotas;
+mod radio;
+mod read_only;
+mod resolver;
+mod resume;
+mod scopes;
+mod server;
+mod session;
+mod soundboard;
+mod sponsorblock;
+mod startup_checks;
+mod stats;
+mod storage;
+mod sync;
+#[cfg(test)]
+mod test_support;
+mod tls;
+mod upload;
 mod util;
+mod volume_transition;
+mod watchdog;
+mod webhooks;
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+#[command(group(
+    clap::ArgGroup::new("admin_credential")
+        .args(["admin_token_file", "oidc_admin_group"])
+        .multiple(true)
+))]
 struct Args {
-    /// Hostname to bind the different APIs to.
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Hostname to bind the different APIs to. Can be given multiple times.
+    ///
+    /// All addresses the hostname resolves to are bound. `::` accepts both IPv6 and IPv4
+    /// connections where the system supports dual-stack sockets.
     #[clap(long, default_value = "localhost")]
-    host: String,
+    host: Vec<String>,
 
     /// Port to bind the different APIs to.
     #[clap(short, long, default_value = "8008")]
     port: u16,
 
+    /// Where to serve the APIs, either `<host>:<port>` or `unix:<path>`. Can be given multiple
+    /// times, and overrides --host and --port.
+    ///
+    /// When started through systemd socket activation, the sockets passed by systemd are used instead.
+    #[clap(long, value_name = "ADDR")]
+    listen: Vec<ListenAddr>,
+
     #[command(flatten)]
     verbose: Verbosity,
 
@@ -35,9 +139,43 @@ struct Args {
     #[clap(long)]
     systemd: bool,
 
+    /// Which player backend to use.
+    #[clap(long, value_enum, default_value = "mpv")]
+    backend: Backend,
+
+    /// URL of the device description of the DLNA renderer to cast to, when using the dlna backend.
+    #[clap(long, value_name = "URL", required_if_eq("backend", "dlna"))]
+    dlna_renderer: Option<String>,
+
+    /// Require confirmation before clearing a playlist with more than this many items.
+    ///
+    /// Clients confirm by passing `force=true`, or by repeating the request with the
+    /// token from the first attempt.
+    #[clap(long, value_name = "N")]
+    clear_confirm_threshold: Option<usize>,
+
+    /// Where to keep persistent data, like saved playlists.
+    ///
+    /// Defaults to `$XDG_STATE_HOME/greg-ng`, or `~/.local/state/greg-ng`.
+    #[clap(long, value_name = "PATH")]
+    data_dir: Option<PathBuf>,
+
+    /// Where to keep the database with saved playlists and the play history.
+    /// Defaults to `greg.db` in the data directory.
+    #[clap(long, value_name = "PATH")]
+    db_path: Option<PathBuf>,
+
     /// Location of the mpv socket. If none is found, this path will be used when mpv is started.
-    #[clap(long, value_name = "PATH", default_value = "/run/mpv/mpv.sock")]
-    mpv_socket_path: String,
+    /// Defaults to `mpv.sock` in the runtime directory.
+    #[clap(long, value_name = "PATH")]
+    mpv_socket_path: Option<String>,
+
+    /// Where the mpv socket and other files that only live as long as greg-ng are kept.
+    ///
+    /// Defaults to `/run/mpv` when running as a systemd service, and otherwise to
+    /// `$XDG_RUNTIME_DIR/greg-ng`, so that several users can run greg-ng side by side.
+    #[clap(long, value_name = "PATH")]
+    runtime_dir: Option<PathBuf>,
 
     /// Location of the mpv binary.
     #[clap(long, value_name = "PATH")]
@@ -55,6 +193,421 @@ struct Args {
     /// started.
     #[clap(long, default_value = "true")]
     force_auto_start: bool,
+
+    /// How many times to try connecting to mpv, waiting longer between every attempt,
+    /// before giving up.
+    #[clap(long, value_name = "COUNT", default_value = "20")]
+    mpv_connect_attempts: u32,
+
+    /// How long to wait for mpv to accept a connection, in seconds, before giving up.
+    #[clap(long, value_name = "SECONDS", default_value = "10")]
+    mpv_connect_timeout: u64,
+
+    /// Have the mpv started by greg-ng write a detailed log to this file, in addition
+    /// to the output that is kept for `/api/admin/mpv-log`.
+    #[clap(long, value_name = "PATH")]
+    mpv_log_file: Option<PathBuf>,
+
+    /// Start mpv without a window, for servers without X or Wayland, and for CI.
+    /// Audio is still played, unless the mpv config says `ao=null`.
+    #[clap(long)]
+    headless: bool,
+
+    /// What to do when another greg-ng instance is already in charge of the mpv at
+    /// --mpv-socket-path.
+    #[clap(long, value_enum, default_value_t, conflicts_with = "dlna_renderer")]
+    takeover: Takeover,
+
+    /// Don't show the Grzegorz image on startup. It is only shown when mpv is idle
+    /// with an empty playlist anyway, so attaching to a running mpv leaves it alone.
+    #[clap(long)]
+    no_splash: bool,
+
+    /// Don't start playing when something is queued while the idle screen is showing,
+    /// and wait for someone to press play instead.
+    #[clap(long)]
+    no_wake_on_queue: bool,
+
+    /// Give up on calls to the player that take longer than this many seconds. After a few
+    /// in a row, calls fail right away for a while, so that requests don't pile up.
+    #[clap(long, value_name = "SECONDS", default_value = "5")]
+    player_timeout: u64,
+
+    /// Exit when mpv has not responded for this many seconds, so that the service manager
+    /// can start it over. 0 never exits.
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value = "60",
+        conflicts_with = "dlna_renderer"
+    )]
+    exit_when_unresponsive: u64,
+
+    /// Only keep the current item and this many after it in mpv, and hold the rest of the
+    /// queue until mpv gets to them. Held items survive mpv restarting, but not greg-ng.
+    #[clap(long, value_name = "ITEMS", conflicts_with = "dlna_renderer")]
+    queue_window: Option<usize>,
+
+    /// The checks every change goes through, in order. Leave out the ones this deployment
+    /// does not want. Without `read-only`, read-only mode can not be turned on.
+    #[clap(
+        long,
+        value_name = "POLICY",
+        value_enum,
+        value_delimiter = ',',
+        default_values = ["read-only", "control-lock", "scopes", "quota"],
+    )]
+    policy: Vec<PolicyKind>,
+
+    /// How many items each client can add within about a half-life. Clients that have not
+    /// logged in are told apart by their address and user agent. Without it, how much each
+    /// client adds is only tracked.
+    #[clap(long, value_name = "ITEMS", value_parser = clap::value_parser!(u32).range(1..))]
+    quota_limit: Option<u32>,
+
+    /// How long it takes for half of what a client has added to stop counting.
+    #[clap(long, value_name = "MINUTES", default_value = "30", value_parser = clap::value_parser!(u64).range(1..))]
+    quota_half_life: u64,
+
+    /// Let clients take turns in the playlist: items added with `/api/load` go ahead of the
+    /// items of clients that have had more turns. Clients are told apart like for the quota,
+    /// so it needs the quota policy, and admins are left out.
+    #[clap(long)]
+    fair_queue: bool,
+
+    /// Fade the volume down when pausing, and back up when resuming.
+    #[clap(long)]
+    duck_on_pause: bool,
+
+    /// Serve a web UI from this directory at `/`, alongside the APIs.
+    ///
+    /// Paths that don't match a file are answered with `index.html`, for client side routing.
+    #[clap(long, value_name = "PATH")]
+    frontend_dir: Option<PathBuf>,
+
+    /// Path to a PEM encoded TLS certificate (chain). If given together with --tls-key,
+    /// the APIs are served over HTTPS and WSS. The files are reloaded when they change.
+    #[clap(long, value_name = "PATH", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// Path to the PEM encoded private key belonging to --tls-cert.
+    #[clap(long, value_name = "PATH", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Follow the playlist and playback position of another greg-ng instance,
+    /// given as the websocket URL of its sync endpoint, e.g. `ws://leader:8008/sync`.
+    ///
+    /// Every instance serves the sync endpoint, so any of them can act as the leader.
+    /// The clocks of the instances should be kept in sync, e.g. with NTP.
+    #[clap(long, value_name = "URL", conflicts_with = "dlna_renderer")]
+    sync_leader: Option<String>,
+
+    /// Another greg-ng instance to forward queue operations to, as `<name>=<url>`,
+    /// e.g. `terminalen=http://terminalen:8008`. Can be given multiple times.
+    ///
+    /// Items are added to a remote with `POST /api/remote/<name>/load`.
+    #[clap(long, value_name = "NAME=URL")]
+    remote: Vec<api::Remote>,
+
+    /// A soundboard clip, as `<name>=<path>`, e.g. `airhorn=/srv/clips/airhorn.mp3`.
+    /// Can be given multiple times.
+    ///
+    /// Clips are listed at `GET /api/soundboard`, and played over whatever is on with
+    /// `POST /api/soundboard/<name>`.
+    #[clap(long, value_name = "NAME=PATH")]
+    soundboard_clip: Vec<ClipSpec>,
+
+    /// A web radio station, as `<name>=<url>`, e.g. `p3=https://lyd.nrk.no/nrk_radio_p3_mp3_h`.
+    /// Can be given multiple times.
+    ///
+    /// Stations are listed at `GET /api/radio`, and tuned into with
+    /// `POST /api/radio/<name>/play`.
+    #[clap(long, value_name = "NAME=URL")]
+    radio_station: Vec<Station>,
+
+    /// An external input mpv can be switched over to, as `<name>=<url>`, e.g.
+    /// `console=av://v4l2:/dev/video0` for a capture card. Can be given multiple times.
+    ///
+    /// Inputs are activated with `POST /api/inputs/<name>/activate`, which suspends the
+    /// playlist until `POST /api/inputs/deactivate`.
+    #[clap(long, value_name = "NAME=URL", conflicts_with = "dlna_renderer")]
+    input: Vec<InputSpec>,
+
+    /// Resolve the next few playlist items with yt-dlp ahead of time, to reduce the
+    /// gap between tracks. Requires mpv 0.38 or newer.
+    #[clap(long, conflicts_with_all = ["dlna_renderer", "sync_leader"])]
+    prefetch: bool,
+
+    /// Let mpv open the next playlist item while the current one is playing, to avoid a
+    /// gap between them. Can also be toggled through `/api/v2/playback/prefetch`.
+    #[clap(long, conflicts_with = "dlna_renderer")]
+    prefetch_playlist: bool,
+
+    /// How much mpv buffers ahead, in megabytes. Uses mpv's default if not given.
+    #[clap(long, value_name = "MB", conflicts_with = "dlna_renderer")]
+    cache_size: Option<u64>,
+
+    /// The hardware decoding mode mpv starts with, like `auto`, `vaapi` or `none`.
+    /// Can be switched at runtime through `/api/v2/playback/hwdec`.
+    #[clap(long, value_name = "MODE", conflicts_with = "dlna_renderer")]
+    hwdec: Option<String>,
+
+    /// The display output mpv is shown and goes fullscreen on, by number like `1`, or by
+    /// name like `HDMI-A-1`. Can be switched at runtime through `/api/display/screen`.
+    #[clap(long, value_name = "SCREEN", conflicts_with = "dlna_renderer")]
+    screen: Option<mpv_setup::Screen>,
+
+    /// Skip items that make no progress for this many seconds while not paused, like
+    /// dead streams. Items mpv fails to open are always skipped.
+    #[clap(long, value_name = "SECONDS", conflicts_with = "dlna_renderer")]
+    playback_timeout: Option<u64>,
+
+    /// Where party mode picks items from when the playlist runs out: `playlist:<name>`
+    /// for a saved playlist, `library` or `library:<dir>` for the media library, or
+    /// `related` for the YouTube mix of the last played video.
+    #[clap(long, value_name = "SOURCE", conflicts_with = "dlna_renderer")]
+    party_source: Option<PartySource>,
+
+    /// Start with party mode on. It can be turned on and off through `/api/party`.
+    #[clap(long, requires = "party_source")]
+    party_mode: bool,
+
+    /// A clip to slip in after the item playing now every `--announcement-interval`, like a
+    /// sponsor jingle. Several clips take turns, in the order they are given.
+    #[clap(
+        long = "announcement",
+        value_name = "PATH",
+        conflicts_with = "dlna_renderer"
+    )]
+    announcements: Vec<String>,
+
+    /// How many minutes apart announcements are made.
+    #[clap(long, value_name = "MINUTES", default_value = "30", value_parser = clap::value_parser!(u64).range(1..))]
+    announcement_interval: u64,
+
+    /// Start with announcements on. They can be turned on and off through
+    /// `/api/announcements`.
+    #[clap(long, requires = "announcements")]
+    announce: bool,
+
+    /// Skip sponsored segments and the like in YouTube videos, using SponsorBlock.
+    #[clap(long, conflicts_with = "dlna_renderer")]
+    sponsorblock: bool,
+
+    /// The SponsorBlock categories to skip, like `sponsor`, `intro`, `outro`,
+    /// `selfpromo`, `interaction` or `music_offtopic`.
+    #[clap(
+        long,
+        value_name = "CATEGORY",
+        value_delimiter = ',',
+        default_values = sponsorblock::DEFAULT_CATEGORIES,
+    )]
+    sponsorblock_categories: Vec<String>,
+
+    /// The SponsorBlock server to fetch segments from.
+    #[clap(long, value_name = "URL", default_value = sponsorblock::DEFAULT_SERVER)]
+    sponsorblock_server: url::Url,
+
+    /// Fetch synced lyrics for music, which are served at `/api/lyrics` and sent to
+    /// clients line by line as they are sung.
+    #[clap(long, conflicts_with = "dlna_renderer")]
+    lyrics: bool,
+
+    /// The LRCLIB compatible server to fetch lyrics from.
+    #[clap(long, value_name = "URL", default_value = lyrics::DEFAULT_PROVIDER)]
+    lyrics_provider: url::Url,
+
+    /// Forget what was played more than this many days ago. By default, only the number
+    /// of entries is limited.
+    #[clap(long, value_name = "DAYS", conflicts_with = "dlna_renderer")]
+    history_retention_days: Option<u64>,
+
+    /// Start with autoplay on, which plays a related video when the playlist runs out.
+    /// It can be turned on and off through `/api/autoplay`.
+    #[clap(long, conflicts_with = "dlna_renderer")]
+    autoplay: bool,
+
+    /// Don't autoplay videos shorter than this many seconds.
+    #[clap(long, value_name = "SECONDS", conflicts_with = "dlna_renderer")]
+    autoplay_min_duration: Option<u64>,
+
+    /// Don't autoplay videos longer than this many seconds.
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value = "900",
+        conflicts_with = "dlna_renderer"
+    )]
+    autoplay_max_duration: u64,
+
+    /// Don't autoplay videos with this in their title, channel or url, ignoring case.
+    /// Can be given multiple times.
+    #[clap(long, value_name = "WORD", conflicts_with = "dlna_renderer")]
+    autoplay_deny: Vec<String>,
+
+    /// Remember how far into items at least this many seconds long playback got, so they
+    /// can be queued with `resume` to continue from there.
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value = "1200",
+        conflicts_with = "dlna_renderer"
+    )]
+    resume_min_duration: u64,
+
+    /// Forget how far into an item playback got after this many days.
+    #[clap(
+        long,
+        value_name = "DAYS",
+        default_value = "30",
+        conflicts_with = "dlna_renderer"
+    )]
+    resume_days: u64,
+
+    /// Remove items that have waited in the playlist for this many hours without being
+    /// played, so that old links do not suddenly start playing. Items can be given their
+    /// own TTL when they are added, with `ttl_hours`.
+    #[clap(long, value_name = "HOURS", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "dlna_renderer")]
+    item_ttl: Option<u64>,
+
+    /// Quiet background music when no clients have been connected for this many minutes,
+    /// and bring it back when someone connects. Background music is whatever autoplay
+    /// picked, and items flagged as such through `/api/admin/playlist/flags`.
+    #[clap(long, value_name = "MINUTES", conflicts_with = "dlna_renderer")]
+    idle_pause_after: Option<u64>,
+
+    /// What to do with background music when nobody is around.
+    #[clap(long, value_enum, default_value_t, conflicts_with = "dlna_renderer")]
+    idle_action: IdleAction,
+
+    /// The volume to turn background music down to, with `--idle-action lower-volume`.
+    #[clap(
+        long,
+        value_name = "VOLUME",
+        default_value = "20",
+        conflicts_with = "dlna_renderer"
+    )]
+    idle_volume: f64,
+
+    /// Retry items that fail to play this many times before giving up on them.
+    #[clap(
+        long,
+        value_name = "ATTEMPTS",
+        default_value = "0",
+        conflicts_with = "dlna_renderer"
+    )]
+    retry_attempts: u32,
+
+    /// How long to wait before retrying a failed item. Doubles with every attempt.
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value = "5",
+        conflicts_with = "dlna_renderer"
+    )]
+    retry_backoff: u64,
+
+    /// A yt-dlp format selection to retry failed items with, like `best[height<=720]`.
+    #[clap(long, value_name = "FORMAT", conflicts_with = "dlna_renderer")]
+    retry_format: Option<String>,
+
+    /// A directory of local media files to index, and make searchable through
+    /// `/api/library/search`. Can be given multiple times.
+    #[clap(long, value_name = "PATH")]
+    library_dir: Vec<PathBuf>,
+
+    /// Where to store the library index. If not given, the index is kept in memory
+    /// and rebuilt on every start.
+    #[clap(long, value_name = "PATH", requires = "library_dir")]
+    library_index: Option<PathBuf>,
+
+    /// Accept media file uploads through `POST /api/upload`, storing them in this
+    /// directory until they have been played.
+    #[clap(long, value_name = "PATH")]
+    upload_dir: Option<PathBuf>,
+
+    /// The largest file that can be uploaded, in megabytes.
+    #[clap(long, value_name = "MB", default_value = "200")]
+    max_upload_size: u64,
+
+    /// Location of the yt-dlp binary, used by --prefetch.
+    #[clap(long, value_name = "PATH", default_value = "yt-dlp")]
+    yt_dlp_path: String,
+
+    /// Measure the loudness of upcoming items with ffmpeg, and adjust their volume so
+    /// that consecutive items play equally loud. Items with replay gain tags use those.
+    #[clap(long, requires = "prefetch")]
+    replay_gain: bool,
+
+    /// Location of the ffmpeg binary, used by --replay-gain.
+    #[clap(long, value_name = "PATH", default_value = "ffmpeg")]
+    ffmpeg_path: String,
+
+    /// POST player events to a webhook, given as `[<event>,...=]<url>`. Can be given multiple times.
+    ///
+    /// The events are track_started, track_finished, playlist_emptied and player_crashed.
+    /// Without a list of events, every event is sent.
+    #[clap(long, value_name = "[EVENTS=]URL", conflicts_with = "dlna_renderer")]
+    webhook: Vec<webhooks::Webhook>,
+
+    /// A file containing the secret used to sign webhook requests, in the
+    /// `X-Greg-Signature` header.
+    #[clap(long, value_name = "PATH", requires = "webhook")]
+    webhook_secret_file: Option<PathBuf>,
+
+    /// Post what is playing and being queued to a chat. Can be given multiple times.
+    ///
+    /// Either `matrix://<homeserver>/<room id>` or `irc://<host>[:<port>]/<channel>[?nick=<nick>]`.
+    #[clap(long, value_name = "URL", conflicts_with = "dlna_renderer")]
+    notify: Vec<notifier::NotifyTarget>,
+
+    /// A file containing the Matrix access token used by --notify.
+    #[clap(long, value_name = "PATH", requires = "notify")]
+    matrix_token_file: Option<PathBuf>,
+
+    /// An mpv property that can be read and written through `/api/property/<name>`.
+    /// Can be given multiple times, and replaces the default list of properties.
+    #[clap(
+        long,
+        value_name = "NAME",
+        conflicts_with = "dlna_renderer",
+        default_values = api::DEFAULT_PROPERTIES
+    )]
+    mpv_property: Vec<String>,
+
+    /// A file containing the token required by the `/api/admin` endpoints. Without it, and
+    /// unless admins can log in with --oidc-admin-group, the admin endpoints, like raw mpv
+    /// commands, bans, and pinning and locking playlist items, are disabled.
+    #[clap(long, value_name = "PATH", conflicts_with = "dlna_renderer")]
+    admin_token_file: Option<PathBuf>,
+
+    /// Only allow these mpv commands through the admin API. Can be given multiple times.
+    /// By default, every command that is not denied is allowed.
+    #[clap(long, value_name = "COMMAND", requires = "admin_credential")]
+    mpv_command_allow: Vec<String>,
+
+    /// Never allow these mpv commands through the admin API. Can be given multiple times,
+    /// and replaces the default list of commands that run programs, load scripts, write
+    /// files or quit mpv.
+    #[clap(
+        long,
+        value_name = "COMMAND",
+        requires = "admin_credential",
+        default_values = api::DEFAULT_DENIED_COMMANDS
+    )]
+    mpv_command_deny: Vec<String>,
+
+    #[command(flatten)]
+    oidc: oidc::OidcArgs,
+}
+
+/// How long to keep trying to report a crash to webhooks before shutting down.
+const WEBHOOK_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Subcommand)]
+enum Command {
+    /// Control a running instance, e.g. `greg-ng ctl volume 50`.
+    Ctl(ctl::CtlArgs),
 }
 
 struct MpvConnectionArgs<'a> {
@@ -63,18 +616,62 @@ struct MpvConnectionArgs<'a> {
     config_file: &'a NamedTempFile,
     auto_start: bool,
     force_auto_start: bool,
+    log_file: Option<PathBuf>,
+    headless: bool,
+    retry: mpv_setup::ConnectRetry,
+    /// Report waiting for mpv as the systemd status.
+    systemd: bool,
 }
 
-/// Helper function to resolve a hostname to an IP address.
+/// Helper function to resolve a hostname to all of its IP addresses.
 /// Why is this not in the standard library? >:(
-async fn resolve(host: &str) -> anyhow::Result<IpAddr> {
+async fn resolve(host: &str) -> anyhow::Result<Vec<IpAddr>> {
     let addr = format!("{}:0", host);
-    let addresses = tokio::net::lookup_host(addr).await?;
-    addresses
-        .into_iter()
-        .find(|addr| addr.is_ipv4())
-        .map(|addr| addr.ip())
-        .ok_or_else(|| anyhow::anyhow!("Failed to resolve address"))
+    let mut addresses: Vec<IpAddr> = Vec::new();
+    for addr in tokio::net::lookup_host(addr).await? {
+        if !addresses.contains(&addr.ip()) {
+            addresses.push(addr.ip());
+        }
+    }
+
+    if addresses.is_empty() {
+        anyhow::bail!("Failed to resolve address");
+    }
+
+    Ok(addresses)
+}
+
+/// The default location for persistent data, following the XDG base directory spec.
+fn default_data_dir() -> anyhow::Result<PathBuf> {
+    let state_home = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = std::env::var_os("HOME")
+                .context("Neither XDG_STATE_HOME nor HOME is set, please pass --data-dir")?;
+            PathBuf::from(home).join(".local/state")
+        }
+    };
+
+    Ok(state_home.join("greg-ng"))
+}
+
+/// The runtime directory used when `--runtime-dir` is not given.
+fn default_runtime_dir(systemd_mode: bool) -> PathBuf {
+    if systemd_mode {
+        return PathBuf::from("/run/mpv");
+    }
+
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("greg-ng"),
+        // The temp dir is shared between users, so the directory is made per user.
+        _ => {
+            let uid = std::fs::metadata("/proc/self")
+                .map(|metadata| metadata.uid().to_string())
+                .or_else(|_| std::env::var("USER"))
+                .unwrap_or_default();
+            std::env::temp_dir().join(format!("greg-ng-{}", uid))
+        }
+    }
 }
 
 /// Helper function that spawns a tokio thread that
@@ -196,6 +793,176 @@ async fn start_status_notifier_thread(
     Ok(handle)
 }
 
+/// Everything the routes are built from besides the player, shared by every backend.
+struct AppServices {
+    clear_guard: PlaylistClearGuard,
+    retries: RetryQueue,
+    autoplay: Autoplay,
+    resolvers: ResolverChain,
+    pending_loads: api::PendingLoads,
+    playlist_store: PlaylistStore,
+    bookmarks: Bookmarks,
+    upload_spool: Option<UploadSpool>,
+    remotes: Vec<api::Remote>,
+    library: Option<LibraryIndex>,
+    soundboard: Soundboard,
+    radio: Radio,
+    frontend_dir: Option<PathBuf>,
+    breaker: CircuitBreaker,
+    wake_on_queue: bool,
+}
+
+/// The routes that work with every player backend.
+fn app_routes(
+    player: PlayerHandle,
+    volume_engine: VolumeTransitionEngine,
+    services: AppServices,
+) -> Router {
+    player
+        .queue_state()
+        .set_wake_on_queue(services.wake_on_queue);
+    let status = api::StatusTracker::spawn(player.clone());
+    tokio::spawn(api::flush_queued_loads(
+        player.clone(),
+        services.resolvers.clone(),
+        services.pending_loads.clone(),
+    ));
+    tokio::spawn(api::release_deferred_loads(
+        player.clone(),
+        services.resolvers.clone(),
+        services.pending_loads.clone(),
+    ));
+    let etag_layer = axum::middleware::from_fn_with_state(status.clone(), api::revision_etag);
+
+    let mut app = Router::new()
+        .nest(
+            "/api/v2",
+            api::rest_api_v2_routes(
+                player.clone(),
+                volume_engine.clone(),
+                services.clear_guard.clone(),
+                services.retries.clone(),
+                services.autoplay.clone(),
+                services.resolvers.clone(),
+                services.pending_loads.clone(),
+            )
+            .layer(etag_layer.clone()),
+        )
+        .nest(
+            "/api",
+            api::rest_api_routes(
+                player.clone(),
+                volume_engine.clone(),
+                services.clear_guard.clone(),
+                services.retries.clone(),
+                services.autoplay.clone(),
+                services.resolvers.clone(),
+                services.pending_loads.clone(),
+            )
+            .layer(etag_layer),
+        )
+        .merge(api::rest_api_docs(
+            player.clone(),
+            volume_engine.clone(),
+            services.clear_guard,
+            services.retries,
+            services.autoplay,
+            services.resolvers.clone(),
+            services.pending_loads.clone(),
+        ))
+        .merge(api::block_routes(player.clone()))
+        .merge(api::control_page_routes(player.clone()))
+        .merge(sync::sync_leader_routes(player.clone()))
+        .merge(api::playlist_routes(
+            player.clone(),
+            services.playlist_store,
+        ))
+        .merge(api::bookmark_routes(
+            player.clone(),
+            services.resolvers,
+            services.pending_loads.clone(),
+            services.bookmarks,
+        ))
+        .merge(api::remote_routes(services.remotes))
+        .merge(api::radio_routes(player.clone(), services.radio))
+        .merge(api::status_routes(status))
+        .merge(api::health_routes(services.breaker, services.pending_loads));
+
+    if let Some(spool) = services.upload_spool {
+        spool.spawn_gc(player.clone());
+        app = app.merge(api::upload_routes(player, spool));
+    }
+
+    if let Some(library) = services.library {
+        app = app.merge(api::library_routes(library));
+    }
+
+    if !services.soundboard.is_empty() {
+        app = app.merge(api::soundboard_routes(volume_engine, services.soundboard));
+    }
+
+    if let Some(frontend_dir) = services.frontend_dir {
+        log::info!("Serving web UI from '{}'", frontend_dir.display());
+        app = app.merge(frontend::frontend_routes(&frontend_dir));
+    }
+
+    app
+}
+
+/// Cast to a DLNA renderer instead of playing with mpv.
+///
+/// The websocket API relies on mpv events, and is not available with this backend.
+async fn run_dlna_backend(
+    renderer_url: &str,
+    duck_on_pause: bool,
+    services: AppServices,
+    listen_addrs: &[ListenAddr],
+    tls_config: Option<RustlsConfig>,
+    systemd_mode: bool,
+) -> anyhow::Result<()> {
+    let player: PlayerHandle = Arc::new(GuardedPlayer::new(
+        Arc::new(
+            DlnaPlayer::connect(renderer_url)
+                .await
+                .context("Failed to connect to DLNA renderer")?,
+        ),
+        services.breaker.clone(),
+    ));
+    let volume_engine = VolumeTransitionEngine::new(player.clone(), duck_on_pause);
+    let app = app_routes(player, volume_engine, services);
+
+    let listeners = bind_listeners(listen_addrs).await?;
+
+    if systemd_mode {
+        sd_notify::notify(&[sd_notify::NotifyState::Ready])
+            .context("Failed to notify systemd that the service is ready")?;
+    }
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {
+            log::info!("Received Ctrl-C, exiting");
+        }
+        result = server::serve_api(listeners, app, tls_config) => {
+            log::info!("API server exited");
+            result?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Use the sockets passed by systemd if started through socket activation,
+/// otherwise bind to the given addresses.
+async fn bind_listeners(listen_addrs: &[ListenAddr]) -> anyhow::Result<Vec<ApiListener>> {
+    let listeners = ApiListener::from_systemd()?;
+    if !listeners.is_empty() {
+        log::info!("Using {} socket(s) passed by systemd", listeners.len());
+        return Ok(listeners);
+    }
+
+    ApiListener::bind_all(listen_addrs).await
+}
+
 async fn shutdown(mpv: Mpv, proc: Option<tokio::process::Child>) {
     log::info!("Shutting down");
     sd_notify::notify(&[sd_notify::NotifyState::Stopping]).unwrap_or_else(|e| {
@@ -219,6 +986,10 @@ async fn shutdown(mpv: Mpv, proc: Option<tokio::process::Child>) {
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
+    if let Some(Command::Ctl(ctl_args)) = args.command {
+        return ctl::run(ctl_args).await;
+    }
+
     let systemd_mode = args.systemd && sd_notify::booted().unwrap_or(false);
     if systemd_mode {
         JournalLog::new()
@@ -239,57 +1010,557 @@ async fn main() -> anyhow::Result<()> {
         log::info!("Running without systemd integration");
     }
 
-    let mpv_config_file = create_mpv_config_file(args.mpv_config_file)?;
+    let listen_addrs = if args.listen.is_empty() {
+        args.host
+            .into_iter()
+            .map(|host| ListenAddr::Tcp {
+                host,
+                port: args.port,
+            })
+            .collect()
+    } else {
+        args.listen
+    };
+
+    let runtime_dir = args
+        .runtime_dir
+        .unwrap_or_else(|| default_runtime_dir(systemd_mode));
+    let mpv_socket_path = args
+        .mpv_socket_path
+        .unwrap_or_else(|| runtime_dir.join("mpv.sock").to_string_lossy().into_owned());
+
+    let data_dir = match args.data_dir {
+        Some(dir) => dir,
+        None => default_data_dir()?,
+    };
+    let db_path = args.db_path.unwrap_or(data_dir.join("greg.db"));
 
-    let (mpv, proc) = connect_to_mpv(&MpvConnectionArgs {
-        socket_path: args.mpv_socket_path,
-        executable_path: args.mpv_executable_path,
-        config_file: &mpv_config_file,
-        auto_start: args.auto_start_mpv,
-        force_auto_start: args.force_auto_start,
+    let startup_check_results = startup_checks::run_startup_checks(&StartupCheckArgs {
+        hosts: listen_addrs
+            .iter()
+            .filter_map(|addr| match addr {
+                ListenAddr::Tcp { host, .. } => Some(host.as_str()),
+                ListenAddr::Unix(_) => None,
+            })
+            .collect(),
+        mpv_socket_path: &mpv_socket_path,
+        mpv_executable_path: args.mpv_executable_path.as_deref(),
+        mpv_config_file: args.mpv_config_file.as_deref(),
+        auto_start_mpv: args.auto_start_mpv,
+        uses_mpv: args.backend == Backend::Mpv,
+        db_path: &db_path,
     })
-    .await
-    .context("Failed to connect to mpv")?;
+    .await;
+    startup_checks::report_startup_checks(&startup_check_results)?;
+
+    let tls_config = match (args.tls_cert, args.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::load_tls_config(cert, key).await?),
+        _ => None,
+    };
+
+    let library = if args.library_dir.is_empty() {
+        None
+    } else {
+        let library = LibraryIndex::open(args.library_index.as_deref())?;
+        library::spawn_indexer(library.clone(), args.library_dir);
+        Some(library)
+    };
+
+    log::debug!("Keeping persistent data in {}", data_dir.display());
+    let storage: StorageHandle = Arc::new(SqliteStorage::open(Some(&db_path))?);
+    let playlist_store = PlaylistStore::new(storage.clone());
+    let bans = BanList::load(storage.clone())?;
+    let api_keys = ApiKeys::load(storage.clone())?;
+    playlist_store.import_files(&data_dir.join("playlists"))?;
+
+    let services = AppServices {
+        clear_guard: PlaylistClearGuard::new(args.clear_confirm_threshold),
+        retries: RetryQueue::new(RetryPolicy {
+            attempts: args.retry_attempts,
+            backoff: Duration::from_secs(args.retry_backoff),
+            fallback_format: args.retry_format,
+        }),
+        autoplay: Autoplay::new(
+            AutoplayFilter {
+                min_duration: args.autoplay_min_duration.map(|secs| secs as f64),
+                max_duration: Some(args.autoplay_max_duration as f64),
+                denylist: args.autoplay_deny,
+            },
+            args.autoplay,
+        ),
+        resolvers: ResolverChain::new(vec![Box::new(SpotifyResolver::default())]),
+        pending_loads: api::PendingLoads::default(),
+        playlist_store,
+        bookmarks: Bookmarks::new(storage.clone()),
+        upload_spool: args
+            .upload_dir
+            .map(|dir| UploadSpool::new(&dir, args.max_upload_size * 1024 * 1024))
+            .transpose()?,
+        remotes: args.remote,
+        library,
+        soundboard: Soundboard::load(args.soundboard_clip)?,
+        radio: Radio::new(args.radio_station)?,
+        frontend_dir: args.frontend_dir,
+        breaker: CircuitBreaker::new(Duration::from_secs(args.player_timeout)),
+        wake_on_queue: !args.no_wake_on_queue,
+    };
+
+    if let (Backend::Dlna, Some(renderer_url)) = (args.backend, &args.dlna_renderer) {
+        return run_dlna_backend(
+            renderer_url,
+            args.duck_on_pause,
+            services,
+            &listen_addrs,
+            tls_config,
+            systemd_mode,
+        )
+        .await;
+    }
+
+    log::debug!("Keeping runtime files in {}", runtime_dir.display());
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&runtime_dir)
+        .with_context(|| format!("Failed to create {}", runtime_dir.display()))?;
+    let mpv_config_file = create_mpv_config_file(args.mpv_config_file, &runtime_dir)?;
+
+    // Another instance may already be in charge of the mpv at the socket.
+    let mut read_only = false;
+    let attached = match Mpv::connect(&mpv_socket_path).await {
+        Ok(existing) => match session::current_owner(&existing).await? {
+            Some(owner) => {
+                match args.takeover {
+                    Takeover::Refuse => anyhow::bail!(
+                        "greg-ng (pid {}) is already in charge of the mpv at {}, see --takeover",
+                        owner.pid,
+                        mpv_socket_path
+                    ),
+                    Takeover::Steal => session::take_over(&existing, &owner).await?,
+                    Takeover::ReadOnly => {
+                        log::info!(
+                            "Sharing mpv with greg-ng (pid {}), without changing anything",
+                            owner.pid
+                        );
+                        read_only = true;
+                    }
+                }
+                Some(existing)
+            }
+            None => {
+                existing.disconnect().await.ok();
+                None
+            }
+        },
+        Err(_) => None,
+    };
+    let in_charge = !read_only;
+    let session = Session::new(read_only);
+
+    let (mpv, mut proc) = match attached {
+        Some(mpv) => (mpv, None),
+        None => startup_checks::check_mpv_connection(
+            connect_to_mpv(&MpvConnectionArgs {
+                socket_path: mpv_socket_path,
+                executable_path: args.mpv_executable_path,
+                config_file: &mpv_config_file,
+                auto_start: args.auto_start_mpv,
+                force_auto_start: args.force_auto_start,
+                log_file: args.mpv_log_file,
+                headless: args.headless,
+                retry: mpv_setup::ConnectRetry {
+                    attempts: args.mpv_connect_attempts,
+                    timeout: Duration::from_secs(args.mpv_connect_timeout),
+                },
+                systemd: systemd_mode,
+            })
+            .await,
+        )?,
+    };
+
+    let mpv_log = MpvLog::new();
+    if let Some(proc) = proc.as_mut() {
+        mpv_log.capture(proc);
+    }
 
     let (connection_counter_tx, connection_counter_rx) = mpsc::channel(10);
 
     let status_notifier_thread_handle =
         start_status_notifier_thread(systemd_mode, mpv.clone(), connection_counter_rx).await?;
 
-    if let Err(e) = show_grzegorz_image(mpv.clone()).await {
+    if in_charge
+        && !args.headless
+        && !args.no_splash
+        && let Err(e) = show_grzegorz_image(mpv.clone(), &runtime_dir).await
+    {
         log::warn!("Could not show Grzegorz image: {}", e);
     }
 
-    let addr = match resolve(&args.host)
-        .await
-        .context(format!("Failed to resolve address: {}", &args.host))
-    {
-        Ok(addr) => addr,
-        Err(e) => {
-            log::error!("{}", e);
-            shutdown(mpv, proc).await;
-            return Err(e);
+    let id_pool = IdPoolHandle::spawn(IdPool::new_with_max_limit(1024));
+    let clients = ClientRegistry::default();
+
+    // What greg-ng knows about the playlist items is shared with everything that follows
+    // mpv's playlist on its own.
+    let mpv_player = MpvPlayer::new(mpv.clone());
+    let queue = mpv_player.queue_state().clone();
+
+    if in_charge {
+        if let Some(cache_size) = args.cache_size
+            && let Err(e) = mpv_setup::set_cache_size(&mpv, cache_size).await
+        {
+            log::warn!("Could not set the cache size: {:#}", e);
+        }
+        if let Some(hwdec) = &args.hwdec
+            && let Err(e) = mpv_setup::set_hwdec(&mpv_player, hwdec).await
+        {
+            log::warn!("Could not set the hardware decoding mode: {:#}", e);
+        }
+        if let Some(screen) = &args.screen
+            && let Err(e) = mpv_setup::set_screen(&mpv, screen).await
+        {
+            log::warn!("Could not move mpv to screen {}: {:#}", screen, e);
+        }
+        if args.prefetch_playlist
+            && let Err(e) = mpv_setup::set_playlist_prefetch(&mpv_player, true).await
+        {
+            log::warn!("Could not enable playlist prefetching: {:#}", e);
         }
+
+        let watchdog = watchdog::run_watchdog(
+            mpv.clone(),
+            queue.clone(),
+            args.playback_timeout.map(Duration::from_secs),
+            services.retries.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = watchdog.await {
+                log::error!("Playback watchdog stopped: {:#}", e);
+            }
+        });
+    }
+
+    let resume_positions = ResumePositions::new(
+        storage.clone(),
+        Duration::from_secs(args.resume_min_duration),
+        Duration::from_secs(args.resume_days * 24 * 60 * 60),
+    );
+    if in_charge {
+        tokio::spawn(resume::record_positions(
+            mpv.clone(),
+            queue.clone(),
+            resume_positions.clone(),
+        ));
+    }
+
+    let play_history = PlayHistory::new(storage);
+    if in_charge {
+        tokio::spawn(history::record_history(
+            mpv.clone(),
+            queue.clone(),
+            play_history.clone(),
+            services.autoplay.clone(),
+        ));
+    }
+    if in_charge && let Some(days) = args.history_retention_days {
+        tokio::spawn(history::prune_history(
+            play_history.clone(),
+            Duration::from_secs(days * 24 * 60 * 60),
+        ));
+    }
+
+    let party = PartyMode::new(args.party_source, args.party_mode);
+    let party_pool = PartyPool {
+        store: services.playlist_store.clone(),
+        library: services.library.clone(),
+        yt_dlp_path: args.yt_dlp_path.clone(),
     };
-    let socket_addr = SocketAddr::new(addr, args.port);
-    log::info!("Starting API on {}", socket_addr);
+    if in_charge {
+        let party_mode = party::run_party_mode(
+            mpv.clone(),
+            queue.clone(),
+            party.clone(),
+            party_pool,
+            play_history.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = party_mode.await {
+                log::error!("Party mode stopped: {:#}", e);
+            }
+        });
 
-    let id_pool = Arc::new(Mutex::new(IdPool::new_with_max_limit(1024)));
+        if args.sponsorblock {
+            let sponsorblock =
+                SponsorBlock::new(args.sponsorblock_server, args.sponsorblock_categories);
+            let skipper = sponsorblock::run_sponsorblock(mpv.clone(), sponsorblock);
+            tokio::spawn(async move {
+                if let Err(e) = skipper.await {
+                    log::error!("SponsorBlock stopped: {:#}", e);
+                }
+            });
+        }
 
-    let app = Router::new()
-        .nest("/api", api::rest_api_routes(mpv.clone()))
-        .nest(
+        let radio = radio::run_radio(mpv.clone(), services.radio.clone());
+        tokio::spawn(async move {
+            if let Err(e) = radio.await {
+                log::error!("Radio stopped: {:#}", e);
+            }
+        });
+
+        let recommender = autoplay::run_autoplay(
+            mpv.clone(),
+            queue.clone(),
+            services.autoplay.clone(),
+            args.yt_dlp_path.clone(),
+            play_history.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = recommender.await {
+                log::error!("Autoplay stopped: {:#}", e);
+            }
+        });
+
+        if args.prefetch {
+            let ffmpeg_path = args.replay_gain.then_some(args.ffmpeg_path);
+            tokio::spawn(prefetch::run_prefetcher(
+                mpv.clone(),
+                queue.clone(),
+                args.yt_dlp_path.clone(),
+                ffmpeg_path,
+            ));
+        }
+    }
+
+    let lyrics = Lyrics::new();
+    if args.lyrics {
+        let provider = LyricsProvider::new(args.lyrics_provider);
+        let fetcher = lyrics::run_lyrics(mpv.clone(), provider, lyrics.clone());
+        tokio::spawn(async move {
+            if let Err(e) = fetcher.await {
+                log::error!("Lyrics stopped: {:#}", e);
+            }
+        });
+    }
+
+    let webhook_secret = args
+        .webhook_secret_file
+        .map(|path| {
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))
+        })
+        .transpose()?;
+    let webhooks = Webhooks::new(
+        args.webhook,
+        webhook_secret
+            .as_deref()
+            .map(|secret| secret.trim_end().as_bytes()),
+    );
+    let admin_token = args
+        .admin_token_file
+        .map(|path| {
+            std::fs::read_to_string(&path)
+                .map(|token| token.trim().to_string())
+                .with_context(|| format!("Failed to read {}", path.display()))
+        })
+        .transpose()?;
+    if admin_token.as_deref() == Some("") {
+        anyhow::bail!("The admin token file is empty");
+    }
+    let read_only_mode = ReadOnlyMode::new(args.policy.contains(&PolicyKind::ReadOnly));
+    let control_lock = ControlLock::default();
+    let quotas = Quotas::new(
+        args.quota_limit,
+        Duration::from_secs(args.quota_half_life * 60),
+    )
+    .with_fair_scheduling(args.fair_queue);
+    let policies = Policies::configured(&args.policy, &read_only_mode, &control_lock, &quotas);
+    let oidc = Oidc::discover(args.oidc).await?;
+    let admin = api::AdminAuth::new(admin_token, oidc.as_ref().is_some_and(Oidc::has_admins));
+
+    if !args.notify.is_empty() {
+        let matrix_token = args
+            .matrix_token_file
+            .map(|path| {
+                std::fs::read_to_string(&path)
+                    .map(|token| token.trim().to_string())
+                    .with_context(|| format!("Failed to read {}", path.display()))
+            })
+            .transpose()?;
+        let posts_to_matrix = args
+            .notify
+            .iter()
+            .any(|target| matches!(target, notifier::NotifyTarget::Matrix { .. }));
+        if posts_to_matrix && matrix_token.is_none() {
+            anyhow::bail!("Posting to Matrix requires --matrix-token-file");
+        }
+
+        let notifier =
+            notifier::run_notifier(mpv.clone(), queue.clone(), args.notify, matrix_token);
+        tokio::spawn(async move {
+            if let Err(e) = notifier.await {
+                log::error!("Notifier stopped: {:#}", e);
+            }
+        });
+    }
+
+    if !webhooks.is_empty() {
+        // When mpv was started by us, its exit is reported below instead.
+        tokio::spawn(webhooks::watch_player(
+            mpv.clone(),
+            webhooks.clone(),
+            proc.is_none(),
+        ));
+    }
+
+    if in_charge && let Some(leader_url) = args.sync_leader {
+        tokio::spawn(sync::follow_leader(mpv.clone(), leader_url));
+    }
+
+    let breaker = services.breaker.clone();
+    let exit_when_unresponsive = Duration::from_secs(args.exit_when_unresponsive);
+    let mut player: PlayerHandle =
+        Arc::new(GuardedPlayer::new(Arc::new(mpv_player), breaker.clone()));
+    if let Some(window) = args.queue_window.filter(|&window| window > 0) {
+        let windowed = Arc::new(WindowedPlayer::new(player, window));
+        tokio::spawn(windowed.clone().feed());
+        player = windowed;
+    }
+    let volume_engine = VolumeTransitionEngine::new(player.clone(), args.duck_on_pause);
+
+    let announcements = Announcements::new(
+        args.announcements,
+        Duration::from_secs(args.announcement_interval * 60),
+        args.announce,
+    );
+    if in_charge {
+        tokio::spawn(announcements::run_announcements(
+            player.clone(),
+            announcements.clone(),
+        ));
+        tokio::spawn(expiry::expire_items(
+            mpv.clone(),
+            player.clone(),
+            args.item_ttl
+                .map(|hours| Duration::from_secs(hours * 60 * 60)),
+        ));
+    }
+
+    let idle_policy = IdlePolicyHandle::new(IdlePolicy {
+        after_minutes: args.idle_pause_after,
+        action: args.idle_action,
+        volume: args.idle_volume,
+    });
+    if in_charge {
+        tokio::spawn(idle_policy::enforce_idle_policy(
+            idle_policy.clone(),
+            volume_engine.clone(),
+            services.autoplay.clone(),
+            clients.clone(),
+        ));
+    }
+
+    let clear_guard = services.clear_guard.clone();
+    let autoplay = services.autoplay.clone();
+    let retries = services.retries.clone();
+    let resolvers = services.resolvers.clone();
+    let pending_loads = services.pending_loads.clone();
+    let policy = api::MpvCommandPolicy {
+        allow: args.mpv_command_allow,
+        deny: args.mpv_command_deny,
+    };
+    let inputs = Inputs::new(args.input)?;
+    let events = api::EventBroadcast::spawn(mpv.clone(), queue.clone());
+    let app = app_routes(player.clone(), volume_engine.clone(), services)
+        .merge(api::event_stream_routes(
+            events.clone(),
+            volume_engine.clone(),
+        ))
+        .merge(api::property_routes(mpv.clone(), args.mpv_property))
+        .merge(api::display_routes(mpv.clone()))
+        .merge(api::history_routes(play_history.clone()))
+        .merge(api::resume_routes(resume_positions))
+        .merge(api::stats_routes(play_history))
+        .merge(api::party_routes(party.clone()))
+        .merge(api::announcement_routes(announcements, admin.clone()))
+        .merge(api::autoplay_routes(autoplay.clone()))
+        .merge(api::lyrics_routes(lyrics))
+        .merge(api::playlist_ops_routes(
+            player.clone(),
+            retries,
+            autoplay.clone(),
+            policies.clone(),
+            admin.clone(),
+        ))
+        .merge(api::inputs_routes(player, inputs))
+        .merge(api::admin_routes(
+            mpv.clone(),
+            admin.clone(),
+            policy,
+            mpv_log,
+            clients.clone(),
+            bans.clone(),
+            api_keys.clone(),
+            quotas,
+            read_only_mode.clone(),
+            idle_policy,
+            volume_engine.clone(),
+        ))
+        .merge(api::control_routes(control_lock.clone()))
+        .merge(api::load_check_routes(
+            policies.clone(),
+            admin.clone(),
+            resolvers.clone(),
+            args.yt_dlp_path,
+        ))
+        .merge(api::me_routes(admin.clone()))
+        .merge(session::session_routes(session.clone()));
+    let app = match &oidc {
+        Some(oidc) => {
+            tokio::spawn(oidc.clone().prune_periodically());
+            app.merge(oidc::oidc_routes(oidc.clone()))
+        }
+        None => app,
+    };
+    // Websocket clients send commands over the connection, so they are left out entirely.
+    let app = if read_only {
+        app.layer(axum::middleware::from_fn(session::reject_changes))
+    } else {
+        app.nest(
             "/ws",
-            api::websocket_api(mpv.clone(), id_pool.clone(), connection_counter_tx.clone()),
+            api::websocket_api(
+                mpv.clone(),
+                volume_engine.clone(),
+                clear_guard,
+                party,
+                autoplay,
+                resolvers,
+                pending_loads,
+                id_pool.clone(),
+                connection_counter_tx.clone(),
+                clients,
+                policies.clone(),
+                control_lock.clone(),
+                PlaybackClock::spawn(mpv.clone()),
+                events,
+            ),
         )
-        .merge(api::rest_api_docs(mpv.clone()))
-        .into_make_service_with_connect_info::<SocketAddr>();
+    };
+    let app = app.layer(axum::middleware::from_fn_with_state(
+        (policies, admin),
+        api::reject_by_policy,
+    ));
+    // Whoever is logged in is known to everything after this, including the policies.
+    let app = app.layer(axum::middleware::from_fn_with_state(
+        (oidc, api_keys),
+        oidc::attach_user,
+    ));
+    // Banned addresses are kept out of everything, including the websocket upgrade.
+    let app = app.layer(axum::middleware::from_fn_with_state(
+        bans,
+        bans::reject_banned,
+    ));
 
-    let listener = match tokio::net::TcpListener::bind(&socket_addr)
-        .await
-        .context(format!("Failed to bind API server to '{}'", &socket_addr))
-    {
-        Ok(listener) => listener,
+    let listeners = match bind_listeners(&listen_addrs).await {
+        Ok(listeners) => listeners,
         Err(e) => {
             log::error!("{}", e);
             shutdown(mpv, proc).await;
@@ -310,17 +1581,58 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    if in_charge
+        && let Some(listener) = listeners.first()
+        && let Err(e) = session
+            .claim(&mpv, listener.to_string(), tls_config.is_some())
+            .await
+    {
+        log::warn!("{:#}", e);
+    }
+
     if let Some(mut proc) = proc {
         tokio::select! {
             exit_status = proc.wait() => {
-                log::warn!("mpv process exited with status: {}", exit_status?);
+                let exit_status = exit_status?;
+                log::warn!("mpv process exited with status: {}", exit_status);
+                if !exit_status.success() {
+                    let event = WebhookEvent::PlayerCrashed {
+                        message: format!("mpv exited with {}", exit_status),
+                    };
+                    if tokio::time::timeout(WEBHOOK_SHUTDOWN_TIMEOUT, webhooks.deliver(event)).await.is_err() {
+                        log::warn!("Timed out reporting the crash to webhooks");
+                    }
+                }
                 shutdown(mpv, Some(proc)).await;
             }
             _ = tokio::signal::ctrl_c() => {
                 log::info!("Received Ctrl-C, exiting");
                 shutdown(mpv, Some(proc)).await;
             }
-            result = axum::serve(listener, app) => {
+            _ = breaker.unresponsive_at_least(exit_when_unresponsive), if !exit_when_unresponsive.is_zero() => {
+                log::error!(
+                    "mpv has not responded for {} seconds, exiting so that it can be started over",
+                    exit_when_unresponsive.as_secs()
+                );
+                let event = WebhookEvent::PlayerCrashed {
+                    message: "mpv stopped responding".to_string(),
+                };
+                if tokio::time::timeout(WEBHOOK_SHUTDOWN_TIMEOUT, webhooks.deliver(event)).await.is_err() {
+                    log::warn!("Timed out reporting the hang to webhooks");
+                }
+                // Kill mpv first, since disconnecting waits for it to respond.
+                if let Err(e) = proc.start_kill() {
+                    log::warn!("Failed to kill mpv process: {}", e);
+                }
+                shutdown(mpv, Some(proc)).await;
+                anyhow::bail!("mpv stopped responding");
+            }
+            _ = session.released() => {
+                // The instance taking over keeps using this mpv.
+                session.hand_over(&mpv).await;
+                shutdown(mpv, None).await;
+            }
+            result = server::serve_api(listeners, app, tls_config) => {
               log::info!("API server exited");
               shutdown(mpv, Some(proc)).await;
               result?;
@@ -337,7 +1649,11 @@ async fn main() -> anyhow::Result<()> {
                 log::info!("Received Ctrl-C, exiting");
                 shutdown(mpv.clone(), None).await;
             }
-            result = axum::serve(listener, app) => {
+            _ = session.released() => {
+                session.hand_over(&mpv).await;
+                shutdown(mpv.clone(), None).await;
+            }
+            result = server::serve_api(listeners, app, tls_config) => {
               log::info!("API server exited");
               shutdown(mpv.clone(), None).await;
               result?;
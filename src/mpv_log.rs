@@ -0,0 +1,137 @@
+//! Keeps the output of the mpv process started by greg-ng, which would otherwise
+//! end up nowhere when running as a service.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use serde::Serialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Child,
+};
+
+/// How many lines of output are kept.
+pub const MAX_LINES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogLine {
+    /// Seconds since the unix epoch.
+    pub timestamp: f64,
+    pub stream: OutputStream,
+    pub line: String,
+}
+
+/// The most recent lines written by mpv.
+#[derive(Debug, Clone, Default)]
+pub struct MpvLog {
+    lines: Arc<Mutex<VecDeque<LogLine>>>,
+}
+
+impl MpvLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start collecting the output of `process`, which has to be started with piped
+    /// stdout and stderr.
+    pub fn capture(&self, process: &mut Child) {
+        if let Some(stdout) = process.stdout.take() {
+            tokio::spawn(self.clone().read_lines(stdout, OutputStream::Stdout));
+        }
+        if let Some(stderr) = process.stderr.take() {
+            tokio::spawn(self.clone().read_lines(stderr, OutputStream::Stderr));
+        }
+    }
+
+    async fn read_lines(self, output: impl AsyncRead + Unpin, stream: OutputStream) {
+        let mut lines = BufReader::new(output).lines();
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => self.push(stream, line),
+                Ok(None) => return,
+                Err(e) => {
+                    log::debug!("Stopped reading mpv {:?}: {}", stream, e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn push(&self, stream: OutputStream, line: String) {
+        let line = line.trim_end().to_string();
+        if line.is_empty() {
+            return;
+        }
+
+        if is_problem(stream, &line) {
+            log::warn!(target: "mpv", "{}", line);
+        } else {
+            log::debug!(target: "mpv", "{}", line);
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or_default();
+
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == MAX_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(LogLine {
+            timestamp,
+            stream,
+            line,
+        });
+    }
+
+    /// The last `count` lines, oldest first.
+    pub fn recent(&self, count: usize) -> Vec<LogLine> {
+        let lines = self.lines.lock().unwrap();
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Whether a line looks like a warning or an error. mpv does not say, so this goes by
+/// the stream it was written to and the wording.
+fn is_problem(stream: OutputStream, line: &str) -> bool {
+    let line = line.to_lowercase();
+    stream == OutputStream::Stderr
+        || ["error", "failed", "warning", "cannot", "can't"]
+            .iter()
+            .any(|word| line.contains(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_lines() {
+        let log = MpvLog::new();
+        for i in 0..MAX_LINES + 5 {
+            log.push(OutputStream::Stdout, format!("line {}\n", i));
+        }
+        log.push(OutputStream::Stdout, "   ".to_string());
+
+        let recent = log.recent(2);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].line, format!("line {}", MAX_LINES + 3));
+        assert_eq!(recent[1].line, format!("line {}", MAX_LINES + 4));
+        assert_eq!(log.recent(usize::MAX).len(), MAX_LINES);
+    }
+}
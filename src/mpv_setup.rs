@@ -1,20 +1,36 @@
-use std::{fs::create_dir_all, io::Write, path::Path};
+use std::{
+    fmt,
+    fs::create_dir_all,
+    io::Write,
+    path::Path,
+    process::Stdio,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
-use mpvipc_async::{Mpv, MpvExt};
+use mpvipc_async::{Mpv, MpvError, MpvExt};
+use serde_json::json;
 use tempfile::NamedTempFile;
 use tokio::process::{Child, Command};
 
-use crate::MpvConnectionArgs;
+use crate::{MpvConnectionArgs, player::Player};
 
 const DEFAULT_MPV_CONFIG_CONTENT: &str = include_str!("../assets/default-mpv.conf");
 
 const THE_MAN_PNG: &[u8] = include_bytes!("../assets/the_man.png");
 
+/// The filename of the placeholder image shown while mpv is idle.
+pub const PLACEHOLDER_FILENAME: &str = "the_man.png";
+
 // https://mpv.io/manual/master/#options-ytdl
 const YTDL_HOOK_ARGS: [&str; 2] = ["try_ytdl_first=yes", "thumbnails=none"];
 
-pub fn create_mpv_config_file(args_config_file: Option<String>) -> anyhow::Result<NamedTempFile> {
+/// Copy the mpv config into `runtime_dir`, for mpv to include.
+pub fn create_mpv_config_file(
+    args_config_file: Option<String>,
+    runtime_dir: &Path,
+) -> anyhow::Result<NamedTempFile> {
     let file_content = if let Some(path) = args_config_file {
         if !Path::new(&path).exists() {
             anyhow::bail!("Mpv config file not found at {}", &path);
@@ -29,14 +45,98 @@ pub fn create_mpv_config_file(args_config_file: Option<String>) -> anyhow::Resul
         .prefix("mpv-")
         .rand_bytes(8)
         .suffix(".conf")
-        .tempfile()?;
+        .tempfile_in(runtime_dir)?;
 
     tmpfile.reopen()?.write_all(file_content.as_bytes())?;
 
     Ok(tmpfile)
 }
 
-pub async fn connect_to_mpv(args: &MpvConnectionArgs<'_>) -> anyhow::Result<(Mpv, Option<Child>)> {
+/// Why [`connect_to_mpv`] failed.
+#[derive(Debug)]
+pub enum MpvConnectError {
+    /// There is no socket, and mpv was not to be started by us.
+    SocketNotFound { socket_path: String },
+
+    /// Preparing the socket, or starting mpv, failed.
+    Io {
+        context: String,
+        error: std::io::Error,
+    },
+
+    /// The mpv we started exited before it could be connected to.
+    Exited { status: std::process::ExitStatus },
+
+    /// mpv did not accept a connection before we gave up.
+    TimedOut {
+        socket_path: String,
+        attempts: u32,
+        last_error: Option<MpvError>,
+    },
+}
+
+impl fmt::Display for MpvConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MpvConnectError::SocketNotFound { socket_path } => write!(
+                f,
+                "No mpv socket found at {}, and --auto-start-mpv is off",
+                socket_path
+            ),
+            MpvConnectError::Io { context, error } => write!(f, "{}: {}", context, error),
+            MpvConnectError::Exited { status } => write!(f, "mpv exited right away ({})", status),
+            MpvConnectError::TimedOut {
+                socket_path,
+                attempts,
+                last_error,
+            } => {
+                write!(
+                    f,
+                    "Could not connect to mpv at {} after {} attempts",
+                    socket_path, attempts
+                )?;
+                if let Some(e) = last_error {
+                    write!(f, ": {}", e)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for MpvConnectError {}
+
+/// How long [`connect_to_mpv`] keeps trying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectRetry {
+    /// Give up after this many attempts.
+    pub attempts: u32,
+    /// Give up after this long, regardless of the attempts left.
+    pub timeout: Duration,
+}
+
+impl ConnectRetry {
+    const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+    const MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+    /// How long to wait after the failed `attempt`, counting from zero.
+    fn backoff(attempt: u32) -> Duration {
+        Self::INITIAL_BACKOFF
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(Self::MAX_BACKOFF)
+    }
+}
+
+fn io_error(context: impl Into<String>) -> impl FnOnce(std::io::Error) -> MpvConnectError {
+    move |error| MpvConnectError::Io {
+        context: context.into(),
+        error,
+    }
+}
+
+pub async fn connect_to_mpv(
+    args: &MpvConnectionArgs<'_>,
+) -> Result<(Mpv, Option<Child>), MpvConnectError> {
     log::debug!("Connecting to mpv");
 
     debug_assert!(
@@ -48,36 +148,43 @@ pub async fn connect_to_mpv(args: &MpvConnectionArgs<'_>) -> anyhow::Result<(Mpv
 
     if !socket_path.exists() {
         log::debug!("Mpv socket not found at {}", &args.socket_path);
-        if !args.auto_start {
-            panic!("Mpv socket not found at {}", &args.socket_path);
-        }
-
-        log::debug!("Ensuring parent dir of mpv socket exists");
-        let parent_dir = Path::new(&args.socket_path)
-            .parent()
-            .context("Failed to get parent dir of mpv socket")?;
-
-        if !parent_dir.is_dir() {
-            create_dir_all(parent_dir).context("Failed to create parent dir of mpv socket")?;
+        if args.auto_start {
+            log::debug!("Ensuring parent dir of mpv socket exists");
+            if let Some(parent_dir) = socket_path.parent()
+                && !parent_dir.is_dir()
+            {
+                create_dir_all(parent_dir)
+                    .map_err(io_error("Failed to create parent dir of mpv socket"))?;
+            }
         }
     } else {
         log::debug!("Existing mpv socket found at {}", &args.socket_path);
         if args.force_auto_start {
             log::debug!("Removing mpv socket");
-            std::fs::remove_file(&args.socket_path)?;
+            std::fs::remove_file(socket_path)
+                .map_err(io_error("Failed to remove the old mpv socket"))?;
         }
     }
 
-    let process_handle = if args.auto_start {
+    let mut process_handle = if args.auto_start {
         log::info!("Starting mpv with socket at {}", &args.socket_path);
 
+        let mut command = Command::new(args.executable_path.as_deref().unwrap_or("mpv"));
+        if let Some(log_file) = &args.log_file {
+            command.arg(format!("--log-file={}", log_file.display()));
+        }
+
+        if args.headless {
+            command.arg("--vo=null").arg("--force-window=no");
+        } else {
+            command.arg("--force-window").arg("--fullscreen");
+        }
+
         // TODO: try to fetch mpv from PATH
         Some(
-            Command::new(args.executable_path.as_deref().unwrap_or("mpv"))
+            command
                 .arg(format!("--input-ipc-server={}", &args.socket_path))
                 .arg("--idle")
-                .arg("--force-window")
-                .arg("--fullscreen")
                 .arg("--no-config")
                 .arg("--ytdl=yes")
                 .args(
@@ -93,43 +200,81 @@ pub async fn connect_to_mpv(args: &MpvConnectionArgs<'_>) -> anyhow::Result<(Mpv
                 // .arg("--no-terminal")
                 .arg("--load-unsafe-playlists")
                 .arg("--keep-open") // Keep last frame of video on end of video
+                // Collected by `MpvLog`, instead of ending up wherever our own output goes.
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
                 .spawn()
-                .context("Failed to start mpv")?,
+                .map_err(io_error("Failed to start mpv"))?,
         )
     } else {
         None
     };
 
-    // Wait for mpv to create the socket
-    if tokio::time::timeout(tokio::time::Duration::from_millis(500), async {
-        while !&socket_path.exists() {
-            log::debug!("Waiting for mpv socket at {}", &args.socket_path);
-            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+    let status = format!("Waiting for mpv at {}", &args.socket_path);
+    if args.systemd {
+        if let Err(e) = sd_notify::notify(&[sd_notify::NotifyState::Status(&status)]) {
+            log::warn!("Failed to update systemd status: {}", e);
         }
-    })
-    .await
-    .is_err()
-    {
-        return Err(anyhow::anyhow!(
-            "Failed to connect to mpv socket: {}",
-            &args.socket_path
-        ));
+    } else {
+        log::info!("{}", status);
     }
 
-    Ok((
-        Mpv::connect(&args.socket_path).await.context(format!(
-            "Failed to connect to mpv socket: {}",
-            &args.socket_path
-        ))?,
-        process_handle,
-    ))
+    let deadline = Instant::now() + args.retry.timeout;
+    let mut last_error = None;
+    let mut attempts = 0;
+    while attempts < args.retry.attempts {
+        if let Some(process) = process_handle.as_mut()
+            && let Ok(Some(status)) = process.try_wait()
+        {
+            return Err(MpvConnectError::Exited { status });
+        }
+
+        if socket_path.exists() {
+            match Mpv::connect(&args.socket_path).await {
+                Ok(mpv) => return Ok((mpv, process_handle)),
+                Err(e) => {
+                    log::debug!("Could not connect to mpv yet: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        } else if attempts == 0 && !args.auto_start {
+            log::warn!("No mpv socket at {} yet", &args.socket_path);
+        }
+
+        let backoff = ConnectRetry::backoff(attempts);
+        attempts += 1;
+        if Instant::now() + backoff > deadline {
+            break;
+        }
+        tokio::time::sleep(backoff).await;
+    }
+
+    if !socket_path.exists() && !args.auto_start {
+        return Err(MpvConnectError::SocketNotFound {
+            socket_path: args.socket_path.clone(),
+        });
+    }
+    Err(MpvConnectError::TimedOut {
+        socket_path: args.socket_path.clone(),
+        attempts,
+        last_error,
+    })
 }
 
-pub async fn show_grzegorz_image(mpv: Mpv) -> anyhow::Result<()> {
-    let path = std::env::temp_dir().join("the_man.png");
+/// Show the placeholder image, if mpv is idle with nothing in the playlist.
+///
+/// An mpv that was already running when we attached to it is left alone if it has
+/// something to play. Returns whether the image is shown.
+pub async fn show_grzegorz_image(mpv: Mpv, runtime_dir: &Path) -> anyhow::Result<bool> {
+    let idle: Option<bool> = mpv.get_property("idle-active").await?;
+    if idle != Some(true) || !mpv.get_playlist().await?.0.is_empty() {
+        log::debug!("mpv has something to play, not showing the Grzegorz image");
+        return Ok(false);
+    }
+
+    let path = runtime_dir.join(PLACEHOLDER_FILENAME);
     std::fs::write(path.as_path(), THE_MAN_PNG)?;
 
-    mpv.playlist_clear().await?;
     mpv.playlist_add(
         path.to_string_lossy().as_ref(),
         mpvipc_async::PlaylistAddTypeOptions::File,
@@ -138,5 +283,261 @@ pub async fn show_grzegorz_image(mpv: Mpv) -> anyhow::Result<()> {
     .await?;
     mpv.next().await?;
 
+    Ok(true)
+}
+
+/// Let mpv open the next playlist item while the current one is still playing, so that
+/// there is no gap between them. This needs the cache, to have somewhere to buffer into.
+pub async fn set_playlist_prefetch(player: &dyn Player, enabled: bool) -> anyhow::Result<()> {
+    if enabled {
+        player.set_property("cache", json!("yes")).await?;
+    }
+    player
+        .set_property("prefetch-playlist", json!(enabled))
+        .await
+}
+
+/// Limit how much mpv buffers ahead, including for prefetched playlist items.
+pub async fn set_cache_size(mpv: &Mpv, megabytes: u64) -> anyhow::Result<()> {
+    mpv.set_property("demuxer-max-bytes", format!("{}MiB", megabytes))
+        .await?;
+    Ok(())
+}
+
+/// Check a hardware decoding mode, like `auto`, `vaapi` or `no`. `none` is accepted
+/// as an alias for `no`.
+pub fn hwdec_mode(mode: &str) -> Option<&str> {
+    let mode = match mode.trim() {
+        "none" => "no",
+        mode => mode,
+    };
+    let valid = !mode.is_empty()
+        && mode
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == ',');
+    valid.then_some(mode)
+}
+
+/// Switch the hardware decoding mode. Takes effect right away, also for the current item.
+pub async fn set_hwdec(player: &dyn Player, mode: &str) -> anyhow::Result<()> {
+    let mode = hwdec_mode(mode).with_context(|| format!("Invalid hwdec mode '{}'", mode))?;
+    player.set_property("hwdec", json!(mode)).await
+}
+
+/// A display output, by number like `1`, or by name like `HDMI-A-1` as the compositor
+/// or X server calls it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Screen {
+    Number(usize),
+    Name(String),
+}
+
+impl FromStr for Screen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(number) = s.parse() {
+            return Ok(Screen::Number(number));
+        }
+        let valid = !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(format!("invalid screen '{}'", s));
+        }
+        Ok(Screen::Name(s.to_string()))
+    }
+}
+
+impl fmt::Display for Screen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Screen::Number(number) => write!(f, "{}", number),
+            Screen::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Show the window, and go fullscreen, on `screen`.
+///
+/// mpv only picks the screen when entering fullscreen, so a fullscreen window is moved by
+/// leaving fullscreen and entering it again.
+pub async fn set_screen(mpv: &Mpv, screen: &Screen) -> anyhow::Result<()> {
+    // A name takes precedence over a number, so it is cleared when switching by number.
+    let name = match screen {
+        Screen::Number(number) => {
+            mpv.set_property("screen", *number).await?;
+            mpv.set_property("fs-screen", *number).await?;
+            String::new()
+        }
+        Screen::Name(name) => name.clone(),
+    };
+    mpv.set_property("screen-name", name.clone()).await?;
+    mpv.set_property("fs-screen-name", name).await?;
+
+    let fullscreen: Option<bool> = mpv.get_property("fullscreen").await?;
+    if fullscreen == Some(true) {
+        mpv.set_property("fullscreen", false).await?;
+        mpv.set_property("fullscreen", true).await?;
+    }
     Ok(())
 }
+
+/// The screen mpv goes fullscreen on, or `None` if it is left up to mpv.
+pub async fn get_screen(mpv: &Mpv) -> anyhow::Result<Option<Screen>> {
+    let name: Option<String> = mpv.get_property("fs-screen-name").await?;
+    if let Some(name) = name.filter(|name| !name.is_empty()) {
+        return Ok(Some(Screen::Name(name)));
+    }
+    let number = mpv.get_property_value("fs-screen").await?;
+    Ok(number
+        .and_then(|number| number.as_u64())
+        .map(|number| Screen::Number(number as usize)))
+}
+
+/// Which streams yt-dlp picks for an item.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Quality {
+    /// Skip video streams taller than this many pixels.
+    pub max_height: Option<u32>,
+    /// Only fetch the audio, for music.
+    pub audio_only: bool,
+}
+
+impl Quality {
+    /// The `ytdl-format` selecting this quality, or `None` for yt-dlp's default.
+    pub fn ytdl_format(&self) -> Option<String> {
+        match (self.audio_only, self.max_height) {
+            (true, _) => Some("bestaudio/best".to_string()),
+            (false, Some(height)) => Some(format!(
+                "bestvideo[height<={height}]+bestaudio/best[height<={height}]/best"
+            )),
+            (false, None) => None,
+        }
+    }
+}
+
+/// Set the quality of every item loaded from now on, that does not pick its own.
+pub async fn set_quality(player: &dyn Player, quality: Quality) -> anyhow::Result<()> {
+    player
+        .set_property(
+            "ytdl-format",
+            json!(quality.ytdl_format().unwrap_or_default()),
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::FakeMpv;
+
+    #[test]
+    fn test_hwdec_mode() {
+        assert_eq!(hwdec_mode("vaapi"), Some("vaapi"));
+        assert_eq!(hwdec_mode("none"), Some("no"));
+        assert_eq!(hwdec_mode("vaapi-copy,auto"), Some("vaapi-copy,auto"));
+        assert_eq!(hwdec_mode(""), None);
+        assert_eq!(hwdec_mode("auto\nquit"), None);
+    }
+
+    #[test]
+    fn test_connect_backoff() {
+        assert_eq!(ConnectRetry::backoff(0), Duration::from_millis(10));
+        assert_eq!(ConnectRetry::backoff(3), Duration::from_millis(80));
+        assert_eq!(ConnectRetry::backoff(40), ConnectRetry::MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn test_connect_to_mpv() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = create_mpv_config_file(None, dir.path()).unwrap();
+        let fake_mpv = FakeMpv::start();
+        let args = |socket_path: &Path| MpvConnectionArgs {
+            socket_path: socket_path.to_string_lossy().into_owned(),
+            executable_path: None,
+            config_file: &config_file,
+            auto_start: false,
+            force_auto_start: false,
+            log_file: None,
+            headless: true,
+            retry: ConnectRetry {
+                attempts: 3,
+                timeout: Duration::from_secs(1),
+            },
+            systemd: false,
+        };
+
+        let (_, process) = connect_to_mpv(&args(fake_mpv.socket_path())).await.unwrap();
+        assert!(process.is_none());
+
+        let missing = dir.path().join("missing.sock");
+        assert!(matches!(
+            connect_to_mpv(&args(&missing)).await,
+            Err(MpvConnectError::SocketNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_show_grzegorz_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let fake_mpv = FakeMpv::start();
+        let mpv = fake_mpv.connect().await;
+
+        assert!(show_grzegorz_image(mpv.clone(), dir.path()).await.unwrap());
+        assert_eq!(
+            fake_mpv.property("playlist").unwrap()[0]["filename"],
+            json!(dir.path().join("the_man.png"))
+        );
+
+        // Attaching to an mpv that is playing something leaves it alone.
+        fake_mpv.set_property(
+            "playlist",
+            json!([{"filename": "song.mp3", "current": true}]),
+        );
+        fake_mpv.set_property("idle-active", json!(false));
+        let commands = fake_mpv.commands().len();
+        assert!(!show_grzegorz_image(mpv, dir.path()).await.unwrap());
+        assert!(
+            fake_mpv.commands()[commands..]
+                .iter()
+                .all(|command| command[0] == "get_property")
+        );
+    }
+
+    #[test]
+    fn test_parse_screen() {
+        assert_eq!("1".parse(), Ok(Screen::Number(1)));
+        assert_eq!(
+            " HDMI-A-1".parse(),
+            Ok(Screen::Name("HDMI-A-1".to_string()))
+        );
+        assert!("".parse::<Screen>().is_err());
+        assert!("HDMI A".parse::<Screen>().is_err());
+    }
+
+    #[test]
+    fn test_quality_ytdl_format() {
+        assert_eq!(Quality::default().ytdl_format(), None);
+        assert_eq!(
+            Quality {
+                max_height: Some(720),
+                audio_only: false,
+            }
+            .ytdl_format()
+            .as_deref(),
+            Some("bestvideo[height<=720]+bestaudio/best[height<=720]/best")
+        );
+        assert_eq!(
+            Quality {
+                max_height: Some(720),
+                audio_only: true,
+            }
+            .ytdl_format()
+            .as_deref(),
+            Some("bestaudio/best")
+        );
+    }
+}
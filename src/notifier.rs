@@ -0,0 +1,425 @@
+//! Posts what is being played and queued into a Matrix room or an IRC channel,
+//! so that people can follow along without opening the web UI.
+
+use std::{collections::HashSet, str::FromStr, time::Duration};
+
+use anyhow::Context;
+use futures::StreamExt;
+use mpvipc_async::{Event, Mpv, MpvDataType, MpvExt, Playlist};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+};
+use url::Url;
+
+use crate::player::{EntryId, QueueState};
+
+/// The observer id used for the properties watched by the notifier.
+const OBSERVER_ID: u64 = 102;
+
+/// When more items than this are queued at once, only the number of items is posted.
+const MAX_LISTED_ADDITIONS: usize = 3;
+
+/// How long to wait for new items to be given their state, which happens just after mpv
+/// announces them, before describing them without it.
+const ITEM_STATE_GRACE: Duration = Duration::from_millis(500);
+
+/// How long to wait before reconnecting to IRC.
+const IRC_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// How many messages can wait for a slow or disconnected target before new ones are dropped.
+const QUEUE_SIZE: usize = 32;
+
+/// IRC servers cut off lines longer than 512 bytes, including the command itself.
+const MAX_IRC_MESSAGE_LENGTH: usize = 400;
+
+/// Where to post notifications.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotifyTarget {
+    /// A Matrix room, given by its id, on `homeserver`.
+    Matrix { homeserver: Url, room: String },
+    /// An IRC channel, joined as `nick`. Only plain text connections are supported.
+    Irc {
+        host: String,
+        port: u16,
+        channel: String,
+        nick: String,
+    },
+}
+
+impl FromStr for NotifyTarget {
+    type Err = String;
+
+    /// Parses `matrix://<homeserver>/<room id>` or `irc://<host>[:<port>]/<channel>[?nick=<nick>]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let url = Url::parse(s).map_err(|e| format!("invalid URL '{}': {}", s, e))?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("'{}' has no host", s))?
+            .to_string();
+        let target = url.path().trim_start_matches('/').to_string();
+
+        match url.scheme() {
+            "matrix" => {
+                if !target.starts_with('!') || !target.contains(':') {
+                    return Err(format!(
+                        "expected a room id like '!abc:example.org' in '{}'",
+                        s
+                    ));
+                }
+                let mut homeserver = Url::parse(&format!("https://{}", host))
+                    .map_err(|e| format!("invalid homeserver '{}' in '{}': {}", host, s, e))?;
+                homeserver
+                    .set_port(url.port())
+                    .map_err(|()| format!("can not use the port of '{}' for https", s))?;
+                Ok(NotifyTarget::Matrix {
+                    homeserver,
+                    room: target,
+                })
+            }
+            "irc" => {
+                if target.is_empty() {
+                    return Err(format!("expected a channel in '{}'", s));
+                }
+                // `#` starts the fragment in URLs, so it is optional, or written as `%23`.
+                let channel = format!("#{}", target.trim_start_matches("%23"));
+                let nick = url
+                    .query_pairs()
+                    .find(|(key, _)| key == "nick")
+                    .map(|(_, nick)| nick.into_owned())
+                    .unwrap_or_else(|| "greg".to_string());
+                Ok(NotifyTarget::Irc {
+                    host,
+                    port: url.port().unwrap_or(6667),
+                    channel,
+                    nick,
+                })
+            }
+            scheme => Err(format!(
+                "unsupported notification target '{}', expected matrix:// or irc://",
+                scheme
+            )),
+        }
+    }
+}
+
+/// Post now-playing updates and queue additions to `targets`, with the titles and notes the
+/// items have in `queue`.
+///
+/// `matrix_token` is the access token used for every Matrix target.
+pub async fn run_notifier(
+    mpv: Mpv,
+    queue: QueueState,
+    targets: Vec<NotifyTarget>,
+    matrix_token: Option<String>,
+) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    let mut senders = Vec::new();
+
+    for target in targets {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        match target {
+            NotifyTarget::Matrix { homeserver, room } => {
+                let token = matrix_token
+                    .clone()
+                    .context("Posting to Matrix requires an access token")?;
+                tokio::spawn(run_matrix(client.clone(), homeserver, room, token, rx));
+            }
+            NotifyTarget::Irc {
+                host,
+                port,
+                channel,
+                nick,
+            } => {
+                tokio::spawn(run_irc(host, port, channel, nick, rx));
+            }
+        }
+        senders.push(tx);
+    }
+
+    let notify = |message: String| {
+        log::debug!("Notifying: {}", message);
+        for sender in &senders {
+            if sender.try_send(message.clone()).is_err() {
+                log::warn!("Notification queue is full, dropping '{}'", message);
+            }
+        }
+    };
+
+    let mut event_stream = mpv.get_event_stream().await;
+    mpv.observe_property(OBSERVER_ID, "playlist").await?;
+    let mut item_states = queue.subscribe_items();
+    let mut started: Option<EntryId> = None;
+
+    let mut known_ids: HashSet<usize> = mpv
+        .get_playlist()
+        .await
+        .map(|playlist| playlist.0.iter().map(|entry| entry.id).collect())
+        .unwrap_or_default();
+
+    while let Some(event) = event_stream.next().await {
+        match event? {
+            Event::StartFile { playlist_entry_id } => {
+                started = Some(EntryId::Player(playlist_entry_id));
+            }
+            Event::FileLoaded => {
+                let note = started.map(|id| queue.get(id).note).unwrap_or_default();
+                let title = match note.title {
+                    Some(title) => Some(title),
+                    None => mpv.get_property("media-title").await.unwrap_or(None),
+                };
+                if let Some(title) = title {
+                    match note.note {
+                        Some(note) => notify(format!("Now playing: {} ({})", title, note)),
+                        None => notify(format!("Now playing: {}", title)),
+                    }
+                }
+            }
+            Event::PropertyChange { name, data, .. } if name == "playlist" => {
+                let playlist = match data {
+                    Some(MpvDataType::Playlist(playlist)) => playlist,
+                    _ => Playlist(vec![]),
+                };
+                if playlist
+                    .0
+                    .iter()
+                    .any(|entry| !known_ids.contains(&entry.id))
+                {
+                    let _ = tokio::time::timeout(ITEM_STATE_GRACE, item_states.changed()).await;
+                    item_states.borrow_and_update();
+                }
+                if let Some(message) = queue_additions(&queue, &mut known_ids, &playlist) {
+                    notify(message);
+                }
+            }
+            Event::Shutdown => break,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Describe the items in `playlist` that are not in `known_ids`, and update `known_ids`.
+fn queue_additions(
+    queue: &QueueState,
+    known_ids: &mut HashSet<usize>,
+    playlist: &Playlist,
+) -> Option<String> {
+    let added: Vec<_> = playlist
+        .0
+        .iter()
+        .filter(|entry| !known_ids.contains(&entry.id))
+        .map(|entry| {
+            queue
+                .get(EntryId::Player(entry.id))
+                .note
+                .title
+                .or_else(|| entry.title.clone())
+                .unwrap_or_else(|| entry.filename.clone())
+        })
+        .collect();
+
+    let message = match added.len() {
+        0 => None,
+        n if n > MAX_LISTED_ADDITIONS => Some(format!("Queued {} items", n)),
+        _ => Some(format!("Queued: {}", added.join(", "))),
+    };
+
+    *known_ids = playlist.0.iter().map(|entry| entry.id).collect();
+    message
+}
+
+async fn run_matrix(
+    client: reqwest::Client,
+    homeserver: Url,
+    room: String,
+    token: String,
+    mut messages: mpsc::Receiver<String>,
+) {
+    while let Some(message) = messages.recv().await {
+        if let Err(e) = post_to_matrix(&client, &homeserver, &room, &token, &message).await {
+            log::warn!("Failed to post to Matrix room {}: {:#}", room, e);
+        }
+    }
+}
+
+async fn post_to_matrix(
+    client: &reqwest::Client,
+    homeserver: &Url,
+    room: &str,
+    token: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    let transaction_id = format!("{:016x}", rand::random::<u64>());
+
+    let mut url = homeserver.clone();
+    url.path_segments_mut()
+        .map_err(|_| anyhow::anyhow!("Invalid homeserver URL"))?
+        .pop_if_empty()
+        .extend([
+            "_matrix",
+            "client",
+            "v3",
+            "rooms",
+            room,
+            "send",
+            "m.room.message",
+            &transaction_id,
+        ]);
+
+    let body = serde_json::json!({
+        "msgtype": "m.notice",
+        "body": message,
+    });
+
+    client
+        .put(url)
+        .bearer_auth(token)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+async fn run_irc(
+    host: String,
+    port: u16,
+    channel: String,
+    nick: String,
+    mut messages: mpsc::Receiver<String>,
+) {
+    loop {
+        match irc_session(&host, port, &channel, &nick, &mut messages).await {
+            Ok(()) => return,
+            Err(e) => log::warn!("Lost the connection to IRC server {}: {:#}", host, e),
+        }
+
+        tokio::time::sleep(IRC_RECONNECT_DELAY).await;
+    }
+}
+
+/// Stay connected to `channel` and post `messages` into it, until there are no more messages.
+async fn irc_session(
+    host: &str,
+    port: u16,
+    channel: &str,
+    nick: &str,
+    messages: &mut mpsc::Receiver<String>,
+) -> anyhow::Result<()> {
+    let stream = TcpStream::connect((host, port))
+        .await
+        .with_context(|| format!("Failed to connect to {}:{}", host, port))?;
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    let mut nick = nick.to_string();
+    write
+        .write_all(format!("NICK {}\r\nUSER {} 0 * :Grzegorz\r\n", nick, nick).as_bytes())
+        .await?;
+
+    let mut joined = false;
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let line = line?.context("Connection closed by the server")?;
+                log::trace!("IRC: {}", line);
+
+                let mut parts = line.split(' ');
+                let first = parts.next().unwrap_or_default();
+                if first == "PING" {
+                    write.write_all(format!("PONG {}\r\n", parts.collect::<Vec<_>>().join(" ")).as_bytes()).await?;
+                    continue;
+                }
+
+                match parts.next() {
+                    // Welcome, the server is ready for us to join.
+                    Some("001") => {
+                        write.write_all(format!("JOIN {}\r\n", channel).as_bytes()).await?;
+                        joined = true;
+                        log::info!("Joined IRC channel {} on {} as {}", channel, host, nick);
+                    }
+                    // Nickname in use.
+                    Some("433") => {
+                        nick.push('_');
+                        write.write_all(format!("NICK {}\r\n", nick).as_bytes()).await?;
+                    }
+                    _ => {}
+                }
+            }
+
+            message = messages.recv(), if joined => {
+                let Some(message) = message else {
+                    write.write_all(b"QUIT\r\n").await?;
+                    return Ok(());
+                };
+                let line = format!("PRIVMSG {} :{}\r\n", channel, irc_text(&message));
+                write.write_all(line.as_bytes()).await?;
+            }
+        }
+    }
+}
+
+/// Make `message` fit on a single IRC line.
+fn irc_text(message: &str) -> String {
+    let mut text: String = message.replace(['\r', '\n'], " ");
+    if text.len() > MAX_IRC_MESSAGE_LENGTH {
+        let mut end = MAX_IRC_MESSAGE_LENGTH;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push('…');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_notify_target() {
+        assert_eq!(
+            "irc://irc.pvv.ntnu.no/%23pvv?nick=grzegorz".parse(),
+            Ok(NotifyTarget::Irc {
+                host: "irc.pvv.ntnu.no".to_string(),
+                port: 6667,
+                channel: "#pvv".to_string(),
+                nick: "grzegorz".to_string(),
+            })
+        );
+        assert_eq!(
+            "irc://localhost:6668/pvv".parse(),
+            Ok(NotifyTarget::Irc {
+                host: "localhost".to_string(),
+                port: 6668,
+                channel: "#pvv".to_string(),
+                nick: "greg".to_string(),
+            })
+        );
+        assert_eq!(
+            "matrix://matrix.pvv.ntnu.no/!abcdef:pvv.ntnu.no".parse(),
+            Ok(NotifyTarget::Matrix {
+                homeserver: Url::parse("https://matrix.pvv.ntnu.no").unwrap(),
+                room: "!abcdef:pvv.ntnu.no".to_string(),
+            })
+        );
+        assert!(
+            "matrix://matrix.pvv.ntnu.no/pvv"
+                .parse::<NotifyTarget>()
+                .is_err()
+        );
+        // Fine as the opaque host of a matrix:// URL, but not as the host of an https one.
+        assert!(
+            "matrix://matrix%20pvv/!abcdef:pvv.ntnu.no"
+                .parse::<NotifyTarget>()
+                .is_err()
+        );
+        assert!("mailto:greg@pvv.ntnu.no".parse::<NotifyTarget>().is_err());
+    }
+}
@@ -0,0 +1,774 @@
+//! Logging in with OpenID Connect, for deployments where everyone has an account with the
+//! same provider, like PVV.
+//!
+//! Logging in goes through the authorization code flow of the provider, and ends with a
+//! session cookie. Requests with the cookie have the [`AuthenticatedUser`] attached, whose
+//! subject is what the items they queue are credited to, and whose groups decide whether
+//! they count as an admin.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use anyhow::Context;
+use axum::{
+    Extension, Json, Router,
+    extract::{Query, Request, State, rejection::QueryRejection},
+    http::{HeaderMap, HeaderValue, header},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ring::signature;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Value, json};
+use url::{Url, form_urlencoded};
+
+use crate::{api::ApiError, api_keys::ApiKeys, history::now, scopes::Scope};
+
+/// The cookie the session token is kept in.
+pub const SESSION_COOKIE: &str = "greg_session";
+
+/// How long a login lasts before having to log in again.
+const SESSION_LIFETIME: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// How long someone has to finish logging in with the provider.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// The most logins that can wait for the provider at once. Starting a login needs no
+/// credentials, so past this the oldest are forgotten instead of using more memory.
+const MAX_PENDING_LOGINS: usize = 1000;
+
+/// How often expired logins and sessions are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How long the provider gets to answer.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Leeway for clocks that are slightly off, when checking when a token expires.
+const CLOCK_SKEW: u64 = 60;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct OidcArgs {
+    /// Let people log in with this OpenID Connect provider, like
+    /// `https://auth.pvv.ntnu.no/oauth2/openid/greg-ng`.
+    #[clap(long, value_name = "URL", requires_all = ["oidc_client_id", "oidc_redirect_url"])]
+    pub oidc_issuer: Option<Url>,
+
+    /// The client id greg-ng is registered with at the provider.
+    #[clap(long, value_name = "ID")]
+    pub oidc_client_id: Option<String>,
+
+    /// A file with the client secret greg-ng is registered with at the provider.
+    #[clap(long, value_name = "PATH")]
+    pub oidc_client_secret_file: Option<PathBuf>,
+
+    /// Where the provider sends people back to after logging in, which is
+    /// `/api/auth/callback` on the address greg-ng is reached at.
+    #[clap(long, value_name = "URL")]
+    pub oidc_redirect_url: Option<Url>,
+
+    /// Members of these groups count as admins, as if they gave the admin token.
+    #[clap(long, value_name = "GROUP", value_delimiter = ',')]
+    pub oidc_admin_group: Vec<String>,
+
+    /// The claim of the ID token that lists the groups of the user.
+    #[clap(long, value_name = "CLAIM", default_value = "groups")]
+    pub oidc_groups_claim: String,
+}
+
+/// Someone who has logged in, or a bot with an API key, attached to their requests by
+/// [`attach_user`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuthenticatedUser {
+    /// The `sub` claim, which the provider keeps the same for this user. The items they
+    /// queue are credited to it.
+    pub subject: String,
+    /// What they are shown as: the `preferred_username`, or the `name`, or else the subject.
+    pub name: String,
+    /// What they can do. People who logged in can do what anyone can, and admins
+    /// everything.
+    pub scopes: Vec<Scope>,
+}
+
+impl AuthenticatedUser {
+    /// Whether they count as if they gave the admin token.
+    pub fn is_admin(&self) -> bool {
+        self.scopes.contains(&Scope::Admin)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    #[serde(deserialize_with = "deserialize_url")]
+    authorization_endpoint: Url,
+    #[serde(deserialize_with = "deserialize_url")]
+    token_endpoint: Url,
+    #[serde(deserialize_with = "deserialize_url")]
+    jwks_uri: Url,
+}
+
+fn deserialize_url<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Url, D::Error> {
+    let url = String::deserialize(deserializer)?;
+    Url::parse(&url).map_err(serde::de::Error::custom)
+}
+
+/// A public key of the provider, from its JWKS.
+#[derive(Debug, Clone, Deserialize)]
+struct Jwk {
+    kid: Option<String>,
+    kty: String,
+    /// RSA modulus and exponent.
+    n: Option<String>,
+    e: Option<String>,
+    /// EC curve and point.
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Debug)]
+struct PendingLogin {
+    nonce: String,
+    return_to: String,
+    expires_at: u64,
+}
+
+#[derive(Debug)]
+struct Session {
+    user: AuthenticatedUser,
+    expires_at: u64,
+}
+
+#[derive(Debug)]
+struct OidcInner {
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: Url,
+    admin_groups: Vec<String>,
+    groups_claim: String,
+    http: reqwest::Client,
+    metadata: ProviderMetadata,
+    keys: RwLock<Vec<Jwk>>,
+    /// Logins that were sent to the provider and have not come back yet, by `state`.
+    pending: Mutex<HashMap<String, PendingLogin>>,
+    /// By session token.
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+/// Logging in with an OpenID Connect provider, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct Oidc {
+    inner: Arc<OidcInner>,
+}
+
+fn random_token() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+fn decode_segment(segment: &str) -> anyhow::Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(segment.trim_end_matches('='))
+        .context("Invalid base64")
+}
+
+/// Check the signature of a JWT against `keys`, returning its claims.
+fn verify_jwt(token: &str, keys: &[Jwk]) -> anyhow::Result<Value> {
+    let mut parts = token.split('.');
+    let (Some(header), Some(claims), Some(sig), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        anyhow::bail!("Not a JWT");
+    };
+    let jwt_header: JwtHeader = serde_json::from_slice(&decode_segment(header)?)?;
+    let message = &token[..header.len() + 1 + claims.len()];
+    let sig = decode_segment(sig)?;
+
+    let candidates = keys
+        .iter()
+        .filter(|key| jwt_header.kid.is_none() || key.kid == jwt_header.kid);
+    for key in candidates {
+        let verified = match (jwt_header.alg.as_str(), key.kty.as_str()) {
+            ("RS256", "RSA") => {
+                let (Some(n), Some(e)) = (&key.n, &key.e) else {
+                    continue;
+                };
+                signature::RsaPublicKeyComponents {
+                    n: decode_segment(n)?,
+                    e: decode_segment(e)?,
+                }
+                .verify(
+                    &signature::RSA_PKCS1_2048_8192_SHA256,
+                    message.as_bytes(),
+                    &sig,
+                )
+                .is_ok()
+            }
+            ("ES256", "EC") if key.crv.as_deref() == Some("P-256") => {
+                let (Some(x), Some(y)) = (&key.x, &key.y) else {
+                    continue;
+                };
+                let point = [vec![4], decode_segment(x)?, decode_segment(y)?].concat();
+                signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point)
+                    .verify(message.as_bytes(), &sig)
+                    .is_ok()
+            }
+            _ => continue,
+        };
+        if verified {
+            return Ok(serde_json::from_slice(&decode_segment(claims)?)?);
+        }
+    }
+    anyhow::bail!(
+        "The token is not signed by any known key of the provider, or uses an unsupported algorithm ({})",
+        jwt_header.alg
+    )
+}
+
+/// The session token in the cookies of a request, if any.
+fn session_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// Whether `return_to` is a path on this server, so that logging in can not be used to send
+/// people elsewhere. Browsers read `\` as `/` and drop tabs and newlines, so `/\evil.example`
+/// would go to another host just like `//evil.example` does.
+fn is_local_path(return_to: &str) -> bool {
+    return_to.starts_with('/')
+        && !return_to.starts_with("//")
+        && !return_to.chars().any(|c| c == '\\' || c.is_control())
+}
+
+async fn get_json<T: for<'de> Deserialize<'de>>(
+    http: &reqwest::Client,
+    url: Url,
+) -> anyhow::Result<T> {
+    let response = http.get(url.clone()).send().await?.error_for_status()?;
+    serde_json::from_str(&response.text().await?)
+        .with_context(|| format!("Unexpected response from {}", url))
+}
+
+impl Oidc {
+    /// Look up the endpoints and keys of the provider in `args`, or `None` if no provider
+    /// is configured.
+    pub async fn discover(args: OidcArgs) -> anyhow::Result<Option<Self>> {
+        let Some(issuer) = args.oidc_issuer else {
+            return Ok(None);
+        };
+        let client_secret = args
+            .oidc_client_secret_file
+            .map(|path| {
+                std::fs::read_to_string(&path)
+                    .map(|secret| secret.trim().to_string())
+                    .with_context(|| format!("Failed to read {}", path.display()))
+            })
+            .transpose()?;
+
+        let http = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .context("Failed to build HTTP client")?;
+        let discovery = Url::parse(&format!(
+            "{}/.well-known/openid-configuration",
+            issuer.as_str().trim_end_matches('/')
+        ))?;
+        let metadata: ProviderMetadata = get_json(&http, discovery)
+            .await
+            .context("Failed to discover the OpenID Connect provider")?;
+        let jwks: Jwks = get_json(&http, metadata.jwks_uri.clone())
+            .await
+            .context("Failed to get the keys of the OpenID Connect provider")?;
+        log::info!("Logging in with {}", metadata.issuer);
+
+        Ok(Some(Self {
+            inner: Arc::new(OidcInner {
+                client_id: args
+                    .oidc_client_id
+                    .context("--oidc-client-id is required")?,
+                client_secret,
+                redirect_url: args
+                    .oidc_redirect_url
+                    .context("--oidc-redirect-url is required")?,
+                admin_groups: args.oidc_admin_group,
+                groups_claim: args.oidc_groups_claim,
+                http,
+                metadata,
+                keys: RwLock::new(jwks.keys),
+                pending: Mutex::default(),
+                sessions: Mutex::default(),
+            }),
+        }))
+    }
+
+    /// Whether anyone who logs in can be an admin.
+    pub fn has_admins(&self) -> bool {
+        !self.inner.admin_groups.is_empty()
+    }
+
+    /// Whoever is logged in with the session cookie in `headers`.
+    pub fn user(&self, headers: &HeaderMap) -> Option<AuthenticatedUser> {
+        let token = session_token(headers)?;
+        let mut sessions = self.inner.sessions.lock().unwrap();
+        match sessions.get(token) {
+            Some(session) if session.expires_at > now() => Some(session.user.clone()),
+            Some(_) => {
+                sessions.remove(token);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Where to send someone to log in, coming back to `return_to` afterwards.
+    fn login_url(&self, return_to: String) -> Url {
+        let state = random_token();
+        let nonce = random_token();
+        let mut pending = self.inner.pending.lock().unwrap();
+        let now = now();
+        pending.retain(|_, login| login.expires_at > now);
+        if pending.len() >= MAX_PENDING_LOGINS
+            && let Some(oldest) = pending
+                .iter()
+                .min_by_key(|(_, login)| login.expires_at)
+                .map(|(state, _)| state.clone())
+        {
+            log::warn!("Too many logins waiting for the provider, forgetting the oldest");
+            pending.remove(&oldest);
+        }
+        pending.insert(
+            state.clone(),
+            PendingLogin {
+                nonce: nonce.clone(),
+                return_to,
+                expires_at: now + LOGIN_TIMEOUT.as_secs(),
+            },
+        );
+
+        let mut url = self.inner.metadata.authorization_endpoint.clone();
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.inner.client_id)
+            .append_pair("redirect_uri", self.inner.redirect_url.as_str())
+            .append_pair("scope", "openid profile groups")
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce);
+        url
+    }
+
+    /// Check the claims of an ID token, and make a user of them.
+    fn user_from_claims(&self, claims: &Value, nonce: &str) -> anyhow::Result<AuthenticatedUser> {
+        let inner = &self.inner;
+        if claims["iss"].as_str() != Some(inner.metadata.issuer.as_str()) {
+            anyhow::bail!("The token is from another issuer");
+        }
+        let audience_matches = match &claims["aud"] {
+            Value::String(aud) => *aud == inner.client_id,
+            Value::Array(auds) => auds.iter().any(|aud| *aud == inner.client_id.as_str()),
+            _ => false,
+        };
+        if !audience_matches {
+            anyhow::bail!("The token is for another client");
+        }
+        if claims["exp"]
+            .as_u64()
+            .is_none_or(|exp| exp + CLOCK_SKEW < now())
+        {
+            anyhow::bail!("The token has expired");
+        }
+        if claims["nonce"].as_str() != Some(nonce) {
+            anyhow::bail!("The token is from another login");
+        }
+
+        let subject = claims["sub"]
+            .as_str()
+            .context("The token has no subject")?
+            .to_string();
+        let name = ["preferred_username", "name"]
+            .iter()
+            .find_map(|claim| claims[claim].as_str())
+            .unwrap_or(&subject)
+            .to_string();
+        let groups: Vec<&str> = match &claims[inner.groups_claim.as_str()] {
+            Value::Array(groups) => groups.iter().filter_map(Value::as_str).collect(),
+            Value::String(group) => vec![group.as_str()],
+            _ => vec![],
+        };
+        let is_admin = inner
+            .admin_groups
+            .iter()
+            .any(|admin_group| groups.contains(&admin_group.as_str()));
+
+        Ok(AuthenticatedUser {
+            subject,
+            name,
+            scopes: if is_admin {
+                Scope::ALL.to_vec()
+            } else {
+                Scope::EVERYONE.to_vec()
+            },
+        })
+    }
+
+    /// Verify an ID token, fetching the keys of the provider again if it was signed with
+    /// one that is not known yet, since providers rotate them.
+    async fn verify_id_token(&self, token: &str) -> anyhow::Result<Value> {
+        {
+            let keys = self.inner.keys.read().unwrap();
+            if let Ok(claims) = verify_jwt(token, &keys) {
+                return Ok(claims);
+            }
+        }
+        let jwks: Jwks = get_json(&self.inner.http, self.inner.metadata.jwks_uri.clone()).await?;
+        *self.inner.keys.write().unwrap() = jwks.keys;
+        verify_jwt(token, &self.inner.keys.read().unwrap())
+    }
+
+    /// Exchange the code the provider sent back for an ID token, and start a session.
+    /// Returns the session token, and where to go back to.
+    async fn finish_login(&self, code: &str, state: &str) -> anyhow::Result<(String, String)> {
+        let login = self.inner.pending.lock().unwrap().remove(state);
+        let login = login
+            .filter(|login| login.expires_at > now())
+            .ok_or_else(|| {
+                ApiError::InvalidArgument("The login has expired, try again".to_string())
+            })?;
+
+        let body = {
+            let mut form = form_urlencoded::Serializer::new(String::new());
+            form.append_pair("grant_type", "authorization_code")
+                .append_pair("code", code)
+                .append_pair("redirect_uri", self.inner.redirect_url.as_str())
+                .append_pair("client_id", &self.inner.client_id);
+            if let Some(secret) = &self.inner.client_secret {
+                form.append_pair("client_secret", secret);
+            }
+            form.finish()
+        };
+        let response = self
+            .inner
+            .http
+            .post(self.inner.metadata.token_endpoint.clone())
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                "application/x-www-form-urlencoded",
+            )
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()
+            .context("The provider refused the login")?;
+        let tokens: Value = serde_json::from_str(&response.text().await?)?;
+        let id_token = tokens["id_token"]
+            .as_str()
+            .context("The provider gave no ID token")?;
+
+        let claims = self.verify_id_token(id_token).await?;
+        let user = self.user_from_claims(&claims, &login.nonce)?;
+        log::info!("{} logged in", user.name);
+
+        let token = random_token();
+        self.inner.sessions.lock().unwrap().insert(
+            token.clone(),
+            Session {
+                user,
+                expires_at: now() + SESSION_LIFETIME.as_secs(),
+            },
+        );
+        Ok((token, login.return_to))
+    }
+
+    /// Forget the logins and sessions that have expired.
+    fn prune(&self) {
+        let now = now();
+        self.inner
+            .pending
+            .lock()
+            .unwrap()
+            .retain(|_, login| login.expires_at > now);
+        self.inner
+            .sessions
+            .lock()
+            .unwrap()
+            .retain(|_, session| session.expires_at > now);
+    }
+
+    /// Keep forgetting the logins and sessions that have expired, since nobody may ever
+    /// come back with them.
+    pub async fn prune_periodically(self) {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.prune();
+        }
+    }
+
+    fn logout(&self, headers: &HeaderMap) {
+        if let Some(token) = session_token(headers) {
+            self.inner.sessions.lock().unwrap().remove(token);
+        }
+    }
+
+    fn session_cookie(&self, token: &str, max_age: u64) -> HeaderValue {
+        let secure = if self.inner.redirect_url.scheme() == "https" {
+            "; Secure"
+        } else {
+            ""
+        };
+        let cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}{}",
+            SESSION_COOKIE, token, max_age, secure
+        );
+        HeaderValue::from_str(&cookie).expect("Session cookies are plain ascii")
+    }
+}
+
+/// The `/api/auth` endpoints, for logging in and out.
+pub fn oidc_routes(oidc: Oidc) -> Router {
+    Router::new()
+        .route("/api/auth/login", get(login))
+        .route("/api/auth/callback", get(callback))
+        .route("/api/auth/me", get(me))
+        .route("/api/auth/logout", post(logout))
+        .with_state(oidc)
+}
+
+#[derive(Deserialize)]
+struct LoginArgs {
+    return_to: Option<String>,
+}
+
+/// Send the browser to the provider to log in, and back to `return_to` afterwards
+async fn login(
+    State(oidc): State<Oidc>,
+    query: Result<Query<LoginArgs>, QueryRejection>,
+) -> Response {
+    let LoginArgs { return_to } = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+    let return_to = return_to
+        .filter(|path| is_local_path(path))
+        .unwrap_or_else(|| "/".to_string());
+    Redirect::to(oidc.login_url(return_to).as_str()).into_response()
+}
+
+#[derive(Deserialize)]
+struct CallbackArgs {
+    code: Option<String>,
+    state: String,
+    error: Option<String>,
+}
+
+/// Where the provider sends the browser back to after logging in
+async fn callback(
+    State(oidc): State<Oidc>,
+    query: Result<Query<CallbackArgs>, QueryRejection>,
+) -> Response {
+    let args = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+    let code = match (args.code, args.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            let error = error.unwrap_or_else(|| "no code was given".to_string());
+            return ApiError::Unauthorized(format!("Logging in failed: {}", error)).into_response();
+        }
+    };
+
+    match oidc.finish_login(&code, &args.state).await {
+        Ok((token, return_to)) => {
+            let mut response = Redirect::to(&return_to).into_response();
+            response.headers_mut().insert(
+                header::SET_COOKIE,
+                oidc.session_cookie(&token, SESSION_LIFETIME.as_secs()),
+            );
+            response
+        }
+        Err(e) => {
+            log::warn!("Logging in failed: {:#}", e);
+            match e.downcast::<ApiError>() {
+                Ok(e) => e.into_response(),
+                Err(e) => {
+                    ApiError::Unauthorized(format!("Logging in failed: {:#}", e)).into_response()
+                }
+            }
+        }
+    }
+}
+
+/// Who is logged in, with their scopes, or null if nobody is
+async fn me(State(oidc): State<Oidc>, headers: HeaderMap) -> Response {
+    Json(json!({ "success": true, "value": oidc.user(&headers) })).into_response()
+}
+
+/// Log out, ending the session
+async fn logout(State(oidc): State<Oidc>, headers: HeaderMap) -> Response {
+    oidc.logout(&headers);
+    let mut response = Json(json!({ "success": true, "value": null })).into_response();
+    response
+        .headers_mut()
+        .insert(header::SET_COOKIE, oidc.session_cookie("", 0));
+    response
+}
+
+/// Attach whoever is logged in, or whose API key is given, to the request. The endpoints
+/// that add items credit them to whoever is attached.
+pub async fn attach_user(
+    State((oidc, keys)): State<(Option<Oidc>, ApiKeys)>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let user = oidc
+        .and_then(|oidc| oidc.user(request.headers()))
+        .or_else(|| keys.user(request.headers()));
+    if let Some(user) = user {
+        request.extensions_mut().insert(user);
+    }
+    next.run(request).await
+}
+
+/// The user attached by [`attach_user`], for handlers that take it as an extractor.
+pub type LoggedIn = Option<Extension<AuthenticatedUser>>;
+
+#[cfg(test)]
+mod tests {
+    use ring::{
+        rand::SystemRandom,
+        signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair},
+    };
+
+    use super::*;
+
+    fn sign(key: &EcdsaKeyPair, claims: &Value) -> String {
+        let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"ES256","kid":"k1"}"#);
+        let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let message = format!("{}.{}", header, claims);
+        let sig = key.sign(&SystemRandom::new(), message.as_bytes()).unwrap();
+        format!("{}.{}", message, URL_SAFE_NO_PAD.encode(sig.as_ref()))
+    }
+
+    #[test]
+    fn test_verify_jwt() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        let point = key.public_key().as_ref();
+        let jwk = Jwk {
+            kid: Some("k1".to_string()),
+            kty: "EC".to_string(),
+            n: None,
+            e: None,
+            crv: Some("P-256".to_string()),
+            x: Some(URL_SAFE_NO_PAD.encode(&point[1..33])),
+            y: Some(URL_SAFE_NO_PAD.encode(&point[33..])),
+        };
+
+        let claims = json!({"sub": "alice", "groups": ["drift"]});
+        let token = sign(&key, &claims);
+        assert_eq!(
+            verify_jwt(&token, std::slice::from_ref(&jwk)).unwrap(),
+            claims
+        );
+
+        let (message, _) = token.rsplit_once('.').unwrap();
+        let forged = format!("{}.{}", message, URL_SAFE_NO_PAD.encode([0; 64]));
+        assert!(verify_jwt(&forged, &[jwk]).is_err());
+    }
+
+    #[test]
+    fn test_is_local_path() {
+        assert!(is_local_path("/"));
+        assert!(is_local_path("/control?tab=queue"));
+        assert!(!is_local_path("https://evil.example"));
+        assert!(!is_local_path("//evil.example"));
+        assert!(!is_local_path("/\\evil.example"));
+        assert!(!is_local_path("/\t/evil.example"));
+        assert!(!is_local_path("/\n/evil.example"));
+        assert!(!is_local_path("evil.example"));
+    }
+
+    #[test]
+    fn test_session_token() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("theme=dark; greg_session=abc"),
+        );
+        assert_eq!(session_token(&headers), Some("abc"));
+    }
+
+    #[test]
+    fn test_forget_logins() {
+        let url = Url::parse("https://auth.example").unwrap();
+        let oidc = Oidc {
+            inner: Arc::new(OidcInner {
+                client_id: "greg".to_string(),
+                client_secret: None,
+                redirect_url: url.clone(),
+                admin_groups: vec![],
+                groups_claim: "groups".to_string(),
+                http: reqwest::Client::new(),
+                metadata: ProviderMetadata {
+                    issuer: url.to_string(),
+                    authorization_endpoint: url.clone(),
+                    token_endpoint: url.clone(),
+                    jwks_uri: url,
+                },
+                keys: RwLock::default(),
+                pending: Mutex::default(),
+                sessions: Mutex::default(),
+            }),
+        };
+
+        for _ in 0..MAX_PENDING_LOGINS + 10 {
+            oidc.login_url("/".to_string());
+        }
+        assert_eq!(oidc.inner.pending.lock().unwrap().len(), MAX_PENDING_LOGINS);
+
+        oidc.inner.sessions.lock().unwrap().insert(
+            "expired".to_string(),
+            Session {
+                user: AuthenticatedUser {
+                    subject: "alice".to_string(),
+                    name: "alice".to_string(),
+                    scopes: vec![],
+                },
+                expires_at: now() - 1,
+            },
+        );
+        oidc.inner
+            .pending
+            .lock()
+            .unwrap()
+            .values_mut()
+            .for_each(|login| login.expires_at = now() - 1);
+        oidc.prune();
+        assert!(oidc.inner.pending.lock().unwrap().is_empty());
+        assert!(oidc.inner.sessions.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,303 @@
+//! Party mode: when the playlist runs out, keep it going with items picked from a pool.
+//!
+//! Items are picked at random, but items that were played recently are less likely to be
+//! picked again, the more recently they were played.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use anyhow::Context;
+use futures::StreamExt;
+use mpvipc_async::{Event, Mpv, MpvExt};
+use rand::distr::{Distribution, weighted::WeightedIndex};
+use tokio::sync::Notify;
+
+use crate::{
+    api::ApiError,
+    autoplay::{self, youtube_video_id},
+    history::PlayHistory,
+    library::LibraryIndex,
+    player::{self, PlaylistLock, QueueState},
+    playlists::PlaylistStore,
+};
+
+/// How many of the most recently played items are made less likely to be picked.
+const RECENT_WINDOW: usize = 50;
+
+/// Where party mode picks items from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartySource {
+    /// A saved playlist, by name.
+    Playlist(String),
+    /// The media library, or the part of it inside a directory.
+    Library(Option<PathBuf>),
+    /// The YouTube mix of the last played video.
+    Related,
+}
+
+impl FromStr for PartySource {
+    type Err = String;
+
+    /// Parses `playlist:<name>`, `library`, `library:<dir>` or `related`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("playlist", name)) if !name.is_empty() => {
+                Ok(PartySource::Playlist(name.to_string()))
+            }
+            Some(("library", dir)) if !dir.is_empty() => {
+                Ok(PartySource::Library(Some(PathBuf::from(dir))))
+            }
+            None if s == "library" => Ok(PartySource::Library(None)),
+            None if s == "related" => Ok(PartySource::Related),
+            _ => Err(format!(
+                "expected playlist:<name>, library, library:<dir> or related, got '{}'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PartySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartySource::Playlist(name) => write!(f, "playlist:{}", name),
+            PartySource::Library(None) => write!(f, "library"),
+            PartySource::Library(Some(dir)) => write!(f, "library:{}", dir.display()),
+            PartySource::Related => write!(f, "related"),
+        }
+    }
+}
+
+/// A shared handle for turning party mode on and off.
+#[derive(Debug, Clone, Default)]
+pub struct PartyMode {
+    source: Option<Arc<PartySource>>,
+    enabled: Arc<AtomicBool>,
+    changed: Arc<Notify>,
+}
+
+impl PartyMode {
+    pub fn new(source: Option<PartySource>, enabled: bool) -> Self {
+        Self {
+            source: source.map(Arc::new),
+            enabled: Arc::new(AtomicBool::new(enabled)),
+            changed: Arc::default(),
+        }
+    }
+
+    pub fn source(&self) -> Option<&PartySource> {
+        self.source.as_deref()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) -> anyhow::Result<()> {
+        if enabled && self.source.is_none() {
+            return Err(
+                ApiError::Conflict("No party mode source is configured".to_string()).into(),
+            );
+        }
+
+        log::info!(
+            "Party mode {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        self.enabled.store(enabled, Ordering::Relaxed);
+        self.changed.notify_one();
+        Ok(())
+    }
+}
+
+/// Everything party mode can pick items from.
+#[derive(Debug, Clone)]
+pub struct PartyPool {
+    pub store: PlaylistStore,
+    pub library: Option<LibraryIndex>,
+    pub yt_dlp_path: String,
+}
+
+impl PartyPool {
+    /// Everything `source` holds. `recent` are the recently played items, newest first.
+    async fn candidates(
+        &self,
+        source: &PartySource,
+        recent: &[String],
+    ) -> anyhow::Result<Vec<String>> {
+        match source {
+            PartySource::Playlist(name) => Ok(self
+                .store
+                .get(name)
+                .await?
+                .items
+                .into_iter()
+                .map(|item| item.filename)
+                .collect()),
+            PartySource::Library(dir) => {
+                let library = self
+                    .library
+                    .clone()
+                    .context("Party mode picks from the library, but no --library-dir is given")?;
+                let paths = tokio::task::spawn_blocking(move || library.indexed_paths()).await??;
+                Ok(paths
+                    .into_iter()
+                    .filter(|path| {
+                        dir.as_ref()
+                            .is_none_or(|dir| Path::new(path).starts_with(dir))
+                    })
+                    .collect())
+            }
+            PartySource::Related => {
+                let Some(video_id) = recent.iter().find_map(|path| youtube_video_id(path)) else {
+                    return Ok(Vec::new());
+                };
+                Ok(autoplay::related_videos(&self.yt_dlp_path, &video_id)
+                    .await?
+                    .into_iter()
+                    .map(|video| video.url)
+                    .collect())
+            }
+        }
+    }
+}
+
+/// How likely each candidate is to be picked, given the recently played items, newest first.
+fn weights(candidates: &[String], recent: &[String]) -> Vec<f64> {
+    candidates
+        .iter()
+        .map(|candidate| {
+            match recent
+                .iter()
+                .take(RECENT_WINDOW)
+                .position(|path| path == candidate)
+            {
+                Some(age) => ((age + 1) as f64 / (RECENT_WINDOW + 1) as f64).powi(2),
+                None => 1.0,
+            }
+        })
+        .collect()
+}
+
+/// Lock the playlist if nothing is left after the current item, returning the lock
+/// together with the recently played items, newest first.
+///
+/// What is still in the playlist counts as the most recently played. While an external
+/// input is active, the queue is suspended and never counts as empty.
+pub async fn lock_empty_queue(
+    mpv: &Mpv,
+    queue: &QueueState,
+    history: &PlayHistory,
+) -> anyhow::Result<Option<(PlaylistLock, Vec<String>)>> {
+    let lock = player::lock_playlist(queue).await;
+    if queue.is_suspended() {
+        return Ok(None);
+    }
+    let playlist = mpv.get_playlist().await?.0;
+    let upcoming = match playlist.iter().position(|entry| entry.current) {
+        Some(current) => playlist.len() - current - 1,
+        None => 0,
+    };
+    if upcoming > 0 {
+        return Ok(None);
+    }
+
+    let recent = playlist
+        .into_iter()
+        .rev()
+        .map(|entry| entry.filename)
+        .chain(
+            history
+                .recent(RECENT_WINDOW)?
+                .into_iter()
+                .map(|entry| entry.path),
+        )
+        .collect();
+    Ok(Some((lock, recent)))
+}
+
+/// Add an item to the playlist, if party mode is on and nothing is left after the current item.
+async fn top_up(
+    mpv: &Mpv,
+    queue: &QueueState,
+    party: &PartyMode,
+    pool: &PartyPool,
+    history: &PlayHistory,
+) -> anyhow::Result<()> {
+    let Some(source) = party.source().filter(|_| party.is_enabled()) else {
+        return Ok(());
+    };
+
+    let Some((_lock, recent)) = lock_empty_queue(mpv, queue, history).await? else {
+        return Ok(());
+    };
+    let candidates = pool.candidates(source, &recent).await?;
+
+    let Ok(distribution) = WeightedIndex::new(weights(&candidates, &recent)) else {
+        log::warn!("Party mode has nothing to pick from {}", source);
+        return Ok(());
+    };
+    let pick = &candidates[distribution.sample(&mut rand::rng())];
+
+    log::info!("Party mode picked '{}'", pick);
+    mpv.run_command_raw("loadfile", &[pick, "append-play"])
+        .await?;
+    Ok(())
+}
+
+/// Keep the playlist going while party mode is on, until mpv goes away.
+pub async fn run_party_mode(
+    mpv: Mpv,
+    queue: QueueState,
+    party: PartyMode,
+    pool: PartyPool,
+    history: PlayHistory,
+) -> anyhow::Result<()> {
+    let mut event_stream = mpv.get_event_stream().await;
+
+    loop {
+        tokio::select! {
+            event = event_stream.next() => match event.context("Lost the mpv events")?? {
+                Event::StartFile { .. } | Event::Idle => {}
+                Event::Shutdown => return Ok(()),
+                _ => continue,
+            },
+            _ = party.changed.notified() => {}
+        }
+
+        if let Err(e) = top_up(&mpv, &queue, &party, &pool, &history).await {
+            log::warn!("Party mode could not pick the next item: {:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weights() {
+        let candidates: Vec<String> = ["a", "b", "c"].map(String::from).to_vec();
+        let recent: Vec<String> = ["c", "x", "a"].map(String::from).to_vec();
+
+        let weights = weights(&candidates, &recent);
+        assert_eq!(weights[1], 1.0);
+        // The most recently played is the least likely.
+        assert!(weights[2] < weights[0]);
+        assert!(weights[0] < weights[1]);
+
+        assert_eq!(
+            "playlist:Friday".parse(),
+            Ok(PartySource::Playlist("Friday".to_string()))
+        );
+        assert_eq!("related".parse(), Ok(PartySource::Related));
+        assert!("playlist:".parse::<PartySource>().is_err());
+    }
+}
@@ -0,0 +1,106 @@
+//! A regular heartbeat with where playback is, for clients that draw a progress bar.
+//!
+//! Each heartbeat says how far into the item playback was at a given wall-clock time, so
+//! clients can move the progress bar along on their own between heartbeats, and notice
+//! when they drift from the player.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use mpvipc_async::Mpv;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+/// How often a heartbeat is sent.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Heartbeat {
+    /// How far into the current item playback is, in seconds, or null if nothing is playing.
+    pub position: Option<f64>,
+    /// The length of the current item in seconds, if known.
+    pub duration: Option<f64>,
+    /// How fast playback goes, where 1.0 is normal speed.
+    pub speed: f64,
+    pub paused: bool,
+    /// When `position` was read, in milliseconds since the unix epoch.
+    pub server_time_ms: u64,
+}
+
+/// Sends heartbeats to whoever is subscribed, as long as anyone is.
+#[derive(Debug, Clone)]
+pub struct PlaybackClock {
+    sender: watch::Sender<Option<Heartbeat>>,
+}
+
+impl PlaybackClock {
+    /// Start reading the playback position of `mpv` in the background.
+    pub fn spawn(mpv: Mpv) -> Self {
+        let sender = watch::Sender::new(None);
+        tokio::spawn(run(mpv, sender.clone()));
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Option<Heartbeat>> {
+        self.sender.subscribe()
+    }
+}
+
+async fn read_heartbeat(mpv: &Mpv) -> anyhow::Result<Heartbeat> {
+    let position: Option<f64> = mpv.get_property("time-pos").await?;
+    let server_time_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)?
+        .as_millis()
+        .try_into()?;
+    Ok(Heartbeat {
+        position,
+        duration: mpv.get_property("duration").await?,
+        speed: mpv.get_property("speed").await?.unwrap_or(1.0),
+        paused: mpv.get_property("pause").await?.unwrap_or(true),
+        server_time_ms,
+    })
+}
+
+async fn run(mpv: Mpv, sender: watch::Sender<Option<Heartbeat>>) {
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        // Nobody is listening, so there is no need to bother mpv.
+        if sender.receiver_count() == 0 {
+            continue;
+        }
+
+        match read_heartbeat(&mpv).await {
+            Ok(heartbeat) => {
+                sender.send_replace(Some(heartbeat));
+            }
+            Err(e) => log::debug!("Failed to read the playback position: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::FakeMpv;
+
+    #[tokio::test]
+    async fn test_heartbeat() {
+        let mpv = FakeMpv::start();
+        mpv.set_property("time-pos", json!(12.5));
+        mpv.set_property("duration", json!(180.0));
+        mpv.set_property("pause", json!(false));
+
+        let clock = PlaybackClock::spawn(mpv.connect().await);
+        let mut heartbeats = clock.subscribe();
+        heartbeats.changed().await.unwrap();
+        let heartbeat = heartbeats.borrow().clone().unwrap();
+        assert_eq!(heartbeat.position, Some(12.5));
+        assert_eq!(heartbeat.duration, Some(180.0));
+        assert_eq!(heartbeat.speed, 1.0);
+        assert!(!heartbeat.paused);
+        assert!(heartbeat.server_time_ms > 0);
+    }
+}
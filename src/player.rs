@@ -0,0 +1,182 @@
+use std::{fmt, str::FromStr, sync::Arc};
+
+use async_trait::async_trait;
+use mpvipc_async::Mpv;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::OwnedMutexGuard;
+
+use crate::api::ApiError;
+
+mod blocks;
+mod breaker;
+mod clear_guard;
+mod dlna;
+mod interject;
+mod mpv;
+mod notes;
+mod pins;
+mod play_at;
+mod priority;
+mod queue;
+mod retry;
+mod wake;
+mod window;
+
+pub use blocks::{block_indices, block_moves, blocks, group_items, ungroup_items};
+pub use breaker::{BreakerState, CircuitBreaker, GuardedPlayer, is_unreachable};
+pub use clear_guard::PlaylistClearGuard;
+pub use dlna::DlnaPlayer;
+pub use interject::{interject, seek_when_loaded};
+pub use mpv::{MpvPlayer, loaded_entry_id};
+pub use notes::ItemNote;
+pub use pins::{ItemFlags, check_clear, check_moves, check_removal, check_shuffle};
+pub use play_at::{cancel_play_at, play_at};
+pub use priority::{Lane, Priority, fair_index, priority_index};
+pub use queue::{EntryId, HeldItem, ItemState, LoadOptions, QueueState};
+pub use retry::{RetryPolicy, RetryQueue};
+pub use wake::wake_on_queue;
+pub use window::WindowedPlayer;
+
+/// A shared handle to whichever player backend is in use.
+pub type PlayerHandle = Arc<dyn Player>;
+
+/// Which backend plays the media.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    /// Play locally with mpv.
+    Mpv,
+    /// Cast to a DLNA / UPnP media renderer on the network.
+    Dlna,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistEntry {
+    pub id: EntryId,
+    pub filename: String,
+    pub title: Option<String>,
+    pub current: bool,
+}
+
+/// Moving the item at `from` to before the item currently at `to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct PlaylistMove {
+    pub from: usize,
+    pub to: usize,
+}
+
+impl FromStr for PlaylistMove {
+    type Err = String;
+
+    /// Parses `<from>:<to>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| format!("expected <from>:<to>, got '{}'", s))?;
+
+        Ok(PlaylistMove {
+            from: from
+                .parse()
+                .map_err(|e| format!("invalid index '{}': {}", from, e))?,
+            to: to
+                .parse()
+                .map_err(|e| format!("invalid index '{}': {}", to, e))?,
+        })
+    }
+}
+
+/// Proof that the playlist is locked, see [`lock_playlist`].
+///
+/// Functions that need the lock, but may be called by someone who already holds it,
+/// take a reference to this instead of locking on their own.
+#[derive(Debug)]
+pub struct PlaylistLock {
+    _guard: OwnedMutexGuard<()>,
+}
+
+/// Lock the playlist of the player `queue` belongs to, for changes made up of several
+/// player commands, like a batch of moves.
+///
+/// Other changes that take the lock will wait until the lock is dropped, so the indices
+/// seen while holding it stay valid.
+pub async fn lock_playlist(queue: &QueueState) -> PlaylistLock {
+    PlaylistLock {
+        _guard: queue.playlist_lock().lock_owned().await,
+    }
+}
+
+/// The error for features the player backend does not support.
+fn unsupported() -> anyhow::Error {
+    ApiError::Conflict("Not supported by the current player backend".to_string()).into()
+}
+
+/// The operations every player backend has to support, so that the REST API
+/// can be served regardless of where the media ends up playing.
+///
+/// Indices are zero based positions in the playlist. Playlist moves follow the
+/// mpv semantics, where the item is inserted before the item currently at `to`.
+#[async_trait]
+pub trait Player: fmt::Debug + Send + Sync {
+    /// What greg-ng keeps track of about the items in the queue of this player.
+    fn queue_state(&self) -> &QueueState;
+
+    /// The mpv instance behind this player, for features that only mpv supports.
+    fn mpv(&self) -> Option<&Mpv> {
+        None
+    }
+
+    /// Get an mpv property, for features that only mpv supports.
+    async fn get_property(&self, _name: &str) -> anyhow::Result<Option<Value>> {
+        Err(unsupported())
+    }
+
+    /// Set an mpv property to a boolean, a number or a string, for features that only mpv
+    /// supports.
+    async fn set_property(&self, _name: &str, _value: Value) -> anyhow::Result<()> {
+        Err(unsupported())
+    }
+
+    /// Append an item to the playlist with `item` as its state, returning the id it was
+    /// given. It is played with the [`LoadOptions`] in `item`.
+    async fn load(&self, url: &str, item: ItemState) -> anyhow::Result<EntryId>;
+
+    async fn is_playing(&self) -> anyhow::Result<bool>;
+    async fn set_playing(&self, playing: bool) -> anyhow::Result<()>;
+
+    /// The volume in percent.
+    async fn get_volume(&self) -> anyhow::Result<f64>;
+    async fn set_volume(&self, volume: f64) -> anyhow::Result<()>;
+
+    /// The playback position of the current item in seconds, if anything is playing.
+    async fn get_time_pos(&self) -> anyhow::Result<Option<f64>>;
+    async fn get_time_remaining(&self) -> anyhow::Result<Option<f64>>;
+    async fn seek(&self, seconds: f64) -> anyhow::Result<()>;
+    async fn seek_percent(&self, percent: f64) -> anyhow::Result<()>;
+
+    async fn playlist(&self) -> anyhow::Result<Vec<PlaylistEntry>>;
+    async fn playlist_next(&self) -> anyhow::Result<()>;
+    async fn playlist_previous(&self) -> anyhow::Result<()>;
+    async fn playlist_goto(&self, index: usize) -> anyhow::Result<()>;
+    async fn playlist_remove(&self, index: usize) -> anyhow::Result<()>;
+    async fn playlist_move(&self, from: usize, to: usize) -> anyhow::Result<()>;
+    /// Remove every item except the one currently playing.
+    async fn playlist_clear(&self) -> anyhow::Result<()>;
+    async fn playlist_shuffle(&self) -> anyhow::Result<()>;
+    async fn is_looping(&self) -> anyhow::Result<bool>;
+    async fn set_looping(&self, looping: bool) -> anyhow::Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_playlist_move() {
+        assert_eq!("3:0".parse(), Ok(PlaylistMove { from: 3, to: 0 }));
+        assert_eq!(" 1:12".parse(), Ok(PlaylistMove { from: 1, to: 12 }));
+        assert!("3".parse::<PlaylistMove>().is_err());
+        assert!("a:0".parse::<PlaylistMove>().is_err());
+        assert!("-1:0".parse::<PlaylistMove>().is_err());
+    }
+}
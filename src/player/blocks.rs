@@ -0,0 +1,182 @@
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::api::ApiError;
+
+use super::{PlaylistEntry, PlaylistMove, QueueState};
+
+/// Items grouped together, like an album or a themed set, to be moved, removed or
+/// skipped as one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Block {
+    pub name: String,
+    /// Where the items of the block are in the playlist, in order.
+    pub indices: Vec<usize>,
+}
+
+/// Every block in `playlist`, in the order they first show up.
+///
+/// The block of every item is kept in its state in `queue`, so that blocks follow their
+/// items around as the playlist changes.
+pub fn blocks(queue: &QueueState, playlist: &[PlaylistEntry]) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+    for (index, entry) in playlist.iter().enumerate() {
+        let Some(name) = queue.item(entry).block else {
+            continue;
+        };
+        match blocks.iter_mut().find(|block| block.name == name) {
+            Some(block) => block.indices.push(index),
+            None => blocks.push(Block {
+                name,
+                indices: vec![index],
+            }),
+        }
+    }
+    blocks
+}
+
+/// The indices of the items in the block called `name`.
+pub fn block_indices(
+    queue: &QueueState,
+    playlist: &[PlaylistEntry],
+    name: &str,
+) -> Result<Vec<usize>, ApiError> {
+    blocks(queue, playlist)
+        .into_iter()
+        .find(|block| block.name == name)
+        .map(|block| block.indices)
+        .ok_or_else(|| ApiError::NotFound(format!("No block named '{}' in the playlist", name)))
+}
+
+/// Group the items at `range` into a block called `name`, taking them out of any block
+/// they were in.
+pub fn group_items(
+    queue: &QueueState,
+    playlist: &[PlaylistEntry],
+    name: &str,
+    range: Range<usize>,
+) -> Result<(), ApiError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(ApiError::InvalidArgument(
+            "A block needs a name".to_string(),
+        ));
+    }
+    if range.is_empty() || range.end > playlist.len() {
+        return Err(ApiError::InvalidIndex(format!(
+            "Can not group items {} to {} (playlist has {} items)",
+            range.start,
+            range.end,
+            playlist.len()
+        )));
+    }
+    if let Ok(indices) = block_indices(queue, playlist, name)
+        && indices.iter().any(|index| !range.contains(index))
+    {
+        return Err(ApiError::Conflict(format!(
+            "There is already a block named '{}'",
+            name
+        )));
+    }
+
+    for entry in &playlist[range] {
+        queue.update(entry.id, |item| item.block = Some(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Split up the block called `name`, leaving its items where they are.
+pub fn ungroup_items(
+    queue: &QueueState,
+    playlist: &[PlaylistEntry],
+    name: &str,
+) -> Result<(), ApiError> {
+    for index in block_indices(queue, playlist, name)? {
+        queue.update(playlist[index].id, |item| item.block = None);
+    }
+    Ok(())
+}
+
+/// The moves that bring the items at `indices` together, in order, before the item
+/// currently at `to`. Like a single move, `to` can be one past the end of the playlist.
+pub fn block_moves(indices: &[usize], to: usize) -> Result<Vec<PlaylistMove>, ApiError> {
+    let (Some(first), Some(last)) = (indices.first(), indices.last()) else {
+        return Ok(Vec::new());
+    };
+    if to > *first && to <= *last {
+        return Err(ApiError::InvalidArgument(
+            "A block can not be moved into itself".to_string(),
+        ));
+    }
+
+    let moves = indices
+        .iter()
+        .enumerate()
+        .map(|(i, from)| {
+            if to <= *first {
+                // Every item lands right after the one before it, below the rest of the block.
+                PlaylistMove {
+                    from: *from,
+                    to: to + i,
+                }
+            } else {
+                // Every item taken out shifts the rest of the block one step down.
+                PlaylistMove { from: from - i, to }
+            }
+        })
+        .collect();
+    Ok(moves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::EntryId;
+
+    #[test]
+    fn test_blocks() {
+        // Two copies of the same file can be in different blocks.
+        let playlist: Vec<PlaylistEntry> = ["a.mp3", "b.mp3", "c.mp3", "d.mp3", "b.mp3"]
+            .into_iter()
+            .enumerate()
+            .map(|(id, filename)| PlaylistEntry {
+                id: EntryId::Player(id),
+                filename: filename.to_string(),
+                title: None,
+                current: false,
+            })
+            .collect();
+        let queue = QueueState::default();
+
+        group_items(&queue, &playlist, "album", 1..3).unwrap();
+        assert_eq!(queue.item(&playlist[1]).block.as_deref(), Some("album"));
+        assert_eq!(queue.item(&playlist[4]).block, None);
+        assert_eq!(
+            block_indices(&queue, &playlist, "album").unwrap(),
+            vec![1, 2]
+        );
+        assert!(group_items(&queue, &playlist, "album", 3..4).is_err());
+        assert!(group_items(&queue, &playlist, "other", 4..6).is_err());
+        assert!(group_items(&queue, &playlist, " ", 0..1).is_err());
+        assert!(block_indices(&queue, &playlist, "missing").is_err());
+
+        // Play the moves out the same way mpv does.
+        let apply = |moves: Vec<PlaylistMove>| {
+            let mut order: Vec<usize> = (0..playlist.len()).collect();
+            for PlaylistMove { from, to } in moves {
+                let item = order.remove(from);
+                let to = if to > from { to - 1 } else { to };
+                order.insert(to.min(order.len()), item);
+            }
+            order
+        };
+        assert_eq!(apply(block_moves(&[1, 2], 0).unwrap()), vec![1, 2, 0, 3, 4]);
+        assert_eq!(apply(block_moves(&[1, 2], 5).unwrap()), vec![0, 3, 4, 1, 2]);
+        assert_eq!(apply(block_moves(&[0, 3], 5).unwrap()), vec![1, 2, 4, 0, 3]);
+        assert!(block_moves(&[1, 2], 2).is_err());
+
+        ungroup_items(&queue, &playlist, "album").unwrap();
+        assert_eq!(blocks(&queue, &playlist), Vec::new());
+    }
+}
@@ -0,0 +1,385 @@
+//! Keeps requests from hanging on a player that has stopped responding.
+//!
+//! Every call to the player is given a time limit. After a few calls in a row have timed
+//! out or could not reach the player, the breaker opens, and calls fail right away for a
+//! while instead of piling up. Then a single call is let through to see if the player has
+//! recovered.
+
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use mpvipc_async::{Mpv, MpvError};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::time::Instant;
+
+use crate::{api::ApiError, history::now};
+
+use super::{EntryId, ItemState, Player, PlayerHandle, PlaylistEntry, QueueState};
+
+/// How many calls in a row have to fail before the breaker opens.
+const FAILURE_THRESHOLD: u32 = 3;
+
+/// How long calls fail right away after the breaker opens, before one is let through.
+const OPEN_DURATION: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BreakerState {
+    /// The player is responding, and calls go through.
+    Closed,
+    /// The player stopped responding, and calls fail right away.
+    Open,
+    /// A call is being let through, to see if the player has recovered.
+    HalfOpen,
+}
+
+/// The state of a [`CircuitBreaker`], as shown by `/health`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BreakerStatus {
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    /// When the player stopped responding, in seconds since the unix epoch.
+    pub unresponsive_since: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerInner {
+    consecutive_failures: u32,
+    /// When the breaker last opened, or opened again after a failed try.
+    opened_at: Option<Instant>,
+    /// When the breaker first opened, kept until the player responds again.
+    unresponsive_since: Option<(Instant, u64)>,
+    /// Whether the call that was let through while half open is still running.
+    trying: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    inner: Arc<Mutex<BreakerInner>>,
+    timeout: Duration,
+}
+
+/// Whether an error means the player could not be reached, rather than it refusing a call.
+pub fn is_unreachable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<MpvError>(),
+            Some(MpvError::MpvSocketConnectionError(_) | MpvError::InternalConnectionError(_))
+        ) || matches!(
+            cause.downcast_ref::<ApiError>(),
+            Some(ApiError::MpvUnavailable(_))
+        )
+    })
+}
+
+impl CircuitBreaker {
+    /// Calls taking longer than `timeout` count as failed.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            inner: Arc::default(),
+            timeout,
+        }
+    }
+
+    pub fn status(&self) -> BreakerStatus {
+        let inner = self.inner.lock().unwrap();
+        let state = match inner.opened_at {
+            None => BreakerState::Closed,
+            Some(_) if inner.trying => BreakerState::HalfOpen,
+            Some(opened_at) if opened_at.elapsed() >= OPEN_DURATION => BreakerState::HalfOpen,
+            Some(_) => BreakerState::Open,
+        };
+        BreakerStatus {
+            state,
+            consecutive_failures: inner.consecutive_failures,
+            unresponsive_since: inner.unresponsive_since.map(|(_, since)| since),
+        }
+    }
+
+    /// How long the player has been unresponsive, if it is.
+    pub fn unresponsive_for(&self) -> Option<Duration> {
+        let inner = self.inner.lock().unwrap();
+        inner.unresponsive_since.map(|(since, _)| since.elapsed())
+    }
+
+    /// Wait until the player has been unresponsive for `limit`.
+    pub async fn unresponsive_at_least(&self, limit: Duration) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            if self
+                .unresponsive_for()
+                .is_some_and(|duration| duration >= limit)
+            {
+                return;
+            }
+        }
+    }
+
+    /// Whether a call may go through now.
+    fn allow(&self) -> Option<Attempt<'_>> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.opened_at {
+            None => Some(Attempt {
+                breaker: self,
+                probe: false,
+            }),
+            Some(opened_at) if opened_at.elapsed() >= OPEN_DURATION && !inner.trying => {
+                inner.trying = true;
+                Some(Attempt {
+                    breaker: self,
+                    probe: true,
+                })
+            }
+            Some(_) => None,
+        }
+    }
+
+    fn succeeded(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.opened_at.is_some() {
+            log::info!("The player is responding again");
+        }
+        *inner = BreakerInner::default();
+    }
+
+    fn failed(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures += 1;
+        if inner.consecutive_failures >= FAILURE_THRESHOLD || inner.trying {
+            if inner.opened_at.is_none() {
+                log::warn!(
+                    "The player failed to respond {} times in a row, failing calls for {} seconds",
+                    inner.consecutive_failures,
+                    OPEN_DURATION.as_secs()
+                );
+            }
+            let now_instant = Instant::now();
+            inner.opened_at = Some(now_instant);
+            inner.unresponsive_since.get_or_insert((now_instant, now()));
+            inner.trying = false;
+        }
+    }
+
+    /// Run a call to the player, unless the breaker is open.
+    pub async fn call<T>(
+        &self,
+        call: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        let Some(_attempt) = self.allow() else {
+            return Err(
+                ApiError::MpvUnavailable("The player is not responding".to_string()).into(),
+            );
+        };
+
+        match tokio::time::timeout(self.timeout, call).await {
+            Ok(result) => {
+                match &result {
+                    Err(e) if is_unreachable(e) => self.failed(),
+                    _ => self.succeeded(),
+                }
+                result
+            }
+            Err(_) => {
+                self.failed();
+                Err(ApiError::MpvUnavailable(format!(
+                    "The player did not respond within {} seconds",
+                    self.timeout.as_secs_f64()
+                ))
+                .into())
+            }
+        }
+    }
+}
+
+/// A call the breaker let through.
+///
+/// If the call that was let through while half open is dropped before it finishes, like
+/// when the client making the request goes away, the next call is let through instead.
+struct Attempt<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        if self.probe {
+            self.breaker.inner.lock().unwrap().trying = false;
+        }
+    }
+}
+
+/// A player whose calls all go through a [`CircuitBreaker`].
+#[derive(Debug)]
+pub struct GuardedPlayer {
+    inner: PlayerHandle,
+    breaker: CircuitBreaker,
+}
+
+impl GuardedPlayer {
+    pub fn new(inner: PlayerHandle, breaker: CircuitBreaker) -> Self {
+        Self { inner, breaker }
+    }
+}
+
+#[async_trait]
+impl Player for GuardedPlayer {
+    fn queue_state(&self) -> &QueueState {
+        self.inner.queue_state()
+    }
+
+    fn mpv(&self) -> Option<&Mpv> {
+        self.inner.mpv()
+    }
+
+    async fn get_property(&self, name: &str) -> anyhow::Result<Option<Value>> {
+        self.breaker.call(self.inner.get_property(name)).await
+    }
+
+    async fn set_property(&self, name: &str, value: Value) -> anyhow::Result<()> {
+        self.breaker
+            .call(self.inner.set_property(name, value))
+            .await
+    }
+
+    async fn load(&self, url: &str, item: ItemState) -> anyhow::Result<EntryId> {
+        self.breaker.call(self.inner.load(url, item)).await
+    }
+
+    async fn is_playing(&self) -> anyhow::Result<bool> {
+        self.breaker.call(self.inner.is_playing()).await
+    }
+
+    async fn set_playing(&self, playing: bool) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.set_playing(playing)).await
+    }
+
+    async fn get_volume(&self) -> anyhow::Result<f64> {
+        self.breaker.call(self.inner.get_volume()).await
+    }
+
+    async fn set_volume(&self, volume: f64) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.set_volume(volume)).await
+    }
+
+    async fn get_time_pos(&self) -> anyhow::Result<Option<f64>> {
+        self.breaker.call(self.inner.get_time_pos()).await
+    }
+
+    async fn get_time_remaining(&self) -> anyhow::Result<Option<f64>> {
+        self.breaker.call(self.inner.get_time_remaining()).await
+    }
+
+    async fn seek(&self, seconds: f64) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.seek(seconds)).await
+    }
+
+    async fn seek_percent(&self, percent: f64) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.seek_percent(percent)).await
+    }
+
+    async fn playlist(&self) -> anyhow::Result<Vec<PlaylistEntry>> {
+        self.breaker.call(self.inner.playlist()).await
+    }
+
+    async fn playlist_next(&self) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.playlist_next()).await
+    }
+
+    async fn playlist_previous(&self) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.playlist_previous()).await
+    }
+
+    async fn playlist_goto(&self, index: usize) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.playlist_goto(index)).await
+    }
+
+    async fn playlist_remove(&self, index: usize) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.playlist_remove(index)).await
+    }
+
+    async fn playlist_move(&self, from: usize, to: usize) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.playlist_move(from, to)).await
+    }
+
+    async fn playlist_clear(&self) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.playlist_clear()).await
+    }
+
+    async fn playlist_shuffle(&self) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.playlist_shuffle()).await
+    }
+
+    async fn is_looping(&self) -> anyhow::Result<bool> {
+        self.breaker.call(self.inner.is_looping()).await
+    }
+
+    async fn set_looping(&self, looping: bool) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.set_looping(looping)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(Duration::from_secs(1));
+        let hang = || std::future::pending::<anyhow::Result<()>>();
+
+        assert!(breaker.call(async { Ok(()) }).await.is_ok());
+        for _ in 0..FAILURE_THRESHOLD {
+            assert!(breaker.call(hang()).await.is_err());
+        }
+        assert_eq!(breaker.status().state, BreakerState::Open);
+        assert!(breaker.status().unresponsive_since.is_some());
+
+        // Calls fail right away while the breaker is open.
+        let error = breaker.call(async { Ok(()) }).await.unwrap_err();
+        assert_eq!(
+            ApiError::from(error),
+            ApiError::MpvUnavailable("The player is not responding".to_string())
+        );
+
+        // A failed try opens it again, and a successful one closes it.
+        tokio::time::advance(OPEN_DURATION).await;
+        assert_eq!(breaker.status().state, BreakerState::HalfOpen);
+        assert!(breaker.call(hang()).await.is_err());
+        assert_eq!(breaker.status().state, BreakerState::Open);
+        tokio::time::advance(OPEN_DURATION).await;
+        assert!(breaker.unresponsive_for().unwrap() > OPEN_DURATION);
+        assert!(breaker.call(async { Ok(()) }).await.is_ok());
+        assert_eq!(breaker.status().state, BreakerState::Closed);
+        assert_eq!(breaker.unresponsive_for(), None);
+
+        // Errors from a player that responds do not count.
+        for _ in 0..FAILURE_THRESHOLD {
+            let refused = async { Err::<(), _>(anyhow::anyhow!("No such item")) };
+            assert!(breaker.call(refused).await.is_err());
+        }
+        assert_eq!(breaker.status().state, BreakerState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dropped_try() {
+        let breaker = CircuitBreaker::new(Duration::from_secs(1));
+        let hang = || std::future::pending::<anyhow::Result<()>>();
+
+        for _ in 0..FAILURE_THRESHOLD {
+            assert!(breaker.call(hang()).await.is_err());
+        }
+        tokio::time::advance(OPEN_DURATION).await;
+
+        // The try is dropped before the player responds, so the next call tries again.
+        let dropped = tokio::time::timeout(Duration::ZERO, breaker.call(hang())).await;
+        assert!(dropped.is_err());
+        assert!(breaker.call(async { Ok(()) }).await.is_ok());
+        assert_eq!(breaker.status().state, BreakerState::Closed);
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::api::ApiError;
+
+/// How long a confirmation token can be used after it was handed out.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Keeps large playlists from being cleared by accident.
+///
+/// Clearing a playlist with more items than the threshold has to be either forced,
+/// or confirmed by repeating the request with the token from the first attempt.
+#[derive(Debug, Clone)]
+pub struct PlaylistClearGuard {
+    threshold: Option<usize>,
+    tokens: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl PlaylistClearGuard {
+    /// Require confirmation for clearing more than `threshold` items, or never if `None`.
+    pub fn new(threshold: Option<usize>) -> Self {
+        Self {
+            threshold,
+            tokens: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Check whether a playlist of `length` items may be cleared.
+    ///
+    /// Fails with [`ApiError::ConfirmationRequired`] and a fresh token if it may not.
+    pub fn check(&self, length: usize, force: bool, token: Option<&str>) -> Result<(), ApiError> {
+        let Some(threshold) = self.threshold else {
+            return Ok(());
+        };
+        if force || length <= threshold {
+            return Ok(());
+        }
+
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.retain(|_, issued| issued.elapsed() < TOKEN_LIFETIME);
+
+        if let Some(token) = token {
+            if tokens.remove(token).is_some() {
+                return Ok(());
+            }
+            log::debug!("Rejected unknown or expired playlist clear token");
+        }
+
+        let token = format!("{:016x}", rand::random::<u64>());
+        tokens.insert(token.clone(), Instant::now());
+
+        Err(ApiError::ConfirmationRequired {
+            message: format!(
+                "The playlist has {} items, repeat the request with force=true or token={} within {} seconds to clear it",
+                length,
+                token,
+                TOKEN_LIFETIME.as_secs()
+            ),
+            token,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clear_confirmation() {
+        let guard = PlaylistClearGuard::new(Some(10));
+        assert!(guard.check(10, false, None).is_ok());
+        assert!(guard.check(11, true, None).is_ok());
+
+        let Err(ApiError::ConfirmationRequired { token, .. }) = guard.check(11, false, None) else {
+            panic!("Clearing a large playlist should require confirmation");
+        };
+        assert!(guard.check(11, false, Some("wrong")).is_err());
+        assert!(guard.check(11, false, Some(&token)).is_ok());
+
+        // Tokens can only be used once.
+        assert!(guard.check(11, false, Some(&token)).is_err());
+
+        assert!(
+            PlaylistClearGuard::new(None)
+                .check(1000, false, None)
+                .is_ok()
+        );
+    }
+}
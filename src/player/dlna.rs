@@ -0,0 +1,628 @@
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use rand::seq::SliceRandom;
+use tokio::sync::Mutex;
+use url::Url;
+
+use super::{EntryId, ItemState, Player, PlaylistEntry, QueueState};
+
+const AV_TRANSPORT_SERVICE: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+const RENDERING_CONTROL_SERVICE: &str = "urn:schemas-upnp-org:service:RenderingControl:1";
+
+/// How often the renderer is asked whether the current item has finished.
+const TRANSPORT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Renderers usually report `STOPPED` for a moment while switching to a new item,
+/// which should not be mistaken for the item having finished.
+const TRACK_CHANGE_GRACE_PERIOD: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// The urls of the items, by the id they were given.
+    items: Vec<(usize, String)>,
+    next_id: usize,
+    current: Option<usize>,
+    looping: bool,
+    /// When the renderer was last told to play something new.
+    last_track_change: Option<Instant>,
+}
+
+/// Casts to a DLNA / UPnP media renderer.
+///
+/// Renderers only know about a single item at a time, so the playlist is kept here,
+/// and the next item is sent to the renderer whenever the current one finishes.
+#[derive(Clone)]
+pub struct DlnaPlayer {
+    client: reqwest::Client,
+    av_transport_url: Url,
+    rendering_control_url: Url,
+    queue: Arc<Mutex<Queue>>,
+    queue_state: QueueState,
+}
+
+impl fmt::Debug for DlnaPlayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DlnaPlayer")
+            .field("av_transport_url", &self.av_transport_url.as_str())
+            .field(
+                "rendering_control_url",
+                &self.rendering_control_url.as_str(),
+            )
+            .finish()
+    }
+}
+
+impl DlnaPlayer {
+    /// Connect to the renderer described by the device description at `description_url`,
+    /// and start following its playback.
+    pub async fn connect(description_url: &str) -> anyhow::Result<Self> {
+        let description_url = Url::parse(description_url)
+            .context(format!("Invalid renderer url '{}'", description_url))?;
+        let client = reqwest::Client::new();
+
+        let description = client
+            .get(description_url.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await
+            .context(format!(
+                "Failed to fetch device description from '{}'",
+                description_url
+            ))?;
+
+        let base_url = match xml_element_text(&description, "URLBase") {
+            Some(base) => Url::parse(&base)?,
+            None => description_url.clone(),
+        };
+        let control_url = |service_type: &str| -> anyhow::Result<Url> {
+            let path = service_control_url(&description, service_type).ok_or_else(|| {
+                anyhow::anyhow!("'{}' does not offer {}", description_url, service_type)
+            })?;
+            Ok(base_url.join(&path)?)
+        };
+
+        let player = Self {
+            av_transport_url: control_url(AV_TRANSPORT_SERVICE)?,
+            rendering_control_url: control_url(RENDERING_CONTROL_SERVICE)?,
+            client,
+            queue: Arc::new(Mutex::new(Queue::default())),
+            queue_state: QueueState::default(),
+        };
+
+        log::info!(
+            "Casting to the DLNA renderer at '{}'",
+            player.av_transport_url
+        );
+
+        tokio::spawn(player.clone().follow_transport_state());
+
+        Ok(player)
+    }
+
+    async fn soap_call(
+        &self,
+        control_url: &Url,
+        service: &str,
+        action: &str,
+        args: &[(&str, &str)],
+    ) -> anyhow::Result<String> {
+        let args: String = args
+            .iter()
+            .map(|(name, value)| format!("<{0}>{1}</{0}>", name, xml_escape(value)))
+            .collect();
+        let body = format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="utf-8"?>"#,
+                r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" "#,
+                r#"s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+                r#"<s:Body><u:{action} xmlns:u="{service}">{args}</u:{action}></s:Body>"#,
+                r#"</s:Envelope>"#,
+            ),
+            action = action,
+            service = service,
+            args = args,
+        );
+
+        let response = self
+            .client
+            .post(control_url.clone())
+            .header("Content-Type", r#"text/xml; charset="utf-8""#)
+            .header("SOAPAction", format!(r#""{}#{}""#, service, action))
+            .body(body)
+            .send()
+            .await
+            .context(format!("Failed to reach DLNA renderer for {}", action))?;
+
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            anyhow::bail!(
+                "DLNA renderer rejected {}: {}",
+                action,
+                xml_element_text(&text, "errorDescription").unwrap_or(status.to_string())
+            );
+        }
+
+        Ok(text)
+    }
+
+    async fn av_transport(&self, action: &str, args: &[(&str, &str)]) -> anyhow::Result<String> {
+        let mut all_args = vec![("InstanceID", "0")];
+        all_args.extend_from_slice(args);
+        self.soap_call(
+            &self.av_transport_url,
+            AV_TRANSPORT_SERVICE,
+            action,
+            &all_args,
+        )
+        .await
+    }
+
+    async fn rendering_control(
+        &self,
+        action: &str,
+        args: &[(&str, &str)],
+    ) -> anyhow::Result<String> {
+        let mut all_args = vec![("InstanceID", "0"), ("Channel", "Master")];
+        all_args.extend_from_slice(args);
+        self.soap_call(
+            &self.rendering_control_url,
+            RENDERING_CONTROL_SERVICE,
+            action,
+            &all_args,
+        )
+        .await
+    }
+
+    async fn transport_state(&self) -> anyhow::Result<String> {
+        let response = self.av_transport("GetTransportInfo", &[]).await?;
+        xml_element_text(&response, "CurrentTransportState")
+            .context("Renderer did not report its transport state")
+    }
+
+    /// Returns the position and duration of the current item, in seconds.
+    async fn position_info(&self) -> anyhow::Result<(Option<f64>, Option<f64>)> {
+        let response = self.av_transport("GetPositionInfo", &[]).await?;
+        let position = xml_element_text(&response, "RelTime").and_then(|t| parse_duration(&t));
+        let duration =
+            xml_element_text(&response, "TrackDuration").and_then(|t| parse_duration(&t));
+        Ok((position, duration))
+    }
+
+    /// Send the item at `index` to the renderer and start playing it.
+    async fn play_index(&self, queue: &mut Queue, index: usize) -> anyhow::Result<()> {
+        let url = queue.items[index].1.clone();
+        log::debug!("Casting '{}'", url);
+
+        queue.current = Some(index);
+        queue.last_track_change = Some(Instant::now());
+
+        self.av_transport(
+            "SetAVTransportURI",
+            &[("CurrentURI", &url), ("CurrentURIMetaData", "")],
+        )
+        .await?;
+        self.av_transport("Play", &[("Speed", "1")]).await?;
+        Ok(())
+    }
+
+    /// The index that comes after the current one, taking looping into account.
+    fn next_index(queue: &Queue) -> Option<usize> {
+        let next = queue.current.map_or(0, |current| current + 1);
+        if next < queue.items.len() {
+            Some(next)
+        } else if queue.looping && !queue.items.is_empty() {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    /// Move on to the next item whenever the renderer finishes the current one.
+    async fn follow_transport_state(self) {
+        let mut was_playing = false;
+        loop {
+            tokio::time::sleep(TRANSPORT_POLL_INTERVAL).await;
+
+            let state = match self.transport_state().await {
+                Ok(state) => state,
+                Err(e) => {
+                    log::warn!("Failed to get DLNA transport state: {:#}", e);
+                    continue;
+                }
+            };
+
+            let is_playing = state == "PLAYING" || state == "TRANSITIONING";
+            let finished = was_playing && state == "STOPPED";
+            was_playing = is_playing;
+            if !finished {
+                continue;
+            }
+
+            let mut queue = self.queue.lock().await;
+            if queue
+                .last_track_change
+                .is_some_and(|t| t.elapsed() < TRACK_CHANGE_GRACE_PERIOD)
+            {
+                continue;
+            }
+
+            match Self::next_index(&queue) {
+                Some(next) => {
+                    if let Err(e) = self.play_index(&mut queue, next).await {
+                        log::error!("Failed to cast next item: {:#}", e);
+                    }
+                }
+                None => queue.current = None,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Player for DlnaPlayer {
+    fn queue_state(&self) -> &QueueState {
+        &self.queue_state
+    }
+
+    async fn load(&self, url: &str, item: ItemState) -> anyhow::Result<EntryId> {
+        if !item.options.is_empty() {
+            return Err(super::unsupported());
+        }
+        let mut queue = self.queue.lock().await;
+        let id = queue.next_id;
+        queue.next_id += 1;
+        self.queue_state.set(EntryId::Player(id), item);
+        queue.items.push((id, url.to_string()));
+        if queue.current.is_none() {
+            let index = queue.items.len() - 1;
+            self.play_index(&mut queue, index).await?;
+        }
+        Ok(EntryId::Player(id))
+    }
+
+    async fn is_playing(&self) -> anyhow::Result<bool> {
+        Ok(self.transport_state().await? == "PLAYING")
+    }
+
+    async fn set_playing(&self, playing: bool) -> anyhow::Result<()> {
+        if playing {
+            self.av_transport("Play", &[("Speed", "1")]).await?;
+        } else {
+            self.av_transport("Pause", &[]).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_volume(&self) -> anyhow::Result<f64> {
+        let response = self.rendering_control("GetVolume", &[]).await?;
+        xml_element_text(&response, "CurrentVolume")
+            .and_then(|volume| volume.parse().ok())
+            .context("Renderer did not report its volume")
+    }
+
+    async fn set_volume(&self, volume: f64) -> anyhow::Result<()> {
+        // UPnP volumes are whole numbers between 0 and 100
+        let volume = (volume.round() as u32).min(100).to_string();
+        self.rendering_control("SetVolume", &[("DesiredVolume", &volume)])
+            .await?;
+        Ok(())
+    }
+
+    async fn get_time_pos(&self) -> anyhow::Result<Option<f64>> {
+        if self.queue.lock().await.current.is_none() {
+            return Ok(None);
+        }
+        Ok(self.position_info().await?.0)
+    }
+
+    async fn get_time_remaining(&self) -> anyhow::Result<Option<f64>> {
+        if self.queue.lock().await.current.is_none() {
+            return Ok(None);
+        }
+        Ok(match self.position_info().await? {
+            (Some(position), Some(duration)) => Some((duration - position).max(0.0)),
+            _ => None,
+        })
+    }
+
+    async fn seek(&self, seconds: f64) -> anyhow::Result<()> {
+        self.av_transport(
+            "Seek",
+            &[("Unit", "REL_TIME"), ("Target", &format_duration(seconds))],
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn seek_percent(&self, percent: f64) -> anyhow::Result<()> {
+        let duration = self
+            .position_info()
+            .await?
+            .1
+            .context("Renderer did not report the duration of the current item")?;
+        self.seek(duration * percent / 100.0).await
+    }
+
+    async fn playlist(&self) -> anyhow::Result<Vec<PlaylistEntry>> {
+        let queue = self.queue.lock().await;
+        Ok(queue
+            .items
+            .iter()
+            .enumerate()
+            .map(|(index, (id, url))| PlaylistEntry {
+                id: EntryId::Player(*id),
+                filename: url.clone(),
+                title: None,
+                current: queue.current == Some(index),
+            })
+            .collect())
+    }
+
+    async fn playlist_next(&self) -> anyhow::Result<()> {
+        let mut queue = self.queue.lock().await;
+        match Self::next_index(&queue) {
+            Some(next) => self.play_index(&mut queue, next).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn playlist_previous(&self) -> anyhow::Result<()> {
+        let mut queue = self.queue.lock().await;
+        match queue.current {
+            Some(current) if current > 0 => self.play_index(&mut queue, current - 1).await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn playlist_goto(&self, index: usize) -> anyhow::Result<()> {
+        let mut queue = self.queue.lock().await;
+        if index >= queue.items.len() {
+            anyhow::bail!("No playlist item at index {}", index);
+        }
+        self.play_index(&mut queue, index).await
+    }
+
+    async fn playlist_remove(&self, index: usize) -> anyhow::Result<()> {
+        let mut queue = self.queue.lock().await;
+        if index >= queue.items.len() {
+            anyhow::bail!("No playlist item at index {}", index);
+        }
+        queue.items.remove(index);
+
+        match queue.current {
+            Some(current) if current > index => queue.current = Some(current - 1),
+            Some(current) if current == index => {
+                // Like mpv, removing the current item skips to the one after it
+                if index < queue.items.len() {
+                    self.play_index(&mut queue, index).await?;
+                } else {
+                    queue.current = None;
+                    queue.last_track_change = Some(Instant::now());
+                    self.av_transport("Stop", &[]).await?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn playlist_move(&self, from: usize, to: usize) -> anyhow::Result<()> {
+        let mut queue = self.queue.lock().await;
+        let Queue { items, current, .. } = &mut *queue;
+        move_item(items, current, from, to);
+        Ok(())
+    }
+
+    async fn playlist_clear(&self) -> anyhow::Result<()> {
+        let mut queue = self.queue.lock().await;
+        match queue.current {
+            Some(current) => {
+                let item = queue.items.swap_remove(current);
+                queue.items = vec![item];
+                queue.current = Some(0);
+            }
+            None => queue.items.clear(),
+        }
+        Ok(())
+    }
+
+    async fn playlist_shuffle(&self) -> anyhow::Result<()> {
+        let mut queue = self.queue.lock().await;
+        let current_id = queue.current.map(|current| queue.items[current].0);
+        queue.items.shuffle(&mut rand::rng());
+        queue.current =
+            current_id.and_then(|id| queue.items.iter().position(|(item, _)| *item == id));
+        Ok(())
+    }
+
+    async fn is_looping(&self) -> anyhow::Result<bool> {
+        Ok(self.queue.lock().await.looping)
+    }
+
+    async fn set_looping(&self, looping: bool) -> anyhow::Result<()> {
+        self.queue.lock().await.looping = looping;
+        Ok(())
+    }
+}
+
+/// Move the item at `from` to before the item at `to`, keeping track of the current item.
+fn move_item<T>(items: &mut Vec<T>, current: &mut Option<usize>, from: usize, to: usize) {
+    if from >= items.len() || to > items.len() || from == to {
+        return;
+    }
+
+    let item = items.remove(from);
+    let insert_at = if to > from { to - 1 } else { to };
+    items.insert(insert_at, item);
+
+    *current = current.map(|current| {
+        if current == from {
+            insert_at
+        } else if from < current && current <= insert_at {
+            current - 1
+        } else if insert_at <= current && current < from {
+            current + 1
+        } else {
+            current
+        }
+    });
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// The text of the first element with the given name, ignoring namespace prefixes.
+///
+/// This is nowhere near a real XML parser, but UPnP responses are simple enough.
+fn xml_element_text(xml: &str, name: &str) -> Option<String> {
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let tag_end = rest.find('>')?;
+        let tag = &rest[..tag_end];
+        let tag_name = tag.split_whitespace().next().unwrap_or_default();
+        let local_name = tag_name.rsplit(':').next().unwrap_or_default();
+
+        if local_name == name && !tag.ends_with('/') {
+            let content = &rest[tag_end + 1..];
+            let end = content.find(&format!("</{}>", tag_name))?;
+            return Some(xml_unescape(content[..end].trim()));
+        }
+        rest = &rest[tag_end + 1..];
+    }
+    None
+}
+
+/// Find the control url of the service with the given type in a device description.
+fn service_control_url(description: &str, service_type: &str) -> Option<String> {
+    description
+        .split("<service>")
+        .skip(1)
+        .map(|service| service.split("</service>").next().unwrap_or_default())
+        .find(|service| xml_element_text(service, "serviceType").as_deref() == Some(service_type))
+        .and_then(|service| xml_element_text(service, "controlURL"))
+}
+
+/// Parse a UPnP duration like `1:02:03` or `0:00:05.500` into seconds.
+fn parse_duration(s: &str) -> Option<f64> {
+    let mut parts = s.split(':');
+    let hours: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+fn format_duration(seconds: f64) -> String {
+    let total = seconds.max(0.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}",
+        total / 3600,
+        (total / 60) % 60,
+        total % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_control_url() {
+        let description = r#"<?xml version="1.0"?>
+            <root xmlns="urn:schemas-upnp-org:device-1-0">
+              <device>
+                <serviceList>
+                  <service>
+                    <serviceType>urn:schemas-upnp-org:service:RenderingControl:1</serviceType>
+                    <controlURL>/RenderingControl/control</controlURL>
+                  </service>
+                  <service>
+                    <serviceType>urn:schemas-upnp-org:service:AVTransport:1</serviceType>
+                    <controlURL>/AVTransport/control</controlURL>
+                  </service>
+                </serviceList>
+              </device>
+            </root>"#;
+
+        assert_eq!(
+            service_control_url(description, AV_TRANSPORT_SERVICE).as_deref(),
+            Some("/AVTransport/control")
+        );
+        assert_eq!(
+            service_control_url(description, RENDERING_CONTROL_SERVICE).as_deref(),
+            Some("/RenderingControl/control")
+        );
+        assert_eq!(
+            service_control_url(description, "urn:schemas-upnp-org:service:Other:1"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_xml_element_text() {
+        let response = r#"<s:Envelope><s:Body><u:GetPositionInfoResponse xmlns:u="x">
+            <Track>1</Track><RelTime>0:01:05</RelTime><TrackURI>http://a/b?c=1&amp;d=2</TrackURI>
+            </u:GetPositionInfoResponse></s:Body></s:Envelope>"#;
+
+        assert_eq!(
+            xml_element_text(response, "RelTime").as_deref(),
+            Some("0:01:05")
+        );
+        assert_eq!(
+            xml_element_text(response, "TrackURI").as_deref(),
+            Some("http://a/b?c=1&d=2")
+        );
+        assert_eq!(xml_element_text(response, "Missing"), None);
+    }
+
+    #[test]
+    fn test_durations() {
+        assert_eq!(parse_duration("1:02:03"), Some(3723.0));
+        assert_eq!(parse_duration("0:00:05.500"), Some(5.5));
+        assert_eq!(parse_duration("NOT_IMPLEMENTED"), None);
+        assert_eq!(format_duration(3723.4), "1:02:03");
+    }
+
+    #[test]
+    fn test_move_item() {
+        let mut items: Vec<String> = ["a", "b", "c", "d"].map(String::from).to_vec();
+        let mut current = Some(1);
+
+        // Move "a" to before "d"
+        move_item(&mut items, &mut current, 0, 3);
+        assert_eq!(items, ["b", "c", "a", "d"]);
+        assert_eq!(current, Some(0));
+
+        // Move "d" to the front
+        move_item(&mut items, &mut current, 3, 0);
+        assert_eq!(items, ["d", "b", "c", "a"]);
+        assert_eq!(current, Some(1));
+
+        // Move "b" to the end
+        move_item(&mut items, &mut current, 1, 4);
+        assert_eq!(items, ["d", "c", "a", "b"]);
+        assert_eq!(current, Some(3));
+    }
+}
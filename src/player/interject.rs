@@ -0,0 +1,224 @@
+use std::{sync::atomic::Ordering, time::Duration};
+
+use anyhow::Context;
+
+use crate::{api::ApiError, volume_transition::VolumeTransitionEngine};
+
+use super::{ItemState, PlayerHandle};
+
+/// How long the interrupted item is faded out before the interjection, and back in after.
+const DUCK_FADE_DURATION: Duration = Duration::from_millis(500);
+
+/// How often to check whether the interjection has finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Interjections are meant to be short. Anything longer than this is cut off.
+const MAX_INTERJECTION_DURATION: Duration = Duration::from_secs(5 * 60);
+
+/// How long to wait for the interrupted item to load again, before seeking in it.
+const RESUME_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What was playing before the interjection.
+#[derive(Debug)]
+struct Interrupted {
+    index: usize,
+    position: Option<f64>,
+    was_playing: bool,
+    volume: f64,
+}
+
+/// Play `url` right away with the given `item` state, and then resume whatever was playing
+/// at the same position.
+///
+/// The interrupted item is faded out before the interjection and back in afterwards.
+/// This returns as soon as the interjection has started, the rest happens in the background.
+/// Only one interjection can play at a time.
+pub async fn interject(
+    volume_engine: VolumeTransitionEngine,
+    url: &str,
+    item: ItemState,
+) -> anyhow::Result<()> {
+    let player = volume_engine.player().clone();
+    if player
+        .queue_state()
+        .interjecting()
+        .swap(true, Ordering::SeqCst)
+    {
+        return Err(
+            ApiError::Conflict("Another interjection is already playing".to_string()).into(),
+        );
+    }
+
+    match start_interjection(&player, &volume_engine, url, item).await {
+        Ok((interrupted, clip_index)) => {
+            tokio::spawn(async move {
+                if let Err(e) =
+                    finish_interjection(&player, &volume_engine, interrupted, clip_index).await
+                {
+                    log::warn!("Failed to resume after interjection: {:#}", e);
+                }
+                player
+                    .queue_state()
+                    .interjecting()
+                    .store(false, Ordering::SeqCst);
+            });
+            Ok(())
+        }
+        Err(e) => {
+            player
+                .queue_state()
+                .interjecting()
+                .store(false, Ordering::SeqCst);
+            Err(e)
+        }
+    }
+}
+
+async fn start_interjection(
+    player: &PlayerHandle,
+    volume_engine: &VolumeTransitionEngine,
+    url: &str,
+    item: ItemState,
+) -> anyhow::Result<(Option<Interrupted>, usize)> {
+    let playlist = player.playlist().await?;
+    let current = playlist.iter().position(|entry| entry.current);
+
+    let interrupted = match current {
+        Some(index) => {
+            let was_playing = player.is_playing().await?;
+            let volume = player.get_volume().await?;
+            if was_playing {
+                volume_engine.fade_to(0.0, DUCK_FADE_DURATION).await?;
+            }
+
+            Some(Interrupted {
+                index,
+                position: player.get_time_pos().await?,
+                was_playing,
+                volume,
+            })
+        }
+        None => None,
+    };
+
+    log::info!("Interjecting with '{}'", url);
+
+    // Place the interjection right after the current item, and jump to it.
+    player.load(url, item).await?;
+    let clip_index = match &interrupted {
+        Some(interrupted) => {
+            player
+                .playlist_move(playlist.len(), interrupted.index + 1)
+                .await?;
+            interrupted.index + 1
+        }
+        None => playlist.len(),
+    };
+    player.playlist_goto(clip_index).await?;
+
+    if let Some(interrupted) = &interrupted {
+        volume_engine.set_volume(interrupted.volume).await?;
+    }
+    player.set_playing(true).await?;
+
+    Ok((interrupted, clip_index))
+}
+
+async fn finish_interjection(
+    player: &PlayerHandle,
+    volume_engine: &VolumeTransitionEngine,
+    interrupted: Option<Interrupted>,
+    clip_index: usize,
+) -> anyhow::Result<()> {
+    let clip = player
+        .playlist()
+        .await?
+        .get(clip_index)
+        .map(|entry| entry.filename.clone())
+        .context("Interjection disappeared from the playlist")?;
+
+    // Wait for the interjection to finish, or for someone to skip past it.
+    let started = tokio::time::Instant::now();
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let playlist = player.playlist().await?;
+        let still_playing = playlist
+            .get(clip_index)
+            .is_some_and(|entry| entry.current && entry.filename == clip);
+        if !still_playing {
+            break;
+        }
+
+        if started.elapsed() > MAX_INTERJECTION_DURATION {
+            log::warn!("Interjection '{}' is too long, cutting it off", clip);
+            break;
+        }
+    }
+
+    let playlist = player.playlist().await?;
+    if playlist.get(clip_index).map(|entry| &entry.filename) != Some(&clip) {
+        log::debug!("Interjection was moved or removed, not resuming");
+        return Ok(());
+    }
+
+    let Some(interrupted) = interrupted else {
+        player.playlist_remove(clip_index).await?;
+        return Ok(());
+    };
+
+    // Only resume if nobody has chosen something else to play in the meantime.
+    let resume = playlist
+        .iter()
+        .position(|entry| entry.current)
+        .is_none_or(|current| current == clip_index || current == clip_index + 1);
+
+    player.playlist_remove(clip_index).await?;
+
+    if !resume {
+        log::debug!("Playlist moved on during interjection, not resuming");
+        return Ok(());
+    }
+
+    log::debug!("Resuming item {} after interjection", interrupted.index);
+
+    if interrupted.was_playing {
+        volume_engine.set_volume(0.0).await?;
+    }
+    player.playlist_goto(interrupted.index).await?;
+
+    if let Some(position) = interrupted.position {
+        seek_when_loaded(player, position).await?;
+    }
+
+    player.set_playing(interrupted.was_playing).await?;
+    if interrupted.was_playing {
+        volume_engine
+            .fade_to(interrupted.volume, DUCK_FADE_DURATION)
+            .await?;
+    } else {
+        volume_engine.set_volume(interrupted.volume).await?;
+    }
+
+    Ok(())
+}
+
+/// Seek in the current item, once it has loaded.
+///
+/// Seeking right after switching items fails until the item has loaded, so keep trying for a bit.
+pub async fn seek_when_loaded(player: &PlayerHandle, position: f64) -> anyhow::Result<()> {
+    let started = tokio::time::Instant::now();
+    loop {
+        if player.get_time_pos().await.ok().flatten().is_some() {
+            match player.seek(position).await {
+                Ok(()) => return Ok(()),
+                Err(e) if started.elapsed() > RESUME_TIMEOUT => return Err(e),
+                Err(_) => {}
+            }
+        } else if started.elapsed() > RESUME_TIMEOUT {
+            anyhow::bail!("Timed out waiting for the interrupted item to load");
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
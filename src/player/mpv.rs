@@ -0,0 +1,186 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use mpvipc_async::{LoopProperty, Mpv, MpvExt, NumberChangeOptions, SeekOptions, Switch};
+use serde_json::Value;
+
+use crate::api::ApiError;
+
+use super::{EntryId, ItemState, Player, PlaylistEntry, QueueState};
+
+/// Plays locally with mpv.
+#[derive(Debug, Clone)]
+pub struct MpvPlayer {
+    mpv: Mpv,
+    queue: QueueState,
+}
+
+impl MpvPlayer {
+    pub fn new(mpv: Mpv) -> Self {
+        Self {
+            mpv,
+            queue: QueueState::default(),
+        }
+    }
+}
+
+/// The id mpv gave the item it just added, from its reply to `loadfile`. mpv before 0.38
+/// does not reply with it.
+pub fn loaded_entry_id(reply: Option<&Value>) -> Option<EntryId> {
+    reply
+        .and_then(|reply| reply.get("playlist_entry_id"))
+        .and_then(Value::as_u64)
+        .map(|id| EntryId::Player(id as usize))
+}
+
+/// The id mpv gave the item it just appended, from its reply to `loadfile`, or from the end
+/// of the playlist if it is too old to reply with it.
+async fn appended_id(mpv: &Mpv, reply: Option<Value>) -> anyhow::Result<EntryId> {
+    if let Some(id) = loaded_entry_id(reply.as_ref()) {
+        return Ok(id);
+    }
+    mpv.get_playlist()
+        .await?
+        .0
+        .last()
+        .map(|entry| EntryId::Player(entry.id))
+        .context("The item did not show up in the playlist")
+}
+
+#[async_trait]
+impl Player for MpvPlayer {
+    fn queue_state(&self) -> &QueueState {
+        &self.queue
+    }
+
+    fn mpv(&self) -> Option<&Mpv> {
+        Some(&self.mpv)
+    }
+
+    async fn get_property(&self, name: &str) -> anyhow::Result<Option<Value>> {
+        Ok(self.mpv.get_property_value(name).await?)
+    }
+
+    async fn set_property(&self, name: &str, value: Value) -> anyhow::Result<()> {
+        let result = match value {
+            Value::Bool(value) => self.mpv.set_property(name, value).await,
+            Value::Number(ref number) => match number.as_f64() {
+                Some(value) => self.mpv.set_property(name, value).await,
+                None => {
+                    return Err(
+                        ApiError::InvalidArgument(format!("Invalid number {}", number)).into(),
+                    );
+                }
+            },
+            Value::String(value) => self.mpv.set_property(name, value).await,
+            _ => {
+                return Err(ApiError::InvalidArgument(
+                    "The value must be a boolean, a number or a string".to_string(),
+                )
+                .into());
+            }
+        };
+        result.with_context(|| format!("Failed to set the property '{}'", name))
+    }
+
+    async fn load(&self, url: &str, item: ItemState) -> anyhow::Result<EntryId> {
+        let options = item.options.to_mpv();
+        let mut args = vec![url, "append"];
+        if !options.is_empty() {
+            args.extend(["-1", &options]);
+        }
+        let reply = self.mpv.run_command_raw("loadfile", &args).await?;
+        let id = appended_id(&self.mpv, reply).await?;
+        self.queue.set(id, item);
+        Ok(id)
+    }
+
+    async fn is_playing(&self) -> anyhow::Result<bool> {
+        Ok(MpvExt::is_playing(&self.mpv).await?)
+    }
+
+    async fn set_playing(&self, playing: bool) -> anyhow::Result<()> {
+        Ok(self
+            .mpv
+            .set_playback(if playing { Switch::On } else { Switch::Off })
+            .await?)
+    }
+
+    async fn get_volume(&self) -> anyhow::Result<f64> {
+        Ok(MpvExt::get_volume(&self.mpv).await?)
+    }
+
+    async fn set_volume(&self, volume: f64) -> anyhow::Result<()> {
+        Ok(MpvExt::set_volume(&self.mpv, volume, NumberChangeOptions::Absolute).await?)
+    }
+
+    async fn get_time_pos(&self) -> anyhow::Result<Option<f64>> {
+        Ok(MpvExt::get_time_pos(&self.mpv).await?)
+    }
+
+    async fn get_time_remaining(&self) -> anyhow::Result<Option<f64>> {
+        Ok(MpvExt::get_time_remaining(&self.mpv).await?)
+    }
+
+    async fn seek(&self, seconds: f64) -> anyhow::Result<()> {
+        Ok(MpvExt::seek(&self.mpv, seconds, SeekOptions::Absolute).await?)
+    }
+
+    async fn seek_percent(&self, percent: f64) -> anyhow::Result<()> {
+        Ok(MpvExt::seek(&self.mpv, percent, SeekOptions::AbsolutePercent).await?)
+    }
+
+    async fn playlist(&self) -> anyhow::Result<Vec<PlaylistEntry>> {
+        Ok(self
+            .mpv
+            .get_playlist()
+            .await?
+            .0
+            .into_iter()
+            .map(|entry| PlaylistEntry {
+                id: EntryId::Player(entry.id),
+                filename: entry.filename,
+                title: entry.title,
+                current: entry.current,
+            })
+            .collect())
+    }
+
+    async fn playlist_next(&self) -> anyhow::Result<()> {
+        Ok(self.mpv.next().await?)
+    }
+
+    async fn playlist_previous(&self) -> anyhow::Result<()> {
+        Ok(self.mpv.prev().await?)
+    }
+
+    async fn playlist_goto(&self, index: usize) -> anyhow::Result<()> {
+        Ok(self.mpv.playlist_play_id(index).await?)
+    }
+
+    async fn playlist_remove(&self, index: usize) -> anyhow::Result<()> {
+        Ok(self.mpv.playlist_remove_id(index).await?)
+    }
+
+    async fn playlist_move(&self, from: usize, to: usize) -> anyhow::Result<()> {
+        Ok(self.mpv.playlist_move_id(from, to).await?)
+    }
+
+    async fn playlist_clear(&self) -> anyhow::Result<()> {
+        Ok(MpvExt::playlist_clear(&self.mpv).await?)
+    }
+
+    async fn playlist_shuffle(&self) -> anyhow::Result<()> {
+        Ok(MpvExt::playlist_shuffle(&self.mpv).await?)
+    }
+
+    async fn is_looping(&self) -> anyhow::Result<bool> {
+        Ok(self.mpv.playlist_is_looping().await? != LoopProperty::No)
+    }
+
+    async fn set_looping(&self, looping: bool) -> anyhow::Result<()> {
+        Ok(self
+            .mpv
+            .set_loop_playlist(if looping { Switch::On } else { Switch::Off })
+            .await?)
+    }
+}
@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+
+use crate::oidc::AuthenticatedUser;
+
+/// What the client that added an item wants it to be shown as, and who they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ItemNote {
+    /// Shown instead of the title of the item.
+    pub title: Option<String>,
+    /// A free form note, like "play this at midnight".
+    pub note: Option<String>,
+    /// Who added the item, by the name they gave, or their name if they logged in.
+    pub queued_by: Option<String>,
+    /// The subject of whoever added the item, if they logged in or gave an API key. Unlike
+    /// their name, it stays the same, so the item is credited to it in the stats.
+    #[serde(default)]
+    pub queued_by_subject: Option<String>,
+}
+
+impl ItemNote {
+    /// A note with the blank parts left out.
+    pub fn new(title: Option<&str>, note: Option<&str>, queued_by: Option<&str>) -> Self {
+        let non_blank = |s: Option<&str>| {
+            s.map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+        };
+        Self {
+            title: non_blank(title),
+            note: non_blank(note),
+            queued_by: non_blank(queued_by),
+            queued_by_subject: None,
+        }
+    }
+
+    /// Credit the item to `user`, whoever the client said queued it, if someone logged in.
+    pub fn credited_to(self, user: Option<&AuthenticatedUser>) -> Self {
+        match user {
+            Some(user) => Self {
+                queued_by: Some(user.name.clone()),
+                queued_by_subject: Some(user.subject.clone()),
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_item_notes() {
+        let note = ItemNote::new(
+            Some(" Midnight song "),
+            Some("play this at midnight"),
+            Some("someone"),
+        );
+        assert_eq!(note.title.as_deref(), Some("Midnight song"));
+        assert!(ItemNote::new(Some(""), Some("  "), None).is_empty());
+        let alice = AuthenticatedUser {
+            subject: "1234".to_string(),
+            name: "alice".to_string(),
+            scopes: vec![],
+        };
+        let credited = note.clone().credited_to(Some(&alice));
+        assert_eq!(credited.queued_by.as_deref(), Some("alice"));
+        assert_eq!(credited.queued_by_subject.as_deref(), Some("1234"));
+        assert_eq!(note.clone().credited_to(None), note);
+    }
+}
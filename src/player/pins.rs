@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+
+use crate::api::ApiError;
+
+use super::{PlaylistEntry, PlaylistMove, QueueState};
+
+/// Flags that admins set on playlist items, mostly to protect them from being bumped
+/// around by other clients. They are kept in the [`ItemState`](super::ItemState) of each
+/// item.
+///
+/// Only admins can set these, and nobody can get around them without clearing them first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ItemFlags {
+    /// The item stays where it is in the playlist. Neither it nor any other item can be
+    /// moved in a way that changes its position, and the playlist can not be shuffled.
+    pub pinned: bool,
+    /// The item can not be removed, neither by itself nor by clearing the playlist.
+    pub locked: bool,
+    /// The item is background music, which is quieted when nobody has been connected for
+    /// a while.
+    pub background: bool,
+}
+
+fn locked_at<'a>(
+    queue: &QueueState,
+    playlist: &'a [PlaylistEntry],
+    index: usize,
+) -> Option<&'a str> {
+    let entry = playlist.get(index)?;
+    queue
+        .item(entry)
+        .flags
+        .locked
+        .then_some(entry.title.as_deref().unwrap_or(&entry.filename))
+}
+
+/// Fail with [`ApiError::PolicyViolation`] if any of the items at `indices` are locked.
+pub fn check_removal(
+    queue: &QueueState,
+    playlist: &[PlaylistEntry],
+    indices: &[usize],
+) -> Result<(), ApiError> {
+    match indices
+        .iter()
+        .find_map(|index| locked_at(queue, playlist, *index))
+    {
+        Some(name) => Err(ApiError::PolicyViolation(format!(
+            "'{}' is locked, and can not be removed",
+            name
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Fail with [`ApiError::PolicyViolation`] if any item in the playlist is locked.
+pub fn check_clear(queue: &QueueState, playlist: &[PlaylistEntry]) -> Result<(), ApiError> {
+    check_removal(queue, playlist, &(0..playlist.len()).collect::<Vec<_>>())
+}
+
+/// Fail with [`ApiError::PolicyViolation`] if any item in the playlist is pinned.
+pub fn check_shuffle(queue: &QueueState, playlist: &[PlaylistEntry]) -> Result<(), ApiError> {
+    if playlist.iter().any(|entry| queue.item(entry).flags.pinned) {
+        return Err(ApiError::PolicyViolation(
+            "The playlist has pinned items, and can not be shuffled".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Fail with [`ApiError::PolicyViolation`] if applying `moves` in order would change the
+/// position of a pinned item. The moves are assumed to be within the playlist.
+pub fn check_moves(
+    queue: &QueueState,
+    playlist: &[PlaylistEntry],
+    moves: &[PlaylistMove],
+) -> Result<(), ApiError> {
+    let pinned: Vec<usize> = (0..playlist.len())
+        .filter(|index| queue.item(&playlist[*index]).flags.pinned)
+        .collect();
+    if pinned.is_empty() {
+        return Ok(());
+    }
+
+    // Play the moves out on the original indices, the same way mpv does.
+    let mut order: Vec<usize> = (0..playlist.len()).collect();
+    for PlaylistMove { from, to } in moves {
+        let item = order.remove(*from);
+        let to = if to > from { to - 1 } else { *to };
+        order.insert(to.min(order.len()), item);
+    }
+
+    match pinned.into_iter().find(|index| order[*index] != *index) {
+        Some(index) => {
+            let entry = &playlist[index];
+            Err(ApiError::PolicyViolation(format!(
+                "'{}' is pinned, and has to stay at position {}",
+                entry.title.as_deref().unwrap_or(&entry.filename),
+                index
+            )))
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::{EntryId, ItemState};
+
+    #[test]
+    fn test_pinned_and_locked_items() {
+        // Two copies of the same file, where only the one at index 1 is pinned and locked.
+        let playlist: Vec<PlaylistEntry> = ["a.mp3", "movie.mkv", "movie.mkv", "b.mp3"]
+            .into_iter()
+            .enumerate()
+            .map(|(id, filename)| PlaylistEntry {
+                id: EntryId::Player(id),
+                filename: filename.to_string(),
+                title: None,
+                current: false,
+            })
+            .collect();
+        let queue = QueueState::default();
+        let flags = ItemFlags {
+            pinned: true,
+            locked: true,
+            background: false,
+        };
+        queue.set(
+            EntryId::Player(1),
+            ItemState {
+                flags,
+                ..ItemState::default()
+            },
+        );
+        assert_eq!(queue.item(&playlist[1]).flags, flags);
+        assert_eq!(queue.item(&playlist[2]).flags, ItemFlags::default());
+
+        let moves = |moves: &[(usize, usize)]| {
+            moves
+                .iter()
+                .map(|(from, to)| PlaylistMove {
+                    from: *from,
+                    to: *to,
+                })
+                .collect::<Vec<_>>()
+        };
+        // Moving things around behind the movie is fine, bumping it is not.
+        assert!(check_moves(&queue, &playlist, &moves(&[(3, 2)])).is_ok());
+        assert!(check_moves(&queue, &playlist, &moves(&[(2, 4), (0, 0)])).is_ok());
+        assert!(check_moves(&queue, &playlist, &moves(&[(3, 0)])).is_err());
+        assert!(check_moves(&queue, &playlist, &moves(&[(1, 4)])).is_err());
+        // Unless the item is moved back into place.
+        assert!(check_moves(&queue, &playlist, &moves(&[(3, 0), (0, 4)])).is_ok());
+
+        assert!(check_removal(&queue, &playlist, &[0, 2]).is_ok());
+        assert!(check_removal(&queue, &playlist, &[1]).is_err());
+        assert!(check_clear(&queue, &playlist).is_err());
+        assert!(check_shuffle(&queue, &playlist).is_err());
+
+        // The flags follow the item when it is replaced.
+        queue.transfer(EntryId::Player(1), EntryId::Player(7));
+        assert_eq!(queue.get(EntryId::Player(1)).flags, ItemFlags::default());
+        assert_eq!(queue.get(EntryId::Player(7)).flags, flags);
+    }
+}
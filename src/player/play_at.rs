@@ -0,0 +1,119 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use mpvipc_async::Switch;
+
+use crate::{api::ApiError, volume_transition::VolumeTransitionEngine};
+
+use super::PlayerHandle;
+
+/// How far in the past a start time can be, and still start playback right away. Requests
+/// take a moment to arrive, so a start time of "now" has always passed.
+const LATE_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// How far ahead playback can be scheduled.
+const MAX_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The scheduled start of playback of a player, kept in its [`QueueState`](super::QueueState).
+#[derive(Debug, Default)]
+pub(super) struct PlaySchedule {
+    /// Incremented every time playback is scheduled or the schedule is cancelled, so that a
+    /// waiting task can notice that it has been replaced.
+    generation: AtomicU64,
+    /// The generation of the task waiting to start playback, or 0 if none is.
+    scheduled: AtomicU64,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Pause now, and resume at `unix_ms` milliseconds since the unix epoch, replacing any
+/// earlier schedule. Hosts that play in sync should have their clocks synced, e.g. with NTP.
+pub async fn play_at(volume_engine: VolumeTransitionEngine, unix_ms: u64) -> anyhow::Result<()> {
+    let now = now_ms();
+    if unix_ms + (LATE_TOLERANCE.as_millis() as u64) < now {
+        return Err(ApiError::InvalidArgument(format!(
+            "The start time is {} ms in the past",
+            now - unix_ms
+        ))
+        .into());
+    }
+    let delay = Duration::from_millis(unix_ms.saturating_sub(now));
+    if delay > MAX_DELAY {
+        return Err(ApiError::InvalidArgument(format!(
+            "Playback can be scheduled at most {} hours ahead",
+            MAX_DELAY.as_secs() / 3600
+        ))
+        .into());
+    }
+
+    let player = volume_engine.player().clone();
+    let schedule = player.queue_state().play_schedule();
+    let generation = schedule.generation.fetch_add(1, Ordering::SeqCst) + 1;
+    volume_engine.set_playback(Switch::Off).await?;
+    log::info!("Starting playback in {:?}", delay);
+    schedule.scheduled.store(generation, Ordering::SeqCst);
+
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        let schedule = player.queue_state().play_schedule();
+        if schedule.generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        schedule.scheduled.store(0, Ordering::SeqCst);
+        let late_ms = now_ms().saturating_sub(unix_ms);
+        match volume_engine.set_playback(Switch::On).await {
+            Ok(()) => log::info!("Started scheduled playback, {} ms late", late_ms),
+            Err(e) => log::warn!("Failed to start scheduled playback: {:#}", e),
+        }
+    });
+
+    Ok(())
+}
+
+/// Cancel the scheduled start of playback, leaving the player paused. Returns whether
+/// anything was scheduled.
+pub fn cancel_play_at(player: &PlayerHandle) -> bool {
+    let schedule = player.queue_state().play_schedule();
+    schedule.generation.fetch_add(1, Ordering::SeqCst);
+    let scheduled = schedule.scheduled.swap(0, Ordering::SeqCst) != 0;
+    if scheduled {
+        log::info!("Cancelled the scheduled start of playback");
+    }
+    scheduled
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{player::MpvPlayer, test_support::FakeMpv};
+
+    #[tokio::test]
+    async fn test_play_at() {
+        let mpv = FakeMpv::start();
+        mpv.set_property("pause", json!(false));
+        let engine =
+            VolumeTransitionEngine::new(Arc::new(MpvPlayer::new(mpv.connect().await)), false);
+
+        assert!(play_at(engine.clone(), now_ms() - 10_000).await.is_err());
+        play_at(engine.clone(), now_ms() + 60_000).await.unwrap();
+        assert_eq!(mpv.property("pause"), Some(json!(true)));
+        assert!(cancel_play_at(engine.player()));
+        assert!(!cancel_play_at(engine.player()));
+
+        play_at(engine, now_ms() + 100).await.unwrap();
+        assert_eq!(mpv.property("pause"), Some(json!(true)));
+        mpv.wait_for(|mpv| mpv.property("pause") == Some(json!(false)))
+            .await;
+    }
+}
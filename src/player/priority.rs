@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use url::form_urlencoded;
+
+use crate::scopes::Scope;
+
+use super::{PlaylistEntry, QueueState};
+
+/// Which lane of the playlist an item is added to. Items go ahead of every item of a lower
+/// priority that has not started playing yet, and behind the ones of the same or higher
+/// priority, so that event organizers can get ahead of the casual queue.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    #[default]
+    Normal,
+    High,
+    Admin,
+}
+
+impl Priority {
+    /// The scope needed to add items with this priority, beyond adding items at all.
+    pub fn needs(self) -> Option<Scope> {
+        match self {
+            Priority::Normal => None,
+            Priority::High => Some(Scope::QueuePriority),
+            Priority::Admin => Some(Scope::Admin),
+        }
+    }
+
+    /// The `priority` in a query string, or [`Priority::Normal`] if it is missing or not
+    /// one of them. Refusing the ones that are not is left to the endpoint.
+    pub fn from_query(query: Option<&str>) -> Self {
+        form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+            .find(|(key, _)| key == "priority")
+            .and_then(|(_, value)| match value.as_ref() {
+                "normal" => Some(Priority::Normal),
+                "high" => Some(Priority::High),
+                "admin" => Some(Priority::Admin),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// The lane of the playlist an item was added to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lane {
+    pub priority: Priority,
+    /// Who the item takes turns in its lane as, if it was added with fair scheduling on.
+    /// This is a [`coarse_identity`](crate::quotas::coarse_identity), so it is not shown.
+    pub fair_identity: Option<String>,
+}
+
+impl Lane {
+    /// Whether an item in this lane goes anywhere but the end of the playlist.
+    pub fn is_ordered(&self) -> bool {
+        self.priority != Priority::Normal || self.fair_identity.is_some()
+    }
+}
+
+/// Where an item with `priority` goes in `playlist`, given the priority of each item.
+fn insert_index(
+    playlist: &[PlaylistEntry],
+    priority: Priority,
+    priority_of: impl Fn(&PlaylistEntry) -> Priority,
+) -> usize {
+    let after_current = playlist
+        .iter()
+        .position(|entry| entry.current)
+        .map_or(0, |current| current + 1);
+    playlist
+        .iter()
+        .enumerate()
+        .skip(after_current)
+        .find(|(_, entry)| priority_of(entry) < priority)
+        .map_or(playlist.len(), |(index, _)| index)
+}
+
+/// Where an item with `priority` goes in `playlist`, going by the priorities the items
+/// were added with.
+pub fn priority_index(queue: &QueueState, playlist: &[PlaylistEntry], priority: Priority) -> usize {
+    insert_index(playlist, priority, |entry| queue.item(entry).lane.priority)
+}
+
+/// Where an item with `priority` from `identity` goes in `playlist`, given the priority and
+/// fair identity of each item.
+///
+/// Within its lane, everyone gets a turn before anyone gets another: the item goes after
+/// the items of the lane that take the same turn as it or an earlier one. Items without an
+/// identity take their turns together.
+fn fair_insert_index(
+    playlist: &[PlaylistEntry],
+    priority: Priority,
+    identity: &str,
+    item_of: impl Fn(&PlaylistEntry) -> (Priority, Option<String>),
+) -> usize {
+    let after_current = playlist
+        .iter()
+        .position(|entry| entry.current)
+        .map_or(0, |current| current + 1);
+    let mut turns: HashMap<Option<String>, usize> = HashMap::new();
+    let mut lane = Vec::new();
+    for (index, entry) in playlist.iter().enumerate().skip(after_current) {
+        let (item_priority, item_identity) = item_of(entry);
+        if item_priority == priority {
+            let turn = turns.entry(item_identity).or_default();
+            lane.push((index, *turn));
+            *turn += 1;
+        }
+    }
+
+    let turn = turns.get(&Some(identity.to_string())).copied().unwrap_or(0);
+    match lane.iter().rev().find(|(_, lane_turn)| *lane_turn <= turn) {
+        Some((index, _)) => index + 1,
+        None => insert_index(playlist, priority, |entry| item_of(entry).0),
+    }
+}
+
+/// Where an item with `priority` from `identity` goes in `playlist` with fair scheduling,
+/// going by the lanes of the items.
+pub fn fair_index(
+    queue: &QueueState,
+    playlist: &[PlaylistEntry],
+    priority: Priority,
+    identity: &str,
+) -> usize {
+    fair_insert_index(playlist, priority, identity, |entry| {
+        let lane = queue.item(entry).lane;
+        (lane.priority, lane.fair_identity)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::EntryId;
+
+    #[test]
+    fn test_insert_index() {
+        let entry = |filename: &str, current: bool| PlaylistEntry {
+            id: EntryId::Player(0),
+            filename: filename.to_string(),
+            title: None,
+            current,
+        };
+        let playlist = [
+            entry("high-played", false),
+            entry("normal-playing", true),
+            entry("high", false),
+            entry("normal", false),
+        ];
+        let priority_of = |entry: &PlaylistEntry| {
+            if entry.filename.starts_with("high") {
+                Priority::High
+            } else {
+                Priority::Normal
+            }
+        };
+
+        assert_eq!(insert_index(&playlist, Priority::Normal, priority_of), 4);
+        assert_eq!(insert_index(&playlist, Priority::High, priority_of), 3);
+        assert_eq!(insert_index(&playlist, Priority::Admin, priority_of), 2);
+        assert_eq!(insert_index(&[], Priority::Admin, priority_of), 0);
+        let nothing_playing = [entry("normal", false)];
+        assert_eq!(
+            insert_index(&nothing_playing, Priority::High, priority_of),
+            0
+        );
+
+        assert_eq!(
+            Priority::from_query(Some("path=a&priority=high")),
+            Priority::High
+        );
+        assert_eq!(
+            Priority::from_query(Some("priority=urgent")),
+            Priority::Normal
+        );
+        assert_eq!(Priority::from_query(None), Priority::Normal);
+    }
+
+    #[test]
+    fn test_fair_insert_index() {
+        // Filenames are like `<identity>-<n>`, with `high-` in front for the high lane.
+        let entry = |filename: &str, current: bool| PlaylistEntry {
+            id: EntryId::Player(0),
+            filename: filename.to_string(),
+            title: None,
+            current,
+        };
+        let item_of = |entry: &PlaylistEntry| match entry.filename.strip_prefix("high-") {
+            Some(rest) => (Priority::High, rest.split('-').next().map(str::to_string)),
+            None => (
+                Priority::Normal,
+                entry
+                    .filename
+                    .split_once('-')
+                    .map(|(identity, _)| identity.to_string()),
+            ),
+        };
+        let index = |playlist: &[PlaylistEntry], priority: Priority, identity: &str| {
+            fair_insert_index(playlist, priority, identity, item_of)
+        };
+
+        let playlist = [
+            entry("a-played", false),
+            entry("a-playing", true),
+            entry("high-c-1", false),
+            entry("a-1", false),
+            entry("b-1", false),
+            entry("untitled", false),
+            entry("a-2", false),
+            entry("a-3", false),
+        ];
+        // b has had a turn, so their next one is after the second of a.
+        assert_eq!(index(&playlist, Priority::Normal, "b"), 7);
+        // c has not had a turn, so their first one is after all the others.
+        assert_eq!(index(&playlist, Priority::Normal, "c"), 6);
+        // a has had more turns than anyone, and waits for everyone else.
+        assert_eq!(index(&playlist, Priority::Normal, "a"), 8);
+        // The high lane takes turns of its own.
+        assert_eq!(index(&playlist, Priority::High, "c"), 3);
+        assert_eq!(index(&playlist, Priority::High, "d"), 3);
+        assert_eq!(index(&playlist, Priority::Admin, "d"), 2);
+
+        // Items in an empty lane go where the lane starts.
+        assert_eq!(index(&playlist[..3], Priority::Normal, "a"), 3);
+        assert_eq!(index(&[], Priority::Normal, "a"), 0);
+    }
+}
@@ -0,0 +1,366 @@
+//! What greg-ng keeps track of about the items in the queue, beyond what the player itself
+//! knows about them.
+//!
+//! Items are kept by their [`EntryId`] rather than their filename, so that two copies of the
+//! same file are told apart, and each keep their own note, lane and flags.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+use tokio::sync::{Mutex as AsyncMutex, watch};
+
+use crate::{oidc::AuthenticatedUser, prefetch::escape_option_value};
+
+use super::{ItemFlags, ItemNote, Lane, PlaylistEntry, play_at::PlaySchedule};
+
+/// Identifies an item for as long as it is in the queue, unlike its index or its filename.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EntryId {
+    /// An item in the player, by the id the player gave it.
+    Player(usize),
+    /// An item held back from the player by a [`WindowedPlayer`](super::WindowedPlayer),
+    /// until it is fed to the player and given an id there.
+    Held(u64),
+}
+
+impl fmt::Display for EntryId {
+    /// Formats as `<id>` for items in the player, and `held-<id>` for held items.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryId::Player(id) => write!(f, "{}", id),
+            EntryId::Held(id) => write!(f, "held-{}", id),
+        }
+    }
+}
+
+impl FromStr for EntryId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |_| format!("invalid entry id '{}'", s);
+        match s.strip_prefix("held-") {
+            Some(id) => id.parse().map(EntryId::Held).map_err(invalid),
+            None => s.parse().map(EntryId::Player).map_err(invalid),
+        }
+    }
+}
+
+/// Options for playing a single item, which the player is given along with it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoadOptions {
+    /// The `ytdl-format` to play the item with, instead of the one set for everything.
+    pub ytdl_format: Option<String>,
+    /// Where to start playing the item, in seconds.
+    pub start: Option<f64>,
+    /// Where to stop playing the item, in seconds.
+    pub end: Option<f64>,
+}
+
+impl LoadOptions {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// The options as mpv takes them with `loadfile`, like `start=10,end=20`.
+    pub fn to_mpv(&self) -> String {
+        let mut options = Vec::new();
+        if let Some(format) = &self.ytdl_format {
+            options.push(format!("ytdl-format={}", escape_option_value(format)));
+        }
+        options.extend(self.start.map(|start| format!("start={}", start)));
+        options.extend(self.end.map(|end| format!("end={}", end)));
+        options.join(",")
+    }
+}
+
+/// What greg-ng knows about an item, besides what the player knows.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ItemState {
+    pub note: ItemNote,
+    /// The lane of the playlist the item was added to.
+    pub lane: Lane,
+    /// How many hours the item can wait in the playlist before it is removed, instead of
+    /// the `--item-ttl` everything else gets.
+    pub ttl_hours: Option<u32>,
+    /// The options the item is played with.
+    pub options: LoadOptions,
+    /// The flags admins have set on the item.
+    pub flags: ItemFlags,
+    /// The name of the block the item is grouped into, see [`blocks`](super::blocks).
+    pub block: Option<String>,
+    /// Whether the item continues from where it was stopped the last time it played, see
+    /// [`resume`](crate::resume).
+    pub resume: bool,
+    /// When the item was first seen in the playlist, as a unix timestamp, see
+    /// [`expiry`](crate::expiry).
+    pub first_seen: Option<u64>,
+}
+
+impl ItemState {
+    /// The state of an item added without a note, credited to `user` if someone logged in.
+    pub fn credited_to(user: Option<&AuthenticatedUser>) -> Self {
+        Self {
+            note: ItemNote::default().credited_to(user),
+            ..Self::default()
+        }
+    }
+}
+
+/// An item held back from the player, see [`WindowedPlayer`](super::WindowedPlayer).
+///
+/// Its state is kept under [`EntryId::Held`], like the state of the items in the player, and
+/// moves along with it once it is fed to the player.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeldItem {
+    pub id: u64,
+    pub url: String,
+}
+
+impl HeldItem {
+    pub fn entry(&self) -> PlaylistEntry {
+        PlaylistEntry {
+            id: EntryId::Held(self.id),
+            filename: self.url.clone(),
+            title: None,
+            current: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct QueueStateInner {
+    items: Mutex<BTreeMap<EntryId, ItemState>>,
+    /// Notified whenever an item is given a state, or its state changes.
+    items_changed: watch::Sender<()>,
+    /// The items held back from the player, in queue order.
+    held: watch::Sender<VecDeque<HeldItem>>,
+    next_held_id: AtomicU64,
+    /// See [`lock_playlist`](super::lock_playlist).
+    playlist_lock: Arc<AsyncMutex<()>>,
+    suspended: AtomicBool,
+    /// Whether an interjection is playing, see [`interject`](super::interject).
+    interjecting: AtomicBool,
+    play_schedule: PlaySchedule,
+    /// See [`wake_on_queue`](super::wake_on_queue).
+    wake_on_queue: AtomicBool,
+}
+
+/// The state of the queue of a player, shared by everything that works with that player.
+///
+/// It is owned by the player, see [`Player::queue_state`](super::Player::queue_state).
+#[derive(Debug, Clone)]
+pub struct QueueState {
+    inner: Arc<QueueStateInner>,
+}
+
+impl Default for QueueState {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(QueueStateInner {
+                items: Mutex::default(),
+                items_changed: watch::Sender::new(()),
+                held: watch::Sender::new(VecDeque::new()),
+                next_held_id: AtomicU64::new(0),
+                playlist_lock: Arc::default(),
+                suspended: AtomicBool::new(false),
+                interjecting: AtomicBool::new(false),
+                play_schedule: PlaySchedule::default(),
+                wake_on_queue: AtomicBool::new(true),
+            }),
+        }
+    }
+}
+
+impl QueueState {
+    /// The state of the item `id`.
+    pub fn get(&self, id: EntryId) -> ItemState {
+        self.inner
+            .items
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The state of the item at `entry`.
+    pub fn item(&self, entry: &PlaylistEntry) -> ItemState {
+        self.get(entry.id)
+    }
+
+    /// The note of the item at `entry`.
+    pub fn note(&self, entry: &PlaylistEntry) -> ItemNote {
+        self.item(entry).note
+    }
+
+    /// The title to show for `entry`, preferring the one it was given when it was added.
+    pub fn display_title(&self, entry: &PlaylistEntry) -> String {
+        self.note(entry)
+            .title
+            .or_else(|| entry.title.clone())
+            .unwrap_or_else(|| entry.filename.clone())
+    }
+
+    /// Keep `state` for the item `id`, which has just been added.
+    pub fn set(&self, id: EntryId, state: ItemState) {
+        {
+            let mut items = self.inner.items.lock().unwrap();
+            if state == ItemState::default() {
+                items.remove(&id);
+            } else {
+                items.insert(id, state);
+            }
+        }
+        self.inner.items_changed.send_replace(());
+    }
+
+    /// Change the state of the item `id`.
+    pub fn update(&self, id: EntryId, update: impl FnOnce(&mut ItemState)) {
+        let mut state = self.get(id);
+        update(&mut state);
+        self.set(id, state);
+    }
+
+    /// Forget the state of the items that are no longer in `playlist`.
+    pub fn forget_gone(&self, playlist: &[PlaylistEntry]) {
+        self.inner
+            .items
+            .lock()
+            .unwrap()
+            .retain(|id, _| playlist.iter().any(|entry| entry.id == *id));
+    }
+
+    /// Keep the state of an item that has been replaced by `to` in the queue.
+    pub fn transfer(&self, from: EntryId, to: EntryId) {
+        let mut items = self.inner.items.lock().unwrap();
+        if let Some(state) = items.remove(&from) {
+            items.insert(to, state);
+        }
+    }
+
+    /// Notified whenever the state of an item changes, which is usually just after the
+    /// player has announced the item itself.
+    pub fn subscribe_items(&self) -> watch::Receiver<()> {
+        self.inner.items_changed.subscribe()
+    }
+
+    /// The items held back from the player, in queue order.
+    pub fn held_items(&self) -> Vec<HeldItem> {
+        self.inner.held.borrow().iter().cloned().collect()
+    }
+
+    /// Notified whenever the held items change.
+    pub fn subscribe_held_items(&self) -> watch::Receiver<VecDeque<HeldItem>> {
+        self.inner.held.subscribe()
+    }
+
+    pub(super) fn held(&self) -> &watch::Sender<VecDeque<HeldItem>> {
+        &self.inner.held
+    }
+
+    /// Whether the playlist is suspended, like while an external input plays instead of it,
+    /// see [`inputs`](crate::inputs).
+    ///
+    /// Nothing should be added to the playlist on its own while this is the case.
+    pub fn is_suspended(&self) -> bool {
+        self.inner.suspended.load(Ordering::SeqCst)
+    }
+
+    pub fn set_suspended(&self, suspended: bool) {
+        self.inner.suspended.store(suspended, Ordering::SeqCst);
+    }
+
+    /// Whether queueing something while the idle screen is showing starts playing it.
+    pub fn set_wake_on_queue(&self, enabled: bool) {
+        self.inner.wake_on_queue.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(super) fn wakes_on_queue(&self) -> bool {
+        self.inner.wake_on_queue.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn interjecting(&self) -> &AtomicBool {
+        &self.inner.interjecting
+    }
+
+    pub(super) fn play_schedule(&self) -> &PlaySchedule {
+        &self.inner.play_schedule
+    }
+
+    pub(super) fn playlist_lock(&self) -> Arc<AsyncMutex<()>> {
+        self.inner.playlist_lock.clone()
+    }
+
+    /// Hold back `url`, giving it a new id.
+    pub(super) fn hold(&self, url: &str) -> HeldItem {
+        HeldItem {
+            id: self.inner.next_held_id.fetch_add(1, Ordering::Relaxed),
+            url: url.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_item_states() {
+        let entry = |id: usize| PlaylistEntry {
+            id: EntryId::Player(id),
+            filename: "song.mp3".to_string(),
+            title: Some("Resolved title".to_string()),
+            current: false,
+        };
+        let queue = QueueState::default();
+        let note = ItemNote::new(Some("Midnight song"), None, Some("someone"));
+        queue.set(
+            EntryId::Player(1),
+            ItemState {
+                note: note.clone(),
+                ..ItemState::default()
+            },
+        );
+        queue.set(EntryId::Player(2), ItemState::credited_to(None));
+
+        // Copies of the same file keep their own note.
+        assert_eq!(queue.note(&entry(1)), note);
+        assert_eq!(queue.note(&entry(2)), ItemNote::default());
+        assert_eq!(queue.display_title(&entry(1)), "Midnight song");
+        assert_eq!(queue.display_title(&entry(2)), "Resolved title");
+
+        queue.transfer(EntryId::Player(1), EntryId::Held(0));
+        assert_eq!(queue.get(EntryId::Held(0)).note, note);
+        assert_eq!(queue.note(&entry(1)), ItemNote::default());
+
+        // The states of items that are gone are forgotten.
+        queue.forget_gone(&[entry(1)]);
+        assert_eq!(queue.get(EntryId::Held(0)), ItemState::default());
+
+        assert_eq!("held-3".parse(), Ok(EntryId::Held(3)));
+        assert_eq!("12".parse(), Ok(EntryId::Player(12)));
+        assert_eq!(EntryId::Held(3).to_string(), "held-3");
+        assert!("held-".parse::<EntryId>().is_err());
+    }
+
+    #[test]
+    fn test_load_options() {
+        assert_eq!(LoadOptions::default().to_mpv(), "");
+        let options = LoadOptions {
+            ytdl_format: Some("bestaudio/best".to_string()),
+            start: Some(10.0),
+            end: Some(72.5),
+        };
+        assert_eq!(
+            options.to_mpv(),
+            "ytdl-format=%14%bestaudio/best,start=10,end=72.5"
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use mpvipc_async::{Mpv, MpvExt};
+use serde::Serialize;
+
+use crate::prefetch::escape_option_value;
+
+use super::{EntryId, QueueState, loaded_entry_id, lock_playlist};
+
+/// How failed playlist items are retried before they are given up on.
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicy {
+    /// How many times an item is retried. With 0, failures are final.
+    pub attempts: u32,
+    /// How long to wait before the first retry. Doubles for every retry after that.
+    pub backoff: Duration,
+    /// A yt-dlp format selection to retry with, like `best[height<=720]`.
+    pub fallback_format: Option<String>,
+}
+
+/// How retrying a failed playlist item is going, shown with the item in the playlist.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RetryStatus {
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub last_error: String,
+}
+
+/// Retries playlist items that failed to play, by moving them up to play next again.
+#[derive(Debug, Clone, Default)]
+pub struct RetryQueue {
+    policy: Arc<RetryPolicy>,
+    /// By the filename of the item.
+    pending: Arc<Mutex<HashMap<String, RetryStatus>>>,
+}
+
+impl RetryQueue {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy: Arc::new(policy),
+            pending: Arc::default(),
+        }
+    }
+
+    /// The retry status of the playlist item with `filename`, if it has failed before.
+    pub fn status(&self, filename: &str) -> Option<RetryStatus> {
+        self.pending.lock().unwrap().get(filename).cloned()
+    }
+
+    /// The item played to the end, so earlier failures are forgotten.
+    pub fn succeeded(&self, filename: &str) {
+        self.pending.lock().unwrap().remove(filename);
+    }
+
+    /// Count another failure of the item with `filename`, returning how long to wait
+    /// before retrying it, or `None` if it is out of attempts.
+    fn record_failure(&self, filename: &str, error: &str) -> Option<Duration> {
+        let mut pending = self.pending.lock().unwrap();
+        let status = pending
+            .entry(filename.to_string())
+            .or_insert_with(|| RetryStatus {
+                attempt: 0,
+                max_attempts: self.policy.attempts,
+                last_error: String::new(),
+            });
+
+        if status.attempt >= self.policy.attempts {
+            pending.remove(filename);
+            return None;
+        }
+
+        status.attempt += 1;
+        status.last_error = error.to_string();
+        Some(self.policy.backoff * 2u32.saturating_pow(status.attempt - 1))
+    }
+
+    /// Schedule a retry of the item with `filename` that failed with `error`.
+    ///
+    /// Returns `false` if it is out of attempts, and the failure is final.
+    pub fn retry(&self, mpv: &Mpv, queue: &QueueState, filename: &str, error: &str) -> bool {
+        let Some(delay) = self.record_failure(filename, error) else {
+            return false;
+        };
+        log::info!("Retrying '{}' in {} seconds", filename, delay.as_secs());
+
+        let retries = self.clone();
+        let mpv = mpv.clone();
+        let queue = queue.clone();
+        let filename = filename.to_string();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Err(e) = retries.requeue(&mpv, &queue, &filename).await {
+                log::warn!("Failed to retry '{}': {:#}", filename, e);
+                retries.pending.lock().unwrap().remove(&filename);
+            }
+        });
+
+        true
+    }
+
+    /// Move the failed item to play right after the current one, starting playback if
+    /// nothing is playing. It keeps its state in `queue`.
+    async fn requeue(&self, mpv: &Mpv, queue: &QueueState, filename: &str) -> anyhow::Result<()> {
+        let _lock = lock_playlist(queue).await;
+
+        let playlist = mpv.get_playlist().await?.0;
+        let Some(index) = playlist
+            .iter()
+            .position(|entry| entry.filename == filename && !entry.current)
+        else {
+            log::debug!(
+                "'{}' left the playlist before it could be retried",
+                filename
+            );
+            self.pending.lock().unwrap().remove(filename);
+            return Ok(());
+        };
+
+        let options = self
+            .policy
+            .fallback_format
+            .as_ref()
+            .map(|format| format!("ytdl-format={}", escape_option_value(format)));
+
+        mpv.playlist_remove_id(index).await?;
+        let mut args = vec![filename, "insert-next-play", "-1"];
+        args.extend(options.as_deref());
+        let reply = mpv.run_command_raw("loadfile", &args).await?;
+        if let Some(id) = loaded_entry_id(reply.as_ref()) {
+            queue.transfer(EntryId::Player(playlist[index].id), id);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_failure() {
+        let queue = RetryQueue::new(RetryPolicy {
+            attempts: 2,
+            backoff: Duration::from_secs(5),
+            fallback_format: None,
+        });
+
+        assert_eq!(
+            queue.record_failure("a", "Loading failed"),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            queue.record_failure("a", "Timed out"),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            queue.status("a"),
+            Some(RetryStatus {
+                attempt: 2,
+                max_attempts: 2,
+                last_error: "Timed out".to_string(),
+            })
+        );
+        assert_eq!(queue.record_failure("a", "Timed out"), None);
+        assert_eq!(queue.status("a"), None);
+
+        queue.record_failure("b", "Loading failed");
+        queue.succeeded("b");
+        assert_eq!(queue.status("b"), None);
+
+        let no_retries = RetryQueue::default();
+        assert_eq!(no_retries.record_failure("c", "Loading failed"), None);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::mpv_setup::PLACEHOLDER_FILENAME;
+
+use super::{PlayerHandle, PlaylistEntry};
+
+/// Whether the player is showing the idle screen, going by its playlist. That is when
+/// nothing is playing, or the only thing playing is the placeholder image.
+pub fn is_idle_screen(playlist: &[PlaylistEntry]) -> bool {
+    match playlist.iter().find(|entry| entry.current) {
+        Some(entry) => entry.filename.ends_with(PLACEHOLDER_FILENAME),
+        None => true,
+    }
+}
+
+/// Start playing the item that was queued at `index`, if the idle screen was showing when
+/// it was queued and waking on queue is on, see [`QueueState::set_wake_on_queue`]. `before`
+/// is the playlist from before queueing.
+///
+/// [`QueueState::set_wake_on_queue`]: super::QueueState::set_wake_on_queue
+pub async fn wake_on_queue(
+    player: &PlayerHandle,
+    before: &[PlaylistEntry],
+    index: usize,
+) -> anyhow::Result<()> {
+    if !player.queue_state().wakes_on_queue() || !is_idle_screen(before) {
+        return Ok(());
+    }
+
+    log::info!("Something was queued on the idle screen, starting playback");
+    player.playlist_goto(index).await?;
+    player.set_playing(true).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        player::{EntryId, MpvPlayer},
+        test_support::FakeMpv,
+    };
+
+    #[tokio::test]
+    async fn test_wake_on_queue() {
+        let mpv = FakeMpv::start();
+        let player: PlayerHandle = Arc::new(MpvPlayer::new(mpv.connect().await));
+
+        let before = player.playlist().await.unwrap();
+        assert!(is_idle_screen(&before));
+        player.load("wake-a.mp3", Default::default()).await.unwrap();
+        wake_on_queue(&player, &before, before.len()).await.unwrap();
+        assert_eq!(mpv.property("pause"), Some(json!(false)));
+        assert_eq!(mpv.property("playlist-pos"), Some(json!(0)));
+
+        let playing = [PlaylistEntry {
+            id: EntryId::Player(1),
+            filename: "wake-a.mp3".to_string(),
+            title: None,
+            current: true,
+        }];
+        assert!(!is_idle_screen(&playing));
+        let placeholder = [PlaylistEntry {
+            id: EntryId::Player(0),
+            filename: format!("/run/greg-ng/{}", PLACEHOLDER_FILENAME),
+            title: None,
+            current: true,
+        }];
+        assert!(is_idle_screen(&placeholder));
+    }
+}
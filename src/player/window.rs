@@ -0,0 +1,438 @@
+//! Keeps most of a long queue in greg-ng, and only the current item and the next few in
+//! the player. The rest are fed to the player as it gets through them, so that the queue
+//! can grow long without the player holding all of it, and outlives the player itself.
+//!
+//! The playlist seen through the [`Player`] trait is the whole queue, with the items that
+//! are held back after the ones in the player.
+
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use mpvipc_async::Mpv;
+use serde_json::Value;
+use tokio::sync::watch;
+
+use crate::api::ApiError;
+
+use super::{EntryId, HeldItem, ItemState, Player, PlayerHandle, PlaylistEntry, QueueState};
+
+/// How often the player is topped up from the held items.
+const FEED_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many items come after the current one, or all of them if nothing is playing.
+fn upcoming(playlist: &[PlaylistEntry]) -> usize {
+    match playlist.iter().position(|entry| entry.current) {
+        Some(current) => playlist.len() - current - 1,
+        None => playlist.len(),
+    }
+}
+
+/// A player that only holds a window of the queue, see the [module docs](self).
+///
+/// The held items are kept in the [`QueueState`] of the player it wraps.
+#[derive(Debug)]
+pub struct WindowedPlayer {
+    inner: PlayerHandle,
+    /// How many items after the current one are kept in the player.
+    window: usize,
+}
+
+impl WindowedPlayer {
+    pub fn new(inner: PlayerHandle, window: usize) -> Self {
+        Self { inner, window }
+    }
+
+    fn held(&self) -> &watch::Sender<VecDeque<HeldItem>> {
+        self.inner.queue_state().held()
+    }
+
+    /// Keep topping up the player from the held items as it gets through its window.
+    pub async fn feed(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(FEED_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if self.held().borrow().is_empty() {
+                continue;
+            }
+            // Feeding the player moves items around, and must not happen in the middle of
+            // someone else's changes.
+            let _lock = super::lock_playlist(self.queue_state()).await;
+            if let Err(e) = self.refill().await {
+                log::debug!("Failed to feed held items to the player: {:#}", e);
+            }
+        }
+    }
+
+    /// Move the first held item into the player, along with its state.
+    async fn feed_one(&self) -> anyhow::Result<bool> {
+        if self.held().borrow().is_empty() {
+            return Ok(false);
+        }
+        self.feed_held(0).await?;
+        Ok(true)
+    }
+
+    /// Move the held item at `held_index` to the end of the player, along with its state.
+    async fn feed_held(&self, held_index: usize) -> anyhow::Result<()> {
+        let item = self.held().borrow()[held_index].clone();
+        let queue = self.inner.queue_state();
+        let id = EntryId::Held(item.id);
+        self.inner.load(&item.url, queue.get(id)).await?;
+        queue.set(id, ItemState::default());
+        self.held().send_modify(|held| {
+            held.remove(held_index);
+        });
+        Ok(())
+    }
+
+    /// Take `entry` out of the player as a held item, along with its state. It still has to
+    /// be removed from the player.
+    fn hold_entry(&self, entry: &PlaylistEntry) -> HeldItem {
+        let queue = self.inner.queue_state();
+        let item = queue.hold(&entry.filename);
+        queue.transfer(entry.id, EntryId::Held(item.id));
+        item
+    }
+
+    /// Move held items into the player until it has a full window of upcoming items.
+    async fn refill(&self) -> anyhow::Result<()> {
+        let mut upcoming = upcoming(&self.inner.playlist().await?);
+        while upcoming < self.window && self.feed_one().await? {
+            upcoming += 1;
+        }
+        Ok(())
+    }
+
+    /// Move the last items in the player back to the held items, until it has no more than
+    /// a window of upcoming items.
+    async fn hold_back(&self) -> anyhow::Result<()> {
+        let playlist = self.inner.playlist().await?;
+        let excess = upcoming(&playlist).saturating_sub(self.window);
+        for (index, entry) in playlist.iter().enumerate().rev().take(excess) {
+            self.inner.playlist_remove(index).await?;
+            let item = self.hold_entry(entry);
+            self.held().send_modify(|held| held.push_front(item));
+        }
+        Ok(())
+    }
+
+    async fn inner_len(&self) -> anyhow::Result<usize> {
+        Ok(self.inner.playlist().await?.len())
+    }
+
+    /// The index into the held items of the queue item at `index`.
+    fn held_index(&self, index: usize, inner_len: usize) -> anyhow::Result<usize> {
+        let held = self.held().borrow().len();
+        if index < inner_len || index - inner_len >= held {
+            return Err(ApiError::InvalidIndex(format!(
+                "No playlist item at index {} (playlist has {} items)",
+                index,
+                inner_len + held
+            ))
+            .into());
+        }
+        Ok(index - inner_len)
+    }
+}
+
+#[async_trait]
+impl Player for WindowedPlayer {
+    fn queue_state(&self) -> &QueueState {
+        self.inner.queue_state()
+    }
+
+    fn mpv(&self) -> Option<&Mpv> {
+        self.inner.mpv()
+    }
+
+    async fn get_property(&self, name: &str) -> anyhow::Result<Option<Value>> {
+        self.inner.get_property(name).await
+    }
+
+    async fn set_property(&self, name: &str, value: Value) -> anyhow::Result<()> {
+        self.inner.set_property(name, value).await
+    }
+
+    async fn load(&self, url: &str, item: ItemState) -> anyhow::Result<EntryId> {
+        let holding = !self.held().borrow().is_empty();
+        if holding || upcoming(&self.inner.playlist().await?) >= self.window {
+            let queue = self.inner.queue_state();
+            let held = queue.hold(url);
+            let id = EntryId::Held(held.id);
+            queue.set(id, item);
+            self.held().send_modify(|items| items.push_back(held));
+            return Ok(id);
+        }
+        self.inner.load(url, item).await
+    }
+
+    async fn is_playing(&self) -> anyhow::Result<bool> {
+        self.inner.is_playing().await
+    }
+
+    async fn set_playing(&self, playing: bool) -> anyhow::Result<()> {
+        self.inner.set_playing(playing).await
+    }
+
+    async fn get_volume(&self) -> anyhow::Result<f64> {
+        self.inner.get_volume().await
+    }
+
+    async fn set_volume(&self, volume: f64) -> anyhow::Result<()> {
+        self.inner.set_volume(volume).await
+    }
+
+    async fn get_time_pos(&self) -> anyhow::Result<Option<f64>> {
+        self.inner.get_time_pos().await
+    }
+
+    async fn get_time_remaining(&self) -> anyhow::Result<Option<f64>> {
+        self.inner.get_time_remaining().await
+    }
+
+    async fn seek(&self, seconds: f64) -> anyhow::Result<()> {
+        self.inner.seek(seconds).await
+    }
+
+    async fn seek_percent(&self, percent: f64) -> anyhow::Result<()> {
+        self.inner.seek_percent(percent).await
+    }
+
+    async fn playlist(&self) -> anyhow::Result<Vec<PlaylistEntry>> {
+        let mut playlist = self.inner.playlist().await?;
+        playlist.extend(self.held().borrow().iter().map(HeldItem::entry));
+        Ok(playlist)
+    }
+
+    async fn playlist_next(&self) -> anyhow::Result<()> {
+        self.refill().await?;
+        self.inner.playlist_next().await
+    }
+
+    async fn playlist_previous(&self) -> anyhow::Result<()> {
+        self.inner.playlist_previous().await
+    }
+
+    async fn playlist_goto(&self, index: usize) -> anyhow::Result<()> {
+        let inner_len = self.inner_len().await?;
+        if index >= inner_len {
+            // Bring everything up to the item into the player, so the order stays the same.
+            let held_index = self.held_index(index, inner_len)?;
+            for _ in 0..=held_index {
+                self.feed_one().await?;
+            }
+        }
+        self.inner.playlist_goto(index).await
+    }
+
+    async fn playlist_remove(&self, index: usize) -> anyhow::Result<()> {
+        let inner_len = self.inner_len().await?;
+        if index < inner_len {
+            self.inner.playlist_remove(index).await?;
+            return self.refill().await;
+        }
+        let held_index = self.held_index(index, inner_len)?;
+        self.held().send_modify(|held| {
+            held.remove(held_index);
+        });
+        Ok(())
+    }
+
+    async fn playlist_move(&self, from: usize, to: usize) -> anyhow::Result<()> {
+        let inner_len = self.inner_len().await?;
+        match (from < inner_len, to <= inner_len) {
+            (true, true) => self.inner.playlist_move(from, to).await?,
+            (false, false) => {
+                let (from, to) = (self.held_index(from, inner_len)?, to - inner_len);
+                self.held().send_modify(|held| {
+                    if let Some(item) = held.remove(from) {
+                        held.insert(if to > from { to - 1 } else { to }.min(held.len()), item);
+                    }
+                });
+            }
+            // Out of the player, to before the held item at `to`. The item playing now has to
+            // stay in the player.
+            (true, false) => {
+                let entry = self.inner.playlist().await?[from].clone();
+                if entry.current {
+                    return Err(ApiError::Conflict(
+                        "The item playing now can not be moved behind the held items".to_string(),
+                    )
+                    .into());
+                }
+                self.inner.playlist_remove(from).await?;
+                let item = self.hold_entry(&entry);
+                self.held().send_modify(|held| {
+                    let to = (to - inner_len).min(held.len());
+                    held.insert(to, item);
+                });
+            }
+            // Into the player, from the held items.
+            (false, true) => {
+                self.feed_held(self.held_index(from, inner_len)?).await?;
+                self.inner.playlist_move(inner_len, to).await?;
+            }
+        }
+        self.refill().await
+    }
+
+    async fn playlist_clear(&self) -> anyhow::Result<()> {
+        self.held().send_modify(|held| held.clear());
+        self.inner.playlist_clear().await
+    }
+
+    /// Shuffles the held items in with the ones in the player, by moving them all into the
+    /// player for the shuffle and holding back what no longer fits in the window after.
+    async fn playlist_shuffle(&self) -> anyhow::Result<()> {
+        while self.feed_one().await? {}
+        self.inner.playlist_shuffle().await?;
+        self.hold_back().await
+    }
+
+    async fn is_looping(&self) -> anyhow::Result<bool> {
+        self.inner.is_looping().await
+    }
+
+    async fn set_looping(&self, looping: bool) -> anyhow::Result<()> {
+        self.inner.set_looping(looping).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        player::{ItemNote, ItemState, LoadOptions, MpvPlayer},
+        test_support::FakeMpv,
+    };
+
+    #[tokio::test]
+    async fn test_windowed_player() {
+        let mpv = FakeMpv::start();
+        let inner: PlayerHandle = Arc::new(MpvPlayer::new(mpv.connect().await));
+        let player = WindowedPlayer::new(inner, 1);
+        let held = || player.queue_state().held_items();
+
+        let mut ids = Vec::new();
+        for url in ["a.mp3", "b.mp3", "c.mp3", "d.mp3"] {
+            ids.push(player.load(url, ItemState::default()).await.unwrap());
+        }
+        let note = ItemNote::new(Some("Held song"), None, None);
+        player.queue_state().set(
+            ids[3],
+            ItemState {
+                note: note.clone(),
+                ..ItemState::default()
+            },
+        );
+        let filenames = |playlist: Vec<PlaylistEntry>| -> Vec<String> {
+            playlist.into_iter().map(|entry| entry.filename).collect()
+        };
+        assert_eq!(
+            filenames(player.playlist().await.unwrap()),
+            ["a.mp3", "b.mp3", "c.mp3", "d.mp3"]
+        );
+        assert_eq!(held().len(), 3);
+
+        player.playlist_remove(2).await.unwrap();
+        player.playlist_move(2, 1).await.unwrap();
+        assert_eq!(
+            filenames(player.playlist().await.unwrap()),
+            ["a.mp3", "d.mp3", "b.mp3"]
+        );
+        assert!(player.playlist_remove(3).await.is_err());
+
+        // The note of the held item followed it into the player.
+        let moved = &player.playlist().await.unwrap()[1];
+        assert!(matches!(moved.id, EntryId::Player(_)));
+        assert_eq!(player.queue_state().note(moved), note);
+
+        // Once the player gets to its last item, the next held one is fed to it.
+        mpv.set_property(
+            "playlist",
+            json!([
+                {"filename": "a.mp3", "id": 1},
+                {"filename": "d.mp3", "id": 2, "current": true},
+            ]),
+        );
+        player.refill().await.unwrap();
+        assert!(held().is_empty());
+        let loaded: Vec<_> = mpv
+            .commands()
+            .into_iter()
+            .filter(|command| command[0] == json!("loadfile"))
+            .map(|command| command[1].clone())
+            .collect();
+        assert_eq!(loaded, [json!("a.mp3"), json!("d.mp3"), json!("b.mp3")]);
+    }
+
+    #[tokio::test]
+    async fn test_shuffle_whole_queue() {
+        let mpv = FakeMpv::start();
+        let inner: PlayerHandle = Arc::new(MpvPlayer::new(mpv.connect().await));
+        let player = WindowedPlayer::new(inner, 1);
+        let held = || player.queue_state().held_items();
+        for url in ["a.mp3", "b.mp3", "c.mp3"] {
+            player.load(url, ItemState::default()).await.unwrap();
+        }
+        assert_eq!(held().len(), 2);
+
+        // The fake player reverses instead of shuffling, so the last held item ends up first.
+        player.playlist_shuffle().await.unwrap();
+        let filenames: Vec<String> = player
+            .playlist()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.filename)
+            .collect();
+        assert_eq!(filenames, ["c.mp3", "b.mp3", "a.mp3"]);
+        let held: Vec<_> = held().into_iter().map(|item| item.url).collect();
+        assert_eq!(held, ["b.mp3", "a.mp3"]);
+    }
+
+    #[tokio::test]
+    async fn test_held_items_keep_their_options() {
+        let mpv = FakeMpv::start();
+        let inner: PlayerHandle = Arc::new(MpvPlayer::new(mpv.connect().await));
+        let player = WindowedPlayer::new(inner, 1);
+        let options = LoadOptions {
+            start: Some(30.0),
+            ..LoadOptions::default()
+        };
+        player.load("a.mp3", ItemState::default()).await.unwrap();
+        let id = player
+            .load(
+                "b.mp3",
+                ItemState {
+                    options: options.clone(),
+                    ..ItemState::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(matches!(id, EntryId::Held(_)));
+        assert_eq!(player.queue_state().get(id).options, options);
+
+        // The item playing now stays in the player.
+        mpv.set_property(
+            "playlist",
+            json!([{"filename": "a.mp3", "id": 1, "current": true}]),
+        );
+        assert!(player.playlist_move(0, 2).await.is_err());
+
+        player.refill().await.unwrap();
+        let loaded = mpv
+            .commands()
+            .into_iter()
+            .rfind(|command| command[0] == json!("loadfile"))
+            .unwrap();
+        assert_eq!(
+            json!(loaded),
+            json!(["loadfile", "b.mp3", "append", "-1", "start=30"])
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Named playlists, saved so they can be prepared in advance.
+
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::ApiError,
+    oidc::AuthenticatedUser,
+    player::{self, ItemState, PlayerHandle},
+    quotas::QuotaCharge,
+    storage::StorageHandle,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct SavedPlaylistItem {
+    pub filename: String,
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct SavedPlaylist {
+    pub name: String,
+    /// When the playlist was saved, in seconds since the unix epoch.
+    pub saved_at: u64,
+    pub items: Vec<SavedPlaylistItem>,
+}
+
+/// How a saved playlist is added to the player.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LoadMode {
+    /// Add the items after the current playlist.
+    #[default]
+    Append,
+    /// Stop what is playing, and play the saved playlist instead.
+    Replace,
+}
+
+/// Saved playlists, kept in the storage.
+#[derive(Debug, Clone)]
+pub struct PlaylistStore {
+    storage: StorageHandle,
+}
+
+fn check_name(name: &str) -> Result<(), ApiError> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.'))
+        && !name.starts_with('.');
+
+    if !valid {
+        return Err(ApiError::InvalidArgument(format!(
+            "Invalid playlist name '{}', only letters, digits, spaces, '-', '_' and '.' are allowed",
+            name
+        )));
+    }
+    Ok(())
+}
+
+fn not_found(name: &str) -> anyhow::Error {
+    ApiError::NotFound(format!("No saved playlist named '{}'", name)).into()
+}
+
+impl PlaylistStore {
+    pub fn new(storage: StorageHandle) -> Self {
+        Self { storage }
+    }
+
+    /// Save the current playlist of `player` as `name`, replacing any playlist with the same name.
+    pub async fn save(&self, name: &str, player: &PlayerHandle) -> anyhow::Result<SavedPlaylist> {
+        check_name(name)?;
+
+        let playlist = SavedPlaylist {
+            name: name.to_string(),
+            saved_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            items: player
+                .playlist()
+                .await?
+                .into_iter()
+                .map(|entry| SavedPlaylistItem {
+                    filename: entry.filename,
+                    title: entry.title,
+                })
+                .collect(),
+        };
+        self.storage
+            .save_playlist(&playlist, true)
+            .with_context(|| format!("Failed to save playlist '{}'", name))?;
+
+        log::info!(
+            "Saved playlist '{}' with {} items",
+            name,
+            playlist.items.len()
+        );
+        Ok(playlist)
+    }
+
+    pub async fn get(&self, name: &str) -> anyhow::Result<SavedPlaylist> {
+        check_name(name)?;
+        self.storage.playlist(name)?.ok_or_else(|| not_found(name))
+    }
+
+    /// All saved playlists, sorted by name.
+    pub async fn list(&self) -> anyhow::Result<Vec<SavedPlaylist>> {
+        let mut playlists = Vec::new();
+        for name in self.storage.playlist_names()? {
+            playlists.extend(self.storage.playlist(&name)?);
+        }
+        Ok(playlists)
+    }
+
+    pub async fn delete(&self, name: &str) -> anyhow::Result<()> {
+        check_name(name)?;
+        if !self.storage.delete_playlist(name)? {
+            return Err(not_found(name));
+        }
+        Ok(())
+    }
+
+    /// Move the playlists saved as JSON files in `dir`, the way they were stored before the
+    /// database, into the storage. Playlists that are already in the storage are kept
+    /// as they are, and `dir` is renamed afterwards so this only happens once.
+    pub fn import_files(&self, dir: &Path) -> anyhow::Result<()> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+        };
+
+        let mut imported = 0;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+
+            match std::fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|content| Ok(serde_json::from_slice::<SavedPlaylist>(&content)?))
+            {
+                Ok(playlist) => {
+                    if self.storage.save_playlist(&playlist, false)? {
+                        imported += 1;
+                    }
+                }
+                Err(e) => log::warn!("Skipping unreadable playlist {}: {:#}", path.display(), e),
+            }
+        }
+
+        let done = dir.with_extension("imported");
+        std::fs::rename(dir, &done)
+            .with_context(|| format!("Failed to rename {} after importing it", dir.display()))?;
+        log::info!(
+            "Imported {} saved playlists, the old files are kept in {}",
+            imported,
+            done.display()
+        );
+        Ok(())
+    }
+
+    /// Add the saved playlist `name` to `player`, crediting its items to `user` and counting
+    /// them with `charge`.
+    pub async fn load(
+        &self,
+        name: &str,
+        mode: LoadMode,
+        player: &PlayerHandle,
+        user: Option<&AuthenticatedUser>,
+        charge: Option<&QuotaCharge>,
+    ) -> anyhow::Result<()> {
+        let playlist = self.get(name).await?;
+
+        let replaced_current = match mode {
+            LoadMode::Append => false,
+            LoadMode::Replace => {
+                player::check_clear(player.queue_state(), &player.playlist().await?)?;
+                // Clearing keeps the current item, which is removed once the new items are in.
+                player.playlist_clear().await?;
+                !player.playlist().await?.is_empty()
+            }
+        };
+
+        let filenames: Vec<_> = playlist
+            .items
+            .iter()
+            .map(|item| item.filename.clone())
+            .collect();
+        for filename in &filenames {
+            player.load(filename, ItemState::credited_to(user)).await?;
+            if let Some(charge) = charge {
+                charge.charge(1);
+            }
+        }
+
+        if replaced_current {
+            if !playlist.items.is_empty() {
+                player.playlist_goto(1).await?;
+            }
+            player.playlist_remove(0).await?;
+        }
+
+        log::info!(
+            "Loaded playlist '{}' with {} items ({:?})",
+            name,
+            playlist.items.len(),
+            mode
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::storage::MemoryStorage;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_saved_playlists() {
+        assert!(check_name("Fredagsfilm 2.0").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("..").is_err());
+        assert!(check_name("../../etc/passwd").is_err());
+        assert!(check_name(&"a".repeat(65)).is_err());
+
+        let store = PlaylistStore::new(Arc::new(MemoryStorage::new()));
+        let playlist = |name: &str, items: &[&str]| SavedPlaylist {
+            name: name.to_string(),
+            saved_at: 1,
+            items: items
+                .iter()
+                .map(|filename| SavedPlaylistItem {
+                    filename: filename.to_string(),
+                    title: None,
+                })
+                .collect(),
+        };
+
+        store
+            .storage
+            .save_playlist(&playlist("Fredagsfilm", &["a"]), true)
+            .unwrap();
+        assert_eq!(
+            store.get("Fredagsfilm").await.unwrap(),
+            playlist("Fredagsfilm", &["a"])
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let files = dir.path().join("playlists");
+        std::fs::create_dir(&files).unwrap();
+        std::fs::write(
+            files.join("Old.json"),
+            serde_json::to_vec(&playlist("Old", &["e"])).unwrap(),
+        )
+        .unwrap();
+        store.import_files(&files).unwrap();
+        assert!(!files.exists());
+
+        let names: Vec<String> = store
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|playlist| playlist.name)
+            .collect();
+        assert_eq!(names, vec!["Fredagsfilm", "Old"]);
+
+        store.delete("Old").await.unwrap();
+        assert!(store.get("Old").await.is_err());
+        assert!(store.delete("Old").await.is_err());
+    }
+}
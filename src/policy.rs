@@ -0,0 +1,301 @@
+//! The checks every command goes through before it changes anything, whether it comes in
+//! over the REST API or a websocket. Each check is a [`PolicyEngine`], and deployments pick
+//! which of them to use with `--policy`.
+//!
+//! Two checks are not policies, since leaving them out is not up to the deployment. Bans
+//! turn away every request from an address, reads and websocket connections included,
+//! see [`reject_banned`](crate::bans::reject_banned). Pinned and locked items depend on
+//! which items a change touches once the playlist is locked, so they are checked where the
+//! change is made, see [`check_moves`](crate::player::check_moves).
+
+use std::{fmt, sync::Arc};
+
+use crate::{
+    api::ApiError,
+    control_lock::ControlLock,
+    player::Priority,
+    quotas::{QuotaCharge, Quotas},
+    read_only::ReadOnlyMode,
+    scopes::Scope,
+};
+
+/// The policies that can be turned on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PolicyKind {
+    /// Refuse changes while read-only mode is on.
+    ReadOnly,
+    /// Refuse changes from everyone but the holder while someone has claimed control.
+    ControlLock,
+    /// Refuse commands that need a scope the token they came with does not have.
+    Scopes,
+    /// Count the items each client adds, and refuse them over `--quota-limit`.
+    Quota,
+}
+
+/// A command about to change something, as seen by the policies.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Command<'a> {
+    /// The path of a REST request, or `None` for a websocket command.
+    pub path: Option<&'a str>,
+    /// Whether the request has the admin token.
+    pub is_admin: bool,
+    /// The token from `/api/control/claim`, if one was given.
+    pub control_token: Option<&'a str>,
+    /// The scopes of whoever sent the command, or `None` if they did not say who they are.
+    pub scopes: Option<&'a [Scope]>,
+    /// The scope needed for what the command does, if any.
+    pub needs: Option<Scope>,
+    /// Who sent the command as far as quotas go, see [`coarse_identity`](crate::quotas::coarse_identity).
+    pub identity: Option<&'a str>,
+    /// The priority items are added with, which may need a scope of its own.
+    pub priority: Priority,
+}
+
+impl Command<'_> {
+    /// Whether this is a REST request to a path under one of `prefixes`.
+    fn is_under(&self, prefixes: &[&str]) -> bool {
+        self.path
+            .is_some_and(|path| prefixes.iter().any(|prefix| path.starts_with(prefix)))
+    }
+
+    /// Who the items this command adds are counted for. Admins are not counted, and neither
+    /// are clients that can not be told apart.
+    fn counted_identity(&self) -> Option<&str> {
+        self.identity
+            .filter(|_| !self.is_admin && self.needs == Some(Scope::QueueAdd))
+    }
+}
+
+/// Something consulted before a command is run, which can refuse it.
+pub trait PolicyEngine: fmt::Debug + Send + Sync {
+    /// Fail with the error shown to the client if `command` is not allowed.
+    fn check_command(&self, command: &Command) -> Result<(), ApiError>;
+}
+
+/// Admins can change things while read-only mode is on. The admin and session endpoints
+/// are left alone, since they check tokens of their own.
+impl PolicyEngine for ReadOnlyMode {
+    fn check_command(&self, command: &Command) -> Result<(), ApiError> {
+        if command.is_admin || command.is_under(&["/api/admin/", "/api/session/"]) {
+            return Ok(());
+        }
+        self.check()
+    }
+}
+
+/// Admins can change things while someone else has control, and control can always be
+/// claimed and released.
+impl PolicyEngine for ControlLock {
+    fn check_command(&self, command: &Command) -> Result<(), ApiError> {
+        if command.is_admin || command.is_under(&["/api/admin/", "/api/session/", "/api/control/"])
+        {
+            return Ok(());
+        }
+        self.check(command.control_token)
+    }
+}
+
+/// Checks that commands sent with a token are within its scopes. Everyone else can do
+/// what anyone can.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScopeCheck;
+
+impl PolicyEngine for ScopeCheck {
+    fn check_command(&self, command: &Command) -> Result<(), ApiError> {
+        if command.is_admin {
+            return Ok(());
+        }
+        let scopes = command.scopes.unwrap_or(&Scope::EVERYONE);
+        let priority = match command.needs {
+            Some(Scope::QueueAdd) => command.priority.needs(),
+            _ => None,
+        };
+        match [command.needs, priority]
+            .into_iter()
+            .flatten()
+            .find(|needs| !needs.granted_by(scopes))
+        {
+            Some(needs) => Err(ApiError::Unauthorized(format!(
+                "This needs the {} scope",
+                needs
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Only refuses clients that are already over the limit. What they add is counted as it is
+/// added, see [`Policies::quota_charge`].
+impl PolicyEngine for Quotas {
+    fn check_command(&self, command: &Command) -> Result<(), ApiError> {
+        match command.counted_identity() {
+            Some(identity) => self.check(identity),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Several policies, which all have to allow a command. The first to refuse it decides
+/// the error.
+#[derive(Debug, Clone, Default)]
+pub struct Policies {
+    engines: Arc<Vec<Arc<dyn PolicyEngine>>>,
+    /// The quotas, if they are one of the policies.
+    quotas: Option<Quotas>,
+}
+
+impl Policies {
+    pub fn new(engines: Vec<Arc<dyn PolicyEngine>>) -> Self {
+        Self {
+            engines: Arc::new(engines),
+            quotas: None,
+        }
+    }
+
+    /// The policies in `kinds`, in that order.
+    pub fn configured(
+        kinds: &[PolicyKind],
+        read_only: &ReadOnlyMode,
+        control: &ControlLock,
+        quotas: &Quotas,
+    ) -> Self {
+        let engines = kinds
+            .iter()
+            .map(|kind| -> Arc<dyn PolicyEngine> {
+                match kind {
+                    PolicyKind::ReadOnly => Arc::new(read_only.clone()),
+                    PolicyKind::ControlLock => Arc::new(control.clone()),
+                    PolicyKind::Scopes => Arc::new(ScopeCheck),
+                    PolicyKind::Quota => Arc::new(quotas.clone()),
+                }
+            })
+            .collect();
+        Self {
+            quotas: kinds.contains(&PolicyKind::Quota).then(|| quotas.clone()),
+            ..Self::new(engines)
+        }
+    }
+
+    /// What counts the items `command` adds towards the quota of whoever sent it, once it
+    /// has been allowed.
+    pub fn quota_charge(&self, command: &Command) -> Option<QuotaCharge> {
+        let quotas = self.quotas.as_ref()?;
+        command
+            .counted_identity()
+            .map(|identity| quotas.charge_to(identity))
+    }
+}
+
+impl PolicyEngine for Policies {
+    fn check_command(&self, command: &Command) -> Result<(), ApiError> {
+        self.engines
+            .iter()
+            .try_for_each(|engine| engine.check_command(command))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_policies() {
+        let read_only = ReadOnlyMode::default();
+        let control = ControlLock::default();
+        let quotas = Quotas::new(Some(1), Duration::from_secs(600));
+        let websocket = Command::default();
+        let admin = Command {
+            is_admin: true,
+            ..Command::default()
+        };
+
+        let policies = Policies::configured(
+            &[PolicyKind::ReadOnly, PolicyKind::ControlLock],
+            &read_only,
+            &control,
+            &quotas,
+        );
+        assert!(policies.check_command(&websocket).is_ok());
+        read_only.set_enabled(true);
+        assert!(policies.check_command(&websocket).is_err());
+        assert!(policies.check_command(&admin).is_ok());
+        read_only.set_enabled(false);
+
+        let (_, token) = control
+            .claim("alice".to_string(), Duration::from_secs(60), None)
+            .unwrap();
+        assert!(policies.check_command(&websocket).is_err());
+        let holder = Command {
+            control_token: Some(&token),
+            ..Command::default()
+        };
+        assert!(policies.check_command(&holder).is_ok());
+        let release = Command {
+            path: Some("/api/control/release"),
+            ..Command::default()
+        };
+        assert!(policies.check_command(&release).is_ok());
+
+        // Policies that are not configured are not consulted.
+        read_only.set_enabled(true);
+        let only_read_only =
+            Policies::configured(&[PolicyKind::ReadOnly], &read_only, &control, &quotas);
+        assert!(only_read_only.check_command(&holder).is_err());
+        read_only.set_enabled(false);
+        assert!(only_read_only.check_command(&websocket).is_ok());
+
+        let scopes = Policies::configured(&[PolicyKind::Scopes], &read_only, &control, &quotas);
+        let remove = |scopes: Option<&'static [Scope]>| Command {
+            scopes,
+            needs: Some(Scope::QueueRemove),
+            ..Command::default()
+        };
+        assert!(scopes.check_command(&remove(None)).is_ok());
+        assert!(
+            scopes
+                .check_command(&remove(Some(&[Scope::QueueAdd])))
+                .is_err()
+        );
+        assert!(scopes.check_command(&remove(Some(&[Scope::Admin]))).is_ok());
+        let admin_with_key = Command {
+            is_admin: true,
+            ..remove(Some(&[]))
+        };
+        assert!(scopes.check_command(&admin_with_key).is_ok());
+        let add_high = |scopes: Option<&'static [Scope]>| Command {
+            scopes,
+            needs: Some(Scope::QueueAdd),
+            priority: Priority::High,
+            ..Command::default()
+        };
+        assert!(scopes.check_command(&add_high(None)).is_err());
+        assert!(
+            scopes
+                .check_command(&add_high(Some(&[Scope::QueueAdd, Scope::QueuePriority])))
+                .is_ok()
+        );
+
+        let quota = Policies::configured(&[PolicyKind::Quota], &read_only, &control, &quotas);
+        let add = Command {
+            needs: Some(Scope::QueueAdd),
+            identity: Some("anon:a"),
+            ..Command::default()
+        };
+        // Checking counts nothing, only what is added does.
+        assert!(quota.check_command(&add).is_ok());
+        assert!(quota.check_command(&add).is_ok());
+        quota.quota_charge(&add).unwrap().charge(1);
+        assert!(quota.check_command(&add).is_err());
+        assert!(only_read_only.quota_charge(&add).is_none());
+        assert!(
+            quota
+                .check_command(&Command {
+                    is_admin: true,
+                    ..add
+                })
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,387 @@
+//! Resolves upcoming playlist items with yt-dlp ahead of time.
+//!
+//! mpv normally runs yt-dlp when an item starts playing, which for YouTube
+//! takes several seconds. Instead, the next few items are resolved in the
+//! background, and swapped for the direct media URLs in the playlist.
+//!
+//! Direct URLs usually expire after a few hours, so swapped items that are
+//! about to expire before being played are swapped back to the original URL.
+//!
+//! With replay gain enabled, upcoming items are also scanned for how loud they are,
+//! and given a volume offset so that consecutive items play at a consistent loudness.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use mpvipc_async::{Mpv, MpvExt};
+use tokio::process::Command;
+use url::Url;
+
+use crate::{
+    loudness,
+    player::{self, EntryId, QueueState},
+};
+
+/// How many items after the current one are resolved ahead of time.
+const LOOKAHEAD: usize = 2;
+
+/// How often the playlist is checked for items to resolve.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a resolved URL is assumed to be valid, when it doesn't say itself.
+const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Resolved URLs that expire sooner than this are not used.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(10 * 60);
+
+/// How long yt-dlp gets to resolve a single item.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+struct ResolvedStream {
+    title: Option<String>,
+    video_url: String,
+    /// Separate audio stream, when the best format is split in two.
+    audio_url: Option<String>,
+    expires_at: SystemTime,
+    /// The replay gain in dB, if the stream has been scanned.
+    gain: Option<f64>,
+}
+
+impl ResolvedStream {
+    fn is_fresh(&self) -> bool {
+        SystemTime::now() + EXPIRY_MARGIN < self.expires_at
+    }
+
+    /// Per-file mpv options for playing the resolved stream as if it was the original item.
+    fn mpv_options(&self) -> String {
+        let mut options = vec!["ytdl=no".to_string()];
+        if let Some(title) = &self.title {
+            options.push(format!("force-media-title={}", escape_option_value(title)));
+        }
+        if let Some(audio_url) = &self.audio_url {
+            options.push(format!(
+                "audio-files-append={}",
+                escape_option_value(audio_url)
+            ));
+        }
+        if let Some(gain) = self.gain {
+            options.push(loudness::mpv_options(gain));
+        }
+        options.join(",")
+    }
+}
+
+/// Quote a value for use in an mpv option list, using the `%<length>%<value>` syntax.
+pub(crate) fn escape_option_value(value: &str) -> String {
+    format!("%{}%{}", value.len(), value)
+}
+
+/// When a resolved URL expires, according to its `expire` query parameter.
+fn parse_expiry(url: &str) -> Option<SystemTime> {
+    let url = Url::parse(url).ok()?;
+    let (_, expire) = url.query_pairs().find(|(key, _)| key == "expire")?;
+    let seconds = expire.parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Parse the output of `yt-dlp --get-title --get-url`.
+fn parse_yt_dlp_output(output: &str) -> Option<ResolvedStream> {
+    let mut lines = output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty());
+    let title = lines.next()?.to_string();
+    let video_url = lines.next()?.to_string();
+    let audio_url = lines.next().map(str::to_string);
+
+    let expires_at = [Some(&video_url), audio_url.as_ref()]
+        .into_iter()
+        .flatten()
+        .filter_map(|url| parse_expiry(url))
+        .min()
+        .unwrap_or_else(|| SystemTime::now() + DEFAULT_TTL);
+
+    Some(ResolvedStream {
+        title: Some(title),
+        video_url,
+        audio_url,
+        expires_at,
+        gain: None,
+    })
+}
+
+/// Keeps track of which playlist items have been swapped for resolved URLs.
+#[derive(Debug)]
+struct Prefetcher {
+    mpv: Mpv,
+    /// The state of the playlist items, which moves along with them when they are swapped.
+    queue: QueueState,
+    yt_dlp_path: String,
+    /// Resolved streams, by original URL.
+    cache: HashMap<String, ResolvedStream>,
+    /// Original URLs, by the resolved URL that replaced them in the playlist.
+    swapped: HashMap<String, String>,
+    /// Location of ffmpeg, if items should be scanned for replay gain.
+    ffmpeg_path: Option<String>,
+    /// Replay gain by original URL or path, or `None` if the scan failed.
+    gains: HashMap<String, Option<f64>>,
+    /// Local files that have been given their replay gain in the playlist.
+    adjusted: HashSet<String>,
+}
+
+impl Prefetcher {
+    /// The replay gain for the item at `original`, scanning `input` if it hasn't been
+    /// scanned before. `None` if replay gain is disabled or the scan failed.
+    async fn gain(&mut self, original: &str, input: &str) -> Option<f64> {
+        let ffmpeg_path = self.ffmpeg_path.as_ref()?;
+        if let Some(gain) = self.gains.get(original) {
+            return *gain;
+        }
+
+        log::debug!("Measuring the loudness of '{}'", original);
+        let gain = match loudness::measure_gain(ffmpeg_path, input).await {
+            Ok(gain) => Some(gain),
+            Err(e) => {
+                log::debug!("Could not measure the loudness of '{}': {:#}", original, e);
+                None
+            }
+        };
+        self.gains.insert(original.to_string(), gain);
+        gain
+    }
+
+    async fn resolve(&mut self, url: &str) -> anyhow::Result<Option<ResolvedStream>> {
+        if let Some(stream) = self.cache.get(url)
+            && stream.is_fresh()
+        {
+            return Ok(Some(stream.clone()));
+        }
+
+        log::debug!("Resolving '{}' ahead of time", url);
+        let output = tokio::time::timeout(
+            RESOLVE_TIMEOUT,
+            Command::new(&self.yt_dlp_path)
+                .args(["--get-title", "--get-url", "--no-playlist", "--no-warnings"])
+                .arg("--")
+                .arg(url)
+                .kill_on_drop(true)
+                .output(),
+        )
+        .await
+        .context("yt-dlp timed out")?
+        .context("Failed to run yt-dlp")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "yt-dlp exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let Some(mut stream) = parse_yt_dlp_output(&String::from_utf8_lossy(&output.stdout))
+            .filter(|stream| stream.video_url != url && stream.is_fresh())
+        else {
+            return Ok(None);
+        };
+
+        let input = stream
+            .audio_url
+            .as_ref()
+            .unwrap_or(&stream.video_url)
+            .clone();
+        stream.gain = self.gain(url, &input).await;
+
+        self.cache.insert(url.to_string(), stream.clone());
+        Ok(Some(stream))
+    }
+
+    /// Replace the playlist item at `index`, if it is still `expected`.
+    async fn replace_item(
+        &self,
+        index: usize,
+        expected: &str,
+        url: &str,
+        options: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let playlist = self.mpv.get_playlist().await?.0;
+        if playlist.get(index).map(|entry| entry.filename.as_str()) != Some(expected) {
+            return Ok(false);
+        }
+
+        // Insert at the end, move it into place, and remove the old item.
+        let mut args = vec![url, "append", "-1"];
+        args.extend(options);
+        let reply = self.mpv.run_command_raw("loadfile", &args).await?;
+        self.mpv.playlist_move_id(playlist.len(), index).await?;
+        self.mpv.playlist_remove_id(index + 1).await?;
+        if let Some(id) = player::loaded_entry_id(reply.as_ref()) {
+            self.queue.transfer(EntryId::Player(playlist[index].id), id);
+        }
+
+        Ok(true)
+    }
+
+    /// Give a local file its replay gain, if it hasn't been given it already.
+    async fn adjust_local_file(&mut self, index: usize, path: &str) -> anyhow::Result<()> {
+        if self.adjusted.contains(path) {
+            return Ok(());
+        }
+        let Some(gain) = self.gain(path, path).await else {
+            return Ok(());
+        };
+
+        let options = loudness::mpv_options(gain);
+        if self.replace_item(index, path, path, Some(&options)).await? {
+            log::debug!("Playing '{}' with a replay gain of {:.1} dB", path, gain);
+            self.adjusted.insert(path.to_string());
+        }
+        Ok(())
+    }
+
+    async fn update(&mut self) -> anyhow::Result<()> {
+        let playlist = self.mpv.get_playlist().await?.0;
+        let Some(current) = playlist.iter().position(|entry| entry.current) else {
+            return Ok(());
+        };
+
+        self.cache.retain(|_, stream| stream.is_fresh());
+
+        // Forget swapped items that have left the playlist.
+        self.swapped
+            .retain(|resolved, _| playlist.iter().any(|entry| &entry.filename == resolved));
+        self.adjusted
+            .retain(|path| playlist.iter().any(|entry| &entry.filename == path));
+        self.gains.retain(|original, _| {
+            self.cache.contains_key(original)
+                || playlist.iter().any(|entry| &entry.filename == original)
+        });
+
+        for (index, entry) in playlist.iter().enumerate().skip(current + 1) {
+            let filename = &entry.filename;
+
+            if let Some(original) = self.swapped.get(filename).cloned() {
+                let fresh = self
+                    .cache
+                    .get(&original)
+                    .is_some_and(|stream| &stream.video_url == filename);
+                if !fresh {
+                    log::debug!("Resolved URL for '{}' is expiring, restoring it", original);
+                    self.replace_item(index, filename, &original, None).await?;
+                    self.swapped.remove(filename);
+                }
+                continue;
+            }
+
+            if index > current + LOOKAHEAD {
+                continue;
+            }
+
+            if !filename.starts_with("http") {
+                self.adjust_local_file(index, filename).await?;
+                continue;
+            }
+
+            let stream = match self.resolve(filename).await {
+                Ok(Some(stream)) => stream,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::debug!("Could not resolve '{}' ahead of time: {:#}", filename, e);
+                    continue;
+                }
+            };
+
+            if self
+                .replace_item(
+                    index,
+                    filename,
+                    &stream.video_url,
+                    Some(&stream.mpv_options()),
+                )
+                .await?
+            {
+                log::info!("Swapped in resolved URL for '{}'", filename);
+                self.swapped
+                    .insert(stream.video_url.clone(), filename.clone());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Keep resolving the upcoming playlist items, until mpv goes away.
+///
+/// With an `ffmpeg_path`, the items are also given a replay gain.
+pub async fn run_prefetcher(
+    mpv: Mpv,
+    queue: QueueState,
+    yt_dlp_path: String,
+    ffmpeg_path: Option<String>,
+) {
+    log::info!(
+        "Resolving the next {} playlist items ahead of time",
+        LOOKAHEAD
+    );
+
+    let mut prefetcher = Prefetcher {
+        mpv,
+        queue,
+        yt_dlp_path,
+        cache: HashMap::new(),
+        swapped: HashMap::new(),
+        ffmpeg_path,
+        gains: HashMap::new(),
+        adjusted: HashSet::new(),
+    };
+
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = prefetcher.update().await {
+            log::warn!("Failed to prefetch playlist items: {:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_yt_dlp_output() {
+        let output = "Some video\n\
+            https://rr1.googlevideo.com/videoplayback?expire=2000000000&itag=248\n\
+            https://rr1.googlevideo.com/videoplayback?expire=1900000000&itag=251\n";
+
+        let stream = parse_yt_dlp_output(output).unwrap();
+        assert_eq!(stream.title.as_deref(), Some("Some video"));
+        assert_eq!(
+            stream.video_url,
+            "https://rr1.googlevideo.com/videoplayback?expire=2000000000&itag=248"
+        );
+        assert!(stream.audio_url.is_some());
+        assert_eq!(
+            stream.expires_at,
+            UNIX_EPOCH + Duration::from_secs(1900000000)
+        );
+
+        assert!(parse_yt_dlp_output("Only a title\n").is_none());
+    }
+
+    #[test]
+    fn test_mpv_options() {
+        let stream = ResolvedStream {
+            title: Some("a, b".to_string()),
+            video_url: "https://example.com/video".to_string(),
+            audio_url: None,
+            expires_at: UNIX_EPOCH,
+            gain: None,
+        };
+        assert_eq!(stream.mpv_options(), "ytdl=no,force-media-title=%4%a, b");
+    }
+}
@@ -0,0 +1,256 @@
+//! How much each client has added lately, for open deployments where most people never log
+//! in. Clients are told apart by a coarse identity: who they logged in as, or else a hash
+//! of their address and user agent. Usage decays by half every half-life, so that someone
+//! who stops adding things is soon let back in.
+//!
+//! The quota policy refuses commands from clients over the limit before they run, and the
+//! items they do add are counted one by one as they are added, through a [`QuotaCharge`].
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    Extension,
+    http::{HeaderMap, header},
+};
+use ring::digest;
+use serde::Serialize;
+
+use crate::{api::ApiError, history::now, oidc::AuthenticatedUser, server::ClientAddr};
+
+/// Usage below this is forgotten.
+const FORGET_BELOW: f64 = 0.01;
+
+/// Who a request is from, as far as quotas go. People who logged in or gave an API key are
+/// known by their subject. Everyone else is known by their address and user agent, hashed
+/// so that the quota table does not list addresses.
+pub fn coarse_identity(
+    addr: &ClientAddr,
+    headers: &HeaderMap,
+    user: Option<&AuthenticatedUser>,
+) -> String {
+    if let Some(user) = user {
+        return format!("user:{}", user.subject);
+    }
+    let ip = match addr {
+        ClientAddr::Tcp(addr) => addr.ip().to_canonical().to_string(),
+        ClientAddr::Unix => "unix".to_string(),
+    };
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let hash = digest::digest(
+        &digest::SHA256,
+        format!("{}\n{}", ip, user_agent).as_bytes(),
+    );
+    let hex: String = hash.as_ref()[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("anon:{}", hex)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Usage {
+    /// As of `updated_at`.
+    score: f64,
+    updated_at: u64,
+    added: u64,
+}
+
+/// A row of the quota table, as listed by `/api/admin/quotas`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuotaEntry {
+    pub identity: String,
+    /// How many items they have added lately, decayed by how long ago they were added.
+    pub usage: f64,
+    /// How many items they have added since they were last forgotten.
+    pub added: u64,
+    /// When they last added something, in seconds since the unix epoch.
+    pub last_added_at: u64,
+}
+
+/// How much each client has added lately, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct Quotas {
+    /// How many items can be added before being refused. Without it, usage is only tracked.
+    limit: Option<f64>,
+    half_life: Duration,
+    /// Whether the items of different clients are interleaved in the playlist.
+    fair: bool,
+    usage: Arc<Mutex<HashMap<String, Usage>>>,
+}
+
+impl Quotas {
+    pub fn new(limit: Option<u32>, half_life: Duration) -> Self {
+        Self {
+            limit: limit.map(f64::from),
+            half_life,
+            fair: false,
+            usage: Arc::default(),
+        }
+    }
+
+    /// Interleave the items clients add to the playlist, so that everyone gets a turn
+    /// before anyone gets another.
+    pub fn with_fair_scheduling(self, fair: bool) -> Self {
+        Self { fair, ..self }
+    }
+
+    fn decayed(&self, usage: &Usage, now: u64) -> f64 {
+        let elapsed = now.saturating_sub(usage.updated_at) as f64;
+        usage.score * 0.5f64.powf(elapsed / self.half_life.as_secs_f64().max(1.0))
+    }
+
+    /// Fail if `identity` has added too much lately to add anything more.
+    pub fn check(&self, identity: &str) -> Result<(), ApiError> {
+        self.check_at(identity, now())
+    }
+
+    fn check_at(&self, identity: &str, now: u64) -> Result<(), ApiError> {
+        let table = self.usage.lock().unwrap();
+        let score = table
+            .get(identity)
+            .map_or(0.0, |usage| self.decayed(usage, now));
+        // One more fits once the usage has decayed to this.
+        let room_at = self.limit.map(|limit| (limit - 1.0).max(FORGET_BELOW));
+        if let Some(room_at) = room_at
+            && score > room_at
+        {
+            let wait = self.half_life.as_secs_f64() * (score / room_at).log2();
+            return Err(ApiError::PolicyViolation(format!(
+                "You have added too much lately, try again in {} minutes",
+                (wait / 60.0).ceil().max(1.0)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Count `items` added by `identity`. They are already added, so this never fails.
+    pub fn charge(&self, identity: &str, items: usize) {
+        self.charge_at(identity, items, now());
+    }
+
+    fn charge_at(&self, identity: &str, items: usize, now: u64) {
+        let mut table = self.usage.lock().unwrap();
+        table.retain(|_, usage| self.decayed(usage, now) >= FORGET_BELOW);
+
+        let usage = table.get(identity).copied();
+        table.insert(
+            identity.to_string(),
+            Usage {
+                score: usage.map_or(0.0, |usage| self.decayed(&usage, now)) + items as f64,
+                updated_at: now,
+                added: usage.map_or(0, |usage| usage.added) + items as u64,
+            },
+        );
+    }
+
+    /// Something that counts the items added by `identity` as they are added.
+    pub fn charge_to(&self, identity: &str) -> QuotaCharge {
+        QuotaCharge {
+            quotas: self.clone(),
+            identity: identity.to_string(),
+        }
+    }
+
+    /// Everyone who has added something lately, most usage first.
+    pub fn list(&self) -> Vec<QuotaEntry> {
+        self.list_at(now())
+    }
+
+    fn list_at(&self, now: u64) -> Vec<QuotaEntry> {
+        let table = self.usage.lock().unwrap();
+        let mut entries: Vec<_> = table
+            .iter()
+            .map(|(identity, usage)| QuotaEntry {
+                identity: identity.clone(),
+                usage: self.decayed(usage, now),
+                added: usage.added,
+                last_added_at: usage.updated_at,
+            })
+            .filter(|entry| entry.usage >= FORGET_BELOW)
+            .collect();
+        entries.sort_by(|a, b| b.usage.total_cmp(&a.usage));
+        entries
+    }
+
+    pub fn limit(&self) -> Option<f64> {
+        self.limit
+    }
+}
+
+/// Counts the items a client adds towards their quota, once they are in the playlist.
+#[derive(Debug, Clone)]
+pub struct QuotaCharge {
+    quotas: Quotas,
+    identity: String,
+}
+
+impl QuotaCharge {
+    /// Count `items` that were just added.
+    pub fn charge(&self, items: usize) {
+        if items > 0 {
+            self.quotas.charge(&self.identity, items);
+        }
+    }
+
+    /// Who the added items take turns as, if the items of clients are interleaved.
+    pub fn fair_identity(&self) -> Option<&str> {
+        self.quotas.fair.then_some(self.identity.as_str())
+    }
+}
+
+impl PartialEq for QuotaCharge {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.quotas.usage, &other.quotas.usage) && self.identity == other.identity
+    }
+}
+
+/// The charge attached by [`reject_by_policy`](crate::api::reject_by_policy) to requests
+/// that add items, for handlers that take it as an extractor.
+pub type Charged = Option<Extension<QuotaCharge>>;
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_quotas() {
+        let quotas = Quotas::new(Some(2), Duration::from_secs(600));
+        assert!(quotas.check_at("anon:a", 1000).is_ok());
+        quotas.charge_at("anon:a", 1, 1000);
+        assert!(quotas.check_at("anon:a", 1000).is_ok());
+        // A playlist counts for every item in it.
+        quotas.charge_at("anon:a", 3, 1000);
+        assert!(quotas.check_at("anon:a", 1000).is_err());
+        assert!(quotas.check_at("anon:b", 1000).is_ok());
+
+        // After two half-lives, there is room for one more.
+        assert!(quotas.check_at("anon:a", 2200).is_ok());
+        quotas.charge_at("anon:a", 1, 2200);
+        assert!(quotas.check_at("anon:a", 2200).is_err());
+        let entries = quotas.list_at(2200);
+        assert_eq!(entries[0].identity, "anon:a");
+        assert_eq!(entries[0].usage, 2.0);
+        assert_eq!(entries[0].added, 5);
+
+        // Long after, everyone is forgotten.
+        assert!(quotas.list_at(1000 + 600 * 20).is_empty());
+
+        let addr = ClientAddr::Tcp("10.0.0.1:1234".parse().unwrap());
+        let mut firefox = HeaderMap::new();
+        firefox.insert(header::USER_AGENT, HeaderValue::from_static("Firefox"));
+        let anonymous = coarse_identity(&addr, &firefox, None);
+        assert!(anonymous.starts_with("anon:"));
+        assert_ne!(anonymous, coarse_identity(&addr, &HeaderMap::new(), None));
+        let other_port = ClientAddr::Tcp("10.0.0.1:4321".parse().unwrap());
+        assert_eq!(anonymous, coarse_identity(&other_port, &firefox, None));
+    }
+}
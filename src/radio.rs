@@ -0,0 +1,347 @@
+//! Web radio: named stations that can be tuned into, and the title of what they play.
+//!
+//! Radio streams never end and have no duration. While any live stream is playing, its
+//! metadata and ICY tags are polled for the song that is on, and every change is announced
+//! to every mpv client as a `script-message`, which is how it reaches the websocket and
+//! event stream clients.
+
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use futures::StreamExt;
+use mpvipc_async::{Event, Mpv};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use url::Url;
+
+use crate::{
+    api::ApiError,
+    oidc::AuthenticatedUser,
+    player::{self, ItemState, PlayerHandle},
+};
+
+/// The `script-message` sent when a live stream starts or its metadata changes, followed
+/// by the [`NowPlaying`] as JSON.
+pub const STREAM_METADATA_MESSAGE: &str = "greg-stream-metadata";
+
+/// How often the metadata of a playing stream is checked.
+const METADATA_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Station {
+    pub name: String,
+    pub url: Url,
+}
+
+impl FromStr for Station {
+    type Err = String;
+
+    /// Parses `<name>=<url>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, url) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <name>=<url>, got '{}'", s))?;
+
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "invalid station name '{}', only letters, digits, '-' and '_' are allowed",
+                name
+            ));
+        }
+
+        let url = Url::parse(url).map_err(|e| format!("invalid URL '{}': {}", url, e))?;
+        Ok(Station {
+            name: name.to_string(),
+            url,
+        })
+    }
+}
+
+/// What a live stream says about itself, from its ICY headers and metadata.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct StreamMetadata {
+    /// The song that is playing.
+    pub title: Option<String>,
+    /// The name the stream gives itself.
+    pub name: Option<String>,
+    pub genre: Option<String>,
+    /// The homepage of the station.
+    pub homepage: Option<String>,
+}
+
+/// The stream that is playing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct NowPlaying {
+    pub path: String,
+    /// The name of the station, if the stream is one of the presets.
+    pub station: Option<String>,
+    #[serde(flatten)]
+    pub metadata: StreamMetadata,
+}
+
+/// The configured stations, and what is playing on the radio right now.
+#[derive(Debug, Clone, Default)]
+pub struct Radio {
+    stations: Arc<BTreeMap<String, Station>>,
+    now_playing: Arc<Mutex<Option<NowPlaying>>>,
+}
+
+impl Radio {
+    pub fn new(stations: Vec<Station>) -> anyhow::Result<Self> {
+        let mut by_name = BTreeMap::new();
+        for station in stations {
+            let name = station.name.clone();
+            if by_name.insert(name.clone(), station).is_some() {
+                anyhow::bail!("The radio station '{}' is configured more than once", name);
+            }
+        }
+
+        Ok(Self {
+            stations: Arc::new(by_name),
+            ..Default::default()
+        })
+    }
+
+    /// Every station, ordered by name.
+    pub fn stations(&self) -> Vec<Station> {
+        self.stations.values().cloned().collect()
+    }
+
+    /// The name of the station streaming from `path`, if any.
+    fn station_at(&self, path: &str) -> Option<String> {
+        self.stations
+            .values()
+            .find(|station| station.url.as_str() == path)
+            .map(|station| station.name.clone())
+    }
+
+    pub fn now_playing(&self) -> Option<NowPlaying> {
+        self.now_playing.lock().unwrap().clone()
+    }
+
+    fn set_now_playing(&self, now_playing: Option<NowPlaying>) {
+        *self.now_playing.lock().unwrap() = now_playing;
+    }
+
+    /// Add the station to the end of the playlist, and switch to it right away.
+    pub async fn play(
+        &self,
+        player: &PlayerHandle,
+        name: &str,
+        user: Option<&AuthenticatedUser>,
+    ) -> anyhow::Result<()> {
+        let station = self
+            .stations
+            .get(name)
+            .ok_or_else(|| ApiError::NotFound(format!("No radio station named '{}'", name)))?;
+
+        log::info!("Tuning into '{}'", name);
+        let _lock = player::lock_playlist(player.queue_state()).await;
+        let playlist = player.playlist().await?;
+        player
+            .load(station.url.as_str(), ItemState::credited_to(user))
+            .await?;
+        let index = playlist.len();
+        player.playlist_goto(index).await?;
+        player.set_playing(true).await
+    }
+}
+
+/// Whether the item that has just been loaded is an endless stream, which mpv
+/// knows no duration for.
+pub async fn is_live(mpv: &Mpv) -> bool {
+    let duration: Option<f64> = mpv.get_property("duration").await.unwrap_or(None);
+    duration.is_none()
+}
+
+/// The stream announced with [`STREAM_METADATA_MESSAGE`].
+pub fn parse_metadata_message(args: &[String]) -> Option<NowPlaying> {
+    match args {
+        [message, now_playing] if message == STREAM_METADATA_MESSAGE => {
+            serde_json::from_str(now_playing).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Pick out what is worth showing from the `metadata` property of a stream.
+///
+/// The song comes from the ICY title, or from regular tags for streams that use those,
+/// like HLS radio. The media title is only used when it is more than the file name.
+fn parse_metadata(
+    metadata: &Map<String, Value>,
+    media_title: Option<String>,
+    path: &str,
+) -> StreamMetadata {
+    let tag = |key: &str| {
+        metadata
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .and_then(|(_, value)| value.as_str())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+
+    let tagged_title = match (tag("artist"), tag("title")) {
+        (Some(artist), Some(title)) => Some(format!("{} - {}", artist, title)),
+        (None, title) => title,
+        (Some(_), None) => None,
+    };
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let media_title = media_title.filter(|title| title != file_name && title != path);
+
+    StreamMetadata {
+        title: tag("icy-title").or(tagged_title).or(media_title),
+        name: tag("icy-name"),
+        genre: tag("icy-genre"),
+        homepage: tag("icy-url"),
+    }
+}
+
+async fn stream_metadata(mpv: &Mpv, path: &str) -> StreamMetadata {
+    let metadata = match mpv.get_property_value("metadata").await {
+        Ok(Some(Value::Object(metadata))) => metadata,
+        _ => Map::new(),
+    };
+    let media_title: Option<String> = mpv.get_property("media-title").await.unwrap_or(None);
+    parse_metadata(&metadata, media_title, path)
+}
+
+async fn announce(mpv: &Mpv, now_playing: &NowPlaying) -> anyhow::Result<()> {
+    let now_playing = serde_json::to_string(now_playing)?;
+    mpv.run_command_raw("script-message", &[STREAM_METADATA_MESSAGE, &now_playing])
+        .await?;
+    Ok(())
+}
+
+async fn update(mpv: &Mpv, radio: &Radio, now_playing: NowPlaying) {
+    if radio.now_playing().as_ref() == Some(&now_playing) {
+        return;
+    }
+    log::debug!("Stream metadata changed to {:?}", now_playing.metadata);
+    if let Err(e) = announce(mpv, &now_playing).await {
+        log::warn!("Failed to announce the stream metadata: {:#}", e);
+    }
+    radio.set_now_playing(Some(now_playing));
+}
+
+/// Keep track of the live stream that is playing and what it says it is playing,
+/// until mpv goes away.
+pub async fn run_radio(mpv: Mpv, radio: Radio) -> anyhow::Result<()> {
+    let mut event_stream = mpv.get_event_stream().await;
+    let mut interval = tokio::time::interval(METADATA_POLL_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            event = event_stream.next() => match event.context("Lost the mpv events")?? {
+                Event::FileLoaded => {
+                    let path: Option<String> = mpv.get_property("path").await.unwrap_or(None);
+                    let Some(path) = path else {
+                        continue;
+                    };
+                    if !is_live(&mpv).await {
+                        continue;
+                    }
+
+                    let now_playing = NowPlaying {
+                        station: radio.station_at(&path),
+                        metadata: stream_metadata(&mpv, &path).await,
+                        path,
+                    };
+                    update(&mpv, &radio, now_playing).await;
+                }
+                Event::EndFile { .. } => radio.set_now_playing(None),
+                Event::Shutdown => return Ok(()),
+                _ => {}
+            },
+
+            _ = interval.tick(), if radio.now_playing().is_some() => {
+                let Some(mut now_playing) = radio.now_playing() else {
+                    continue;
+                };
+                now_playing.metadata = stream_metadata(&mpv, &now_playing.path).await;
+                update(&mpv, &radio, now_playing).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_stations() {
+        let stations: Vec<Station> = [
+            "nrk=https://lyd.nrk.no/nrk_radio_p3_mp3_h",
+            "jazz=http://jazz.example.org/live",
+        ]
+        .into_iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+        let radio = Radio::new(stations.clone()).unwrap();
+        assert_eq!(radio.stations()[0].name, "jazz");
+        assert_eq!(
+            radio
+                .station_at("https://lyd.nrk.no/nrk_radio_p3_mp3_h")
+                .as_deref(),
+            Some("nrk")
+        );
+
+        assert!(Radio::new(vec![stations[0].clone(), stations[0].clone()]).is_err());
+        assert!("p3 radio=http://example.org".parse::<Station>().is_err());
+        assert!("p3=not a url".parse::<Station>().is_err());
+
+        let metadata = json!({
+            "icy-name": "NRK P3",
+            "icy-genre": "",
+            "icy-title": "Band - Song",
+        });
+        let metadata = parse_metadata(
+            metadata.as_object().unwrap(),
+            Some("nrk_radio_p3_mp3_h".to_string()),
+            "https://lyd.nrk.no/nrk_radio_p3_mp3_h",
+        );
+        assert_eq!(metadata.title.as_deref(), Some("Band - Song"));
+        assert_eq!(metadata.name.as_deref(), Some("NRK P3"));
+        assert_eq!(metadata.genre, None);
+
+        let tagged = json!({ "ARTIST": "Band", "TITLE": "Other Song" });
+        let metadata = parse_metadata(
+            tagged.as_object().unwrap(),
+            None,
+            "https://example.org/live.m3u8",
+        );
+        assert_eq!(metadata.title.as_deref(), Some("Band - Other Song"));
+        let untagged = parse_metadata(
+            &Map::new(),
+            Some("live.m3u8".to_string()),
+            "https://example.org/live.m3u8",
+        );
+        assert_eq!(untagged.title, None);
+
+        let now_playing = NowPlaying {
+            path: "https://lyd.nrk.no/nrk_radio_p3_mp3_h".to_string(),
+            station: Some("nrk".to_string()),
+            metadata,
+        };
+        let args = [
+            STREAM_METADATA_MESSAGE.to_string(),
+            serde_json::to_string(&now_playing).unwrap(),
+        ];
+        assert_eq!(parse_metadata_message(&args), Some(now_playing));
+    }
+}
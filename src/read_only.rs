@@ -0,0 +1,53 @@
+//! A switch for keeping the player as it is, like during a presentation where the queue
+//! must not change. Everything can still be watched while it is on.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use crate::api::ApiError;
+
+#[derive(Debug, Clone, Default)]
+pub struct ReadOnlyMode {
+    enabled: Arc<AtomicBool>,
+    /// Whether read-only mode is one of the `--policy` policies. If not, turning it on
+    /// changes nothing.
+    applied: bool,
+}
+
+impl ReadOnlyMode {
+    pub fn new(applied: bool) -> Self {
+        Self {
+            enabled: Arc::default(),
+            applied,
+        }
+    }
+
+    pub fn is_applied(&self) -> bool {
+        self.applied
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        log::info!(
+            "Read-only mode {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Fails with the error shown to clients trying to change something while it is on.
+    pub fn check(&self) -> Result<(), ApiError> {
+        if self.is_enabled() {
+            return Err(ApiError::PolicyViolation(
+                "The player is read-only right now, nothing can be changed until an admin turns it off"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
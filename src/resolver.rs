@@ -0,0 +1,66 @@
+//! Resolvers for urls that can not be played as they are.
+//!
+//! Every url that is loaded goes through a [`ResolverChain`], where the first
+//! [`UrlResolver`] that claims the url turns it into the items to add instead.
+//! Urls that no resolver claims are added unchanged.
+
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+use url::Url;
+
+use crate::util::canonicalize_url;
+
+mod spotify;
+
+pub use spotify::SpotifyResolver;
+
+/// A handler for urls from a service that mpv can not play directly.
+#[async_trait]
+pub trait UrlResolver: fmt::Debug + Send + Sync {
+    /// The name of the service, for logging.
+    fn name(&self) -> &'static str;
+
+    /// Whether this resolver handles the url.
+    fn claims(&self, url: &Url) -> bool;
+
+    /// The urls to add to the playlist in place of `url`, in order.
+    async fn resolve(&self, url: &Url) -> anyhow::Result<Vec<String>>;
+}
+
+/// The resolvers that loaded urls are passed through, tried in order.
+#[derive(Debug, Clone, Default)]
+pub struct ResolverChain {
+    resolvers: Arc<Vec<Box<dyn UrlResolver>>>,
+}
+
+impl ResolverChain {
+    pub fn new(resolvers: Vec<Box<dyn UrlResolver>>) -> Self {
+        Self {
+            resolvers: Arc::new(resolvers),
+        }
+    }
+
+    /// The urls to add to the playlist for `input`, which is canonicalized first.
+    pub async fn resolve(&self, input: &str) -> anyhow::Result<Vec<String>> {
+        let canonical = canonicalize_url(input);
+        let Ok(url) = Url::parse(&canonical) else {
+            return Ok(vec![canonical]);
+        };
+        let Some(resolver) = self.resolvers.iter().find(|resolver| resolver.claims(&url)) else {
+            return Ok(vec![canonical]);
+        };
+
+        let urls = resolver.resolve(&url).await?;
+        log::debug!(
+            "Resolved {} url {} into {} items",
+            resolver.name(),
+            url,
+            urls.len()
+        );
+        if urls.is_empty() {
+            anyhow::bail!("Found nothing to play for {}", url);
+        }
+        Ok(urls)
+    }
+}
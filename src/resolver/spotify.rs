@@ -0,0 +1,198 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use serde::Deserialize;
+use url::Url;
+
+use super::UrlResolver;
+
+/// How long Spotify gets to answer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the track listing is embedded in the Spotify embed pages.
+const NEXT_DATA_START: &str = r#"<script id="__NEXT_DATA__" type="application/json">"#;
+
+#[derive(Debug, Deserialize)]
+struct NextData {
+    props: NextProps,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NextProps {
+    page_props: PageProps,
+}
+
+#[derive(Debug, Deserialize)]
+struct PageProps {
+    state: EmbedState,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbedState {
+    data: EmbedData,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbedData {
+    entity: Entity,
+}
+
+/// A track, or an album or playlist with a `track_list`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entity {
+    name: String,
+    #[serde(default)]
+    artists: Vec<Artist>,
+    #[serde(default)]
+    track_list: Vec<ListedTrack>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Artist {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListedTrack {
+    title: String,
+    /// The artists, comma separated.
+    subtitle: String,
+}
+
+/// A YouTube search for the track, which is resolved by yt-dlp when it is played.
+fn youtube_search(artists: &str, title: &str) -> String {
+    format!("ytdl://ytsearch1:{} - {}", artists, title)
+}
+
+/// The kind and id of a Spotify track, album or playlist url, like
+/// `https://open.spotify.com/intl-de/track/<id>`.
+fn spotify_item(url: &Url) -> Option<(&str, &str)> {
+    if url.host_str() != Some("open.spotify.com") {
+        return None;
+    }
+    let mut segments = url
+        .path_segments()?
+        .filter(|segment| !segment.is_empty() && !segment.starts_with("intl-"));
+    match (segments.next()?, segments.next()?) {
+        (kind @ ("track" | "album" | "playlist"), id) => Some((kind, id)),
+        _ => None,
+    }
+}
+
+/// Turn a Spotify embed page into YouTube searches for its tracks.
+fn parse_embed_page(html: &str) -> anyhow::Result<Vec<String>> {
+    let (_, data) = html
+        .split_once(NEXT_DATA_START)
+        .context("The Spotify page has no track data")?;
+    let (data, _) = data
+        .split_once("</script>")
+        .context("The Spotify page has no track data")?;
+    let data: NextData =
+        serde_json::from_str(data).context("Failed to parse the Spotify track data")?;
+    let entity = data.props.page_props.state.data.entity;
+
+    if entity.track_list.is_empty() {
+        let artists: Vec<&str> = entity.artists.iter().map(|a| a.name.as_str()).collect();
+        return Ok(vec![youtube_search(&artists.join(", "), &entity.name)]);
+    }
+    Ok(entity
+        .track_list
+        .iter()
+        .map(|track| youtube_search(&track.subtitle, &track.title))
+        .collect())
+}
+
+/// Plays Spotify tracks, albums and playlists by searching YouTube for each track,
+/// since Spotify itself can not be streamed.
+#[derive(Debug, Clone)]
+pub struct SpotifyResolver {
+    client: reqwest::Client,
+}
+
+impl Default for SpotifyResolver {
+    fn default() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("Failed to build HTTP client"),
+        }
+    }
+}
+
+#[async_trait]
+impl UrlResolver for SpotifyResolver {
+    fn name(&self) -> &'static str {
+        "Spotify"
+    }
+
+    fn claims(&self, url: &Url) -> bool {
+        spotify_item(url).is_some()
+    }
+
+    async fn resolve(&self, url: &Url) -> anyhow::Result<Vec<String>> {
+        let (kind, id) = spotify_item(url).context("Not a Spotify track, album or playlist")?;
+        let embed = format!("https://open.spotify.com/embed/{}/{}", kind, id);
+
+        let body = self
+            .client
+            .get(embed)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        parse_embed_page(&String::from_utf8_lossy(&body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_embed_page() {
+        let url =
+            Url::parse("https://open.spotify.com/intl-de/track/4cOdK2wGLETKBW3PvgPWqT?si=abc")
+                .unwrap();
+        assert_eq!(
+            spotify_item(&url),
+            Some(("track", "4cOdK2wGLETKBW3PvgPWqT"))
+        );
+        let url = Url::parse("https://open.spotify.com/artist/0gxyHStUsqpMadRV0Di1Qt").unwrap();
+        assert_eq!(spotify_item(&url), None);
+
+        let track = r#"<html><script id="__NEXT_DATA__" type="application/json">
+            {"props": {"pageProps": {"state": {"data": {"entity": {
+                "type": "track",
+                "name": "Never Gonna Give You Up",
+                "artists": [{"name": "Rick Astley", "uri": "spotify:artist:0gxyHStUsqpMadRV0Di1Qt"}]
+            }}}}}}
+            </script></html>"#;
+        assert_eq!(
+            parse_embed_page(track).unwrap(),
+            vec!["ytdl://ytsearch1:Rick Astley - Never Gonna Give You Up"]
+        );
+
+        let playlist = r#"<script id="__NEXT_DATA__" type="application/json">
+            {"props": {"pageProps": {"state": {"data": {"entity": {
+                "type": "playlist",
+                "name": "Friday",
+                "trackList": [
+                    {"title": "Song A", "subtitle": "Band", "duration": 212000},
+                    {"title": "Song B", "subtitle": "Band, Singer", "duration": 180000}
+                ]
+            }}}}}}
+            </script>"#;
+        assert_eq!(
+            parse_embed_page(playlist).unwrap(),
+            vec![
+                "ytdl://ytsearch1:Band - Song A",
+                "ytdl://ytsearch1:Band, Singer - Song B"
+            ]
+        );
+    }
+}
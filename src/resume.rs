@@ -0,0 +1,203 @@
+//! Remembers how far into long items, like movies, playback got, so that they can be
+//! resumed from there when they are queued again.
+
+use std::{sync::Arc, time::Duration};
+
+use futures::StreamExt;
+use mpvipc_async::{EndFileReason, Event, Mpv, MpvExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    history::now,
+    player::{self, EntryId, MpvPlayer, PlayerHandle, QueueState},
+    storage::StorageHandle,
+};
+
+/// How often the position of the current item is saved.
+const SAVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Positions this close to the end count as finished, so that an item that was stopped
+/// during the credits starts over next time.
+const END_MARGIN: f64 = 60.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedPosition {
+    pub path: String,
+    /// How far into the item playback got, in seconds.
+    pub position: f64,
+    /// Seconds since the unix epoch.
+    pub saved_at: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResumePositions {
+    storage: StorageHandle,
+    /// Only items at least this many seconds long have their position saved.
+    min_duration: f64,
+    /// Positions saved longer ago than this are not resumed from.
+    max_age: Duration,
+}
+
+impl ResumePositions {
+    pub fn new(storage: StorageHandle, min_duration: Duration, max_age: Duration) -> Self {
+        Self {
+            storage,
+            min_duration: min_duration.as_secs_f64(),
+            max_age,
+        }
+    }
+
+    /// Where `path` can be resumed from, if it was played recently enough.
+    pub fn position(&self, path: &str) -> anyhow::Result<Option<SavedPosition>> {
+        let cutoff = now().saturating_sub(self.max_age.as_secs());
+        Ok(self
+            .storage
+            .saved_position(path)?
+            .filter(|position| position.saved_at >= cutoff))
+    }
+
+    /// Returns whether a position was saved for `path`.
+    pub fn forget(&self, path: &str) -> anyhow::Result<bool> {
+        self.storage.forget_position(path)
+    }
+
+    /// Save how far into `path` playback is, or forget it if the item is short or nearly over.
+    fn update(&self, path: &str, position: f64, duration: f64) -> anyhow::Result<()> {
+        if duration < self.min_duration {
+            return Ok(());
+        }
+        if position >= duration - END_MARGIN {
+            self.forget(path)?;
+            return Ok(());
+        }
+        self.storage.save_position(&SavedPosition {
+            path: path.to_string(),
+            position,
+            saved_at: now(),
+        })
+    }
+}
+
+async fn save_position(mpv: &Mpv, positions: &ResumePositions) -> anyhow::Result<()> {
+    if !MpvExt::is_playing(mpv).await? {
+        return Ok(());
+    }
+    let path: Option<String> = mpv.get_property("path").await?;
+    let position: Option<f64> = mpv.get_property("time-pos").await?;
+    let duration: Option<f64> = mpv.get_property("duration").await?;
+    if let (Some(path), Some(position), Some(duration)) = (path, position, duration) {
+        positions.update(&path, position, duration)?;
+    }
+    Ok(())
+}
+
+/// Seek to the saved position of the item `id` that just loaded, if it was queued with
+/// `resume`.
+async fn resume(
+    mpv: &Mpv,
+    queue: &QueueState,
+    id: EntryId,
+    positions: &ResumePositions,
+) -> anyhow::Result<()> {
+    if !queue.get(id).resume {
+        return Ok(());
+    }
+    queue.update(id, |item| item.resume = false);
+    let Some(path) = mpv.get_property::<String>("path").await? else {
+        return Ok(());
+    };
+    let Some(saved) = positions.position(&path)? else {
+        return Ok(());
+    };
+
+    log::info!("Resuming {} from {:.0} seconds", path, saved.position);
+    let player: PlayerHandle = Arc::new(MpvPlayer::new(mpv.clone()));
+    tokio::spawn(async move {
+        if let Err(e) = player::seek_when_loaded(&player, saved.position).await {
+            log::warn!("Failed to resume {}: {:#}", saved.path, e);
+        }
+    });
+    Ok(())
+}
+
+/// Keep saving the position of long items while they play, and resume those queued with
+/// `resume` when they start.
+pub async fn record_positions(mpv: Mpv, queue: QueueState, positions: ResumePositions) {
+    let mut event_stream = mpv.get_event_stream().await;
+    let mut interval = tokio::time::interval(SAVE_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut started: Option<EntryId> = None;
+    let mut current: Option<String> = None;
+
+    loop {
+        tokio::select! {
+            event = event_stream.next() => match event {
+                Some(Ok(Event::StartFile { playlist_entry_id })) => {
+                    started = Some(EntryId::Player(playlist_entry_id));
+                }
+                Some(Ok(Event::FileLoaded)) => {
+                    current = mpv.get_property("path").await.unwrap_or(None);
+                    if let Some(id) = started
+                        && let Err(e) = resume(&mpv, &queue, id, &positions).await
+                    {
+                        log::warn!("Failed to look up where to resume from: {:#}", e);
+                    }
+                }
+                Some(Ok(Event::EndFile {
+                    reason: EndFileReason::Eof,
+                    ..
+                })) => {
+                    if let Some(path) = current.take()
+                        && let Err(e) = positions.forget(&path)
+                    {
+                        log::warn!("Failed to forget the position of {}: {:#}", path, e);
+                    }
+                }
+                Some(Ok(Event::Shutdown)) | None => break,
+                _ => {}
+            },
+            _ = interval.tick() => {
+                if let Err(e) = save_position(&mpv, &positions).await {
+                    log::debug!("Failed to save the playback position: {:#}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::MemoryStorage;
+
+    use super::*;
+
+    #[test]
+    fn test_resume_positions() {
+        let positions = ResumePositions::new(
+            Arc::new(MemoryStorage::new()),
+            Duration::from_secs(20 * 60),
+            Duration::from_secs(24 * 60 * 60),
+        );
+
+        // Short items are not remembered.
+        positions.update("song.mp3", 60.0, 200.0).unwrap();
+        assert_eq!(positions.position("song.mp3").unwrap(), None);
+
+        positions.update("movie.mkv", 600.0, 7200.0).unwrap();
+        let saved = positions.position("movie.mkv").unwrap().unwrap();
+        assert_eq!(saved.position, 600.0);
+        positions.update("movie.mkv", 7190.0, 7200.0).unwrap();
+        assert_eq!(positions.position("movie.mkv").unwrap(), None);
+
+        // Old positions are not resumed from.
+        positions
+            .storage
+            .save_position(&SavedPosition {
+                path: "old.mkv".to_string(),
+                position: 600.0,
+                saved_at: 1,
+            })
+            .unwrap();
+        assert_eq!(positions.position("old.mkv").unwrap(), None);
+    }
+}
@@ -0,0 +1,177 @@
+//! What a token can be used for. API keys carry a set of scopes, and each command needs the
+//! scope for what it does, checked by the `scopes` policy.
+//!
+//! Requests without a token, and people who logged in, can do [`Scope::EVERYONE`], which
+//! leaves out adding items with high priority. Admins, and tokens with `admin:*`, can do
+//! everything.
+
+use std::{fmt, str::FromStr};
+
+use axum::http::Method;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Scope {
+    /// Adding items to the playlist.
+    #[serde(rename = "queue:add")]
+    QueueAdd,
+    /// Adding items with high priority, ahead of the ones added without.
+    #[serde(rename = "queue:priority")]
+    QueuePriority,
+    /// Removing items from the playlist, and clearing it.
+    #[serde(rename = "queue:remove")]
+    QueueRemove,
+    /// Everything else that changes something, like pausing, seeking, skipping, moving
+    /// items and the volume.
+    #[serde(rename = "playback:control")]
+    PlaybackControl,
+    /// Everything, including the admin endpoints.
+    #[serde(rename = "admin:*")]
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 5] = [
+        Scope::QueueAdd,
+        Scope::QueuePriority,
+        Scope::QueueRemove,
+        Scope::PlaybackControl,
+        Scope::Admin,
+    ];
+
+    /// What anyone can do without a token.
+    pub const EVERYONE: [Scope; 3] = [Scope::QueueAdd, Scope::QueueRemove, Scope::PlaybackControl];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::QueueAdd => "queue:add",
+            Scope::QueuePriority => "queue:priority",
+            Scope::QueueRemove => "queue:remove",
+            Scope::PlaybackControl => "playback:control",
+            Scope::Admin => "admin:*",
+        }
+    }
+
+    /// The scope a REST request needs, or `None` for requests that change nothing, and for
+    /// the endpoints that check tokens of their own.
+    pub fn needed_for(method: &Method, path: &str) -> Option<Scope> {
+        if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+            return None;
+        }
+        let path = path
+            .strip_prefix("/api/v2")
+            .or_else(|| path.strip_prefix("/api"))
+            .unwrap_or(path);
+        let own_tokens = ["/admin/", "/session/", "/control/", "/auth/"];
+        // The load check and the bulk playlist operations ask the policies themselves.
+        let checks_itself = ["/load/check", "/playlist/ops"];
+        if own_tokens.iter().any(|prefix| path.starts_with(prefix)) || checks_itself.contains(&path)
+        {
+            return None;
+        }
+
+        let plays_saved = ["/playlists/", "/remote/", "/radio/", "/bookmarks/"]
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+            && (path.ends_with("/load") || path.ends_with("/play"));
+        Some(match (method, path) {
+            (&Method::DELETE, "/playlist" | "/playlist/item" | "/playlist/deferred") => {
+                Scope::QueueRemove
+            }
+            (&Method::DELETE, path) if path.starts_with("/playlist/blocks/") => Scope::QueueRemove,
+            (_, "/load" | "/interject" | "/upload") => Scope::QueueAdd,
+            _ if plays_saved => Scope::QueueAdd,
+            _ => Scope::PlaybackControl,
+        })
+    }
+
+    /// Whether `scopes` let through a command that needs `self`.
+    pub fn granted_by(self, scopes: &[Scope]) -> bool {
+        scopes.contains(&self) || scopes.contains(&Scope::Admin)
+    }
+
+    /// Everything `scopes` let through, with `admin:*` spelled out.
+    pub fn effective(scopes: &[Scope]) -> Vec<Scope> {
+        Scope::ALL
+            .into_iter()
+            .filter(|scope| scope.granted_by(scopes))
+            .collect()
+    }
+
+    /// Parse a list like `queue:add,playback:control`.
+    pub fn parse_list(scopes: &str) -> Result<Vec<Scope>, String> {
+        let mut parsed: Vec<Scope> = scopes
+            .split(',')
+            .map(str::trim)
+            .filter(|scope| !scope.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        parsed.sort();
+        parsed.dedup();
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .into_iter()
+            .find(|known| known.as_str() == scope)
+            .ok_or_else(|| {
+                let known: Vec<_> = Scope::ALL.iter().map(|scope| scope.as_str()).collect();
+                format!(
+                    "Unknown scope '{}', expected one of {}",
+                    scope,
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_needed_for() {
+        let needed = |method: Method, path: &str| Scope::needed_for(&method, path);
+        assert_eq!(needed(Method::GET, "/api/v2/playlist"), None);
+        assert_eq!(needed(Method::POST, "/api/load"), Some(Scope::QueueAdd));
+        assert_eq!(
+            needed(Method::POST, "/api/playlists/party/load"),
+            Some(Scope::QueueAdd)
+        );
+        assert_eq!(
+            needed(Method::DELETE, "/api/v2/playlist/item"),
+            Some(Scope::QueueRemove)
+        );
+        assert_eq!(
+            needed(Method::POST, "/api/v2/play"),
+            Some(Scope::PlaybackControl)
+        );
+        assert_eq!(
+            needed(Method::POST, "/api/v2/playlist/move"),
+            Some(Scope::PlaybackControl)
+        );
+        assert_eq!(needed(Method::POST, "/api/admin/readonly"), None);
+        assert_eq!(needed(Method::POST, "/api/load/check"), None);
+        assert_eq!(needed(Method::POST, "/api/playlist/ops"), None);
+
+        assert_eq!(
+            Scope::parse_list("playback:control, queue:add,queue:add"),
+            Ok(vec![Scope::QueueAdd, Scope::PlaybackControl])
+        );
+        assert!(Scope::parse_list("queue:everything").is_err());
+        assert!(Scope::QueueRemove.granted_by(&[Scope::Admin]));
+        assert!(!Scope::QueueRemove.granted_by(&[Scope::QueueAdd]));
+        assert_eq!(Scope::effective(&[Scope::Admin]), Scope::ALL);
+    }
+}
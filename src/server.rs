@@ -0,0 +1,370 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    os::{
+        fd::{FromRawFd, OwnedFd},
+        unix::fs::FileTypeExt,
+    },
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::Context;
+use axum::{Router, extract::connect_info::Connected, serve::IncomingStream};
+use axum_server::tls_rustls::RustlsConfig;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, UnixListener};
+use tower_http::compression::CompressionLayer;
+
+use crate::resolve;
+
+/// Where to listen for API connections, as given to `--listen`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddr {
+    /// `<host>:<port>`
+    Tcp { host: String, port: u16 },
+
+    /// `unix:<path>`
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("missing path after 'unix:'".to_string());
+            }
+            return Ok(ListenAddr::Unix(PathBuf::from(path)));
+        }
+
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected '<host>:<port>' or 'unix:<path>', got '{}'", s))?;
+        let port = port
+            .parse()
+            .map_err(|e| format!("invalid port '{}': {}", port, e))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        Ok(ListenAddr::Tcp {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+/// The address of a connected API client.
+///
+/// Clients connecting over a unix socket usually don't have an address,
+/// so there is nothing more to say about them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClientAddr {
+    Tcp(SocketAddr),
+    Unix,
+}
+
+impl fmt::Display for ClientAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientAddr::Tcp(addr) => write!(f, "{}", addr),
+            ClientAddr::Unix => write!(f, "unix socket client"),
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for ClientAddr {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        ClientAddr::Tcp(*stream.remote_addr())
+    }
+}
+
+impl Connected<IncomingStream<'_, UnixListener>> for ClientAddr {
+    fn connect_info(_stream: IncomingStream<'_, UnixListener>) -> Self {
+        ClientAddr::Unix
+    }
+}
+
+// NOTE: axum_server hands over the plain peer address instead of an `IncomingStream`.
+impl Connected<SocketAddr> for ClientAddr {
+    fn connect_info(addr: SocketAddr) -> Self {
+        ClientAddr::Tcp(addr)
+    }
+}
+
+impl Connected<std::os::unix::net::SocketAddr> for ClientAddr {
+    fn connect_info(_addr: std::os::unix::net::SocketAddr) -> Self {
+        ClientAddr::Unix
+    }
+}
+
+/// A bound socket the API is served on.
+pub enum ApiListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl fmt::Display for ApiListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiListener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{}", addr),
+                Err(_) => write!(f, "<unknown tcp address>"),
+            },
+            ApiListener::Unix(listener) => {
+                match listener
+                    .local_addr()
+                    .ok()
+                    .and_then(|addr| addr.as_pathname().map(|path| path.display().to_string()))
+                {
+                    Some(path) => write!(f, "unix:{}", path),
+                    None => write!(f, "<unnamed unix socket>"),
+                }
+            }
+        }
+    }
+}
+
+impl ApiListener {
+    /// Bind to all the given addresses. Hostnames are bound on every address they resolve to.
+    ///
+    /// If both `::` and `0.0.0.0` are requested on the same port, a single dual-stack
+    /// socket is used for both where the system supports it.
+    pub async fn bind_all(addrs: &[ListenAddr]) -> anyhow::Result<Vec<Self>> {
+        let mut listeners = Vec::new();
+        let mut socket_addrs: Vec<SocketAddr> = Vec::new();
+
+        for addr in addrs {
+            match addr {
+                ListenAddr::Tcp { host, port } => {
+                    let ips = resolve(host)
+                        .await
+                        .context(format!("Failed to resolve address: {}", host))?;
+                    for ip in ips {
+                        let socket_addr = SocketAddr::new(ip, *port);
+                        if !socket_addrs.contains(&socket_addr) {
+                            socket_addrs.push(socket_addr);
+                        }
+                    }
+                }
+                ListenAddr::Unix(path) => listeners.push(Self::bind_unix(path)?),
+            }
+        }
+
+        // NOTE: the unspecified IPv6 addresses are bound first, so that we know which
+        //       IPv4 addresses are already covered by a dual-stack socket.
+        socket_addrs.sort_by_key(|addr| !is_unspecified_ipv6(addr));
+
+        let mut dual_stack_ports = Vec::new();
+        for socket_addr in socket_addrs {
+            if socket_addr.is_ipv4()
+                && socket_addr.ip().is_unspecified()
+                && dual_stack_ports.contains(&socket_addr.port())
+            {
+                log::debug!(
+                    "Not binding {} separately, it is covered by the dual-stack socket on [::]:{}",
+                    socket_addr,
+                    socket_addr.port()
+                );
+                continue;
+            }
+
+            let (listener, dual_stack) = bind_tcp(socket_addr)
+                .context(format!("Failed to bind API server to '{}'", socket_addr))?;
+            if dual_stack && socket_addr.port() != 0 {
+                log::debug!("{} is a dual-stack socket", socket_addr);
+                dual_stack_ports.push(socket_addr.port());
+            }
+            listeners.push(ApiListener::Tcp(listener));
+        }
+
+        Ok(listeners)
+    }
+
+    fn bind_unix(path: &Path) -> anyhow::Result<Self> {
+        remove_stale_socket(path)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).context(format!(
+                "Failed to create directory for '{}'",
+                path.display()
+            ))?;
+        }
+        let listener = UnixListener::bind(path)
+            .context(format!("Failed to bind API server to '{}'", path.display()))?;
+        Ok(ApiListener::Unix(listener))
+    }
+
+    /// Take over the listening sockets passed by systemd socket activation, if any.
+    pub fn from_systemd() -> anyhow::Result<Vec<Self>> {
+        let fds = sd_notify::listen_fds().context("Failed to read sockets passed by systemd")?;
+
+        fds.map(|fd| {
+            // SAFETY: systemd hands these file descriptors over to us, and listen_fds
+            //         unsets LISTEN_FDS, so they will not be taken over twice.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            from_activated_fd(fd)
+        })
+        .collect()
+    }
+}
+
+fn from_activated_fd(fd: OwnedFd) -> anyhow::Result<ApiListener> {
+    // NOTE: getsockname fails to convert into an ip address for unix sockets,
+    //       which is how we tell them apart.
+    let tcp_listener = std::net::TcpListener::from(fd);
+    if tcp_listener.local_addr().is_ok() {
+        tcp_listener.set_nonblocking(true)?;
+        return Ok(ApiListener::Tcp(TcpListener::from_std(tcp_listener)?));
+    }
+
+    let unix_listener = std::os::unix::net::UnixListener::from(OwnedFd::from(tcp_listener));
+    unix_listener
+        .local_addr()
+        .context("Socket passed by systemd is neither a tcp nor a unix socket")?;
+    unix_listener.set_nonblocking(true)?;
+    Ok(ApiListener::Unix(UnixListener::from_std(unix_listener)?))
+}
+
+fn is_unspecified_ipv6(addr: &SocketAddr) -> bool {
+    addr.is_ipv6() && addr.ip().is_unspecified()
+}
+
+/// Bind a tcp socket, returning whether it accepts both IPv6 and IPv4 connections.
+fn bind_tcp(addr: SocketAddr) -> anyhow::Result<(TcpListener, bool)> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    // NOTE: this is what std and tokio do on unix as well, so that restarting
+    //       does not fail because of connections lingering in TIME_WAIT.
+    socket.set_reuse_address(true)?;
+
+    let dual_stack = is_unspecified_ipv6(&addr) && socket.set_only_v6(false).is_ok();
+
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    Ok((TcpListener::from_std(socket.into())?, dual_stack))
+}
+
+/// A socket file left behind by a previous run would make binding fail,
+/// but anything that is not a socket is left alone.
+fn remove_stale_socket(path: &Path) -> anyhow::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path).context(
+            format!("Failed to remove stale socket '{}'", path.display()),
+        ),
+        Ok(_) => anyhow::bail!("'{}' exists and is not a socket", path.display()),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Serve the API on all the given listeners, over TLS if a config is given.
+///
+/// Responses are compressed with brotli or gzip for clients that accept it, since the
+/// playlist gets big with metadata attached. Event streams and small responses are not.
+pub async fn serve_api(
+    listeners: Vec<ApiListener>,
+    app: Router,
+    tls_config: Option<RustlsConfig>,
+) -> anyhow::Result<()> {
+    let app = app.layer(CompressionLayer::new());
+    futures::future::try_join_all(
+        listeners
+            .into_iter()
+            .map(|listener| serve_listener(listener, app.clone(), tls_config.clone())),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn serve_listener(
+    listener: ApiListener,
+    app: Router,
+    tls_config: Option<RustlsConfig>,
+) -> anyhow::Result<()> {
+    log::info!(
+        "Starting API on {} ({})",
+        listener,
+        if tls_config.is_some() {
+            "https"
+        } else {
+            "http"
+        },
+    );
+
+    let app = app.into_make_service_with_connect_info::<ClientAddr>();
+
+    match (listener, tls_config) {
+        (ApiListener::Tcp(listener), Some(tls_config)) => {
+            axum_server::from_tcp_rustls(listener.into_std()?, tls_config)?
+                .serve(app)
+                .await?
+        }
+        (ApiListener::Unix(listener), Some(tls_config)) => {
+            axum_server::from_unix_rustls(listener.into_std()?, tls_config)?
+                .serve(app)
+                .await?
+        }
+        (ApiListener::Tcp(listener), None) => axum::serve(listener, app).await?,
+        (ApiListener::Unix(listener), None) => axum::serve(listener, app).await?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listen_addr() {
+        assert_eq!(
+            "unix:/run/greg-ng/http.sock".parse(),
+            Ok(ListenAddr::Unix(PathBuf::from("/run/greg-ng/http.sock")))
+        );
+        assert_eq!(
+            "localhost:8008".parse(),
+            Ok(ListenAddr::Tcp {
+                host: "localhost".to_string(),
+                port: 8008
+            })
+        );
+        assert_eq!(
+            "[::1]:8008".parse(),
+            Ok(ListenAddr::Tcp {
+                host: "::1".to_string(),
+                port: 8008
+            })
+        );
+        assert!("unix:".parse::<ListenAddr>().is_err());
+        assert!("localhost".parse::<ListenAddr>().is_err());
+        assert!("localhost:http".parse::<ListenAddr>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bind_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub/http.sock");
+        let addrs = [
+            ListenAddr::Tcp {
+                host: "127.0.0.1".to_string(),
+                port: 0,
+            },
+            ListenAddr::Unix(path.clone()),
+        ];
+
+        let listeners = ApiListener::bind_all(&addrs).await.unwrap();
+        assert_eq!(listeners.len(), 2);
+        assert!(
+            listeners
+                .iter()
+                .any(|listener| listener.to_string() == format!("unix:{}", path.display()))
+        );
+        drop(listeners);
+
+        // The socket file is still there, and should be replaced on the next bind.
+        assert!(path.exists());
+        ApiListener::bind_all(&addrs).await.unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+//! Which greg-ng instance is in charge of an mpv, and handing it over to another.
+//!
+//! The instance in charge writes who it is into an mpv property. An instance that attaches
+//! to the same mpv later reads it, and depending on `--takeover` refuses to start, asks the
+//! other instance to shut down through its API, or shares mpv without changing anything.
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use axum::{
+    Json, Router,
+    extract::{Query, Request, State, rejection::QueryRejection},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use mpvipc_async::Mpv;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::sync::Notify;
+use url::Url;
+
+use crate::{api::ApiError, server::ListenAddr};
+
+/// mpv keeps properties under `user-data/` for its clients, and forgets them when it exits.
+const OWNER_PROPERTY: &str = "user-data/greg-ng/owner";
+
+/// How long the instance in charge gets to let go of mpv.
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What to do when another greg-ng instance is already in charge of mpv.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Takeover {
+    /// Exit, leaving the other instance in charge.
+    #[default]
+    Refuse,
+    /// Ask the other instance to shut down, and take over its mpv.
+    Steal,
+    /// Share mpv with the other instance, only serving requests that change nothing.
+    ReadOnly,
+}
+
+/// The greg-ng instance in charge of an mpv.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Owner {
+    pub pid: u32,
+    /// Where its API is served, as given to `--listen`.
+    pub listen: String,
+    pub tls: bool,
+    /// Proves to the instance that whoever asks it to shut down can read this property,
+    /// and so can control mpv anyway.
+    pub token: String,
+}
+
+impl Owner {
+    /// Whether the instance is still running. An instance that crashed leaves the
+    /// property behind.
+    pub fn is_alive(&self) -> bool {
+        self.pid != std::process::id() && Path::new("/proc").join(self.pid.to_string()).exists()
+    }
+
+    /// Ask the instance to shut down, leaving mpv running.
+    pub async fn request_release(&self) -> anyhow::Result<()> {
+        let listen: ListenAddr = self.listen.parse().map_err(anyhow::Error::msg)?;
+        let scheme = if self.tls { "https" } else { "http" };
+        let (client, base_url) = match listen {
+            ListenAddr::Unix(path) => (
+                reqwest::Client::builder().unix_socket(path).build()?,
+                format!("{}://localhost", scheme),
+            ),
+            ListenAddr::Tcp { host, port } => {
+                let host = match host.as_str() {
+                    "0.0.0.0" => "127.0.0.1".to_string(),
+                    "::" => "[::1]".to_string(),
+                    host if host.contains(':') => format!("[{}]", host),
+                    host => host.to_string(),
+                };
+                (
+                    reqwest::Client::new(),
+                    format!("{}://{}:{}", scheme, host, port),
+                )
+            }
+        };
+
+        let mut url = Url::parse(&format!("{}/api/session/release", base_url))?;
+        url.query_pairs_mut().append_pair("token", &self.token);
+        client.post(url).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// The instance in charge of `mpv`, if it is still running.
+pub async fn current_owner(mpv: &Mpv) -> anyhow::Result<Option<Owner>> {
+    let owner = mpv.get_property_value(OWNER_PROPERTY).await.ok().flatten();
+    Ok(owner
+        .filter(|owner| !owner.is_null())
+        .map(serde_json::from_value::<Owner>)
+        .transpose()
+        .context("Invalid greg-ng owner in mpv")?
+        .filter(Owner::is_alive))
+}
+
+/// Have `owner` hand `mpv` over, and wait until it has.
+pub async fn take_over(mpv: &Mpv, owner: &Owner) -> anyhow::Result<()> {
+    log::info!("Asking greg-ng (pid {}) to hand over mpv", owner.pid);
+    owner
+        .request_release()
+        .await
+        .with_context(|| format!("Failed to reach greg-ng at {}", owner.listen))?;
+
+    tokio::time::timeout(RELEASE_TIMEOUT, async {
+        while current_owner(mpv).await?.as_ref() == Some(owner) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        anyhow::Ok(())
+    })
+    .await
+    .context("Timed out waiting for the other greg-ng to hand over mpv")?
+}
+
+/// This instance's part in the protocol.
+#[derive(Debug, Clone)]
+pub struct Session {
+    token: String,
+    read_only: bool,
+    released: Arc<Notify>,
+}
+
+impl Session {
+    pub fn new(read_only: bool) -> Self {
+        Self {
+            token: format!("{:032x}", rand::random::<u128>()),
+            read_only,
+            released: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Put this instance in charge of `mpv`, with its API at `listen`.
+    pub async fn claim(&self, mpv: &Mpv, listen: String, tls: bool) -> anyhow::Result<()> {
+        let owner = Owner {
+            pid: std::process::id(),
+            listen,
+            tls,
+            token: self.token.clone(),
+        };
+        mpv.set_property(OWNER_PROPERTY, json!(owner))
+            .await
+            .context("Failed to claim mpv")?;
+        Ok(())
+    }
+
+    /// Resolves when another instance has asked to take over.
+    pub async fn released(&self) {
+        self.released.notified().await
+    }
+
+    /// Let go of `mpv`, so that the instance taking over knows it can start.
+    pub async fn hand_over(&self, mpv: &Mpv) {
+        log::info!("Handing mpv over to another greg-ng instance");
+        if let Err(e) = mpv.set_property(OWNER_PROPERTY, Value::Null).await {
+            log::warn!("Failed to let go of mpv: {}", e);
+        }
+    }
+}
+
+/// The `/api/session` endpoints, for seeing whether this instance is in charge, and for
+/// another instance to take over.
+pub fn session_routes(session: Session) -> Router {
+    Router::new()
+        .route("/api/session", get(get_session))
+        .route("/api/session/release", post(release))
+        .with_state(session)
+}
+
+/// Check whether this instance shares mpv with another one in charge of it
+async fn get_session(State(session): State<Session>) -> Response {
+    Json(json!({
+        "success": true,
+        "value": { "pid": std::process::id(), "read_only": session.read_only },
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct ReleaseArgs {
+    token: String,
+}
+
+/// Shut down, leaving mpv to the instance taking over. Needs the token from mpv.
+async fn release(
+    State(session): State<Session>,
+    query: Result<Query<ReleaseArgs>, QueryRejection>,
+) -> Response {
+    let args = match query {
+        Ok(Query(args)) => args,
+        Err(rejection) => return ApiError::InvalidArgument(rejection.body_text()).into_response(),
+    };
+    if session.read_only || args.token != session.token {
+        return ApiError::Unauthorized("Not the token of this instance".to_string())
+            .into_response();
+    }
+
+    // Shutting down stops the server, so give it a moment to answer first.
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        session.released.notify_one();
+    });
+    Json(json!({ "success": true, "value": null })).into_response()
+}
+
+/// Reject every request that could change something, for instances running with
+/// `--takeover read-only`.
+pub async fn reject_changes(request: Request, next: Next) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
+    }
+    ApiError::PolicyViolation(
+        "This instance is read-only, another greg-ng is in charge of mpv".to_string(),
+    )
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::FakeMpv;
+
+    #[tokio::test]
+    async fn test_hand_over() {
+        let mpv = FakeMpv::start();
+        let client = mpv.connect().await;
+        let session = Session::new(false);
+        session
+            .claim(&client, "127.0.0.1:8008".to_string(), false)
+            .await
+            .unwrap();
+        // This process can not be another instance.
+        assert_eq!(current_owner(&client).await.unwrap(), None);
+        let owner: Owner = serde_json::from_value(mpv.property(OWNER_PROPERTY).unwrap()).unwrap();
+        assert_eq!(owner.token, session.token);
+
+        let router = session_routes(session.clone());
+        let release = |token: &str| {
+            Request::post(format!("/api/session/release?token={}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = router.clone().oneshot(release("wrong")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = router.oneshot(release(&owner.token)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        session.released().await;
+
+        session.hand_over(&client).await;
+        assert_eq!(mpv.property(OWNER_PROPERTY), Some(Value::Null));
+    }
+}
@@ -0,0 +1,180 @@
+//! A soundboard of short clips, played over whatever is on with an interjection.
+//!
+//! Clips are configured by name, and are probed when greg-ng starts, so a missing or
+//! broken file is noticed right away instead of when someone presses the button.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::Context;
+use serde::Serialize;
+use symphonia::core::{
+    formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+};
+
+/// A clip as given on the command line, as `<name>=<path>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipSpec {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl FromStr for ClipSpec {
+    type Err = String;
+
+    /// Parses `<name>=<path>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, path) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <name>=<path>, got '{}'", s))?;
+
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "invalid clip name '{}', only letters, digits, '-' and '_' are allowed",
+                name
+            ));
+        }
+        if path.is_empty() {
+            return Err(format!("missing path for clip '{}'", name));
+        }
+
+        Ok(ClipSpec {
+            name: name.to_string(),
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Clip {
+    pub name: String,
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// In seconds, if the file says.
+    pub duration: Option<f64>,
+}
+
+/// The configured clips, by name.
+#[derive(Debug, Clone, Default)]
+pub struct Soundboard {
+    clips: Arc<BTreeMap<String, Clip>>,
+}
+
+impl Soundboard {
+    /// Probe every clip, failing if any of them can not be read.
+    pub fn load(specs: Vec<ClipSpec>) -> anyhow::Result<Self> {
+        let mut clips = BTreeMap::new();
+        for ClipSpec { name, path } in specs {
+            let duration = probe_duration(&path)
+                .with_context(|| format!("Failed to read clip '{}' at {}", name, path.display()))?;
+            log::debug!("Loaded clip '{}' ({:?} seconds)", name, duration);
+
+            let clip = Clip {
+                name: name.clone(),
+                path,
+                duration,
+            };
+            if clips.insert(name.clone(), clip).is_some() {
+                anyhow::bail!("The clip '{}' is configured more than once", name);
+            }
+        }
+
+        Ok(Self {
+            clips: Arc::new(clips),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clips.is_empty()
+    }
+
+    /// Every clip, ordered by name.
+    pub fn clips(&self) -> Vec<Clip> {
+        self.clips.values().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Clip> {
+        self.clips.get(name)
+    }
+}
+
+/// The duration of the default track of a media file, in seconds.
+fn probe_duration(path: &Path) -> anyhow::Result<Option<f64>> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    let Some(track) = probed.format.default_track() else {
+        return Ok(None);
+    };
+    let params = &track.codec_params;
+    Ok(params
+        .time_base
+        .zip(params.n_frames)
+        .map(|(time_base, frames)| {
+            let time = time_base.calc_time(frames);
+            time.seconds as f64 + time.frac
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// A mono 16 bit WAV file of `samples` samples of silence at 8 kHz.
+    fn silent_wav(samples: u32) -> Vec<u8> {
+        let data_len = samples * 2;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&16000u32.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        wav.resize(wav.len() + data_len as usize, 0);
+        wav
+    }
+
+    #[test]
+    fn test_load() {
+        let mut file = tempfile::Builder::new().suffix(".wav").tempfile().unwrap();
+        file.write_all(&silent_wav(12000)).unwrap();
+
+        let spec: ClipSpec = format!("airhorn={}", file.path().display())
+            .parse()
+            .unwrap();
+        let soundboard = Soundboard::load(vec![spec.clone()]).unwrap();
+        assert_eq!(soundboard.get("airhorn").unwrap().duration, Some(1.5));
+
+        assert!(Soundboard::load(vec![spec.clone(), spec]).is_err());
+        assert!("air horn=/tmp/x.wav".parse::<ClipSpec>().is_err());
+        assert!("airhorn=".parse::<ClipSpec>().is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! Skips sponsored segments, intros and the like in YouTube videos, using SponsorBlock.
+//!
+//! The segments of every YouTube item are fetched when it starts, and the playback
+//! position is watched to seek past them. Skips are announced to every mpv client as a
+//! `script-message`, which is how they reach the websocket and event stream clients.
+//!
+//! Items that have been swapped for a resolved stream by the prefetcher no longer have a
+//! YouTube url, and are not skipped in.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use futures::StreamExt;
+use mpvipc_async::{Event, Mpv, MpvExt, SeekOptions};
+use serde::Deserialize;
+use url::Url;
+
+use crate::autoplay::youtube_video_id;
+
+/// The `script-message` sent when a segment is skipped, followed by its category, start and end.
+pub const SEGMENT_SKIPPED_MESSAGE: &str = "greg-segment-skipped";
+
+/// The public SponsorBlock server.
+pub const DEFAULT_SERVER: &str = "https://sponsor.ajay.app";
+
+/// The categories that are skipped unless configured otherwise.
+pub const DEFAULT_CATEGORIES: [&str; 2] = ["sponsor", "selfpromo"];
+
+/// How often the playback position is checked while the current item has segments.
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long SponsorBlock gets to answer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Segments shorter than this are not worth a seek.
+const MIN_SEGMENT_LENGTH: f64 = 1.0;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SkipSegment {
+    segment: (f64, f64),
+    category: String,
+    #[serde(default = "default_action_type")]
+    action_type: String,
+}
+
+fn default_action_type() -> String {
+    "skip".to_string()
+}
+
+/// A segment of the current item that is to be skipped.
+#[derive(Debug, Clone, PartialEq)]
+struct Segment {
+    start: f64,
+    end: f64,
+    category: String,
+    skipped: bool,
+}
+
+/// The category, start and end of a skip announced with [`SEGMENT_SKIPPED_MESSAGE`].
+pub fn parse_skip_message(args: &[String]) -> Option<(&str, f64, f64)> {
+    match args {
+        [message, category, start, end] if message == SEGMENT_SKIPPED_MESSAGE => {
+            Some((category, start.parse().ok()?, end.parse().ok()?))
+        }
+        _ => None,
+    }
+}
+
+/// Parse the response of the `skipSegments` endpoint, keeping the segments worth skipping.
+fn parse_segments(body: &[u8]) -> anyhow::Result<Vec<Segment>> {
+    let segments: Vec<SkipSegment> =
+        serde_json::from_slice(body).context("Failed to parse the SponsorBlock segments")?;
+    Ok(segments
+        .into_iter()
+        .filter(|segment| segment.action_type == "skip")
+        .filter(|segment| segment.segment.1 - segment.segment.0 >= MIN_SEGMENT_LENGTH)
+        .map(|segment| Segment {
+            start: segment.segment.0,
+            end: segment.segment.1,
+            category: segment.category,
+            skipped: false,
+        })
+        .collect())
+}
+
+/// The segment that playback at `time_pos` is inside of and should skip, if any.
+fn segment_at(segments: &mut [Segment], time_pos: f64) -> Option<&mut Segment> {
+    segments
+        .iter_mut()
+        .find(|segment| !segment.skipped && segment.start <= time_pos && time_pos < segment.end)
+}
+
+/// A client for a SponsorBlock server.
+#[derive(Debug, Clone)]
+pub struct SponsorBlock {
+    client: reqwest::Client,
+    server: Url,
+    categories: Vec<String>,
+}
+
+impl SponsorBlock {
+    pub fn new(server: Url, categories: Vec<String>) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("Failed to build HTTP client"),
+            server,
+            categories,
+        }
+    }
+
+    async fn segments(&self, video_id: &str) -> anyhow::Result<Vec<Segment>> {
+        let mut url = self.server.join("/api/skipSegments")?;
+        url.query_pairs_mut()
+            .append_pair("videoID", video_id)
+            .append_pair("categories", &serde_json::to_string(&self.categories)?);
+
+        let response = self.client.get(url).send().await?;
+        // SponsorBlock answers with a 404 when there are no segments.
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        let body = response.error_for_status()?.bytes().await?;
+        parse_segments(&body)
+    }
+}
+
+/// Fetch the segments of the item that is playing, if it is a YouTube video.
+async fn current_segments(mpv: &Mpv, sponsorblock: &SponsorBlock) -> Vec<Segment> {
+    let path: Option<String> = mpv.get_property("path").await.unwrap_or(None);
+    let Some(video_id) = path.as_deref().and_then(youtube_video_id) else {
+        return Vec::new();
+    };
+
+    match sponsorblock.segments(&video_id).await {
+        Ok(segments) => {
+            log::debug!("Found {} segments to skip in {}", segments.len(), video_id);
+            segments
+        }
+        Err(e) => {
+            log::debug!("Could not fetch the segments of {}: {:#}", video_id, e);
+            Vec::new()
+        }
+    }
+}
+
+async fn skip(mpv: &Mpv, segment: &Segment) -> anyhow::Result<()> {
+    log::info!(
+        "Skipping {} segment from {:.1} to {:.1}",
+        segment.category,
+        segment.start,
+        segment.end
+    );
+    mpv.seek(segment.end, SeekOptions::Absolute).await?;
+    mpv.run_command_raw(
+        "script-message",
+        &[
+            SEGMENT_SKIPPED_MESSAGE,
+            &segment.category,
+            &segment.start.to_string(),
+            &segment.end.to_string(),
+        ],
+    )
+    .await?;
+    Ok(())
+}
+
+/// Keep skipping segments in whatever is playing, until mpv goes away.
+pub async fn run_sponsorblock(mpv: Mpv, sponsorblock: SponsorBlock) -> anyhow::Result<()> {
+    let mut event_stream = mpv.get_event_stream().await;
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let mut segments: Vec<Segment> = Vec::new();
+
+    loop {
+        tokio::select! {
+            event = event_stream.next() => match event.context("Lost the mpv events")?? {
+                Event::FileLoaded => segments = current_segments(&mpv, &sponsorblock).await,
+                Event::EndFile { .. } => segments.clear(),
+                Event::Shutdown => return Ok(()),
+                _ => {}
+            },
+
+            _ = interval.tick(), if !segments.is_empty() => {
+                let time_pos: Option<f64> = mpv.get_property("time-pos").await.unwrap_or(None);
+                if let Some(segment) = time_pos.and_then(|time_pos| segment_at(&mut segments, time_pos)) {
+                    // Only skip once, so seeking back into a segment plays it.
+                    segment.skipped = true;
+                    if let Err(e) = skip(&mpv, segment).await {
+                        log::warn!("Failed to skip a segment: {:#}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segments() {
+        let body = br#"[
+            {"segment": [0.0, 12.5], "UUID": "a", "category": "sponsor", "actionType": "skip"},
+            {"segment": [30.0, 30.2], "UUID": "b", "category": "sponsor", "actionType": "skip"},
+            {"segment": [60.0, 90.0], "UUID": "c", "category": "selfpromo", "actionType": "mute"},
+            {"segment": [120.0, 140.0], "UUID": "d", "category": "intro"}
+        ]"#;
+        let mut segments = parse_segments(body).unwrap();
+        assert_eq!(segments.len(), 2);
+
+        assert!(segment_at(&mut segments, 130.0).is_some_and(|s| s.category == "intro"));
+        assert!(segment_at(&mut segments, 12.5).is_none());
+
+        segments[0].skipped = true;
+        assert!(segment_at(&mut segments, 5.0).is_none());
+
+        let args = ["greg-segment-skipped", "sponsor", "0", "12.5"].map(String::from);
+        assert_eq!(parse_skip_message(&args), Some(("sponsor", 0.0, 12.5)));
+    }
+}
@@ -0,0 +1,421 @@
+use std::{fmt, path::Path};
+
+use tokio::{net::UnixStream, process::Command};
+
+use crate::{mpv_setup::MpvConnectError, resolve};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckStatus {
+    Ok(String),
+    /// Something is missing, but greg-ng can still run without it.
+    Warning(String),
+    /// greg-ng can not run like this.
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    /// What the user can do to fix the problem, shown for warnings and failures.
+    pub hint: String,
+}
+
+impl CheckResult {
+    pub fn is_failed(&self) -> bool {
+        matches!(self.status, CheckStatus::Failed(_))
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.status {
+            CheckStatus::Ok(message) => write!(f, "[ OK ] {}: {}", self.name, message),
+            CheckStatus::Warning(message) => write!(
+                f,
+                "[WARN] {}: {}\n       hint: {}",
+                self.name, message, self.hint
+            ),
+            CheckStatus::Failed(message) => write!(
+                f,
+                "[FAIL] {}: {}\n       hint: {}",
+                self.name, message, self.hint
+            ),
+        }
+    }
+}
+
+pub struct StartupCheckArgs<'a> {
+    /// The hosts the APIs will be bound to, not including unix sockets.
+    pub hosts: Vec<&'a str>,
+    pub mpv_socket_path: &'a str,
+    pub mpv_executable_path: Option<&'a str>,
+    pub mpv_config_file: Option<&'a str>,
+    pub auto_start_mpv: bool,
+    /// Whether mpv is used at all, or another player backend.
+    pub uses_mpv: bool,
+    /// Where the database with saved playlists and the play history is kept.
+    pub db_path: &'a Path,
+}
+
+/// Check that everything greg-ng depends on is in place, before trying to start.
+pub async fn run_startup_checks(args: &StartupCheckArgs<'_>) -> Vec<CheckResult> {
+    let mut results = Vec::new();
+
+    if args.uses_mpv {
+        if args.auto_start_mpv {
+            // An mpv that is already running is attached to, instead of starting another.
+            match check_running_mpv(args.mpv_socket_path).await {
+                Some(result) => results.push(result),
+                None => {
+                    results.push(
+                        check_mpv_executable(args.mpv_executable_path.unwrap_or("mpv")).await,
+                    );
+                    results.push(check_socket_directory(args.mpv_socket_path));
+                }
+            }
+        } else {
+            results.push(check_existing_socket(args.mpv_socket_path));
+        }
+        if let Some(config_file) = args.mpv_config_file {
+            results.push(check_mpv_config_file(config_file));
+        }
+    }
+    results.push(check_ytdlp().await);
+    results.push(check_temp_directory());
+    results.push(check_data_directory(args.db_path));
+    for host in &args.hosts {
+        results.push(check_host(host).await);
+    }
+
+    results
+}
+
+/// Log the results of the startup checks, and fail with a summary if any of them failed.
+pub fn report_startup_checks(results: &[CheckResult]) -> anyhow::Result<()> {
+    for result in results {
+        match result.status {
+            CheckStatus::Ok(_) => log::info!("{}", result),
+            CheckStatus::Warning(_) => log::warn!("{}", result),
+            CheckStatus::Failed(_) => log::error!("{}", result),
+        }
+    }
+
+    let failed: Vec<_> = results
+        .iter()
+        .filter(|r| r.is_failed())
+        .map(|r| r.name)
+        .collect();
+
+    if !failed.is_empty() {
+        anyhow::bail!(
+            "{} of {} startup checks failed: {}",
+            failed.len(),
+            results.len(),
+            failed.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Run `<executable> --version` and return the first line of the output.
+async fn executable_version(executable: &str) -> Result<String, String> {
+    let output = Command::new(executable)
+        .arg("--version")
+        .output()
+        .await
+        .map_err(|e| format!("could not run '{}': {}", executable, e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "'{} --version' exited with {}",
+            executable, output.status
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string())
+}
+
+async fn check_mpv_executable(executable: &str) -> CheckResult {
+    let status = match executable_version(executable).await {
+        // The first line looks like "mpv 0.38.0 Copyright © 2000-2024 mpv/MPlayer/mplayer2 projects"
+        Ok(version) => CheckStatus::Ok(
+            version
+                .split_whitespace()
+                .take(2)
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        Err(e) => CheckStatus::Failed(e),
+    };
+
+    CheckResult {
+        name: "mpv executable",
+        status,
+        hint: "Install mpv, or point --mpv-executable-path to the mpv binary".to_string(),
+    }
+}
+
+async fn check_ytdlp() -> CheckResult {
+    let status = match executable_version("yt-dlp").await {
+        Ok(version) => CheckStatus::Ok(format!("yt-dlp {}", version)),
+        Err(e) => CheckStatus::Warning(e),
+    };
+
+    CheckResult {
+        name: "yt-dlp",
+        status,
+        hint: "Install yt-dlp and make sure it is in PATH, otherwise only direct media urls and local files can be played".to_string(),
+    }
+}
+
+/// Check that the directory of the mpv socket exists, or can be created, and is writable.
+fn check_socket_directory(socket_path: &str) -> CheckResult {
+    check_parent_directory(
+        "mpv socket directory",
+        Path::new(socket_path),
+        format!(
+            "Make sure the user running greg-ng can write to the directory of '{}', or choose another --mpv-socket-path",
+            socket_path
+        ),
+    )
+}
+
+/// The database is created in its directory if it is not there, and written to as long
+/// as greg-ng runs.
+fn check_data_directory(db_path: &Path) -> CheckResult {
+    check_parent_directory(
+        "data directory",
+        db_path,
+        format!(
+            "Make sure the user running greg-ng can write to the directory of '{}', or choose another --data-dir or --db-path",
+            db_path.display()
+        ),
+    )
+}
+
+/// Check that the directory `path` goes in exists, or can be created, and is writable.
+fn check_parent_directory(name: &'static str, path: &Path, hint: String) -> CheckResult {
+    let Some(parent_dir) = path.parent() else {
+        return CheckResult {
+            name,
+            status: CheckStatus::Failed(format!("'{}' has no parent directory", path.display())),
+            hint,
+        };
+    };
+
+    // If the directory does not exist yet, it will be created by the closest existing ancestor
+    let existing_dir = parent_dir
+        .ancestors()
+        .find(|dir| dir.is_dir())
+        .unwrap_or(parent_dir);
+
+    let status = match tempfile::tempfile_in(existing_dir) {
+        Ok(_) if existing_dir == parent_dir => {
+            CheckStatus::Ok(format!("'{}' is writable", parent_dir.display()))
+        }
+        Ok(_) => CheckStatus::Ok(format!(
+            "'{}' does not exist, but can be created",
+            parent_dir.display()
+        )),
+        Err(e) => CheckStatus::Failed(format!(
+            "'{}' is not writable: {}",
+            existing_dir.display(),
+            e
+        )),
+    };
+
+    CheckResult { name, status, hint }
+}
+
+fn check_mpv_config_file(config_file: &str) -> CheckResult {
+    let status = match std::fs::metadata(config_file) {
+        Ok(metadata) if metadata.is_file() => CheckStatus::Ok(format!("found '{}'", config_file)),
+        Ok(_) => CheckStatus::Failed(format!("'{}' is not a file", config_file)),
+        Err(e) => CheckStatus::Failed(format!("could not read '{}': {}", config_file, e)),
+    };
+
+    CheckResult {
+        name: "mpv config file",
+        status,
+        hint:
+            "Check the path given to --mpv-config-file, or leave it out to use the default config"
+                .to_string(),
+    }
+}
+
+/// When not starting mpv ourselves, there has to be a running instance to connect to.
+fn check_existing_socket(socket_path: &str) -> CheckResult {
+    let status = if Path::new(socket_path).exists() {
+        CheckStatus::Ok(format!("found mpv socket at '{}'", socket_path))
+    } else {
+        CheckStatus::Failed(format!("no mpv socket found at '{}'", socket_path))
+    };
+
+    CheckResult {
+        name: "mpv socket",
+        status,
+        hint: format!(
+            "Start mpv with --input-ipc-server={}, or run greg-ng with --auto-start-mpv",
+            socket_path
+        ),
+    }
+}
+
+/// An mpv that answers on the socket is attached to, so it does not matter whether greg-ng
+/// could have started one itself. Returns nothing if there is no such mpv.
+async fn check_running_mpv(socket_path: &str) -> Option<CheckResult> {
+    UnixStream::connect(socket_path).await.ok()?;
+    Some(CheckResult {
+        name: "mpv socket",
+        status: CheckStatus::Ok(format!(
+            "found a running mpv at '{}', attaching to it",
+            socket_path
+        )),
+        hint: String::new(),
+    })
+}
+
+/// Whether mpv could be connected to can only be known by trying, so this is checked
+/// after the others, once it has been tried.
+pub fn check_mpv_connection<T>(connected: Result<T, MpvConnectError>) -> anyhow::Result<T> {
+    let error = match connected {
+        Ok(connected) => return Ok(connected),
+        Err(error) => error,
+    };
+    let hint = match &error {
+        MpvConnectError::SocketNotFound { socket_path } => format!(
+            "Start mpv with --input-ipc-server={}, or run greg-ng with --auto-start-mpv",
+            socket_path
+        ),
+        MpvConnectError::Io { .. } => {
+            "Check that the mpv socket directory is writable, and that --mpv-executable-path points to the mpv binary"
+                .to_string()
+        }
+        MpvConnectError::Exited { .. } => {
+            "Pass --mpv-log-file to see why mpv exited, or try starting mpv by hand".to_string()
+        }
+        MpvConnectError::TimedOut { .. } => {
+            "Raise --mpv-connect-attempts or --mpv-connect-timeout if mpv is slow to start".to_string()
+        }
+    };
+    let result = CheckResult {
+        name: "mpv connection",
+        status: CheckStatus::Failed(error.to_string()),
+        hint,
+    };
+    log::error!("{}", result);
+    anyhow::bail!("The {} startup check failed: {}", result.name, error)
+}
+
+/// The mpv config file and the placeholder image are written to the temp directory.
+fn check_temp_directory() -> CheckResult {
+    let temp_dir = std::env::temp_dir();
+
+    let status = match tempfile::tempfile_in(&temp_dir) {
+        Ok(_) => CheckStatus::Ok(format!("'{}' is writable", temp_dir.display())),
+        Err(e) => CheckStatus::Failed(format!("'{}' is not writable: {}", temp_dir.display(), e)),
+    };
+
+    CheckResult {
+        name: "temp directory",
+        status,
+        hint: "Make sure the temp directory is writable, or point TMPDIR somewhere else"
+            .to_string(),
+    }
+}
+
+async fn check_host(host: &str) -> CheckResult {
+    let status = match resolve(host).await {
+        Ok(addrs) => CheckStatus::Ok(format!(
+            "'{}' resolves to {}",
+            host,
+            addrs
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+        Err(e) => CheckStatus::Failed(format!("could not resolve '{}': {}", host, e)),
+    };
+
+    CheckResult {
+        name: "host address",
+        status,
+        hint: "Check that --host is a valid hostname or ip address, and that DNS is working"
+            .to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_startup_checks() {
+        let ok = CheckResult {
+            name: "ok",
+            status: CheckStatus::Ok("fine".to_string()),
+            hint: String::new(),
+        };
+        let warning = CheckResult {
+            name: "warning",
+            status: CheckStatus::Warning("meh".to_string()),
+            hint: "do something".to_string(),
+        };
+        let failed = CheckResult {
+            name: "failed",
+            status: CheckStatus::Failed("broken".to_string()),
+            hint: "fix it".to_string(),
+        };
+
+        assert!(report_startup_checks(&[ok.clone(), warning.clone()]).is_ok());
+
+        let err = report_startup_checks(&[ok, warning, failed]).unwrap_err();
+        assert_eq!(err.to_string(), "1 of 3 startup checks failed: failed");
+    }
+
+    #[test]
+    fn test_check_socket_directory() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let socket_path = dir.path().join("mpv.sock");
+        let result = check_socket_directory(socket_path.to_str().unwrap());
+        assert!(matches!(result.status, CheckStatus::Ok(_)), "{}", result);
+
+        let socket_path = dir.path().join("does/not/exist/mpv.sock");
+        let result = check_socket_directory(socket_path.to_str().unwrap());
+        assert!(matches!(result.status, CheckStatus::Ok(_)), "{}", result);
+    }
+
+    #[tokio::test]
+    async fn test_check_running_mpv() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("mpv.sock");
+        let socket_path = socket_path.to_str().unwrap();
+        assert_eq!(check_running_mpv(socket_path).await, None);
+
+        let _listener = tokio::net::UnixListener::bind(socket_path).unwrap();
+        let result = check_running_mpv(socket_path).await.unwrap();
+        assert!(matches!(result.status, CheckStatus::Ok(_)), "{}", result);
+    }
+
+    #[test]
+    fn test_check_mpv_connection() {
+        assert_eq!(check_mpv_connection(Ok(7)).unwrap(), 7);
+        let not_found = Err::<(), _>(MpvConnectError::SocketNotFound {
+            socket_path: "/run/mpv/mpv.sock".to_string(),
+        });
+        let err = check_mpv_connection(not_found).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The mpv connection startup check failed: No mpv socket found at /run/mpv/mpv.sock, and --auto-start-mpv is off"
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Statistics over the play history, like the most played tracks of a semester.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{
+    api::ApiError,
+    history::{HistoryEntry, Outcome},
+};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The entries that started within `from..to`, in seconds since the unix epoch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeRange {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+impl TimeRange {
+    /// Parse `from` and `to` with [`parse_time`].
+    pub fn parse(from: Option<&str>, to: Option<&str>) -> Result<Self, ApiError> {
+        let parse = |name: &str, value: Option<&str>| {
+            value
+                .map(|value| {
+                    parse_time(value).ok_or_else(|| {
+                        ApiError::InvalidArgument(format!("Invalid {} time '{}'", name, value))
+                    })
+                })
+                .transpose()
+        };
+        Ok(Self {
+            from: parse("from", from)?,
+            to: parse("to", to)?,
+        })
+    }
+
+    pub fn contains(&self, entry: &HistoryEntry) -> bool {
+        self.from.is_none_or(|from| entry.started_at >= from)
+            && self.to.is_none_or(|to| entry.started_at < to)
+    }
+}
+
+/// Parse a point in time given as seconds since the unix epoch, or as a `YYYY-MM-DD` date,
+/// which means midnight UTC at the start of that day.
+pub fn parse_time(s: &str) -> Option<u64> {
+    if let Ok(seconds) = s.parse() {
+        return Some(seconds);
+    }
+
+    let mut parts = s.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    Some(days * SECONDS_PER_DAY)
+}
+
+/// Days since the unix epoch of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The `YYYY-MM-DD` date of a day since the unix epoch.
+fn civil_from_days(days: i64) -> String {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// How long the entry was played for, counting entries that are still playing up to `now`.
+fn played_seconds(entry: &HistoryEntry, now: u64) -> u64 {
+    entry
+        .ended_at
+        .unwrap_or(now)
+        .saturating_sub(entry.started_at)
+}
+
+fn minutes(seconds: u64) -> f64 {
+    (seconds as f64 / 6.0).round() / 10.0
+}
+
+/// Entries that actually played, within `range`.
+fn played<'a>(
+    entries: &'a [HistoryEntry],
+    range: &'a TimeRange,
+) -> impl Iterator<Item = &'a HistoryEntry> {
+    entries.iter().filter(move |entry| {
+        range.contains(entry) && !matches!(entry.outcome, Outcome::Failed { .. } | Outcome::Expired)
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopTrack {
+    pub path: String,
+    /// The latest title the track was played with.
+    pub title: Option<String>,
+    pub plays: usize,
+    pub minutes: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopQueuer {
+    pub name: String,
+    /// How many of the items they added were played.
+    pub plays: usize,
+    pub minutes: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DailyMinutes {
+    /// The UTC date, as `YYYY-MM-DD`.
+    pub date: String,
+    pub minutes: f64,
+}
+
+/// The `limit` most played tracks, most played first. Ties go to the most listened to.
+pub fn top_tracks(
+    entries: &[HistoryEntry],
+    range: &TimeRange,
+    now: u64,
+    limit: usize,
+) -> Vec<TopTrack> {
+    let mut tracks: BTreeMap<&str, (Option<&str>, usize, u64)> = BTreeMap::new();
+    for entry in played(entries, range) {
+        let (title, plays, seconds) = tracks.entry(&entry.path).or_default();
+        *title = entry.title.as_deref().or(*title);
+        *plays += 1;
+        *seconds += played_seconds(entry, now);
+    }
+
+    let mut tracks: Vec<_> = tracks.into_iter().collect();
+    tracks.sort_by(
+        |(_, (_, a_plays, a_seconds)), (_, (_, b_plays, b_seconds))| {
+            b_plays.cmp(a_plays).then(b_seconds.cmp(a_seconds))
+        },
+    );
+    tracks
+        .into_iter()
+        .take(limit)
+        .map(|(path, (title, plays, seconds))| TopTrack {
+            path: path.to_string(),
+            title: title.map(str::to_string),
+            plays,
+            minutes: minutes(seconds),
+        })
+        .collect()
+}
+
+/// The `limit` people whose items were played the most, most played first. People who
+/// logged in are told apart by their subject, and listed by the last name they went by.
+pub fn top_queuers(
+    entries: &[HistoryEntry],
+    range: &TimeRange,
+    now: u64,
+    limit: usize,
+) -> Vec<TopQueuer> {
+    let mut queuers: BTreeMap<&str, (&str, usize, u64)> = BTreeMap::new();
+    for entry in played(entries, range) {
+        let Some(name) = entry.queued_by.as_deref() else {
+            continue;
+        };
+        let who = entry.queued_by_subject.as_deref().unwrap_or(name);
+        let (last_name, plays, seconds) = queuers.entry(who).or_default();
+        *last_name = name;
+        *plays += 1;
+        *seconds += played_seconds(entry, now);
+    }
+
+    let mut queuers: Vec<_> = queuers.into_values().collect();
+    queuers.sort_by(|(_, a_plays, a_seconds), (_, b_plays, b_seconds)| {
+        b_plays.cmp(a_plays).then(b_seconds.cmp(a_seconds))
+    });
+    queuers
+        .into_iter()
+        .take(limit)
+        .map(|(name, plays, seconds)| TopQueuer {
+            name: name.to_string(),
+            plays,
+            minutes: minutes(seconds),
+        })
+        .collect()
+}
+
+/// How many minutes were played every day something was played, oldest first.
+/// Everything is counted on the day it started.
+pub fn daily_minutes(entries: &[HistoryEntry], range: &TimeRange, now: u64) -> Vec<DailyMinutes> {
+    let mut days: BTreeMap<u64, u64> = BTreeMap::new();
+    for entry in played(entries, range) {
+        *days.entry(entry.started_at / SECONDS_PER_DAY).or_default() += played_seconds(entry, now);
+    }
+
+    days.into_iter()
+        .map(|(day, seconds)| DailyMinutes {
+            date: civil_from_days(day as i64),
+            minutes: minutes(seconds),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let entry =
+            |path: &str, started_at: u64, seconds: u64, queued_by: Option<&str>| HistoryEntry {
+                path: path.to_string(),
+                title: Some(path.to_uppercase()),
+                started_at,
+                ended_at: Some(started_at + seconds),
+                autoplay: false,
+                queued_by: queued_by.map(str::to_string),
+                queued_by_subject: None,
+                live: false,
+                outcome: Outcome::Finished,
+            };
+        let day = parse_time("2026-05-20").unwrap();
+        assert_eq!(day, 1779235200);
+        assert_eq!(
+            civil_from_days((day / SECONDS_PER_DAY) as i64),
+            "2026-05-20"
+        );
+        assert_eq!(parse_time("2026-13-01"), None);
+
+        let mut failed = entry("b", day + 400, 0, Some("alice"));
+        failed.outcome = Outcome::Failed {
+            error: "Loading failed".to_string(),
+        };
+        let entries = vec![
+            entry("a", day, 180, Some("alice")),
+            entry("b", day + 200, 120, Some("bob")),
+            failed,
+            entry("b", day + SECONDS_PER_DAY, 120, None),
+            entry("c", day + SECONDS_PER_DAY + 200, 60, Some("bob")),
+        ];
+        let all = TimeRange::default();
+
+        let tracks = top_tracks(&entries, &all, 0, 2);
+        assert_eq!(tracks.len(), 2);
+        assert_eq!((tracks[0].path.as_str(), tracks[0].plays), ("b", 2));
+        assert_eq!((tracks[1].path.as_str(), tracks[1].minutes), ("a", 3.0));
+
+        let queuers = top_queuers(&entries, &all, 0, 10);
+        let queuers: Vec<_> = queuers.iter().map(|q| (q.name.as_str(), q.plays)).collect();
+        assert_eq!(queuers, vec![("bob", 2), ("alice", 1)]);
+
+        // Someone who logged in is counted once, by the name they went by last.
+        let logged_in = |path: &str, started_at: u64, name: &str| HistoryEntry {
+            queued_by_subject: Some("u-42".to_string()),
+            ..entry(path, started_at, 60, Some(name))
+        };
+        let renamed = [
+            logged_in("a", day, "carol"),
+            logged_in("b", day + 100, "caroline"),
+            entry("c", day + 200, 60, Some("carol")),
+        ];
+        let queuers = top_queuers(&renamed, &all, 0, 10);
+        let queuers: Vec<_> = queuers.iter().map(|q| (q.name.as_str(), q.plays)).collect();
+        assert_eq!(queuers, vec![("caroline", 2), ("carol", 1)]);
+
+        let first_day = TimeRange {
+            from: None,
+            to: Some(day + SECONDS_PER_DAY),
+        };
+        assert_eq!(
+            daily_minutes(&entries, &first_day, 0),
+            vec![DailyMinutes {
+                date: "2026-05-20".to_string(),
+                minutes: 5.0,
+            }]
+        );
+        assert_eq!(daily_minutes(&entries, &all, 0).len(), 2);
+    }
+}
@@ -0,0 +1,236 @@
+//! Where persistent state, like saved playlists and the play history, is kept.
+//!
+//! In production this is an SQLite database. Tests use [`MemoryStorage`], so they never
+//! touch the filesystem.
+
+use std::{fmt::Debug, net::IpAddr, sync::Arc};
+
+use crate::{
+    api_keys::{ApiKey, NewApiKey},
+    bans::Ban,
+    bookmarks::{Bookmark, NewBookmark},
+    history::{HistoryEntry, Outcome},
+    playlists::SavedPlaylist,
+    resume::SavedPosition,
+    stats::TimeRange,
+};
+
+#[cfg(test)]
+mod memory;
+mod sqlite;
+
+#[cfg(test)]
+pub use memory::MemoryStorage;
+pub use sqlite::SqliteStorage;
+
+pub type StorageHandle = Arc<dyn Storage>;
+
+pub trait Storage: Debug + Send + Sync {
+    /// Store `playlist`, replacing any playlist with the same name unless `replace` is false.
+    /// Returns whether it was stored.
+    fn save_playlist(&self, playlist: &SavedPlaylist, replace: bool) -> anyhow::Result<bool>;
+    fn playlist(&self, name: &str) -> anyhow::Result<Option<SavedPlaylist>>;
+    /// The names of every saved playlist, sorted.
+    fn playlist_names(&self) -> anyhow::Result<Vec<String>>;
+    /// Returns whether there was a playlist to delete.
+    fn delete_playlist(&self, name: &str) -> anyhow::Result<bool>;
+
+    fn push_history(&self, entry: &HistoryEntry) -> anyhow::Result<()>;
+    /// End the latest history entry at `ended_at` with `outcome`, if it is still playing,
+    /// and is of `path` if given. Returns whether there was such an entry.
+    fn end_history(
+        &self,
+        path: Option<&str>,
+        outcome: &Outcome,
+        ended_at: u64,
+    ) -> anyhow::Result<bool>;
+    /// Every history entry that started within `range`, oldest first.
+    fn history(&self, range: &TimeRange) -> anyhow::Result<Vec<HistoryEntry>>;
+    /// The last `count` history entries, newest first.
+    fn recent_history(&self, count: usize) -> anyhow::Result<Vec<HistoryEntry>>;
+    /// Forget the history entries that started before `cutoff`, returning how many there were.
+    fn prune_history(&self, cutoff: u64) -> anyhow::Result<usize>;
+
+    /// Store `ban`, replacing any ban of the same address.
+    fn ban(&self, ban: &Ban) -> anyhow::Result<()>;
+    /// Returns whether the address was banned.
+    fn unban(&self, address: &IpAddr) -> anyhow::Result<bool>;
+    /// Every ban, including those that have expired, sorted by address.
+    fn bans(&self) -> anyhow::Result<Vec<Ban>>;
+
+    /// Store `position`, replacing any position saved for the same path.
+    fn save_position(&self, position: &SavedPosition) -> anyhow::Result<()>;
+    fn saved_position(&self, path: &str) -> anyhow::Result<Option<SavedPosition>>;
+    /// Returns whether a position was saved for `path`.
+    fn forget_position(&self, path: &str) -> anyhow::Result<bool>;
+
+    /// Store `bookmark`, returning its id.
+    fn add_bookmark(&self, bookmark: &NewBookmark) -> anyhow::Result<Bookmark>;
+    fn bookmark(&self, id: u64) -> anyhow::Result<Option<Bookmark>>;
+    /// The bookmarks in `url`, or every bookmark if no url is given, in the order they were
+    /// added.
+    fn bookmarks(&self, url: Option<&str>) -> anyhow::Result<Vec<Bookmark>>;
+    /// Returns whether there was a bookmark to delete.
+    fn delete_bookmark(&self, id: u64) -> anyhow::Result<bool>;
+
+    /// Store `key`, returning its id.
+    fn add_api_key(&self, key: &NewApiKey) -> anyhow::Result<ApiKey>;
+    /// Every API key, in the order they were added.
+    fn api_keys(&self) -> anyhow::Result<Vec<ApiKey>>;
+    /// Returns whether there was a key to relabel.
+    fn set_api_key_label(&self, id: u64, label: &str) -> anyhow::Result<bool>;
+    /// Returns whether there was a key to delete.
+    fn delete_api_key(&self, id: u64) -> anyhow::Result<bool>;
+}
+
+/// Put a [`Storage`] implementation through everything it is expected to do.
+#[cfg(test)]
+fn check_storage(storage: &dyn Storage) {
+    use crate::{playlists::SavedPlaylistItem, scopes::Scope};
+
+    let playlist = |name: &str, items: &[&str]| SavedPlaylist {
+        name: name.to_string(),
+        saved_at: 1,
+        items: items
+            .iter()
+            .map(|filename| SavedPlaylistItem {
+                filename: filename.to_string(),
+                title: None,
+            })
+            .collect(),
+    };
+    assert!(
+        storage
+            .save_playlist(&playlist("b", &["1", "2"]), true)
+            .unwrap()
+    );
+    assert!(storage.save_playlist(&playlist("b", &["3"]), true).unwrap());
+    assert!(
+        !storage
+            .save_playlist(&playlist("b", &["4"]), false)
+            .unwrap()
+    );
+    assert!(storage.save_playlist(&playlist("a", &[]), false).unwrap());
+    assert_eq!(storage.playlist("b").unwrap(), Some(playlist("b", &["3"])));
+    assert_eq!(storage.playlist_names().unwrap(), vec!["a", "b"]);
+    assert!(storage.delete_playlist("a").unwrap());
+    assert!(!storage.delete_playlist("a").unwrap());
+    assert_eq!(storage.playlist("a").unwrap(), None);
+
+    let entry = |path: &str, started_at: u64| HistoryEntry {
+        path: path.to_string(),
+        title: None,
+        started_at,
+        ended_at: None,
+        autoplay: false,
+        queued_by: Some("someone".to_string()),
+        queued_by_subject: Some("u-1".to_string()),
+        live: false,
+        outcome: Outcome::Playing,
+    };
+    storage.push_history(&entry("x", 10)).unwrap();
+    assert!(
+        !storage
+            .end_history(Some("y"), &Outcome::Finished, 20)
+            .unwrap()
+    );
+    assert!(
+        storage
+            .end_history(Some("x"), &Outcome::Finished, 20)
+            .unwrap()
+    );
+    assert!(!storage.end_history(None, &Outcome::Skipped, 30).unwrap());
+    storage.push_history(&entry("y", 30)).unwrap();
+    let failed = Outcome::Failed {
+        error: "Loading failed".to_string(),
+    };
+    assert!(storage.end_history(None, &failed, 40).unwrap());
+
+    let recent = storage.recent_history(1).unwrap();
+    assert_eq!(recent[0].path, "y");
+    assert_eq!(recent[0].ended_at, Some(40));
+    assert_eq!(recent[0].outcome, failed);
+    let range = TimeRange {
+        from: Some(5),
+        to: Some(30),
+    };
+    let history = storage.history(&range).unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].outcome, Outcome::Finished);
+    assert_eq!(storage.prune_history(30).unwrap(), 1);
+    assert_eq!(storage.recent_history(10).unwrap().len(), 1);
+
+    let ban = |address: &str, expires_at: Option<u64>| Ban {
+        address: address.parse().unwrap(),
+        banned_at: 1,
+        expires_at,
+        reason: Some("spam".to_string()),
+    };
+    storage.ban(&ban("10.0.0.2", None)).unwrap();
+    storage.ban(&ban("10.0.0.1", None)).unwrap();
+    storage.ban(&ban("10.0.0.2", Some(100))).unwrap();
+    assert_eq!(
+        storage.bans().unwrap(),
+        vec![ban("10.0.0.1", None), ban("10.0.0.2", Some(100))]
+    );
+    assert!(storage.unban(&"10.0.0.1".parse().unwrap()).unwrap());
+    assert!(!storage.unban(&"10.0.0.1".parse().unwrap()).unwrap());
+    assert_eq!(storage.bans().unwrap().len(), 1);
+
+    let position = |position: f64| SavedPosition {
+        path: "movie.mkv".to_string(),
+        position,
+        saved_at: 1,
+    };
+    assert_eq!(storage.saved_position("movie.mkv").unwrap(), None);
+    storage.save_position(&position(60.0)).unwrap();
+    storage.save_position(&position(120.5)).unwrap();
+    assert_eq!(
+        storage.saved_position("movie.mkv").unwrap(),
+        Some(position(120.5))
+    );
+    assert!(storage.forget_position("movie.mkv").unwrap());
+    assert!(!storage.forget_position("movie.mkv").unwrap());
+
+    let bookmark = |url: &str, position: f64| NewBookmark {
+        url: url.to_string(),
+        position,
+        label: "Lecture".to_string(),
+        created_at: 1,
+    };
+    let first = storage.add_bookmark(&bookmark("a.mp4", 60.0)).unwrap();
+    let second = storage.add_bookmark(&bookmark("b.mp4", 90.0)).unwrap();
+    storage.add_bookmark(&bookmark("a.mp4", 30.5)).unwrap();
+    assert_ne!(first.id, second.id);
+    assert_eq!(storage.bookmark(second.id).unwrap(), Some(second.clone()));
+    assert_eq!(storage.bookmarks(None).unwrap().len(), 3);
+    let positions: Vec<f64> = storage
+        .bookmarks(Some("a.mp4"))
+        .unwrap()
+        .iter()
+        .map(|bookmark| bookmark.position)
+        .collect();
+    assert_eq!(positions, vec![60.0, 30.5]);
+    assert!(storage.delete_bookmark(first.id).unwrap());
+    assert!(!storage.delete_bookmark(first.id).unwrap());
+    assert_eq!(storage.bookmark(first.id).unwrap(), None);
+
+    let api_key = |label: &str, hash: &str| NewApiKey {
+        label: label.to_string(),
+        scopes: vec![Scope::QueueAdd, Scope::PlaybackControl],
+        created_at: 1,
+        hash: hash.to_string(),
+    };
+    let bridge = storage.add_api_key(&api_key("irc-bridge", "aa")).unwrap();
+    let infoscreen = storage.add_api_key(&api_key("infoscreen", "bb")).unwrap();
+    assert_ne!(bridge.id, infoscreen.id);
+    assert!(storage.set_api_key_label(bridge.id, "irc").unwrap());
+    assert!(!storage.set_api_key_label(100, "irc").unwrap());
+    assert_eq!(
+        storage.api_keys().unwrap(),
+        vec![api_key("irc", "aa").with_id(bridge.id), infoscreen.clone()]
+    );
+    assert!(storage.delete_api_key(bridge.id).unwrap());
+    assert!(!storage.delete_api_key(bridge.id).unwrap());
+    assert_eq!(storage.api_keys().unwrap(), vec![infoscreen]);
+}
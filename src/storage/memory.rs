@@ -0,0 +1,189 @@
+use std::{collections::BTreeMap, net::IpAddr, sync::Mutex};
+
+use crate::{
+    api_keys::{ApiKey, NewApiKey},
+    bans::Ban,
+    bookmarks::{Bookmark, NewBookmark},
+    history::{HistoryEntry, Outcome},
+    playlists::SavedPlaylist,
+    resume::SavedPosition,
+    stats::TimeRange,
+};
+
+use super::Storage;
+
+/// Keeps everything in memory, and forgets it when dropped.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    playlists: Mutex<BTreeMap<String, SavedPlaylist>>,
+    history: Mutex<Vec<HistoryEntry>>,
+    bans: Mutex<BTreeMap<IpAddr, Ban>>,
+    positions: Mutex<BTreeMap<String, SavedPosition>>,
+    bookmarks: Mutex<BTreeMap<u64, Bookmark>>,
+    api_keys: Mutex<BTreeMap<u64, ApiKey>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn save_playlist(&self, playlist: &SavedPlaylist, replace: bool) -> anyhow::Result<bool> {
+        let mut playlists = self.playlists.lock().unwrap();
+        if !replace && playlists.contains_key(&playlist.name) {
+            return Ok(false);
+        }
+        playlists.insert(playlist.name.clone(), playlist.clone());
+        Ok(true)
+    }
+
+    fn playlist(&self, name: &str) -> anyhow::Result<Option<SavedPlaylist>> {
+        Ok(self.playlists.lock().unwrap().get(name).cloned())
+    }
+
+    fn playlist_names(&self) -> anyhow::Result<Vec<String>> {
+        Ok(self.playlists.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn delete_playlist(&self, name: &str) -> anyhow::Result<bool> {
+        Ok(self.playlists.lock().unwrap().remove(name).is_some())
+    }
+
+    fn push_history(&self, entry: &HistoryEntry) -> anyhow::Result<()> {
+        self.history.lock().unwrap().push(entry.clone());
+        Ok(())
+    }
+
+    fn end_history(
+        &self,
+        path: Option<&str>,
+        outcome: &Outcome,
+        ended_at: u64,
+    ) -> anyhow::Result<bool> {
+        let mut history = self.history.lock().unwrap();
+        match history.last_mut() {
+            Some(entry)
+                if entry.outcome == Outcome::Playing
+                    && path.is_none_or(|path| entry.path == path) =>
+            {
+                entry.ended_at = Some(ended_at);
+                entry.outcome = outcome.clone();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn history(&self, range: &TimeRange) -> anyhow::Result<Vec<HistoryEntry>> {
+        let history = self.history.lock().unwrap();
+        Ok(history
+            .iter()
+            .filter(|entry| range.contains(entry))
+            .cloned()
+            .collect())
+    }
+
+    fn recent_history(&self, count: usize) -> anyhow::Result<Vec<HistoryEntry>> {
+        let history = self.history.lock().unwrap();
+        Ok(history.iter().rev().take(count).cloned().collect())
+    }
+
+    fn prune_history(&self, cutoff: u64) -> anyhow::Result<usize> {
+        let mut history = self.history.lock().unwrap();
+        let count = history.len();
+        history.retain(|entry| entry.started_at >= cutoff);
+        Ok(count - history.len())
+    }
+
+    fn ban(&self, ban: &Ban) -> anyhow::Result<()> {
+        self.bans.lock().unwrap().insert(ban.address, ban.clone());
+        Ok(())
+    }
+
+    fn unban(&self, address: &IpAddr) -> anyhow::Result<bool> {
+        Ok(self.bans.lock().unwrap().remove(address).is_some())
+    }
+
+    fn bans(&self) -> anyhow::Result<Vec<Ban>> {
+        Ok(self.bans.lock().unwrap().values().cloned().collect())
+    }
+
+    fn save_position(&self, position: &SavedPosition) -> anyhow::Result<()> {
+        self.positions
+            .lock()
+            .unwrap()
+            .insert(position.path.clone(), position.clone());
+        Ok(())
+    }
+
+    fn saved_position(&self, path: &str) -> anyhow::Result<Option<SavedPosition>> {
+        Ok(self.positions.lock().unwrap().get(path).cloned())
+    }
+
+    fn forget_position(&self, path: &str) -> anyhow::Result<bool> {
+        Ok(self.positions.lock().unwrap().remove(path).is_some())
+    }
+
+    fn add_bookmark(&self, bookmark: &NewBookmark) -> anyhow::Result<Bookmark> {
+        let mut bookmarks = self.bookmarks.lock().unwrap();
+        let id = bookmarks.keys().last().map_or(1, |id| id + 1);
+        let bookmark = bookmark.clone().with_id(id);
+        bookmarks.insert(id, bookmark.clone());
+        Ok(bookmark)
+    }
+
+    fn bookmark(&self, id: u64) -> anyhow::Result<Option<Bookmark>> {
+        Ok(self.bookmarks.lock().unwrap().get(&id).cloned())
+    }
+
+    fn bookmarks(&self, url: Option<&str>) -> anyhow::Result<Vec<Bookmark>> {
+        let bookmarks = self.bookmarks.lock().unwrap();
+        Ok(bookmarks
+            .values()
+            .filter(|bookmark| url.is_none_or(|url| bookmark.url == url))
+            .cloned()
+            .collect())
+    }
+
+    fn delete_bookmark(&self, id: u64) -> anyhow::Result<bool> {
+        Ok(self.bookmarks.lock().unwrap().remove(&id).is_some())
+    }
+
+    fn add_api_key(&self, key: &NewApiKey) -> anyhow::Result<ApiKey> {
+        let mut api_keys = self.api_keys.lock().unwrap();
+        let id = api_keys.keys().last().map_or(1, |id| id + 1);
+        let api_key = key.clone().with_id(id);
+        api_keys.insert(id, api_key.clone());
+        Ok(api_key)
+    }
+
+    fn api_keys(&self) -> anyhow::Result<Vec<ApiKey>> {
+        Ok(self.api_keys.lock().unwrap().values().cloned().collect())
+    }
+
+    fn set_api_key_label(&self, id: u64, label: &str) -> anyhow::Result<bool> {
+        let mut api_keys = self.api_keys.lock().unwrap();
+        let api_key = api_keys.get_mut(&id);
+        let found = api_key.is_some();
+        if let Some(api_key) = api_key {
+            api_key.label = label.to_string();
+        }
+        Ok(found)
+    }
+
+    fn delete_api_key(&self, id: u64) -> anyhow::Result<bool> {
+        Ok(self.api_keys.lock().unwrap().remove(&id).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_storage() {
+        super::super::check_storage(&MemoryStorage::new());
+    }
+}
@@ -0,0 +1,538 @@
+//! The schema is brought up to date on startup by running the migrations that have not
+//! been run on the database yet. How many have been run is kept in `user_version`.
+
+use std::{net::IpAddr, path::Path, sync::Mutex};
+
+use anyhow::Context;
+use rusqlite::{Connection, OptionalExtension, Row, params, types::Type};
+
+use crate::{
+    api_keys::{ApiKey, NewApiKey, scopes_column, scopes_from_column},
+    bans::Ban,
+    bookmarks::{Bookmark, NewBookmark},
+    history::{HistoryEntry, Outcome},
+    playlists::{SavedPlaylist, SavedPlaylistItem},
+    resume::SavedPosition,
+    stats::TimeRange,
+};
+
+use super::Storage;
+
+/// The schema changes, in order. Existing migrations must never be changed, only added to.
+const MIGRATIONS: &[&str] = &[
+    // 1: Saved playlists and the play history.
+    "CREATE TABLE playlists (
+        name TEXT PRIMARY KEY,
+        saved_at INTEGER NOT NULL
+    );
+    CREATE TABLE playlist_items (
+        playlist TEXT NOT NULL REFERENCES playlists (name) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        filename TEXT NOT NULL,
+        title TEXT,
+        PRIMARY KEY (playlist, position)
+    );
+    CREATE TABLE history (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        path TEXT NOT NULL,
+        title TEXT,
+        started_at INTEGER NOT NULL,
+        ended_at INTEGER,
+        autoplay INTEGER NOT NULL,
+        queued_by TEXT,
+        live INTEGER NOT NULL,
+        outcome TEXT NOT NULL,
+        error TEXT
+    );
+    CREATE INDEX history_started_at ON history (started_at);",
+    // 2: Banned addresses.
+    "CREATE TABLE bans (
+        address TEXT PRIMARY KEY,
+        banned_at INTEGER NOT NULL,
+        expires_at INTEGER,
+        reason TEXT
+    );",
+    // 3: How far into long items playback got, for resuming them.
+    "CREATE TABLE positions (
+        path TEXT PRIMARY KEY,
+        position REAL NOT NULL,
+        saved_at INTEGER NOT NULL
+    );",
+    // 4: Bookmarks within media.
+    "CREATE TABLE bookmarks (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        url TEXT NOT NULL,
+        position REAL NOT NULL,
+        label TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX bookmarks_url ON bookmarks (url);",
+    // 5: API keys, by the hash of the key.
+    "CREATE TABLE api_keys (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        label TEXT NOT NULL,
+        scope TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        hash TEXT NOT NULL UNIQUE
+    );",
+    // 6: API keys with a list of scopes, instead of just queue or admin.
+    "UPDATE api_keys SET scope = 'queue:add,queue:remove,playback:control' WHERE scope = 'queue';
+    UPDATE api_keys SET scope = 'admin:*' WHERE scope = 'admin';",
+    // 7: Who queued an item by their subject, for those who logged in.
+    "ALTER TABLE history ADD COLUMN queued_by_subject TEXT;",
+];
+
+/// Keeps everything in an SQLite database.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// Open the database stored at `path`, or an in-memory database if no path is given,
+    /// and run any migrations it is missing.
+    pub fn open(path: Option<&Path>) -> anyhow::Result<Self> {
+        let mut conn = match path {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent).with_context(|| {
+                        format!("Failed to create directory {}", parent.display())
+                    })?;
+                }
+                Connection::open(path)
+                    .with_context(|| format!("Failed to open database at {}", path.display()))?
+            }
+            None => Connection::open_in_memory()?,
+        };
+
+        conn.execute_batch("PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL;")
+            .context("Failed to configure the database")?;
+        migrate(&mut conn)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut Connection) -> rusqlite::Result<T>) -> anyhow::Result<T> {
+        let mut conn = self.conn.lock().unwrap();
+        Ok(f(&mut conn)?)
+    }
+}
+
+const HISTORY_COLUMNS: &str = "path, title, started_at, ended_at, autoplay, queued_by, live, outcome, error, queued_by_subject";
+
+fn history_entry(row: &Row) -> rusqlite::Result<HistoryEntry> {
+    Ok(HistoryEntry {
+        path: row.get(0)?,
+        title: row.get(1)?,
+        started_at: row.get::<_, i64>(2)? as u64,
+        ended_at: row.get::<_, Option<i64>>(3)?.map(|t| t as u64),
+        autoplay: row.get(4)?,
+        queued_by: row.get(5)?,
+        live: row.get(6)?,
+        outcome: Outcome::from_columns(&row.get::<_, String>(7)?, row.get(8)?),
+        queued_by_subject: row.get(9)?,
+    })
+}
+
+fn ban(row: &Row) -> rusqlite::Result<Ban> {
+    Ok(Ban {
+        address: row
+            .get::<_, String>(0)?
+            .parse()
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, Type::Text, Box::new(e)))?,
+        banned_at: row.get::<_, i64>(1)? as u64,
+        expires_at: row.get::<_, Option<i64>>(2)?.map(|t| t as u64),
+        reason: row.get(3)?,
+    })
+}
+
+const BOOKMARK_COLUMNS: &str = "id, url, position, label, created_at";
+
+fn bookmark(row: &Row) -> rusqlite::Result<Bookmark> {
+    Ok(Bookmark {
+        id: row.get::<_, i64>(0)? as u64,
+        url: row.get(1)?,
+        position: row.get(2)?,
+        label: row.get(3)?,
+        created_at: row.get::<_, i64>(4)? as u64,
+    })
+}
+
+const API_KEY_COLUMNS: &str = "id, label, scope, created_at, hash";
+
+fn api_key(row: &Row) -> rusqlite::Result<ApiKey> {
+    Ok(ApiKey {
+        id: row.get::<_, i64>(0)? as u64,
+        label: row.get(1)?,
+        scopes: scopes_from_column(&row.get::<_, String>(2)?),
+        created_at: row.get::<_, i64>(3)? as u64,
+        hash: row.get(4)?,
+    })
+}
+
+impl Storage for SqliteStorage {
+    fn save_playlist(&self, playlist: &SavedPlaylist, replace: bool) -> anyhow::Result<bool> {
+        self.with(|conn| {
+            let tx = conn.transaction()?;
+            let saved = tx.execute(
+                if replace {
+                    "INSERT OR REPLACE INTO playlists (name, saved_at) VALUES (?1, ?2)"
+                } else {
+                    "INSERT OR IGNORE INTO playlists (name, saved_at) VALUES (?1, ?2)"
+                },
+                params![playlist.name, playlist.saved_at as i64],
+            )? > 0;
+            if saved {
+                let mut insert_item = tx.prepare(
+                    "INSERT INTO playlist_items (playlist, position, filename, title)
+                     VALUES (?1, ?2, ?3, ?4)",
+                )?;
+                for (position, item) in playlist.items.iter().enumerate() {
+                    insert_item.execute(params![
+                        playlist.name,
+                        position as i64,
+                        item.filename,
+                        item.title
+                    ])?;
+                }
+            }
+            tx.commit()?;
+            Ok(saved)
+        })
+    }
+
+    fn playlist(&self, name: &str) -> anyhow::Result<Option<SavedPlaylist>> {
+        self.with(|conn| {
+            let Some(saved_at) = conn
+                .query_row(
+                    "SELECT saved_at FROM playlists WHERE name = ?1",
+                    params![name],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()?
+            else {
+                return Ok(None);
+            };
+            let items = conn
+                .prepare(
+                    "SELECT filename, title FROM playlist_items
+                     WHERE playlist = ?1 ORDER BY position",
+                )?
+                .query_map(params![name], |row| {
+                    Ok(SavedPlaylistItem {
+                        filename: row.get(0)?,
+                        title: row.get(1)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(Some(SavedPlaylist {
+                name: name.to_string(),
+                saved_at: saved_at as u64,
+                items,
+            }))
+        })
+    }
+
+    fn playlist_names(&self) -> anyhow::Result<Vec<String>> {
+        self.with(|conn| {
+            conn.prepare("SELECT name FROM playlists ORDER BY name")?
+                .query_map([], |row| row.get(0))?
+                .collect()
+        })
+    }
+
+    fn delete_playlist(&self, name: &str) -> anyhow::Result<bool> {
+        self.with(|conn| conn.execute("DELETE FROM playlists WHERE name = ?1", params![name]))
+            .map(|deleted| deleted > 0)
+    }
+
+    fn push_history(&self, entry: &HistoryEntry) -> anyhow::Result<()> {
+        let (outcome, error) = entry.outcome.to_columns();
+        self.with(|conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO history ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                    HISTORY_COLUMNS
+                ),
+                params![
+                    entry.path,
+                    entry.title,
+                    entry.started_at as i64,
+                    entry.ended_at.map(|t| t as i64),
+                    entry.autoplay,
+                    entry.queued_by,
+                    entry.live,
+                    outcome,
+                    error,
+                    entry.queued_by_subject
+                ],
+            )
+        })?;
+        Ok(())
+    }
+
+    fn end_history(
+        &self,
+        path: Option<&str>,
+        outcome: &Outcome,
+        ended_at: u64,
+    ) -> anyhow::Result<bool> {
+        let (outcome, error) = outcome.to_columns();
+        let updated = self.with(|conn| {
+            conn.execute(
+                "UPDATE history SET ended_at = ?1, outcome = ?2, error = ?3
+                 WHERE id = (SELECT max(id) FROM history)
+                   AND outcome = 'playing'
+                   AND (?4 IS NULL OR path = ?4)",
+                params![ended_at as i64, outcome, error, path],
+            )
+        })?;
+        Ok(updated > 0)
+    }
+
+    fn history(&self, range: &TimeRange) -> anyhow::Result<Vec<HistoryEntry>> {
+        self.with(|conn| {
+            conn.prepare(&format!(
+                "SELECT {} FROM history
+                 WHERE (?1 IS NULL OR started_at >= ?1) AND (?2 IS NULL OR started_at < ?2)
+                 ORDER BY id",
+                HISTORY_COLUMNS
+            ))?
+            .query_map(
+                params![range.from.map(|t| t as i64), range.to.map(|t| t as i64)],
+                history_entry,
+            )?
+            .collect()
+        })
+    }
+
+    fn recent_history(&self, count: usize) -> anyhow::Result<Vec<HistoryEntry>> {
+        self.with(|conn| {
+            conn.prepare(&format!(
+                "SELECT {} FROM history ORDER BY id DESC LIMIT ?1",
+                HISTORY_COLUMNS
+            ))?
+            .query_map(params![count as i64], history_entry)?
+            .collect()
+        })
+    }
+
+    fn prune_history(&self, cutoff: u64) -> anyhow::Result<usize> {
+        self.with(|conn| {
+            conn.execute(
+                "DELETE FROM history WHERE started_at < ?1",
+                params![cutoff as i64],
+            )
+        })
+    }
+
+    fn ban(&self, ban: &Ban) -> anyhow::Result<()> {
+        self.with(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO bans (address, banned_at, expires_at, reason)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    ban.address.to_string(),
+                    ban.banned_at as i64,
+                    ban.expires_at.map(|t| t as i64),
+                    ban.reason
+                ],
+            )
+        })?;
+        Ok(())
+    }
+
+    fn unban(&self, address: &IpAddr) -> anyhow::Result<bool> {
+        self.with(|conn| {
+            conn.execute(
+                "DELETE FROM bans WHERE address = ?1",
+                params![address.to_string()],
+            )
+        })
+        .map(|deleted| deleted > 0)
+    }
+
+    fn bans(&self) -> anyhow::Result<Vec<Ban>> {
+        let mut bans: Vec<Ban> = self.with(|conn| {
+            conn.prepare("SELECT address, banned_at, expires_at, reason FROM bans")?
+                .query_map([], ban)?
+                .collect()
+        })?;
+        // Sorted as addresses, not as text.
+        bans.sort_by_key(|ban| ban.address);
+        Ok(bans)
+    }
+
+    fn save_position(&self, position: &SavedPosition) -> anyhow::Result<()> {
+        self.with(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO positions (path, position, saved_at) VALUES (?1, ?2, ?3)",
+                params![position.path, position.position, position.saved_at as i64],
+            )
+        })?;
+        Ok(())
+    }
+
+    fn saved_position(&self, path: &str) -> anyhow::Result<Option<SavedPosition>> {
+        self.with(|conn| {
+            conn.query_row(
+                "SELECT position, saved_at FROM positions WHERE path = ?1",
+                params![path],
+                |row| {
+                    Ok(SavedPosition {
+                        path: path.to_string(),
+                        position: row.get(0)?,
+                        saved_at: row.get::<_, i64>(1)? as u64,
+                    })
+                },
+            )
+            .optional()
+        })
+    }
+
+    fn forget_position(&self, path: &str) -> anyhow::Result<bool> {
+        self.with(|conn| conn.execute("DELETE FROM positions WHERE path = ?1", params![path]))
+            .map(|deleted| deleted > 0)
+    }
+
+    fn add_bookmark(&self, bookmark: &NewBookmark) -> anyhow::Result<Bookmark> {
+        let id = self.with(|conn| {
+            conn.execute(
+                "INSERT INTO bookmarks (url, position, label, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    bookmark.url,
+                    bookmark.position,
+                    bookmark.label,
+                    bookmark.created_at as i64
+                ],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })?;
+        Ok(bookmark.clone().with_id(id as u64))
+    }
+
+    fn bookmark(&self, id: u64) -> anyhow::Result<Option<Bookmark>> {
+        self.with(|conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM bookmarks WHERE id = ?1", BOOKMARK_COLUMNS),
+                params![id as i64],
+                bookmark,
+            )
+            .optional()
+        })
+    }
+
+    fn bookmarks(&self, url: Option<&str>) -> anyhow::Result<Vec<Bookmark>> {
+        self.with(|conn| {
+            conn.prepare(&format!(
+                "SELECT {} FROM bookmarks WHERE ?1 IS NULL OR url = ?1 ORDER BY id",
+                BOOKMARK_COLUMNS
+            ))?
+            .query_map(params![url], bookmark)?
+            .collect()
+        })
+    }
+
+    fn delete_bookmark(&self, id: u64) -> anyhow::Result<bool> {
+        self.with(|conn| conn.execute("DELETE FROM bookmarks WHERE id = ?1", params![id as i64]))
+            .map(|deleted| deleted > 0)
+    }
+
+    fn add_api_key(&self, key: &NewApiKey) -> anyhow::Result<ApiKey> {
+        let id = self.with(|conn| {
+            conn.execute(
+                "INSERT INTO api_keys (label, scope, created_at, hash) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    key.label,
+                    scopes_column(&key.scopes),
+                    key.created_at as i64,
+                    key.hash
+                ],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })?;
+        Ok(key.clone().with_id(id as u64))
+    }
+
+    fn api_keys(&self) -> anyhow::Result<Vec<ApiKey>> {
+        self.with(|conn| {
+            conn.prepare(&format!(
+                "SELECT {} FROM api_keys ORDER BY id",
+                API_KEY_COLUMNS
+            ))?
+            .query_map([], api_key)?
+            .collect()
+        })
+    }
+
+    fn set_api_key_label(&self, id: u64, label: &str) -> anyhow::Result<bool> {
+        self.with(|conn| {
+            conn.execute(
+                "UPDATE api_keys SET label = ?1 WHERE id = ?2",
+                params![label, id as i64],
+            )
+        })
+        .map(|updated| updated > 0)
+    }
+
+    fn delete_api_key(&self, id: u64) -> anyhow::Result<bool> {
+        self.with(|conn| conn.execute("DELETE FROM api_keys WHERE id = ?1", params![id as i64]))
+            .map(|deleted| deleted > 0)
+    }
+}
+
+fn migrate(conn: &mut Connection) -> anyhow::Result<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let version = version as usize;
+    if version > MIGRATIONS.len() {
+        anyhow::bail!(
+            "The database is from a newer version of greg-ng (schema version {}, expected at most {})",
+            version,
+            MIGRATIONS.len()
+        );
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        log::info!("Migrating the database to schema version {}", index + 1);
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)
+            .with_context(|| format!("Failed to migrate the database to version {}", index + 1))?;
+        tx.pragma_update(None, "user_version", (index + 1) as i64)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_storage() {
+        super::super::check_storage(&SqliteStorage::open(None).unwrap());
+    }
+
+    #[test]
+    fn test_migrations() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state/greg.db");
+
+        let db = SqliteStorage::open(Some(&path)).unwrap();
+        let version: i64 = db
+            .with(|conn| conn.query_row("PRAGMA user_version", [], |row| row.get(0)))
+            .unwrap();
+        assert_eq!(version as usize, MIGRATIONS.len());
+        drop(db);
+
+        // Opening it again runs nothing twice.
+        SqliteStorage::open(Some(&path)).unwrap();
+
+        let db = SqliteStorage::open(None).unwrap();
+        db.with(|conn| conn.pragma_update(None, "user_version", MIGRATIONS.len() as i64 + 1))
+            .unwrap();
+        let mut conn = db.conn.lock().unwrap();
+        assert!(migrate(&mut conn).is_err());
+    }
+}
@@ -0,0 +1,280 @@
+//! Synchronized playback between several greg-ng instances.
+//!
+//! The leader publishes its playlist and playback position on `/sync`, and
+//! followers connect to it over a websocket and mirror it. Small drift is
+//! corrected by speeding up or slowing down mpv, larger drift by seeking.
+//!
+//! The position is timestamped with the wall clock of the leader, so the
+//! clocks of all instances should be kept in sync, e.g. with NTP.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    Router,
+    extract::{
+        State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    response::Response,
+    routing::get,
+};
+use futures::StreamExt;
+use mpvipc_async::{Mpv, MpvExt, PlaylistAddOptions, PlaylistAddTypeOptions, Switch};
+use serde::{Deserialize, Serialize};
+
+use crate::player::PlayerHandle;
+
+/// How often the leader sends its state to followers.
+const BROADCAST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long a follower waits before reconnecting to the leader.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Drift that is small enough to be ignored, in seconds.
+const DRIFT_TOLERANCE: f64 = 0.04;
+
+/// Drift that is too large to be corrected by adjusting the speed, in seconds.
+const SEEK_THRESHOLD: f64 = 2.0;
+
+/// The largest speed adjustment used for drift correction, as a fraction of normal speed.
+const MAX_SPEED_ADJUSTMENT: f64 = 0.05;
+
+/// A snapshot of the playback state of the leader.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncState {
+    pub playlist: Vec<String>,
+    pub current: Option<usize>,
+    pub playing: bool,
+    /// Playback position of the current item in seconds.
+    pub position: Option<f64>,
+    /// When the position was read, in milliseconds since the unix epoch.
+    pub timestamp_ms: u64,
+}
+
+impl SyncState {
+    async fn read(player: &PlayerHandle) -> anyhow::Result<Self> {
+        let playlist = player.playlist().await?;
+        Ok(Self {
+            current: playlist.iter().position(|entry| entry.current),
+            playlist: playlist.into_iter().map(|entry| entry.filename).collect(),
+            playing: player.is_playing().await?,
+            position: player.get_time_pos().await?,
+            timestamp_ms: now_ms(),
+        })
+    }
+
+    /// Where the leader should be right now, accounting for the time the state spent in transit.
+    fn expected_position(&self) -> Option<f64> {
+        let position = self.position?;
+        if !self.playing {
+            return Some(position);
+        }
+        let elapsed_ms = now_ms().saturating_sub(self.timestamp_ms);
+        Some(position + elapsed_ms as f64 / 1000.0)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+// ------ //
+// Leader //
+// ------ //
+
+/// The `/sync` endpoint followers connect to.
+pub fn sync_leader_routes(player: PlayerHandle) -> Router {
+    Router::new()
+        .route("/sync", get(sync_handler))
+        .with_state(player)
+}
+
+async fn sync_handler(ws: WebSocketUpgrade, State(player): State<PlayerHandle>) -> Response {
+    ws.on_upgrade(move |socket| async move {
+        log::info!("Sync follower connected");
+        if let Err(e) = broadcast_loop(socket, player).await {
+            log::debug!("Sync follower connection ended: {}", e);
+        }
+        log::info!("Sync follower disconnected");
+    })
+}
+
+async fn broadcast_loop(mut socket: WebSocket, player: PlayerHandle) -> anyhow::Result<()> {
+    let mut interval = tokio::time::interval(BROADCAST_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let state = SyncState::read(&player).await?;
+                socket
+                    .send(Message::Text(serde_json::to_string(&state)?.into()))
+                    .await?;
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Err(e)) => return Err(e.into()),
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+// -------- //
+// Follower //
+// -------- //
+
+/// What to do about the drift between the follower and the leader.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DriftCorrection {
+    Seek,
+    Speed(f64),
+}
+
+/// `drift` is how far ahead of the leader the follower is, in seconds.
+fn drift_correction(drift: f64) -> DriftCorrection {
+    if drift.abs() > SEEK_THRESHOLD {
+        DriftCorrection::Seek
+    } else if drift.abs() < DRIFT_TOLERANCE {
+        DriftCorrection::Speed(1.0)
+    } else {
+        // Aim to catch up within roughly two seconds.
+        DriftCorrection::Speed(
+            1.0 - (drift / 2.0).clamp(-MAX_SPEED_ADJUSTMENT, MAX_SPEED_ADJUSTMENT),
+        )
+    }
+}
+
+/// Follow the leader at `leader_url` (e.g. `ws://host:8008/sync`) forever, reconnecting when
+/// the connection is lost.
+pub async fn follow_leader(mpv: Mpv, leader_url: String) {
+    loop {
+        log::info!("Connecting to sync leader at {}", leader_url);
+        if let Err(e) = follow_connection(&mpv, &leader_url).await {
+            log::warn!("Lost connection to sync leader: {}", e);
+        }
+
+        if let Err(e) = mpv.set_property("speed", 1.0).await {
+            log::warn!("Failed to reset playback speed: {}", e);
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn follow_connection(mpv: &Mpv, leader_url: &str) -> anyhow::Result<()> {
+    let (mut socket, _) = tokio_tungstenite::connect_async(leader_url).await?;
+    log::info!("Connected to sync leader");
+
+    let mut speed = 1.0;
+    while let Some(message) = socket.next().await {
+        let state: SyncState = match message? {
+            tokio_tungstenite::tungstenite::Message::Text(text) => serde_json::from_str(&text)?,
+            tokio_tungstenite::tungstenite::Message::Close(_) => break,
+            _ => continue,
+        };
+        apply_state(mpv, &state, &mut speed).await?;
+    }
+
+    socket.close(None).await.ok();
+    Ok(())
+}
+
+async fn apply_state(mpv: &Mpv, state: &SyncState, speed: &mut f64) -> anyhow::Result<()> {
+    let playlist: Vec<String> = mpv
+        .get_playlist()
+        .await?
+        .0
+        .into_iter()
+        .map(|entry| entry.filename)
+        .collect();
+
+    if playlist != state.playlist {
+        log::debug!("Replacing playlist with the one of the sync leader");
+        for (i, url) in state.playlist.iter().enumerate() {
+            let option = if i == 0 {
+                PlaylistAddOptions::Replace
+            } else {
+                PlaylistAddOptions::Append
+            };
+            mpv.playlist_add(url, PlaylistAddTypeOptions::File, option)
+                .await?;
+        }
+        if state.playlist.is_empty() {
+            mpv.stop().await?;
+        }
+    }
+
+    let current = mpv
+        .get_playlist()
+        .await?
+        .0
+        .iter()
+        .position(|entry| entry.current);
+    if let Some(index) = state.current
+        && current != state.current
+    {
+        log::debug!(
+            "Switching to playlist item {} to follow the sync leader",
+            index
+        );
+        mpv.playlist_play_id(index).await?;
+    }
+
+    if MpvExt::is_playing(mpv).await? != state.playing {
+        mpv.set_playback(if state.playing {
+            Switch::On
+        } else {
+            Switch::Off
+        })
+        .await?;
+    }
+
+    let (Some(expected), Some(position)) = (state.expected_position(), mpv.get_time_pos().await?)
+    else {
+        return Ok(());
+    };
+
+    let new_speed = match drift_correction(position - expected) {
+        DriftCorrection::Seek => {
+            log::debug!(
+                "Drifted {:.2}s from the sync leader, seeking",
+                position - expected
+            );
+            MpvExt::seek(mpv, expected, mpvipc_async::SeekOptions::Absolute).await?;
+            1.0
+        }
+        DriftCorrection::Speed(_) if !state.playing => 1.0,
+        DriftCorrection::Speed(speed) => speed,
+    };
+
+    if new_speed != *speed {
+        log::trace!(
+            "Adjusting playback speed to {:.3} to follow the sync leader",
+            new_speed
+        );
+        mpv.set_property("speed", new_speed).await?;
+        *speed = new_speed;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drift_correction() {
+        assert_eq!(drift_correction(0.01), DriftCorrection::Speed(1.0));
+        assert_eq!(drift_correction(-0.01), DriftCorrection::Speed(1.0));
+        assert_eq!(drift_correction(5.0), DriftCorrection::Seek);
+        assert_eq!(drift_correction(-5.0), DriftCorrection::Seek);
+
+        // Ahead of the leader slows down, behind speeds up, within bounds
+        assert_eq!(drift_correction(0.05), DriftCorrection::Speed(0.975));
+        assert_eq!(drift_correction(-1.0), DriftCorrection::Speed(1.05));
+        assert_eq!(drift_correction(1.0), DriftCorrection::Speed(0.95));
+    }
+}
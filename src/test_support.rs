@@ -0,0 +1,295 @@
+//! Helpers for testing the API end to end, without a real mpv or a display.
+//!
+//! [`FakeMpv`] speaks just enough of the mpv JSON IPC protocol for the handlers: it keeps a
+//! map of properties, answers `get_property`, `set_property` and `observe_property`, and
+//! sends whatever events the test scripts.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use mpvipc_async::Mpv;
+use serde_json::{Value, json};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::broadcast,
+};
+
+#[derive(Debug, Default)]
+struct FakeMpvState {
+    properties: BTreeMap<String, Value>,
+    /// Which properties are observed, by observer id.
+    observers: Vec<(u64, String)>,
+    commands: Vec<Vec<Value>>,
+    /// The id of the last playlist entry added, which like in mpv is never reused.
+    last_entry_id: u64,
+}
+
+/// A fake mpv, listening on a unix socket in a temporary directory.
+///
+/// Every connection sees the same properties, and gets every event.
+#[derive(Debug)]
+pub struct FakeMpv {
+    _dir: TempDir,
+    socket_path: PathBuf,
+    state: Arc<Mutex<FakeMpvState>>,
+    events: broadcast::Sender<Value>,
+}
+
+impl FakeMpv {
+    /// Start listening, with the properties of an idle mpv with an empty playlist.
+    pub fn start() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("mpv.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        let properties = [
+            ("volume", json!(50.0)),
+            ("mute", json!(false)),
+            ("pause", json!(true)),
+            ("speed", json!(1.0)),
+            ("loop-playlist", json!("no")),
+            ("playlist", json!([])),
+            ("idle-active", json!(true)),
+        ];
+        let state = Arc::new(Mutex::new(FakeMpvState {
+            properties: properties
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            ..Default::default()
+        }));
+        let (events, _) = broadcast::channel(256);
+
+        let (accept_state, accept_events) = (state.clone(), events.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_connection(
+                    stream,
+                    accept_state.clone(),
+                    accept_events.subscribe(),
+                ));
+            }
+        });
+
+        Self {
+            _dir: dir,
+            socket_path,
+            state,
+            events,
+        }
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Connect a client to this fake, like the server does to a real mpv.
+    pub async fn connect(&self) -> Mpv {
+        Mpv::connect(self.socket_path.to_str().unwrap())
+            .await
+            .unwrap()
+    }
+
+    pub fn property(&self, name: &str) -> Option<Value> {
+        self.state.lock().unwrap().properties.get(name).cloned()
+    }
+
+    /// Change a property as if mpv changed it, notifying its observers.
+    pub fn set_property(&self, name: &str, value: Value) {
+        let changes = set_property(&mut self.state.lock().unwrap(), name, value);
+        for change in changes {
+            let _ = self.events.send(change);
+        }
+    }
+
+    /// Every command received so far, oldest first.
+    pub fn commands(&self) -> Vec<Vec<Value>> {
+        self.state.lock().unwrap().commands.clone()
+    }
+
+    /// Send an event, like `{"event": "start-file", "playlist_entry_id": 1}`, to every client.
+    pub fn emit(&self, event: Value) {
+        let _ = self.events.send(event);
+    }
+
+    /// Wait until `check` holds for this fake, failing the test if it takes too long.
+    pub async fn wait_for(&self, check: impl Fn(&Self) -> bool) {
+        for _ in 0..200 {
+            if check(self) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Timed out waiting on the fake mpv");
+    }
+}
+
+/// Set a property, returning the `property-change` events for its observers.
+fn set_property(state: &mut FakeMpvState, name: &str, value: Value) -> Vec<Value> {
+    state.properties.insert(name.to_string(), value.clone());
+    state
+        .observers
+        .iter()
+        .filter(|(_, property)| property == name)
+        .map(|(id, _)| json!({"event": "property-change", "id": id, "name": name, "data": value}))
+        .collect()
+}
+
+/// Handle one command, returning its response and the events it caused.
+fn handle_command(state: &mut FakeMpvState, command: &[Value]) -> (Value, Vec<Value>) {
+    state.commands.push(command.to_vec());
+
+    let name = command.first().and_then(Value::as_str).unwrap_or_default();
+    let arg = |i: usize| command.get(i).cloned().unwrap_or(Value::Null);
+    match name {
+        "get_property" => match state.properties.get(arg(1).as_str().unwrap_or_default()) {
+            Some(value) => (json!({"error": "success", "data": value}), vec![]),
+            None => (json!({"error": "property unavailable"}), vec![]),
+        },
+        "set_property" => {
+            let events = set_property(state, arg(1).as_str().unwrap_or_default(), arg(2));
+            (json!({"error": "success"}), events)
+        }
+        "observe_property" => {
+            let (id, property) = (arg(1).as_u64().unwrap_or_default(), arg(2));
+            let property = property.as_str().unwrap_or_default().to_string();
+            // mpv sends the current value right away.
+            let event = json!({
+                "event": "property-change",
+                "id": id,
+                "name": property,
+                "data": state.properties.get(&property),
+            });
+            state.observers.push((id, property));
+            (json!({"error": "success"}), vec![event])
+        }
+        "unobserve_property" => {
+            let id = arg(1).as_u64();
+            state
+                .observers
+                .retain(|(observer, _)| Some(*observer) != id);
+            (json!({"error": "success"}), vec![])
+        }
+        "loadfile" => {
+            let filename = arg(1);
+            state.last_entry_id += 1;
+            let id = state.last_entry_id;
+            let playlist = state.properties.entry("playlist".to_string()).or_default();
+            let mut items = playlist.as_array().cloned().unwrap_or_default();
+            items.push(json!({"filename": filename, "id": id}));
+            let events = set_property(state, "playlist", Value::Array(items));
+            (
+                json!({"error": "success", "data": {"playlist_entry_id": id}}),
+                events,
+            )
+        }
+        "playlist-remove" => {
+            let index = arg(1).as_u64().unwrap_or_default() as usize;
+            let playlist = state.properties.entry("playlist".to_string()).or_default();
+            let mut items = playlist.as_array().cloned().unwrap_or_default();
+            if index >= items.len() {
+                return (json!({"error": "error running command"}), vec![]);
+            }
+            items.remove(index);
+            let events = set_property(state, "playlist", Value::Array(items));
+            (json!({"error": "success"}), events)
+        }
+        // Reversed instead of shuffled, so that tests know the order it ends up in.
+        "playlist-shuffle" => {
+            let playlist = state.properties.entry("playlist".to_string()).or_default();
+            let mut items = playlist.as_array().cloned().unwrap_or_default();
+            items.reverse();
+            let events = set_property(state, "playlist", Value::Array(items));
+            (json!({"error": "success"}), events)
+        }
+        _ => (json!({"error": "success"}), vec![]),
+    }
+}
+
+async fn serve_connection(
+    stream: UnixStream,
+    state: Arc<Mutex<FakeMpvState>>,
+    mut events: broadcast::Receiver<Value>,
+) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    loop {
+        let outgoing = tokio::select! {
+            line = lines.next_line() => {
+                let Ok(Some(line)) = line else {
+                    return;
+                };
+                let Ok(request) = serde_json::from_str::<Value>(&line) else {
+                    continue;
+                };
+                let command = request["command"].as_array().cloned().unwrap_or_default();
+                let (mut response, events) = handle_command(&mut state.lock().unwrap(), &command);
+                if let Some(request_id) = request.get("request_id") {
+                    response["request_id"] = request_id.clone();
+                }
+                std::iter::once(response).chain(events).collect()
+            }
+            event = events.recv() => match event {
+                Ok(event) => vec![event],
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+        };
+
+        for message in outgoing {
+            let line = format!("{}\n", message);
+            if write.write_all(line.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fake_mpv() {
+        let mpv = FakeMpv::start();
+        let stream = UnixStream::connect(mpv.socket_path()).await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let mut request = async |command: Value| {
+            let line = format!("{}\n", json!({"command": command, "request_id": 7}));
+            write.write_all(line.as_bytes()).await.unwrap();
+        };
+        let mut next = async || -> Value {
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap()
+        };
+
+        request(json!(["get_property", "volume"])).await;
+        assert_eq!(
+            next().await,
+            json!({"error": "success", "data": 50.0, "request_id": 7})
+        );
+        request(json!(["get_property", "nonexistent"])).await;
+        assert_eq!(next().await["error"], "property unavailable");
+
+        request(json!(["observe_property", 1, "volume"])).await;
+        assert_eq!(next().await["error"], "success");
+        assert_eq!(next().await["data"], 50.0);
+        request(json!(["set_property", "volume", 30.0])).await;
+        assert_eq!(next().await["error"], "success");
+        assert_eq!(
+            next().await,
+            json!({"event": "property-change", "id": 1, "name": "volume", "data": 30.0})
+        );
+        assert_eq!(mpv.property("volume"), Some(json!(30.0)));
+
+        mpv.emit(json!({"event": "start-file", "playlist_entry_id": 1}));
+        assert_eq!(next().await["event"], "start-file");
+        assert_eq!(mpv.commands().len(), 4);
+    }
+}
@@ -0,0 +1,66 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use axum_server::tls_rustls::RustlsConfig;
+
+/// How often the certificate files are checked for changes.
+const CERTIFICATE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Load a TLS certificate and private key, and keep reloading them whenever the files change,
+/// so that renewed certificates (e.g. from ACME clients) are picked up without a restart.
+pub async fn load_tls_config(
+    cert_path: PathBuf,
+    key_path: PathBuf,
+) -> anyhow::Result<RustlsConfig> {
+    let config = RustlsConfig::from_pem_file(&cert_path, &key_path)
+        .await
+        .context(format!(
+            "Failed to load TLS certificate '{}' and key '{}'",
+            cert_path.display(),
+            key_path.display()
+        ))?;
+
+    log::debug!(
+        "Loaded TLS certificate '{}' and key '{}'",
+        cert_path.display(),
+        key_path.display()
+    );
+
+    tokio::spawn(certificate_reloader(config.clone(), cert_path, key_path));
+
+    Ok(config)
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+async fn certificate_reloader(config: RustlsConfig, cert_path: PathBuf, key_path: PathBuf) {
+    let mut last_modified = (modified_time(&cert_path), modified_time(&key_path));
+
+    loop {
+        tokio::time::sleep(CERTIFICATE_POLL_INTERVAL).await;
+
+        let modified = (modified_time(&cert_path), modified_time(&key_path));
+        if modified == last_modified {
+            continue;
+        }
+
+        // NOTE: the new modification time is saved even if reloading fails, to avoid
+        //       spamming the log while a certificate renewal is only halfway done.
+        //       Whenever the other file is written, the pair will be reloaded.
+        last_modified = modified;
+
+        match config.reload_from_pem_file(&cert_path, &key_path).await {
+            Ok(()) => log::info!("Reloaded TLS certificate '{}'", cert_path.display()),
+            Err(e) => log::error!(
+                "Failed to reload TLS certificate '{}': {}",
+                cert_path.display(),
+                e
+            ),
+        }
+    }
+}
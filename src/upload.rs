@@ -0,0 +1,146 @@
+//! A spool directory for media files uploaded through the API.
+//!
+//! Uploaded files are only kept around while they are in the playlist. Once an
+//! upload has been played, it is removed from the playlist and deleted.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+
+use crate::player::PlayerHandle;
+
+/// How often the spool directory is cleaned up.
+const GC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Files younger than this are never deleted, so that an upload isn't removed
+/// between being written and being added to the playlist.
+const GC_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct UploadSpool {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl UploadSpool {
+    /// Use `dir` as the spool directory, creating it if necessary.
+    pub fn new(dir: &Path, max_size: u64) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create upload directory {}", dir.display()))?;
+
+        Ok(Self {
+            // The playlist refers to uploads by absolute path, so that is what we compare with.
+            dir: dir.canonicalize()?,
+            max_size,
+        })
+    }
+
+    /// The largest file that can be uploaded, in bytes.
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// A fresh path to store an upload named `filename` at.
+    ///
+    /// Only the extension of the original name is kept, since mpv uses it to detect the format.
+    pub fn new_path(&self, filename: Option<&str>) -> PathBuf {
+        let extension = filename
+            .and_then(|name| Path::new(name).extension())
+            .and_then(|ext| ext.to_str())
+            .filter(|ext| ext.len() <= 10 && ext.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        let name = format!("{:016x}", rand::random::<u64>());
+        match extension {
+            Some(extension) => self
+                .dir
+                .join(format!("{}.{}", name, extension.to_lowercase())),
+            None => self.dir.join(name),
+        }
+    }
+
+    fn contains(&self, filename: &str) -> bool {
+        Path::new(filename).parent() == Some(self.dir.as_path())
+    }
+
+    /// Remove uploads that have been played from the playlist, and delete
+    /// every upload that is no longer in the playlist.
+    async fn collect_garbage(&self, player: &PlayerHandle) -> anyhow::Result<()> {
+        let playlist = player.playlist().await?;
+        let current = playlist.iter().position(|entry| entry.current);
+
+        // Looping playlists will come back around to the uploads.
+        if let Some(current) = current
+            && !player.is_looping().await?
+        {
+            // Back to front, so that the indices stay valid.
+            for index in (0..current).rev() {
+                if self.contains(&playlist[index].filename) {
+                    log::debug!("Removing played upload '{}'", playlist[index].filename);
+                    player.playlist_remove(index).await?;
+                }
+            }
+        }
+
+        let playlist = player.playlist().await?;
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let age = entry
+                .metadata()
+                .await?
+                .modified()
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .unwrap_or_default();
+
+            let in_playlist = playlist
+                .iter()
+                .any(|item| Path::new(&item.filename) == path);
+
+            if !in_playlist && age > GC_GRACE_PERIOD {
+                log::debug!("Deleting upload {}", path.display());
+                tokio::fs::remove_file(&path).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Periodically clean up the spool directory.
+    pub fn spawn_gc(&self, player: PlayerHandle) {
+        let spool = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(GC_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = spool.collect_garbage(&player).await {
+                    log::warn!("Failed to clean up uploads: {:#}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = UploadSpool::new(dir.path(), 1024).unwrap();
+
+        let path = spool.new_path(Some("../../meme.MP4"));
+        assert!(spool.contains(path.to_str().unwrap()));
+        assert_eq!(path.extension().unwrap(), "mp4");
+
+        let path = spool.new_path(Some("weird.ext/../../etc"));
+        assert!(spool.contains(path.to_str().unwrap()));
+        assert_eq!(path.extension(), None);
+
+        assert!(!spool.contains("/tmp/meme.mp4"));
+    }
+}
@@ -1,5 +1,13 @@
+mod clients;
 mod connection_counter;
 mod id_pool;
+mod paging;
+mod timestamp;
+mod url;
 
+pub use clients::ClientRegistry;
 pub use connection_counter::ConnectionEvent;
-pub use id_pool::IdPool;
+pub use id_pool::{IdPool, IdPoolHandle, PooledId};
+pub use paging::Page;
+pub use timestamp::parse_timestamp;
+pub use url::canonicalize_url;
@@ -0,0 +1,196 @@
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use tokio::sync::Notify;
+
+use crate::{history::now, oidc::AuthenticatedUser, server::ClientAddr};
+
+/// A websocket client, as listed by `/api/admin/connections`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConnectedClient {
+    pub id: u64,
+    /// What the client calls itself, like `grzegorz-clients`.
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub address: String,
+    /// In seconds since the unix epoch.
+    pub connected_at: u64,
+    /// When the client last sent a message, in seconds since the unix epoch.
+    pub last_activity: u64,
+    /// How many messages to the client were dropped because it was too slow to take them.
+    pub dropped_messages: u64,
+}
+
+/// How well messages have reached the websocket clients since startup, as shown by
+/// `/api/admin/connections/delivery`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DeliveryStats {
+    /// Messages dropped because the client was too slow to take them.
+    pub dropped_messages: u64,
+    /// Clients disconnected because they could not keep up at all.
+    pub slow_disconnects: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    client: ConnectedClient,
+    ip: Option<IpAddr>,
+    kicked: Arc<Notify>,
+    control_token: Option<String>,
+    user: Option<AuthenticatedUser>,
+    identity: Option<String>,
+}
+
+/// The websocket clients that are connected right now, by channel id.
+#[derive(Debug, Clone, Default)]
+pub struct ClientRegistry {
+    clients: Arc<Mutex<BTreeMap<u64, Entry>>>,
+    delivery: Arc<Mutex<DeliveryStats>>,
+}
+
+impl ClientRegistry {
+    /// Add a client, returning what is notified when it is kicked.
+    pub fn connect(
+        &self,
+        id: u64,
+        addr: &ClientAddr,
+        name: Option<String>,
+        version: Option<String>,
+    ) -> Arc<Notify> {
+        let now = now();
+        let kicked = Arc::new(Notify::new());
+        self.clients.lock().unwrap().insert(
+            id,
+            Entry {
+                client: ConnectedClient {
+                    id,
+                    name,
+                    version,
+                    address: addr.to_string(),
+                    connected_at: now,
+                    last_activity: now,
+                    dropped_messages: 0,
+                },
+                ip: match addr {
+                    ClientAddr::Tcp(addr) => Some(addr.ip().to_canonical()),
+                    ClientAddr::Unix => None,
+                },
+                kicked: kicked.clone(),
+                control_token: None,
+                user: None,
+                identity: None,
+            },
+        );
+        kicked
+    }
+
+    /// Name a client after it has connected, keeping its version if it gives none.
+    pub fn identify(&self, id: u64, name: String, version: Option<String>) {
+        if let Some(Entry { client, .. }) = self.clients.lock().unwrap().get_mut(&id) {
+            client.name = Some(name);
+            client.version = version.or(client.version.take());
+        }
+    }
+
+    /// Remember the token the client gave for `/api/control`, checked with every command.
+    pub fn set_control_token(&self, id: u64, token: Option<String>) {
+        if let Some(entry) = self.clients.lock().unwrap().get_mut(&id) {
+            entry.control_token = token;
+        }
+    }
+
+    pub fn control_token(&self, id: u64) -> Option<String> {
+        let clients = self.clients.lock().unwrap();
+        clients
+            .get(&id)
+            .and_then(|entry| entry.control_token.clone())
+    }
+
+    /// Remember who logged in with the session the client connected with.
+    pub fn set_user(&self, id: u64, user: Option<AuthenticatedUser>) {
+        if let Some(entry) = self.clients.lock().unwrap().get_mut(&id) {
+            entry.user = user;
+        }
+    }
+
+    pub fn user(&self, id: u64) -> Option<AuthenticatedUser> {
+        let clients = self.clients.lock().unwrap();
+        clients.get(&id).and_then(|entry| entry.user.clone())
+    }
+
+    /// Remember who the client is as far as quotas go.
+    pub fn set_identity(&self, id: u64, identity: String) {
+        if let Some(entry) = self.clients.lock().unwrap().get_mut(&id) {
+            entry.identity = Some(identity);
+        }
+    }
+
+    pub fn identity(&self, id: u64) -> Option<String> {
+        let clients = self.clients.lock().unwrap();
+        clients.get(&id).and_then(|entry| entry.identity.clone())
+    }
+
+    pub fn touch(&self, id: u64) {
+        if let Some(Entry { client, .. }) = self.clients.lock().unwrap().get_mut(&id) {
+            client.last_activity = now();
+        }
+    }
+
+    /// Count messages that were dropped before reaching a slow client.
+    pub fn count_dropped(&self, id: u64, dropped: u64) {
+        if let Some(Entry { client, .. }) = self.clients.lock().unwrap().get_mut(&id) {
+            client.dropped_messages += dropped;
+        }
+        self.delivery.lock().unwrap().dropped_messages += dropped;
+    }
+
+    /// Count a client that was disconnected for not keeping up.
+    pub fn count_slow_disconnect(&self) {
+        self.delivery.lock().unwrap().slow_disconnects += 1;
+    }
+
+    pub fn delivery_stats(&self) -> DeliveryStats {
+        *self.delivery.lock().unwrap()
+    }
+
+    pub fn disconnect(&self, id: u64) {
+        self.clients.lock().unwrap().remove(&id);
+    }
+
+    /// Close the connection of a client. Returns whether it was connected.
+    pub fn kick(&self, id: u64) -> bool {
+        match self.clients.lock().unwrap().get(&id) {
+            Some(entry) => {
+                entry.kicked.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Close every connection from `ip`, returning how many there were.
+    pub fn kick_address(&self, ip: &IpAddr) -> usize {
+        let clients = self.clients.lock().unwrap();
+        let from_ip = clients
+            .values()
+            .filter(|entry| entry.ip.as_ref() == Some(ip));
+        from_ip.map(|entry| entry.kicked.notify_one()).count()
+    }
+
+    /// Every connected client, oldest connection first.
+    pub fn list(&self) -> Vec<ConnectedClient> {
+        let mut clients: Vec<_> = self
+            .clients
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| entry.client.clone())
+            .collect();
+        clients.sort_by_key(|client| (client.connected_at, client.id));
+        clients
+    }
+}
@@ -1,12 +1,29 @@
-use std::{collections::BTreeSet, fmt::Debug};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+};
 
-use tokio::sync::watch;
+use tokio::sync::{mpsc, oneshot, watch};
 
-/// A relatively naive ID pool implementation.
+/// An id handed out by an [`IdPool`], along with which generation it was handed out in.
+/// Ids are reused, so the generation tells apart the holders of the same id over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PooledId {
+    pub id: u64,
+    pub generation: u64,
+}
+
+/// Hands out the smallest free id, starting from 1.
 pub struct IdPool {
     max_id: u64,
+    /// The ids in use, with the generation each was handed out in.
+    used: BTreeMap<u64, u64>,
+    /// Released ids below `highest_id`, which are handed out again before new ones.
     free_ids: BTreeSet<u64>,
-    id_count: u64,
+    /// Every id from 1 up to this one is either in use or free. Releasing the highest id
+    /// lowers it, so that the free ids do not pile up.
+    highest_id: u64,
+    next_generation: u64,
     id_count_watch_sender: watch::Sender<u64>,
     id_count_watch_receiver: watch::Receiver<u64>,
 }
@@ -15,22 +32,17 @@ impl Debug for IdPool {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("IdPool")
             .field("max_id", &self.max_id)
+            .field("used", &self.used)
             .field("free_ids", &self.free_ids)
-            .field("id_count", &self.id_count)
+            .field("highest_id", &self.highest_id)
+            .field("next_generation", &self.next_generation)
             .finish()
     }
 }
 
 impl Default for IdPool {
     fn default() -> Self {
-        let (id_count_watch_sender, id_count_watch_receiver) = watch::channel(0);
-        Self {
-            max_id: u64::MAX,
-            free_ids: BTreeSet::new(),
-            id_count: 0,
-            id_count_watch_sender,
-            id_count_watch_receiver,
-        }
+        Self::new_with_max_limit(u64::MAX)
     }
 }
 
@@ -41,6 +53,10 @@ pub enum IdPoolError {
     NoFreeIds,
     IdNotInUse(u64),
     IdOutOfBound(u64),
+    /// The id has been released and handed out again since this generation of it.
+    StaleId(u64),
+    /// The task owning the pool has stopped.
+    Stopped,
 }
 
 impl IdPool {
@@ -48,53 +64,138 @@ impl IdPool {
         let (id_count_watch_sender, id_count_watch_receiver) = watch::channel(0);
         Self {
             max_id,
+            used: BTreeMap::new(),
             free_ids: BTreeSet::new(),
-            id_count: 0,
+            highest_id: 0,
+            next_generation: 0,
             id_count_watch_sender,
             id_count_watch_receiver,
         }
     }
 
     pub fn id_count(&self) -> u64 {
-        self.id_count - self.free_ids.len() as u64
+        self.used.len() as u64
     }
 
     pub fn id_is_used(&self, id: u64) -> Result<bool, IdPoolError> {
-        if id > self.max_id {
+        if id == 0 || id > self.max_id {
             Err(IdPoolError::IdOutOfBound(id))
-        } else if self.free_ids.contains(&id) {
-            Ok(false)
         } else {
-            Ok(id <= self.id_count)
+            Ok(self.used.contains_key(&id))
         }
     }
 
-    pub fn request_id(&mut self) -> Result<u64, IdPoolError> {
-        if !self.free_ids.is_empty() {
-            let id = self.free_ids.pop_first().unwrap();
-            self.update_watch();
-            Ok(id)
-        } else if self.id_count < self.max_id {
-            self.id_count += 1;
-            self.update_watch();
-            Ok(self.id_count)
-        } else {
-            Err(IdPoolError::NoFreeIds)
-        }
+    pub fn request_id(&mut self) -> Result<PooledId, IdPoolError> {
+        let id = match self.free_ids.pop_first() {
+            Some(id) => id,
+            None if self.highest_id < self.max_id => {
+                self.highest_id += 1;
+                self.highest_id
+            }
+            None => return Err(IdPoolError::NoFreeIds),
+        };
+
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        self.used.insert(id, generation);
+        self.update_watch();
+        Ok(PooledId { id, generation })
     }
 
-    pub fn release_id(&mut self, id: u64) -> Result<(), IdPoolError> {
-        if !self.id_is_used(id)? {
-            Err(IdPoolError::IdNotInUse(id))
+    pub fn release_id(&mut self, id: PooledId) -> Result<(), IdPoolError> {
+        if !self.id_is_used(id.id)? {
+            return Err(IdPoolError::IdNotInUse(id.id));
+        }
+        if self.used[&id.id] != id.generation {
+            return Err(IdPoolError::StaleId(id.id));
+        }
+
+        self.used.remove(&id.id);
+        if id.id == self.highest_id {
+            self.highest_id -= 1;
+            while self.free_ids.remove(&self.highest_id) {
+                self.highest_id -= 1;
+            }
         } else {
-            self.free_ids.insert(id);
-            self.update_watch();
-            Ok(())
+            self.free_ids.insert(id.id);
         }
+        self.update_watch();
+        Ok(())
     }
 
     fn update_watch(&self) {
-        self.id_count_watch_sender.send(self.id_count()).unwrap();
+        self.id_count_watch_sender.send_replace(self.id_count());
+    }
+
+    pub fn get_id_count_watch_receiver(&self) -> watch::Receiver<u64> {
+        self.id_count_watch_receiver.clone()
+    }
+}
+
+enum IdPoolRequest {
+    RequestId(oneshot::Sender<Result<PooledId, IdPoolError>>),
+    ReleaseId(PooledId, oneshot::Sender<Result<(), IdPoolError>>),
+}
+
+/// An [`IdPool`] owned by a task of its own, so that async code can share it without
+/// holding a lock across the executor.
+#[derive(Debug, Clone)]
+pub struct IdPoolHandle {
+    sender: mpsc::Sender<IdPoolRequest>,
+    id_count_watch_receiver: watch::Receiver<u64>,
+}
+
+impl IdPoolHandle {
+    /// Start the task owning `pool`, which runs until every handle is dropped.
+    pub fn spawn(mut pool: IdPool) -> Self {
+        let (sender, mut receiver) = mpsc::channel(32);
+        let id_count_watch_receiver = pool.get_id_count_watch_receiver();
+        tokio::spawn(async move {
+            while let Some(request) = receiver.recv().await {
+                match request {
+                    IdPoolRequest::RequestId(reply) => {
+                        // The requester may have given up waiting, and then nobody else
+                        // will give the id back.
+                        if let Err(Ok(id)) = reply.send(pool.request_id()) {
+                            let _ = pool.release_id(id);
+                        }
+                    }
+                    // The requester may have given up waiting, which is fine.
+                    IdPoolRequest::ReleaseId(id, reply) => {
+                        let _ = reply.send(pool.release_id(id));
+                    }
+                }
+            }
+        });
+        Self {
+            sender,
+            id_count_watch_receiver,
+        }
+    }
+
+    async fn ask<T>(
+        &self,
+        request: impl FnOnce(oneshot::Sender<Result<T, IdPoolError>>) -> IdPoolRequest,
+    ) -> Result<T, IdPoolError> {
+        let (reply, response) = oneshot::channel();
+        self.sender
+            .send(request(reply))
+            .await
+            .map_err(|_| IdPoolError::Stopped)?;
+        response.await.map_err(|_| IdPoolError::Stopped)?
+    }
+
+    pub async fn request_id(&self) -> Result<PooledId, IdPoolError> {
+        self.ask(IdPoolRequest::RequestId).await
+    }
+
+    pub async fn release_id(&self, id: PooledId) -> Result<(), IdPoolError> {
+        self.ask(|reply| IdPoolRequest::ReleaseId(id, reply)).await
+    }
+
+    /// How many ids are in use right now.
+    pub fn id_count(&self) -> u64 {
+        *self.id_count_watch_receiver.borrow()
     }
 
     pub fn get_id_count_watch_receiver(&self) -> watch::Receiver<u64> {
@@ -104,29 +205,47 @@ impl IdPool {
 
 #[cfg(test)]
 mod tests {
+    use proptest::{prelude::*, sample::Index};
+
     use super::*;
 
     #[test]
     fn test_id_pool() {
         let mut pool = IdPool::new_with_max_limit(10);
-        assert_eq!(pool.request_id(), Ok(1));
-        assert_eq!(pool.request_id(), Ok(2));
-        assert_eq!(pool.request_id(), Ok(3));
-        assert_eq!(pool.request_id(), Ok(4));
+        let ids: Vec<PooledId> = (0..4).map(|_| pool.request_id().unwrap()).collect();
+        assert_eq!(ids.iter().map(|id| id.id).collect::<Vec<_>>(), [1, 2, 3, 4]);
         assert_eq!(pool.id_count(), 4);
-        assert_eq!(pool.request_id(), Ok(5));
-        assert_eq!(pool.request_id(), Ok(6));
-        assert_eq!(pool.request_id(), Ok(7));
-        assert_eq!(pool.request_id(), Ok(8));
-        assert_eq!(pool.request_id(), Ok(9));
-        assert_eq!(pool.request_id(), Ok(10));
+        let mut ids: Vec<PooledId> = ids
+            .into_iter()
+            .chain((0..6).map(|_| pool.request_id().unwrap()))
+            .collect();
+        assert_eq!(ids[9].id, 10);
         assert_eq!(pool.id_count(), 10);
         assert_eq!(pool.request_id(), Err(IdPoolError::NoFreeIds));
-        assert_eq!(pool.release_id(5), Ok(()));
-        assert_eq!(pool.release_id(5), Err(IdPoolError::IdNotInUse(5)));
+        assert_eq!(pool.release_id(ids[4]), Ok(()));
+        assert_eq!(pool.release_id(ids[4]), Err(IdPoolError::IdNotInUse(5)));
         assert_eq!(pool.id_count(), 9);
-        assert_eq!(pool.request_id(), Ok(5));
-        assert_eq!(pool.release_id(11), Err(IdPoolError::IdOutOfBound(11)));
+        let reused = pool.request_id().unwrap();
+        assert_eq!(reused.id, 5);
+        // The old holder of id 5 can not release it from the new one.
+        assert_eq!(pool.release_id(ids[4]), Err(IdPoolError::StaleId(5)));
+        ids[4] = reused;
+        let out_of_bound = PooledId {
+            id: 11,
+            generation: 0,
+        };
+        assert_eq!(
+            pool.release_id(out_of_bound),
+            Err(IdPoolError::IdOutOfBound(11))
+        );
+        assert_eq!(pool.id_is_used(0), Err(IdPoolError::IdOutOfBound(0)));
+
+        // Releasing the highest ids shrinks the pool, instead of keeping them as free ids.
+        for id in ids.into_iter().rev() {
+            pool.release_id(id).unwrap();
+        }
+        assert_eq!(pool.highest_id, 0);
+        assert!(pool.free_ids.is_empty());
     }
 
     #[test]
@@ -135,11 +254,94 @@ mod tests {
         let receiver = pool.get_id_count_watch_receiver();
 
         assert_eq!(receiver.borrow().clone(), 0);
-        pool.request_id().unwrap();
+        let first = pool.request_id().unwrap();
         assert_eq!(receiver.borrow().clone(), 1);
         pool.request_id().unwrap();
         assert_eq!(receiver.borrow().clone(), 2);
-        pool.release_id(1).unwrap();
+        pool.release_id(first).unwrap();
         assert_eq!(receiver.borrow().clone(), 1);
     }
+
+    #[derive(Debug, Clone)]
+    enum Operation {
+        Request,
+        Release(Index),
+        ReleaseStale(Index),
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            Just(Operation::Request),
+            any::<Index>().prop_map(Operation::Release),
+            any::<Index>().prop_map(Operation::ReleaseStale),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_id_pool_operations(operations in prop::collection::vec(operation(), 0..200)) {
+            let max_id = 16;
+            let mut pool = IdPool::new_with_max_limit(max_id);
+            let mut held: Vec<PooledId> = Vec::new();
+            let mut released: Vec<PooledId> = Vec::new();
+
+            for operation in operations {
+                match operation {
+                    Operation::Request => {
+                        let smallest_free = (1..=max_id).find(|id| held.iter().all(|held| held.id != *id));
+                        match (pool.request_id(), smallest_free) {
+                            (Ok(id), Some(expected)) => {
+                                prop_assert_eq!(id.id, expected);
+                                held.push(id);
+                            }
+                            (Err(IdPoolError::NoFreeIds), None) => {}
+                            (result, expected) => prop_assert!(false, "Got {:?}, expected {:?}", result, expected),
+                        }
+                    }
+                    Operation::Release(index) if !held.is_empty() => {
+                        let id = held.swap_remove(index.index(held.len()));
+                        prop_assert_eq!(pool.release_id(id), Ok(()));
+                        released.push(id);
+                    }
+                    Operation::ReleaseStale(index) if !released.is_empty() => {
+                        let id = *index.get(&released);
+                        prop_assert!(pool.release_id(id).is_err());
+                    }
+                    _ => {}
+                }
+
+                prop_assert_eq!(pool.id_count(), held.len() as u64);
+                prop_assert_eq!(pool.highest_id, held.iter().map(|id| id.id).max().unwrap_or(0));
+                prop_assert_eq!(pool.free_ids.len() + pool.used.len(), pool.highest_id as usize);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_id_pool_handle() {
+        let pool = IdPoolHandle::spawn(IdPool::new_with_max_limit(2));
+        let receiver = pool.get_id_count_watch_receiver();
+
+        let first = pool.request_id().await.unwrap();
+        assert_eq!(first.id, 1);
+        assert_eq!(pool.request_id().await.unwrap().id, 2);
+        assert_eq!(pool.request_id().await, Err(IdPoolError::NoFreeIds));
+        assert_eq!(pool.id_count(), 2);
+        assert_eq!(pool.release_id(first).await, Ok(()));
+        assert_eq!(
+            pool.release_id(first).await,
+            Err(IdPoolError::IdNotInUse(1))
+        );
+        assert_eq!(*receiver.borrow(), 1);
+
+        // An id for someone who stopped waiting for it is given back right away.
+        let (reply, response) = oneshot::channel();
+        drop(response);
+        pool.sender
+            .send(IdPoolRequest::RequestId(reply))
+            .await
+            .unwrap();
+        assert_eq!(pool.request_id().await.unwrap().id, 1);
+        assert_eq!(pool.id_count(), 2);
+    }
 }
@@ -0,0 +1,109 @@
+use serde_json::{Map, Value};
+
+/// Which part of a long list to return, and which fields of each item, for clients that
+/// can not take all of it at once.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Page {
+    /// How many items to skip.
+    pub offset: usize,
+    /// The most items to return, or every item if not given.
+    pub limit: Option<usize>,
+    /// Only these fields of each item are returned, or every field if not given. Fields of
+    /// nested objects are given with dots, like `data.queued_by`.
+    pub fields: Option<Vec<String>>,
+}
+
+impl Page {
+    /// `fields` is a comma separated list of field names.
+    pub fn new(offset: Option<usize>, limit: Option<usize>, fields: Option<&str>) -> Self {
+        Self {
+            offset: offset.unwrap_or(0),
+            limit,
+            fields: fields.map(|fields| {
+                fields
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|field| !field.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }),
+        }
+    }
+
+    /// How many items from the start of the list are needed to fill this page.
+    pub fn end(&self) -> Option<usize> {
+        self.limit.map(|limit| self.offset.saturating_add(limit))
+    }
+
+    /// The items on this page, with only the selected fields.
+    pub fn apply(&self, items: impl IntoIterator<Item = Value>) -> Vec<Value> {
+        items
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .map(|item| self.select(item))
+            .collect()
+    }
+
+    fn select(&self, item: Value) -> Value {
+        let Some(fields) = &self.fields else {
+            return item;
+        };
+        let mut selected = Value::Object(Map::new());
+        for field in fields {
+            if let Some(value) = lookup(&item, field) {
+                insert(&mut selected, field, value.clone());
+            }
+        }
+        selected
+    }
+}
+
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |value, key| value.get(key))
+}
+
+fn insert(mut target: &mut Value, path: &str, value: Value) {
+    let mut keys = path.split('.').peekable();
+    while let Some(key) = keys.next() {
+        let Some(object) = target.as_object_mut() else {
+            return;
+        };
+        if keys.peek().is_none() {
+            object.insert(key.to_string(), value);
+            return;
+        }
+        target = object
+            .entry(key)
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_page() {
+        let items: Vec<Value> = (0..5)
+            .map(|i| json!({"index": i, "data": {"note": "hi", "queued_by": "alice"}}))
+            .collect();
+
+        let page = Page::new(Some(1), Some(2), Some("index, data.queued_by,missing"));
+        assert_eq!(page.end(), Some(3));
+        assert_eq!(
+            page.apply(items.clone()),
+            vec![
+                json!({"index": 1, "data": {"queued_by": "alice"}}),
+                json!({"index": 2, "data": {"queued_by": "alice"}}),
+            ]
+        );
+
+        let page = Page::new(Some(4), None, None);
+        assert_eq!(page.end(), None);
+        assert_eq!(page.apply(items.clone()), vec![items[4].clone()]);
+        assert!(Page::new(Some(10), Some(5), None).apply(items).is_empty());
+    }
+}
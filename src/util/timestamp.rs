@@ -0,0 +1,46 @@
+/// Parse a timestamp like `72.5`, `1:12` or `1:02:03` into seconds.
+pub fn parse_timestamp(input: &str) -> Option<f64> {
+    let parts: Vec<&str> = input.trim().split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+
+    let mut seconds = 0.0;
+    for (i, part) in parts.iter().enumerate() {
+        let last = i == parts.len() - 1;
+        let valid = !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_digit() || (last && c == '.'));
+        if !valid {
+            return None;
+        }
+
+        let value: f64 = part.parse().ok()?;
+        if i > 0 && value >= 60.0 {
+            return None;
+        }
+        seconds = seconds * 60.0 + value;
+    }
+
+    Some(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("72.5"), Some(72.5));
+        assert_eq!(parse_timestamp("1:12"), Some(72.0));
+        assert_eq!(parse_timestamp(" 1:02:03 "), Some(3723.0));
+        assert_eq!(parse_timestamp("0:05.5"), Some(5.5));
+        assert_eq!(parse_timestamp("1:60"), None);
+        assert_eq!(parse_timestamp("-5"), None);
+        assert_eq!(parse_timestamp("1.5:00"), None);
+        assert_eq!(parse_timestamp("1::2"), None);
+        assert_eq!(parse_timestamp("1:2:3:4"), None);
+        assert_eq!(parse_timestamp(""), None);
+    }
+}
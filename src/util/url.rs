@@ -0,0 +1,246 @@
+use url::Url;
+
+/// Query parameters that only exist for tracking purposes, and never change what is played.
+const TRACKING_PARAMS: [&str; 5] = ["fbclid", "gclid", "igshid", "mc_cid", "mc_eid"];
+
+/// Query parameters that only track on some sites, and may mean something elsewhere. Keyed
+/// by domain, which also covers its subdomains.
+const SITE_TRACKING_PARAMS: [(&str, &[&str]); 5] = [
+    ("youtube.com", &["si", "feature", "pp", "ab_channel"]),
+    ("youtu.be", &["si", "feature"]),
+    ("soundcloud.com", &["si"]),
+    ("twitter.com", &["ref_src"]),
+    ("x.com", &["ref_src"]),
+];
+
+/// Query parameters on youtube watch urls that change what is played, besides the video id.
+/// These are kept, in this order.
+const YOUTUBE_WATCH_PARAMS: [&str; 2] = ["list", "t"];
+
+/// Normalize a url to a canonical form, so that different urls pointing to
+/// the same item compare equal.
+///
+/// This strips tracking parameters, rewrites the different youtube url forms
+/// (`youtu.be`, `/shorts/`, `/live/`, `/embed/`, mobile and music subdomains) into
+/// a single `watch?v=` form, and drops playlist position parameters like `index`.
+///
+/// Anything that is not an http(s) url, like local file paths, is returned as is
+/// (apart from surrounding whitespace).
+pub fn canonicalize_url(input: &str) -> String {
+    let input = input.trim();
+
+    let mut url = match Url::parse(input) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+        _ => return input.to_string(),
+    };
+
+    if let Some(canonical) = canonicalize_youtube_url(&url) {
+        return canonical;
+    }
+
+    // The pairs that are kept are left as they were, since signed urls break if so much as
+    // their encoding changes.
+    let host = url.host_str().unwrap_or_default();
+    let Some(query) = url.query() else {
+        return url.to_string();
+    };
+    let pairs: Vec<&str> = query.split('&').collect();
+    let kept: Vec<&str> = pairs
+        .iter()
+        .copied()
+        .filter(|pair| !is_tracking_param(host, pair))
+        .collect();
+    if kept.len() < pairs.len() {
+        let query = kept.join("&");
+        url.set_query((!query.is_empty()).then_some(&query));
+    }
+
+    url.to_string()
+}
+
+/// Whether the raw query `pair` on `host` is only there for tracking.
+fn is_tracking_param(host: &str, pair: &str) -> bool {
+    let Some((key, _)) = url::form_urlencoded::parse(pair.as_bytes()).next() else {
+        return false;
+    };
+    let on_site = |domain: &str| {
+        host.strip_suffix(domain)
+            .is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+    };
+    key.starts_with("utm_")
+        || TRACKING_PARAMS.contains(&key.as_ref())
+        || SITE_TRACKING_PARAMS
+            .iter()
+            .any(|(domain, params)| on_site(domain) && params.contains(&key.as_ref()))
+}
+
+fn canonicalize_youtube_url(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+
+    let mut segments = url.path_segments()?.filter(|s| !s.is_empty());
+
+    let video_id = match host {
+        "youtu.be" => Some(segments.next()?.to_string()),
+        "youtube.com" | "m.youtube.com" | "music.youtube.com" | "youtube-nocookie.com" => {
+            match segments.next() {
+                Some("watch") => url
+                    .query_pairs()
+                    .find(|(key, _)| key == "v")
+                    .map(|(_, value)| value.into_owned()),
+                Some("shorts" | "live" | "embed" | "v") => Some(segments.next()?.to_string()),
+                Some("playlist") => None,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    let mut canonical = Url::parse("https://www.youtube.com").ok()?;
+    match video_id {
+        Some(id) => {
+            canonical.set_path("/watch");
+            canonical.query_pairs_mut().append_pair("v", &id);
+            for name in YOUTUBE_WATCH_PARAMS {
+                if let Some(value) = param(name) {
+                    canonical.query_pairs_mut().append_pair(name, &value);
+                }
+            }
+        }
+        None => {
+            canonical.set_path("/playlist");
+            canonical
+                .query_pairs_mut()
+                .append_pair("list", &param("list")?);
+        }
+    }
+
+    Some(canonical.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CANONICAL: &str = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+
+    #[test]
+    fn test_youtube_url_forms() {
+        for url in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtube.com/watch?v=dQw4w9WgXcQ",
+            "http://m.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ&feature=share",
+            "https://youtu.be/dQw4w9WgXcQ",
+            "https://youtu.be/dQw4w9WgXcQ?si=AbCdEfGhIjKlMnOp",
+            "https://www.youtube.com/shorts/dQw4w9WgXcQ",
+            "https://youtube.com/shorts/dQw4w9WgXcQ?feature=share",
+            "https://www.youtube.com/live/dQw4w9WgXcQ?si=xyz",
+            "https://www.youtube.com/embed/dQw4w9WgXcQ",
+            "https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?feature=youtu.be&v=dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&ab_channel=RickAstley",
+            "  https://www.youtube.com/watch?v=dQw4w9WgXcQ#comments  ",
+        ] {
+            assert_eq!(canonicalize_url(url), CANONICAL, "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_youtube_timestamps_are_kept() {
+        assert_eq!(
+            canonicalize_url("https://youtu.be/dQw4w9WgXcQ?t=42"),
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42",
+        );
+        assert_eq!(
+            canonicalize_url("https://www.youtube.com/watch?t=42&v=dQw4w9WgXcQ&pp=ygU"),
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42",
+        );
+    }
+
+    #[test]
+    fn test_youtube_playlists() {
+        assert_eq!(
+            canonicalize_url(
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PL1234&index=7&start_radio=1"
+            ),
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PL1234",
+        );
+        assert_eq!(
+            canonicalize_url("https://www.youtube.com/playlist?list=PL1234&si=abc"),
+            "https://www.youtube.com/playlist?list=PL1234",
+        );
+        assert_eq!(
+            canonicalize_url("https://m.youtube.com/playlist?list=PL1234&index=2"),
+            "https://www.youtube.com/playlist?list=PL1234",
+        );
+    }
+
+    #[test]
+    fn test_youtube_non_video_pages_are_left_alone() {
+        assert_eq!(
+            canonicalize_url("https://www.youtube.com/@pvvntnu/videos"),
+            "https://www.youtube.com/@pvvntnu/videos",
+        );
+        assert_eq!(
+            canonicalize_url("https://www.youtube.com/playlist"),
+            "https://www.youtube.com/playlist",
+        );
+    }
+
+    #[test]
+    fn test_tracking_params_are_stripped() {
+        assert_eq!(
+            canonicalize_url(
+                "https://soundcloud.com/artist/track?utm_source=clipboard&utm_medium=text&si=123"
+            ),
+            "https://soundcloud.com/artist/track",
+        );
+        assert_eq!(
+            canonicalize_url("https://example.com/video.mp4?fbclid=abc&quality=720"),
+            "https://example.com/video.mp4?quality=720",
+        );
+        assert_eq!(
+            canonicalize_url("https://EXAMPLE.com/radio.ogg#live"),
+            "https://example.com/radio.ogg#live",
+        );
+    }
+
+    #[test]
+    fn test_other_params_are_kept_as_they_are() {
+        // Signed urls break if their query is encoded differently.
+        for url in [
+            "https://bucket.example.com/song.mp3?X-Amz-Signature=a%2Fb%20c&token",
+            "https://example.com/clip.mp4?feature=intro&si=2&pp=1",
+        ] {
+            assert_eq!(canonicalize_url(url), url);
+        }
+        assert_eq!(
+            canonicalize_url("https://example.com/song.mp3?name=a%20b&utm_source=x&token"),
+            "https://example.com/song.mp3?name=a%20b&token",
+        );
+        assert_eq!(
+            canonicalize_url("https://twitter.com/pvv/status/1?ref_src=twsrc"),
+            "https://twitter.com/pvv/status/1",
+        );
+    }
+
+    #[test]
+    fn test_non_http_input_is_untouched() {
+        assert_eq!(
+            canonicalize_url("/home/music/song.flac"),
+            "/home/music/song.flac"
+        );
+        assert_eq!(
+            canonicalize_url(" ytdl://ytsearch:never gonna give you up "),
+            "ytdl://ytsearch:never gonna give you up",
+        );
+        assert_eq!(canonicalize_url("not a url"), "not a url");
+    }
+}
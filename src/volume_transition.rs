@@ -0,0 +1,266 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use mpvipc_async::Switch;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, watch};
+
+use crate::{api::ApiError, player::PlayerHandle};
+
+/// Amount of intermediate volume steps used for a single fade.
+const FADE_STEPS: u32 = 20;
+
+/// How long it takes to fade out the volume before pausing, when ducking is enabled.
+const DUCK_FADE_OUT_DURATION: Duration = Duration::from_millis(300);
+
+/// How long it takes to fade the volume back in after resuming, when ducking is enabled.
+const DUCK_FADE_IN_DURATION: Duration = Duration::from_millis(1500);
+
+#[derive(Debug, Default)]
+struct TransitionState {
+    /// Incremented every time a transition starts or the volume is set directly,
+    /// so that a running fade can notice that it has been superseded.
+    generation: u64,
+
+    /// The volume to fade back in to when resuming after a ducked pause.
+    restore_volume: Option<f64>,
+
+    /// Incremented every time the volume cap changes, so that an expiry timer
+    /// can notice that the cap it was started for has been replaced.
+    cap_generation: u64,
+}
+
+/// A temporary hard ceiling on the player volume, overriding user commands.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct VolumeCap {
+    /// The highest volume the player is allowed to be set to.
+    pub max_volume: f64,
+
+    /// When the cap is lifted, in seconds since the unix epoch.
+    pub expires_at: u64,
+}
+
+/// Handles smooth volume changes for the player.
+///
+/// All volume and playback changes that should respect ongoing transitions
+/// should go through this, instead of talking to the player directly.
+#[derive(Debug, Clone)]
+pub struct VolumeTransitionEngine {
+    player: PlayerHandle,
+    duck_on_pause: bool,
+    state: Arc<Mutex<TransitionState>>,
+    cap_watch_sender: Arc<watch::Sender<Option<VolumeCap>>>,
+}
+
+impl VolumeTransitionEngine {
+    pub fn new(player: PlayerHandle, duck_on_pause: bool) -> Self {
+        Self {
+            player,
+            duck_on_pause,
+            state: Arc::new(Mutex::new(TransitionState::default())),
+            cap_watch_sender: Arc::new(watch::channel(None).0),
+        }
+    }
+
+    /// The player this engine controls.
+    pub fn player(&self) -> &PlayerHandle {
+        &self.player
+    }
+
+    /// Set the volume immediately, cancelling any ongoing fade.
+    ///
+    /// The volume is clamped to the current volume cap, if there is one.
+    pub async fn set_volume(&self, volume: f64) -> anyhow::Result<()> {
+        {
+            let mut state = self.state.lock().await;
+            state.generation += 1;
+            state.restore_volume = None;
+        }
+
+        self.player.set_volume(self.clamp_to_cap(volume)).await
+    }
+
+    /// The currently active volume cap, if any.
+    pub fn volume_cap(&self) -> Option<VolumeCap> {
+        *self.cap_watch_sender.borrow()
+    }
+
+    pub fn get_volume_cap_watch_receiver(&self) -> watch::Receiver<Option<VolumeCap>> {
+        self.cap_watch_sender.subscribe()
+    }
+
+    /// Cap the volume at `max_volume` for `duration`.
+    ///
+    /// If the player is currently louder than the cap, the volume is lowered immediately.
+    /// Setting a new cap replaces the old one.
+    pub async fn set_volume_cap(&self, max_volume: f64, duration: Duration) -> anyhow::Result<()> {
+        if !(0.0..=100.0).contains(&max_volume) {
+            anyhow::bail!("max_volume must be between 0 and 100");
+        }
+
+        let expires_at = SystemTime::now().checked_add(duration).ok_or_else(|| {
+            ApiError::InvalidArgument(format!("A volume cap can not last {:?}", duration))
+        })?;
+        let cap = VolumeCap {
+            max_volume,
+            expires_at: expires_at.duration_since(UNIX_EPOCH)?.as_secs(),
+        };
+
+        let cap_generation = {
+            let mut state = self.state.lock().await;
+            state.cap_generation += 1;
+            state.cap_generation
+        };
+
+        log::info!("Capping volume at {} for {:?}", max_volume, duration);
+        self.cap_watch_sender.send_replace(Some(cap));
+
+        let engine = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            if engine.state.lock().await.cap_generation == cap_generation {
+                log::info!("Volume cap of {} expired", max_volume);
+                engine.cap_watch_sender.send_replace(None);
+            }
+        });
+
+        let volume = self.player.get_volume().await?;
+        if volume > max_volume {
+            self.set_volume(max_volume).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Lift the volume cap before it expires.
+    pub async fn clear_volume_cap(&self) {
+        self.state.lock().await.cap_generation += 1;
+        if self.cap_watch_sender.send_replace(None).is_some() {
+            log::info!("Volume cap lifted");
+        }
+    }
+
+    fn clamp_to_cap(&self, volume: f64) -> f64 {
+        match self.volume_cap() {
+            Some(cap) if volume > cap.max_volume => {
+                log::debug!("Clamping volume {} to cap {}", volume, cap.max_volume);
+                cap.max_volume
+            }
+            _ => volume,
+        }
+    }
+
+    /// Gradually move the volume to `target` over `duration`.
+    ///
+    /// Returns `false` if the fade was interrupted by another volume change.
+    pub async fn fade_to(&self, target: f64, duration: Duration) -> anyhow::Result<bool> {
+        let target = self.clamp_to_cap(target);
+        let generation = {
+            let mut state = self.state.lock().await;
+            state.generation += 1;
+            state.generation
+        };
+
+        let start = self.player.get_volume().await?;
+        let step_duration = duration / FADE_STEPS;
+
+        for step in 1..=FADE_STEPS {
+            tokio::time::sleep(step_duration).await;
+
+            if self.state.lock().await.generation != generation {
+                log::trace!("Volume fade to {} was superseded", target);
+                return Ok(false);
+            }
+
+            let volume = start + (target - start) * (step as f64 / FADE_STEPS as f64);
+            self.player.set_volume(volume).await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Pause or resume playback, ducking the volume around the pause if enabled.
+    ///
+    /// Fails with [`ApiError::Conflict`] if the volume is changed while ducking, which
+    /// leaves the player playing.
+    pub async fn set_playback(&self, switch: Switch) -> anyhow::Result<()> {
+        let is_playing = self.player.is_playing().await?;
+        let should_play = match switch {
+            Switch::On => true,
+            Switch::Off => false,
+            Switch::Toggle => !is_playing,
+        };
+
+        if !self.duck_on_pause {
+            return self.player.set_playing(should_play).await;
+        }
+
+        match (is_playing, should_play) {
+            (true, false) => self.duck_and_pause().await,
+            (false, true) => self.resume_and_restore().await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn duck_and_pause(&self) -> anyhow::Result<()> {
+        log::trace!("Ducking volume before pausing");
+        let volume = self.player.get_volume().await?;
+        self.state.lock().await.restore_volume.get_or_insert(volume);
+
+        // Whatever interrupted the fade, like resuming, wins, so the player is left playing.
+        if !self.fade_to(0.0, DUCK_FADE_OUT_DURATION).await? {
+            return Err(ApiError::Conflict(
+                "The pause was interrupted by another volume change, and the player is still playing"
+                    .to_string(),
+            )
+            .into());
+        }
+        self.player.set_playing(false).await
+    }
+
+    async fn resume_and_restore(&self) -> anyhow::Result<()> {
+        let restore_volume = self.state.lock().await.restore_volume.take();
+        self.player.set_playing(true).await?;
+
+        if let Some(volume) = restore_volume {
+            log::trace!("Restoring volume to {} after ducked pause", volume);
+            self.fade_to(volume, DUCK_FADE_IN_DURATION).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{player::MpvPlayer, test_support::FakeMpv};
+
+    #[tokio::test]
+    async fn test_interrupted_pause() {
+        let mpv = FakeMpv::start();
+        mpv.set_property("pause", json!(false));
+        let engine =
+            VolumeTransitionEngine::new(Arc::new(MpvPlayer::new(mpv.connect().await)), true);
+
+        let pausing = tokio::spawn({
+            let engine = engine.clone();
+            async move { engine.set_playback(Switch::Off).await }
+        });
+        tokio::time::sleep(DUCK_FADE_OUT_DURATION / 4).await;
+        engine.set_volume(30.0).await.unwrap();
+
+        let e = pausing.await.unwrap().unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<ApiError>(),
+            Some(ApiError::Conflict(_))
+        ));
+        assert_eq!(mpv.property("pause"), Some(json!(false)));
+    }
+}
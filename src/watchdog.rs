@@ -0,0 +1,217 @@
+//! Notices when the current item fails to play, and moves on to the next one.
+//! Failed items are retried according to the [`RetryQueue`] before they count as failed.
+//!
+//! Failures are announced to every mpv client as a `script-message`, which is how
+//! they reach the websocket and event stream clients, and the play history.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use futures::StreamExt;
+use mpvipc_async::{EndFileReason, Event, Mpv, MpvExt};
+
+use crate::player::{EntryId, QueueState, RetryQueue};
+
+/// The `script-message` sent when an item fails, followed by its entry id, its path and the
+/// error. The entry id is empty if it is not known.
+pub const PLAYBACK_FAILED_MESSAGE: &str = "greg-playback-failed";
+
+/// How often playback progress is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The entry id, path and error of a failure announced with [`PLAYBACK_FAILED_MESSAGE`].
+pub fn parse_failure_message(args: &[String]) -> Option<(Option<EntryId>, &str, &str)> {
+    match args {
+        [message, id, path, error] if message == PLAYBACK_FAILED_MESSAGE => {
+            Some((id.parse().ok(), path, error))
+        }
+        _ => None,
+    }
+}
+
+/// Progress of the current item, as seen by the last check.
+#[derive(Debug, Default)]
+struct Progress {
+    path: Option<String>,
+    time_pos: Option<f64>,
+    stalled_for: Duration,
+    /// Set once the current item has been given up on, so it is only reported once.
+    failed: bool,
+}
+
+impl Progress {
+    /// Update with the latest state, and return whether playback has been stuck for `timeout`.
+    fn check(
+        &mut self,
+        path: Option<String>,
+        time_pos: Option<f64>,
+        paused: bool,
+        timeout: Duration,
+    ) -> bool {
+        if path != self.path {
+            *self = Progress {
+                path,
+                time_pos,
+                ..Default::default()
+            };
+            return false;
+        }
+
+        if paused || self.path.is_none() || self.failed || time_pos != self.time_pos {
+            self.time_pos = time_pos;
+            self.stalled_for = Duration::ZERO;
+            return false;
+        }
+
+        self.stalled_for += CHECK_INTERVAL;
+        if self.stalled_for >= timeout {
+            self.failed = true;
+            return true;
+        }
+        false
+    }
+}
+
+async fn announce_failure(mpv: &Mpv, id: Option<usize>, path: &str, error: &str) {
+    log::warn!("Playback of '{}' failed: {}", path, error);
+    let id = id
+        .map(|id| EntryId::Player(id).to_string())
+        .unwrap_or_default();
+    let result = mpv
+        .run_command_raw(
+            "script-message",
+            &[PLAYBACK_FAILED_MESSAGE, &id, path, error],
+        )
+        .await;
+    if let Err(e) = result {
+        log::warn!("Failed to announce the playback failure: {}", e);
+    }
+}
+
+/// Retry the failed item `id` if there are attempts left, and otherwise report it as failed.
+async fn handle_failure(
+    mpv: &Mpv,
+    queue: &QueueState,
+    retries: &RetryQueue,
+    id: Option<usize>,
+    path: &str,
+    error: &str,
+) {
+    if retries.retry(mpv, queue, path, error) {
+        log::warn!("Playback of '{}' failed, retrying: {}", path, error);
+    } else {
+        announce_failure(mpv, id, path, error).await;
+    }
+}
+
+/// Report items that mpv fails to open, and with a `timeout`, skip items that stop
+/// making progress while not paused, like dead streams.
+pub async fn run_watchdog(
+    mpv: Mpv,
+    queue: QueueState,
+    timeout: Option<Duration>,
+    retries: RetryQueue,
+) -> anyhow::Result<()> {
+    let mut event_stream = mpv.get_event_stream().await;
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let mut progress = Progress::default();
+    // The entry id and path of the item that started last.
+    let mut started: Option<(usize, String)> = None;
+
+    loop {
+        tokio::select! {
+            event = event_stream.next() => match event.context("Lost the mpv events")?? {
+                Event::StartFile { playlist_entry_id } => {
+                    started = mpv
+                        .get_playlist()
+                        .await
+                        .ok()
+                        .and_then(|playlist| {
+                            playlist.0.into_iter().find(|entry| entry.id == playlist_entry_id)
+                        })
+                        .map(|entry| (entry.id, entry.filename));
+                }
+                Event::EndFile { reason: EndFileReason::Error, file_error, .. } => {
+                    if let Some((id, path)) = started.take() {
+                        let error = file_error.unwrap_or_else(|| "unknown error".to_string());
+                        handle_failure(&mpv, &queue, &retries, Some(id), &path, &error).await;
+                    }
+                }
+                Event::EndFile { reason: EndFileReason::Eof, .. } => {
+                    if let Some((_, path)) = started.take() {
+                        retries.succeeded(&path);
+                    }
+                }
+                Event::EndFile { .. } => started = None,
+                Event::Shutdown => return Ok(()),
+                _ => {}
+            },
+
+            _ = interval.tick(), if timeout.is_some() => {
+                let timeout = timeout.unwrap();
+                let path: Option<String> = mpv.get_property("path").await.unwrap_or(None);
+                let time_pos: Option<f64> = mpv.get_property("time-pos").await.unwrap_or(None);
+                let paused: bool = mpv.get_property("pause").await.unwrap_or(None).unwrap_or(false);
+
+                if progress.check(path, time_pos, paused, timeout) {
+                    let path = progress.path.clone().unwrap_or_default();
+                    let id = started
+                        .as_ref()
+                        .filter(|(_, started)| *started == path)
+                        .map(|(id, _)| *id);
+                    let error = format!("No progress for {} seconds", timeout.as_secs());
+                    handle_failure(&mpv, &queue, &retries, id, &path, &error).await;
+                    mpv.run_command_raw("playlist-next", &["force"]).await?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_check() {
+        let timeout = CHECK_INTERVAL * 3;
+        let path = || Some("https://example.com/stream".to_string());
+        let mut progress = Progress::default();
+
+        assert!(!progress.check(path(), None, false, timeout));
+        assert!(!progress.check(path(), None, false, timeout));
+        assert!(!progress.check(path(), Some(0.5), false, timeout));
+
+        // Pausing is not a failure.
+        for _ in 0..5 {
+            assert!(!progress.check(path(), Some(0.5), true, timeout));
+        }
+
+        assert!(!progress.check(path(), Some(0.5), false, timeout));
+        assert!(!progress.check(path(), Some(0.5), false, timeout));
+        assert!(progress.check(path(), Some(0.5), false, timeout));
+        assert!(!progress.check(path(), Some(0.5), false, timeout));
+
+        assert!(!progress.check(None, None, false, timeout));
+        assert_eq!(
+            parse_failure_message(&[
+                PLAYBACK_FAILED_MESSAGE.to_string(),
+                "3".to_string(),
+                "a".to_string(),
+                "b".to_string()
+            ]),
+            Some((Some(EntryId::Player(3)), "a", "b"))
+        );
+        assert_eq!(
+            parse_failure_message(&[
+                PLAYBACK_FAILED_MESSAGE.to_string(),
+                String::new(),
+                "a".to_string(),
+                "b".to_string()
+            ]),
+            Some((None, "a", "b"))
+        );
+    }
+}
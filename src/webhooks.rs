@@ -0,0 +1,423 @@
+//! Webhooks, for notifying other services about what the player is doing.
+//!
+//! Every event is POSTed as JSON to each webhook whose filter matches it. Failed
+//! deliveries are retried with exponential backoff. When a secret is configured,
+//! the body is signed with HMAC-SHA256 in the `X-Greg-Signature` header.
+
+use std::{
+    fmt,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::StreamExt;
+use mpvipc_async::{EndFileReason, Event, Mpv, MpvDataType, MpvExt};
+use ring::hmac;
+use serde::Serialize;
+use url::Url;
+
+/// How long to wait for a webhook to respond.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many times a delivery is attempted before giving up.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// The delay before the first retry, doubled for every retry after that.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The observer id used for the properties watched for webhook events.
+const OBSERVER_ID: u64 = 101;
+
+/// The kinds of events webhooks can be sent for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEventKind {
+    TrackStarted,
+    TrackFinished,
+    PlaylistEmptied,
+    PlayerCrashed,
+}
+
+impl FromStr for WebhookEventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "track_started" => Ok(WebhookEventKind::TrackStarted),
+            "track_finished" => Ok(WebhookEventKind::TrackFinished),
+            "playlist_emptied" => Ok(WebhookEventKind::PlaylistEmptied),
+            "player_crashed" => Ok(WebhookEventKind::PlayerCrashed),
+            _ => Err(format!(
+                "unknown event '{}', expected one of track_started, track_finished, playlist_emptied, player_crashed",
+                s
+            )),
+        }
+    }
+}
+
+/// An event that webhooks are sent for.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    TrackStarted {
+        path: String,
+        title: Option<String>,
+    },
+    TrackFinished {
+        path: String,
+        title: Option<String>,
+        /// `finished`, `skipped` or `error`.
+        reason: &'static str,
+    },
+    PlaylistEmptied,
+    PlayerCrashed {
+        message: String,
+    },
+}
+
+impl WebhookEvent {
+    pub fn kind(&self) -> WebhookEventKind {
+        match self {
+            WebhookEvent::TrackStarted { .. } => WebhookEventKind::TrackStarted,
+            WebhookEvent::TrackFinished { .. } => WebhookEventKind::TrackFinished,
+            WebhookEvent::PlaylistEmptied => WebhookEventKind::PlaylistEmptied,
+            WebhookEvent::PlayerCrashed { .. } => WebhookEventKind::PlayerCrashed,
+        }
+    }
+
+    /// A human readable description of the event.
+    fn summary(&self) -> String {
+        match self {
+            WebhookEvent::TrackStarted { path, title } => {
+                format!("Now playing: {}", title.as_deref().unwrap_or(path))
+            }
+            WebhookEvent::TrackFinished {
+                path,
+                title,
+                reason,
+            } => format!(
+                "Finished playing ({}): {}",
+                reason,
+                title.as_deref().unwrap_or(path)
+            ),
+            WebhookEvent::PlaylistEmptied => "The playlist is empty".to_string(),
+            WebhookEvent::PlayerCrashed { message } => format!("The player crashed: {}", message),
+        }
+    }
+}
+
+/// The body of a webhook request.
+#[derive(Debug, Serialize)]
+struct WebhookPayload<'a> {
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+    /// When the event happened, in seconds since the unix epoch.
+    timestamp: u64,
+    /// The same as `summary`, so that Discord webhooks can be used directly.
+    content: String,
+    summary: String,
+}
+
+/// A URL to send events to, and which events to send.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Webhook {
+    pub url: Url,
+    /// `None` means every event.
+    pub events: Option<Vec<WebhookEventKind>>,
+}
+
+impl FromStr for Webhook {
+    type Err = String;
+
+    /// Parses `[<event>,...=]<url>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (events, url) = if s.starts_with("http://") || s.starts_with("https://") {
+            (None, s)
+        } else {
+            let (events, url) = s
+                .split_once('=')
+                .ok_or_else(|| format!("expected [<event>,...=]<url>, got '{}'", s))?;
+            let events = events
+                .split(',')
+                .map(|event| event.trim().parse())
+                .collect::<Result<Vec<_>, _>>()?;
+            (Some(events), url)
+        };
+
+        let url = Url::parse(url).map_err(|e| format!("invalid URL '{}': {}", url, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("webhook URL '{}' must be http or https", url));
+        }
+
+        Ok(Webhook { url, events })
+    }
+}
+
+impl Webhook {
+    fn wants(&self, kind: WebhookEventKind) -> bool {
+        self.events
+            .as_ref()
+            .is_none_or(|events| events.contains(&kind))
+    }
+}
+
+/// Sends events to the configured webhooks.
+#[derive(Clone)]
+pub struct Webhooks {
+    client: reqwest::Client,
+    webhooks: Arc<Vec<Webhook>>,
+    key: Option<Arc<hmac::Key>>,
+}
+
+impl fmt::Debug for Webhooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Webhooks")
+            .field("webhooks", &self.webhooks)
+            .field("signed", &self.key.is_some())
+            .finish()
+    }
+}
+
+impl Webhooks {
+    pub fn new(webhooks: Vec<Webhook>, secret: Option<&[u8]>) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("Failed to build HTTP client"),
+            webhooks: Arc::new(webhooks),
+            key: secret.map(|secret| Arc::new(hmac::Key::new(hmac::HMAC_SHA256, secret))),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.webhooks.is_empty()
+    }
+
+    /// Send `event` to every interested webhook in the background.
+    pub fn send(&self, event: WebhookEvent) {
+        let webhooks = self.clone();
+        tokio::spawn(async move { webhooks.deliver(event).await });
+    }
+
+    /// Send `event` to every interested webhook, and wait until they have all
+    /// received it or given up.
+    pub async fn deliver(&self, event: WebhookEvent) {
+        let body = match self.payload(&event) {
+            Ok(body) => body,
+            Err(e) => {
+                log::error!("Failed to serialize webhook payload: {}", e);
+                return;
+            }
+        };
+
+        let deliveries = self
+            .webhooks
+            .iter()
+            .filter(|webhook| webhook.wants(event.kind()))
+            .map(|webhook| self.deliver_to(webhook, &event, &body));
+
+        futures::future::join_all(deliveries).await;
+    }
+
+    fn payload(&self, event: &WebhookEvent) -> serde_json::Result<Vec<u8>> {
+        let summary = event.summary();
+        serde_json::to_vec(&WebhookPayload {
+            event,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            content: summary.clone(),
+            summary,
+        })
+    }
+
+    async fn deliver_to(&self, webhook: &Webhook, event: &WebhookEvent, body: &[u8]) {
+        let event_name = event_name(event);
+        let mut backoff = INITIAL_BACKOFF;
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let mut request = self
+                .client
+                .post(webhook.url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header("X-Greg-Event", event_name)
+                .body(body.to_vec());
+
+            if let Some(key) = &self.key {
+                request = request.header("X-Greg-Signature", signature(key, body));
+            }
+
+            let retry = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    log::debug!("Delivered {} webhook to {}", event_name, webhook.url);
+                    return;
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    log::warn!(
+                        "Webhook {} responded with {} to {} (attempt {}/{})",
+                        webhook.url,
+                        status,
+                        event_name,
+                        attempt,
+                        MAX_ATTEMPTS
+                    );
+                    // Other client errors will not go away by trying again.
+                    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Failed to send {} to webhook {} (attempt {}/{}): {}",
+                        event_name,
+                        webhook.url,
+                        attempt,
+                        MAX_ATTEMPTS,
+                        e
+                    );
+                    true
+                }
+            };
+
+            if !retry || attempt == MAX_ATTEMPTS {
+                break;
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+
+        log::error!(
+            "Giving up on delivering {} to webhook {}",
+            event_name,
+            webhook.url
+        );
+    }
+}
+
+fn event_name(event: &WebhookEvent) -> &'static str {
+    match event.kind() {
+        WebhookEventKind::TrackStarted => "track_started",
+        WebhookEventKind::TrackFinished => "track_finished",
+        WebhookEventKind::PlaylistEmptied => "playlist_emptied",
+        WebhookEventKind::PlayerCrashed => "player_crashed",
+    }
+}
+
+/// `sha256=<hex encoded HMAC-SHA256 of the body>`.
+fn signature(key: &hmac::Key, body: &[u8]) -> String {
+    let tag = hmac::sign(key, body);
+    let hex: String = tag
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+/// Translate mpv events into webhook events, until the connection to mpv is lost.
+///
+/// Losing the connection is reported as a crash if `report_disconnect` is set.
+pub async fn watch_player(mpv: Mpv, webhooks: Webhooks, report_disconnect: bool) {
+    let mut event_stream = mpv.get_event_stream().await;
+
+    if let Err(e) = mpv.observe_property(OBSERVER_ID, "playlist").await {
+        log::warn!("Failed to observe the playlist for webhooks: {}", e);
+    }
+
+    let mut current: Option<(String, Option<String>)> = None;
+    let mut playlist_empty = mpv
+        .get_playlist()
+        .await
+        .map(|playlist| playlist.0.is_empty())
+        .unwrap_or(true);
+
+    let message = loop {
+        let event = match event_stream.next().await {
+            Some(Ok(event)) => event,
+            Some(Err(e)) => break format!("Lost the connection to mpv: {}", e),
+            None => break "Lost the connection to mpv".to_string(),
+        };
+
+        match event {
+            Event::FileLoaded => {
+                let path: Option<String> = mpv.get_property("path").await.unwrap_or(None);
+                let title: Option<String> = mpv.get_property("media-title").await.unwrap_or(None);
+                if let Some(path) = path {
+                    current = Some((path.clone(), title.clone()));
+                    webhooks.send(WebhookEvent::TrackStarted { path, title });
+                }
+            }
+            Event::EndFile { reason, .. } => {
+                let reason = match reason {
+                    EndFileReason::Eof => "finished",
+                    EndFileReason::Stop => "skipped",
+                    EndFileReason::Error => "error",
+                    _ => continue,
+                };
+                if let Some((path, title)) = current.take() {
+                    webhooks.send(WebhookEvent::TrackFinished {
+                        path,
+                        title,
+                        reason,
+                    });
+                }
+            }
+            Event::PropertyChange { name, data, .. } if name == "playlist" => {
+                let empty = match data {
+                    Some(MpvDataType::Playlist(playlist)) => playlist.0.is_empty(),
+                    _ => true,
+                };
+                if empty && !playlist_empty {
+                    webhooks.send(WebhookEvent::PlaylistEmptied);
+                }
+                playlist_empty = empty;
+            }
+            Event::Shutdown => return,
+            _ => {}
+        }
+    };
+
+    log::warn!("{}", message);
+    if report_disconnect {
+        webhooks
+            .deliver(WebhookEvent::PlayerCrashed { message })
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_webhook() {
+        let webhook: Webhook = "https://example.com/hook?token=abc".parse().unwrap();
+        assert_eq!(webhook.url.as_str(), "https://example.com/hook?token=abc");
+        assert!(webhook.wants(WebhookEventKind::PlayerCrashed));
+
+        let webhook: Webhook = "track_started,player_crashed=http://localhost:9000/"
+            .parse()
+            .unwrap();
+        assert!(webhook.wants(WebhookEventKind::TrackStarted));
+        assert!(!webhook.wants(WebhookEventKind::TrackFinished));
+
+        assert!(
+            "track_exploded=http://localhost:9000/"
+                .parse::<Webhook>()
+                .is_err()
+        );
+        assert!("ftp://example.com/".parse::<Webhook>().is_err());
+    }
+
+    #[test]
+    fn test_signature() {
+        // From RFC 4231, test case 2.
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"Jefe");
+        assert_eq!(
+            signature(&key, b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}